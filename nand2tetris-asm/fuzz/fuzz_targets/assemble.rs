@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nand2tetris_asm::{assemble, build_symbol_table, preprocess};
+
+fuzz_target!(|data: &[u8]| {
+    let src = String::from_utf8_lossy(data);
+    let code = preprocess(src.lines().map(String::from).collect());
+
+    if let Ok(symbol_table) = build_symbol_table(&code) {
+        let _ = assemble(&code, &symbol_table);
+    }
+});
@@ -0,0 +1,120 @@
+// 差分テスト用に、乱数で正しい Hack アセンブリを作る
+//
+// ジャンプはすべて前向きなので必ず止まり、最後の `(END) @END 0;JMP` で止まる。M を読み書き
+// するのは、A に小さなアドレス・変数・定義済みシンボル・ラベルを入れた直後だけにして、RAM の
+// 外や KBD には触らない
+
+use nand2tetris_core::random::Random;
+
+pub const END: &str = "END";
+
+const COMPS: [&str; 28] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A",
+    "A-D", "D&A", "D|A", "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+];
+const DESTS: [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+const JUMPS: [&str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+const SYMBOLS: [&str; 7] = ["SP", "LCL", "ARG", "THIS", "THAT", "R13", "R15"];
+const VARIABLES: usize = 8;
+const MAX_INSTRUCTIONS: usize = 200;
+
+pub fn generate(seed: u64) -> String {
+    let mut random = Random::new(seed);
+    let mut lines = Vec::new();
+    // まだ置いていないラベル
+    let mut pending: Vec<String> = Vec::new();
+    let mut labels = 0;
+    // A が RAM の読み書きに使えるアドレスか
+    let mut safe = false;
+
+    let instructions = 10 + random.below(MAX_INSTRUCTIONS - 10);
+    while lines.len() < instructions {
+        match random.below(8) {
+            0 | 1 => {
+                let address = match random.below(3) {
+                    0 => format!("v{}", random.below(VARIABLES)),
+                    1 => random.choose(&SYMBOLS).to_string(),
+                    _ => random.below(1024).to_string(),
+                };
+                lines.push(format!("@{}", address));
+                safe = true;
+            }
+            // 値として使う大きな数
+            2 => {
+                let value = match random.below(4) {
+                    0 => random.choose(&["SCREEN", "KBD"]).to_string(),
+                    _ => (random.word() & 0x7fff).to_string(),
+                };
+                lines.push(format!("@{}", value));
+                safe = false;
+            }
+            // 前向きのジャンプ。ジャンプ先は実行前の A
+            3 => {
+                labels += 1;
+                let label = format!("L{}", labels);
+                lines.push(format!("@{}", label));
+                pending.push(label);
+                let (instruction, writes_a) = c_instruction(&mut random, true);
+                let jump = random.choose(&JUMPS);
+                lines.push(format!("{};{}", instruction, jump));
+                safe = !writes_a;
+            }
+            _ => {
+                let (instruction, writes_a) = c_instruction(&mut random, safe);
+                lines.push(instruction);
+                safe &= !writes_a;
+            }
+        }
+        // 置くのを先延ばしにすると、飛び越える命令が増える。ジャンプしてきたときの A は
+        // わからない
+        while !pending.is_empty() && random.one_in(3) {
+            let label = pending.remove(random.below(pending.len()));
+            lines.push(format!("({})", label));
+            safe = false;
+        }
+    }
+    for label in pending {
+        lines.push(format!("({})", label));
+    }
+    lines.extend([
+        format!("({})", END),
+        format!("@{}", END),
+        "0;JMP".to_string(),
+    ]);
+    lines.join("\n") + "\n"
+}
+
+// (dest=comp, A に書くか)。memory でなければ M を使わない
+fn c_instruction(random: &mut Random, memory: bool) -> (String, bool) {
+    let usable = |s: &&str| memory || !s.contains('M');
+    let comps: Vec<&str> = COMPS.into_iter().filter(|comp| usable(comp)).collect();
+    let dests: Vec<&str> = DESTS.into_iter().filter(|dest| usable(dest)).collect();
+    let comp = random.choose(&comps);
+    let dest = random.choose(&dests);
+    let instruction = match *dest {
+        "" => comp.to_string(),
+        dest => format!("{}={}", dest, comp),
+    };
+    (instruction, dest.contains('A'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::{Interpreter, Stop};
+
+    #[test]
+    fn test_generated_programs_halt() {
+        for seed in 0..200 {
+            let source = generate(seed);
+            assert_eq!(source, generate(seed));
+            let mut interpreter = Interpreter::new(&source).unwrap();
+            assert_eq!(
+                interpreter.run(100_000).unwrap(),
+                Stop::Halted,
+                "seed {}",
+                seed
+            );
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// アセンブリを機械語にせずにそのまま実行する
+//
+// アセンブラと CPU エミュレータを突き合わせる差分テストの基準。シンボルもアセンブラとは別に
+// 解決する（ラベルは次の命令の位置、定義済みシンボル、残りは 16 から出現順の変数）。
+// comp は機械語のビットではなく書き方のとおりに計算する
+
+use anyhow::{Context, Result, bail, ensure};
+use nand2tetris_core::{
+    source::code_lines,
+    symbols::{self, KBD},
+};
+use std::collections::HashMap;
+
+use crate::{Address, Instruction, parse_instruction};
+
+pub const RAM_SIZE: usize = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // 最後の命令の後まで進んだ
+    Finished,
+    // `(END) @END 0;JMP` の @END に来た
+    Halted,
+}
+
+pub struct Interpreter {
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: usize,
+    pub steps: u64,
+    // ラベルを除いた命令。A 命令はアドレスに解決してある
+    instructions: Vec<Instruction>,
+}
+
+impl Interpreter {
+    pub fn new(source: &str) -> Result<Self> {
+        let parsed: Vec<Instruction> = code_lines(source)
+            .map(|(_, line)| parse_instruction(line))
+            .collect();
+
+        let mut addresses: HashMap<String, u16> = HashMap::new();
+        let mut count = 0;
+        for instruction in &parsed {
+            match instruction {
+                Instruction::Label(label) => {
+                    ensure!(
+                        addresses.insert(label.clone(), count).is_none(),
+                        "Label '{}' is defined twice",
+                        label
+                    );
+                }
+                _ => count += 1,
+            }
+        }
+        let mut next_variable = 16;
+        let mut instructions = Vec::new();
+        for instruction in parsed {
+            match instruction {
+                Instruction::Label(_) => {}
+                Instruction::A(Address::Symbol(symbol)) => {
+                    let address = match symbols::predefined(&symbol) {
+                        Some(address) => address,
+                        None => *addresses.entry(symbol).or_insert_with(|| {
+                            next_variable += 1;
+                            next_variable - 1
+                        }),
+                    };
+                    instructions.push(Instruction::A(Address::Number(address)));
+                }
+                instruction => instructions.push(instruction),
+            }
+        }
+
+        Ok(Interpreter {
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            steps: 0,
+            instructions,
+        })
+    }
+
+    pub fn is_halted(&self) -> bool {
+        let is_bare_jump = |instruction: Option<&Instruction>| {
+            matches!(
+                instruction,
+                Some(Instruction::C { dest: None, comp, jump: Some(jump) })
+                    if comp == "0" && jump == "JMP"
+            )
+        };
+        match self.instructions.get(self.pc) {
+            Some(Instruction::A(Address::Number(address))) => {
+                *address as usize == self.pc && is_bare_jump(self.instructions.get(self.pc + 1))
+            }
+            instruction => is_bare_jump(instruction) && self.a as usize == self.pc,
+        }
+    }
+
+    pub fn run(&mut self, max_steps: u64) -> Result<Stop> {
+        loop {
+            if self.pc >= self.instructions.len() {
+                return Ok(Stop::Finished);
+            }
+            if self.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            ensure!(
+                self.steps < max_steps,
+                "The program did not stop within {} steps",
+                max_steps
+            );
+            self.step()?;
+        }
+    }
+
+    pub fn step(&mut self) -> Result<()> {
+        let instruction = self
+            .instructions
+            .get(self.pc)
+            .cloned()
+            .context("The program has already finished")?;
+        self.steps += 1;
+
+        let Instruction::C { dest, comp, jump } = instruction else {
+            if let Instruction::A(Address::Number(address)) = instruction {
+                self.a = address;
+            }
+            self.pc += 1;
+            return Ok(());
+        };
+
+        // M の読み書きとジャンプ先は実行前の A
+        let address = self.a;
+        let y = if comp.contains('M') {
+            self.read(address)?
+        } else {
+            self.a
+        };
+        let value = compute(&comp.replace('M', "A"), self.d, y)?;
+        let dest = dest.unwrap_or_default();
+        if dest.contains('M') {
+            self.write(address, value)?;
+        }
+        if dest.contains('A') {
+            self.a = value;
+        }
+        if dest.contains('D') {
+            self.d = value;
+        }
+
+        let signed = value as i16;
+        let jumps = match jump.as_deref() {
+            None => false,
+            Some("JGT") => signed > 0,
+            Some("JEQ") => signed == 0,
+            Some("JGE") => signed >= 0,
+            Some("JLT") => signed < 0,
+            Some("JNE") => signed != 0,
+            Some("JLE") => signed <= 0,
+            Some("JMP") => true,
+            Some(jump) => bail!("Unknown jump '{}'", jump),
+        };
+        self.pc = if jumps { address as usize } else { self.pc + 1 };
+        Ok(())
+    }
+
+    fn read(&self, address: u16) -> Result<u16> {
+        self.ram
+            .get(address as usize)
+            .copied()
+            .with_context(|| format!("Address {} is out of RAM", address))
+    }
+
+    fn write(&mut self, address: u16, value: u16) -> Result<()> {
+        // KBD には書けない
+        if address == KBD {
+            return Ok(());
+        }
+        *self
+            .ram
+            .get_mut(address as usize)
+            .with_context(|| format!("Address {} is out of RAM", address))? = value;
+        Ok(())
+    }
+}
+
+// M は A に置き換えてある。y は A か M の値
+fn compute(comp: &str, d: u16, y: u16) -> Result<u16> {
+    Ok(match comp {
+        "0" => 0,
+        "1" => 1,
+        "-1" => 0xffff,
+        "D" => d,
+        "A" => y,
+        "!D" => !d,
+        "!A" => !y,
+        "-D" => d.wrapping_neg(),
+        "-A" => y.wrapping_neg(),
+        "D+1" => d.wrapping_add(1),
+        "A+1" => y.wrapping_add(1),
+        "D-1" => d.wrapping_sub(1),
+        "A-1" => y.wrapping_sub(1),
+        "D+A" => d.wrapping_add(y),
+        "D-A" => d.wrapping_sub(y),
+        "A-D" => y.wrapping_sub(d),
+        "D&A" => d & y,
+        "D|A" => d | y,
+        _ => bail!("Unknown comp '{}'", comp),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let source = "@R0\nD=M\n@i\nM=D\n(LOOP)\n@i\nMD=M-1\n@sum\nM=M+1\n@i\nD=M\n@LOOP\nD;JGT\n\
+                      @14\nAD=A+1;JMP\n@99\n(END)\n@END\n0;JMP\n";
+        let mut interpreter = Interpreter::new(source).unwrap();
+        interpreter.ram[0] = 3;
+        assert_eq!(interpreter.run(1000).unwrap(), Stop::Halted);
+        // i は 16、sum は 17
+        assert_eq!(interpreter.ram[16], 0);
+        assert_eq!(interpreter.ram[17], 3);
+        // ジャンプ先は更新前の A（14 番地の @99 を飛ばさない）
+        assert_eq!(interpreter.pc, 15);
+        assert_eq!((interpreter.a, interpreter.d), (99, 15));
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut interpreter = Interpreter::new("@32767\nD=A+1\nA=D\nM=1\n").unwrap();
+        assert!(interpreter.run(10).is_err());
+        assert!(Interpreter::new("(A)\n(A)\n").is_err());
+    }
+}
@@ -0,0 +1,495 @@
+pub mod generate;
+pub mod interpret;
+
+use anyhow::{Context, Result, ensure};
+use nand2tetris_core::{
+    cli::{Fail, Failure},
+    diagnostic::at_line,
+    source::{code_lines, strip_comment},
+    symbols,
+};
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    slice,
+};
+
+pub fn read_assembly(file_path: &str) -> Result<Vec<String>> {
+    let file = File::open(file_path)?;
+
+    let reader = BufReader::new(file);
+    let mut lines = vec![];
+
+    for line in reader.lines().map_while(Result::ok) {
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+pub fn preprocess(assembly_code: Vec<String>) -> Vec<String> {
+    assembly_code
+        .iter()
+        .map(|line| strip_comment(line))
+        // 空行をスキップ
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub fn build_symbol_table(code: &[String]) -> Result<HashMap<String, u16>> {
+    build_symbol_table_with(code, &HashMap::new())
+}
+
+// extra は定義済みシンボルに加えるシンボル（n2t.toml の [symbols] など）
+pub fn build_symbol_table_with(
+    code: &[String],
+    extra: &HashMap<String, u16>,
+) -> Result<HashMap<String, u16>> {
+    // 初期化初期化
+    let mut symbol_table = HashMap::new();
+
+    symbol_table.extend(symbols::all());
+    symbol_table.extend(extra.iter().map(|(name, &value)| (name.clone(), value)));
+
+    // 1回目のパス ラベルのみ処理
+    let mut labels = HashSet::new();
+    let mut current_line_num: u16 = 0;
+    for line in code {
+        if let Some(label) = line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            ensure!(
+                !extra.contains_key(label),
+                "label '{label}' is already defined as a symbol"
+            );
+            ensure!(
+                labels.insert(label),
+                "label '{label}' is defined more than once"
+            );
+            symbol_table.insert(label.to_string(), current_line_num);
+        } else {
+            // ROMは32K語まで
+            current_line_num = current_line_num
+                .checked_add(1)
+                .filter(|n| *n <= 0x8000)
+                .context("program too large: exceeds 32K ROM words")?;
+        }
+    }
+
+    // 2回目のパス 変数を処理
+    let mut not_defined_variable = 16; // 未定義の変数は16から
+    for line in code {
+        if line.starts_with('@') && line[1..].parse::<u16>().is_err() {
+            let symbol = &line[1..];
+            if !symbol_table.contains_key(symbol) {
+                ensure!(
+                    not_defined_variable < 16384,
+                    "too many variables: RAM[16..16384] exhausted at '{symbol}'"
+                );
+                symbol_table.insert(symbol.to_string(), not_defined_variable);
+                not_defined_variable += 1;
+            }
+        }
+    }
+
+    Ok(symbol_table)
+}
+
+pub use nand2tetris_core::symbols::is_predefined;
+
+// 機械語とデバッグ用のシンボル情報
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub words: Vec<u16>,
+    // 定義済みシンボル・ラベル・変数すべて
+    pub symbols: HashMap<String, u16>,
+    // ラベル名 → ROM アドレス
+    pub labels: HashMap<String, u16>,
+    // 変数名 → RAM アドレス（定義済みシンボルを除く）
+    pub variables: HashMap<String, u16>,
+    // ROM アドレスごとの元ソースの行番号（1始まり）
+    pub source_lines: Vec<usize>,
+}
+
+// ファイルを経由せずにメモリ上でアセンブルする
+pub fn assemble_program(source: &str) -> Result<Program> {
+    assemble_program_with(source, &HashMap::new())
+}
+
+// extra のシンボルを定義済みとしてアセンブルする。変数には数えない
+pub fn assemble_program_with(source: &str, extra: &HashMap<String, u16>) -> Result<Program> {
+    let mut code = Vec::new();
+    let mut lines = Vec::new();
+    let mut source_lines = Vec::new();
+    for (line, trimmed) in code_lines(source) {
+        if !trimmed.starts_with('(') {
+            source_lines.push(line);
+        }
+        code.push(trimmed.to_string());
+        lines.push(line);
+    }
+
+    let symbols = build_symbol_table_with(&code, extra).fail(Failure::Semantic)?;
+    let labels: HashMap<String, u16> = parse_program(&code)
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Label(label) => symbols.get(&label).map(|&address| (label, address)),
+            _ => None,
+        })
+        .collect();
+
+    // エラーに行番号を付けるため 1 命令ずつ
+    let mut words = Vec::new();
+    for (instruction, &line) in code.iter().zip(&lines) {
+        let binary = assemble(slice::from_ref(instruction), &symbols)
+            .map_err(|e| at_line(line, e))
+            .fail(Failure::Parse)?;
+        for word in binary {
+            let word = u16::from_str_radix(word.trim_end(), 2)
+                .map_err(|_| Failure::Parse.wrap(at_line(line, "invalid instruction")))?;
+            words.push(word);
+        }
+    }
+
+    let variables = symbols
+        .iter()
+        .filter(|(name, _)| {
+            !labels.contains_key(*name) && !is_predefined(name) && !extra.contains_key(*name)
+        })
+        .map(|(name, &address)| (name.clone(), address))
+        .collect();
+
+    Ok(Program {
+        words,
+        symbols,
+        labels,
+        variables,
+        source_lines,
+    })
+}
+
+// デバッガ向けのシンボルファイル（.sym）。1行に「種類 名前 アドレス」
+pub fn format_symbol_file(program: &Program) -> String {
+    let mut labels: Vec<_> = program.labels.iter().collect();
+    labels.sort_by_key(|&(name, &address)| (address, name));
+    let mut variables: Vec<_> = program.variables.iter().collect();
+    variables.sort_by_key(|&(name, &address)| (address, name));
+
+    let mut out = String::from("# hack symbols v1\n");
+    for (name, address) in labels {
+        out.push_str(&format!("label {name} {address}\n"));
+    }
+    for (name, address) in variables {
+        out.push_str(&format!("var {name} {address}\n"));
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Address {
+    Number(u16),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Instruction {
+    A(Address),
+    C {
+        dest: Option<String>,
+        comp: String,
+        jump: Option<String>,
+    },
+    Label(String),
+}
+
+pub fn parse_instruction(line: &str) -> Instruction {
+    if let Some(label) = line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+        return Instruction::Label(label.to_string());
+    }
+
+    if let Some(sym) = line.strip_prefix('@') {
+        // A命令
+        let address = match sym.parse::<u16>() {
+            Ok(num) => Address::Number(num),
+            Err(_) => Address::Symbol(sym.to_string()),
+        };
+        return Instruction::A(address);
+    }
+
+    // C命令 dest=comp;jump
+    let (dest_comp, jump) = match line.split_once(';') {
+        Some((dc, j)) => (dc, Some(j.to_string())),
+        None => (line, None),
+    };
+    let (dest, comp) = match dest_comp.split_once('=') {
+        Some((d, c)) => (Some(d.to_string()), c),
+        None => (None, dest_comp),
+    };
+
+    Instruction::C {
+        dest,
+        comp: comp.to_string(),
+        jump,
+    }
+}
+
+pub fn parse_program(code: &[String]) -> Vec<Instruction> {
+    code.iter().map(|line| parse_instruction(line)).collect()
+}
+
+pub fn assemble(code: &[String], symbol_table: &HashMap<String, u16>) -> Result<Vec<String>> {
+    let mut binary_code = vec![];
+
+    for instruction in parse_program(code) {
+        match instruction {
+            Instruction::Label(_) => continue,
+            Instruction::A(address) => {
+                let val = match address {
+                    Address::Number(num) => num,
+                    Address::Symbol(sym) => *symbol_table
+                        .get(&sym)
+                        .with_context(|| format!("undefined symbol: {sym}"))?,
+                };
+                binary_code.push(format!("{:016b}\n", val));
+            }
+            Instruction::C { dest, comp, jump } => {
+                let dest = dest.as_deref().unwrap_or("");
+                let dest = format!(
+                    "{}{}{}",
+                    if dest.contains('A') { "1" } else { "0" },
+                    if dest.contains('D') { "1" } else { "0" },
+                    if dest.contains('M') { "1" } else { "0" },
+                );
+                let comp = comp_table(&comp)?;
+                let jump = jump.as_deref().map_or(Ok("000"), jump_table)?;
+                binary_code.push(format!("111{}{}{}\n", comp, dest, jump));
+            }
+        }
+    }
+
+    Ok(binary_code)
+}
+
+// compは必須のため、変換に失敗したらErrにする
+fn comp_table(comp: &str) -> Result<&str> {
+    match comp {
+        // a = 0
+        "0" => Ok("0101010"),
+        "1" => Ok("0111111"),
+        "-1" => Ok("0111010"),
+        "D" => Ok("0001100"),
+        "A" => Ok("0110000"),
+        "!D" => Ok("0001101"),
+        "!A" => Ok("0110001"),
+        "-D" => Ok("0001111"),
+        "-A" => Ok("0110011"),
+        "D+1" => Ok("0011111"),
+        "A+1" => Ok("0110111"),
+        "D-1" => Ok("0001110"),
+        "A-1" => Ok("0110010"),
+        "D+A" => Ok("0000010"),
+        "D-A" => Ok("0010011"),
+        "A-D" => Ok("0000111"),
+        "D&A" => Ok("0000000"),
+        "D|A" => Ok("0010101"),
+        // a = 1
+        "M" => Ok("1110000"),
+        "!M" => Ok("1110001"),
+        "-M" => Ok("1110011"),
+        "M+1" => Ok("1110111"),
+        "M-1" => Ok("1110010"),
+        "D+M" => Ok("1000010"),
+        "D-M" => Ok("1010011"),
+        "M-D" => Ok("1000111"),
+        "D&M" => Ok("1000000"),
+        "D|M" => Ok("1010101"),
+        _ => anyhow::bail!("invalid comp pattern: {comp}"),
+    }
+}
+
+// jump も書いたなら既知のものだけ
+fn jump_table(jump: &str) -> Result<&str> {
+    match jump {
+        "JGT" => Ok("001"),
+        "JEQ" => Ok("010"),
+        "JGE" => Ok("011"),
+        "JLT" => Ok("100"),
+        "JNE" => Ok("101"),
+        "JLE" => Ok("110"),
+        "JMP" => Ok("111"),
+        _ => anyhow::bail!("invalid jump pattern: {jump}"),
+    }
+}
+
+pub fn write_binary_code(file_path: &str, binary_code: Vec<String>) -> Result<()> {
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    for line in binary_code {
+        writer.write_all(line.as_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nand2tetris_core::cli::exit_code;
+
+    fn lines(src: &str) -> Vec<String> {
+        preprocess(src.lines().map(String::from).collect())
+    }
+
+    #[test]
+    fn test_assemble_basic() {
+        let code = lines("@2\nD=A // comment\n(LOOP)\n@LOOP\n0;JMP");
+        let table = build_symbol_table(&code).unwrap();
+        let binary = assemble(&code, &table).unwrap();
+        assert_eq!(
+            binary,
+            vec![
+                "0000000000000010\n",
+                "1110110000010000\n",
+                "0000000000000010\n",
+                "1110101010000111\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_program() {
+        let source =
+            "// count down\n@R0\n\n(LOOP)\nM=M-1 // dec\n@LOOP\nM;JGT\n(END)\n@END\n0;JMP\n";
+        let program = assemble_program(source).unwrap();
+
+        assert_eq!(
+            program.words,
+            vec![
+                0b0000000000000000,
+                0b1111110010001000,
+                0b0000000000000001,
+                0b1111110000000001,
+                0b0000000000000100,
+                0b1110101010000111,
+            ]
+        );
+        assert_eq!(program.source_lines, vec![2, 5, 6, 7, 9, 10]);
+        assert_eq!(
+            program.labels,
+            HashMap::from([("LOOP".to_string(), 1), ("END".to_string(), 4)])
+        );
+        assert_eq!(program.symbols["THAT"], 4);
+        assert!(program.variables.is_empty());
+    }
+
+    #[test]
+    fn test_assemble_program_with_symbols() {
+        let extra = HashMap::from([("LED".to_string(), 24577)]);
+        let program = assemble_program_with("@LED\nM=1\n@count\nM=0", &extra).unwrap();
+        assert_eq!(program.words[0], 24577);
+        assert_eq!(program.words[2], 16);
+        assert_eq!(
+            program.variables,
+            HashMap::from([("count".to_string(), 16)])
+        );
+
+        let err = assemble_program_with("(LED)\n0;JMP", &extra).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "label 'LED' is already defined as a symbol"
+        );
+    }
+
+    #[test]
+    fn test_format_symbol_file() {
+        let program = assemble_program("@counter\nM=0\n(LOOP)\n@R15\n@i\n@LOOP\n0;JMP").unwrap();
+        assert_eq!(
+            format_symbol_file(&program),
+            "# hack symbols v1\nlabel LOOP 2\nvar counter 16\nvar i 17\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_instruction() {
+        assert_eq!(
+            parse_instruction("@21"),
+            Instruction::A(Address::Number(21))
+        );
+        assert_eq!(
+            parse_instruction("@LOOP"),
+            Instruction::A(Address::Symbol("LOOP".to_string()))
+        );
+        assert_eq!(
+            parse_instruction("(END)"),
+            Instruction::Label("END".to_string())
+        );
+        assert_eq!(
+            parse_instruction("AM=M+1;JGT"),
+            Instruction::C {
+                dest: Some("AM".to_string()),
+                comp: "M+1".to_string(),
+                jump: Some("JGT".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_instruction("0;JMP"),
+            Instruction::C {
+                dest: None,
+                comp: "0".to_string(),
+                jump: Some("JMP".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_program_json_roundtrip() {
+        let program = parse_program(&lines("@i\nM=1\n(LOOP)\n@LOOP\nD;JGT"));
+        let json = serde_json::to_string(&program).unwrap();
+        let decoded: Vec<Instruction> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+    }
+
+    #[test]
+    fn test_error_has_line_number() {
+        let error = assemble_program("// Max\n@1\n\nD=Q\n").unwrap_err();
+        assert_eq!(error.to_string(), "Line 4: invalid comp pattern: Q");
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let error = assemble_program("@1\nD=D+A;JXX\n").unwrap_err();
+        assert_eq!(error.to_string(), "Line 2: invalid jump pattern: JXX");
+        assert_eq!(exit_code(&error), 3);
+
+        let error = assemble_program("(LOOP)\n@LOOP\n(LOOP)\n0;JMP\n").unwrap_err();
+        assert_eq!(error.to_string(), "label 'LOOP' is defined more than once");
+        assert_eq!(exit_code(&error), 4);
+    }
+
+    #[test]
+    fn test_too_many_variables_is_error() {
+        let code: Vec<String> = (0..16400).map(|i| format!("@v{i}")).collect();
+        assert!(build_symbol_table(&code).is_err());
+    }
+
+    #[test]
+    fn test_program_too_large_is_error() {
+        let code = vec!["0".to_string(); 0x8001];
+        assert!(build_symbol_table(&code).is_err());
+    }
+
+    #[test]
+    fn test_malformed_lines_do_not_panic() {
+        for src in [
+            "@", "(", ")", "()", "=", ";", "A=", "=D", "@é", "D=D+A;", "(é)",
+        ] {
+            let code = lines(src);
+            if let Ok(table) = build_symbol_table(&code) {
+                let _ = assemble(&code, &table);
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// tests/golden の .asm をアセンブルし、コミットしてある .hack と比べる
+// 参照出力を書き直すには N2T_BLESS=1 cargo test --test golden
+
+use anyhow::Result;
+use nand2tetris_asm::assemble_program;
+use nand2tetris_core::golden::check_corpus;
+use std::{fs, path::Path};
+
+fn assemble(path: &Path) -> Result<String> {
+    let program = assemble_program(&fs::read_to_string(path)?)?;
+    Ok(program
+        .words
+        .iter()
+        .map(|word| format!("{:016b}\n", word))
+        .collect())
+}
+
+#[test]
+fn test_golden() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    if let Err(e) = check_corpus(&corpus, "asm", "hack", assemble) {
+        panic!("{:#}", e);
+    }
+}
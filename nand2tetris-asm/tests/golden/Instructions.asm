@@ -0,0 +1,57 @@
+// Every comp, dest and jump field, predefined symbols and variables
+
+@R15
+@SP
+@LCL
+@ARG
+@THIS
+@THAT
+@SCREEN
+@KBD
+@first
+@second
+@first
+@32767
+0
+1
+-1
+D
+A
+!D
+!A
+-D
+-A
+D+1
+A+1
+D-1
+A-1
+D+A
+D-A
+A-D
+D&A
+D|A
+M
+!M
+-M
+M+1
+M-1
+D+M
+D-M
+M-D
+D&M
+D|M
+M=0
+D=0
+MD=0
+A=0
+AM=0
+AD=0
+AMD=0
+0;JGT
+0;JEQ
+0;JGE
+0;JLT
+0;JNE
+0;JLE
+0;JMP
+AMD=D+1;JNE
@@ -0,0 +1,172 @@
+// CI 向けの終わりの表（--ci）と、警告があれば失敗にする --fail-on-warning
+//
+// 入力ファイルごとにエラー・警告の数と出力の大きさを集め、finish で表にする。生成される
+// コードが大きくなったことに CI のログで気づけるようにする
+//
+// File           Errors  Warnings  Output
+// src/Main.jack  0       1         132 commands
+// Total          0       1         132 commands
+
+use clap::Args;
+use std::sync::Mutex;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct CiArgs {
+    /// Print a table of the files processed with their errors, warnings and output sizes at the end
+    #[arg(long)]
+    pub ci: bool,
+    /// Exit with code 7 if there were any warnings (unlike --deny-warnings, the output is still written)
+    #[arg(long)]
+    pub fail_on_warning: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub file: String,
+    pub errors: usize,
+    pub warnings: usize,
+    // 生成したコードの大きさ。出力がなければ None
+    pub size: Option<usize>,
+}
+
+struct Table {
+    args: CiArgs,
+    // 大きさの単位（words、instructions、commands）
+    unit: &'static str,
+    rows: Vec<Row>,
+}
+
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+pub fn set_ci(args: &CiArgs, unit: &'static str) {
+    *TABLE.lock().unwrap() = (args.ci || args.fail_on_warning).then(|| Table {
+        args: args.clone(),
+        unit,
+        rows: Vec::new(),
+    });
+}
+
+// 表示しなかった警告も数える
+pub fn count(file: &str, errors: usize, warnings: usize) {
+    with_row(file, |row| {
+        row.errors += errors;
+        row.warnings += warnings;
+    });
+}
+
+pub fn output(file: &str, size: usize) {
+    with_row(file, |row| *row.size.get_or_insert(0) += size);
+}
+
+// 行はファイルが最初に出てきた順
+fn with_row(file: &str, f: impl FnOnce(&mut Row)) {
+    let mut table = TABLE.lock().unwrap();
+    let Some(table) = table.as_mut() else {
+        return;
+    };
+    let index = match table.rows.iter().position(|row| row.file == file) {
+        Some(index) => index,
+        None => {
+            table.rows.push(Row {
+                file: file.to_string(),
+                errors: 0,
+                warnings: 0,
+                size: None,
+            });
+            table.rows.len() - 1
+        }
+    };
+    f(&mut table.rows[index]);
+}
+
+// (--ci の表, --fail-on-warning で失敗にする警告の数)
+pub fn finish() -> (Option<String>, Option<usize>) {
+    let table = TABLE.lock().unwrap();
+    let Some(table) = table.as_ref() else {
+        return (None, None);
+    };
+    let warnings: usize = table.rows.iter().map(|row| row.warnings).sum();
+    (
+        table.args.ci.then(|| format_table(&table.rows, table.unit)),
+        (table.args.fail_on_warning && warnings > 0).then_some(warnings),
+    )
+}
+
+pub fn format_table(rows: &[Row], unit: &str) -> String {
+    let size = |size: Option<usize>| match size {
+        Some(size) => format!("{} {}", size, unit),
+        None => "-".to_string(),
+    };
+    let total = Row {
+        file: "Total".to_string(),
+        errors: rows.iter().map(|row| row.errors).sum(),
+        warnings: rows.iter().map(|row| row.warnings).sum(),
+        size: rows.iter().filter_map(|row| row.size).reduce(|a, b| a + b),
+    };
+    let mut lines = vec![[
+        "File".to_string(),
+        "Errors".to_string(),
+        "Warnings".to_string(),
+        "Output".to_string(),
+    ]];
+    lines.extend(rows.iter().chain([&total]).map(|row| {
+        [
+            row.file.clone(),
+            row.errors.to_string(),
+            row.warnings.to_string(),
+            size(row.size),
+        ]
+    }));
+    let widths: Vec<usize> = (0..4)
+        .map(|column| {
+            lines
+                .iter()
+                .map(|line| line[column].len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    lines
+        .iter()
+        .map(|line| {
+            let cells: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell))
+                .collect();
+            cells.join("  ").trim_end().to_string() + "\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_table() {
+        let rows = [
+            Row {
+                file: "src/Main.jack".to_string(),
+                errors: 0,
+                warnings: 1,
+                size: Some(132),
+            },
+            Row {
+                file: "src/Ball.jack".to_string(),
+                errors: 2,
+                warnings: 0,
+                size: None,
+            },
+        ];
+        assert_eq!(
+            format_table(&rows, "commands"),
+            "\
+File           Errors  Warnings  Output
+src/Main.jack  0       1         132 commands
+src/Ball.jack  2       0         -
+Total          2       1         132 commands
+"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+// コマンドラインのツールで共通の終了コード、--quiet と --color
+//
+// 採点スクリプトが分岐できるように、失敗の種類ごとに終了コードを分ける。
+// 種類はエラーを作ったところで fail で付け、表示は元のエラーのまま。
+// 種類の付いたエラーをさらに fail しても、最初に付けた（原因に近い）種類のまま
+
+use clap::ValueEnum;
+use std::{
+    env, fmt,
+    io::{self, IsTerminal},
+    process::ExitCode,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    ci,
+    diagnostic::{Severity, bare, emit, header, line_error, summary},
+    sarif::{self, Finding},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    // 構文の誤り（.asm、.vm、.jack、.hdl、.tst）
+    Parse,
+    // 構文は正しいが意味が通らない（未定義の名前、型、配線など）
+    Semantic,
+    // ファイルの読み書き
+    Io,
+    // テストが実行できて、結果が期待と違った
+    Test,
+    // 警告をエラーとして扱った（--deny-warnings など）
+    Warnings,
+}
+
+impl Failure {
+    // 1 はそれ以外のエラー、2 は clap の使い方の誤り
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Parse => 3,
+            Failure::Semantic => 4,
+            Failure::Io => 5,
+            Failure::Test => 6,
+            Failure::Warnings => 7,
+        }
+    }
+
+    pub fn wrap(self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        let error = error.into();
+        if failure(&error).is_some() {
+            return error;
+        }
+        // 文脈として付けるので、元のエラーの型への downcast もそのまま使える
+        let message = error.to_string();
+        error.context(Tagged {
+            failure: self,
+            message,
+        })
+    }
+}
+
+// 表示は元のエラーと同じ
+#[derive(Debug)]
+struct Tagged {
+    failure: Failure,
+    message: String,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+pub trait Fail<T> {
+    fn fail(self, failure: Failure) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> Fail<T> for Result<T, E> {
+    fn fail(self, failure: Failure) -> anyhow::Result<T> {
+        self.map_err(|error| failure.wrap(error))
+    }
+}
+
+// 付けた種類。なければ、ファイルの読み書きのエラーを含むかどうか
+pub fn failure(error: &anyhow::Error) -> Option<Failure> {
+    if let Some(tagged) = error.downcast_ref::<Tagged>() {
+        return Some(tagged.failure);
+    }
+    error
+        .chain()
+        .any(|cause| cause.is::<io::Error>())
+        .then_some(Failure::Io)
+}
+
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    failure(error).map_or(1, Failure::code)
+}
+
+// main の結果を表示して終了コードにする。--quiet でもエラーは表示する。
+// 行の分かるエラーはほかの診断と同じ形にし、最後に診断の数のまとめを出す。
+// --sarif と --ci があれば、ここで書く
+pub fn finish(result: anyhow::Result<()>) -> ExitCode {
+    let mut code = match &result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            match line_error(error) {
+                Some(e) => {
+                    if let Some(file) = &e.file {
+                        ci::count(file, 1, 0);
+                        sarif::record(Finding {
+                            severity: Severity::Error,
+                            file: file.clone(),
+                            line: e.line,
+                            column: None,
+                            message: e.message.clone(),
+                        });
+                    }
+                    emit(
+                        Severity::Error,
+                        &header(Severity::Error, &e.location(), &e.message),
+                    )
+                }
+                None => eprintln!("{}", bare(Severity::Error, &error.to_string())),
+            }
+            ExitCode::from(exit_code(error))
+        }
+    };
+    if let Err(error) = sarif::write() {
+        eprintln!("{}", bare(Severity::Error, &error.to_string()));
+        if result.is_ok() {
+            code = ExitCode::from(exit_code(&error));
+        }
+    }
+    let (table, warnings) = ci::finish();
+    if let Some(table) = table {
+        print!("{}", table);
+    }
+    if let Some(warnings) = warnings
+        && result.is_ok()
+    {
+        let plural = if warnings == 1 { "" } else { "s" };
+        eprintln!(
+            "{}",
+            bare(
+                Severity::Error,
+                &format!(
+                    "Failed with {} warning{} (--fail-on-warning)",
+                    warnings, plural
+                )
+            )
+        );
+        code = ExitCode::from(Failure::Warnings.code());
+    }
+    if let Some(summary) = summary()
+        && !is_quiet()
+    {
+        eprintln!("{}", summary);
+    }
+    code
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stderr is a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+// 診断は標準エラーに出すので、auto はそちらが端末かで決める
+pub fn set_color(choice: ColorChoice) {
+    let color = match choice {
+        ColorChoice::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Style {
+    Location,
+    Error,
+    Warning,
+}
+
+// 色を使わないときはそのまま
+pub(crate) fn paint(style: Style, text: &str) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return text.to_string();
+    }
+    let code = match style {
+        Style::Location => "1",
+        Style::Error => "1;31",
+        Style::Warning => "1;33",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+// 経過や結果のまとめ。--quiet なら表示しない（頼まれて標準出力に書くものには使わない）
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::cli::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+// 警告。--quiet なら表示しない
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::cli::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn test_failure() {
+        let error = Failure::Parse.wrap(anyhow!("Line 3: invalid instruction"));
+        assert_eq!(error.to_string(), "Line 3: invalid instruction");
+        assert_eq!(exit_code(&error), 3);
+        // 外側の文脈や種類があっても中の種類を使う
+        let error = Err::<(), _>(error).context("Failed to assemble 'Max.asm'");
+        assert_eq!(exit_code(&error.fail(Failure::Test).unwrap_err()), 3);
+
+        let missing = std::fs::read("/nonexistent/Max.asm").context("Failed to read 'Max.asm'");
+        assert_eq!(
+            failure(&missing.fail(Failure::Parse).unwrap_err()),
+            Some(Failure::Io)
+        );
+        let tested: anyhow::Result<()> = Err(anyhow!("1 of 2 tests failed")).fail(Failure::Test);
+        assert_eq!(exit_code(&tested.unwrap_err()), 6);
+        assert_eq!(exit_code(&anyhow!("other")), 1);
+        // 元のエラーの型も取り出せる
+        let error = Failure::Semantic.wrap(fmt::Error);
+        assert!(error.downcast_ref::<fmt::Error>().is_some());
+        assert_eq!(exit_code(&error), 4);
+    }
+}
@@ -0,0 +1,142 @@
+// 毎回同じフラグを付けなくてすむように、ツールごとの既定値を設定ファイルに書いておく
+//
+// ~/.config/n2t/config.toml（$XDG_CONFIG_HOME があればその下）を読み、カレントディレクトリから
+// 親へたどって最初に見つかった .n2t/config.toml で上書きする。フラグはどちらよりも優先する。
+// 各ツールは自分のセクションだけを読む
+//
+// [jack]
+// out_dir = "build"
+// optimize = 1
+// deny_warnings = true
+//
+// [emu]
+// screen = "tty"
+// keymap = "~/.config/n2t/keys.toml"
+
+use anyhow::{Context, Result, anyhow};
+use serde::de::DeserializeOwned;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+pub const PROJECT_CONFIG: &str = ".n2t/config.toml";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    table: Table,
+}
+
+impl Config {
+    // 設定ファイルがなければ空
+    pub fn load() -> Result<Self> {
+        let cwd = env::current_dir().context("Failed to get the current directory")?;
+        let project = cwd
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG))
+            .find(|path| path.is_file());
+        let files: Vec<PathBuf> = user_config().into_iter().chain(project).collect();
+        Self::from_files(&files)
+    }
+
+    // 後のファイルほど優先する。ないファイルは飛ばす
+    pub fn from_files(paths: &[PathBuf]) -> Result<Self> {
+        let mut table = Table::new();
+        for path in paths.iter().filter(|path| path.is_file()) {
+            let text = fs::read_to_string(path)
+                .context(format!("Failed to read config '{}'", path.display()))?;
+            let file: Table = toml::from_str(&text).map_err(|e| {
+                anyhow!(
+                    "Invalid config '{}': {}",
+                    path.display(),
+                    e.to_string().trim_end()
+                )
+            })?;
+            merge(&mut table, file);
+        }
+        Ok(Config { table })
+    }
+
+    // [name] を T として読む。セクションがなければ T の既定値
+    pub fn section<T: DeserializeOwned + Default>(&self, name: &str) -> Result<T> {
+        match self.table.get(name) {
+            Some(value) => value.clone().try_into().map_err(|e| {
+                anyhow!(
+                    "Invalid [{}] section in the config: {}",
+                    name,
+                    e.to_string().trim_end()
+                )
+            }),
+            None => Ok(T::default()),
+        }
+    }
+}
+
+fn user_config() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("n2t/config.toml"))
+}
+
+// テーブルはキーごとに、それ以外は丸ごと上書きする
+fn merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// 設定ファイルのパスの先頭の ~/ はホームディレクトリ。相対パスはカレントディレクトリから
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Jack {
+        out_dir: Option<PathBuf>,
+        optimize: Option<u8>,
+        deny_warnings: Option<bool>,
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = env::temp_dir().join(format!("core-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user = dir.join("user.toml");
+        let project = dir.join("project.toml");
+        fs::write(&user, "[jack]\noptimize = 1\ndeny_warnings = true\n").unwrap();
+        fs::write(&project, "[jack]\noptimize = 2\nout_dir = \"build\"\n").unwrap();
+        let config = Config::from_files(&[user.clone(), project, dir.join("missing.toml")]);
+        fs::write(&user, "[jack]\noptimise = 1\n").unwrap();
+        let invalid = Config::from_files(&[user]).unwrap().section::<Jack>("jack");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(
+            config.section::<Jack>("jack").unwrap(),
+            Jack {
+                out_dir: Some(PathBuf::from("build")),
+                optimize: Some(2),
+                deny_warnings: Some(true),
+            }
+        );
+        assert_eq!(config.section::<Jack>("emu").unwrap(), Jack::default());
+        assert!(invalid.is_err());
+    }
+}
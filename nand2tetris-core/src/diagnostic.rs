@@ -0,0 +1,222 @@
+// エラーの位置の付け方と、端末への表示。Jack と HDL の診断は列まで持つが、重さの区別と
+// 表示の形はどのツールでも同じ
+//
+// Main.jack: Line 3, column 5: error: Expected ';' but found '}'
+// Main.vm: Line 7: error: Unknown segment: 'stack'
+
+use std::{
+    fmt,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::cli::{Style, failure, paint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    fn style(self) -> Style {
+        match self {
+            Severity::Warning => Style::Warning,
+            Severity::Error => Style::Error,
+        }
+    }
+}
+
+// 診断の 1 行目。続けてソースの抜き出しなどを付けてもよい
+pub fn header(severity: Severity, location: &str, message: &str) -> String {
+    format!(
+        "{}: {}: {}",
+        paint(Style::Location, location),
+        paint(severity.style(), severity.label()),
+        message
+    )
+}
+
+// 場所の分からない診断。「error: ...」
+pub fn bare(severity: Severity, message: &str) -> String {
+    format!("{}: {}", paint(severity.style(), severity.label()), message)
+}
+
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+// 標準エラーに出して数える。数は終わりのまとめの行に使う
+pub fn emit(severity: Severity, text: &str) {
+    match severity {
+        Severity::Warning => &WARNINGS,
+        Severity::Error => &ERRORS,
+    }
+    .fetch_add(1, Ordering::Relaxed);
+    eprintln!("{}", text);
+}
+
+// 「1 error」「2 errors」
+pub fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+// 「2 errors, 1 warning」。何も出していなければ None
+pub fn summary() -> Option<String> {
+    let count = |n: usize, severity: Severity| (n > 0).then(|| count(n, severity.label()));
+    let errors = ERRORS.load(Ordering::Relaxed);
+    let warnings = WARNINGS.load(Ordering::Relaxed);
+    let parts: Vec<String> = [
+        count(errors, Severity::Error),
+        count(warnings, Severity::Warning),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if parts.is_empty() {
+        return None;
+    }
+    let severity = if errors > 0 {
+        Severity::Error
+    } else {
+        Severity::Warning
+    };
+    Some(paint(severity.style(), &parts.join(", ")))
+}
+
+// 1 行に 1 命令のソース（.asm と .vm）のエラー。
+// file があれば「Foo.vm: Line 3: ...」、なければ「Line 3: ...」
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    pub file: Option<String>,
+    pub line: usize,
+    pub message: String,
+}
+
+impl LineError {
+    pub fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}: Line {}", file, self.line),
+            None => format!("Line {}", self.line),
+        }
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location(), self.message)
+    }
+}
+
+impl std::error::Error for LineError {}
+
+pub fn at_line(line: usize, error: impl fmt::Display) -> anyhow::Error {
+    LineError {
+        file: None,
+        line,
+        message: error.to_string(),
+    }
+    .into()
+}
+
+pub fn in_file(file: &str, line: usize, error: impl fmt::Display) -> anyhow::Error {
+    LineError {
+        file: Some(file.to_string()),
+        line,
+        message: error.to_string(),
+    }
+    .into()
+}
+
+// エラーがファイル名のない LineError だけなら、ファイル名を付ける。ほかのエラーはそのまま
+pub fn with_file(error: anyhow::Error, file: &str) -> anyhow::Error {
+    match line_error(&error) {
+        Some(line_error) if line_error.file.is_none() => {
+            let file = file.to_string();
+            replace_file(&error, file)
+        }
+        _ => error,
+    }
+}
+
+// LineError のファイル名の前にディレクトリを付ける。ファイル名だけを付けるライブラリのエラーを、
+// 入力のパスに合わせる（--sarif の場所にもなる）
+pub fn in_dir(error: anyhow::Error, dir: &Path) -> anyhow::Error {
+    match line_error(&error).and_then(|line_error| line_error.file.as_ref()) {
+        Some(file) if !dir.as_os_str().is_empty() => {
+            let file = dir.join(file).display().to_string();
+            replace_file(&error, file)
+        }
+        _ => error,
+    }
+}
+
+// line_error のあるエラーのファイル名を変える。付いていた失敗の種類は付け直す
+fn replace_file(error: &anyhow::Error, file: String) -> anyhow::Error {
+    let line_error = LineError {
+        file: Some(file),
+        ..line_error(error).expect("caller checked").clone()
+    };
+    match failure(error) {
+        Some(failure) => failure.wrap(line_error),
+        None => line_error.into(),
+    }
+}
+
+// 外側に文脈の付いていない LineError
+pub fn line_error(error: &anyhow::Error) -> Option<&LineError> {
+    error
+        .downcast_ref::<LineError>()
+        .filter(|line_error| line_error.to_string() == error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Failure;
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count(1, "error"), "1 error");
+        assert_eq!(count(2, "error"), "2 errors");
+        assert_eq!(count(0, "warning"), "0 warnings");
+    }
+
+    #[test]
+    fn test_line_error() {
+        assert_eq!(
+            at_line(3, "undefined symbol: LOOP").to_string(),
+            "Line 3: undefined symbol: LOOP"
+        );
+        let error = in_file("Main.vm", 7, "Unknown segment: 'stack'");
+        assert_eq!(
+            error.to_string(),
+            "Main.vm: Line 7: Unknown segment: 'stack'"
+        );
+        assert_eq!(error.downcast_ref::<LineError>().unwrap().line, 7);
+        assert_eq!(
+            with_file(at_line(2, "invalid comp pattern: Q"), "Max.asm").to_string(),
+            "Max.asm: Line 2: invalid comp pattern: Q"
+        );
+        let error = with_file(Failure::Parse.wrap(at_line(2, "invalid")), "Max.asm");
+        assert_eq!(error.to_string(), "Max.asm: Line 2: invalid");
+        assert_eq!(failure(&error), Some(Failure::Parse));
+        let error = in_dir(error, Path::new("projects/06"));
+        assert_eq!(error.to_string(), "projects/06/Max.asm: Line 2: invalid");
+        assert_eq!(failure(&error), Some(Failure::Parse));
+        assert_eq!(
+            header(Severity::Warning, "Main.jack: Line 1, column 5", "unused"),
+            "Main.jack: Line 1, column 5: warning: unused"
+        );
+    }
+}
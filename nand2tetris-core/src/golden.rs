@@ -0,0 +1,155 @@
+// 既知の入力を変換した結果を、コミットしてある参照出力（ゴールデンファイル）と比べる
+//
+// コーパスのディレクトリにある入力ファイル（と、複数ファイルの入力になるサブディレクトリ）ごとに、
+// 拡張子を出力のものに変えたファイルが参照出力。N2T_BLESS=1 で実行すると比べずに書き直す。
+// 入力のない参照出力は古いものとして失敗にする（書き直すときは消す）
+//
+// N2T_BLESS=1 cargo test --test golden
+
+use anyhow::{Context, Result, bail};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+pub const BLESS: &str = "N2T_BLESS";
+
+pub fn blessing() -> bool {
+    env::var_os(BLESS).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+// input の拡張子のファイルとサブディレクトリを translate し、output の拡張子の参照出力と比べる。
+// 全部のケースを比べてから、違ったものをまとめて返す
+pub fn check_corpus(
+    corpus: &Path,
+    input: &str,
+    output: &str,
+    translate: impl Fn(&Path) -> Result<String>,
+) -> Result<()> {
+    check_corpus_with(corpus, input, output, translate, blessing())
+}
+
+fn check_corpus_with(
+    corpus: &Path,
+    input: &str,
+    output: &str,
+    translate: impl Fn(&Path) -> Result<String>,
+    bless: bool,
+) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(corpus)
+        .context(format!("Failed to read directory '{}'", corpus.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+
+    let mut cases = Vec::new();
+    let mut failures = Vec::new();
+    for path in &entries {
+        if path.is_dir() || path.extension().is_some_and(|ext| ext == input) {
+            let expected = path.with_extension(output);
+            cases.push(expected.clone());
+            let actual =
+                translate(path).context(format!("Failed to translate '{}'", name(path)))?;
+            if bless {
+                fs::write(&expected, actual)
+                    .context(format!("Failed to write '{}'", expected.display()))?;
+            } else if let Err(e) = check(&expected, &actual) {
+                failures.push(e.to_string());
+            }
+        }
+    }
+    for path in &entries {
+        if path.is_file()
+            && path.extension().is_some_and(|ext| ext == output)
+            && !cases.contains(path)
+        {
+            if bless {
+                fs::remove_file(path).context(format!("Failed to remove '{}'", path.display()))?;
+            } else {
+                failures.push(format!(
+                    "{}: no input for this reference output",
+                    name(path)
+                ));
+            }
+        }
+    }
+    if cases.is_empty() {
+        bail!("No .{} files found in '{}'", input, corpus.display());
+    }
+    if !failures.is_empty() {
+        bail!(
+            "Reference outputs differ (run with {}=1 to update them)\n{}",
+            BLESS,
+            failures.join("\n")
+        );
+    }
+    Ok(())
+}
+
+// 最初に違う行と、違う行数を返す
+pub fn check(expected: &Path, actual: &str) -> Result<()> {
+    let Ok(text) = fs::read_to_string(expected) else {
+        bail!("{}: missing reference output", name(expected));
+    };
+    if text == actual {
+        return Ok(());
+    }
+    let expected_lines: Vec<&str> = text.lines().collect();
+    let actual_lines: Vec<&str> = actual.lines().collect();
+    let length = expected_lines.len().max(actual_lines.len());
+    let differing: Vec<usize> = (0..length)
+        .filter(|&i| expected_lines.get(i) != actual_lines.get(i))
+        .collect();
+    let Some(&first) = differing.first() else {
+        bail!("{}: differs only in line endings", name(expected));
+    };
+    bail!(
+        "{}: Line {}: {} differing line{}\n  expected: {}\n  actual:   {}",
+        name(expected),
+        first + 1,
+        differing.len(),
+        if differing.len() == 1 { "" } else { "s" },
+        expected_lines.get(first).unwrap_or(&"<end of file>"),
+        actual_lines.get(first).unwrap_or(&"<end of file>")
+    )
+}
+
+fn name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_corpus() {
+        let dir = env::temp_dir().join(format!("core-golden-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Pair")).unwrap();
+        fs::write(dir.join("Same.in"), "a\nb\n").unwrap();
+        fs::write(dir.join("Same.out"), "A\nB\n").unwrap();
+        fs::write(dir.join("Changed.in"), "a\nc\n").unwrap();
+        fs::write(dir.join("Changed.out"), "A\nB\n").unwrap();
+        fs::write(dir.join("Pair/x.in"), "x\n").unwrap();
+        fs::write(dir.join("Stale.out"), "S\n").unwrap();
+        let upper = |path: &Path| -> Result<String> {
+            if path.is_dir() {
+                return Ok("PAIR\n".to_string());
+            }
+            Ok(fs::read_to_string(path)?.to_uppercase())
+        };
+        let result = check_corpus_with(&dir, "in", "out", upper, false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("Reference outputs differ (run with N2T_BLESS=1"));
+        assert!(
+            message.contains("Changed.out: Line 2: 1 differing line\n  expected: B\n  actual:   C")
+        );
+        assert!(message.contains("Pair.out: missing reference output"));
+        assert!(message.contains("Stale.out: no input for this reference output"));
+        assert!(!message.contains("Same.out"));
+    }
+}
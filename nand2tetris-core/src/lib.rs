@@ -0,0 +1,12 @@
+// アセンブラ・VM 変換器・Jack コンパイラ・HDL シミュレータ・エミュレータで共有するもの
+pub mod ci;
+pub mod cli;
+pub mod config;
+pub mod diagnostic;
+pub mod golden;
+pub mod random;
+pub mod sarif;
+pub mod source;
+pub mod symbols;
+pub mod test_report;
+pub mod timeout;
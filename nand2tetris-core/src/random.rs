@@ -0,0 +1,61 @@
+// 再現できる乱数（xorshift64*）。同じ seed なら同じ列になる
+//
+// HDL の等価性の確認や、差分テスト用のプログラムの生成に使う
+
+#[derive(Debug, Clone)]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // 0 のままだとずっと 0
+        Random(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // 0..n の一様な乱数。n は 1 以上
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() >> 32) % n as u64) as usize
+    }
+
+    // 1/n の確率で true
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    // 一様な乱数だけでは 0 や -1 のような境目の値がほとんど出ないので、8 回に 1 回はそこから選ぶ
+    pub fn word(&mut self) -> u16 {
+        const EDGES: [u16; 5] = [0, 1, 0x7fff, 0x8000, 0xffff];
+        let random = self.next_u64();
+        if random & 7 == 0 {
+            EDGES[(random >> 3) as usize % EDGES.len()]
+        } else {
+            (random >> 32) as u16
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Random::new(7);
+        let mut b = Random::new(7);
+        let mut c = Random::new(8);
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..4).map(|_| c.next_u64()).collect::<Vec<_>>());
+        assert!((0..100).all(|_| a.below(3) < 3));
+    }
+}
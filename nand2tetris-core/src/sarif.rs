@@ -0,0 +1,135 @@
+// エラーと警告を SARIF 2.1.0 で書く
+//
+// GitHub のコードスキャン（upload-sarif）などが、PR の該当する行に注釈として表示する。
+// --sarif を付けたツールは、場所の分かる診断をここに集め、finish で書く
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::{fs, path::PathBuf, sync::Mutex};
+
+use crate::diagnostic::Severity;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    // 表示したとおりのパス（CI ではリポジトリからの相対パス）
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+// (ツール名, 書き出すファイル)。None なら集めない
+static OUTPUT: Mutex<Option<(String, PathBuf)>> = Mutex::new(None);
+static FINDINGS: Mutex<Vec<Finding>> = Mutex::new(Vec::new());
+
+pub fn set_sarif(tool: &str, path: Option<PathBuf>) {
+    *OUTPUT.lock().unwrap() = path.map(|path| (tool.to_string(), path));
+}
+
+pub fn record(finding: Finding) {
+    if OUTPUT.lock().unwrap().is_some() {
+        FINDINGS.lock().unwrap().push(finding);
+    }
+}
+
+// 診断がなくても書く（前回の注釈を消すため）
+pub fn write() -> Result<()> {
+    let Some((tool, path)) = OUTPUT.lock().unwrap().clone() else {
+        return Ok(());
+    };
+    let text = to_sarif(&tool, &FINDINGS.lock().unwrap());
+    fs::write(&path, text).context(format!("Failed to write '{}'", path.display()))
+}
+
+pub fn to_sarif(tool: &str, findings: &[Finding]) -> String {
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|finding| {
+            let mut region = json!({ "startLine": finding.line });
+            if let Some(column) = finding.column {
+                region["startColumn"] = json!(column);
+            }
+            json!({
+                "ruleId": finding.severity.label(),
+                "level": finding.severity.label(),
+                "message": { "text": finding.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": {
+                            "uri": finding.file.replace('\\', "/"),
+                            "uriBaseId": "%SRCROOT%",
+                        },
+                        "region": region,
+                    },
+                }],
+            })
+        })
+        .collect();
+    // ルールは重さごとに 1 つ。エラーと警告の文面はルールではなく結果に書く
+    let rules: Vec<Value> = [Severity::Error, Severity::Warning]
+        .into_iter()
+        .map(|severity| {
+            let name = match severity {
+                Severity::Error => "Errors",
+                Severity::Warning => "Warnings",
+            };
+            json!({
+                "id": severity.label(),
+                "shortDescription": { "text": format!("{} reported by {}", name, tool) },
+                "defaultConfiguration": { "level": severity.label() },
+            })
+        })
+        .collect();
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": { "name": tool, "rules": rules } },
+            "results": results,
+        }],
+    });
+    serde_json::to_string_pretty(&sarif).expect("JSON values serialize") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_sarif() {
+        let findings = [
+            Finding {
+                severity: Severity::Warning,
+                file: "src\\Main.jack".to_string(),
+                line: 3,
+                column: Some(5),
+                message: "Variable 'x' is never read".to_string(),
+            },
+            Finding {
+                severity: Severity::Error,
+                file: "Max.asm".to_string(),
+                line: 12,
+                column: None,
+                message: "invalid comp pattern: Q".to_string(),
+            },
+        ];
+        let sarif: Value = serde_json::from_str(&to_sarif("nand2tetris-jack", &findings)).unwrap();
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(run["tool"]["driver"]["name"], "nand2tetris-jack");
+        assert_eq!(run["tool"]["driver"]["rules"][1]["id"], "warning");
+
+        let warning = &run["results"][0];
+        assert_eq!(warning["level"], "warning");
+        assert_eq!(warning["message"]["text"], "Variable 'x' is never read");
+        let location = &warning["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/Main.jack");
+        assert_eq!(
+            location["region"],
+            json!({ "startLine": 3, "startColumn": 5 })
+        );
+        let region = &run["results"][1]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region, &json!({ "startLine": 12 }));
+    }
+}
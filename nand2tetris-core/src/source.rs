@@ -0,0 +1,30 @@
+// .asm と .vm のソースの読み方。// から行末まではコメントで、前後の空白と空行は無視する
+
+// コメントと前後の空白を除いた行
+pub fn strip_comment(line: &str) -> &str {
+    line.split("//").next().unwrap_or("").trim()
+}
+
+// 空でない行と、その行番号（1 始まり。空行・コメントも数える）
+pub fn code_lines(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, strip_comment(line)))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_lines() {
+        let source = "// header\n\n  @2 // two\nD=A\n   \n(END)";
+        assert_eq!(
+            code_lines(source).collect::<Vec<_>>(),
+            [(3, "@2"), (4, "D=A"), (6, "(END)")]
+        );
+        assert_eq!(strip_comment("push constant 1//x"), "push constant 1");
+    }
+}
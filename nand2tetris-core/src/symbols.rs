@@ -0,0 +1,53 @@
+// Hack の定義済みシンボル
+
+pub const SCREEN: u16 = 16384;
+pub const KBD: u16 = 24576;
+
+// R0..R15 を除く
+pub const PREDEFINED: [(&str, u16); 7] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", SCREEN),
+    ("KBD", KBD),
+];
+
+// R07 のような書き方は認めない
+pub fn predefined(name: &str) -> Option<u16> {
+    if let Some(&(_, address)) = PREDEFINED.iter().find(|(symbol, _)| *symbol == name) {
+        return Some(address);
+    }
+    name.strip_prefix('R')
+        .and_then(|n| n.parse::<u16>().ok())
+        .filter(|&n| n <= 15 && name == format!("R{n}"))
+}
+
+pub fn is_predefined(name: &str) -> bool {
+    predefined(name).is_some()
+}
+
+// R0..R15 も含めたすべて
+pub fn all() -> impl Iterator<Item = (String, u16)> {
+    (0..16).map(|n| (format!("R{n}"), n)).chain(
+        PREDEFINED
+            .iter()
+            .map(|&(name, address)| (name.to_string(), address)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predefined() {
+        assert_eq!(predefined("THAT"), Some(4));
+        assert_eq!(predefined("R15"), Some(15));
+        assert_eq!(predefined("R16"), None);
+        assert_eq!(predefined("R07"), None);
+        assert_eq!(predefined("LOOP"), None);
+        assert_eq!(all().count(), 23);
+    }
+}
@@ -0,0 +1,206 @@
+// テストの結果を CI が読める形（JUnit XML と TAP）で書く
+//
+// GitLab・Jenkins・GitHub Actions のテスト集計は JUnit XML を、prove などは TAP を読む。
+// 1 本の .tst（か、実行後の確認）が 1 つのテストケース
+
+use anyhow::{Context, Result};
+use clap::Args;
+use std::{fs, path::PathBuf, time::Duration};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    // JUnit の testsuite。続いた同じ名前のケースをまとめる
+    pub suite: String,
+    pub name: String,
+    pub duration: Duration,
+    // 失敗の理由（比較の差分など）。合格なら None
+    pub failure: Option<String>,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ReportArgs {
+    /// Also write the results as JUnit XML to this file ("-" for stdout)
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<PathBuf>,
+    /// Also write the results as TAP to this file ("-" for stdout)
+    #[arg(long, value_name = "FILE")]
+    pub tap: Option<PathBuf>,
+}
+
+impl ReportArgs {
+    // 標準出力に書くなら、ほかの出力は出さない
+    pub fn to_stdout(&self) -> bool {
+        [&self.junit, &self.tap]
+            .into_iter()
+            .flatten()
+            .any(|path| path.as_os_str() == "-")
+    }
+
+    pub fn write(&self, cases: &[TestCase]) -> Result<()> {
+        for (path, text) in [(&self.junit, junit(cases)), (&self.tap, tap(cases))] {
+            match path {
+                Some(path) if path.as_os_str() == "-" => print!("{}", text),
+                Some(path) => fs::write(path, text)
+                    .context(format!("Failed to write '{}'", path.display()))?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn junit(cases: &[TestCase]) -> String {
+    let failures = |cases: &[TestCase]| cases.iter().filter(|c| c.failure.is_some()).count();
+    let time = |cases: &[TestCase]| cases.iter().map(|c| c.duration).sum::<Duration>();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        cases.len(),
+        failures(cases),
+        time(cases).as_secs_f64()
+    ));
+    for suite in cases.chunk_by(|a, b| a.suite == b.suite) {
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+            escape(&suite[0].suite),
+            suite.len(),
+            failures(suite),
+            time(suite).as_secs_f64()
+        ));
+        for case in suite {
+            let open = format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape(&case.name),
+                escape(&case.suite),
+                case.duration.as_secs_f64()
+            );
+            match &case.failure {
+                None => xml.push_str(&format!("{}/>\n", open)),
+                Some(failure) => xml.push_str(&format!(
+                    "{}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                    open,
+                    escape(failure.lines().next().unwrap_or_default()),
+                    escape(failure)
+                )),
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+// TAP version 13。失敗の理由は YAML のブロックに書く
+pub fn tap(cases: &[TestCase]) -> String {
+    let mut text = format!("TAP version 13\n1..{}\n", cases.len());
+    for (i, case) in cases.iter().enumerate() {
+        let name = match case.suite.as_str() {
+            "" => case.name.clone(),
+            suite => format!("{}/{}", suite, case.name),
+        };
+        // # は TAP のディレクティブになる
+        let name = name.replace('#', "\\#");
+        match &case.failure {
+            None => text.push_str(&format!("ok {} - {}\n", i + 1, name)),
+            Some(failure) => {
+                text.push_str(&format!(
+                    "not ok {} - {}\n  ---\n  message: |\n",
+                    i + 1,
+                    name
+                ));
+                for line in failure.lines() {
+                    text.push_str(&format!("    {}\n", line));
+                }
+                text.push_str(&format!(
+                    "  duration_ms: {}\n  ...\n",
+                    case.duration.as_millis()
+                ));
+            }
+        }
+    }
+    text
+}
+
+// XML の特殊文字を置き換え、XML に書けない制御文字は落とす
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cases() -> Vec<TestCase> {
+        let case = |suite: &str, name: &str, millis, failure: Option<&str>| TestCase {
+            suite: suite.to_string(),
+            name: name.to_string(),
+            duration: Duration::from_millis(millis),
+            failure: failure.map(String::from),
+        };
+        vec![
+            case("01", "And.tst", 10, None),
+            case(
+                "01",
+                "Or.tst",
+                20,
+                Some(
+                    "Comparison failure at line 2 of 'Or.cmp'\n  expected: | 0 | 1 |\n  actual:   | 0 | 0 |",
+                ),
+            ),
+            case("02", "Add16 <&>.tst", 5, None),
+        ]
+    }
+
+    #[test]
+    fn test_junit() {
+        assert_eq!(
+            junit(&cases()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites tests=\"3\" failures=\"1\" time=\"0.035\">\n\
+             \x20 <testsuite name=\"01\" tests=\"2\" failures=\"1\" time=\"0.030\">\n\
+             \x20   <testcase name=\"And.tst\" classname=\"01\" time=\"0.010\"/>\n\
+             \x20   <testcase name=\"Or.tst\" classname=\"01\" time=\"0.020\">\n\
+             \x20     <failure message=\"Comparison failure at line 2 of &apos;Or.cmp&apos;\">\
+             Comparison failure at line 2 of &apos;Or.cmp&apos;\n  expected: | 0 | 1 |\n  actual:   | 0 | 0 |\
+             </failure>\n\
+             \x20   </testcase>\n\
+             \x20 </testsuite>\n\
+             \x20 <testsuite name=\"02\" tests=\"1\" failures=\"0\" time=\"0.005\">\n\
+             \x20   <testcase name=\"Add16 &lt;&amp;&gt;.tst\" classname=\"02\" time=\"0.005\"/>\n\
+             \x20 </testsuite>\n\
+             </testsuites>\n"
+        );
+    }
+
+    #[test]
+    fn test_tap() {
+        assert_eq!(
+            tap(&cases()),
+            "TAP version 13\n\
+             1..3\n\
+             ok 1 - 01/And.tst\n\
+             not ok 2 - 01/Or.tst\n\
+             \x20 ---\n\
+             \x20 message: |\n\
+             \x20   Comparison failure at line 2 of 'Or.cmp'\n\
+             \x20     expected: | 0 | 1 |\n\
+             \x20     actual:   | 0 | 0 |\n\
+             \x20 duration_ms: 20\n\
+             \x20 ...\n\
+             ok 3 - 02/Add16 <&>.tst\n"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+// テストの時間切れ（--timeout と --total-timeout）
+//
+// シミュレーションは同じスレッドで動くので、止めるのは協調的に行う。テストの実行器は
+// コマンドの合間や一定のサイクルごとに check を呼び、期限を過ぎていれば TimedOut の
+// エラーで抜ける。期限はスレッドごとに持つので、並べて実行するスクリプトはそれぞれの
+// スレッドの期限で止まる
+
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::cli::Failure;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct TimeoutArgs {
+    /// Fail a test that runs longer than this (e.g. "10s", "500ms", "2m"; a plain number is seconds)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+    /// Stop after this much time in total; the tests that have not finished are reported as timeouts
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub total_timeout: Option<Duration>,
+}
+
+impl TimeoutArgs {
+    // 全体の期限はここから数える
+    pub fn budget(&self) -> Budget {
+        Budget {
+            per_test: self.timeout,
+            total: self
+                .total_timeout
+                .map(|limit| (Instant::now() + limit, limit)),
+        }
+    }
+}
+
+// テストごとの制限と、全体の (期限, 制限)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    per_test: Option<Duration>,
+    total: Option<(Instant, Duration)>,
+}
+
+// 時間切れのエラー。表示はどちらの制限かを含む
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    pub limit: Duration,
+    pub total: bool,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.total {
+            write!(
+                f,
+                "Timed out: the total time limit of {} ran out (--total-timeout)",
+                format_duration(self.limit)
+            )
+        } else {
+            write!(
+                f,
+                "Timed out after {} (--timeout)",
+                format_duration(self.limit)
+            )
+        }
+    }
+}
+
+impl Error for TimedOut {}
+
+thread_local! {
+    static DEADLINE: Cell<Option<(Instant, TimedOut)>> = const { Cell::new(None) };
+}
+
+impl Budget {
+    // 1 つのテストを期限付きで実行する。全体の期限を過ぎていれば実行せずに時間切れにする。
+    // 入れ子にしたときは外側の期限のほうが早ければそちらのまま
+    pub fn run<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let now = Instant::now();
+        let per_test = self.per_test.map(|limit| {
+            (
+                now + limit,
+                TimedOut {
+                    limit,
+                    total: false,
+                },
+            )
+        });
+        let total = self
+            .total
+            .map(|(end, limit)| (end, TimedOut { limit, total: true }));
+        if let Some((end, timed_out)) = total
+            && now >= end
+        {
+            return Err(Failure::Test.wrap(anyhow!(timed_out)));
+        }
+        let outer = DEADLINE.get();
+        let deadline = [outer, per_test, total]
+            .into_iter()
+            .flatten()
+            .min_by_key(|(end, _)| *end);
+        DEADLINE.set(deadline);
+        let result = f();
+        DEADLINE.set(outer);
+        result
+    }
+}
+
+// 期限を過ぎていれば時間切れのエラー。期限がなければ何もしない
+pub fn check() -> Result<()> {
+    match DEADLINE.get() {
+        Some((end, timed_out)) if Instant::now() >= end => {
+            Err(Failure::Test.wrap(anyhow!(timed_out)))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+// 10s、500ms、1.5m、2h。単位がなければ秒
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        unit => bail!("Unknown unit '{}' (expected ms, s, m or h)", unit),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration '{}' (e.g. 10s, 500ms, 2m)", text))?;
+    Duration::try_from_secs_f64(number * scale)
+        .ok()
+        .filter(|duration| !duration.is_zero())
+        .ok_or_else(|| anyhow!("The duration must be positive: '{}'", text))
+}
+
+// 2m、10s、1.5s、500ms
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis >= 60_000 && millis.is_multiple_of(60_000) {
+        format!("{}m", millis / 60_000)
+    } else if millis >= 1000 {
+        format!("{}s", duration.as_secs_f64())
+    } else {
+        format!("{}ms", millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("10s").unwrap(), Duration::from_secs(10));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("3").unwrap(), Duration::from_secs(3));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("10 days").is_err());
+        assert!(parse_duration("s").is_err());
+        assert_eq!(format_duration(Duration::from_secs(120)), "2m");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_millis(20)), "20ms");
+    }
+
+    #[test]
+    fn test_budget() {
+        let args = TimeoutArgs {
+            timeout: Some(Duration::from_millis(20)),
+            total_timeout: None,
+        };
+        let budget = args.budget();
+        // 止まらないループも check で抜ける
+        let error = budget
+            .run(|| -> Result<()> {
+                loop {
+                    check()?;
+                }
+            })
+            .unwrap_err();
+        assert!(is_timeout(&error));
+        assert_eq!(error.to_string(), "Timed out after 20ms (--timeout)");
+        assert_eq!(crate::cli::failure(&error), Some(Failure::Test));
+        // 期限は run の外には残らない
+        assert!(check().is_ok());
+        assert!(budget.run(check).is_ok());
+
+        // 全体の期限を過ぎたら、残りのテストは実行しない
+        let args = TimeoutArgs {
+            timeout: None,
+            total_timeout: Some(Duration::from_millis(10)),
+        };
+        let budget = args.budget();
+        thread::sleep(Duration::from_millis(20));
+        let error = budget.run(|| -> Result<()> { panic!() }).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Timed out: the total time limit of 10ms ran out (--total-timeout)"
+        );
+    }
+}
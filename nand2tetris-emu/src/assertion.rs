@@ -0,0 +1,157 @@
+use anyhow::{Context, Result, bail};
+use std::fmt;
+
+use crate::{Cpu, dump::parse_address};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    A,
+    D,
+    Pc,
+    Ram(u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::A => write!(f, "A"),
+            Target::D => write!(f, "D"),
+            Target::Pc => write!(f, "PC"),
+            Target::Ram(address) => write!(f, "RAM[{}]", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Assertion {
+    pub target: Target,
+    pub expected: u16,
+}
+
+impl Assertion {
+    pub fn actual(&self, cpu: &Cpu) -> u16 {
+        match self.target {
+            Target::A => cpu.a,
+            Target::D => cpu.d,
+            Target::Pc => cpu.pc,
+            Target::Ram(address) => cpu.ram[address as usize],
+        }
+    }
+}
+
+// "RAM[2]=6, RAM[0]=258, D=-1" のような指定を解析する
+pub fn parse_assertions(spec: &str) -> Result<Vec<Assertion>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let Some((target, value)) = item.split_once('=') else {
+                bail!("Invalid assertion '{}': expected TARGET=VALUE", item);
+            };
+            Ok(Assertion {
+                target: parse_target(target.trim())?,
+                expected: parse_value(value.trim())
+                    .context(format!("Invalid assertion '{}'", item))?,
+            })
+        })
+        .collect()
+}
+
+fn parse_target(text: &str) -> Result<Target> {
+    Ok(match text {
+        "A" => Target::A,
+        "D" => Target::D,
+        "PC" => Target::Pc,
+        _ => {
+            // RAM[...] の括弧は省略できる（R2=6 など）
+            let address = text
+                .strip_prefix("RAM[")
+                .and_then(|rest| rest.strip_suffix(']'))
+                .unwrap_or(text);
+            Target::Ram(parse_address(address)?)
+        }
+    })
+}
+
+// 10進（負数可）または 0x 付きの16進
+fn parse_value(text: &str) -> Result<u16> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).context(format!("Invalid value '{}'", text));
+    }
+    let value: i32 = text.parse().context(format!("Invalid value '{}'", text))?;
+    if !(i16::MIN as i32..=u16::MAX as i32).contains(&value) {
+        bail!("Value out of range: {}", text);
+    }
+    Ok(value as u16)
+}
+
+// 不一致をすべて列挙し、1つでもあればエラーにする
+pub fn check_assertions(cpu: &Cpu, assertions: &[Assertion]) -> Result<()> {
+    let failures: Vec<String> = assertions
+        .iter()
+        .filter(|a| a.actual(cpu) != a.expected)
+        .map(|a| {
+            format!(
+                "  {}: expected {}, got {}",
+                a.target,
+                a.expected as i16,
+                a.actual(cpu) as i16
+            )
+        })
+        .collect();
+
+    if !failures.is_empty() {
+        bail!(
+            "{} of {} assertions failed:\n{}",
+            failures.len(),
+            assertions.len(),
+            failures.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("RAM[2]=6", Target::Ram(2), 6)]
+    #[case("RAM[SP]=258", Target::Ram(0), 258)]
+    #[case("R15 = -1", Target::Ram(15), 0xffff)]
+    #[case("D=0x7fff", Target::D, 0x7fff)]
+    #[case("PC=10", Target::Pc, 10)]
+    fn test_parse_assertion(#[case] spec: &str, #[case] target: Target, #[case] expected: u16) {
+        assert_eq!(
+            parse_assertions(spec).unwrap(),
+            vec![Assertion { target, expected }]
+        );
+    }
+
+    #[rstest]
+    #[case("RAM[2]")]
+    #[case("RAM[40000]=1")]
+    #[case("RAM[2]=70000")]
+    #[case("X=1")]
+    fn test_parse_assertion_invalid(#[case] spec: &str) {
+        assert!(parse_assertions(spec).is_err());
+    }
+
+    #[test]
+    fn test_check_assertions() {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        cpu.ram[0] = 258;
+        cpu.ram[2] = 5;
+
+        let assertions = parse_assertions("RAM[2]=6, RAM[0]=258").unwrap();
+        let err = check_assertions(&cpu, &assertions).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1 of 2 assertions failed:\n  RAM[2]: expected 6, got 5"
+        );
+
+        cpu.ram[2] = 6;
+        assert!(check_assertions(&cpu, &assertions).is_ok());
+    }
+}
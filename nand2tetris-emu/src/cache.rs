@@ -0,0 +1,169 @@
+// build の途中のものを、入力の内容とツールのバージョンから作ったキーで保存しておく
+//
+// 段階は Jack のクラスごとの VM コードと、VM コードからアセンブル済みのプログラムまで（OS を
+// 含む）。入力が前と同じ段階は飛ばす。エントリは <キー>.<拡張子> のファイルで、1 回のビルドで
+// 使わなかったものは prune で消すので、キャッシュは最後のビルドの分しか大きくならない
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+// 既定では出力先と同じディレクトリに作る
+pub const CACHE_DIR: &str = ".n2t-cache";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    toolchain: String,
+    // このビルドで読み書きしたファイル名
+    used: HashSet<String>,
+    pub stats: Stats,
+}
+
+impl Cache {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).context(format!("Failed to create '{}'", dir.display()))?;
+        Ok(Cache {
+            dir: dir.to_path_buf(),
+            toolchain: toolchain(),
+            used: HashSet::new(),
+            stats: Stats::default(),
+        })
+    }
+
+    // 段階の名前と入力から作るキー。入力の区切りが変わっても同じにならないように長さも入れる
+    pub fn key(&self, stage: &str, inputs: &[&str]) -> String {
+        let mut hash = Fnv::new();
+        for input in [self.toolchain.as_str(), stage].iter().chain(inputs) {
+            hash.write(&(input.len() as u64).to_le_bytes());
+            hash.write(input.as_bytes());
+        }
+        format!("{:016x}", hash.0)
+    }
+
+    // extensions のファイルがすべてあれば、その内容を同じ順で返す
+    pub fn get(&mut self, key: &str, extensions: &[&str]) -> Option<Vec<String>> {
+        let texts: Option<Vec<String>> = extensions
+            .iter()
+            .map(|extension| fs::read_to_string(self.dir.join(entry(key, extension))).ok())
+            .collect();
+        match texts {
+            Some(texts) => {
+                self.stats.hits += 1;
+                self.used
+                    .extend(extensions.iter().map(|extension| entry(key, extension)));
+                Some(texts)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn put(&mut self, key: &str, entries: &[(&str, &str)]) -> Result<()> {
+        for (extension, text) in entries {
+            let name = entry(key, extension);
+            let path = self.dir.join(&name);
+            fs::write(&path, text).context(format!("Failed to write '{}'", path.display()))?;
+            self.used.insert(name);
+        }
+        Ok(())
+    }
+
+    // このビルドで使わなかったエントリを消す。キャッシュのものでないファイルには触らない
+    pub fn prune(&self) -> Result<()> {
+        let entries = fs::read_dir(&self.dir)
+            .context(format!("Failed to read directory '{}'", self.dir.display()))?;
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_entry = name.split_once('.').is_some_and(|(key, _)| {
+                key.len() == 16 && key.bytes().all(|b| b.is_ascii_hexdigit())
+            });
+            if is_entry && !self.used.contains(&name) {
+                fs::remove_file(entry.path())
+                    .context(format!("Failed to remove '{}'", entry.path().display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn entry(key: &str, extension: &str) -> String {
+    format!("{}.{}", key, extension)
+}
+
+// バージョンを上げずにツールを作り直したときも古いエントリを使わないように、実行ファイルの
+// 更新時刻も入れる
+fn toolchain() -> String {
+    let modified = env::current_exe()
+        .and_then(fs::metadata)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+    format!("{} {}", env!("CARGO_PKG_VERSION"), modified)
+}
+
+// FNV-1a。std のハッシュはバージョンによって変わりうるので使わない
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let dir = env::temp_dir().join(format!("emu-cache-{}", std::process::id()));
+        let mut cache = Cache::open(&dir).unwrap();
+        let key = cache.key("vm", &["class Main {}"]);
+        assert_ne!(key, cache.key("vm", &["class Main {", "}"]));
+        assert_eq!(cache.get(&key, &["vm", "lines"]), None);
+        cache
+            .put(&key, &[("vm", "return\n"), ("lines", "[1]")])
+            .unwrap();
+        assert_eq!(
+            cache.get(&key, &["vm", "lines"]),
+            Some(vec!["return\n".to_string(), "[1]".to_string()])
+        );
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let mut cache = Cache::open(&dir).unwrap();
+        let other = cache.key("vm", &["class Other {}"]);
+        cache.put(&other, &[("vm", "")]).unwrap();
+        cache.prune().unwrap();
+        let names: HashSet<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(cache.get(&key, &["vm"]), None);
+        assert_eq!(cache.stats, Stats { hits: 0, misses: 1 });
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            names,
+            HashSet::from([format!("{}.vm", other), "notes.txt".to_string()])
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt::Write, ops::RangeInclusive};
+
+use crate::{Cpu, screen::KBD};
+
+// 2つの実行で値が違った RAM のアドレス
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamDifference {
+    pub address: u16,
+    pub left: u16,
+    pub right: u16,
+}
+
+// ranges が空なら RAM 全体（0..=KBD）を比べる
+pub fn diff_ram(left: &Cpu, right: &Cpu, ranges: &[RangeInclusive<u16>]) -> Vec<RamDifference> {
+    let all = [0..=KBD as u16];
+    let ranges = if ranges.is_empty() { &all[..] } else { ranges };
+    let mut differences: Vec<RamDifference> = ranges
+        .iter()
+        .flat_map(|range| range.clone())
+        .filter(|&address| left.ram[address as usize] != right.ram[address as usize])
+        .map(|address| RamDifference {
+            address,
+            left: left.ram[address as usize],
+            right: right.ram[address as usize],
+        })
+        .collect();
+    differences.sort_by_key(|d| d.address);
+    differences.dedup();
+    differences
+}
+
+// 最初の limit 件を表示する
+pub fn format_differences(differences: &[RamDifference], limit: usize) -> String {
+    let mut out = String::new();
+    for difference in differences.iter().take(limit) {
+        writeln!(
+            out,
+            "RAM[{}]: {} vs {}",
+            difference.address, difference.left as i16, difference.right as i16
+        )
+        .unwrap();
+    }
+    if differences.len() > limit {
+        writeln!(out, "... and {} more", differences.len() - limit).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(cells: &[(usize, u16)]) -> Cpu {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        for &(address, value) in cells {
+            cpu.ram[address] = value;
+        }
+        cpu
+    }
+
+    #[test]
+    fn test_diff_ram() {
+        let left = cpu(&[(0, 256), (16, 3), (300, 0xffff)]);
+        let right = cpu(&[(0, 257), (16, 3), (17, 1), (300, 1)]);
+
+        let all = diff_ram(&left, &right, &[]);
+        let addresses: Vec<u16> = all.iter().map(|d| d.address).collect();
+        assert_eq!(addresses, [0, 17, 300]);
+        assert_eq!(
+            format_differences(&all, 2),
+            "RAM[0]: 256 vs 257\nRAM[17]: 0 vs 1\n... and 1 more\n"
+        );
+
+        // 重なった範囲でも同じアドレスは1回だけ
+        let selected = diff_ram(&left, &right, &[16..=300, 300..=300]);
+        assert_eq!(
+            selected,
+            [
+                RamDifference {
+                    address: 17,
+                    left: 0,
+                    right: 1
+                },
+                RamDifference {
+                    address: 300,
+                    left: 0xffff,
+                    right: 1
+                },
+            ]
+        );
+    }
+}
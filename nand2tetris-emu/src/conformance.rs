@@ -0,0 +1,344 @@
+// 公式の Java ツールと同じ入力を処理して、結果が同じか確かめる
+//
+// .asm は公式の Assembler と nand2tetris-asm でアセンブルして .hack を比べる。.tst は
+// load するものに合わせて公式の HardwareSimulator・CPUEmulator・VMEmulator と `n2t test`
+// で実行し、比較の成否と output-file の .out を比べる。どちらも入力のディレクトリを一時
+// ディレクトリに写してから実行するので、元のファイルは書き換えない
+
+use anyhow::{Context, Result, bail};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    grade::copy_files,
+    suite::{self, Engine},
+};
+
+pub const TOOLS_ENV: &str = "N2T_TOOLS";
+
+// nand2tetris/tools のディレクトリ
+#[derive(Debug, Clone)]
+pub struct Tools {
+    dir: PathBuf,
+}
+
+impl Tools {
+    // dir、$N2T_TOOLS、PATH の順に Assembler.sh（Windows では .bat）のあるディレクトリを探す
+    pub fn find(dir: Option<&Path>) -> Result<Self> {
+        let candidates: Vec<PathBuf> = match dir {
+            Some(dir) => vec![dir.to_path_buf()],
+            None => env::var_os(TOOLS_ENV)
+                .map(PathBuf::from)
+                .into_iter()
+                .chain(env::var_os("PATH").iter().flat_map(env::split_paths))
+                .collect(),
+        };
+        candidates
+            .into_iter()
+            .find(|dir| dir.join(script_name("Assembler")).is_file())
+            .map(|dir| Tools { dir })
+            .with_context(|| match dir {
+                Some(dir) => format!(
+                    "'{}' has no {}: pass the tools directory of the nand2tetris software suite",
+                    dir.display(),
+                    script_name("Assembler")
+                ),
+                None => format!(
+                    "The official nand2tetris tools were not found: pass --tools, set {} or add the tools directory to PATH",
+                    TOOLS_ENV
+                ),
+            })
+    }
+
+    // ツールは引数のファイルを絶対パスで受け取る
+    fn run(&self, tool: &str, input: &Path) -> Result<String> {
+        let script = self.dir.join(script_name(tool));
+        // zip から展開すると実行権限がないことがあるので sh に渡す
+        let mut command = if cfg!(windows) {
+            Command::new(&script)
+        } else {
+            let mut command = Command::new("sh");
+            command.arg(&script);
+            command
+        };
+        let output = command
+            .arg(input)
+            .output()
+            .context(format!("Failed to run '{}'", script.display()))?;
+        Ok(format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+fn script_name(tool: &str) -> String {
+    format!("{}.{}", tool, if cfg!(windows) { "bat" } else { "sh" })
+}
+
+// 入力のファイルと、ディレクトリの下の .asm と .tst を名前順に
+pub fn inputs(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found = Vec::new();
+            find_inputs(path, &mut found)?;
+            found.sort();
+            inputs.extend(found);
+        } else {
+            inputs.push(path.clone());
+        }
+    }
+    Ok(inputs)
+}
+
+fn find_inputs(dir: &Path, inputs: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .context(format!("Failed to read directory '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok())
+    {
+        let path = entry.path();
+        if path.is_dir() {
+            find_inputs(&path, inputs)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "asm" || ext == "tst")
+        {
+            inputs.push(path);
+        }
+    }
+    Ok(())
+}
+
+// 違っていればその説明、同じなら None
+pub fn check(tools: &Tools, input: &Path) -> Result<Option<String>> {
+    match input.extension().and_then(|ext| ext.to_str()) {
+        Some("asm") => check_asm(tools, input),
+        Some("tst") => check_script(tools, input),
+        _ => bail!(
+            "'{}' is neither an .asm file nor a .tst script",
+            input.display()
+        ),
+    }
+}
+
+fn check_asm(tools: &Tools, input: &Path) -> Result<Option<String>> {
+    let source =
+        fs::read_to_string(input).context(format!("Failed to read file '{}'", input.display()))?;
+    let ours = nand2tetris_asm::assemble_program(&source).map(|program| {
+        program
+            .words
+            .iter()
+            .map(|word| format!("{:016b}\n", word))
+            .collect::<String>()
+    });
+
+    let work = Scratch::new()?;
+    let copy = work.copy(input)?;
+    let log = tools.run("Assembler", &copy)?;
+    let official = fs::read_to_string(copy.with_extension("hack")).ok();
+
+    Ok(match (ours, official) {
+        (Ok(ours), Some(official)) => compare("the .hack file", &official, &ours),
+        // どちらも受け付けない
+        (Err(_), None) => None,
+        (Ok(_), None) => Some(format!(
+            "the official assembler rejects it but this one does not: {}",
+            log.trim()
+        )),
+        (Err(e), Some(_)) => Some(format!(
+            "this assembler rejects it but the official one does not: {}",
+            e
+        )),
+    })
+}
+
+fn check_script(tools: &Tools, script: &Path) -> Result<Option<String>> {
+    let source = fs::read_to_string(script)
+        .context(format!("Failed to read file '{}'", script.display()))?;
+    let tool = match suite::engine(&source) {
+        Engine::Hardware => "HardwareSimulator",
+        Engine::Cpu => "CPUEmulator",
+        Engine::Vm => "VMEmulator",
+    };
+    let output = suite::argument(&source, "output-file");
+
+    let official_dir = Scratch::new()?;
+    let official_script = official_dir.copy_dir(script)?;
+    let log = tools.run(tool, &official_script)?;
+    // compare-to がなければ最後まで実行できれば合格
+    let official_passed = log.contains("Comparison ended successfully")
+        || (suite::argument(&source, "compare-to").is_none() && log.contains("End of script"));
+    let official_out = read_output(&official_script, output.as_deref());
+
+    let our_dir = Scratch::new()?;
+    let our_script = our_dir.copy_dir(script)?;
+    let ours = suite::run_script(&our_script, false);
+    let our_out = read_output(&our_script, output.as_deref());
+
+    if official_passed != ours.is_ok() {
+        return Ok(Some(match ours {
+            Ok(_) => format!(
+                "the official {} fails but this one passes: {}",
+                tool,
+                log.trim()
+            ),
+            Err(e) => format!("the official {} passes but this one fails: {}", tool, e),
+        }));
+    }
+    Ok(match (official_out, our_out) {
+        (Some(official), Some(ours)) => compare(
+            &format!("'{}'", output.unwrap_or_default()),
+            &official,
+            &ours,
+        ),
+        (Some(_), None) => Some(format!(
+            "the official {} writes '{}' but this one does not",
+            tool,
+            output.unwrap_or_default()
+        )),
+        (None, Some(_)) => Some(format!(
+            "this one writes '{}' but the official {} does not",
+            output.unwrap_or_default(),
+            tool
+        )),
+        (None, None) => None,
+    })
+}
+
+fn read_output(script: &Path, output: Option<&str>) -> Option<String> {
+    let path = script.parent()?.join(output?);
+    fs::read_to_string(path).ok()
+}
+
+// 行末の \r は Windows 版のツールが書くので比べない
+fn compare(what: &str, official: &str, ours: &str) -> Option<String> {
+    let official: Vec<&str> = official.lines().map(str::trim_end).collect();
+    let ours: Vec<&str> = ours.lines().map(str::trim_end).collect();
+    let line = (0..official.len().max(ours.len())).find(|&i| official.get(i) != ours.get(i))?;
+    Some(format!(
+        "{} differs at line {}\n  official: {}\n  ours:     {}",
+        what,
+        line + 1,
+        official.get(line).unwrap_or(&"<end of file>"),
+        ours.get(line).unwrap_or(&"<end of file>")
+    ))
+}
+
+// 使い終わったら消す一時ディレクトリ
+struct Scratch {
+    dir: PathBuf,
+}
+
+static SCRATCH: AtomicUsize = AtomicUsize::new(0);
+
+impl Scratch {
+    fn new() -> Result<Self> {
+        let dir = env::temp_dir().join(format!(
+            "n2t-conformance-{}-{}",
+            process::id(),
+            SCRATCH.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).context(format!("Failed to create '{}'", dir.display()))?;
+        Ok(Scratch { dir })
+    }
+
+    fn copy(&self, file: &Path) -> Result<PathBuf> {
+        let target = self.dir.join(file.file_name().unwrap_or_default());
+        fs::copy(file, &target).context(format!("Failed to copy '{}'", file.display()))?;
+        Ok(target)
+    }
+
+    // file のディレクトリのファイルを全部写し、写した file を返す
+    fn copy_dir(&self, file: &Path) -> Result<PathBuf> {
+        let dir = file
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        copy_files(dir, &self.dir, |_| true)?;
+        Ok(self.dir.join(file.file_name().unwrap_or_default()))
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, text: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    // Java の代わりに決まった結果を書くツール
+    #[test]
+    fn test_check() {
+        let root = env::temp_dir().join(format!("emu-conformance-{}", process::id()));
+        let tools = root.join("tools");
+        write(
+            &tools,
+            "Assembler.sh",
+            "case \"$1\" in *Bad.asm) echo 'In line 1, Expected computation'; exit 1;; esac\n\
+             printf '0000000000000010\\n1110110000010000\\n' > \"${1%.asm}.hack\"\n",
+        );
+        write(
+            &tools,
+            "HardwareSimulator.sh",
+            "cd \"$(dirname \"$1\")\"\n\
+             printf '|in |out|\\n| 0 | 1 |\\n| 1 | 0 |\\n' > Not.out\n\
+             echo 'End of script'\n",
+        );
+        let inputs = root.join("inputs");
+        write(&inputs, "asm/Same.asm", "@2\nD=A\n");
+        write(&inputs, "asm/Different.asm", "@3\nD=A\n");
+        write(&inputs, "asm/Bad.asm", "D=Q\n");
+        write(
+            &inputs,
+            "01/Not.hdl",
+            "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }",
+        );
+        write(
+            &inputs,
+            "01/Not.tst",
+            "load Not.hdl, output-file Not.out, output-list in out;\n\
+             set in 0, eval, output;\nset in 1, eval, output;\n",
+        );
+
+        let found = Tools::find(Some(&tools));
+        let missing = Tools::find(Some(&inputs));
+        let results: Vec<(String, Option<String>)> = super::inputs(&[inputs])
+            .unwrap()
+            .iter()
+            .map(|input| {
+                let name = input.file_name().unwrap().to_string_lossy().into_owned();
+                (name, check(found.as_ref().unwrap(), input).unwrap())
+            })
+            .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(missing.is_err());
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["Not.tst", "Bad.asm", "Different.asm", "Same.asm"]);
+        assert_eq!(results[0].1, None);
+        assert_eq!(results[1].1, None);
+        assert_eq!(
+            results[2].1.as_deref(),
+            Some(
+                "the .hack file differs at line 1\n  official: 0000000000000010\n  ours:     0000000000000011"
+            )
+        );
+        assert_eq!(results[3].1, None);
+    }
+}
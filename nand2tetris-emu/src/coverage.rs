@@ -0,0 +1,459 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    disasm,
+    symbols::Symbols,
+};
+
+// 条件付きジャンプが分岐した回数としなかった回数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    // 片方にしか進んでいなければその説明
+    fn untested(&self) -> Option<&'static str> {
+        match (self.taken, self.not_taken) {
+            (0, 0) => None,
+            (0, _) => Some("branch never taken"),
+            (_, 0) => Some("branch always taken"),
+            _ => None,
+        }
+    }
+}
+
+// ソースの1行分の集計
+#[derive(Debug, Default)]
+struct LineStats {
+    // 行の命令のうち一番多く実行された回数
+    hits: u64,
+    branches: Vec<Branch>,
+}
+
+// --report に書く集計。lines はソースがあるときだけ数える
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoverageSummary {
+    pub instructions: usize,
+    pub executed: usize,
+    pub branches: usize,
+    pub branches_hit: usize,
+    pub lines: usize,
+    pub lines_hit: usize,
+    pub never_executed: Vec<String>,
+}
+
+// 実行した ROM アドレスと条件付きジャンプの向きを数える
+pub struct Coverage {
+    rom: Vec<u16>,
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, Branch>,
+}
+
+impl Coverage {
+    pub fn new(program: &[u16]) -> Self {
+        Coverage {
+            rom: program.to_vec(),
+            hits: vec![0; program.len()],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits.get(address as usize).copied().unwrap_or_default()
+    }
+
+    pub fn branch(&self, address: u16) -> Option<Branch> {
+        self.branches.get(&address).copied()
+    }
+
+    fn executed(&self) -> usize {
+        self.hits.iter().filter(|&&hits| hits > 0).count()
+    }
+
+    // 分岐の向きごとに数える（LCOV と同じ）
+    fn branches_hit(&self) -> (usize, usize) {
+        let hit = self
+            .branches
+            .values()
+            .map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize)
+            .sum();
+        (hit, self.conditional_jumps() * 2)
+    }
+
+    fn conditional_jumps(&self) -> usize {
+        self.rom
+            .iter()
+            .filter(|&&instruction| is_conditional_jump(instruction))
+            .count()
+    }
+
+    // ファイル名（拡張子なし）→ 行番号 → 集計
+    fn source_lines<'a>(
+        &self,
+        symbols: &'a Symbols,
+    ) -> BTreeMap<&'a str, BTreeMap<usize, LineStats>> {
+        let mut files: BTreeMap<&'a str, BTreeMap<usize, LineStats>> = BTreeMap::new();
+        for address in 0..self.rom.len() as u16 {
+            let Some(source) = symbols.source_at(address) else {
+                continue;
+            };
+            let line = files
+                .entry(source.file.as_str())
+                .or_default()
+                .entry(source.line)
+                .or_default();
+            line.hits = line.hits.max(self.hits(address));
+            if is_conditional_jump(self.rom[address as usize]) {
+                line.branches.push(self.branch(address).unwrap_or_default());
+            }
+        }
+        files
+    }
+
+    // 一度も実行されなかった関数（VM 関数がなければラベル）
+    fn unreached<'a>(&self, symbols: &'a Symbols) -> Vec<(u16, &'a str)> {
+        symbols
+            .regions()
+            .into_iter()
+            .filter(|&(address, _)| (address as usize) < self.rom.len() && self.hits(address) == 0)
+            .collect()
+    }
+
+    pub fn summary(&self, symbols: &Symbols) -> CoverageSummary {
+        let (branches_hit, branches) = self.branches_hit();
+        let lines: Vec<u64> = self
+            .source_lines(symbols)
+            .values()
+            .flat_map(|lines| lines.values().map(|stats| stats.hits))
+            .collect();
+        CoverageSummary {
+            instructions: self.rom.len(),
+            executed: self.executed(),
+            branches,
+            branches_hit,
+            lines: lines.len(),
+            lines_hit: lines.iter().filter(|&&hits| hits > 0).count(),
+            never_executed: self
+                .unreached(symbols)
+                .into_iter()
+                .map(|(_, name)| name.to_string())
+                .collect(),
+        }
+    }
+
+    // 実行回数を付けたソース（ソースがなければ逆アセンブル）
+    pub fn report(&self, symbols: &Symbols, files: &[PathBuf]) -> Result<String> {
+        let (branches_hit, branches) = self.branches_hit();
+        let mut out = format!(
+            "Coverage: {}/{} instructions ({:.1}%), {}/{} branches\n",
+            self.executed(),
+            self.rom.len(),
+            self.executed() as f64 * 100.0 / self.rom.len().max(1) as f64,
+            branches_hit,
+            branches
+        );
+        let unreached = self.unreached(symbols);
+        if !unreached.is_empty() {
+            let names: Vec<&str> = unreached.iter().map(|&(_, name)| name).collect();
+            writeln!(out, "Never executed: {}", names.join(", ")).unwrap();
+        }
+
+        if !symbols.has_sources() {
+            writeln!(out).unwrap();
+            for address in 0..self.rom.len() as u16 {
+                let text = disasm::disassemble_at(&self.rom, address, symbols);
+                let note = self.branch(address).as_ref().and_then(Branch::untested);
+                write_line(&mut out, self.hits(address), address as usize, &text, note);
+            }
+            return Ok(out);
+        }
+
+        for (file, lines) in self.source_lines(symbols) {
+            let path = find_source(files, file);
+            let text = match path {
+                Some(path) => fs::read_to_string(path)
+                    .context(format!("Failed to read file '{}'", path.display()))?,
+                None => String::new(),
+            };
+            let name = path
+                .and_then(|path| path.file_name())
+                .map_or(file.to_string(), |name| name.to_string_lossy().into_owned());
+            write!(out, "\n{}\n", name).unwrap();
+
+            // ソースがなければ命令のある行だけを出す
+            let source_lines: Vec<&str> = text.lines().collect();
+            let count = match path {
+                Some(_) => source_lines.len(),
+                None => lines.keys().max().copied().unwrap_or_default(),
+            };
+            for number in 1..=count {
+                let source = source_lines.get(number - 1).copied().unwrap_or_default();
+                let Some(stats) = lines.get(&number) else {
+                    if path.is_some() {
+                        let line = format!("{:>9} | {:>5} | {}", "-", number, source);
+                        writeln!(out, "{}", line.trim_end()).unwrap();
+                    }
+                    continue;
+                };
+                let note = stats.branches.iter().find_map(Branch::untested);
+                write_line(&mut out, stats.hits, number, source, note);
+            }
+        }
+        Ok(out)
+    }
+
+    // LCOV のトレースファイル。ソースがなければ ROM ファイルの行（アドレス + 1）で書く
+    pub fn lcov(&self, symbols: &Symbols, files: &[PathBuf], input: &Path) -> String {
+        let mut out = String::from("TN:\n");
+        if !symbols.has_sources() {
+            writeln!(out, "SF:{}", input.display()).unwrap();
+            let functions: Vec<(usize, &str, u64)> = symbols
+                .regions()
+                .into_iter()
+                .filter(|&(address, _)| (address as usize) < self.rom.len())
+                .map(|(address, name)| (address as usize + 1, name, self.hits(address)))
+                .collect();
+            let lines = (0..self.rom.len() as u16).map(|address| {
+                let mut stats = LineStats {
+                    hits: self.hits(address),
+                    branches: Vec::new(),
+                };
+                if is_conditional_jump(self.rom[address as usize]) {
+                    stats
+                        .branches
+                        .push(self.branch(address).unwrap_or_default());
+                }
+                (address as usize + 1, stats)
+            });
+            write_record(&mut out, &functions, lines);
+            return out;
+        }
+
+        for (file, lines) in self.source_lines(symbols) {
+            match find_source(files, file) {
+                Some(path) => writeln!(out, "SF:{}", path.display()).unwrap(),
+                None => writeln!(out, "SF:{}", file).unwrap(),
+            }
+            let functions: Vec<(usize, &str, u64)> = symbols
+                .regions()
+                .into_iter()
+                .filter_map(|(address, name)| {
+                    let source = symbols.source_at(address)?;
+                    (source.file == file).then(|| (source.line, name, self.hits(address)))
+                })
+                .collect();
+            write_record(&mut out, &functions, lines.into_iter());
+        }
+        out
+    }
+
+    // 拡張子が .info / .lcov なら LCOV、それ以外は実行回数付きのテキスト
+    pub fn save(
+        &self,
+        path: &Path,
+        symbols: &Symbols,
+        files: &[PathBuf],
+        input: &Path,
+    ) -> Result<()> {
+        let text = if path
+            .extension()
+            .is_some_and(|ext| ext == "info" || ext == "lcov")
+        {
+            self.lcov(symbols, files, input)
+        } else {
+            self.report(symbols, files)?
+        };
+        fs::write(path, text).context(format!("Failed to write '{}'", path.display()))
+    }
+}
+
+impl StepHook for Coverage {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()> {
+        if let Some(hits) = self.hits.get_mut(step.pc as usize) {
+            *hits += 1;
+        }
+        if is_conditional_jump(step.instruction) {
+            let branch = self.branches.entry(step.pc).or_default();
+            if cpu.pc == step.pc.wrapping_add(1) {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+        // 終端のループは実行する前に止まるので、入ったところで1回実行したことにする
+        if cpu.is_halted() {
+            let pc = cpu.pc as usize;
+            let length = if self.rom.get(pc).is_some_and(|&i| i & 0x8000 == 0) {
+                2
+            } else {
+                1
+            };
+            for hits in self.hits.iter_mut().skip(pc).take(length) {
+                *hits += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+// C 命令で JMP 以外のジャンプ
+fn is_conditional_jump(instruction: u16) -> bool {
+    instruction & 0x8000 != 0 && (1..=6).contains(&(instruction & 0x7))
+}
+
+// gcov と同じく、実行されなかった行は #####
+fn write_line(out: &mut String, hits: u64, number: usize, text: &str, note: Option<&str>) {
+    let count = match hits {
+        0 => "#####".to_string(),
+        hits => hits.to_string(),
+    };
+    let mut line = format!("{:>9} | {:>5} | {}", count, number, text);
+    if let Some(note) = note {
+        write!(line, "  <- {}", note).unwrap();
+    }
+    writeln!(out, "{}", line.trim_end()).unwrap();
+}
+
+fn find_source<'a>(files: &'a [PathBuf], stem: &str) -> Option<&'a Path> {
+    files
+        .iter()
+        .find(|path| path.file_stem().is_some_and(|s| s == stem))
+        .map(PathBuf::as_path)
+}
+
+fn write_record(
+    out: &mut String,
+    functions: &[(usize, &str, u64)],
+    lines: impl Iterator<Item = (usize, LineStats)>,
+) {
+    for (line, name, _) in functions {
+        writeln!(out, "FN:{},{}", line, name).unwrap();
+    }
+    for (_, name, hits) in functions {
+        writeln!(out, "FNDA:{},{}", hits, name).unwrap();
+    }
+    writeln!(out, "FNF:{}", functions.len()).unwrap();
+    writeln!(
+        out,
+        "FNH:{}",
+        functions.iter().filter(|(_, _, hits)| *hits > 0).count()
+    )
+    .unwrap();
+
+    let (mut found, mut hit, mut branches_found, mut branches_hit) = (0, 0, 0, 0);
+    let mut data = String::new();
+    for (line, stats) in lines {
+        for (block, branch) in stats.branches.iter().enumerate() {
+            for (index, count) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                // 行が実行されていなければ分岐の回数は "-"
+                let count = if stats.hits == 0 {
+                    "-".to_string()
+                } else {
+                    count.to_string()
+                };
+                writeln!(out, "BRDA:{},{},{},{}", line, block, index, count).unwrap();
+                branches_found += 1;
+                branches_hit += (count != "-" && count != "0") as usize;
+            }
+        }
+        writeln!(data, "DA:{},{}", line, stats.hits).unwrap();
+        found += 1;
+        hit += (stats.hits > 0) as usize;
+    }
+    writeln!(out, "BRF:{}", branches_found).unwrap();
+    writeln!(out, "BRH:{}", branches_hit).unwrap();
+    out.push_str(&data);
+    writeln!(out, "LF:{}", found).unwrap();
+    writeln!(out, "LH:{}", hit).unwrap();
+    out.push_str("end_of_record\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, loader, screen::Headless};
+
+    #[test]
+    fn test_coverage_report() {
+        let dir = std::env::temp_dir().join(format!("emu-coverage-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Prog.asm");
+        fs::write(
+            &path,
+            "@R0\nD=M\n@POS\nD;JGT\n@R1\nM=0\n(END)\n@END\n0;JMP\n(POS)\n@R1\nM=1\n",
+        )
+        .unwrap();
+        let program = loader::load_program(&path, None).unwrap();
+
+        let mut cpu = Cpu::new(&program.words).unwrap();
+        let mut coverage = Coverage::new(&program.words);
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut coverage])
+            .unwrap();
+        let report = coverage
+            .report(&program.symbols, &program.source_files)
+            .unwrap();
+        let lcov = coverage.lcov(&program.symbols, &program.source_files, &path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // R0 = 0 なので分岐せず、POS 以降は実行されない
+        assert_eq!(
+            coverage.branch(3),
+            Some(Branch {
+                taken: 0,
+                not_taken: 1
+            })
+        );
+        assert_eq!(
+            report,
+            "Coverage: 8/10 instructions (80.0%), 1/2 branches\n\
+             Never executed: POS\n\
+             \n\
+             Prog.asm\n        \
+                     1 |     1 | @R0\n        \
+                     1 |     2 | D=M\n        \
+                     1 |     3 | @POS\n        \
+                     1 |     4 | D;JGT  <- branch never taken\n        \
+                     1 |     5 | @R1\n        \
+                     1 |     6 | M=0\n        \
+                     - |     7 | (END)\n        \
+                     1 |     8 | @END\n        \
+                     1 |     9 | 0;JMP\n        \
+                     - |    10 | (POS)\n    \
+                 ##### |    11 | @R1\n    \
+                 ##### |    12 | M=1\n"
+        );
+        assert!(lcov.contains("BRDA:4,0,0,0\nBRDA:4,0,1,1\n"), "{}", lcov);
+        assert!(lcov.contains("DA:11,0\n"), "{}", lcov);
+        assert!(lcov.ends_with("LF:10\nLH:8\nend_of_record\n"), "{}", lcov);
+    }
+
+    #[test]
+    fn test_coverage_without_sources() {
+        // @3 D;JEQ @0 0;JMP  （D = 0 なので分岐する）
+        let program = [3, 0xe302, 0, 0xea87];
+        let mut cpu = Cpu::new(&program).unwrap();
+        let mut coverage = Coverage::new(&program);
+        for _ in 0..2 {
+            let step = cpu.step().unwrap();
+            coverage.on_step(&cpu, &step).unwrap();
+        }
+        let report = coverage.report(&Symbols::default(), &[]).unwrap();
+        assert!(
+            report.contains("        1 |     1 | D;JEQ  <- branch always taken\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("    ##### |     2 | @0\n"), "{}", report);
+    }
+}
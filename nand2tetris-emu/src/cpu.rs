@@ -0,0 +1,598 @@
+use anyhow::{Result, bail};
+use nand2tetris_core::timeout;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    input::InputMode,
+    native::NativeOs,
+    screen::{KBD, SCREEN, ScreenBackend},
+    screenshot::{save_screenshot, screenshot_path},
+    throttle::Throttle,
+};
+
+pub const ROM_SIZE: usize = 32768;
+pub const RAM_SIZE: usize = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // PC がプログラムの末尾を越えた
+    Finished,
+    // 自分自身へ無条件ジャンプするループに入った（Sys.halt の呼び出しを含む）
+    Halted,
+    // サイクル数の上限に達した
+    MaxCycles,
+    // ウィンドウが閉じられた
+    Closed,
+    // ブレークポイントのアドレスに到達した（その命令は未実行）
+    Breakpoint,
+}
+
+// 1命令の実行結果（トレースや統計用）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StepInfo {
+    pub pc: u16,
+    pub instruction: u16,
+    pub read: Option<u16>,
+    pub write: Option<(u16, u16)>,
+}
+
+// 命令の実行ごとに呼ばれるフック
+pub trait StepHook {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()>;
+}
+
+#[derive(Clone)]
+pub struct RunOptions {
+    // 画面更新とキーボード読み取りの間隔（サイクル）
+    pub refresh_interval: u64,
+    pub max_cycles: Option<u64>,
+    // 目標クロック周波数（None なら最高速）
+    pub hz: Option<u64>,
+    // スクリーンショットを保存するサイクルと保存先（{cycle} は置換される）
+    pub screenshot_at: Vec<u64>,
+    pub screenshot_file: PathBuf,
+    // この ROM アドレスの命令を実行する前に止まる
+    pub breakpoints: BTreeSet<u16>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            refresh_interval: 10_000,
+            max_cycles: None,
+            hz: None,
+            screenshot_at: Vec::new(),
+            screenshot_file: PathBuf::from("screen-{cycle}.png"),
+            breakpoints: BTreeSet::new(),
+        }
+    }
+}
+
+pub struct Cpu {
+    pub rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+    pub input: InputMode,
+    // OS の関数を Rust で実行する（--native-os）
+    pub native: Option<Box<NativeOs>>,
+    // Sys.halt のアドレス。OS の Sys.halt は自己ジャンプにならないので入口で止まったとみなす
+    pub sys_halt: Option<u16>,
+    program_len: usize,
+    screen_dirty: bool,
+}
+
+impl Cpu {
+    pub fn new(program: &[u16]) -> Result<Self> {
+        if program.len() > ROM_SIZE {
+            bail!(
+                "Program too large: {} words (ROM holds {})",
+                program.len(),
+                ROM_SIZE
+            );
+        }
+
+        let mut rom = vec![0; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+
+        Ok(Cpu {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            input: InputMode::Live,
+            native: None,
+            sys_halt: None,
+            program_len: program.len(),
+            screen_dirty: true,
+        })
+    }
+
+    pub fn program_len(&self) -> usize {
+        self.program_len
+    }
+
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.cycles = 0;
+    }
+
+    // PCがプログラムの末尾を越えたら終了とみなす
+    pub fn is_finished(&self) -> bool {
+        self.pc as usize >= self.program_len
+    }
+
+    pub fn step(&mut self) -> Result<StepInfo> {
+        let instruction = self.fetch()?;
+        let mut info = StepInfo {
+            pc: self.pc,
+            instruction,
+            ..StepInfo::default()
+        };
+
+        // OS の関数の入口なら、関数全体を 1 サイクルで実行して戻る
+        if let Some(mut native) = self.native.take_if(|native| native.handles(self.pc)) {
+            let result = native.call(self);
+            self.native = Some(native);
+            result?;
+            self.cycles += 1;
+            return Ok(info);
+        }
+
+        if instruction & 0x8000 == 0 {
+            // A命令
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+        } else {
+            // C命令 111a cccc ccdd djjj
+            let address = self.a;
+            let y = if instruction & 0x1000 != 0 {
+                info.read = Some(address);
+                self.read(address)?
+            } else {
+                self.a
+            };
+            let out = alu(self.d, y, (instruction >> 6) & 0x3f);
+
+            // M への書き込みは更新前の A を使う
+            if instruction & 0x0008 != 0 {
+                info.write = Some((address, out));
+                self.write(address, out)?;
+            }
+            if instruction & 0x0020 != 0 {
+                self.a = out;
+            }
+            if instruction & 0x0010 != 0 {
+                self.d = out;
+            }
+
+            self.pc = if jump(out, instruction & 0x7) {
+                address
+            } else {
+                self.pc.wrapping_add(1)
+            };
+        }
+
+        self.cycles += 1;
+        Ok(info)
+    }
+
+    // 終端イディオム `(END) @END 0;JMP`、A が自身を指す `0;JMP`、または Sys.halt の入口
+    pub fn is_halted(&self) -> bool {
+        if self.sys_halt == Some(self.pc) {
+            return true;
+        }
+        let pc = self.pc as usize;
+        let Some(&instruction) = self.rom.get(pc) else {
+            return false;
+        };
+
+        if instruction == self.pc && instruction & 0x8000 == 0 {
+            return self.rom.get(pc + 1).is_some_and(|&next| is_bare_jump(next));
+        }
+        is_bare_jump(instruction) && self.a == self.pc
+    }
+
+    pub fn check_stop(&self, max_cycles: Option<u64>) -> Option<StopReason> {
+        if self.is_finished() {
+            Some(StopReason::Finished)
+        } else if self.is_halted() {
+            Some(StopReason::Halted)
+        } else if max_cycles.is_some_and(|max| self.cycles >= max) {
+            Some(StopReason::MaxCycles)
+        } else {
+            None
+        }
+    }
+
+    pub fn run(&mut self, max_cycles: Option<u64>) -> Result<StopReason> {
+        loop {
+            if let Some(reason) = self.check_stop(max_cycles) {
+                return Ok(reason);
+            }
+            self.step()?;
+        }
+    }
+
+    // refresh_interval サイクルごとに画面を更新し、キーボードを読む
+    pub fn run_with_backend(
+        &mut self,
+        backend: &mut dyn ScreenBackend,
+        options: &RunOptions,
+        hooks: &mut [&mut dyn StepHook],
+    ) -> Result<StopReason> {
+        let refresh_interval = options.refresh_interval.max(1);
+        let throttle = options.hz.map(|hz| Throttle::new(hz, self.cycles));
+        // ブレークポイントで止まった位置から再開できるよう、最初の命令では止まらない
+        let start_cycles = self.cycles;
+
+        let reason = loop {
+            if !backend.is_open() {
+                break StopReason::Closed;
+            }
+            if options.screenshot_at.contains(&self.cycles) {
+                self.save_screenshot(&options.screenshot_file)?;
+            }
+            if let Some(reason) = self.check_stop(options.max_cycles) {
+                break reason;
+            }
+            if self.cycles != start_cycles && options.breakpoints.contains(&self.pc) {
+                break StopReason::Breakpoint;
+            }
+            if self.cycles.is_multiple_of(refresh_interval) {
+                // テストの --timeout
+                timeout::check()?;
+                self.sync_io(backend)?;
+                if backend.take_screenshot_request() {
+                    self.save_screenshot(&options.screenshot_file)?;
+                }
+            }
+            if let Some(throttle) = &throttle
+                && self.cycles.is_multiple_of(throttle.check_interval())
+            {
+                throttle.wait(self.cycles);
+            }
+            if let Some(key) = self.input.due(self.cycles) {
+                self.ram[KBD] = key;
+            }
+            let info = self.step()?;
+            for hook in hooks.iter_mut() {
+                hook.on_step(self, &info)?;
+            }
+        };
+
+        self.sync_io(backend)?;
+        Ok(reason)
+    }
+
+    pub fn sync_io(&mut self, backend: &mut dyn ScreenBackend) -> Result<()> {
+        let polled = backend.poll_key()?;
+        self.ram[KBD] = self.input.on_poll(self.cycles, polled, self.ram[KBD]);
+        if self.screen_dirty {
+            backend.refresh(self.screen())?;
+            self.screen_dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn save_screenshot(&self, template: &Path) -> Result<()> {
+        save_screenshot(&screenshot_path(template, self.cycles), self.screen())
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN..KBD]
+    }
+
+    pub fn set_key(&mut self, key: u16) {
+        self.ram[KBD] = key;
+    }
+
+    pub fn read(&self, address: u16) -> Result<u16> {
+        match self.ram.get(address as usize) {
+            Some(value) => Ok(*value),
+            None => bail!("RAM address out of range: {} (PC={})", address, self.pc),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u16) -> Result<()> {
+        let address_index = address as usize;
+
+        // KBD は読み取り専用
+        if address_index == KBD {
+            return Ok(());
+        }
+        if (SCREEN..KBD).contains(&address_index) {
+            self.screen_dirty = true;
+        }
+
+        match self.ram.get_mut(address_index) {
+            Some(cell) => {
+                *cell = value;
+                Ok(())
+            }
+            None => bail!("RAM address out of range: {} (PC={})", address, self.pc),
+        }
+    }
+
+    fn fetch(&self) -> Result<u16> {
+        match self.rom.get(self.pc as usize) {
+            Some(instruction) => Ok(*instruction),
+            None => bail!("PC out of range: {}", self.pc),
+        }
+    }
+}
+
+// dest なしの無条件ジャンプ（状態を変えない）
+fn is_bare_jump(instruction: u16) -> bool {
+    instruction & 0xe000 == 0xe000 && instruction & 0x0038 == 0 && instruction & 0x7 == 0x7
+}
+
+// zx nx zy ny f no
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let mut x = x;
+    let mut y = y;
+
+    if control & 0b100000 != 0 {
+        x = 0;
+    }
+    if control & 0b010000 != 0 {
+        x = !x;
+    }
+    if control & 0b001000 != 0 {
+        y = 0;
+    }
+    if control & 0b000100 != 0 {
+        y = !y;
+    }
+    let mut out = if control & 0b000010 != 0 {
+        x.wrapping_add(y)
+    } else {
+        x & y
+    };
+    if control & 0b000001 != 0 {
+        out = !out;
+    }
+
+    out
+}
+
+// j1(out < 0) j2(out = 0) j3(out > 0)
+fn jump(out: u16, bits: u16) -> bool {
+    let value = out as i16;
+    (bits & 0b100 != 0 && value < 0)
+        || (bits & 0b010 != 0 && value == 0)
+        || (bits & 0b001 != 0 && value > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    // ========================================
+    // ALU
+    // ========================================
+
+    #[rstest]
+    #[case(0b101010, 0)] // 0
+    #[case(0b111111, 1)] // 1
+    #[case(0b111010, 0xffff)] // -1
+    #[case(0b001100, 7)] // D
+    #[case(0b110000, 3)] // A
+    #[case(0b000010, 10)] // D+A
+    #[case(0b010011, 4)] // D-A
+    #[case(0b000111, 0xfffc)] // A-D
+    #[case(0b000000, 3)] // D&A
+    #[case(0b010101, 7)] // D|A
+    #[case(0b011111, 8)] // D+1
+    #[case(0b001111, 0xfff9)] // -D
+    fn test_alu(#[case] control: u16, #[case] expected: u16) {
+        assert_eq!(alu(7, 3, control), expected);
+    }
+
+    // ========================================
+    // 実行
+    // ========================================
+
+    #[test]
+    fn test_add_program() {
+        // RAM[0] = 2 + 3
+        let program = [
+            0b0000000000000010, // @2
+            0b1110110000010000, // D=A
+            0b0000000000000011, // @3
+            0b1110000010010000, // D=D+A
+            0b0000000000000000, // @0
+            0b1110001100001000, // M=D
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        cpu.run(None).unwrap();
+        assert_eq!(cpu.ram[0], 5);
+        assert_eq!(cpu.cycles, 6);
+    }
+
+    #[test]
+    fn test_jump_uses_old_a() {
+        let program = [
+            0b0000000000000100, // @4
+            0b1110110000100111, // A=A;JMP (ジャンプ先は更新前のA)
+            0b0000000000000000, // @0
+            0b1110111111001000, // M=1
+            0b0000000000000001, // @1
+            0b1110111111001000, // M=1
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        cpu.run(None).unwrap();
+        assert_eq!(cpu.ram[0], 0);
+        assert_eq!(cpu.ram[1], 1);
+    }
+
+    #[rstest]
+    #[case(0b001, 5, true)]
+    #[case(0b001, 0, false)]
+    #[case(0b010, 0, true)]
+    #[case(0b100, 0x8000, true)]
+    #[case(0b110, 0, true)]
+    #[case(0b101, 0, false)]
+    #[case(0b111, 0, true)]
+    fn test_jump(#[case] bits: u16, #[case] out: u16, #[case] expected: bool) {
+        assert_eq!(jump(out, bits), expected);
+    }
+
+    #[test]
+    fn test_kbd_is_read_only() {
+        let program = [
+            0b0110000000000000, // @KBD
+            0b1110111111001000, // M=1
+            0b1111110000010000, // D=M
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        cpu.set_key(65);
+        cpu.run(None).unwrap();
+        assert_eq!(cpu.d, 65);
+    }
+
+    #[test]
+    fn test_screen_write_marks_dirty() {
+        struct Recorder(Vec<u16>);
+        impl ScreenBackend for Recorder {
+            fn refresh(&mut self, screen: &[u16]) -> Result<()> {
+                self.0 = screen.to_vec();
+                Ok(())
+            }
+            fn poll_key(&mut self) -> Result<u16> {
+                Ok(0)
+            }
+        }
+
+        let program = [
+            0b0100000000000000, // @SCREEN
+            0b1110111010001000, // M=-1
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        let mut recorder = Recorder(vec![]);
+        let options = RunOptions {
+            refresh_interval: 100,
+            ..RunOptions::default()
+        };
+        cpu.run_with_backend(&mut recorder, &options, &mut [])
+            .unwrap();
+        assert_eq!(recorder.0.len(), 8192);
+        assert_eq!(recorder.0[0], 0xffff);
+    }
+
+    #[test]
+    fn test_replay_is_deterministic() {
+        // RAM[0] += KBD を繰り返す
+        let program = [
+            0b0110000000000000, // @KBD
+            0b1111110000010000, // D=M
+            0b0000000000000000, // @0
+            0b1111000010001000, // M=D+M
+            0b0000000000000000, // @0
+            0b1110101010000111, // 0;JMP
+        ];
+        let options = RunOptions {
+            refresh_interval: 7,
+            max_cycles: Some(600),
+            ..RunOptions::default()
+        };
+
+        struct Keys(u16);
+        impl ScreenBackend for Keys {
+            fn refresh(&mut self, _screen: &[u16]) -> Result<()> {
+                Ok(())
+            }
+            fn poll_key(&mut self) -> Result<u16> {
+                self.0 = (self.0 + 1) % 3;
+                Ok(self.0)
+            }
+        }
+
+        let mut recorded = Cpu::new(&program).unwrap();
+        recorded.input = InputMode::Record(Default::default());
+        recorded
+            .run_with_backend(&mut Keys(0), &options, &mut [])
+            .unwrap();
+        let log = recorded.input.recorded().unwrap().clone();
+        assert!(!log.events.is_empty());
+
+        let mut replayed = Cpu::new(&program).unwrap();
+        replayed.input = InputMode::replay(log);
+        replayed
+            .run_with_backend(&mut crate::screen::Headless, &options, &mut [])
+            .unwrap();
+        assert_eq!(replayed.ram[0], recorded.ram[0]);
+    }
+
+    #[test]
+    fn test_halt_loop_detected() {
+        let program = [
+            0b0000000000000000, // @0
+            0b1110111111001000, // M=1
+            0b0000000000000010, // (END) @END
+            0b1110101010000111, // 0;JMP
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        assert_eq!(cpu.run(None).unwrap(), StopReason::Halted);
+        assert_eq!(cpu.ram[0], 1);
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.cycles, 2);
+    }
+
+    #[test]
+    fn test_max_cycles() {
+        let program = [
+            0b0000000000000000, // @0
+            0b1111110111001000, // M=M+1
+            0b0000000000000000, // @0
+            0b1110101010000111, // 0;JMP
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        assert_eq!(cpu.run(Some(100)).unwrap(), StopReason::MaxCycles);
+        assert_eq!(cpu.cycles, 100);
+        assert_eq!(cpu.ram[0], 25);
+    }
+
+    #[test]
+    fn test_breakpoint_stops_and_resumes() {
+        let program = [
+            0b0000000000000000, // @0
+            0b1111110111001000, // M=M+1
+            0b0000000000000000, // @0
+            0b1110101010000111, // 0;JMP
+        ];
+        let options = RunOptions {
+            breakpoints: BTreeSet::from([1]),
+            max_cycles: Some(100),
+            ..RunOptions::default()
+        };
+        let mut cpu = Cpu::new(&program).unwrap();
+        let mut run = || {
+            cpu.run_with_backend(&mut crate::screen::Headless, &options, &mut [])
+                .unwrap()
+        };
+
+        // 止まった位置からの再開では同じブレークポイントで即座に止まらない
+        assert_eq!(run(), StopReason::Breakpoint);
+        assert_eq!(run(), StopReason::Breakpoint);
+        assert_eq!((cpu.pc, cpu.ram[0]), (1, 1));
+    }
+
+    #[test]
+    fn test_ram_out_of_range() {
+        let program = [
+            0b1110111010100000, // A=-1
+            0b1110111111001000, // M=1
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        assert!(cpu.run(None).is_err());
+    }
+}
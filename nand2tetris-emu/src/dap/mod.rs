@@ -0,0 +1,714 @@
+// Debug Adapter Protocol サーバー（VS Code などから接続する）
+mod protocol;
+
+pub use protocol::{Sender, read_message, write_message};
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::{
+    Cpu,
+    debugger::Debugger,
+    expr::parse_expr,
+    frame::{Frame, SAVED_WORDS, call_stack},
+    loader,
+    symbols::Symbols,
+};
+
+// スレッドは CPU の1つだけ
+const THREAD_ID: u64 = 1;
+// continue 中にメッセージ（pause など）を確認する間隔（命令数）
+const POLL_INTERVAL: u64 = 10_000;
+
+// variablesReference の割り当て。引数は FRAME_ARGUMENTS + フレームの深さ
+const REGISTERS: u64 = 1;
+const POINTERS: u64 = 2;
+const VARIABLES: u64 = 3;
+const FRAME_ARGUMENTS: u64 = 100;
+
+// 読み込んだプログラムと、ソースのパスを組み立てるための情報
+struct Program {
+    cpu: Cpu,
+    debugger: Debugger,
+    path: PathBuf,
+    source_files: Vec<PathBuf>,
+}
+
+impl Program {
+    // 読み込んだソース（.vm.map があれば .jack）、なければディレクトリ内の <file>.vm、
+    // .asm ならそのファイル
+    fn source_path(&self, file: &str) -> PathBuf {
+        if let Some(path) = self
+            .source_files
+            .iter()
+            .find(|path| path.file_stem().is_some_and(|s| s == file))
+        {
+            return path.clone();
+        }
+        if self.path.is_dir() {
+            return self.path.join(format!("{}.vm", file));
+        }
+        let extension = self
+            .path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("asm");
+        self.path.with_file_name(format!("{}.{}", file, extension))
+    }
+
+    fn symbols(&self) -> &Symbols {
+        self.debugger.symbols()
+    }
+}
+
+enum Resume {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+// 応答を返したあとに行うこと
+enum After {
+    Initialized,
+    Entry,
+    Resume(Resume),
+}
+
+pub struct Session<'a> {
+    out: Sender<'a>,
+    program: Option<Program>,
+    stop_on_entry: bool,
+    // ソースファイル名（拡張子なし）ごとのブレークポイントと条件
+    source_breakpoints: HashMap<String, BTreeMap<u16, Option<String>>>,
+    function_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+    // 実行中に届いたリクエストは止まってから処理する
+    pending: VecDeque<Value>,
+    quit: bool,
+}
+
+// 入力を別スレッドで読み、実行中でも pause を受け取れるようにする
+pub fn serve(input: Box<dyn BufRead + Send>, out: &mut dyn Write) -> Result<()> {
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = read_message(input.as_mut()) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(out);
+    while !session.quit {
+        let message = match session.pending.pop_front() {
+            Some(message) => message,
+            None => match messages.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+        session.handle(&message, &messages)?;
+    }
+    Ok(())
+}
+
+impl<'a> Session<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Session {
+            out: Sender::new(out),
+            program: None,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            pending: VecDeque::new(),
+            quit: false,
+        }
+    }
+
+    // リクエストの失敗はエラー応答として返し、書き込みの失敗だけを呼び出し元に返す
+    pub fn handle(&mut self, request: &Value, messages: &Receiver<Value>) -> Result<()> {
+        if request["type"] != "request" {
+            return Ok(());
+        }
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        match self.dispatch(command, arguments) {
+            Ok((body, after)) => {
+                self.out.response(request, body)?;
+                match after {
+                    Some(After::Initialized) => self.out.event("initialized", json!({}))?,
+                    Some(After::Entry) => self.stopped("entry", None)?,
+                    Some(After::Resume(resume)) => self.resume(resume, messages)?,
+                    None => {}
+                }
+            }
+            Err(e) => self.out.error(request, &e.to_string())?,
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, command: &str, arguments: &Value) -> Result<(Value, Option<After>)> {
+        let body = match command {
+            "initialize" => json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+            }),
+            "launch" => {
+                self.launch(arguments)?;
+                return Ok((json!({}), Some(After::Initialized)));
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments)?,
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments)?,
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments)?,
+            "configurationDone" => {
+                self.program()?;
+                let after = if self.stop_on_entry {
+                    After::Entry
+                } else {
+                    After::Resume(Resume::Continue)
+                };
+                return Ok((json!({}), Some(after)));
+            }
+            "threads" => json!({ "threads": [{ "id": THREAD_ID, "name": "Hack CPU" }] }),
+            "stackTrace" => self.stack_trace()?,
+            "scopes" => self.scopes(arguments)?,
+            "variables" => self.variables(arguments)?,
+            "evaluate" => self.evaluate(arguments)?,
+            "continue" => {
+                self.program()?;
+                return Ok((
+                    json!({ "allThreadsContinued": true }),
+                    Some(After::Resume(Resume::Continue)),
+                ));
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let program = self.program()?;
+                let resume = match command {
+                    "next" => Resume::Next,
+                    "stepIn" => Resume::StepIn,
+                    _ => {
+                        // 関数の外では戻る先がない
+                        Frame::current(&program.cpu).context("Not inside a function frame")?;
+                        Resume::StepOut
+                    }
+                };
+                return Ok((json!({}), Some(After::Resume(resume))));
+            }
+            // 止まっているときに届いた pause は何もしない
+            "pause" => json!({}),
+            "disconnect" | "terminate" => {
+                self.quit = true;
+                json!({})
+            }
+            _ => bail!("Unsupported request '{}'", command),
+        };
+        Ok((body, None))
+    }
+
+    fn program(&self) -> Result<&Program> {
+        self.program
+            .as_ref()
+            .context("No program has been launched")
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<()> {
+        let path = PathBuf::from(
+            arguments["program"]
+                .as_str()
+                .context("launch requires 'program'")?,
+        );
+        let loaded = loader::load_program(&path, None)?;
+        let symbols = match arguments["sym"].as_str() {
+            Some(file) => Symbols::load(Path::new(file))?,
+            None => loaded.symbols,
+        };
+
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        let mut cpu = Cpu::new(&loaded.words)?;
+        cpu.sys_halt = symbols.resolve("Sys.halt");
+        self.program = Some(Program {
+            cpu,
+            debugger: Debugger::new(symbols),
+            path,
+            source_files: loaded.source_files,
+        });
+        Ok(())
+    }
+
+    // 行に対応する命令がなければ未検証のブレークポイントとして返す
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value> {
+        let program = self.program()?;
+        let source = &arguments["source"];
+        let path = source["path"]
+            .as_str()
+            .or(source["name"].as_str())
+            .context("setBreakpoints requires a source path")?;
+        let file = Path::new(path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut addresses = BTreeMap::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            let condition = breakpoint["condition"]
+                .as_str()
+                .filter(|condition| !condition.trim().is_empty());
+            if let Some(Err(e)) = condition.map(|c| parse_expr(c, program.symbols())) {
+                breakpoints
+                    .push(json!({ "verified": false, "line": line, "message": e.to_string() }));
+                continue;
+            }
+            match program.symbols().address_of(&file, line) {
+                Some(address) => {
+                    addresses.insert(address, condition.map(str::to_string));
+                    breakpoints.push(json!({ "verified": true, "line": line }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instructions on this line",
+                })),
+            }
+        }
+
+        self.source_breakpoints.insert(file, addresses);
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Result<Value> {
+        let program = self.program()?;
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = breakpoint["name"].as_str().unwrap_or_default();
+            match program.debugger.resolve(name) {
+                Ok(address) => {
+                    addresses.insert(address);
+                    breakpoints.push(json!({ "verified": true }));
+                }
+                Err(e) => breakpoints.push(json!({ "verified": false, "message": e.to_string() })),
+            }
+        }
+
+        self.function_breakpoints = addresses;
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value> {
+        self.program()?;
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+            match parse_reference(reference).map(|address| address as i64 + offset) {
+                Some(address @ 0..=0x7fff) => {
+                    addresses.insert(address as u16);
+                    breakpoints.push(json!({ "verified": true }));
+                }
+                _ => breakpoints.push(json!({
+                    "verified": false,
+                    "message": format!("Invalid instruction reference '{}'", reference),
+                })),
+            }
+        }
+
+        self.instruction_breakpoints = addresses;
+        self.sync_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn sync_breakpoints(&mut self) {
+        let mut all: BTreeSet<u16> = self
+            .source_breakpoints
+            .values()
+            .flat_map(|addresses| addresses.keys())
+            .copied()
+            .collect();
+        all.extend(&self.function_breakpoints);
+        all.extend(&self.instruction_breakpoints);
+        let Some(program) = self.program.as_mut() else {
+            return;
+        };
+        program.debugger.set_breakpoints(all);
+        // 条件は setBreakpoints で検証済み
+        for (&address, condition) in self.source_breakpoints.values().flatten() {
+            if let Some(condition) = condition {
+                program
+                    .debugger
+                    .set_condition(address, condition)
+                    .expect("condition was parsed when the breakpoint was set");
+            }
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value> {
+        let program = self.program()?;
+        let symbols = program.symbols();
+        let frames: Vec<Value> = call_stack(&program.cpu)
+            .iter()
+            .enumerate()
+            .map(|(depth, entry)| {
+                let name = match symbols.function_at(entry.site) {
+                    Some(function) => function.to_string(),
+                    None => program.debugger.describe(entry.site),
+                };
+                let mut frame = json!({
+                    "id": depth,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": entry.site.to_string(),
+                });
+                if let Some(source) = symbols.source_at(entry.site) {
+                    let path = program.source_path(&source.file);
+                    frame["source"] = json!({
+                        "name": path.file_name().and_then(|n| n.to_str()),
+                        "path": path,
+                    });
+                    frame["line"] = json!(source.line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn scopes(&self, arguments: &Value) -> Result<Value> {
+        let program = self.program()?;
+        let depth = arguments["frameId"].as_u64().unwrap_or_default();
+
+        let mut scopes = vec![
+            json!({ "name": "Registers", "variablesReference": REGISTERS, "expensive": false }),
+            json!({ "name": "R0..R15", "variablesReference": POINTERS, "expensive": false }),
+        ];
+        if program.symbols().variables().next().is_some() {
+            scopes.push(
+                json!({ "name": "Variables", "variablesReference": VARIABLES, "expensive": false }),
+            );
+        }
+        if frame_arguments(&program.cpu, depth).is_some() {
+            scopes.insert(
+                0,
+                json!({
+                    "name": "Arguments",
+                    "variablesReference": FRAME_ARGUMENTS + depth,
+                    "expensive": false,
+                }),
+            );
+        }
+        Ok(json!({ "scopes": scopes }))
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value> {
+        let program = self.program()?;
+        let cpu = &program.cpu;
+        let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+
+        let variables: Vec<(String, u16)> = match reference {
+            REGISTERS => vec![
+                ("PC".to_string(), cpu.pc),
+                ("A".to_string(), cpu.a),
+                ("D".to_string(), cpu.d),
+            ],
+            POINTERS => ["SP", "LCL", "ARG", "THIS", "THAT"]
+                .iter()
+                .map(|name| name.to_string())
+                .chain((5..16).map(|n| format!("R{}", n)))
+                .enumerate()
+                .map(|(address, name)| (name, cpu.ram[address]))
+                .collect(),
+            VARIABLES => program
+                .symbols()
+                .variables()
+                .map(|(address, name)| (name.to_string(), cpu.ram[address as usize]))
+                .collect(),
+            reference if reference >= FRAME_ARGUMENTS => {
+                frame_arguments(cpu, reference - FRAME_ARGUMENTS)
+                    .unwrap_or_default()
+                    .enumerate()
+                    .map(|(index, address)| (format!("argument {}", index), cpu.ram[address]))
+                    .collect()
+            }
+            _ => bail!("Unknown variables reference {}", reference),
+        };
+
+        let variables: Vec<Value> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                json!({ "name": name, "value": (value as i16).to_string(), "variablesReference": 0 })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    // デバッガの eval と同じ式
+    fn evaluate(&self, arguments: &Value) -> Result<Value> {
+        let program = self.program()?;
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+        let value = parse_expr(expression, program.symbols())?.eval(&program.cpu)?;
+        Ok(json!({ "result": value.to_string(), "variablesReference": 0 }))
+    }
+
+    fn resume(&mut self, resume: Resume, messages: &Receiver<Value>) -> Result<()> {
+        let program = self.program.as_mut().expect("checked before resuming");
+        let result = match resume {
+            Resume::Continue => return self.run(messages),
+            Resume::Next => program.debugger.next(&mut program.cpu, &mut io::sink()),
+            Resume::StepIn => program.cpu.step().map(|_| ()),
+            Resume::StepOut => program.debugger.finish(&mut program.cpu, &mut io::sink()),
+        };
+        match result {
+            Ok(()) => self.after_step(),
+            Err(e) => self.stopped("exception", Some(&e.to_string())),
+        }
+    }
+
+    fn after_step(&mut self) -> Result<()> {
+        let program = self.program()?;
+        if program.cpu.is_finished() || program.cpu.is_halted() {
+            return self.terminated();
+        }
+        let reason = if program.debugger.should_stop(&program.cpu).unwrap_or(true) {
+            "breakpoint"
+        } else {
+            "step"
+        };
+        self.stopped(reason, None)
+    }
+
+    // ブレークポイントか終了まで実行する。POLL_INTERVAL ごとに pause を確認する
+    fn run(&mut self, messages: &Receiver<Value>) -> Result<()> {
+        let mut steps: u64 = 0;
+        loop {
+            let program = self.program.as_mut().expect("checked before resuming");
+            let cpu = &mut program.cpu;
+            if cpu.is_finished() || cpu.is_halted() {
+                return self.terminated();
+            }
+            if let Err(e) = cpu.step() {
+                return self.stopped("exception", Some(&e.to_string()));
+            }
+            match program.debugger.should_stop(cpu) {
+                Ok(false) => {}
+                Ok(true) => return self.stopped("breakpoint", None),
+                Err(e) => return self.stopped("exception", Some(&e.to_string())),
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(POLL_INTERVAL) {
+                while let Ok(message) = messages.try_recv() {
+                    match message["command"].as_str() {
+                        Some("pause") => {
+                            self.out.response(&message, json!({}))?;
+                            return self.stopped("pause", None);
+                        }
+                        Some("threads") | Some("disconnect") | Some("terminate") => {
+                            self.handle(&message, messages)?;
+                            if self.quit {
+                                return Ok(());
+                            }
+                        }
+                        _ => self.pending.push_back(message),
+                    }
+                }
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<&str>) -> Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+            self.out.event(
+                "output",
+                json!({ "category": "stderr", "output": format!("{}\n", text) }),
+            )?;
+        }
+        self.out.event("stopped", body)
+    }
+
+    fn terminated(&mut self) -> Result<()> {
+        self.out.event("exited", json!({ "exitCode": 0 }))?;
+        self.out.event("terminated", json!({}))
+    }
+}
+
+// 呼び出し時に積まれた引数の RAM アドレス（ARG から保存領域の手前まで）
+fn frame_arguments(cpu: &Cpu, depth: u64) -> Option<std::ops::Range<usize>> {
+    let entry = call_stack(cpu).get(depth as usize).copied()?;
+    let end = entry.lcl.checked_sub(SAVED_WORDS)?;
+    // 関数の外（ブートストラップなど）では ARG が意味を持たない
+    if entry.lcl == 0 || entry.arg > end {
+        return None;
+    }
+    Some(entry.arg as usize..end as usize)
+}
+
+// 10進か 0x 付きの16進
+fn parse_reference(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Cursor};
+
+    fn request(seq: u64, command: &str, arguments: Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_message(
+            &mut bytes,
+            &json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments }),
+        )
+        .unwrap();
+        bytes
+    }
+
+    // リクエストを順に流し、返ってきたメッセージをすべて読む
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let input: Vec<u8> = requests
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (command, arguments))| request(i as u64 + 1, command, arguments.clone()))
+            .collect();
+        let mut out = Vec::new();
+        serve(Box::new(Cursor::new(input)), &mut out).unwrap();
+
+        let mut messages = Vec::new();
+        let mut reader = out.as_slice();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn find<'a>(messages: &'a [Value], kind: &str, name: &str) -> Vec<&'a Value> {
+        let key = if kind == "event" { "event" } else { "command" };
+        messages
+            .iter()
+            .filter(|m| m["type"] == kind && m[key] == name)
+            .collect()
+    }
+
+    #[test]
+    fn test_breakpoint_stack_and_variables() {
+        let dir = std::env::temp_dir().join(format!("emu-dap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 6\ncall Main.double 1\npop static 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "hack" })),
+            ("launch", json!({ "program": dir })),
+            (
+                "setBreakpoints",
+                json!({
+                    "source": { "path": dir.join("Main.vm") },
+                    "breakpoints": [{ "line": 2, "condition": "RAM[ARG] == 6" }, { "line": 9 }],
+                }),
+            ),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            (
+                "variables",
+                json!({ "variablesReference": FRAME_ARGUMENTS }),
+            ),
+            ("evaluate", json!({ "expression": "SP" })),
+            ("continue", json!({ "threadId": 1 })),
+            ("evaluate", json!({ "expression": "RAM[16] + 1" })),
+            ("disconnect", json!({})),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(find(&messages, "event", "initialized").len(), 1);
+        let breakpoints = &find(&messages, "response", "setBreakpoints")[0]["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[1]["verified"], false);
+
+        let stopped = find(&messages, "event", "stopped");
+        assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+        let frames = &find(&messages, "response", "stackTrace")[0]["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "Main.double");
+        assert_eq!(frames[0]["line"], 2);
+        assert_eq!(frames[0]["source"]["name"], "Main.vm");
+        assert_eq!(frames[1]["name"], "Sys.init");
+        assert_eq!(frames[1]["line"], 3);
+
+        let arguments = &find(&messages, "response", "variables")[0]["body"]["variables"];
+        assert_eq!(arguments[0]["name"], "argument 0");
+        assert_eq!(arguments[0]["value"], "6");
+
+        // continue で最後（END のループ）まで進む
+        assert_eq!(find(&messages, "event", "terminated").len(), 1);
+        let results: Vec<&Value> = find(&messages, "response", "evaluate")
+            .iter()
+            .map(|m| &m["body"]["result"])
+            .collect();
+        assert_eq!(results[1], "13");
+    }
+
+    #[test]
+    fn test_errors_are_responses() {
+        let messages = session(&[
+            ("stackTrace", json!({})),
+            ("launch", json!({})),
+            ("frobnicate", json!({})),
+        ]);
+
+        let errors: Vec<&str> = messages
+            .iter()
+            .map(|m| {
+                assert_eq!(m["success"], false);
+                m["message"].as_str().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "No program has been launched",
+                "launch requires 'program'",
+                "Unsupported request 'frobnicate'",
+            ]
+        );
+    }
+}
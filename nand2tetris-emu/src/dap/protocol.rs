@@ -0,0 +1,111 @@
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+
+// Content-Length ヘッダ付きの JSON を1つ読む。入力が終わったら None
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            match length {
+                Some(_) => break,
+                None => continue,
+            }
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("Invalid DAP header '{}'", line);
+        };
+        if name.trim().eq_ignore_ascii_case("Content-Length") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context(format!("Invalid Content-Length '{}'", value.trim()))?,
+            );
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        serde_json::from_slice(&body).context("Invalid DAP message")?,
+    ))
+}
+
+pub fn write_message(out: &mut dyn Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+// 応答とイベントに通し番号を振って書き出す
+pub struct Sender<'a> {
+    out: &'a mut dyn Write,
+    seq: u64,
+}
+
+impl<'a> Sender<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        Sender { out, seq: 0 }
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(self.out, &message)
+    }
+
+    pub fn response(&mut self, request: &Value, body: Value) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    pub fn error(&mut self, request: &Value, message: &str) -> Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    pub fn event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let mut out = Vec::new();
+        let mut sender = Sender::new(&mut out);
+        sender.event("stopped", json!({"reason": "entry"})).unwrap();
+
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.starts_with("Content-Length: "), "{}", text);
+
+        let message = read_message(&mut out.as_slice()).unwrap().unwrap();
+        assert_eq!(message["seq"], 1);
+        assert_eq!(message["body"]["reason"], "entry");
+        assert!(read_message(&mut &b""[..]).unwrap().is_none());
+    }
+}
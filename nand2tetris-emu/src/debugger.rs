@@ -0,0 +1,671 @@
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{BufRead, Write},
+};
+
+use crate::{
+    Cpu,
+    cpu::{RunOptions, StepHook, StopReason},
+    disasm::disassemble_at,
+    dump::{format_dump, parse_address, parse_ranges_with},
+    expr::{Expr, parse_expr},
+    frame::{Frame, format_backtrace},
+    screen::ScreenBackend,
+    symbols::Symbols,
+};
+
+const PROMPT: &str = "(hdb) ";
+const HELP: &str = "\
+break LOC [if EXPR]
+               set a breakpoint (ROM address, LABEL or LABEL+N),
+               optionally only stopping when EXPR is non-zero
+delete [LOC]   delete a breakpoint (all if omitted)
+breaks         list breakpoints
+continue       run until a breakpoint or the end of the program
+step [N]       execute N instructions (default 1)
+next           step over calls (one VM command or Jack line when the source map is known)
+finish         run until the current function returns
+backtrace      show the call stack reconstructed from the saved frames
+regs           show PC, A, D and the cycle count
+x RANGES       show RAM (e.g. x R0..R15,256..260 or x counter)
+eval EXPR      evaluate an expression (e.g. eval RAM[SP-1] * 2, eval D == 0)
+list [LOC]     disassemble around PC or LOC
+quit           stop the program
+";
+
+// ブレークポイントで止まったときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnBreak {
+    /// Drop to the debugger prompt
+    Prompt,
+    /// Print the registers and R0..R15, then stop
+    Dump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Quit,
+}
+
+// 条件付きブレークポイントの条件（表示用に元の文字列も持つ）
+struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    conditions: HashMap<u16, Condition>,
+    symbols: Symbols,
+    on_break: OnBreak,
+}
+
+impl Debugger {
+    pub fn new(symbols: Symbols) -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            conditions: HashMap::new(),
+            symbols,
+            on_break: OnBreak::Prompt,
+        }
+    }
+
+    pub fn with_on_break(mut self, on_break: OnBreak) -> Self {
+        self.on_break = on_break;
+        self
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    // ROM アドレスは数値かラベル名（LOOP+2 のようなオフセット付きも可）で指定する
+    pub fn resolve(&self, text: &str) -> Result<u16> {
+        let text = text.trim();
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (
+                name,
+                offset
+                    .parse::<u16>()
+                    .context(format!("Invalid offset in '{}'", text))?,
+            ),
+            None => (text, 0),
+        };
+        match self.symbols.resolve(name) {
+            Some(address) => Ok(address + offset),
+            None => text
+                .parse()
+                .context(format!("Invalid ROM address or unknown label '{}'", text)),
+        }
+    }
+
+    // 条件はすべて外れる
+    pub fn set_breakpoints(&mut self, breakpoints: BTreeSet<u16>) {
+        self.breakpoints = breakpoints;
+        self.conditions.clear();
+    }
+
+    pub fn set_condition(&mut self, address: u16, text: &str) -> Result<()> {
+        let expr = parse_expr(text, &self.symbols)?;
+        self.conditions.insert(
+            address,
+            Condition {
+                text: text.to_string(),
+                expr,
+            },
+        );
+        Ok(())
+    }
+
+    // PC がブレークポイントにあり、条件があればそれが成り立つか
+    pub fn should_stop(&self, cpu: &Cpu) -> Result<bool> {
+        if !self.breakpoints.contains(&cpu.pc) {
+            return Ok(false);
+        }
+        match self.conditions.get(&cpu.pc) {
+            Some(condition) => Ok(condition.expr.eval(cpu)? != 0),
+            None => Ok(true),
+        }
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    // "LOOP if RAM[0] > 2048" のように条件を付けられる
+    pub fn add_breakpoint(&mut self, text: &str) -> Result<u16> {
+        let (location, condition) = match text.split_once(" if ") {
+            Some((location, condition)) => (location, Some(condition.trim())),
+            None => (text, None),
+        };
+        let address = self.resolve(location)?;
+        match condition {
+            Some(condition) => self.set_condition(address, condition)?,
+            None => {
+                self.conditions.remove(&address);
+            }
+        }
+        self.breakpoints.insert(address);
+        Ok(address)
+    }
+
+    fn describe_breakpoint(&self, address: u16) -> String {
+        match self.conditions.get(&address) {
+            Some(condition) => format!("{} if {}", self.describe(address), condition.text),
+            None => self.describe(address),
+        }
+    }
+
+    // 条件の評価に失敗したら、その理由を出して止まる
+    fn hits_breakpoint(&self, cpu: &Cpu, out: &mut dyn Write) -> Result<bool> {
+        match self.should_stop(cpu) {
+            Ok(stop) => Ok(stop),
+            Err(e) => {
+                writeln!(out, "Error in breakpoint condition: {}", e)?;
+                Ok(true)
+            }
+        }
+    }
+
+    // 数値にラベルを添えて表示する（例: 12 (LOOP+2)）
+    pub fn describe(&self, address: u16) -> String {
+        match self.symbols.locate(address) {
+            Some(location) => format!("{} ({})", address, location),
+            None => address.to_string(),
+        }
+    }
+
+    // 終了するまで実行し、ブレークポイントで止まるたびに on_break に従う
+    pub fn run(
+        &mut self,
+        cpu: &mut Cpu,
+        backend: &mut dyn ScreenBackend,
+        options: &RunOptions,
+        hooks: &mut [&mut dyn StepHook],
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<StopReason> {
+        loop {
+            let options = RunOptions {
+                breakpoints: self.breakpoints.clone(),
+                ..options.clone()
+            };
+            let reason = cpu.run_with_backend(backend, &options, hooks)?;
+            if reason != StopReason::Breakpoint {
+                return Ok(reason);
+            }
+            if !self.hits_breakpoint(cpu, out)? {
+                continue;
+            }
+
+            writeln!(out, "Breakpoint at {}", self.describe(cpu.pc))?;
+            match self.on_break {
+                OnBreak::Dump => {
+                    write!(out, "{}", format_dump(cpu, &[0..=15]))?;
+                    if Frame::current(cpu).is_some() {
+                        write!(out, "{}", format_backtrace(cpu, &self.symbols))?;
+                    }
+                    return Ok(reason);
+                }
+                OnBreak::Prompt => {
+                    if self.prompt(cpu, input, out)? == Action::Quit {
+                        return Ok(reason);
+                    }
+                }
+            }
+        }
+    }
+
+    // continue か quit が入力されるまでコマンドを処理する（入力の終わりは quit）
+    pub fn prompt(
+        &mut self,
+        cpu: &mut Cpu,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<Action> {
+        loop {
+            write!(out, "{}", PROMPT)?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                writeln!(out)?;
+                return Ok(Action::Quit);
+            }
+            match self.execute(line.trim(), cpu, out) {
+                Ok(Some(action)) => return Ok(action),
+                Ok(None) => {}
+                Err(e) => writeln!(out, "Error: {}", e)?,
+            }
+        }
+    }
+
+    // 1行のコマンドを実行する。None ならプロンプトを続ける
+    pub fn execute(
+        &mut self,
+        line: &str,
+        cpu: &mut Cpu,
+        out: &mut dyn Write,
+    ) -> Result<Option<Action>> {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "" => {}
+            "break" | "b" => {
+                let address = self.add_breakpoint(arg)?;
+                writeln!(
+                    out,
+                    "Breakpoint set at {}",
+                    self.describe_breakpoint(address)
+                )?;
+            }
+            "delete" | "d" if arg.is_empty() => {
+                self.set_breakpoints(BTreeSet::new());
+                writeln!(out, "All breakpoints deleted")?;
+            }
+            "delete" | "d" => {
+                let address = self.resolve(arg)?;
+                self.conditions.remove(&address);
+                if !self.breakpoints.remove(&address) {
+                    bail!("No breakpoint at {}", self.describe(address));
+                }
+                writeln!(out, "Breakpoint deleted at {}", self.describe(address))?;
+            }
+            "breaks" | "info" => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints")?;
+                }
+                for &address in &self.breakpoints {
+                    writeln!(out, "{}", self.describe_breakpoint(address))?;
+                }
+            }
+            "continue" | "c" => return Ok(Some(Action::Continue)),
+            "step" | "s" => {
+                let count: u64 = if arg.is_empty() {
+                    1
+                } else {
+                    arg.parse()
+                        .context(format!("Invalid step count '{}'", arg))?
+                };
+                for _ in 0..count {
+                    if cpu.is_finished() {
+                        writeln!(out, "Program finished")?;
+                        break;
+                    }
+                    cpu.step()?;
+                }
+                self.print_current(cpu, out)?;
+            }
+            "next" | "n" => {
+                self.next(cpu, out)?;
+                self.print_current(cpu, out)?;
+            }
+            "finish" | "fin" => {
+                self.finish(cpu, out)?;
+                self.print_current(cpu, out)?;
+            }
+            "backtrace" | "bt" => write!(out, "{}", format_backtrace(cpu, &self.symbols))?,
+            "regs" | "r" => {
+                writeln!(
+                    out,
+                    "PC={} A={} D={} cycles={}",
+                    self.describe(cpu.pc),
+                    cpu.a as i16,
+                    cpu.d as i16,
+                    cpu.cycles
+                )?;
+            }
+            "x" => {
+                // 変数名でも指定できる
+                let ranges = parse_ranges_with(arg, |text| {
+                    match self.symbols.resolve_variable(text.trim()) {
+                        Some(address) => Ok(address),
+                        None => parse_address(text),
+                    }
+                })?;
+                for range in ranges {
+                    for address in range {
+                        write!(out, "RAM[{}]={}", address, cpu.ram[address as usize] as i16)?;
+                        if let Some(name) = self.symbols.variable_at(address) {
+                            write!(out, " ({})", name)?;
+                        }
+                        writeln!(out)?;
+                    }
+                }
+            }
+            "eval" | "p" => {
+                let value = parse_expr(arg, &self.symbols)?.eval(cpu)?;
+                writeln!(out, "{}", value)?;
+            }
+            "list" | "l" => {
+                let center = if arg.is_empty() {
+                    cpu.pc
+                } else {
+                    self.resolve(arg)?
+                };
+                self.list(cpu, center, out)?;
+            }
+            "quit" | "q" => return Ok(Some(Action::Quit)),
+            "help" | "h" => write!(out, "{}", HELP)?,
+            _ => bail!("Unknown command '{}' (type 'help')", command),
+        }
+        Ok(None)
+    }
+
+    fn print_current(&self, cpu: &Cpu, out: &mut dyn Write) -> Result<()> {
+        write!(
+            out,
+            "{}: {}",
+            self.describe(cpu.pc),
+            disassemble_at(&cpu.rom, cpu.pc, &self.symbols)
+        )?;
+        if let Some(source) = self.symbols.source_at(cpu.pc) {
+            write!(out, "  [{}:{}]", source.file, source.line)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    // 1命令進め、それが call のジャンプなら戻ってくるまで実行する。
+    // ジャンプ先で積まれた戻りアドレスがジャンプの次を指していれば call とみなす
+    fn step_over_call(&self, cpu: &mut Cpu, out: &mut dyn Write) -> Result<bool> {
+        let from = cpu.pc;
+        cpu.step()?;
+        if let Some(frame) = Frame::current(cpu)
+            && cpu.pc != from.wrapping_add(1)
+            && frame.return_address == from.wrapping_add(1)
+        {
+            return self.run_until(cpu, out, |cpu| frame.has_returned(cpu));
+        }
+        Ok(true)
+    }
+
+    // ソースマップがあれば VM の行が変わるまで、なければ1命令ぶん call を飛ばして進める
+    pub fn next(&self, cpu: &mut Cpu, out: &mut dyn Write) -> Result<()> {
+        if cpu.is_finished() {
+            writeln!(out, "Program finished")?;
+            return Ok(());
+        }
+        let Some(start) = self.symbols.source_at(cpu.pc).cloned() else {
+            self.step_over_call(cpu, out)?;
+            return Ok(());
+        };
+
+        // return を越えたら呼び出し元で止まる
+        let frame = Frame::current(cpu);
+        loop {
+            if !self.step_over_call(cpu, out)? {
+                return Ok(());
+            }
+            if frame.is_some_and(|frame| frame.has_returned(cpu)) {
+                return Ok(());
+            }
+            match self.symbols.source_at(cpu.pc) {
+                Some(source) if *source != start => return Ok(()),
+                _ => {}
+            }
+            if self.stopped(cpu, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // 今の関数から戻るまで実行する
+    pub fn finish(&self, cpu: &mut Cpu, out: &mut dyn Write) -> Result<()> {
+        let frame = Frame::current(cpu).context("Not inside a function frame")?;
+        self.run_until(cpu, out, |cpu| frame.has_returned(cpu))?;
+        Ok(())
+    }
+
+    // 条件を満たすまで実行する。終了・停止・ブレークポイントで止まったら false
+    fn run_until(
+        &self,
+        cpu: &mut Cpu,
+        out: &mut dyn Write,
+        done: impl Fn(&Cpu) -> bool,
+    ) -> Result<bool> {
+        loop {
+            if self.stopped(cpu, out)? {
+                return Ok(false);
+            }
+            cpu.step()?;
+            if done(cpu) {
+                return Ok(true);
+            }
+            if self.hits_breakpoint(cpu, out)? {
+                writeln!(out, "Breakpoint at {}", self.describe(cpu.pc))?;
+                return Ok(false);
+            }
+        }
+    }
+
+    fn stopped(&self, cpu: &Cpu, out: &mut dyn Write) -> Result<bool> {
+        if cpu.is_finished() {
+            writeln!(out, "Program finished")?;
+        } else if cpu.is_halted() {
+            writeln!(out, "Program halted")?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    // 前後数命令を逆アセンブルし、PC とブレークポイントに印を付ける
+    fn list(&self, cpu: &Cpu, center: u16, out: &mut dyn Write) -> Result<()> {
+        let start = center.saturating_sub(4);
+        let end = (center as usize + 5).min(cpu.program_len().max(center as usize + 1));
+        for address in start as usize..end {
+            let address = address as u16;
+            if let Some(label) = self.symbols.label_at(address) {
+                writeln!(out, "      ({})", label)?;
+            }
+            let marker = match (address == cpu.pc, self.breakpoints.contains(&address)) {
+                (true, true) => "=>*",
+                (true, false) => "=> ",
+                (false, true) => "  *",
+                (false, false) => "   ",
+            };
+            writeln!(
+                out,
+                "{} {:>5}  {}",
+                marker,
+                address,
+                disassemble_at(&cpu.rom, address, &self.symbols)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::Headless;
+    use std::collections::HashMap;
+
+    // RAM[0] を 3 回インクリメントして止まる
+    const PROGRAM: &[u16] = &[
+        0b0000000000000011, // @3
+        0b1110110000010000, // D=A
+        0b0000000000000000, // (LOOP) @0
+        0b1111110111001000, // M=M+1
+        0b0000000000000010, // @LOOP
+        0b1110001110010000, // D=D-1
+        0b1110001100000101, // D;JNE
+        0b0000000000000111, // (END) @END
+        0b1110101010000111, // 0;JMP
+    ];
+
+    fn debugger() -> Debugger {
+        Debugger::new(Symbols::from_labels(&HashMap::from([
+            ("LOOP".to_string(), 2),
+            ("END".to_string(), 7),
+        ])))
+    }
+
+    fn session(debugger: &mut Debugger, commands: &str) -> (Cpu, String) {
+        let mut cpu = Cpu::new(PROGRAM).unwrap();
+        let mut input = commands.as_bytes();
+        let mut out = Vec::new();
+        debugger
+            .run(
+                &mut cpu,
+                &mut Headless,
+                &RunOptions::default(),
+                &mut [],
+                &mut input,
+                &mut out,
+            )
+            .unwrap();
+        (cpu, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let debugger = debugger();
+        assert_eq!(debugger.resolve("LOOP").unwrap(), 2);
+        assert_eq!(debugger.resolve("LOOP+2").unwrap(), 4);
+        assert_eq!(debugger.resolve("1234").unwrap(), 1234);
+        assert!(debugger.resolve("MISSING").is_err());
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint("LOOP").unwrap();
+
+        let (cpu, out) = session(&mut debugger, "x R0\nc\nc\ndelete LOOP\nc\n");
+        assert_eq!(cpu.ram[0], 3);
+        assert_eq!(out.matches("Breakpoint at 2 (LOOP)").count(), 3);
+        assert!(out.contains("(hdb) RAM[0]=0\n"));
+    }
+
+    #[test]
+    fn test_conditional_break_and_eval() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint("LOOP if R0 == 2").unwrap();
+
+        let (cpu, out) = session(&mut debugger, "breaks\neval R0 * 10 + D\nc\n");
+        assert_eq!(cpu.ram[0], 3);
+        assert_eq!(out.matches("Breakpoint at").count(), 1);
+        assert!(out.contains("(hdb) 2 (LOOP) if R0 == 2\n"), "{}", out);
+        // 2 回目のループの先頭で R0=2, D=1
+        assert!(out.contains("(hdb) 21\n"), "{}", out);
+
+        assert!(debugger.add_breakpoint("LOOP if R0 ==").is_err());
+    }
+
+    #[test]
+    fn test_break_dump() {
+        let mut debugger = debugger().with_on_break(OnBreak::Dump);
+        debugger.add_breakpoint("LOOP+1").unwrap();
+
+        let (cpu, out) = session(&mut debugger, "");
+        assert_eq!(cpu.pc, 3);
+        assert!(out.starts_with("Breakpoint at 3 (LOOP+1)\nPC=3 A=0 D=3\nRAM[0]=0\n"));
+    }
+
+    #[test]
+    fn test_step_and_list() {
+        let mut debugger = debugger();
+        debugger.add_breakpoint("LOOP+4").unwrap();
+        let mut cpu = Cpu::new(PROGRAM).unwrap();
+        let mut out = Vec::new();
+
+        debugger.execute("step 2", &mut cpu, &mut out).unwrap();
+        debugger.execute("list", &mut cpu, &mut out).unwrap();
+        assert!(debugger.execute("bogus", &mut cpu, &mut out).is_err());
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("2 (LOOP): @0\n"));
+        assert!(out.contains("      (LOOP)\n=>      2  @0\n"));
+        assert!(out.contains("  *     6  D;JNE\n"));
+    }
+
+    fn vm_session(breakpoint: &str, commands: &str) -> (Cpu, String) {
+        let dir =
+            std::env::temp_dir().join(format!("emu-debug-{}-{}", std::process::id(), breakpoint));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 6\ncall Main.double 1\npop static 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("Main.vm"),
+            "function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+        let program = crate::loader::load_program(&dir, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut debugger = Debugger::new(program.symbols);
+        debugger.add_breakpoint(breakpoint).unwrap();
+        let mut cpu = Cpu::new(&program.words).unwrap();
+        let mut input = commands.as_bytes();
+        let mut out = Vec::new();
+        debugger
+            .run(
+                &mut cpu,
+                &mut Headless,
+                &RunOptions::default(),
+                &mut [],
+                &mut input,
+                &mut out,
+            )
+            .unwrap();
+        (cpu, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_next_steps_over_call() {
+        let (cpu, out) = vm_session("Sys.init", "next\nnext\nquit\n");
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[1].ends_with("[Sys:3]"), "{}", lines[1]);
+        assert!(lines[2].ends_with("[Sys:4]"), "{}", lines[2]);
+        // Main.double(6) の戻り値がスタックの先頭にある
+        assert_eq!(cpu.ram[cpu.ram[0] as usize - 1], 12);
+    }
+
+    #[test]
+    fn test_finish_returns_to_caller() {
+        let (cpu, out) = vm_session("Main.double", "finish\nquit\n");
+
+        assert!(out.lines().nth(1).unwrap().ends_with("[Sys:4]"), "{}", out);
+        assert_eq!(cpu.ram[cpu.ram[0] as usize - 1], 12);
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let (_, out) = vm_session("Main.double", "bt\nquit\n");
+
+        let lines: Vec<&str> = out
+            .lines()
+            .skip(1)
+            .map(|line| line.trim_start_matches(PROMPT))
+            .filter(|line| !line.is_empty())
+            .collect();
+        assert_eq!(lines.len(), 3, "{}", out);
+        assert!(lines[0].starts_with("#0  Main.double (pc="), "{}", out);
+        assert!(lines[0].ends_with("[Main:2]"), "{}", out);
+        assert!(lines[1].starts_with("#1  Sys.init (pc="), "{}", out);
+        assert!(lines[1].ends_with("[Sys:3]"), "{}", out);
+        // ブートストラップは関数ではない
+        assert!(lines[2].starts_with("#2  ?? "), "{}", out);
+    }
+
+    #[test]
+    fn test_finish_outside_function() {
+        let mut cpu = Cpu::new(PROGRAM).unwrap();
+        let mut out = Vec::new();
+        assert!(debugger().execute("finish", &mut cpu, &mut out).is_err());
+    }
+}
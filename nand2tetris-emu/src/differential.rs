@@ -0,0 +1,276 @@
+// 乱数で作ったプログラムを 2 通りに実行し、結果が同じか確かめる（差分テスト）
+//
+// VM プログラムは nand2tetris-vm のインタプリタと、変換器・アセンブラ・CPU エミュレータで
+// 実行し、止まったときのポインタ・temp・static・スタックと、セグメントが指す範囲を比べる。
+// アセンブリは nand2tetris-asm のインタプリタと、アセンブラ・CPU エミュレータで実行し、
+// PC・A・D と RAM 全体を比べる。同じ seed からはいつも同じプログラムができる
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use nand2tetris_asm::{assemble_program, generate as asm_generate, interpret as asm_interpret};
+use nand2tetris_vm::{
+    TranslateOptions, VMTranslator,
+    generate::{self as vm_generate, HALT, POINTERS, REGISTERS},
+    interpret as vm_interpret,
+};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::cpu::{Cpu, StopReason};
+
+// インタプリタのステップ数と CPU のサイクル数の上限。生成したプログラムはずっと手前で止まる
+const MAX_STEPS: u64 = 1_000_000;
+const MAX_CYCLES: u64 = 50_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kind {
+    /// VM programs: the VM interpreter against the translator, assembler and CPU
+    Vm,
+    /// Assembly programs: the assembly interpreter against the assembler and CPU
+    Asm,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Vm => "vm",
+            Kind::Asm => "asm",
+        })
+    }
+}
+
+pub struct Case {
+    pub kind: Kind,
+    pub seed: u64,
+    // (ファイル名, ソース)。アセンブリは 1 ファイル
+    pub sources: Vec<(String, String)>,
+}
+
+impl Case {
+    pub fn generate(kind: Kind, seed: u64) -> Self {
+        let sources = match kind {
+            Kind::Vm => vm_generate::generate(seed),
+            Kind::Asm => vec![(format!("Fuzz{}", seed), asm_generate::generate(seed))],
+        };
+        Case {
+            kind,
+            seed,
+            sources,
+        }
+    }
+
+    // 違っていればその説明、同じなら None。基準のインタプリタが失敗したらエラー
+    pub fn check(&self) -> Result<Option<String>> {
+        match self.kind {
+            Kind::Vm => check_vm(&self.sources),
+            Kind::Asm => check_asm(&self.sources[0].1),
+        }
+    }
+
+    // VM は dir/fuzz-vm-<seed>/ に .vm を、アセンブリは dir/fuzz-asm-<seed>.asm に書く
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        let name = format!("fuzz-{}-{}", self.kind, self.seed);
+        let (path, files) = match self.kind {
+            Kind::Vm => {
+                let path = dir.join(name);
+                let files = self
+                    .sources
+                    .iter()
+                    .map(|(file, source)| (path.join(format!("{}.vm", file)), source))
+                    .collect();
+                (path, files)
+            }
+            Kind::Asm => {
+                let path = dir.join(format!("{}.asm", name));
+                (path.clone(), vec![(path, &self.sources[0].1)])
+            }
+        };
+        for (file, source) in files {
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)
+                    .context(format!("Failed to create '{}'", parent.display()))?;
+            }
+            fs::write(&file, source).context(format!("Failed to write '{}'", file.display()))?;
+        }
+        Ok(path)
+    }
+}
+
+fn check_vm(sources: &[(String, String)]) -> Result<Option<String>> {
+    let mut interpreter = vm_interpret::Interpreter::new(sources)?;
+    interpreter.ram[..REGISTERS.len()].copy_from_slice(&REGISTERS);
+    interpreter
+        .run(MAX_STEPS)
+        .context("The VM interpreter failed on a generated program")?;
+
+    let translation = match VMTranslator::translate_sources(sources, &TranslateOptions::default()) {
+        Ok(translation) => translation,
+        Err(e) => return Ok(Some(format!("the translator rejects it: {:#}", e))),
+    };
+    let program = match assemble_program(&translation.asm) {
+        Ok(program) => program,
+        Err(e) => {
+            return Ok(Some(format!(
+                "the translated code does not assemble: {:#}",
+                e
+            )));
+        }
+    };
+    let mut cpu = Cpu::new(&program.words)?;
+    cpu.ram[..REGISTERS.len()].copy_from_slice(&REGISTERS);
+    let halt = program.labels.get(HALT).copied();
+    match cpu.run(Some(MAX_CYCLES)) {
+        Err(e) => return Ok(Some(format!("the CPU stopped with an error: {:#}", e))),
+        Ok(StopReason::Halted) if Some(cpu.pc) == halt => {}
+        Ok(reason) => {
+            return Ok(Some(format!(
+                "the CPU stopped at ROM[{}] ({:?}) instead of {}",
+                cpu.pc, reason, HALT
+            )));
+        }
+    }
+
+    // R13..R15 は変換器の作業用。SP より上は呼び出しの跡
+    let sp = interpreter.ram[0] as usize;
+    let ranges = [
+        0..13,
+        16..sp.max(256),
+        1000..1200,
+        POINTERS.start as usize..POINTERS.end as usize + 16,
+    ];
+    Ok(compare_ram(
+        &cpu.ram,
+        &interpreter.ram,
+        ranges.into_iter().flatten(),
+        "VM",
+    ))
+}
+
+fn check_asm(source: &str) -> Result<Option<String>> {
+    let mut interpreter = asm_interpret::Interpreter::new(source)?;
+    interpreter
+        .run(MAX_STEPS)
+        .context("The assembly interpreter failed on a generated program")?;
+
+    let program = match assemble_program(source) {
+        Ok(program) => program,
+        Err(e) => return Ok(Some(format!("the assembler rejects it: {:#}", e))),
+    };
+    let mut cpu = Cpu::new(&program.words)?;
+    match cpu.run(Some(MAX_CYCLES)) {
+        Err(e) => return Ok(Some(format!("the CPU stopped with an error: {:#}", e))),
+        Ok(StopReason::Halted) => {}
+        Ok(reason) => {
+            return Ok(Some(format!(
+                "the CPU stopped at ROM[{}] ({:?}) instead of halting",
+                cpu.pc, reason
+            )));
+        }
+    }
+
+    for (register, ours, expected) in [
+        ("PC", cpu.pc, interpreter.pc as u16),
+        ("A", cpu.a, interpreter.a),
+        ("D", cpu.d, interpreter.d),
+    ] {
+        if ours != expected {
+            return Ok(Some(format!(
+                "{} is {} on the CPU but {} in the assembly interpreter",
+                register, ours as i16, expected as i16
+            )));
+        }
+    }
+    Ok(compare_ram(
+        &cpu.ram,
+        &interpreter.ram,
+        0..interpreter.ram.len(),
+        "assembly",
+    ))
+}
+
+// 最初に違うアドレスと、違う語数
+fn compare_ram(
+    ours: &[u16],
+    expected: &[u16],
+    addresses: impl IntoIterator<Item = usize>,
+    interpreter: &str,
+) -> Option<String> {
+    let differing: Vec<usize> = addresses
+        .into_iter()
+        .filter(|&address| ours[address] != expected[address])
+        .collect();
+    let &first = differing.first()?;
+    Some(format!(
+        "RAM[{}] is {} on the CPU but {} in the {} interpreter ({} differing word{})",
+        first,
+        ours[first] as i16,
+        expected[first] as i16,
+        interpreter,
+        differing.len(),
+        if differing.len() == 1 { "" } else { "s" }
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_programs_agree() {
+        for kind in [Kind::Vm, Kind::Asm] {
+            for seed in 0..40 {
+                let case = Case::generate(kind, seed);
+                assert_eq!(case.check().unwrap(), None, "{} seed {}", kind, seed);
+            }
+        }
+    }
+
+    // x - y があふれる比較（変換器が以前まちがえていた）
+    #[test]
+    fn test_overflowing_comparisons() {
+        let source = "push constant 32767\npush constant 1\nneg\ngt\n\
+                      push constant 32767\nneg\npush constant 2\nlt\n\
+                      push constant 1\npush constant 32767\nneg\nlt\n\
+                      label HALT\ngoto HALT\n";
+        let case = Case {
+            kind: Kind::Vm,
+            seed: 0,
+            sources: vec![("Main".to_string(), source.to_string())],
+        };
+        assert_eq!(case.check().unwrap(), None);
+    }
+
+    #[test]
+    fn test_divergence() {
+        // 基準のインタプリタが実行できないものは、食い違いではなくエラー
+        let case = Case {
+            kind: Kind::Asm,
+            seed: 0,
+            sources: vec![("Bad".to_string(), "D=A+D\n(END)\n@END\n0;JMP\n".to_string())],
+        };
+        assert!(case.check().is_err());
+
+        let message = compare_ram(&[0, 1, 2, 3], &[0, 1, 5, 6], 0..4, "VM").unwrap();
+        assert_eq!(
+            message,
+            "RAM[2] is 2 on the CPU but 5 in the VM interpreter (2 differing words)"
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("emu-differential-{}", std::process::id()));
+        let vm = Case::generate(Kind::Vm, 3).save(&dir).unwrap();
+        let asm = Case::generate(Kind::Asm, 3).save(&dir).unwrap();
+        let main = fs::read_to_string(vm.join("Main.vm")).unwrap();
+        let saved = fs::read_to_string(&asm).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vm.file_name().unwrap(), "fuzz-vm-3");
+        assert!(main.contains("label HALT\ngoto HALT\n"));
+        assert_eq!(asm.file_name().unwrap(), "fuzz-asm-3.asm");
+        assert_eq!(saved, asm_generate::generate(3));
+    }
+}
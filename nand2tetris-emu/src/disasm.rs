@@ -0,0 +1,129 @@
+use crate::symbols::Symbols;
+
+// 機械語を Hack アセンブリに戻す
+pub fn disassemble(instruction: u16) -> String {
+    if instruction & 0x8000 == 0 {
+        return format!("@{}", instruction);
+    }
+
+    let comp = comp_mnemonic((instruction >> 6) & 0x7f).unwrap_or("?");
+    let dest = match (instruction >> 3) & 0x7 {
+        0 => "",
+        1 => "M=",
+        2 => "D=",
+        3 => "DM=",
+        4 => "A=",
+        5 => "AM=",
+        6 => "AD=",
+        _ => "ADM=",
+    };
+    let jump = match instruction & 0x7 {
+        0 => "",
+        1 => ";JGT",
+        2 => ";JEQ",
+        3 => ";JGE",
+        4 => ";JLT",
+        5 => ";JNE",
+        6 => ";JLE",
+        _ => ";JMP",
+    };
+
+    format!("{}{}{}", dest, comp, jump)
+}
+
+// A 命令の値を、次の命令がジャンプならラベル名、メモリを使うなら変数名で表示する
+pub fn disassemble_at(rom: &[u16], address: u16, symbols: &Symbols) -> String {
+    let instruction = rom[address as usize];
+    if instruction & 0x8000 == 0
+        && let Some(&next) = rom.get(address as usize + 1)
+        && next & 0x8000 != 0
+    {
+        let name = if next & 0x7 != 0 {
+            symbols.label_at(instruction)
+        } else if next & 0x1008 != 0 {
+            symbols.variable_at(instruction)
+        } else {
+            None
+        };
+        if let Some(name) = name {
+            return format!("@{}", name);
+        }
+    }
+    disassemble(instruction)
+}
+
+// a + c1..c6 の7ビット
+fn comp_mnemonic(bits: u16) -> Option<&'static str> {
+    let mnemonic = match bits {
+        0b0101010 => "0",
+        0b0111111 => "1",
+        0b0111010 => "-1",
+        0b0001100 => "D",
+        0b0110000 => "A",
+        0b0001101 => "!D",
+        0b0110001 => "!A",
+        0b0001111 => "-D",
+        0b0110011 => "-A",
+        0b0011111 => "D+1",
+        0b0110111 => "A+1",
+        0b0001110 => "D-1",
+        0b0110010 => "A-1",
+        0b0000010 => "D+A",
+        0b0010011 => "D-A",
+        0b0000111 => "A-D",
+        0b0000000 => "D&A",
+        0b0010101 => "D|A",
+        0b1110000 => "M",
+        0b1110001 => "!M",
+        0b1110011 => "-M",
+        0b1110111 => "M+1",
+        0b1110010 => "M-1",
+        0b1000010 => "D+M",
+        0b1010011 => "D-M",
+        0b1000111 => "M-D",
+        0b1000000 => "D&M",
+        0b1010101 => "D|M",
+        _ => return None,
+    };
+    Some(mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0b0000000000010101, "@21")]
+    #[case(0b1110110000010000, "D=A")]
+    #[case(0b1111110111001000, "M=M+1")]
+    #[case(0b1110101010000111, "0;JMP")]
+    #[case(0b1110001100000001, "D;JGT")]
+    #[case(0b1111110010101000, "AM=M-1")]
+    #[case(0b1111101010111111, "ADM=?;JMP")]
+    fn test_disassemble(#[case] instruction: u16, #[case] expected: &str) {
+        assert_eq!(disassemble(instruction), expected);
+    }
+
+    #[test]
+    fn test_disassemble_with_symbols() {
+        let symbols = Symbols::parse("label LOOP 2\nvar counter 16").unwrap();
+        let rom = [
+            0b0000000000010000, // @counter
+            0b1111110111001000, // M=M+1
+            0b0000000000000010, // @LOOP
+            0b1110101010000111, // 0;JMP
+            0b0000000000010000, // @16
+            0b1110110000010000, // D=A
+            0b0000000000000010, // @2
+        ];
+
+        let text: Vec<_> = (0..rom.len() as u16)
+            .map(|address| disassemble_at(&rom, address, &symbols))
+            .collect();
+        assert_eq!(
+            text,
+            ["@counter", "M=M+1", "@LOOP", "0;JMP", "@16", "D=A", "@2"]
+        );
+    }
+}
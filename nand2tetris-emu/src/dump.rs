@@ -0,0 +1,185 @@
+use anyhow::{Context, Result, bail, ensure};
+use clap::ValueEnum;
+use nand2tetris_core::symbols::predefined;
+use std::{fmt::Write, fs, ops::RangeInclusive, path::Path};
+
+use crate::{Cpu, cpu::RAM_SIZE};
+
+// "R0..R15,256..300,SP" のような指定を解析する（範囲は両端を含む）
+pub fn parse_ranges(spec: &str) -> Result<Vec<RangeInclusive<u16>>> {
+    parse_ranges_with(spec, parse_address)
+}
+
+// アドレスの解釈だけを差し替える（ROM のラベル名など）
+pub fn parse_ranges_with(
+    spec: &str,
+    parse_address: impl Fn(&str) -> Result<u16>,
+) -> Result<Vec<RangeInclusive<u16>>> {
+    let mut ranges = Vec::new();
+
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let range = match item.split_once("..") {
+            Some((start, end)) => {
+                let start = parse_address(start)?;
+                let end = parse_address(end)?;
+                ensure!(start <= end, "Invalid range '{}': start > end", item);
+                start..=end
+            }
+            None => {
+                let address = parse_address(item)?;
+                address..=address
+            }
+        };
+        ranges.push(range);
+    }
+
+    Ok(ranges)
+}
+
+pub fn parse_address(text: &str) -> Result<u16> {
+    let text = text.trim();
+    let address = match predefined(text) {
+        Some(address) => address,
+        None => match text.strip_prefix('R') {
+            Some(n) => {
+                let n: u16 = n.parse().context(format!("Invalid register '{}'", text))?;
+                ensure!(n <= 15, "Invalid register '{}'", text);
+                n
+            }
+            None => text
+                .parse()
+                .context(format!("Invalid address '{}'", text))?,
+        },
+    };
+
+    if address as usize >= RAM_SIZE {
+        bail!("Address out of range: {}", text);
+    }
+    Ok(address)
+}
+
+// Hack の値は16ビット2の補数なので符号付きで表示する
+pub fn format_dump(cpu: &Cpu, ranges: &[RangeInclusive<u16>]) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "PC={} A={} D={}", cpu.pc, cpu.a as i16, cpu.d as i16);
+    for range in ranges {
+        for address in range.clone() {
+            let _ = writeln!(out, "RAM[{}]={}", address, cpu.ram[address as usize] as i16);
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Radix {
+    /// Signed decimal
+    Dec,
+    /// Four hexadecimal digits
+    Hex,
+    /// Sixteen binary digits
+    Bin,
+}
+
+pub fn format_value(value: u16, radix: Radix) -> String {
+    match radix {
+        Radix::Dec => (value as i16).to_string(),
+        Radix::Hex => format!("{:04x}", value),
+        Radix::Bin => format!("{:016b}", value),
+    }
+}
+
+// 1行に "アドレス 値"
+pub fn format_ram(cpu: &Cpu, ranges: &[RangeInclusive<u16>], radix: Radix) -> String {
+    let mut out = String::new();
+    for range in ranges {
+        for address in range.clone() {
+            let value = format_value(cpu.ram[address as usize], radix);
+            let _ = writeln!(out, "{} {}", address, value);
+        }
+    }
+    out
+}
+
+// 1行に1語。bin はそのまま .hack として読み込める
+pub fn format_rom(cpu: &Cpu, radix: Radix) -> String {
+    let mut out = String::new();
+    for &word in &cpu.rom[..cpu.program_len()] {
+        let _ = writeln!(out, "{}", format_value(word, radix));
+    }
+    out
+}
+
+pub fn write_ram(
+    path: &Path,
+    cpu: &Cpu,
+    ranges: &[RangeInclusive<u16>],
+    radix: Radix,
+) -> Result<()> {
+    fs::write(path, format_ram(cpu, ranges, radix))
+        .context(format!("Failed to write '{}'", path.display()))
+}
+
+pub fn write_rom(path: &Path, cpu: &Cpu, radix: Radix) -> Result<()> {
+    fs::write(path, format_rom(cpu, radix)).context(format!("Failed to write '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("R0..R2", vec![0..=2])]
+    #[case("256..300", vec![256..=300])]
+    #[case("SP, LCL,R15", vec![0..=0, 1..=1, 15..=15])]
+    #[case("SCREEN..16385,KBD", vec![16384..=16385, 24576..=24576])]
+    #[case("", vec![])]
+    fn test_parse_ranges(#[case] spec: &str, #[case] expected: Vec<RangeInclusive<u16>>) {
+        assert_eq!(parse_ranges(spec).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("R16")]
+    #[case("10..5")]
+    #[case("40000")]
+    #[case("foo")]
+    fn test_parse_ranges_invalid(#[case] spec: &str) {
+        assert!(parse_ranges(spec).is_err());
+    }
+
+    #[test]
+    fn test_format_dump() {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        cpu.a = 2;
+        cpu.d = 0xffff;
+        cpu.pc = 7;
+        cpu.ram[0] = 256;
+        cpu.ram[1] = 0xfffe;
+
+        let dump = format_dump(&cpu, &parse_ranges("R0..R1").unwrap());
+        assert_eq!(dump, "PC=7 A=2 D=-1\nRAM[0]=256\nRAM[1]=-2\n");
+    }
+
+    #[rstest]
+    #[case(Radix::Dec, "0 256\n1 -2\n")]
+    #[case(Radix::Hex, "0 0100\n1 fffe\n")]
+    #[case(Radix::Bin, "0 0000000100000000\n1 1111111111111110\n")]
+    fn test_format_ram(#[case] radix: Radix, #[case] expected: &str) {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        cpu.ram[0] = 256;
+        cpu.ram[1] = 0xfffe;
+
+        assert_eq!(format_ram(&cpu, &[0..=1], radix), expected);
+    }
+
+    #[test]
+    fn test_format_rom_roundtrip() {
+        let program = [0x0002, 0xec10];
+        let cpu = Cpu::new(&program).unwrap();
+
+        let text = format_rom(&cpu, Radix::Bin);
+        assert_eq!(crate::loader::parse_hack(&text).unwrap(), program);
+    }
+}
@@ -0,0 +1,323 @@
+// デバッガの式（条件付きブレークポイントと eval）。
+// 値は Hack と同じく符号付き16ビットとして読み、計算は i32 で行う
+use anyhow::{Result, anyhow, bail, ensure};
+
+use crate::{Cpu, cpu::RAM_SIZE, dump::parse_address, symbols::Symbols};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    D,
+    Pc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    BitOr,
+    BitAnd,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i32),
+    Register(Register),
+    Ram(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// 優先順位の低い順。同じ段の演算子は左結合
+const LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div)],
+];
+
+const PUNCTUATION: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "&", "+", "-", "*", "/", "!", "(", ")", "[",
+    "]",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i32),
+    Name(String),
+    Punct(&'static str),
+}
+
+// A / D / PC、数値（10進か 0x 付き16進）、RAM[式]、R0..R15 や SP と変数名（その RAM の値）
+pub fn parse_expr(text: &str, symbols: &Symbols) -> Result<Expr> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        symbols,
+    };
+    let expr = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {} in '{}'", describe(token), text);
+    }
+    Ok(expr)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c.is_ascii_digit() {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let digits = &rest[..length];
+            let value = match digits.strip_prefix("0x") {
+                Some(hex) => i32::from_str_radix(hex, 16),
+                None => digits.parse(),
+            };
+            match value {
+                Ok(value) => tokens.push(Token::Number(value)),
+                Err(_) => bail!("Invalid number '{}'", digits),
+            }
+            length
+        } else if c.is_ascii_alphabetic() || "_.$:".contains(c) {
+            // Hack のシンボルに使える文字
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$:".contains(c)))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..length].to_string()));
+            length
+        } else {
+            match PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+                Some(punct) => {
+                    tokens.push(Token::Punct(punct));
+                    punct.len()
+                }
+                None => bail!("Unexpected character '{}'", c),
+            }
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(value) => format!("'{}'", value),
+        Token::Name(name) => format!("'{}'", name),
+        Token::Punct(punct) => format!("'{}'", punct),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        match token {
+            Some(token) => Ok(token),
+            None => bail!("Unexpected end of expression"),
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        match self.next()? {
+            Token::Punct(p) if p == punct => Ok(()),
+            token => bail!("Expected '{}' but found {}", punct, describe(&token)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Punct(punct)) = self.peek() {
+            let Some(&(_, op)) = operators.iter().find(|(p, _)| p == punct) else {
+                break;
+            };
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next()? {
+            Token::Punct("-") => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?))),
+            Token::Punct("!") => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Token::Punct("(") => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) if name == "RAM" => {
+                self.expect("[")?;
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expr::Ram(Box::new(address)))
+            }
+            Token::Name(name) => self.name(&name),
+            token => bail!("Unexpected {}", describe(&token)),
+        }
+    }
+
+    fn name(&self, name: &str) -> Result<Expr> {
+        let address = match name {
+            "A" => return Ok(Expr::Register(Register::A)),
+            "D" => return Ok(Expr::Register(Register::D)),
+            "PC" => return Ok(Expr::Register(Register::Pc)),
+            _ => match self.symbols.resolve_variable(name) {
+                Some(address) => address,
+                None => parse_address(name).map_err(|_| anyhow!("Unknown name '{}'", name))?,
+            },
+        };
+        Ok(Expr::Ram(Box::new(Expr::Number(address as i32))))
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, cpu: &Cpu) -> Result<i32> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Register(Register::A) => cpu.a as i16 as i32,
+            Expr::Register(Register::D) => cpu.d as i16 as i32,
+            Expr::Register(Register::Pc) => cpu.pc as i32,
+            Expr::Ram(address) => {
+                let address = address.eval(cpu)?;
+                ensure!(
+                    (0..RAM_SIZE as i32).contains(&address),
+                    "RAM address out of range: {}",
+                    address
+                );
+                cpu.read(address as u16)? as i16 as i32
+            }
+            Expr::Unary(UnaryOp::Neg, operand) => operand.eval(cpu)?.wrapping_neg(),
+            Expr::Unary(UnaryOp::Not, operand) => (operand.eval(cpu)? == 0) as i32,
+            // && と || は短絡評価する
+            Expr::Binary(BinaryOp::And, left, right) => {
+                (left.eval(cpu)? != 0 && right.eval(cpu)? != 0) as i32
+            }
+            Expr::Binary(BinaryOp::Or, left, right) => {
+                (left.eval(cpu)? != 0 || right.eval(cpu)? != 0) as i32
+            }
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(cpu)?, right.eval(cpu)?);
+                match op {
+                    BinaryOp::Eq => (left == right) as i32,
+                    BinaryOp::Ne => (left != right) as i32,
+                    BinaryOp::Lt => (left < right) as i32,
+                    BinaryOp::Gt => (left > right) as i32,
+                    BinaryOp::Le => (left <= right) as i32,
+                    BinaryOp::Ge => (left >= right) as i32,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    BinaryOp::Div => {
+                        ensure!(right != 0, "Division by zero");
+                        left.wrapping_div(right)
+                    }
+                    BinaryOp::And | BinaryOp::Or => unreachable!("short-circuited above"),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::collections::HashMap;
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        cpu.a = 7;
+        cpu.d = 0xffff;
+        cpu.ram[0] = 258;
+        cpu.ram[16] = 3;
+        cpu.ram[257] = 42;
+        cpu
+    }
+
+    fn symbols() -> Symbols {
+        Symbols::default().with_variables(&HashMap::from([("counter".to_string(), 16)]))
+    }
+
+    #[rstest]
+    #[case("1 + 2 * 3", 7)]
+    #[case("(1 + 2) * 3", 9)]
+    #[case("D", -1)]
+    #[case("-A + 0x10", 9)]
+    #[case("RAM[SP - 1]", 42)]
+    #[case("RAM[0] > 256 && counter == 3", 1)]
+    #[case("R0 < 256 || !counter", 0)]
+    #[case("counter & 1 | 4", 5)]
+    #[case("7 / 2 - 1 >= 2", 1)]
+    fn test_eval(#[case] text: &str, #[case] expected: i32) {
+        let expr = parse_expr(text, &symbols()).unwrap();
+        assert_eq!(expr.eval(&cpu()).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("1 +", "Unexpected end of expression")]
+    #[case("RAM[1", "Unexpected end of expression")]
+    #[case("1 2", "Unexpected '2' in '1 2'")]
+    #[case("total > 0", "Unknown name 'total'")]
+    #[case("1 # 2", "Unexpected character '#'")]
+    fn test_parse_errors(#[case] text: &str, #[case] message: &str) {
+        let err = parse_expr(text, &symbols()).unwrap_err();
+        assert_eq!(err.to_string(), message);
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let cpu = cpu();
+        let eval = |text| parse_expr(text, &symbols()).unwrap().eval(&cpu);
+        assert_eq!(
+            eval("1 / (A - 7)").unwrap_err().to_string(),
+            "Division by zero"
+        );
+        assert!(eval("RAM[-1]").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write;
+
+use crate::{Cpu, symbols::Symbols};
+
+// VM 変換器が積むフレームの語数（戻りアドレス, LCL, ARG, THIS, THAT）
+pub const SAVED_WORDS: u16 = 5;
+// スタックの先頭（ブートストラップが SP=256 にする）
+pub const STACK_BASE: u16 = 256;
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+
+// 現在の関数のフレーム。LCL の直前に呼び出し元の状態が保存されている
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub lcl: u16,
+    pub return_address: u16,
+    pub caller_lcl: u16,
+    pub caller_arg: u16,
+}
+
+impl Frame {
+    // LCL がスタック内を指していなければ関数の外とみなす
+    pub fn current(cpu: &Cpu) -> Option<Frame> {
+        Frame::at(cpu, cpu.ram[LCL])
+    }
+
+    pub fn at(cpu: &Cpu, lcl: u16) -> Option<Frame> {
+        if lcl < STACK_BASE + SAVED_WORDS || lcl > cpu.ram[SP] {
+            return None;
+        }
+        let saved = (lcl - SAVED_WORDS) as usize;
+        Some(Frame {
+            lcl,
+            return_address: cpu.ram[saved],
+            caller_lcl: cpu.ram[saved + 1],
+            caller_arg: cpu.ram[saved + 2],
+        })
+    }
+
+    // 呼び出し元のフレーム
+    pub fn caller(&self, cpu: &Cpu) -> Option<Frame> {
+        // 保存された LCL はこのフレームより下にあるはず。壊れたスタックで回り続けないようにする
+        if self.caller_lcl >= self.lcl {
+            return None;
+        }
+        Frame::at(cpu, self.caller_lcl)
+    }
+
+    // このフレームから戻った直後か
+    pub fn has_returned(&self, cpu: &Cpu) -> bool {
+        cpu.pc == self.return_address && cpu.ram[LCL] == self.caller_lcl
+    }
+}
+
+// コールスタックの1段。pc は実行中の位置か、呼び出しから戻る位置。
+// site は関数と行を引くアドレスで、呼び出し元の段は戻り先の直前（call のジャンプ）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEntry {
+    pub pc: u16,
+    pub site: u16,
+    pub lcl: u16,
+    pub arg: u16,
+}
+
+// 保存されたフレームを LCL からたどり、内側から順に並べる
+pub fn call_stack(cpu: &Cpu) -> Vec<StackEntry> {
+    let mut entries = vec![StackEntry {
+        pc: cpu.pc,
+        site: cpu.pc,
+        lcl: cpu.ram[LCL],
+        arg: cpu.ram[ARG],
+    }];
+    let mut frame = Frame::current(cpu);
+    while let Some(current) = frame {
+        entries.push(StackEntry {
+            pc: current.return_address,
+            site: current.return_address.saturating_sub(1),
+            lcl: current.caller_lcl,
+            arg: current.caller_arg,
+        });
+        frame = current.caller(cpu);
+    }
+    entries
+}
+
+// 例: #0  Main.double (pc=45, LCL=261, ARG=256) [Main:2]
+pub fn format_backtrace(cpu: &Cpu, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    for (depth, entry) in call_stack(cpu).iter().enumerate() {
+        write!(
+            out,
+            "#{:<2} {} (pc={}, LCL={}, ARG={})",
+            depth,
+            symbols.function_at(entry.site).unwrap_or("??"),
+            entry.pc,
+            entry.lcl,
+            entry.arg
+        )
+        .unwrap();
+        if let Some(source) = symbols.source_at(entry.site) {
+            write!(out, " [{}:{}]", source.file, source.line).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_current_frame() {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        assert_eq!(Frame::current(&cpu), None);
+
+        // 256 から戻りアドレス 42、LCL=0、ARG=256、THIS、THAT を積んだ状態
+        cpu.ram[256..261].copy_from_slice(&[42, 0, 256, 0, 0]);
+        cpu.ram[SP] = 263;
+        cpu.ram[LCL] = 261;
+
+        let frame = Frame::current(&cpu).unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                lcl: 261,
+                return_address: 42,
+                caller_lcl: 0,
+                caller_arg: 256,
+            }
+        );
+
+        cpu.pc = 42;
+        assert!(!frame.has_returned(&cpu));
+        cpu.ram[LCL] = 0;
+        assert!(frame.has_returned(&cpu));
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut cpu = Cpu::new(&[]).unwrap();
+        // Sys.init（LCL=261）から Main.run（LCL=268）を呼び、さらに Main.double（LCL=275）を呼んだ状態
+        cpu.ram[256..261].copy_from_slice(&[3, 0, 256, 0, 0]);
+        cpu.ram[263..268].copy_from_slice(&[30, 261, 256, 0, 0]);
+        cpu.ram[270..275].copy_from_slice(&[52, 268, 262, 0, 0]);
+        cpu.ram[SP] = 276;
+        cpu.ram[LCL] = 275;
+        cpu.ram[ARG] = 269;
+        cpu.pc = 61;
+
+        let symbols = Symbols::from_labels(&HashMap::from([
+            ("Sys.init".to_string(), 10),
+            ("Sys.init$ret0".to_string(), 31),
+            ("Main.run".to_string(), 40),
+            ("Main.double".to_string(), 60),
+        ]));
+        assert_eq!(
+            format_backtrace(&cpu, &symbols),
+            "\
+#0  Main.double (pc=61, LCL=275, ARG=269)
+#1  Main.run (pc=52, LCL=268, ARG=262)
+#2  Sys.init (pc=30, LCL=261, ARG=256)
+#3  ?? (pc=3, LCL=0, ARG=256)
+"
+        );
+    }
+}
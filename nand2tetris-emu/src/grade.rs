@@ -0,0 +1,477 @@
+// 学生ごとの提出物にテストスクリプトを実行し、結果を CSV か JSON の表にする
+//
+// submissions の下のディレクトリが 1 人分。tests の下の .cmp と組になった .tst（projects
+// ディレクトリでもよい）を全員に実行する。tests/<dir>/X.tst は、提出物の <dir>（なければ
+// その親、…、提出物のルート）のファイルに、テスト側の .tst と .cmp、それと提出物にない
+// ファイル（テスト用の Main.jack など）を足した一時ディレクトリで実行する。
+// 1 本ずつ `n2t test` の子プロセスで動かし、時間切れなら止める。全体の制限を過ぎたら残りは
+// 実行せずに時間切れにする
+
+use anyhow::{Context, Result, anyhow};
+use nand2tetris_core::timeout::TimedOut;
+use serde::Serialize;
+use std::{
+    env, fmt, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::suite::{self, SuiteTest};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    // 比較が合わなかった
+    Fail,
+    // 読めない・組み立てられない・異常終了した
+    Error,
+    Timeout,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "fail",
+            Outcome::Error => "error",
+            Outcome::Timeout => "timeout",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GradeResult {
+    pub student: String,
+    // tests からの相対パス
+    pub test: String,
+    pub engine: String,
+    pub outcome: Outcome,
+    pub seconds: f64,
+    // 失敗の理由。合格なら空
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Grader {
+    // 1 本のスクリプトを実行するコマンド（n2t test）。スクリプトのパスを最後に足す
+    pub command: Vec<String>,
+    pub timeout: Duration,
+    // 全体の制限（--total-timeout）。grade を呼んだときから数える
+    pub total_timeout: Option<Duration>,
+    pub jobs: usize,
+}
+
+pub struct Report {
+    pub results: Vec<GradeResult>,
+}
+
+// submissions の下のディレクトリを名前順に
+pub fn submissions(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut students: Vec<PathBuf> = fs::read_dir(dir)
+        .context(format!("Failed to read directory '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    students.sort();
+    Ok(students)
+}
+
+// tests が projects ディレクトリならプロジェクト順、そうでなければパスの名前順
+pub fn tests(dir: &Path) -> Result<Vec<SuiteTest>> {
+    match suite::projects_dir(dir) {
+        Some(projects) => suite::discover(&projects),
+        None => suite::discover_in(dir, ""),
+    }
+}
+
+impl Grader {
+    pub fn grade(&self, students: &[PathBuf], tests_dir: &Path, tests: &[SuiteTest]) -> Report {
+        let cases: Vec<(&PathBuf, &SuiteTest)> = students
+            .iter()
+            .flat_map(|student| tests.iter().map(move |test| (student, test)))
+            .collect();
+        let total = self
+            .total_timeout
+            .map(|limit| (Instant::now() + limit, limit));
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<GradeResult>>> =
+            Mutex::new(cases.iter().map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.clamp(1, cases.len().max(1)) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(&(student, test)) = cases.get(index) else {
+                            break;
+                        };
+                        let result = self.grade_one(student, tests_dir, test, index, total);
+                        results.lock().unwrap()[index] = Some(result);
+                    }
+                });
+            }
+        });
+        Report {
+            results: results
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .map(Option::unwrap)
+                .collect(),
+        }
+    }
+
+    fn grade_one(
+        &self,
+        student: &Path,
+        tests_dir: &Path,
+        test: &SuiteTest,
+        index: usize,
+        total: Option<(Instant, Duration)>,
+    ) -> GradeResult {
+        let name = test.script.strip_prefix(tests_dir).unwrap_or(&test.script);
+        let mut result = GradeResult {
+            student: file_name(student),
+            test: name.to_string_lossy().replace('\\', "/"),
+            engine: test.engine.to_string(),
+            outcome: Outcome::Error,
+            seconds: 0.0,
+            message: String::new(),
+        };
+        if let Some((end, limit)) = total
+            && Instant::now() >= end
+        {
+            result.outcome = Outcome::Timeout;
+            result.message = TimedOut { limit, total: true }.to_string();
+            return result;
+        }
+        let work = env::temp_dir().join(format!("n2t-grade-{}-{}", process::id(), index));
+        let script = prepare(&work, student, tests_dir, &test.script).and_then(|()| {
+            let script = work.join(test.script.file_name().unwrap_or_default());
+            let mut command = Command::new(&self.command[0]);
+            command.args(&self.command[1..]).arg(&script);
+            run_worker(&mut command, self.timeout, total)
+        });
+        let message = match script {
+            Ok((outcome, seconds, message)) => {
+                result.outcome = outcome;
+                result.seconds = seconds;
+                message
+            }
+            Err(e) => format!("{:#}", e),
+        };
+        // 一時ディレクトリのパスは見せない
+        let prefix = format!("{}{}", work.display(), std::path::MAIN_SEPARATOR);
+        result.message = message.replace(&prefix, "");
+        let _ = fs::remove_dir_all(&work);
+        result
+    }
+}
+
+// 提出物のファイルの上に、テストの .tst と .cmp、提出物にないファイルを置く
+pub fn prepare(work: &Path, student: &Path, tests_dir: &Path, script: &Path) -> Result<()> {
+    let test_dir = script.parent().unwrap_or(tests_dir);
+    let relative = test_dir.strip_prefix(tests_dir).unwrap_or(Path::new(""));
+    let source = relative
+        .ancestors()
+        .map(|dir| student.join(dir))
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| student.to_path_buf());
+    fs::create_dir_all(work).context(format!("Failed to create '{}'", work.display()))?;
+    copy_files(&source, work, |_| true)?;
+    copy_files(test_dir, work, |path| {
+        path.extension()
+            .is_some_and(|ext| ext == "tst" || ext == "cmp")
+            || !work.join(path.file_name().unwrap_or_default()).exists()
+    })
+}
+
+pub(crate) fn copy_files(from: &Path, to: &Path, filter: impl Fn(&Path) -> bool) -> Result<()> {
+    for entry in fs::read_dir(from)
+        .context(format!("Failed to read directory '{}'", from.display()))?
+        .filter_map(|entry| entry.ok())
+    {
+        let path = entry.path();
+        if path.is_file() && filter(&path) {
+            let target = to.join(entry.file_name());
+            fs::copy(&path, &target).context(format!("Failed to copy '{}'", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+// 終了コードで分ける。0 は合格、6（比較の失敗）は不合格、それ以外はエラー。
+// timeout か全体の (期限, 制限) の早いほうで止める
+pub fn run_worker(
+    command: &mut Command,
+    timeout: Duration,
+    total: Option<(Instant, Duration)>,
+) -> Result<(Outcome, f64, String)> {
+    let start = Instant::now();
+    let per_test = (
+        start + timeout,
+        TimedOut {
+            limit: timeout,
+            total: false,
+        },
+    );
+    let (end, timed_out) = match total {
+        Some((end, limit)) if end < per_test.0 => (end, TimedOut { limit, total: true }),
+        _ => per_test,
+    };
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start the test")?;
+    // パイプが詰まらないように読みながら待つ
+    let mut stderr = child
+        .stderr
+        .take()
+        .context("Failed to read the test output")?;
+    let reader = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= end {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let seconds = start.elapsed().as_secs_f64();
+    let text = reader
+        .join()
+        .map_err(|_| anyhow!("Failed to read the test output"))?;
+    let message = text.trim().trim_start_matches("error: ").to_string();
+    Ok(match status.map(|status| status.code()) {
+        None => (Outcome::Timeout, seconds, timed_out.to_string()),
+        Some(Some(0)) => (Outcome::Pass, seconds, String::new()),
+        Some(Some(6)) => (Outcome::Fail, seconds, message),
+        Some(Some(_)) => (Outcome::Error, seconds, message),
+        Some(None) if message.is_empty() => {
+            (Outcome::Error, seconds, "The test crashed".to_string())
+        }
+        Some(None) => (Outcome::Error, seconds, message),
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+impl Report {
+    pub fn to_csv(&self) -> String {
+        let mut text = String::from("student,test,engine,outcome,seconds,message\n");
+        for result in &self.results {
+            text.push_str(&format!(
+                "{},{},{},{},{:.3},{}\n",
+                csv_field(&result.student),
+                csv_field(&result.test),
+                result.engine,
+                result.outcome,
+                result.seconds,
+                csv_field(&result.message)
+            ));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.results)? + "\n")
+    }
+
+    // 学生ごとの（名前, 合格, 全体）
+    pub fn scores(&self) -> Vec<(String, usize, usize)> {
+        let mut scores: Vec<(String, usize, usize)> = Vec::new();
+        for result in &self.results {
+            if scores
+                .last()
+                .is_none_or(|(name, ..)| *name != result.student)
+            {
+                scores.push((result.student.clone(), 0, 0));
+            }
+            let score = scores.last_mut().unwrap();
+            score.1 += (result.outcome == Outcome::Pass) as usize;
+            score.2 += 1;
+        }
+        scores
+    }
+}
+
+// カンマ・引用符・改行を含むなら引用符で囲む
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, text: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn test_prepare() {
+        let root = env::temp_dir().join(format!("emu-grade-{}", process::id()));
+        let tests_dir = root.join("tests");
+        let student = root.join("alice");
+        write(
+            &tests_dir,
+            "12/MathTest/MathTest.tst",
+            "load, compare-to MathTest.cmp;",
+        );
+        write(&tests_dir, "12/MathTest/MathTest.cmp", "|RAM[8000]|");
+        write(&tests_dir, "12/MathTest/Main.jack", "// driver");
+        write(&tests_dir, "12/MathTest/Math.jack", "// skeleton");
+        write(&student, "12/Math.jack", "// alice");
+        write(&student, "12/MathTest.cmp", "// edited");
+        let work = root.join("work");
+        let prepared = prepare(
+            &work,
+            &student,
+            &tests_dir,
+            &tests_dir.join("12/MathTest/MathTest.tst"),
+        );
+        let read = |name: &str| fs::read_to_string(work.join(name)).unwrap();
+        let files = prepared.map(|()| {
+            (
+                read("Math.jack"),
+                read("Main.jack"),
+                read("MathTest.cmp"),
+                read("MathTest.tst"),
+            )
+        });
+        fs::remove_dir_all(&root).unwrap();
+
+        let (math, main, cmp, tst) = files.unwrap();
+        assert_eq!(math, "// alice");
+        assert_eq!(main, "// driver");
+        assert_eq!(cmp, "|RAM[8000]|");
+        assert_eq!(tst, "load, compare-to MathTest.cmp;");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_worker() {
+        let timeout = Duration::from_secs(5);
+        let run = |script: &str| {
+            let (outcome, _, message) =
+                run_worker(Command::new("sh").args(["-c", script]), timeout, None).unwrap();
+            (outcome, message)
+        };
+        assert_eq!(run("exit 0"), (Outcome::Pass, String::new()));
+        assert_eq!(
+            run("echo 'error: Comparison failure' >&2; exit 6"),
+            (Outcome::Fail, "Comparison failure".to_string())
+        );
+        assert_eq!(
+            run("echo 'error: x.hdl: Line 1: error: bad' >&2; exit 3").0,
+            Outcome::Error
+        );
+        let (outcome, _, message) = run_worker(
+            Command::new("sh").args(["-c", "exec sleep 10"]),
+            Duration::from_millis(100),
+            None,
+        )
+        .unwrap();
+        assert_eq!(outcome, Outcome::Timeout);
+        assert_eq!(message, "Timed out after 100ms (--timeout)");
+        // 全体の期限のほうが早ければそちらで止める
+        let (outcome, _, message) = run_worker(
+            Command::new("sh").args(["-c", "exec sleep 10"]),
+            timeout,
+            Some((
+                Instant::now() + Duration::from_millis(100),
+                Duration::from_secs(2),
+            )),
+        )
+        .unwrap();
+        assert_eq!(outcome, Outcome::Timeout);
+        assert_eq!(
+            message,
+            "Timed out: the total time limit of 2s ran out (--total-timeout)"
+        );
+    }
+
+    // 全体の制限を過ぎたあとのテストは実行しない
+    #[test]
+    fn test_total_timeout() {
+        let grader = Grader {
+            command: vec!["false".to_string()],
+            timeout: Duration::from_secs(5),
+            total_timeout: Some(Duration::ZERO),
+            jobs: 2,
+        };
+        let test = SuiteTest {
+            project: "01".to_string(),
+            script: PathBuf::from("tests/01/Not.tst"),
+            engine: suite::Engine::Hardware,
+        };
+        let students = [PathBuf::from("alice"), PathBuf::from("bob")];
+        let report = grader.grade(&students, Path::new("tests"), &[test]);
+        assert_eq!(report.results.len(), 2);
+        for result in &report.results {
+            assert_eq!(result.outcome, Outcome::Timeout);
+            assert_eq!(
+                result.message,
+                "Timed out: the total time limit of 0ms ran out (--total-timeout)"
+            );
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let result = |student: &str, outcome, message: &str| GradeResult {
+            student: student.to_string(),
+            test: "01/Not.tst".to_string(),
+            engine: "Hardware".to_string(),
+            outcome,
+            seconds: 0.25,
+            message: message.to_string(),
+        };
+        let report = Report {
+            results: vec![
+                result("alice", Outcome::Pass, ""),
+                result("bob", Outcome::Fail, "expected: |, 1|\nactual: \"0\""),
+                result("bob", Outcome::Pass, ""),
+            ],
+        };
+        assert_eq!(
+            report.to_csv(),
+            "student,test,engine,outcome,seconds,message\n\
+             alice,01/Not.tst,Hardware,pass,0.250,\n\
+             bob,01/Not.tst,Hardware,fail,0.250,\"expected: |, 1|\nactual: \"\"0\"\"\"\n\
+             bob,01/Not.tst,Hardware,pass,0.250,\n"
+        );
+        assert_eq!(
+            report.scores(),
+            [("alice".to_string(), 1, 1), ("bob".to_string(), 1, 2)]
+        );
+        assert!(report.to_json().unwrap().contains("\"outcome\": \"fail\""));
+    }
+}
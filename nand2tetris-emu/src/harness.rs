@@ -0,0 +1,288 @@
+use anyhow::{Context, Result, bail};
+use nand2tetris_core::timeout::Budget;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Cpu,
+    assertion::{Assertion, check_assertions},
+    cpu::{RunOptions, StopReason},
+    loader::{LoadedProgram, load_jack},
+    native::{NativeOs, native_functions},
+    screen::{Headless, WORDS_PER_ROW},
+    screenshot::load_png,
+    tst::{TestRunner, run_script_with},
+};
+
+const ARG: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct HarnessOptions {
+    // Some なら OS の関数を Rust で実行する（空ならすべてのクラス）
+    pub native_os: Option<Vec<String>>,
+    // プログラムを実行するテストの最大サイクル数
+    pub max_cycles: u64,
+    // 実行後の RAM とレジスタ
+    pub assertions: Vec<Assertion>,
+    // 実行後の画面と比べる PNG
+    pub expect_screen: Option<PathBuf>,
+    // テストごとと全体の時間の制限
+    pub budget: Budget,
+}
+
+impl Default for HarnessOptions {
+    fn default() -> Self {
+        HarnessOptions {
+            native_os: None,
+            max_cycles: 50_000_000,
+            assertions: Vec::new(),
+            expect_screen: None,
+            budget: Budget::default(),
+        }
+    }
+}
+
+// 1つのテストの結果。error がなければ合格
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    pub error: Option<String>,
+    pub duration: Duration,
+}
+
+// Jack のディレクトリをコンパイルして OS とつなぎ、中の .tst と、実行後の RAM と画面の
+// 指定で確かめる
+pub struct Harness {
+    dir: PathBuf,
+    program: LoadedProgram,
+    options: HarnessOptions,
+}
+
+impl Harness {
+    pub fn build(dir: &Path, options: HarnessOptions) -> Result<Self> {
+        let native = match &options.native_os {
+            Some(classes) => native_functions(classes)?,
+            None => Vec::new(),
+        };
+        Ok(Harness {
+            dir: dir.to_path_buf(),
+            program: load_jack(dir, &native)?,
+            options,
+        })
+    }
+
+    // n2t.toml のようにビルド済みのプログラムを使う
+    pub fn new(dir: &Path, program: LoadedProgram, options: HarnessOptions) -> Self {
+        Harness {
+            dir: dir.to_path_buf(),
+            program,
+            options,
+        }
+    }
+
+    pub fn program(&self) -> &LoadedProgram {
+        &self.program
+    }
+
+    // .tst を名前順に実行し、RAM か画面の指定があるか .tst がなければプログラムも実行する
+    pub fn run(&self) -> Result<Vec<TestResult>> {
+        let mut scripts: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .context(format!("Failed to read directory '{}'", self.dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tst"))
+            .collect();
+        scripts.sort();
+        Ok(self.run_scripts(&scripts))
+    }
+
+    // run と同じだが、.tst はディレクトリから探さずに与える
+    pub fn run_scripts(&self, scripts: &[PathBuf]) -> Vec<TestResult> {
+        let mut results = Vec::new();
+        for script in scripts {
+            let start = Instant::now();
+            let runner = TestRunner::with_program(
+                script.parent().unwrap_or(&self.dir),
+                self.program.clone(),
+                self.options.native_os.clone(),
+            );
+            let error = self
+                .options
+                .budget
+                .run(|| run_script_with(script, runner))
+                .err()
+                .map(|e| e.to_string());
+            results.push(TestResult {
+                name: script
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                error,
+                duration: start.elapsed(),
+            });
+        }
+        if scripts.is_empty()
+            || !self.options.assertions.is_empty()
+            || self.options.expect_screen.is_some()
+        {
+            let start = Instant::now();
+            let error = self
+                .options
+                .budget
+                .run(|| self.run_program())
+                .err()
+                .map(|e| e.to_string());
+            results.push(TestResult {
+                name: "run".to_string(),
+                error,
+                duration: start.elapsed(),
+            });
+        }
+        results
+    }
+
+    // Sys.halt か最大サイクル数まで実行する。Sys.error で止まったら失敗
+    fn run_program(&self) -> Result<()> {
+        let symbols = &self.program.symbols;
+        let mut cpu = Cpu::new(&self.program.words)?;
+        if let Some(classes) = &self.options.native_os {
+            cpu.native = Some(Box::new(NativeOs::new(symbols, classes)?));
+        }
+        cpu.sys_halt = symbols.resolve("Sys.halt");
+        let sys_error = symbols.resolve("Sys.error");
+        let options = RunOptions {
+            max_cycles: Some(self.options.max_cycles),
+            breakpoints: sys_error.into_iter().collect(),
+            ..RunOptions::default()
+        };
+        let reason = cpu.run_with_backend(&mut Headless, &options, &mut [])?;
+        if reason == StopReason::Breakpoint && Some(cpu.pc) == sys_error {
+            let code = cpu.ram[cpu.ram[ARG] as usize] as i16;
+            bail!("Sys.error({}) after {} cycles", code, cpu.cycles);
+        }
+
+        check_assertions(&cpu, &self.options.assertions)?;
+        if let Some(path) = &self.options.expect_screen {
+            let expected = load_png(path)?;
+            let differences: Vec<usize> = (0..expected.len() * 16)
+                .filter(|&bit| {
+                    (expected[bit / 16] ^ cpu.screen()[bit / 16]) & (1 << (bit % 16)) != 0
+                })
+                .collect();
+            if let Some(&first) = differences.first() {
+                let word = first / 16;
+                bail!(
+                    "The screen differs from '{}' in {} pixels (first at x={}, y={})",
+                    path.display(),
+                    differences.len(),
+                    word % WORDS_PER_ROW * 16 + first % 16,
+                    word / WORDS_PER_ROW
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assertion::parse_assertions;
+    use nand2tetris_core::timeout::TimeoutArgs;
+
+    const MAIN: &str = "class Main {
+    function void main() {
+        do Memory.poke(8000, 6 * 7);
+        do Memory.poke(8001, 10 / Memory.peek(8002));
+        return;
+    }
+}";
+
+    fn project(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("emu-harness-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Main.jack"), MAIN).unwrap();
+        dir
+    }
+
+    fn native(assertions: &str) -> HarnessOptions {
+        HarnessOptions {
+            native_os: Some(Vec::new()),
+            assertions: parse_assertions(assertions).unwrap(),
+            ..HarnessOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_run_scripts() {
+        let dir = project("tst");
+        fs::write(
+            dir.join("MainTest.tst"),
+            "load, compare-to MainTest.cmp, output-list RAM[8000]%D2.6.1;\n\
+             repeat 100000 { vmstep; } output;",
+        )
+        .unwrap();
+        fs::write(dir.join("MainTest.cmp"), "|RAM[8000]|\n|      42 |\n").unwrap();
+
+        let harness = Harness::build(&dir, native("")).unwrap();
+        let results = harness.run().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // .tst があって RAM の指定がなければ、プログラムだけの実行はしない（Sys.error で止まる）
+        let results: Vec<(&str, Option<&str>)> = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.error.as_deref()))
+            .collect();
+        assert_eq!(results, [("MainTest.tst", None)]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let dir = project("timeout");
+        fs::write(
+            dir.join("Main.jack"),
+            "class Main { function void main() { while (true) { do Memory.poke(8000, 1); } return; } }",
+        )
+        .unwrap();
+        fs::write(dir.join("Forever.tst"), "load; repeat { vmstep; }").unwrap();
+        let options = HarnessOptions {
+            max_cycles: u64::MAX,
+            budget: TimeoutArgs {
+                timeout: Some(Duration::from_millis(100)),
+                total_timeout: None,
+            }
+            .budget(),
+            assertions: parse_assertions("RAM[8000]=42").unwrap(),
+            ..HarnessOptions::default()
+        };
+        let harness = Harness::build(&dir, options).unwrap();
+        let results = harness.run().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // 終わらないスクリプトもプログラムも時間切れで失敗する
+        let results: Vec<(&str, Option<&str>)> = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.error.as_deref()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("Forever.tst", Some("Timed out after 100ms (--timeout)")),
+                ("run", Some("Timed out after 100ms (--timeout)"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_program() {
+        let dir = project("run");
+        let harness = Harness::build(&dir, native("RAM[8000]=42")).unwrap();
+        let results = harness.run().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(results.len(), 1);
+        let error = results[0].error.as_deref().unwrap();
+        assert!(error.starts_with("Sys.error(3) after"), "{}", error);
+    }
+}
@@ -0,0 +1,375 @@
+use anyhow::{Result, bail};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    frame::Frame,
+    screen::SCREEN,
+    symbols::Symbols,
+};
+
+// Jack OS のヒープ
+pub const HEAP_START: u16 = 2048;
+pub const HEAP_END: u16 = SCREEN as u16;
+
+const ARG: usize = 2;
+
+// Memory.alloc が返したブロック。site は呼び出した命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub base: u16,
+    pub size: u16,
+    pub site: u16,
+}
+
+impl Block {
+    fn contains(&self, address: u16) -> bool {
+        (self.base..self.base.saturating_add(self.size)).contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeapError {
+    DoubleFree {
+        site: u16,
+        block: Block,
+    },
+    InvalidFree {
+        site: u16,
+        address: u16,
+    },
+    // block は直前のブロック（なければ None）
+    OutOfBounds {
+        pc: u16,
+        address: u16,
+        write: bool,
+        block: Option<Block>,
+    },
+    UseAfterFree {
+        pc: u16,
+        address: u16,
+        write: bool,
+        block: Block,
+    },
+}
+
+// Memory.alloc / Memory.deAlloc の呼び出しを追い、解放漏れ・二重解放・ブロック外へのアクセスを調べる
+pub struct HeapChecker {
+    symbols: Symbols,
+    alloc: u16,
+    dealloc: Option<u16>,
+    live: BTreeMap<u16, Block>,
+    freed: BTreeMap<u16, Block>,
+    // 戻りを待っている Memory.alloc の呼び出し（フレーム, 大きさ, 呼び出し元）
+    pending: Vec<(Frame, u16, u16)>,
+    errors: Vec<HeapError>,
+    // 同じ命令のブロック外アクセスは1回だけ記録する
+    reported: HashSet<u16>,
+    allocations: usize,
+    frees: usize,
+}
+
+impl HeapChecker {
+    pub fn new(symbols: Symbols) -> Result<Self> {
+        let Some(alloc) = symbols.resolve("Memory.alloc") else {
+            bail!(
+                "Heap checking needs the Memory.alloc symbol: run the .vm sources or give a .sym file"
+            );
+        };
+        Ok(HeapChecker {
+            dealloc: symbols.resolve("Memory.deAlloc"),
+            symbols,
+            alloc,
+            live: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            pending: Vec::new(),
+            errors: Vec::new(),
+            reported: HashSet::new(),
+            allocations: 0,
+            frees: 0,
+        })
+    }
+
+    pub fn errors(&self) -> &[HeapError] {
+        &self.errors
+    }
+
+    // まだ解放されていないブロック
+    pub fn leaks(&self) -> impl Iterator<Item = &Block> {
+        self.live.values()
+    }
+
+    fn block_at(blocks: &BTreeMap<u16, Block>, address: u16) -> Option<&Block> {
+        blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.contains(address))
+    }
+
+    fn allocated(&mut self, block: Block) {
+        self.allocations += 1;
+        // 再利用された領域の解放済みブロックは忘れる
+        let end = block.base.saturating_add(block.size);
+        self.freed.retain(|_, freed| {
+            freed.base.saturating_add(freed.size) <= block.base || freed.base >= end
+        });
+        self.live.insert(block.base, block);
+    }
+
+    fn deallocated(&mut self, address: u16, site: u16) {
+        self.frees += 1;
+        if let Some(block) = self.live.remove(&address) {
+            self.freed.insert(address, block);
+        } else if let Some(&block) = self.freed.get(&address) {
+            self.errors.push(HeapError::DoubleFree { site, block });
+        } else {
+            self.errors.push(HeapError::InvalidFree { site, address });
+        }
+    }
+
+    fn access(&mut self, pc: u16, address: u16, write: bool) {
+        if !(HEAP_START..HEAP_END).contains(&address)
+            || Self::block_at(&self.live, address).is_some()
+            || self.reported.contains(&pc)
+            // アロケータ自身はブロックの外を管理している
+            || self
+                .symbols
+                .function_at(pc)
+                .is_some_and(|name| name.starts_with("Memory."))
+        {
+            return;
+        }
+        self.reported.insert(pc);
+        let error = match Self::block_at(&self.freed, address) {
+            Some(&block) => HeapError::UseAfterFree {
+                pc,
+                address,
+                write,
+                block,
+            },
+            None => HeapError::OutOfBounds {
+                pc,
+                address,
+                write,
+                block: self.live.range(..address).next_back().map(|(_, b)| *b),
+            },
+        };
+        self.errors.push(error);
+    }
+
+    // PC=123 (Main.main+4) [Main:12]
+    fn describe(&self, pc: u16) -> String {
+        let mut text = format!("PC={}", pc);
+        if let Some(location) = self.symbols.locate(pc) {
+            write!(text, " ({})", location).unwrap();
+        }
+        if let Some(source) = self.symbols.source_at(pc) {
+            write!(text, " [{}:{}]", source.file, source.line).unwrap();
+        }
+        text
+    }
+
+    fn describe_block(&self, block: &Block) -> String {
+        format!(
+            "the block at {} ({} words) allocated at {}",
+            block.base,
+            block.size,
+            self.describe(block.site)
+        )
+    }
+
+    pub fn report(&self) -> String {
+        let leaks: Vec<&Block> = self.leaks().collect();
+        let mut out = format!(
+            "Heap: {} allocations, {} frees, {} leaked blocks ({} words), {} errors\n",
+            self.allocations,
+            self.frees,
+            leaks.len(),
+            leaks.iter().map(|b| b.size as usize).sum::<usize>(),
+            self.errors.len()
+        );
+        for error in &self.errors {
+            let line = match error {
+                HeapError::DoubleFree { site, block } => format!(
+                    "Double free at {} of {}",
+                    self.describe(*site),
+                    self.describe_block(block)
+                ),
+                HeapError::InvalidFree { site, address } => format!(
+                    "Invalid free at {}: {} is not an allocated block",
+                    self.describe(*site),
+                    address
+                ),
+                HeapError::OutOfBounds {
+                    pc,
+                    address,
+                    write,
+                    block,
+                } => {
+                    let mut line = format!(
+                        "Out-of-bounds {} of RAM[{}] at {}",
+                        if *write { "write" } else { "read" },
+                        address,
+                        self.describe(*pc)
+                    );
+                    if let Some(block) = block {
+                        write!(
+                            line,
+                            ": {} words past the end of {}",
+                            address - (block.base + block.size),
+                            self.describe_block(block)
+                        )
+                        .unwrap();
+                    }
+                    line
+                }
+                HeapError::UseAfterFree {
+                    pc,
+                    address,
+                    write,
+                    block,
+                } => format!(
+                    "Use after free: {} of RAM[{}] at {} in {}",
+                    if *write { "write" } else { "read" },
+                    address,
+                    self.describe(*pc),
+                    self.describe_block(block)
+                ),
+            };
+            writeln!(out, "{}", line).unwrap();
+        }
+        for block in leaks {
+            writeln!(out, "Leaked {}", self.describe_block(block)).unwrap();
+        }
+        out
+    }
+}
+
+impl StepHook for HeapChecker {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()> {
+        if let Some(address) = step.read {
+            self.access(step.pc, address, false);
+        }
+        if let Some((address, _)) = step.write {
+            self.access(step.pc, address, true);
+        }
+
+        // Memory.alloc から戻ったらスタックの先頭が返り値
+        while let Some(&(frame, size, site)) = self.pending.last() {
+            if !frame.has_returned(cpu) {
+                break;
+            }
+            self.pending.pop();
+            let base = cpu.ram[cpu.ram[0].wrapping_sub(1) as usize];
+            self.allocated(Block { base, size, site });
+        }
+
+        // 関数の入口へのジャンプで、積まれた戻りアドレスがジャンプの次なら call
+        let next = step.pc.wrapping_add(1);
+        if cpu.pc == next || (cpu.pc != self.alloc && Some(cpu.pc) != self.dealloc) {
+            return Ok(());
+        }
+        let Some(frame) = Frame::current(cpu).filter(|f| f.return_address == next) else {
+            return Ok(());
+        };
+        let argument = cpu.ram[cpu.ram[ARG] as usize];
+        if cpu.pc == self.alloc {
+            self.pending.push((frame, argument, step.pc));
+        } else {
+            self.deallocated(argument, step.pc);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, loader, screen::Headless};
+    use std::fs;
+
+    #[test]
+    fn test_heap_checker() {
+        let dir = std::env::temp_dir().join(format!("emu-heap-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // 2048 から順に切り出すだけのアロケータ
+        fs::write(
+            dir.join("Memory.vm"),
+            "function Memory.alloc 0\npush constant 2048\npush static 0\nadd\n\
+             push static 0\npush argument 0\nadd\npop static 0\nreturn\n\
+             function Memory.deAlloc 0\npush constant 0\nreturn",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\n\
+             push constant 3\ncall Memory.alloc 1\npop pointer 1\n\
+             push constant 7\npop that 3\n\
+             push pointer 1\ncall Memory.deAlloc 1\npop temp 0\n\
+             push that 0\npop temp 0\n\
+             push pointer 1\ncall Memory.deAlloc 1\npop temp 0\n\
+             push constant 2\ncall Memory.alloc 1\npop temp 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        let program = loader::load_program(&dir, None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut cpu = Cpu::new(&program.words).unwrap();
+        let mut checker = HeapChecker::new(program.symbols.clone()).unwrap();
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut checker])
+            .unwrap();
+
+        let kinds: Vec<&str> = checker
+            .errors()
+            .iter()
+            .map(|error| match error {
+                HeapError::OutOfBounds {
+                    address: 2051,
+                    write: true,
+                    block: Some(b),
+                    ..
+                } if b.base == 2048 => "out of bounds",
+                HeapError::UseAfterFree {
+                    address: 2048,
+                    write: false,
+                    ..
+                } => "use after free",
+                HeapError::DoubleFree { block, .. } if block.base == 2048 => "double free",
+                _ => "unexpected",
+            })
+            .collect();
+        assert_eq!(kinds, ["out of bounds", "use after free", "double free"]);
+
+        let leaks: Vec<(u16, u16)> = checker.leaks().map(|b| (b.base, b.size)).collect();
+        assert_eq!(leaks, [(2051, 2)]);
+
+        let report = checker.report();
+        assert!(
+            report
+                .starts_with("Heap: 2 allocations, 2 frees, 1 leaked blocks (2 words), 3 errors\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("Out-of-bounds write of RAM[2051] at PC="),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains(": 0 words past the end of the block at 2048 (3 words)"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_heap_checker_needs_memory_alloc() {
+        assert!(HeapChecker::new(Symbols::default()).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    screen::{KBD, SCREEN},
+    symbols::Symbols,
+};
+
+// 画像は1行 256 ワードで、1ワードを SCALE × SCALE の点にする。
+// 1行目が R0..R15 と static、2〜8行目がスタック、9〜64行目がヒープ、65〜96行目が画面になる
+const WORDS_PER_ROW: usize = 256;
+const SCALE: usize = 4;
+
+// VM の慣習でのメモリの区分
+pub fn region(address: usize) -> &'static str {
+    match address {
+        0..=15 => "registers",
+        16..=255 => "static",
+        256..=2047 => "stack",
+        2048..SCREEN => "heap",
+        SCREEN..KBD => "screen",
+        KBD => "keyboard",
+        _ => "unused",
+    }
+}
+
+const REGIONS: [&str; 6] = ["registers", "static", "stack", "heap", "screen", "keyboard"];
+
+// 区分ごとの読み書きの合計と、読み書きしたアドレスの数と一番大きいアドレス
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RegionStats {
+    pub region: &'static str,
+    pub reads: u64,
+    pub writes: u64,
+    pub addresses: usize,
+    pub highest: Option<u16>,
+}
+
+fn register_name(address: usize) -> Option<String> {
+    Some(match address {
+        0 => "SP".to_string(),
+        1 => "LCL".to_string(),
+        2 => "ARG".to_string(),
+        3 => "THIS".to_string(),
+        4 => "THAT".to_string(),
+        5..=15 => format!("R{}", address),
+        KBD => "KBD".to_string(),
+        _ => return None,
+    })
+}
+
+// RAM のアドレスごとに読み書きの回数を数える
+pub struct MemoryHeatmap {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl Default for MemoryHeatmap {
+    fn default() -> Self {
+        MemoryHeatmap {
+            reads: vec![0; KBD + 1],
+            writes: vec![0; KBD + 1],
+        }
+    }
+}
+
+impl MemoryHeatmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reads(&self, address: u16) -> u64 {
+        self.reads
+            .get(address as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn writes(&self, address: u16) -> u64 {
+        self.writes
+            .get(address as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn regions(&self) -> Vec<RegionStats> {
+        let mut stats: Vec<RegionStats> = REGIONS
+            .iter()
+            .map(|&region| RegionStats {
+                region,
+                reads: 0,
+                writes: 0,
+                addresses: 0,
+                highest: None,
+            })
+            .collect();
+        for (address, (&reads, &writes)) in self.reads.iter().zip(&self.writes).enumerate() {
+            if reads == 0 && writes == 0 {
+                continue;
+            }
+            let Some(region) = stats.iter_mut().find(|s| s.region == region(address)) else {
+                continue;
+            };
+            region.reads += reads;
+            region.writes += writes;
+            region.addresses += 1;
+            region.highest = Some(address as u16);
+        }
+        stats
+    }
+
+    // 一度でも読み書きしたアドレスだけを書く
+    pub fn write_csv<W: Write>(&self, mut out: W, symbols: &Symbols) -> Result<()> {
+        writeln!(out, "address,region,name,reads,writes")?;
+        for (address, (&reads, &writes)) in self.reads.iter().zip(&self.writes).enumerate() {
+            if reads == 0 && writes == 0 {
+                continue;
+            }
+            let name = register_name(address)
+                .or_else(|| symbols.variable_at(address as u16).map(String::from))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                address,
+                region(address),
+                name,
+                reads,
+                writes
+            )?;
+        }
+        Ok(())
+    }
+
+    // 書き込みを赤、読み込みを緑の明るさにする（回数の対数）
+    pub fn write_png<W: Write>(&self, out: W) -> Result<()> {
+        let rows = KBD / WORDS_PER_ROW;
+        let (width, height) = (WORDS_PER_ROW * SCALE, rows * SCALE);
+        let scale = |counts: &[u64]| {
+            let max = (counts.iter().copied().max().unwrap_or_default() as f64).ln_1p();
+            move |count: u64| match count {
+                0 => 0,
+                count => {
+                    let ratio = (count as f64).ln_1p() / max.max(f64::MIN_POSITIVE);
+                    (64.0 + 191.0 * ratio) as u8
+                }
+            }
+        };
+        let (red, green) = (scale(&self.writes), scale(&self.reads));
+
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let address = (y / SCALE) * WORDS_PER_ROW + x / SCALE;
+                pixels.extend([red(self.writes[address]), green(self.reads[address]), 0]);
+            }
+        }
+
+        let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    // 拡張子で CSV か PNG を選ぶ
+    pub fn save(&self, path: &Path, symbols: &Symbols) -> Result<()> {
+        let file = File::create(path).context(format!("Failed to create '{}'", path.display()))?;
+        let mut out = BufWriter::new(file);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => self.write_csv(&mut out, symbols)?,
+            Some("png") => self.write_png(&mut out)?,
+            _ => bail!(
+                "Unsupported heatmap format '{}': use .csv or .png",
+                path.display()
+            ),
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl StepHook for MemoryHeatmap {
+    fn on_step(&mut self, _: &Cpu, step: &StepInfo) -> Result<()> {
+        if let Some(count) = step.read.and_then(|a| self.reads.get_mut(a as usize)) {
+            *count += 1;
+        }
+        if let Some(count) = step
+            .write
+            .and_then(|(a, _)| self.writes.get_mut(a as usize))
+        {
+            *count += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, screen::Headless};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_heatmap() {
+        // RAM[16] に 1 を2回足してから画面の先頭に書く
+        let program = [
+            16,     // @16
+            0xfdc8, // M=M+1
+            16,     // @16
+            0xfdc8, // M=M+1
+            16,     // @16
+            0xfc10, // D=M
+            SCREEN as u16,
+            0xe308, // M=D
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        let mut heatmap = MemoryHeatmap::new();
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut heatmap])
+            .unwrap();
+
+        assert_eq!(heatmap.reads(16), 3);
+        assert_eq!(heatmap.writes(16), 2);
+        assert_eq!(heatmap.writes(SCREEN as u16), 1);
+
+        let symbols =
+            Symbols::default().with_variables(&HashMap::from([("counter".to_string(), 16)]));
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv, &symbols).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "address,region,name,reads,writes\n16,static,counter,3,2\n16384,screen,,0,1\n"
+        );
+
+        let regions = heatmap.regions();
+        assert_eq!(
+            regions[1],
+            RegionStats {
+                region: "static",
+                reads: 3,
+                writes: 2,
+                addresses: 1,
+                highest: Some(16),
+            }
+        );
+        assert_eq!(regions[4].writes, 1);
+
+        let mut image = Vec::new();
+        heatmap.write_png(&mut image).unwrap();
+        let info = png::Decoder::new(std::io::Cursor::new(image))
+            .read_info()
+            .unwrap()
+            .info()
+            .clone();
+        assert_eq!((info.width, info.height), (1024, 384));
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{Context, Result, bail};
+use std::{fmt::Write, fs, path::Path};
+
+const HEADER: &str = "# hack input log v1";
+
+// KBD の値が変化したサイクルと新しい値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub key: u16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputLog {
+    pub events: Vec<KeyEvent>,
+}
+
+impl InputLog {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events: Vec<KeyEvent> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parse = || -> Option<KeyEvent> {
+                let (cycle, key) = line.split_once(char::is_whitespace)?;
+                Some(KeyEvent {
+                    cycle: cycle.parse().ok()?,
+                    key: key.trim().parse().ok()?,
+                })
+            };
+            let Some(event) = parse() else {
+                bail!("Line {}: invalid input event '{}'", i + 1, line);
+            };
+            if events.last().is_some_and(|last| last.cycle > event.cycle) {
+                bail!("Line {}: events must be in cycle order", i + 1);
+            }
+            events.push(event);
+        }
+
+        Ok(InputLog { events })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("{}\n", HEADER);
+        for event in &self.events {
+            let _ = writeln!(out, "{} {}", event.cycle, event.key);
+        }
+        out
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read input log '{}'", path.display()))?;
+        Self::parse(&text).context(format!("Invalid input log '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_text()).context(format!("Failed to write '{}'", path.display()))
+    }
+}
+
+// キーボード入力の取得方法
+#[derive(Debug, Clone, Default)]
+pub enum InputMode {
+    // バックエンドから読む
+    #[default]
+    Live,
+    // バックエンドから読み、変化を記録する
+    Record(InputLog),
+    // 記録したイベントを同じサイクルで再生する
+    Replay {
+        log: InputLog,
+        next: usize,
+    },
+}
+
+impl InputMode {
+    pub fn replay(log: InputLog) -> Self {
+        InputMode::Replay { log, next: 0 }
+    }
+
+    // 同期時点でのキー入力を反映し、KBD に書く値を返す
+    pub fn on_poll(&mut self, cycle: u64, polled: u16, current: u16) -> u16 {
+        match self {
+            InputMode::Live => polled,
+            InputMode::Record(log) => {
+                let last = log.events.last().map_or(0, |e| e.key);
+                if polled != last {
+                    log.events.push(KeyEvent { cycle, key: polled });
+                }
+                polled
+            }
+            InputMode::Replay { .. } => current,
+        }
+    }
+
+    // 再生中にこのサイクルで発生するイベントがあれば返す
+    pub fn due(&mut self, cycle: u64) -> Option<u16> {
+        let InputMode::Replay { log, next } = self else {
+            return None;
+        };
+
+        let mut key = None;
+        while let Some(event) = log.events.get(*next) {
+            if event.cycle > cycle {
+                break;
+            }
+            key = Some(event.key);
+            *next += 1;
+        }
+        key
+    }
+
+    pub fn recorded(&self) -> Option<&InputLog> {
+        match self {
+            InputMode::Record(log) => Some(log),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_roundtrip() {
+        let log = InputLog {
+            events: vec![
+                KeyEvent { cycle: 10, key: 65 },
+                KeyEvent { cycle: 20, key: 0 },
+            ],
+        };
+        let text = log.to_text();
+        assert_eq!(text, "# hack input log v1\n10 65\n20 0\n");
+        assert_eq!(InputLog::parse(&text).unwrap(), log);
+    }
+
+    #[test]
+    fn test_log_invalid() {
+        assert!(InputLog::parse("10\n").is_err());
+        assert!(InputLog::parse("20 1\n10 0\n").is_err());
+    }
+
+    #[test]
+    fn test_record_only_changes() {
+        let mut mode = InputMode::Record(InputLog::default());
+        for (cycle, key) in [(0, 0), (10, 65), (20, 65), (30, 0)] {
+            assert_eq!(mode.on_poll(cycle, key, 0), key);
+        }
+        let events = &mode.recorded().unwrap().events;
+        assert_eq!(
+            events,
+            &vec![
+                KeyEvent { cycle: 10, key: 65 },
+                KeyEvent { cycle: 30, key: 0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_due() {
+        let mut mode = InputMode::replay(InputLog::parse("5 65\n8 0\n").unwrap());
+        assert_eq!(mode.due(4), None);
+        assert_eq!(mode.due(5), Some(65));
+        assert_eq!(mode.due(6), None);
+        assert_eq!(mode.due(8), Some(0));
+        // 再生中はバックエンドのキーを無視する
+        assert_eq!(mode.on_poll(9, 66, 0), 0);
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path};
+
+// バックエンドに依存しないキーの表現
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    Char(char),
+    Enter,
+    Backspace,
+    Left,
+    Up,
+    Right,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Esc,
+    F(u8),
+}
+
+impl HostKey {
+    pub fn from_name(name: &str) -> Result<Self> {
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(HostKey::Char(c));
+        }
+
+        let key = match name.to_ascii_lowercase().as_str() {
+            "space" => HostKey::Char(' '),
+            "enter" | "newline" | "return" => HostKey::Enter,
+            "backspace" => HostKey::Backspace,
+            "left" => HostKey::Left,
+            "up" => HostKey::Up,
+            "right" => HostKey::Right,
+            "down" => HostKey::Down,
+            "home" => HostKey::Home,
+            "end" => HostKey::End,
+            "pageup" => HostKey::PageUp,
+            "pagedown" => HostKey::PageDown,
+            "insert" => HostKey::Insert,
+            "delete" => HostKey::Delete,
+            "esc" | "escape" => HostKey::Esc,
+            other => match other.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n @ 1..=12) => HostKey::F(n),
+                _ => bail!("Unknown key name: '{}'", name),
+            },
+        };
+        Ok(key)
+    }
+
+    // Hack 仕様のキーコード
+    pub fn hack_code(self) -> u16 {
+        match self {
+            HostKey::Char(c) if (' '..='~').contains(&c) => c as u16,
+            HostKey::Char(_) => 0,
+            HostKey::Enter => 128,
+            HostKey::Backspace => 129,
+            HostKey::Left => 130,
+            HostKey::Up => 131,
+            HostKey::Right => 132,
+            HostKey::Down => 133,
+            HostKey::Home => 134,
+            HostKey::End => 135,
+            HostKey::PageUp => 136,
+            HostKey::PageDown => 137,
+            HostKey::Insert => 138,
+            HostKey::Delete => 139,
+            HostKey::Esc => 140,
+            HostKey::F(n) => 140 + n as u16,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyMapFile {
+    #[serde(default)]
+    keys: HashMap<String, u16>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KeyMap {
+    overrides: HashMap<HostKey, u16>,
+}
+
+impl KeyMap {
+    // [keys] テーブルで既定のコードを上書きする
+    //
+    // [keys]
+    // Esc = 27
+    // w = 131
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read keymap '{}'", path.display()))?;
+        Self::parse(&text).context(format!("Invalid keymap '{}'", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: KeyMapFile = toml::from_str(text)?;
+        let mut overrides = HashMap::new();
+
+        for (name, code) in file.keys {
+            overrides.insert(HostKey::from_name(&name)?, code);
+        }
+
+        Ok(KeyMap { overrides })
+    }
+
+    pub fn code(&self, key: HostKey) -> u16 {
+        self.overrides
+            .get(&key)
+            .copied()
+            .unwrap_or_else(|| key.hack_code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(HostKey::Char('a'), 97)]
+    #[case(HostKey::Char('Z'), 90)]
+    #[case(HostKey::Char(' '), 32)]
+    #[case(HostKey::Char('é'), 0)]
+    #[case(HostKey::Enter, 128)]
+    #[case(HostKey::Backspace, 129)]
+    #[case(HostKey::Left, 130)]
+    #[case(HostKey::Up, 131)]
+    #[case(HostKey::Right, 132)]
+    #[case(HostKey::Down, 133)]
+    #[case(HostKey::Delete, 139)]
+    #[case(HostKey::Esc, 140)]
+    #[case(HostKey::F(1), 141)]
+    #[case(HostKey::F(12), 152)]
+    fn test_hack_code(#[case] key: HostKey, #[case] expected: u16) {
+        assert_eq!(KeyMap::default().code(key), expected);
+    }
+
+    #[test]
+    fn test_keymap_overrides() {
+        let keymap = KeyMap::parse("[keys]\nEsc = 27\nw = 131\nF1 = 200\n").unwrap();
+        assert_eq!(keymap.code(HostKey::Esc), 27);
+        assert_eq!(keymap.code(HostKey::Char('w')), 131);
+        assert_eq!(keymap.code(HostKey::F(1)), 200);
+        assert_eq!(keymap.code(HostKey::Enter), 128);
+    }
+
+    #[rstest]
+    #[case("[keys]\nF13 = 1\n")]
+    #[case("[keys]\nbogus = 1\n")]
+    #[case("[keys]\nEsc = \"x\"\n")]
+    fn test_keymap_invalid(#[case] text: &str) {
+        assert!(KeyMap::parse(text).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+pub mod assertion;
+pub mod cache;
+pub mod compare;
+pub mod conformance;
+pub mod coverage;
+pub mod cpu;
+pub mod dap;
+pub mod debugger;
+pub mod differential;
+pub mod disasm;
+pub mod dump;
+pub mod expr;
+pub mod frame;
+pub mod grade;
+pub mod harness;
+pub mod heap;
+pub mod heatmap;
+pub mod input;
+pub mod keyboard;
+pub mod loader;
+pub mod machine;
+pub mod manifest;
+pub mod native;
+pub mod profile;
+pub mod repl;
+pub mod report;
+pub mod sanitizer;
+pub mod screen;
+pub mod screenshot;
+pub mod serve;
+pub mod stack_guard;
+pub mod state;
+pub mod suite;
+pub mod symbols;
+pub mod throttle;
+pub mod trace;
+pub mod tst;
+
+pub use cpu::Cpu;
+pub use machine::Machine;
+pub use screen::ScreenBackend;
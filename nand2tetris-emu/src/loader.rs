@@ -0,0 +1,975 @@
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use clap::ValueEnum;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nand2tetris_core::{
+    cli::{Fail, Failure},
+    diagnostic::with_file,
+    source::code_lines,
+};
+use nand2tetris_jack::{
+    analysis::{Signatures, check_class},
+    ast::Class,
+    generate_with_lines, jack_files, os, parser,
+    source_map::{SourceMap, map_path},
+};
+use nand2tetris_vm::{SourceLocation, TranslateOptions, VMTranslator};
+
+use crate::{
+    cache::{Cache, Stats},
+    symbols::Symbols,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RomFormat {
+    /// ASCII `{:016b}` lines as written by the assembler
+    Text,
+    /// One hexadecimal word per line
+    Hex,
+    /// Raw big-endian 16-bit words
+    Binary,
+}
+
+// ROM の内容と、分かっていればラベルと変数
+#[derive(Clone)]
+pub struct LoadedProgram {
+    pub words: Vec<u16>,
+    pub symbols: Symbols,
+    // 元の .asm / .vm ファイル
+    pub source_files: Vec<PathBuf>,
+}
+
+// .asm はメモリ上でアセンブルし、.vm とディレクトリは変換してからアセンブルする。
+// それ以外は ROM イメージとして読み、隣に .sym があればシンボルとして使う
+pub fn load_program(path: &Path, format: Option<RomFormat>) -> Result<LoadedProgram> {
+    load_program_with(path, format, &[])
+}
+
+// native は Rust で実行する OS の関数（native::native_functions）。同梱の OS をつなぐとき、
+// それらの本体は空にして、どこからも呼ばれなくなった関数は除く
+pub fn load_program_with(
+    path: &Path,
+    format: Option<RomFormat>,
+    native: &[&str],
+) -> Result<LoadedProgram> {
+    if format.is_none() {
+        if path.is_dir() || path.extension().is_some_and(|e| e == "vm") {
+            return load_vm(path, native);
+        }
+        if path.extension().is_some_and(|e| e == "asm") {
+            let source = fs::read_to_string(path)
+                .context(format!("Failed to read file '{}'", path.display()))?;
+            return load_asm(path, &source, &HashMap::new());
+        }
+    }
+
+    let sym = path.with_extension("sym");
+    Ok(LoadedProgram {
+        words: load_rom(path, format)?,
+        symbols: if sym.is_file() {
+            Symbols::load(&sym)?
+        } else {
+            Symbols::default()
+        },
+        source_files: Vec::new(),
+    })
+}
+
+fn load_asm(path: &Path, source: &str, symbols: &HashMap<String, u16>) -> Result<LoadedProgram> {
+    let program = nand2tetris_asm::assemble_program_with(source, symbols)
+        .map_err(|e| with_file(e, &path.display().to_string()))?;
+
+    let file = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let sources = program
+        .source_lines
+        .iter()
+        .map(|&line| {
+            Some(SourceLocation {
+                file: file.to_string(),
+                line,
+            })
+        })
+        .collect();
+
+    Ok(LoadedProgram {
+        words: program.words,
+        symbols: Symbols::from_labels(&program.labels)
+            .with_variables(&program.variables)
+            .with_sources(sources),
+        source_files: vec![path.to_path_buf()],
+    })
+}
+
+// .vm ファイルかディレクトリ。隣に .vm.map があれば行は Jack の行になる
+fn load_vm(path: &Path, native: &[&str]) -> Result<LoadedProgram> {
+    build_vm_files(path, &vm_files(path)?, native, &BuildOptions::default())
+        .map(|build| build.program)
+}
+
+// build の設定。n2t.toml で決める
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildOptions {
+    // Jack コンパイラの -O のレベル
+    pub optimize: u8,
+    // アセンブラの定義済みシンボルに加えるもの
+    pub symbols: HashMap<String, u16>,
+    // Some なら途中のものをこのディレクトリにキャッシュする
+    pub cache: Option<PathBuf>,
+}
+
+// .hack までの途中のものもすべて
+pub struct Build {
+    pub program: LoadedProgram,
+    // .jack ファイルと、そこから生成した VM コードとソースマップ。.jack からでなければ空
+    pub classes: Vec<(PathBuf, String, SourceMap)>,
+    // OS とつないだ全体のアセンブリ
+    pub asm: String,
+    // キャッシュを使ったときは、飛ばした段階と実行した段階の数
+    pub cache: Option<Stats>,
+}
+
+// path はエラーメッセージに使う
+pub fn build_asm(path: &Path, options: &BuildOptions) -> Result<Build> {
+    let source =
+        fs::read_to_string(path).context(format!("Failed to read file '{}'", path.display()))?;
+    Ok(Build {
+        program: load_asm(path, &source, &options.symbols)?,
+        classes: Vec::new(),
+        asm: source,
+        cache: None,
+    })
+}
+
+pub fn build_vm_files(
+    path: &Path,
+    files: &[PathBuf],
+    native: &[&str],
+    options: &BuildOptions,
+) -> Result<Build> {
+    let mut sources = Vec::new();
+    for file in files {
+        let code = fs::read_to_string(file)
+            .context(format!("Failed to read file '{}'", file.display()))?;
+        let stem = file
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .context("Invalid filename")?;
+        sources.push((stem.to_string(), code));
+    }
+    let (maps, source_files) = jack_maps(files.to_vec(), &sources)?;
+    let mut cache = options.cache.as_deref().map(Cache::open).transpose()?;
+    let (program, asm) = link_vm(
+        path,
+        &sources,
+        &maps,
+        native,
+        source_files,
+        options,
+        cache.as_mut(),
+    )?;
+    Ok(Build {
+        program,
+        classes: Vec::new(),
+        asm,
+        cache: finish(cache)?,
+    })
+}
+
+// .jack をメモリ上でコンパイルし、.vm と同じように OS とつなぐ。ソースマップも作るので
+// 行は Jack の行になる
+pub fn load_jack(path: &Path, native: &[&str]) -> Result<LoadedProgram> {
+    build_jack(path, native).map(|build| build.program)
+}
+
+pub fn build_jack(path: &Path, native: &[&str]) -> Result<Build> {
+    build_jack_files(path, &jack_files(path)?, native, &BuildOptions::default())
+}
+
+pub fn build_jack_files(
+    path: &Path,
+    files: &[PathBuf],
+    native: &[&str],
+    options: &BuildOptions,
+) -> Result<Build> {
+    let classes = compile_jack_files(files)?;
+    let all: Vec<Class> = classes.iter().map(|(_, class)| class.clone()).collect();
+    let mut cache = options.cache.as_deref().map(Cache::open).transpose()?;
+    // -O2 は他のクラスの小さなサブルーチンを展開するので、すべてのクラスが入力になる
+    let project = match (&cache, options.optimize >= 2) {
+        (Some(_), true) => files
+            .iter()
+            .map(|file| {
+                fs::read_to_string(file)
+                    .context(format!("Failed to read file '{}'", file.display()))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        _ => Vec::new(),
+    };
+    let mut sources = Vec::new();
+    let mut maps = HashMap::new();
+    for (file, class) in &classes {
+        let generate = || {
+            generate_with_lines(class, &all, options.optimize)
+                .map_err(|e| anyhow!("{}: {}", file.display(), e))
+        };
+        let (code, lines) = match &mut cache {
+            Some(cache) => {
+                let source = fs::read_to_string(file)
+                    .context(format!("Failed to read file '{}'", file.display()))?;
+                let level = options.optimize.to_string();
+                let mut inputs = vec![level.as_str(), source.as_str()];
+                inputs.extend(project.iter().map(|source| source.as_str()));
+                let key = cache.key("vm", &inputs);
+                match cache.get(&key, &["vm", "lines"]) {
+                    Some(texts) => {
+                        let lines = serde_json::from_str(&texts[1])
+                            .context(format!("Invalid cache entry '{}'", key))?;
+                        (texts[0].clone(), lines)
+                    }
+                    None => {
+                        let (code, lines) = generate()?;
+                        let json = serde_json::to_string(&lines)?;
+                        cache.put(&key, &[("vm", &code), ("lines", &json)])?;
+                        (code, lines)
+                    }
+                }
+            }
+            None => generate()?,
+        };
+        let name = class.name.name.clone();
+        let source = file.file_name().unwrap_or_default().to_string_lossy();
+        maps.insert(
+            name.clone(),
+            SourceMap {
+                source: source.into_owned(),
+                lines,
+            },
+        );
+        sources.push((name, code));
+    }
+    let files: Vec<PathBuf> = classes.into_iter().map(|(file, _)| file).collect();
+    let (program, asm) = link_vm(
+        path,
+        &sources,
+        &maps,
+        native,
+        files.clone(),
+        options,
+        cache.as_mut(),
+    )?;
+    let classes = files
+        .into_iter()
+        .zip(sources)
+        .map(|(file, (name, code))| {
+            let map = maps.remove(&name).unwrap();
+            (file, code, map)
+        })
+        .collect();
+    Ok(Build {
+        program,
+        classes,
+        asm,
+        cache: finish(cache)?,
+    })
+}
+
+// .jack をすべて解析して検査する。エラーがあればすべてまとめて返す
+pub fn compile_jack(path: &Path) -> Result<Vec<(PathBuf, Class)>> {
+    compile_jack_files(&jack_files(path)?)
+}
+
+pub fn compile_jack_files(files: &[PathBuf]) -> Result<Vec<(PathBuf, Class)>> {
+    let mut classes = Vec::new();
+    let mut errors = Vec::new();
+    for file in files {
+        let file = file.clone();
+        let source = fs::read_to_string(&file)
+            .context(format!("Failed to read file '{}'", file.display()))?;
+        let (class, diagnostics) = parser::parse_with_diagnostics(&source);
+        errors.extend(
+            diagnostics
+                .iter()
+                .filter(|d| d.is_error())
+                .map(|d| format!("{}: {}", file.display(), d)),
+        );
+        if let Some(class) = class {
+            classes.push((file, class));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join("\n"))).fail(Failure::Parse);
+    }
+    let all: Vec<Class> = classes.iter().map(|(_, class)| class.clone()).collect();
+    let signatures = Signatures::new(&all);
+    for (file, class) in &classes {
+        errors.extend(
+            check_class(class, &signatures)
+                .iter()
+                .filter(|d| d.is_error())
+                .map(|d| format!("{}: {}", file.display(), d)),
+        );
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join("\n"))).fail(Failure::Semantic);
+    }
+    Ok(classes)
+}
+
+// Sys.init があるときだけブートストラップを入れる（Project 7 のテストはなし）。
+// OS のクラスを呼んでいて定義がなければ、同梱の OS をつなぐ。アセンブリも返す
+fn link_vm(
+    path: &Path,
+    sources: &[(String, String)],
+    maps: &HashMap<String, SourceMap>,
+    native: &[&str],
+    source_files: Vec<PathBuf>,
+    build: &BuildOptions,
+    cache: Option<&mut Cache>,
+) -> Result<(LoadedProgram, String)> {
+    let Some(cache) = cache else {
+        return translate_vm(path, sources, maps, native, source_files, build, false);
+    };
+    // VM コードとソースマップ、OS の関数を Rust で実行するか、追加のシンボルがすべて同じなら、
+    // 翻訳とアセンブルの結果も同じ
+    let mut inputs: Vec<String> = vec![native.join(","), defines_sys_init(sources).to_string()];
+    let mut symbols: Vec<_> = build.symbols.iter().collect();
+    symbols.sort();
+    inputs.extend(
+        symbols
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value)),
+    );
+    for (name, code) in sources {
+        inputs.push(name.clone());
+        inputs.push(code.clone());
+        inputs.push(maps.get(name).map(|map| map.to_json()).unwrap_or_default());
+    }
+    let key = cache.key(
+        "link",
+        &inputs.iter().map(String::as_str).collect::<Vec<_>>(),
+    );
+    if let Some(texts) = cache.get(&key, &["asm", "hack", "sym"]) {
+        let program = LoadedProgram {
+            words: parse_hack(&texts[1]).context(format!("Invalid cache entry '{}'", key))?,
+            symbols: Symbols::parse(&texts[2]).context(format!("Invalid cache entry '{}'", key))?,
+            source_files,
+        };
+        return Ok((program, texts[0].clone()));
+    }
+    let (program, asm) = translate_vm(path, sources, maps, native, source_files, build, false)?;
+    let hack: String = program
+        .words
+        .iter()
+        .map(|word| format!("{:016b}\n", word))
+        .collect();
+    cache.put(
+        &key,
+        &[
+            ("asm", &asm),
+            ("hack", &hack),
+            ("sym", &program.symbols.format()),
+        ],
+    )?;
+    Ok((program, asm))
+}
+
+// ファイルを使わずに (名前, VM コード) を OS とつなぐ（n2t serve）。name はエラーメッセージに使う。
+// link_os なら OS の関数を呼んでいなくても OS とブートストラップを入れる（Jack のプログラム）
+pub fn link_vm_sources(
+    name: &str,
+    sources: &[(String, String)],
+    native: &[&str],
+    link_os: bool,
+) -> Result<(LoadedProgram, String)> {
+    translate_vm(
+        Path::new(name),
+        sources,
+        &HashMap::new(),
+        native,
+        Vec::new(),
+        &BuildOptions::default(),
+        link_os,
+    )
+}
+
+// 使わなかったエントリを消して、結果の数を返す
+fn finish(cache: Option<Cache>) -> Result<Option<Stats>> {
+    cache
+        .map(|cache| {
+            cache.prune()?;
+            Ok(cache.stats)
+        })
+        .transpose()
+}
+
+fn translate_vm(
+    path: &Path,
+    sources: &[(String, String)],
+    maps: &HashMap<String, SourceMap>,
+    native: &[&str],
+    source_files: Vec<PathBuf>,
+    build: &BuildOptions,
+    link_os: bool,
+) -> Result<(LoadedProgram, String)> {
+    let libraries = os_libraries(sources, native, link_os)?;
+    let options = TranslateOptions {
+        bootstrap: defines_sys_init(sources) || libraries.iter().any(|(name, _)| name == "Sys"),
+        libraries,
+        ..Default::default()
+    };
+    let translation = VMTranslator::translate_sources(sources, &options)?;
+    let program = nand2tetris_asm::assemble_program_with(&translation.asm, &build.symbols)
+        .map_err(|e| with_file(e, &path.display().to_string()))?;
+
+    // ROM → アセンブリの行 → VM の行 → (ソースマップがあれば) Jack の行
+    let sources = program
+        .source_lines
+        .iter()
+        .map(|&line| {
+            let location = translation.location(line - 1)?;
+            match maps.get(&location.file) {
+                Some(map) => Some(SourceLocation {
+                    file: location.file.clone(),
+                    line: map.jack_line(location.line)?,
+                }),
+                None => Some(location.clone()),
+            }
+        })
+        .collect();
+
+    let program = LoadedProgram {
+        words: program.words,
+        symbols: Symbols::from_labels(&program.labels)
+            .with_variables(&program.variables)
+            .with_sources(sources),
+        source_files,
+    };
+    Ok((program, translation.asm))
+}
+
+// Jack コンパイラの --source-map が書いた Foo.vm.map を .vm の名前ごとに読み、
+// 対応する Foo.jack をソースファイルにする。.vm と行数が合わない古いマップは使わない
+fn jack_maps(
+    vm_files: Vec<PathBuf>,
+    sources: &[(String, String)],
+) -> Result<(HashMap<String, SourceMap>, Vec<PathBuf>)> {
+    let mut maps = HashMap::new();
+    let mut files = Vec::new();
+    for (file, (stem, vm)) in vm_files.into_iter().zip(sources) {
+        let map_file = map_path(&file);
+        if map_file.is_file() {
+            let map = SourceMap::load(&map_file)?;
+            if map.lines.len() == vm.lines().count() {
+                files.push(file.with_file_name(&map.source));
+                maps.insert(stem.clone(), map);
+                continue;
+            }
+        }
+        files.push(file);
+    }
+    Ok((maps, files))
+}
+
+// ディレクトリなら中の .vm ファイル
+// VM 変換器と同じくファイル名順
+pub fn vm_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .context(format!("Failed to read directory '{}'", path.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vm"))
+        .collect();
+    if files.is_empty() {
+        bail!("No .vm files found in '{}'", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn defines_sys_init(sources: &[(String, String)]) -> bool {
+    sources
+        .iter()
+        .any(|(_, code)| commands(code).any(|command| command == ("function", "Sys.init")))
+}
+
+// 呼ばれているのにどのファイルにもない OS のクラスがあれば、定義のない OS のクラスを
+// すべて返す（OS のクラスどうしも呼び合い、Sys.init が全体を初期化するため）
+fn os_libraries(
+    sources: &[(String, String)],
+    native: &[&str],
+    link_os: bool,
+) -> Result<Vec<(String, String)>> {
+    let (mut defined, mut called) = (Vec::new(), Vec::new());
+    for (_, code) in sources {
+        for (command, function) in commands(code) {
+            match command {
+                "function" => defined.push(class_of(function).to_string()),
+                "call" => called.push(function.to_string()),
+                _ => {}
+            }
+        }
+    }
+    if !link_os
+        && !called.iter().any(|function| {
+            let class = class_of(function);
+            os::is_os_class(class) && !defined.iter().any(|name| name == class)
+        })
+    {
+        return Ok(Vec::new());
+    }
+    let libraries: Vec<(String, String)> = os::compile_os(0)?
+        .into_iter()
+        .filter(|(name, _)| !defined.contains(name))
+        .collect();
+    if native.is_empty() {
+        return Ok(libraries);
+    }
+    Ok(strip_native(libraries, called, native))
+}
+
+// native の関数の本体を 0 を返すだけにし、Sys.init とプログラムから呼ばれる関数だけを残す。
+// Sys.error は native の関数がエラーを報告するのに使う
+pub(crate) fn strip_native(
+    libraries: Vec<(String, String)>,
+    mut called: Vec<String>,
+    native: &[&str],
+) -> Vec<(String, String)> {
+    let mut functions = Vec::new();
+    for (class, code) in &libraries {
+        let mut text = String::new();
+        let mut name = "";
+        for line in code.lines() {
+            if let Some(("function", function)) = commands(line).next() {
+                if !name.is_empty() {
+                    functions.push((class.clone(), name, text));
+                }
+                name = function;
+                text = if native.contains(&function) {
+                    format!("function {} 0\npush constant 0\nreturn\n", function)
+                } else {
+                    String::new()
+                };
+            }
+            if !native.contains(&name) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        if !name.is_empty() {
+            functions.push((class.clone(), name, text));
+        }
+    }
+
+    // 呼び出しをたどって残す関数を決める
+    called.extend(["Sys.init".to_string(), "Sys.error".to_string()]);
+    let mut kept = vec![false; functions.len()];
+    while let Some(function) = called.pop() {
+        for (i, (_, name, text)) in functions.iter().enumerate() {
+            if kept[i] || *name != function {
+                continue;
+            }
+            kept[i] = true;
+            called.extend(
+                commands(text)
+                    .filter(|&(command, _)| command == "call")
+                    .map(|(_, callee)| callee.to_string()),
+            );
+        }
+    }
+
+    libraries
+        .iter()
+        .map(|(class, _)| {
+            let code: String = functions
+                .iter()
+                .zip(&kept)
+                .filter(|((owner, _, _), kept)| owner == class && **kept)
+                .map(|((_, _, text), _)| text.as_str())
+                .collect();
+            (class.clone(), code)
+        })
+        .filter(|(_, code)| !code.is_empty())
+        .collect()
+}
+
+// 各行の最初の 2 語（function / call と関数名）
+pub(crate) fn commands(source: &str) -> impl Iterator<Item = (&str, &str)> {
+    code_lines(source).filter_map(|(_, line)| {
+        let mut words = line.split_whitespace();
+        Some((words.next()?, words.next()?))
+    })
+}
+
+pub(crate) fn class_of(function: &str) -> &str {
+    function.split('.').next().unwrap_or_default()
+}
+
+pub fn load_hack(path: &Path) -> Result<Vec<u16>> {
+    load_rom(path, None)
+}
+
+pub fn load_rom(path: &Path, format: Option<RomFormat>) -> Result<Vec<u16>> {
+    let bytes = fs::read(path).context(format!("Failed to read file '{}'", path.display()))?;
+    let format = format.unwrap_or_else(|| detect_format(path, &bytes));
+
+    parse_rom(&bytes, format).context(format!("Failed to load '{}'", path.display()))
+}
+
+// 拡張子を優先し、なければ内容から判定する
+pub fn detect_format(path: &Path, bytes: &[u8]) -> RomFormat {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("bin") => return RomFormat::Binary,
+        Some("hex") => return RomFormat::Hex,
+        _ => {}
+    }
+
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return RomFormat::Binary;
+    };
+    if text
+        .bytes()
+        .any(|b| !(b.is_ascii_graphic() || b.is_ascii_whitespace()))
+    {
+        return RomFormat::Binary;
+    }
+
+    let is_text = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .all(|l| l.len() == 16 && l.bytes().all(|b| b == b'0' || b == b'1'));
+
+    if is_text {
+        RomFormat::Text
+    } else {
+        RomFormat::Hex
+    }
+}
+
+pub fn parse_rom(bytes: &[u8], format: RomFormat) -> Result<Vec<u16>> {
+    match format {
+        RomFormat::Text => parse_lines(bytes, parse_text_word),
+        RomFormat::Hex => parse_lines(bytes, parse_hex_word),
+        RomFormat::Binary => parse_binary(bytes),
+    }
+}
+
+pub fn parse_hack(text: &str) -> Result<Vec<u16>> {
+    parse_rom(text.as_bytes(), RomFormat::Text)
+}
+
+fn parse_lines(bytes: &[u8], parse_word: fn(&str) -> Option<u16>) -> Result<Vec<u16>> {
+    let text = std::str::from_utf8(bytes).context("ROM file is not valid UTF-8 text")?;
+    let mut program = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_word(line) {
+            Some(word) => program.push(word),
+            None => bail!(
+                "ROM[{}] (line {}): invalid instruction '{}'",
+                program.len(),
+                i + 1,
+                line
+            ),
+        }
+    }
+
+    Ok(program)
+}
+
+fn parse_text_word(line: &str) -> Option<u16> {
+    if line.len() != 16 || !line.bytes().all(|b| b == b'0' || b == b'1') {
+        return None;
+    }
+    u16::from_str_radix(line, 2).ok()
+}
+
+fn parse_hex_word(line: &str) -> Option<u16> {
+    let digits = line
+        .strip_prefix("0x")
+        .or_else(|| line.strip_prefix("0X"))
+        .unwrap_or(line);
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<u16>> {
+    ensure!(
+        bytes.len().is_multiple_of(2),
+        "ROM[{}]: truncated word (file size {} is odd)",
+        bytes.len() / 2,
+        bytes.len()
+    );
+
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|w| u16::from_be_bytes([w[0], w[1]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("a.hack", b"0000000000000010\n1110110000010000\n", RomFormat::Text)]
+    #[case("a.hack", b"0002\nEC10\n", RomFormat::Hex)]
+    #[case("a.hack", &[0x00, 0x02, 0xec, 0x10], RomFormat::Binary)]
+    #[case("a.bin", b"0000000000000010\n", RomFormat::Binary)]
+    #[case("a.hex", b"0000000000000010\n", RomFormat::Hex)]
+    fn test_detect_format(#[case] name: &str, #[case] bytes: &[u8], #[case] expected: RomFormat) {
+        assert_eq!(detect_format(Path::new(name), bytes), expected);
+    }
+
+    #[rstest]
+    #[case(b"0000000000000010\n1110110000010000\n", RomFormat::Text)]
+    #[case(b"0x0002\nec10\n", RomFormat::Hex)]
+    #[case(&[0x00, 0x02, 0xec, 0x10], RomFormat::Binary)]
+    fn test_parse_rom(#[case] bytes: &[u8], #[case] format: RomFormat) {
+        assert_eq!(parse_rom(bytes, format).unwrap(), vec![0x0002, 0xec10]);
+    }
+
+    #[test]
+    fn test_error_reports_address_and_line() {
+        let err = parse_hack("0000000000000010\n\n11101100000100\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ROM[1] (line 3): invalid instruction '11101100000100'"
+        );
+    }
+
+    #[test]
+    fn test_odd_binary_is_error() {
+        assert!(parse_rom(&[0x00, 0x02, 0xec], RomFormat::Binary).is_err());
+    }
+
+    #[test]
+    fn test_load_hack_with_sym_file() {
+        let dir = std::env::temp_dir().join(format!("emu-load-sym-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Prog.hack"),
+            "0000000000010000\n1110101010001000\n",
+        )
+        .unwrap();
+        fs::write(dir.join("Prog.sym"), "label START 0\nvar counter 16\n").unwrap();
+
+        let program = load_program(&dir.join("Prog.hack"), None).unwrap();
+        assert_eq!(program.symbols.resolve("START"), Some(0));
+        assert_eq!(program.symbols.variable_at(16), Some("counter"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_vm_directory() {
+        let dir = std::env::temp_dir().join(format!("emu-load-vm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 6\ncall Main.double 1\npop static 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+
+        let program = load_program(&dir, None).unwrap();
+        let mut cpu = crate::Cpu::new(&program.words).unwrap();
+        cpu.run(Some(10_000)).unwrap();
+
+        // Sys.0 は最初の静的変数なので RAM[16]
+        assert_eq!(cpu.ram[16], 12);
+
+        let entry = program.symbols.resolve("Main.double").unwrap();
+        // ローカル変数なしの function は命令を生成しないので最初の push が来る
+        let source = program.symbols.source_at(entry).unwrap();
+        assert_eq!((source.file.as_str(), source.line), ("Main", 2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_vm_links_os() {
+        let dir = std::env::temp_dir().join(format!("emu-load-os-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.main 0\npush constant 8000\npush constant 6\npush constant 7\n\
+             call Math.multiply 2\ncall Memory.poke 2\npop temp 0\npush constant 0\nreturn",
+        )
+        .unwrap();
+
+        let program = load_program(&dir, None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(program.symbols.resolve("Sys.init").is_some());
+        assert!(program.symbols.resolve("Output.printInt").is_some());
+
+        // Sys.init は Main.main の後で Sys.halt を呼ぶので、上限の前に止まる
+        let mut cpu = crate::Cpu::new(&program.words).unwrap();
+        cpu.sys_halt = program.symbols.resolve("Sys.halt");
+        assert_eq!(
+            cpu.run(Some(20_000_000)).unwrap(),
+            crate::cpu::StopReason::Halted
+        );
+        assert_eq!(cpu.pc, cpu.sys_halt.unwrap());
+        assert_eq!(cpu.ram[8000], 42);
+    }
+
+    #[test]
+    fn test_build_jack_links_os() {
+        let dir = std::env::temp_dir().join(format!("emu-build-os-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Main.jack"),
+            "class Main { function void main() { do Memory.poke(8000, Math.multiply(6, 7)); return; } }",
+        )
+        .unwrap();
+
+        let build = build_jack(&dir, &[]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let mut cpu = crate::Cpu::new(&build.program.words).unwrap();
+        cpu.sys_halt = build.program.symbols.resolve("Sys.halt");
+        assert_eq!(
+            cpu.run(Some(50_000_000)).unwrap(),
+            crate::cpu::StopReason::Halted
+        );
+        assert_eq!(cpu.pc, cpu.sys_halt.unwrap());
+        assert_eq!(cpu.ram[8000], 42);
+    }
+
+    #[test]
+    fn test_load_vm_with_jack_map() {
+        let dir = std::env::temp_dir().join(format!("emu-load-jack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 6\ncall Main.double 1\npop static 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.vm.map"),
+            r#"{"source":"Main.jack","lines":[2,3,3,3,3]}"#,
+        )
+        .unwrap();
+        // 行数が合わないマップは無視する
+        fs::write(
+            dir.join("Sys.vm.map"),
+            r#"{"source":"Sys.jack","lines":[1]}"#,
+        )
+        .unwrap();
+
+        let program = load_program(&dir, None).unwrap();
+        let entry = program.symbols.resolve("Main.double").unwrap();
+        let source = program.symbols.source_at(entry).unwrap();
+        assert_eq!((source.file.as_str(), source.line), ("Main", 3));
+        let sys = program.symbols.resolve("Sys.init").unwrap();
+        assert_eq!(program.symbols.source_at(sys).unwrap().line, 2);
+
+        let mut files = program.source_files.clone();
+        files.sort();
+        assert_eq!(files, [dir.join("Main.jack"), dir.join("Sys.vm")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_jack() {
+        let dir = std::env::temp_dir().join(format!("emu-build-jack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Sys.init があるので OS はつながない
+        fs::write(
+            dir.join("Sys.jack"),
+            "class Sys {\n\
+             function void init() {\n\
+             var Array ram;\n\
+             let ram = 8000;\n\
+             let ram[0] = Main.double(21);\n\
+             while (true) {}\n\
+             return;\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.jack"),
+            "class Main {\n\
+             function int double(int n) {\n\
+             return n + n;\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+
+        let build = build_jack(&dir, &[]).unwrap();
+        // 2 回目はすべてキャッシュから。Main を変えると Main の VM コードとリンクだけやり直す
+        let options = BuildOptions {
+            cache: Some(dir.join(crate::cache::CACHE_DIR)),
+            ..BuildOptions::default()
+        };
+        let files = jack_files(&dir).unwrap();
+        let first = build_jack_files(&dir, &files, &[], &options).unwrap();
+        let second = build_jack_files(&dir, &files, &[], &options).unwrap();
+        fs::write(
+            dir.join("Main.jack"),
+            "class Main { function int double(int n) { return n * 2; } }",
+        )
+        .unwrap();
+        let third = build_jack_files(&dir, &files, &[], &options).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let stats = |build: &Build| build.cache.map(|stats| (stats.hits, stats.misses));
+        assert_eq!(stats(&first), Some((0, 3)));
+        assert_eq!(stats(&second), Some((3, 0)));
+        assert_eq!(stats(&third), Some((1, 2)));
+        assert_eq!(second.program.words, build.program.words);
+        assert_eq!(second.asm, build.asm);
+        assert_eq!(
+            second.program.symbols.format(),
+            build.program.symbols.format()
+        );
+        assert_eq!(second.classes[0].2, build.classes[0].2);
+        assert_ne!(third.program.words, build.program.words);
+        let classes: Vec<(&str, usize)> = build
+            .classes
+            .iter()
+            .map(|(_, code, map)| (map.source.as_str(), code.lines().count()))
+            .collect();
+        assert_eq!(classes, [("Main.jack", 5), ("Sys.jack", 21)]);
+        assert!(build.asm.contains("(Main.double)"));
+
+        let symbols = &build.program.symbols;
+        let entry = symbols.resolve("Main.double").unwrap();
+        let source = symbols.source_at(entry).unwrap();
+        assert_eq!((source.file.as_str(), source.line), ("Main", 3));
+        let mut cpu = crate::Cpu::new(&build.program.words).unwrap();
+        cpu.run(Some(10_000)).unwrap();
+        assert_eq!(cpu.ram[8000], 42);
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{
+    Cpu,
+    cpu::{RunOptions, StepHook, StepInfo, StopReason},
+    loader,
+    native::{NativeOs, native_functions},
+    screen::{Headless, KBD, ScreenBackend},
+    symbols::Symbols,
+};
+
+// 他のアプリケーションに組み込むための Hack コンピュータ。
+// 画面とキーボードは ScreenBackend の実装を渡して差し替える
+pub struct Machine {
+    cpu: Cpu,
+    symbols: Symbols,
+    io: Box<dyn ScreenBackend>,
+    refresh_interval: u64,
+}
+
+impl Machine {
+    // 画面もキーボードもないヘッドレスのマシン
+    pub fn new(program: &[u16]) -> Result<Self> {
+        Ok(Machine {
+            cpu: Cpu::new(program)?,
+            symbols: Symbols::default(),
+            io: Box::new(Headless),
+            refresh_interval: RunOptions::default().refresh_interval,
+        })
+    }
+
+    // .hack / .asm / .vm / ディレクトリを読み込む
+    pub fn load(path: &Path) -> Result<Self> {
+        let program = loader::load_program(path, None)?;
+        let mut machine = Machine::new(&program.words)?;
+        machine.symbols = program.symbols;
+        Ok(machine)
+    }
+
+    // classes の OS の関数を Rust で実行する（空ならすべて）。同梱の OS はその分小さくつなぐ
+    pub fn load_with_native_os(path: &Path, classes: &[String]) -> Result<Self> {
+        let program = loader::load_program_with(path, None, &native_functions(classes)?)?;
+        let mut machine = Machine::new(&program.words)?;
+        machine.cpu.native = Some(Box::new(NativeOs::new(&program.symbols, classes)?));
+        machine.symbols = program.symbols;
+        Ok(machine)
+    }
+
+    pub fn with_io(mut self, io: impl ScreenBackend + 'static) -> Self {
+        self.io = Box::new(io);
+        self
+    }
+
+    // 画面更新とキーボード読み取りの間隔（サイクル）
+    pub fn with_refresh_interval(mut self, cycles: u64) -> Self {
+        self.refresh_interval = cycles.max(1);
+        self
+    }
+
+    // 1命令を実行する。refresh_interval サイクルごとに画面とキーボードを同期する
+    pub fn step(&mut self) -> Result<StepInfo> {
+        if self.cpu.cycles.is_multiple_of(self.refresh_interval) {
+            self.cpu.sync_io(self.io.as_mut())?;
+        }
+        if let Some(key) = self.cpu.input.due(self.cpu.cycles) {
+            self.cpu.set_key(key);
+        }
+        self.cpu.step()
+    }
+
+    // 最大 cycles 命令を実行し、実行した数を返す。止まったら（halt かプログラムの末尾）
+    // それより少なくなる
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<u64> {
+        let start = self.cpu.cycles;
+        let options = RunOptions {
+            max_cycles: Some(start.saturating_add(cycles)),
+            ..RunOptions::default()
+        };
+        self.run(&options, &mut [])?;
+        Ok(self.cpu.cycles - start)
+    }
+
+    pub fn run(
+        &mut self,
+        options: &RunOptions,
+        hooks: &mut [&mut dyn StepHook],
+    ) -> Result<StopReason> {
+        let options = RunOptions {
+            refresh_interval: self.refresh_interval,
+            ..options.clone()
+        };
+        self.cpu.run_with_backend(self.io.as_mut(), &options, hooks)
+    }
+
+    // 画面とキーボードを今すぐ同期する
+    pub fn sync_io(&mut self) -> Result<()> {
+        self.cpu.sync_io(self.io.as_mut())
+    }
+
+    // RAM はそのままで PC とサイクル数を戻す（Hack の reset と同じ）
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    pub fn stop_reason(&self, max_cycles: Option<u64>) -> Option<StopReason> {
+        self.cpu.check_stop(max_cycles)
+    }
+
+    pub fn peek(&self, address: u16) -> Result<u16> {
+        self.cpu.read(address)
+    }
+
+    // KBD も含めて直接書き換える。KBD は次の同期でキーボードの値に戻り、
+    // 画面の再描画も次の同期で行われる
+    pub fn poke(&mut self, address: u16, value: u16) -> Result<()> {
+        if address as usize == KBD {
+            self.cpu.set_key(value);
+        } else {
+            self.cpu.write(address, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_key(&mut self, key: u16) {
+        self.cpu.set_key(key);
+    }
+
+    pub fn a(&self) -> u16 {
+        self.cpu.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.cpu.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.cpu.ram
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.cpu.rom
+    }
+
+    pub fn screen(&self) -> &[u16] {
+        self.cpu.screen()
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{KeyLatch, SCREEN};
+    use std::{cell::RefCell, rc::Rc};
+
+    // 押されたキーを画面の先頭に書き続ける
+    const ECHO_KEY: &[u16] = &[
+        0b0110000000000000, // @KBD
+        0b1111110000010000, // D=M
+        0b0100000000000000, // @SCREEN
+        0b1110001100001000, // M=D
+        0b0000000000000000, // @0
+        0b1110101010000111, // 0;JMP
+    ];
+
+    #[derive(Default)]
+    struct Recorder {
+        frames: Rc<RefCell<Vec<u16>>>,
+        key: u16,
+    }
+
+    impl ScreenBackend for Recorder {
+        fn refresh(&mut self, screen: &[u16]) -> Result<()> {
+            self.frames.borrow_mut().push(screen[0]);
+            Ok(())
+        }
+
+        fn poll_key(&mut self) -> Result<u16> {
+            Ok(self.key)
+        }
+    }
+
+    #[test]
+    fn test_step_with_io() {
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let recorder = Recorder {
+            frames: frames.clone(),
+            key: 65,
+        };
+        let mut machine = Machine::new(ECHO_KEY)
+            .unwrap()
+            .with_io(recorder)
+            .with_refresh_interval(6);
+
+        for _ in 0..12 {
+            machine.step().unwrap();
+        }
+        machine.sync_io().unwrap();
+
+        assert_eq!(machine.peek(SCREEN as u16).unwrap(), 65);
+        assert_eq!(machine.cycles(), 12);
+        // 最初の同期で空の画面、以降は書き込みがあるたびに再描画される
+        assert_eq!(*frames.borrow(), vec![0, 65, 65]);
+    }
+
+    #[test]
+    fn test_run_cycles_with_key_latch() {
+        let key = KeyLatch::default();
+        let mut machine = Machine::new(ECHO_KEY).unwrap().with_io(key.clone());
+        key.set(65);
+        assert_eq!(machine.run_cycles(20_000).unwrap(), 20_000);
+        assert_eq!(machine.peek(SCREEN as u16).unwrap(), 65);
+        key.set(0);
+        machine.run_cycles(20_000).unwrap();
+        assert_eq!(machine.peek(SCREEN as u16).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_poke_and_reset() {
+        let mut machine = Machine::new(ECHO_KEY).unwrap();
+        machine.step().unwrap();
+        machine.poke(KBD as u16, 32).unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.d(), 32);
+
+        machine.reset();
+        assert_eq!((machine.pc(), machine.cycles()), (0, 0));
+        assert!(machine.poke(40000, 1).is_err());
+    }
+}
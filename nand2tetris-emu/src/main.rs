@@ -0,0 +1,1288 @@
+use anyhow::{Context, Result, anyhow};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use nand2tetris_core::{
+    cli::{ColorChoice, Failure, finish, set_color, set_quiet},
+    config::{Config, expand_home},
+    status,
+    test_report::{ReportArgs, TestCase},
+    timeout::{TimeoutArgs, is_timeout, parse_duration},
+};
+use nand2tetris_emu::{
+    Cpu,
+    assertion::{check_assertions, parse_assertions},
+    cache::CACHE_DIR,
+    compare,
+    conformance::{self, Tools},
+    coverage::Coverage,
+    cpu::{RunOptions, StepHook, StopReason},
+    dap,
+    debugger::{Action, Debugger, OnBreak},
+    differential::{Case, Kind},
+    dump::{self, Radix},
+    frame::{Frame, format_backtrace},
+    grade::{self, Grader},
+    harness::{Harness, HarnessOptions, TestResult},
+    heap::HeapChecker,
+    heatmap::MemoryHeatmap,
+    input::{InputLog, InputMode},
+    keyboard::KeyMap,
+    loader::{self, BuildOptions, RomFormat},
+    manifest::{MANIFEST, Project, manifest_path},
+    native::{NativeOs, native_functions},
+    profile::Profiler,
+    repl::{Repl, ReplOptions},
+    report::RunReport,
+    sanitizer::UninitChecker,
+    screen::{self, Headless, ScreenKind, ScreenOptions, TtyStyle},
+    serve::{self, ServeOptions},
+    stack_guard::StackGuard,
+    state::Snapshot,
+    suite::{self, Matrix},
+    symbols::Symbols,
+    trace::Tracer,
+    tst::{
+        Comparison,
+        record::{self, Recording},
+    },
+};
+use nand2tetris_jack::{jack_files, source_map::map_path};
+use nand2tetris_vm::VMTranslator;
+use serde::Deserialize;
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Parser)]
+#[command(about = "Nand2Tetris Hack CPU Emulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Print nothing but errors and the output asked for
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// When to color errors and warnings
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: ColorChoice,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Load a program (.hack, .asm, .vm or a directory of .vm files) into ROM and run it to completion
+    Run(Box<RunArgs>),
+    /// Compile a .jack file or a directory of .jack files, link the OS, translate and assemble it to .hack
+    /// (without an input, build the project of the n2t.toml in this directory or a parent)
+    Build(BuildArgs),
+    /// Run a test script (.tst) on the hardware simulator, CPU emulator or VM emulator as its `load` needs,
+    /// or build a directory of .jack files and test it
+    /// (without a script, build the project of the n2t.toml in this directory or a parent and run its tests;
+    /// given the nand2tetris directory or its projects directory, run every official test script)
+    Test(TestArgs),
+    /// Load a program and start the debugger prompt
+    Debug(DebugArgs),
+    /// Serve the Debug Adapter Protocol on stdin/stdout (for VS Code and other DAP clients)
+    Dap,
+    /// Run two programs with the same input and cycle limit and compare their final RAM
+    CompareRun(CompareRunArgs),
+    /// Run the official Java tools and this toolchain on the same .asm files and .tst scripts
+    /// and report where their outputs differ
+    Conformance(ConformanceArgs),
+    /// Generate random programs and check that the toolchain runs them as a reference
+    /// interpreter does
+    Fuzz(FuzzArgs),
+    /// Run the test scripts of a tests directory against every student's submission
+    /// and write a CSV or JSON report of the results
+    Grade(GradeArgs),
+    /// Run an .asm or .hack program and write a .tst script and .cmp file next to it that
+    /// check the chosen RAM cells at chosen intervals
+    RecordTest(RecordTestArgs),
+    /// Start a Jack REPL: each input is compiled to VM code and run on the same machine
+    Repl(ReplArgs),
+    /// Serve a JSON HTTP API that assembles, translates, compiles and runs programs sent in requests
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// A .jack file, a directory of .jack files, or an n2t.toml or a directory with one
+    input: Option<PathBuf>,
+    /// Write the program to this file (default: <dir>/<dir>.hack, or Foo.hack for Foo.jack)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Optimize the generated code as `jack -O` does (overrides `optimize` in n2t.toml)
+    #[arg(short = 'O', value_name = "LEVEL", num_args = 0..=1, default_missing_value = "1")]
+    optimize: Option<u8>,
+    // 設定ファイルの optimize。n2t.toml の optimize より弱い
+    #[arg(skip)]
+    config_optimize: Option<u8>,
+    /// Also write Foo.vm and Foo.vm.map next to each .jack file and the assembly next to the .hack file
+    #[arg(long)]
+    intermediate: bool,
+    /// Also write labels, variables and the Jack line of each ROM address to a .sym file next to the .hack file
+    #[arg(long)]
+    sym: bool,
+    /// Do not read or update the build cache (.n2t-cache next to the .hack file, or in the project)
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(Args)]
+struct ReplArgs {
+    /// A .jack file or a directory of .jack files whose classes can be used from the REPL
+    project: Option<PathBuf>,
+    /// Run the Jack OS functions of these classes (default: all that can be) natively
+    #[arg(long, value_name = "CLASSES", num_args = 0..=1, require_equals = true, value_delimiter = ',')]
+    native_os: Option<Vec<String>>,
+    /// Stop an input after this many cycles
+    #[arg(long, default_value_t = ReplOptions::default().max_cycles)]
+    max_cycles: u64,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on (use 0.0.0.0:8080 to accept connections from other machines)
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    /// Handle this many requests at a time (default: the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Stop a /run request after this many cycles, whatever the request asks for
+    #[arg(long, default_value_t = ServeOptions::default().max_cycles)]
+    max_cycles: u64,
+    /// Stop a /run request that runs longer than this (e.g. "10s", "500ms"; a plain number is seconds)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "10s")]
+    timeout: Duration,
+    /// Reject request bodies larger than this many bytes
+    #[arg(long, value_name = "BYTES", default_value_t = ServeOptions::default().max_body)]
+    max_body: usize,
+}
+
+#[derive(Args)]
+struct CompareRunArgs {
+    left: PathBuf,
+    right: PathBuf,
+    /// Stop each program after this many cycles
+    #[arg(long, default_value_t = 10_000_000)]
+    max_cycles: u64,
+    /// Only compare these RAM ranges (e.g. R0..R15,256..300,SCREEN..24575; default: all RAM)
+    #[arg(long, value_name = "RANGES")]
+    ranges: Option<String>,
+    /// Replay keyboard events recorded with `run --record-input` in both runs
+    #[arg(long, value_name = "FILE")]
+    replay_input: Option<PathBuf>,
+    /// Maximum number of differing addresses to print
+    #[arg(long, default_value_t = 20)]
+    limit: usize,
+}
+
+#[derive(Args)]
+struct DebugArgs {
+    input: PathBuf,
+    /// ROM file format (detected from extension/content by default)
+    #[arg(long, value_enum)]
+    format: Option<RomFormat>,
+    /// Symbol file written by the assembler (default: <input>.sym if present)
+    #[arg(long, value_name = "FILE")]
+    sym: Option<PathBuf>,
+    /// Set a breakpoint at a ROM address or label, optionally "LOC if EXPR" (repeatable)
+    #[arg(long, value_name = "LOC")]
+    break_at: Vec<String>,
+}
+
+#[derive(Args)]
+struct ConformanceArgs {
+    /// .asm files, .tst scripts, or directories to search for them
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// The tools directory of the nand2tetris software suite (default: $N2T_TOOLS, or the
+    /// directory on PATH with Assembler.sh)
+    #[arg(long, value_name = "DIR")]
+    tools: Option<PathBuf>,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+#[derive(Args)]
+struct FuzzArgs {
+    /// Generate only this kind of program (default: both)
+    #[arg(long, value_enum)]
+    kind: Option<Kind>,
+    /// Seed of the first program; the following programs use the next seeds (default: from the clock)
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Number of seeds to try
+    #[arg(long, default_value_t = 1000)]
+    count: u64,
+    /// Directory to save the programs that diverge to
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    save: PathBuf,
+}
+
+#[derive(Args)]
+struct GradeArgs {
+    /// Directory with one subdirectory per student
+    #[arg(long, value_name = "DIR")]
+    submissions: PathBuf,
+    /// Directory of .tst scripts with their .cmp files (e.g. the nand2tetris projects directory)
+    #[arg(long, value_name = "DIR")]
+    tests: PathBuf,
+    /// Write the report to this file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Report format [default: json for a .json output, csv otherwise]
+    #[arg(long, value_enum)]
+    format: Option<GradeFormat>,
+    /// Run this many tests at a time (default: the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    // --timeout がなければ GRADE_TIMEOUT
+    #[command(flatten)]
+    timeout: TimeoutArgs,
+}
+
+#[derive(Args)]
+struct RecordTestArgs {
+    /// An .asm or .hack file
+    program: PathBuf,
+    /// RAM cells to record (e.g. R0..R2,256..260,SCREEN)
+    #[arg(long, value_name = "CELLS")]
+    cells: String,
+    /// Set registers and RAM before the run (e.g. "RAM[0]=3, RAM[1]=5")
+    #[arg(long, value_name = "VALUES")]
+    set: Option<String>,
+    /// Record a row after every this many cycles (default: one row at the end)
+    #[arg(long, value_name = "CYCLES")]
+    every: Option<u64>,
+    /// Run this many cycles (default: until the program halts)
+    #[arg(long)]
+    cycles: Option<u64>,
+    /// Base name of the .tst, .cmp and .out files (default: the program's name)
+    #[arg(long)]
+    name: Option<String>,
+    /// Overwrite an existing .tst or .cmp file
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GradeFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Args)]
+struct TestArgs {
+    /// A .tst script, or a directory of .jack files: it is compiled, linked with the OS,
+    /// translated and assembled, then checked with its .tst scripts and the options below.
+    /// An n2t.toml or a directory with one is built and checked with its `tests`
+    script: Option<PathBuf>,
+    /// Run the program and check registers and RAM afterwards (e.g. "RAM[8000]=42, RAM[8001]=-1")
+    #[arg(long, value_name = "ASSERTIONS")]
+    assert: Option<String>,
+    /// Run the program and compare the screen afterwards with a PNG screenshot
+    #[arg(long, value_name = "FILE")]
+    expect_screen: Option<PathBuf>,
+    /// Run the Jack OS functions of these classes natively (see `run --native-os`)
+    #[arg(long, value_name = "CLASSES", num_args = 0..=1, require_equals = true, value_delimiter = ',')]
+    native_os: Option<Vec<String>>,
+    /// Stop running the program after this many cycles (the .tst scripts set their own limits)
+    #[arg(long, default_value_t = HarnessOptions::default().max_cycles)]
+    max_cycles: u64,
+    /// Write the program to this .hack file when all tests pass
+    #[arg(long, value_name = "FILE")]
+    hack: Option<PathBuf>,
+    #[command(flatten)]
+    report: ReportArgs,
+    #[command(flatten)]
+    timeout: TimeoutArgs,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// The program: a .hack or .asm file, a .vm file, or a directory of .vm or .jack files (not
+    /// needed with --load-state)
+    #[arg(required_unless_present = "load_state")]
+    input: Option<PathBuf>,
+    /// ROM file format (detected from extension/content by default)
+    #[arg(long, value_enum)]
+    format: Option<RomFormat>,
+    /// Symbol file written by the assembler (default: <input>.sym if present)
+    #[arg(long, value_name = "FILE")]
+    sym: Option<PathBuf>,
+    /// Screen and keyboard backend [default: headless]
+    #[arg(long, value_enum)]
+    screen: Option<ScreenKind>,
+    /// Character style for --screen=tty [default: braille]
+    #[arg(long, value_enum)]
+    tty_style: Option<TtyStyle>,
+    /// Maximum screen refresh rate in frames per second
+    #[arg(long, default_value_t = 30)]
+    refresh_hz: u32,
+    /// Throttle execution to this clock rate in cycles per second
+    #[arg(long, conflicts_with = "turbo")]
+    hz: Option<u64>,
+    /// Run as fast as possible (no throttling)
+    #[arg(long)]
+    turbo: bool,
+    /// Save a screenshot when the cycle count reaches this value (repeatable)
+    #[arg(long, value_name = "CYCLE")]
+    screenshot_at: Vec<u64>,
+    /// Screenshot file (.png or .ppm); {cycle} is replaced by the cycle count
+    #[arg(long, value_name = "FILE", default_value = "screen-{cycle}.png")]
+    screenshot_file: PathBuf,
+    /// Stop before executing this ROM address or label (e.g. 1234, LOOP, Main.main+3; repeatable)
+    #[arg(long, value_name = "LOC")]
+    break_at: Vec<String>,
+    /// What to do when a breakpoint is hit (default: prompt on a terminal, otherwise dump)
+    #[arg(long, value_enum)]
+    on_break: Option<OnBreak>,
+    /// Stop after this many cycles
+    #[arg(long)]
+    max_cycles: Option<u64>,
+    /// Print PC, A, D and the given RAM cells after the run (e.g. R0..R15,256..300)
+    #[arg(long, num_args = 0..=1, default_missing_value = "")]
+    dump: Option<String>,
+    /// Check registers and RAM after the run and exit non-zero on mismatch (e.g. "RAM[2]=6, RAM[0]=258")
+    #[arg(long, value_name = "SPEC")]
+    assert: Option<String>,
+    /// Write the given RAM range to a file after the run
+    #[arg(long, num_args = 2, value_names = ["RANGE", "FILE"])]
+    dump_ram: Option<Vec<String>>,
+    /// Print every executed instruction with PC, A, D and RAM writes
+    #[arg(long)]
+    trace: bool,
+    /// Write the trace to a file instead of stdout
+    #[arg(long, value_name = "FILE", requires = "trace")]
+    trace_file: Option<PathBuf>,
+    /// Only trace instructions in these ROM addresses or labels (e.g. 10..20,42,LOOP..END)
+    #[arg(long, value_name = "RANGES", requires = "trace")]
+    trace_pc: Option<String>,
+    /// Print cycles, calls and inclusive/exclusive counts per function after the run
+    #[arg(long)]
+    profile: bool,
+    /// Write cycles, the per-function profile, coverage and memory statistics to a file (.json or .csv)
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+    /// Write the profile as folded stacks for inferno/flamegraph.pl
+    #[arg(long, value_name = "FILE")]
+    flamegraph: Option<PathBuf>,
+    /// Write executed-instruction counts per source line to a file (LCOV for .info/.lcov, annotated text otherwise)
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+    /// Write RAM read/write counts per address to a file (.csv, or .png for a heatmap image; repeatable)
+    #[arg(long, value_name = "FILE")]
+    heatmap: Vec<PathBuf>,
+    /// Stop with an error when SP leaves the VM stack (256..2048) after it has been set up
+    #[arg(long)]
+    check_stack: bool,
+    /// Warn (once per address, with PC and symbol) when a RAM cell is read before it was ever written
+    #[arg(long, conflicts_with = "load_state")]
+    check_uninit: bool,
+    /// Track Memory.alloc/deAlloc calls and report leaks, double frees and out-of-block heap accesses
+    #[arg(long)]
+    check_heap: bool,
+    /// Run the Jack OS functions of these classes (default: Math, Memory, Array, String, Screen,
+    /// Output, Keyboard) natively instead of emulating their VM code
+    #[arg(long, value_name = "CLASSES", num_args = 0..=1, require_equals = true, value_delimiter = ',')]
+    native_os: Option<Vec<String>>,
+    /// Record keyboard events with cycle timestamps to a file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay_input")]
+    record_input: Option<PathBuf>,
+    /// Replay keyboard events recorded with --record-input
+    #[arg(long, value_name = "FILE")]
+    replay_input: Option<PathBuf>,
+    /// Resume from a saved machine state instead of loading a ROM file
+    #[arg(long, value_name = "FILE", conflicts_with = "input")]
+    load_state: Option<PathBuf>,
+    /// Save RAM, ROM, registers and cycle count to a file after the run
+    #[arg(long, value_name = "FILE")]
+    save_state: Option<PathBuf>,
+    /// Write the loaded ROM to a file after the run
+    #[arg(long, value_name = "FILE")]
+    dump_rom: Option<PathBuf>,
+    /// Number format for --dump-ram and --dump-rom
+    #[arg(long, value_enum, default_value = "dec")]
+    dump_radix: Radix,
+    /// TOML file remapping host keys to Hack keyboard codes
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+}
+
+// 設定ファイルの [emu]。フラグのないものだけに使う
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    screen: Option<ScreenKind>,
+    tty_style: Option<TtyStyle>,
+    keymap: Option<PathBuf>,
+    optimize: Option<u8>,
+}
+
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    set_quiet(cli.quiet);
+    set_color(cli.color);
+
+    finish(
+        apply_config(&mut cli.command).and_then(|()| match cli.command {
+            Command::Run(args) => run(&args),
+            Command::Build(args) => build(&args),
+            Command::Test(args) => test(&args),
+            Command::Debug(args) => debug(&args),
+            Command::Dap => {
+                dap::serve(Box::new(io::BufReader::new(io::stdin())), &mut io::stdout())
+            }
+            Command::CompareRun(args) => compare_run(&args),
+            Command::Conformance(args) => conformance(&args),
+            Command::Fuzz(args) => fuzz(&args),
+            Command::Grade(args) => grade(&args),
+            Command::RecordTest(args) => record_test(&args),
+            Command::Repl(args) => repl(&args),
+            Command::Serve(args) => serve(&args),
+        }),
+    )
+}
+
+fn apply_config(command: &mut Command) -> Result<()> {
+    let defaults: Defaults = Config::load()?.section("emu")?;
+    match command {
+        Command::Run(args) => {
+            args.screen = args.screen.or(defaults.screen);
+            args.tty_style = args.tty_style.or(defaults.tty_style);
+            args.keymap = args
+                .keymap
+                .take()
+                .or(defaults.keymap.map(|path| expand_home(&path)));
+        }
+        Command::Build(args) => args.config_optimize = defaults.optimize,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn run(args: &RunArgs) -> Result<()> {
+    let screen = args.screen.unwrap_or(ScreenKind::Headless);
+    let dump_ranges = args.dump.as_deref().map(dump::parse_ranges).transpose()?;
+    let assertions = args.assert.as_deref().map(parse_assertions).transpose()?;
+    let dump_ram = match args.dump_ram.as_deref() {
+        Some([range, file]) => Some((dump::parse_ranges(range)?, PathBuf::from(file))),
+        _ => None,
+    };
+    let mut symbols = Symbols::default();
+    let mut source_files = Vec::new();
+    let mut program_len = None;
+    let (mut cpu, input) = match (&args.load_state, &args.input) {
+        (Some(state), _) => (Snapshot::load(state)?.restore()?, state),
+        (None, Some(input)) => {
+            let native = match &args.native_os {
+                Some(classes) => native_functions(classes)?,
+                None => Vec::new(),
+            };
+            let program = loader::load_program_with(input, args.format, &native)?;
+            symbols = program.symbols;
+            source_files = program.source_files;
+            program_len = Some(program.words.len());
+            (Cpu::new(&program.words)?, input)
+        }
+        (None, None) => unreachable!("clap requires input or --load-state"),
+    };
+    if let Some(file) = &args.sym {
+        symbols = Symbols::load(file)?;
+    }
+    cpu.sys_halt = symbols.resolve("Sys.halt");
+    if let Some(classes) = &args.native_os {
+        cpu.native = Some(Box::new(NativeOs::new(&symbols, classes)?));
+    }
+    if args.record_input.is_some() {
+        cpu.input = InputMode::Record(InputLog::default());
+    } else if let Some(file) = &args.replay_input {
+        cpu.input = InputMode::replay(InputLog::load(file)?);
+    }
+
+    // tty 画面は端末を占有するのでプロンプトを出せない
+    let on_break =
+        args.on_break
+            .unwrap_or(if io::stdin().is_terminal() && screen != ScreenKind::Tty {
+                OnBreak::Prompt
+            } else {
+                OnBreak::Dump
+            });
+    let mut debugger = Debugger::new(symbols.clone()).with_on_break(on_break);
+    for location in &args.break_at {
+        debugger.add_breakpoint(location)?;
+    }
+
+    let options = ScreenOptions {
+        tty_style: args.tty_style.unwrap_or(TtyStyle::Braille),
+        refresh_hz: args.refresh_hz,
+        keymap: match &args.keymap {
+            Some(path) => KeyMap::load(path)?,
+            None => KeyMap::default(),
+        },
+    };
+    let mut backend = screen::create_backend(screen, &options)?;
+
+    let run_options = RunOptions {
+        max_cycles: args.max_cycles,
+        hz: if args.turbo { None } else { args.hz },
+        screenshot_at: args.screenshot_at.clone(),
+        screenshot_file: args.screenshot_file.clone(),
+        ..RunOptions::default()
+    };
+    let mut tracer = if args.trace {
+        let out: Box<dyn Write> = match &args.trace_file {
+            Some(file) => Box::new(BufWriter::new(
+                File::create(file).context(format!("Failed to create '{}'", file.display()))?,
+            )),
+            None => Box::new(BufWriter::new(io::stdout())),
+        };
+        let filter = match &args.trace_pc {
+            Some(spec) => dump::parse_ranges_with(spec, |text| debugger.resolve(text))?,
+            None => Vec::new(),
+        };
+        Some(Tracer::new(out, filter).with_symbols(symbols.clone()))
+    } else {
+        None
+    };
+    // --report はすべての集計を使う
+    let report = args.report.is_some();
+    let mut profiler =
+        (args.profile || args.flamegraph.is_some() || report).then(|| Profiler::new(&symbols));
+    // 保存した状態から再開したときは末尾の 0 をプログラムに含めない
+    let program_len = program_len.unwrap_or_else(|| {
+        cpu.rom
+            .iter()
+            .rposition(|&word| word != 0)
+            .map_or(0, |i| i + 1)
+    });
+    let mut coverage =
+        (args.coverage.is_some() || report).then(|| Coverage::new(&cpu.rom[..program_len]));
+    let mut heatmap = (!args.heatmap.is_empty() || report).then(MemoryHeatmap::new);
+    let mut stack_guard = args.check_stack.then(|| StackGuard::new(symbols.clone()));
+    let mut uninit_checker = args
+        .check_uninit
+        .then(|| UninitChecker::new(io::stderr(), symbols.clone()));
+    let mut heap_checker = args
+        .check_heap
+        .then(|| HeapChecker::new(symbols.clone()))
+        .transpose()?;
+    let mut hooks: Vec<&mut dyn StepHook> = Vec::new();
+    if let Some(tracer) = tracer.as_mut() {
+        hooks.push(tracer);
+    }
+    if let Some(profiler) = profiler.as_mut() {
+        hooks.push(profiler);
+    }
+    if let Some(coverage) = coverage.as_mut() {
+        hooks.push(coverage);
+    }
+    if let Some(heatmap) = heatmap.as_mut() {
+        hooks.push(heatmap);
+    }
+    if let Some(stack_guard) = stack_guard.as_mut() {
+        hooks.push(stack_guard);
+    }
+    if let Some(uninit_checker) = uninit_checker.as_mut() {
+        hooks.push(uninit_checker);
+    }
+    if let Some(heap_checker) = heap_checker.as_mut() {
+        hooks.push(heap_checker);
+    }
+
+    let reason = debugger
+        .run(
+            &mut cpu,
+            backend.as_mut(),
+            &run_options,
+            &mut hooks,
+            &mut io::stdin().lock(),
+            &mut io::stdout(),
+        )
+        .inspect_err(|_| print_call_stack(&cpu, &symbols))?;
+    drop(hooks);
+    if let Some(tracer) = tracer {
+        tracer.into_inner().flush()?;
+    }
+
+    // 画面付きで停止した場合は閉じられるまで表示を残す
+    if reason == StopReason::Halted && screen != ScreenKind::Headless {
+        while backend.is_open() {
+            cpu.sync_io(backend.as_mut())?;
+            thread::sleep(Duration::from_millis(30));
+        }
+    }
+    drop(backend);
+
+    status!(
+        "Execution {}: {} ({} cycles)",
+        status(reason),
+        input.display(),
+        cpu.cycles
+    );
+
+    if let Some(ranges) = &dump_ranges {
+        print!("{}", dump::format_dump(&cpu, ranges));
+    }
+    if let Some(profiler) = &profiler {
+        if args.profile {
+            print!("{}", profiler.report());
+        }
+        if let Some(file) = &args.flamegraph {
+            fs::write(file, profiler.folded())
+                .context(format!("Failed to write '{}'", file.display()))?;
+        }
+    }
+    if let Some(heap_checker) = &heap_checker {
+        print!("{}", heap_checker.report());
+    }
+    if let (Some(file), Some(coverage)) = (&args.coverage, &coverage) {
+        coverage.save(file, &symbols, &source_files, input)?;
+    }
+    if let Some(heatmap) = &heatmap {
+        for file in &args.heatmap {
+            heatmap.save(file, &symbols)?;
+        }
+    }
+    if let (Some(file), Some(profiler), Some(coverage), Some(heatmap)) =
+        (&args.report, &profiler, &coverage, &heatmap)
+    {
+        RunReport::new(
+            input,
+            status(reason),
+            cpu.cycles,
+            profiler,
+            coverage,
+            heatmap,
+            &symbols,
+        )
+        .save(file)?;
+    }
+    if let Some((ranges, file)) = &dump_ram {
+        dump::write_ram(file, &cpu, ranges, args.dump_radix)?;
+    }
+    if let (Some(file), Some(log)) = (&args.record_input, cpu.input.recorded()) {
+        log.save(file)?;
+    }
+    if let Some(file) = &args.save_state {
+        Snapshot::capture(&cpu).save(file)?;
+    }
+    if let Some(file) = &args.dump_rom {
+        dump::write_rom(file, &cpu, args.dump_radix)?;
+    }
+    if let Some(assertions) = &assertions {
+        check_assertions(&cpu, assertions).inspect_err(|_| print_call_stack(&cpu, &symbols))?;
+        status!("All {} assertions passed", assertions.len());
+    }
+    Ok(())
+}
+
+// VM の関数内で止まったときだけ表示する
+fn print_call_stack(cpu: &Cpu, symbols: &Symbols) {
+    if Frame::current(cpu).is_some() {
+        eprint!("Call stack:\n{}", format_backtrace(cpu, symbols));
+    }
+}
+
+fn status(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::Finished => "completed",
+        StopReason::Halted => "halted",
+        StopReason::MaxCycles => "stopped at max cycles",
+        StopReason::Closed => "interrupted",
+        StopReason::Breakpoint => "stopped at breakpoint",
+    }
+}
+
+fn debug(args: &DebugArgs) -> Result<()> {
+    let program = loader::load_program(&args.input, args.format)?;
+    let mut cpu = Cpu::new(&program.words)?;
+    let symbols = match &args.sym {
+        Some(file) => Symbols::load(file)?,
+        None => program.symbols,
+    };
+    cpu.sys_halt = symbols.resolve("Sys.halt");
+    let mut debugger = Debugger::new(symbols);
+    for location in &args.break_at {
+        debugger.add_breakpoint(location)?;
+    }
+
+    println!(
+        "Loaded {} ({} words). Type 'help' for commands.",
+        args.input.display(),
+        cpu.program_len()
+    );
+    let mut input = io::stdin().lock();
+    let mut out = io::stdout();
+    loop {
+        if debugger.prompt(&mut cpu, &mut input, &mut out)? == Action::Quit {
+            return Ok(());
+        }
+        let reason = debugger.run(
+            &mut cpu,
+            &mut Headless,
+            &RunOptions::default(),
+            &mut [],
+            &mut input,
+            &mut out,
+        )?;
+        if reason == StopReason::Breakpoint {
+            return Ok(());
+        }
+        println!("Execution {} ({} cycles)", status(reason), cpu.cycles);
+    }
+}
+
+fn compare_run(args: &CompareRunArgs) -> Result<()> {
+    let ranges = match &args.ranges {
+        Some(spec) => dump::parse_ranges(spec)?,
+        None => Vec::new(),
+    };
+    let replay = args
+        .replay_input
+        .as_deref()
+        .map(InputLog::load)
+        .transpose()?;
+    let options = RunOptions {
+        max_cycles: Some(args.max_cycles),
+        ..RunOptions::default()
+    };
+
+    let run_one = |path: &PathBuf| -> Result<Cpu> {
+        let program = loader::load_program(path, None)?;
+        let mut cpu = Cpu::new(&program.words)?;
+        if let Some(log) = &replay {
+            cpu.input = InputMode::replay(log.clone());
+        }
+        let reason = cpu
+            .run_with_backend(&mut Headless, &options, &mut [])
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        status!(
+            "{}: {} ({} cycles)",
+            path.display(),
+            status(reason),
+            cpu.cycles
+        );
+        Ok(cpu)
+    };
+    let left = run_one(&args.left)?;
+    let right = run_one(&args.right)?;
+
+    let differences = compare::diff_ram(&left, &right, &ranges);
+    if !differences.is_empty() {
+        print!("{}", compare::format_differences(&differences, args.limit));
+        return Err(Failure::Test.wrap(anyhow!("{} RAM words differ", differences.len())));
+    }
+    status!("RAM matches");
+    Ok(())
+}
+
+// 入力がなければカレントディレクトリから n2t.toml を探す
+fn find_project(input: Option<&Path>) -> Result<Option<Project>> {
+    match input {
+        Some(input) => manifest_path(input)
+            .map(|path| Project::load(&path))
+            .transpose(),
+        None => Project::find()?
+            .context(format!(
+                "No input given and no {} in the current directory or its parents",
+                MANIFEST
+            ))
+            .map(Some),
+    }
+}
+
+// 途中のファイルは書く前にすべて作っておくので、エラーがあれば何も書かない
+fn build(args: &BuildArgs) -> Result<()> {
+    // フラグはマニフェストの設定より優先する
+    let (input, build, output, intermediate, sym) = match find_project(args.input.as_deref())? {
+        Some(mut project) => {
+            if let Some(level) = args.optimize {
+                project.manifest.optimize = level;
+            }
+            if args.no_cache {
+                project.manifest.cache = false;
+            }
+            let output = match &args.output {
+                Some(output) => output.clone(),
+                None => project.output()?,
+            };
+            let manifest = &project.manifest;
+            (
+                project.dir.join(MANIFEST),
+                project.build(&[])?,
+                output,
+                args.intermediate || manifest.intermediate,
+                args.sym || manifest.sym,
+            )
+        }
+        None => {
+            // find_project は入力がないときは None を返さない
+            let input = args.input.clone().unwrap_or_default();
+            let output = match &args.output {
+                Some(output) => output.clone(),
+                None => VMTranslator::output_path(&input)?.with_extension("hack"),
+            };
+            let options = BuildOptions {
+                optimize: args.optimize.or(args.config_optimize).unwrap_or(0),
+                cache: (!args.no_cache).then(|| output.with_file_name(CACHE_DIR)),
+                ..BuildOptions::default()
+            };
+            let build = loader::build_jack_files(&input, &jack_files(&input)?, &[], &options)?;
+            (input, build, output, args.intermediate, args.sym)
+        }
+    };
+    let write = |path: &Path, text: &str| -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).context(format!("Failed to create '{}'", dir.display()))?;
+        }
+        fs::write(path, text).context(format!("Failed to write '{}'", path.display()))
+    };
+    if intermediate {
+        for (file, code, map) in &build.classes {
+            let vm = file.with_extension("vm");
+            write(&vm, code)?;
+            write(&map_path(&vm), &map.to_json())?;
+        }
+        write(&output.with_extension("asm"), &build.asm)?;
+    }
+    if sym {
+        write(
+            &output.with_extension("sym"),
+            &build.program.symbols.format(),
+        )?;
+    }
+    let words = &build.program.words;
+    let text: String = words
+        .iter()
+        .map(|word| format!("{:016b}\n", word))
+        .collect();
+    write(&output, &text)?;
+    match build.cache {
+        Some(stats) => status!(
+            "{} -> {} ({} words, {} of {} stages cached)",
+            input.display(),
+            output.display(),
+            words.len(),
+            stats.hits,
+            stats.hits + stats.misses
+        ),
+        None => status!(
+            "{} -> {} ({} words)",
+            input.display(),
+            output.display(),
+            words.len()
+        ),
+    }
+    Ok(())
+}
+
+fn test(args: &TestArgs) -> Result<()> {
+    if let Some(project) = find_project(args.script.as_deref())? {
+        return test_project(args, &project);
+    }
+    // find_project は入力がないときは None を返さない
+    let script = args.script.clone().unwrap_or_default();
+    if let Some(projects) = suite::projects_dir(&script) {
+        return test_suite(args, &projects);
+    }
+    if script.is_dir() {
+        return test_jack(args, &script);
+    }
+    let start = Instant::now();
+    let result = args
+        .timeout
+        .budget()
+        .run(|| suite::run_script(&script, false));
+    args.report.write(&[TestCase {
+        suite: file_name(script.parent().unwrap_or(Path::new(""))),
+        name: file_name(&script),
+        duration: start.elapsed(),
+        failure: result.as_ref().err().map(|e| e.to_string()),
+    }])?;
+    match result? {
+        Comparison::Passed => status!("End of script - Comparison ended successfully"),
+        Comparison::Skipped | Comparison::Failed(_) => status!("End of script"),
+    }
+    Ok(())
+}
+
+fn conformance(args: &ConformanceArgs) -> Result<()> {
+    let tools = Tools::find(args.tools.as_deref())?;
+    let inputs = conformance::inputs(&args.inputs)?;
+    let mut divergences = Vec::new();
+    let mut cases = Vec::new();
+    for input in &inputs {
+        let start = Instant::now();
+        let divergence = conformance::check(&tools, input)?;
+        match &divergence {
+            None => status!("SAME {}", input.display()),
+            Some(message) => {
+                status!("DIFF {}: {}", input.display(), message);
+                divergences.push(format!("{}: {}", input.display(), message));
+            }
+        }
+        cases.push(TestCase {
+            suite: file_name(input.parent().unwrap_or(Path::new(""))),
+            name: file_name(input),
+            duration: start.elapsed(),
+            failure: divergence,
+        });
+    }
+    args.report.write(&cases)?;
+    if !divergences.is_empty() {
+        return Err(Failure::Test.wrap(anyhow!(
+            "{} of {} inputs differ from the official tools\n{}",
+            divergences.len(),
+            inputs.len(),
+            divergences.join("\n")
+        )));
+    }
+    status!("{} inputs, no differences", inputs.len());
+    Ok(())
+}
+
+fn fuzz(args: &FuzzArgs) -> Result<()> {
+    let kinds = match args.kind {
+        Some(kind) => vec![kind],
+        None => vec![Kind::Vm, Kind::Asm],
+    };
+    let start = match args.seed {
+        Some(seed) => seed,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs()),
+    };
+    let seeds = start..start.saturating_add(args.count);
+    status!("Seeds {}..{}", seeds.start, seeds.end);
+
+    let mut divergences = Vec::new();
+    for seed in seeds.clone() {
+        for &kind in &kinds {
+            let case = Case::generate(kind, seed);
+            let Some(message) = case.check().context(format!("{} seed {}", kind, seed))? else {
+                continue;
+            };
+            let saved = case.save(&args.save)?;
+            status!(
+                "DIFF {} seed {}: {} (saved to '{}')",
+                kind,
+                seed,
+                message,
+                saved.display()
+            );
+            divergences.push(format!(
+                "{} seed {}: {}\n  reproduce with: n2t fuzz --kind {} --seed {} --count 1",
+                kind, seed, message, kind, seed
+            ));
+        }
+    }
+    let total = (seeds.end - seeds.start) * kinds.len() as u64;
+    if !divergences.is_empty() {
+        return Err(Failure::Test.wrap(anyhow!(
+            "{} of {} generated programs diverge\n{}",
+            divergences.len(),
+            total,
+            divergences.join("\n")
+        )));
+    }
+    status!("{} generated programs, no differences", total);
+    Ok(())
+}
+
+// grade の --timeout の既定値。暴走した提出物で採点が止まらないように
+const GRADE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn grade(args: &GradeArgs) -> Result<()> {
+    let students = grade::submissions(&args.submissions)?;
+    let tests = grade::tests(&args.tests)?;
+    if tests.is_empty() {
+        return Err(anyhow!(
+            "No .tst files with a .cmp file found in '{}'",
+            args.tests.display()
+        ));
+    }
+    let exe = std::env::current_exe().context("Failed to find the n2t executable")?;
+    let grader = Grader {
+        command: [
+            exe.to_string_lossy().as_ref(),
+            "test",
+            "--quiet",
+            "--color=never",
+        ]
+        .map(String::from)
+        .to_vec(),
+        timeout: args.timeout.timeout.unwrap_or(GRADE_TIMEOUT),
+        total_timeout: args.timeout.total_timeout,
+        jobs: args.jobs.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|jobs| jobs.get())
+                .unwrap_or(1)
+        }),
+    };
+    let report = grader.grade(&students, &args.tests, &tests);
+
+    let json = args
+        .output
+        .as_ref()
+        .is_some_and(|output| output.extension().is_some_and(|ext| ext == "json"));
+    let text = match args.format {
+        Some(GradeFormat::Json) => report.to_json()?,
+        None if json => report.to_json()?,
+        Some(GradeFormat::Csv) | None => report.to_csv(),
+    };
+    match &args.output {
+        Some(output) => {
+            fs::write(output, text).context(format!("Failed to write '{}'", output.display()))?;
+            for (student, passed, total) in report.scores() {
+                status!("{}: {}/{}", student, passed, total);
+            }
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn record_test(args: &RecordTestArgs) -> Result<()> {
+    record::check_program(&args.program)?;
+    let cells: Vec<u16> = dump::parse_ranges(&args.cells)?
+        .into_iter()
+        .flatten()
+        .collect();
+    let set = args
+        .set
+        .as_deref()
+        .map(parse_assertions)
+        .transpose()?
+        .unwrap_or_default();
+    if args.every == Some(0) {
+        return Err(anyhow!("--every must be at least 1"));
+    }
+    let cycles = match args.cycles {
+        Some(cycles) => cycles,
+        None => record::cycles_to_halt(&args.program, &set)?,
+    };
+    let dir = args.program.parent().unwrap_or(Path::new(""));
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None => file_name(&args.program.with_extension("")),
+    };
+    let script = dir.join(format!("{}.tst", name));
+    let compare = dir.join(format!("{}.cmp", name));
+    if !args.force
+        && let Some(existing) = [&script, &compare].into_iter().find(|path| path.exists())
+    {
+        return Err(anyhow!(
+            "'{}' already exists (use --force to overwrite it)",
+            existing.display()
+        ));
+    }
+
+    let recording = Recording {
+        program: PathBuf::from(file_name(&args.program)),
+        cells,
+        set,
+        every: args.every,
+        cycles,
+    };
+    let (script_text, compare_text) = recording.record(dir, &name)?;
+    fs::write(&script, script_text).context(format!("Failed to write '{}'", script.display()))?;
+    fs::write(&compare, &compare_text)
+        .context(format!("Failed to write '{}'", compare.display()))?;
+    let rows = compare_text.lines().count().saturating_sub(1);
+    status!(
+        "{} -> {}, {} ({} cycles, {} row{})",
+        args.program.display(),
+        script.display(),
+        compare.display(),
+        cycles,
+        rows,
+        if rows == 1 { "" } else { "s" }
+    );
+    Ok(())
+}
+
+// 公式のテストスクリプトを全部実行し、プロジェクトごとの表を出す
+fn test_suite(args: &TestArgs, projects: &Path) -> Result<()> {
+    let tests = suite::discover(projects)?;
+    let mut matrix = Matrix::default();
+    let mut failures = Vec::new();
+    let mut cases = Vec::new();
+    let budget = args.timeout.budget();
+    for test in &tests {
+        let name = test.script.strip_prefix(projects).unwrap_or(&test.script);
+        let start = Instant::now();
+        let result = budget.run(|| suite::run(test));
+        cases.push(TestCase {
+            suite: test.project.clone(),
+            // プロジェクトのディレクトリから
+            name: name
+                .components()
+                .skip(1)
+                .collect::<PathBuf>()
+                .display()
+                .to_string(),
+            duration: start.elapsed(),
+            failure: result.as_ref().err().map(|e| e.to_string()),
+        });
+        match result {
+            Ok(()) => {
+                status!("PASS {}", name.display());
+                matrix.add(test, true);
+            }
+            Err(e) => {
+                let label = if is_timeout(&e) { "TIMEOUT" } else { "FAIL" };
+                status!("{} {}: {}", label, name.display(), e);
+                failures.push(format!("{}: {}", name.display(), e));
+                matrix.add(test, false);
+            }
+        }
+    }
+    if !args.report.to_stdout() {
+        print!("{}", matrix.format());
+    }
+    args.report.write(&cases)?;
+    if !failures.is_empty() {
+        return Err(Failure::Test.wrap(anyhow!(
+            "{} of {} tests failed\n{}",
+            failures.len(),
+            tests.len(),
+            failures.join("\n")
+        )));
+    }
+    Ok(())
+}
+
+// JUnit の testsuite の名前
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn harness_options(args: &TestArgs) -> Result<HarnessOptions> {
+    Ok(HarnessOptions {
+        native_os: args.native_os.clone(),
+        max_cycles: args.max_cycles,
+        assertions: args
+            .assert
+            .as_deref()
+            .map(parse_assertions)
+            .transpose()?
+            .unwrap_or_default(),
+        expect_screen: args.expect_screen.clone(),
+        budget: args.timeout.budget(),
+    })
+}
+
+// .jack からテスト済みの .hack まで
+fn test_jack(args: &TestArgs, dir: &Path) -> Result<()> {
+    let harness = Harness::build(dir, harness_options(args)?)?;
+    status!(
+        "Built {} ({} words)",
+        dir.display(),
+        harness.program().words.len()
+    );
+    let results = harness.run()?;
+    report_tests(args, dir, &harness, &results)
+}
+
+// n2t.toml の設定でビルドし、tests の .tst で確かめる
+fn test_project(args: &TestArgs, project: &Project) -> Result<()> {
+    let options = harness_options(args)?;
+    let native = match &options.native_os {
+        Some(classes) => native_functions(classes)?,
+        None => Vec::new(),
+    };
+    let program = project.build(&native)?.program;
+    status!(
+        "Built {} ({} words)",
+        project.dir.display(),
+        program.words.len()
+    );
+    let harness = Harness::new(&project.dir, program, options);
+    let results = harness.run_scripts(&project.tests()?);
+    report_tests(args, &project.dir, &harness, &results)
+}
+
+fn report_tests(
+    args: &TestArgs,
+    input: &Path,
+    harness: &Harness,
+    results: &[TestResult],
+) -> Result<()> {
+    let cases: Vec<TestCase> = results
+        .iter()
+        .map(|result| TestCase {
+            suite: file_name(input),
+            name: result.name.clone(),
+            duration: result.duration,
+            failure: result.error.clone(),
+        })
+        .collect();
+    args.report.write(&cases)?;
+    for result in results {
+        match &result.error {
+            None => status!("PASS {}", result.name),
+            Some(error) => status!("FAIL {}: {}", result.name, error),
+        }
+    }
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    status!("{} passed, {} failed", results.len() - failed, failed);
+    if failed > 0 {
+        // --quiet では PASS/FAIL の行を出さないので、失敗したものはエラーに含める
+        let failures: Vec<String> = results
+            .iter()
+            .filter_map(|r| r.error.as_ref().map(|e| format!("{}: {}", r.name, e)))
+            .collect();
+        return Err(Failure::Test.wrap(anyhow!(
+            "{} of {} tests failed\n{}",
+            failed,
+            results.len(),
+            failures.join("\n")
+        )));
+    }
+
+    if let Some(file) = &args.hack {
+        let text: String = harness
+            .program()
+            .words
+            .iter()
+            .map(|word| format!("{:016b}\n", word))
+            .collect();
+        fs::write(file, text).context(format!("Failed to write '{}'", file.display()))?;
+        status!("{} -> {}", input.display(), file.display());
+    }
+    Ok(())
+}
+
+fn repl(args: &ReplArgs) -> Result<()> {
+    let options = ReplOptions {
+        native_os: args.native_os.clone(),
+        max_cycles: args.max_cycles,
+    };
+    let mut repl = Repl::new(args.project.as_deref(), options)?;
+    repl.run(&mut io::stdin().lock(), &mut io::stdout())
+}
+
+fn serve(args: &ServeArgs) -> Result<()> {
+    let options = ServeOptions {
+        max_cycles: args.max_cycles,
+        budget: TimeoutArgs {
+            timeout: Some(args.timeout),
+            total_timeout: None,
+        }
+        .budget(),
+        max_body: args.max_body,
+    };
+    let jobs = args.jobs.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|jobs| jobs.get())
+            .unwrap_or(1)
+    });
+    serve::serve(&args.addr, jobs, &options)
+}
@@ -0,0 +1,285 @@
+// n2t.toml。プロジェクトのディレクトリに置くと `build` と `test` が引数なしで動く
+//
+// パスはマニフェストのディレクトリからの相対パス
+//
+// target = "jack"             # jack, vm か asm
+// sources = ["src"]
+// optimize = 1
+// output = "build/Pong.hack"
+// intermediate = true
+// sym = true
+// cache = false
+// tests = ["test"]
+//
+// [symbols]
+// LED = 24577
+
+use anyhow::{Context, Result, bail};
+use nand2tetris_jack::jack_files;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::CACHE_DIR,
+    loader::{Build, BuildOptions, build_asm, build_jack_files, build_vm_files, vm_files},
+};
+
+pub const MANIFEST: &str = "n2t.toml";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    #[default]
+    Jack,
+    Vm,
+    Asm,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    // ソースの種類
+    #[serde(default)]
+    pub target: Target,
+    // ソースのディレクトリかファイル（既定はマニフェストのディレクトリ）
+    #[serde(default = "default_sources")]
+    pub sources: Vec<PathBuf>,
+    // Jack コンパイラの最適化レベル（-O と同じ）
+    #[serde(default)]
+    pub optimize: u8,
+    // .hack の出力先（既定は <dir>/<dir>.hack）
+    pub output: Option<PathBuf>,
+    // --intermediate と同じ
+    #[serde(default)]
+    pub intermediate: bool,
+    // --sym と同じ
+    #[serde(default)]
+    pub sym: bool,
+    // false なら --no-cache と同じ
+    #[serde(default = "default_cache")]
+    pub cache: bool,
+    // アセンブラの定義済みシンボルに加えるもの
+    #[serde(default)]
+    pub symbols: BTreeMap<String, u16>,
+    // .tst ファイルか、.tst のあるディレクトリ（既定はソースのディレクトリ）
+    #[serde(default)]
+    pub tests: Vec<PathBuf>,
+}
+
+fn default_sources() -> Vec<PathBuf> {
+    vec![PathBuf::from(".")]
+}
+
+fn default_cache() -> bool {
+    true
+}
+
+pub struct Project {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+
+// path が n2t.toml か、n2t.toml のあるディレクトリならマニフェストのパス
+pub fn manifest_path(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        Some(path.join(MANIFEST)).filter(|manifest| manifest.is_file())
+    } else {
+        path.file_name()
+            .is_some_and(|name| name == MANIFEST)
+            .then(|| path.to_path_buf())
+    }
+}
+
+impl Project {
+    // カレントディレクトリから親へたどって n2t.toml を探す
+    pub fn find() -> Result<Option<Self>> {
+        let cwd = env::current_dir().context("Failed to get the current directory")?;
+        for dir in cwd.ancestors() {
+            if let Some(path) = manifest_path(dir) {
+                return Self::load(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read manifest '{}'", path.display()))?;
+        let manifest: Manifest =
+            toml::from_str(&text).context(format!("Invalid manifest '{}'", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        // 既定の出力先にディレクトリの名前を使うので絶対パスにする
+        let dir = fs::canonicalize(if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        })
+        .context(format!("Failed to read directory '{}'", dir.display()))?;
+        Ok(Project { dir, manifest })
+    }
+
+    pub fn output(&self) -> Result<PathBuf> {
+        match &self.manifest.output {
+            Some(output) => Ok(self.dir.join(output)),
+            // dir が "." などでも名前が取れるよう絶対パスから
+            None => {
+                let dir = fs::canonicalize(&self.dir)
+                    .context(format!("Failed to read directory '{}'", self.dir.display()))?;
+                let name = dir.file_name().context("Invalid directory name")?;
+                Ok(self.dir.join(name).with_extension("hack"))
+            }
+        }
+    }
+
+    pub fn options(&self) -> BuildOptions {
+        BuildOptions {
+            optimize: self.manifest.optimize,
+            symbols: self.manifest.symbols.clone().into_iter().collect(),
+            cache: self.manifest.cache.then(|| self.dir.join(CACHE_DIR)),
+        }
+    }
+
+    // sources の中の target のファイル。ファイル名順で、同じファイルは 1 回だけ
+    pub fn source_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for source in &self.manifest.sources {
+            let path = self.dir.join(source);
+            let found = match self.manifest.target {
+                Target::Jack => jack_files(&path)?,
+                Target::Vm => vm_files(&path)?,
+                Target::Asm if path.is_dir() => asm_files(&path)?,
+                Target::Asm => vec![path],
+            };
+            for file in found {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn build(&self, native: &[&str]) -> Result<Build> {
+        let files = self.source_files()?;
+        let options = self.options();
+        match self.manifest.target {
+            Target::Jack => build_jack_files(&self.dir, &files, native, &options),
+            Target::Vm => build_vm_files(&self.dir, &files, native, &options),
+            Target::Asm => match files.as_slice() {
+                [file] => build_asm(file, &options),
+                _ => bail!(
+                    "An asm project needs exactly one .asm file, but '{}' has {}",
+                    self.dir.display(),
+                    files.len()
+                ),
+            },
+        }
+    }
+
+    // テストの .tst ファイル。名前順
+    pub fn tests(&self) -> Result<Vec<PathBuf>> {
+        let paths = if self.manifest.tests.is_empty() {
+            &self.manifest.sources
+        } else {
+            &self.manifest.tests
+        };
+        let mut scripts = Vec::new();
+        for path in paths {
+            let path = self.dir.join(path);
+            if !path.is_dir() {
+                scripts.push(path);
+                continue;
+            }
+            let mut found: Vec<PathBuf> = fs::read_dir(&path)
+                .context(format!("Failed to read directory '{}'", path.display()))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "tst"))
+                .collect();
+            found.sort();
+            scripts.extend(found);
+        }
+        Ok(scripts)
+    }
+}
+
+fn asm_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .context(format!("Failed to read directory '{}'", path.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "asm"))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, manifest: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("emu-manifest-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join(MANIFEST), manifest).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest: Manifest = toml::from_str(
+            "target = \"vm\"\nsources = [\"src\"]\noptimize = 1\ntests = [\"test/Main.tst\"]\n\
+             [symbols]\nLED = 24577\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.target, Target::Vm);
+        assert_eq!(manifest.sources, [PathBuf::from("src")]);
+        assert_eq!(manifest.optimize, 1);
+        assert_eq!(manifest.symbols["LED"], 24577);
+        assert_eq!(manifest.tests, [PathBuf::from("test/Main.tst")]);
+
+        let manifest: Manifest = toml::from_str("").unwrap();
+        assert_eq!(manifest.target, Target::Jack);
+        assert_eq!(manifest.sources, [PathBuf::from(".")]);
+        assert!(toml::from_str::<Manifest>("target = \"hdl\"").is_err());
+        assert!(toml::from_str::<Manifest>("optimise = 1").is_err());
+    }
+
+    #[test]
+    fn test_build_asm_project() {
+        let dir = project(
+            "asm",
+            "target = \"asm\"\nsources = [\"src\"]\n[symbols]\nLED = 24577\n",
+        );
+        fs::write(dir.join("src/Blink.asm"), "@LED\nM=1\n").unwrap();
+        fs::write(dir.join("src/Blink.tst"), "").unwrap();
+        let project = Project::load(&manifest_path(&dir).unwrap()).unwrap();
+        let build = project.build(&[]);
+        let output = project.output();
+        let tests = project.tests();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(build.unwrap().program.words[0], 24577);
+        assert_eq!(
+            output.unwrap(),
+            project
+                .dir
+                .join(format!("emu-manifest-asm-{}.hack", std::process::id()))
+        );
+        assert_eq!(tests.unwrap(), [project.dir.join("src/Blink.tst")]);
+
+        // dir が "." でもディレクトリの名前を使う（テストはクレートのディレクトリで動く）
+        let project = Project {
+            dir: PathBuf::from("."),
+            manifest: toml::from_str("").unwrap(),
+        };
+        assert_eq!(
+            project.output().unwrap(),
+            Path::new("./nand2tetris-emu.hack")
+        );
+    }
+}
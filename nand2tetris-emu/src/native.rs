@@ -0,0 +1,816 @@
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    Cpu,
+    cpu::ROM_SIZE,
+    heap::{HEAP_END, HEAP_START},
+    screen::{KBD, SCREEN, SCREEN_WORDS, WORDS_PER_ROW},
+    symbols::Symbols,
+};
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+
+// Output の 1 行の高さ（画素）と、画面の行数・桁数
+const CHAR_HEIGHT: u16 = 11;
+const TEXT_ROWS: u16 = 23;
+const TEXT_COLUMNS: u16 = 64;
+
+// Rust で実行できる OS のクラス
+pub const NATIVE_CLASSES: &[&str] = &[
+    "Math", "Memory", "Array", "String", "Screen", "Output", "Keyboard",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    MathInit,
+    Abs,
+    Multiply,
+    Divide,
+    Min,
+    Max,
+    Sqrt,
+    MemoryInit,
+    Peek,
+    Poke,
+    Alloc,
+    DeAlloc,
+    ArrayNew,
+    ArrayDispose,
+    StringNew,
+    StringDispose,
+    Length,
+    CharAt,
+    SetCharAt,
+    AppendChar,
+    EraseLastChar,
+    IntValue,
+    SetInt,
+    NewLine,
+    BackSpaceChar,
+    DoubleQuote,
+    ScreenInit,
+    ClearScreen,
+    SetColor,
+    DrawPixel,
+    DrawLine,
+    DrawRectangle,
+    DrawCircle,
+    OutputInit,
+    MoveCursor,
+    PrintChar,
+    PrintString,
+    PrintInt,
+    Println,
+    BackSpace,
+    KeyPressed,
+}
+
+const FUNCTIONS: &[(&str, Function)] = &[
+    ("Math.init", Function::MathInit),
+    ("Math.abs", Function::Abs),
+    ("Math.multiply", Function::Multiply),
+    ("Math.divide", Function::Divide),
+    ("Math.min", Function::Min),
+    ("Math.max", Function::Max),
+    ("Math.sqrt", Function::Sqrt),
+    ("Memory.init", Function::MemoryInit),
+    ("Memory.peek", Function::Peek),
+    ("Memory.poke", Function::Poke),
+    ("Memory.alloc", Function::Alloc),
+    ("Memory.deAlloc", Function::DeAlloc),
+    ("Array.new", Function::ArrayNew),
+    ("Array.dispose", Function::ArrayDispose),
+    ("String.new", Function::StringNew),
+    ("String.dispose", Function::StringDispose),
+    ("String.length", Function::Length),
+    ("String.charAt", Function::CharAt),
+    ("String.setCharAt", Function::SetCharAt),
+    ("String.appendChar", Function::AppendChar),
+    ("String.eraseLastChar", Function::EraseLastChar),
+    ("String.intValue", Function::IntValue),
+    ("String.setInt", Function::SetInt),
+    ("String.newLine", Function::NewLine),
+    ("String.backSpace", Function::BackSpaceChar),
+    ("String.doubleQuote", Function::DoubleQuote),
+    ("Screen.init", Function::ScreenInit),
+    ("Screen.clearScreen", Function::ClearScreen),
+    ("Screen.setColor", Function::SetColor),
+    ("Screen.drawPixel", Function::DrawPixel),
+    ("Screen.drawLine", Function::DrawLine),
+    ("Screen.drawRectangle", Function::DrawRectangle),
+    ("Screen.drawCircle", Function::DrawCircle),
+    ("Output.init", Function::OutputInit),
+    ("Output.moveCursor", Function::MoveCursor),
+    ("Output.printChar", Function::PrintChar),
+    ("Output.printString", Function::PrintString),
+    ("Output.printInt", Function::PrintInt),
+    ("Output.println", Function::Println),
+    ("Output.backSpace", Function::BackSpace),
+    ("Keyboard.keyPressed", Function::KeyPressed),
+];
+
+// 関数の結果。Error は Sys.error に渡すエラーコード
+enum Outcome {
+    Return(u16),
+    Error(u16),
+}
+
+// classes の関数のうち Rust で実行するもの（空ならすべてのクラス）。
+// String と Array はこちらのヒープを使うので Memory も必要で、
+// Output.printString は String の中身を読むので String と一緒のときだけ
+pub fn native_functions(classes: &[String]) -> Result<Vec<&'static str>> {
+    let classes: Vec<&str> = if classes.is_empty() {
+        NATIVE_CLASSES.to_vec()
+    } else {
+        classes.iter().map(String::as_str).collect()
+    };
+    for class in &classes {
+        if !NATIVE_CLASSES.contains(class) {
+            bail!(
+                "Unknown native OS class '{}' (expected one of {})",
+                class,
+                NATIVE_CLASSES.join(", ")
+            );
+        }
+    }
+    for class in ["Array", "String"] {
+        if classes.contains(&class) && !classes.contains(&"Memory") {
+            bail!("Native {} needs native Memory", class);
+        }
+    }
+    Ok(FUNCTIONS
+        .iter()
+        .map(|&(name, _)| name)
+        .filter(|name| {
+            classes.contains(&name.split('.').next().unwrap_or_default())
+                && (*name != "Output.printString" || classes.contains(&"String"))
+        })
+        .collect())
+}
+
+// Jack OS の関数の入口に来たら、VM の関数の代わりに Rust の実装を実行して return する。
+// エラーは Sys.error にエラーコードを渡して続ける（OS と同じ表示で止まる）
+pub struct NativeOs {
+    entries: Vec<Option<Function>>,
+    sys_error: Option<u16>,
+    // Memory: 空き領域（先頭 → 語数）と確保したブロック（先頭 → 語数）
+    free: BTreeMap<u16, u16>,
+    blocks: HashMap<u16, u16>,
+    // Screen
+    color: bool,
+    // Output
+    font: Vec<[u8; 11]>,
+    row: u16,
+    column: u16,
+}
+
+impl NativeOs {
+    pub fn new(symbols: &Symbols, classes: &[String]) -> Result<Self> {
+        let functions = native_functions(classes)?;
+        let mut entries = vec![None; ROM_SIZE];
+        let mut found = 0;
+        for &(name, function) in FUNCTIONS {
+            if functions.contains(&name)
+                && let Some(address) = symbols.resolve(name)
+            {
+                entries[address as usize] = Some(function);
+                found += 1;
+            }
+        }
+        if found == 0 {
+            bail!("Native OS calls need the OS function symbols: run the .vm sources");
+        }
+        let mut native = NativeOs {
+            entries,
+            sys_error: symbols.resolve("Sys.error"),
+            free: BTreeMap::new(),
+            blocks: HashMap::new(),
+            color: true,
+            font: nand2tetris_jack::os::font()?,
+            row: 0,
+            column: 0,
+        };
+        native.init_heap();
+        Ok(native)
+    }
+
+    pub fn handles(&self, pc: u16) -> bool {
+        self.entries.get(pc as usize).is_some_and(Option::is_some)
+    }
+
+    // 関数の入口（フレームは作られ、ローカル変数はまだ）で呼ぶ
+    pub fn call(&mut self, cpu: &mut Cpu) -> Result<()> {
+        let Some(function) = self.entries[cpu.pc as usize] else {
+            bail!("No native OS function at {}", cpu.pc);
+        };
+        match self.execute(function, cpu)? {
+            Outcome::Return(value) => return_from(cpu, value),
+            Outcome::Error(code) => {
+                let Some(entry) = self.sys_error else {
+                    bail!("OS error {} (Sys.error is not defined)", code);
+                };
+                // Sys.error は第 1 引数しか使わないので、同じフレームのまま引数を差し替える
+                let arg = cpu.ram[ARG];
+                cpu.write(arg, code)?;
+                cpu.pc = entry;
+                Ok(())
+            }
+        }
+    }
+
+    fn execute(&mut self, function: Function, cpu: &mut Cpu) -> Result<Outcome> {
+        // 引数は最大 4 個。使わない分はスタックの先の値になる
+        let base = cpu.ram[ARG] as usize;
+        let args: [u16; 4] = std::array::from_fn(|i| cpu.ram.get(base + i).copied().unwrap_or(0));
+        let int = |i: usize| args[i] as i16;
+        let done = Outcome::Return(0);
+        Ok(match function {
+            Function::MathInit => done,
+            Function::Abs => Outcome::Return(int(0).wrapping_abs() as u16),
+            Function::Multiply => Outcome::Return(int(0).wrapping_mul(int(1)) as u16),
+            Function::Divide => match divide(int(0), int(1)) {
+                Some(q) => Outcome::Return(q as u16),
+                None => Outcome::Error(3),
+            },
+            Function::Min => Outcome::Return(int(0).min(int(1)) as u16),
+            Function::Max => Outcome::Return(int(0).max(int(1)) as u16),
+            Function::Sqrt => match int(0) {
+                x if x < 0 => Outcome::Error(4),
+                x => Outcome::Return(sqrt(x) as u16),
+            },
+            Function::MemoryInit => {
+                self.init_heap();
+                done
+            }
+            Function::Peek => Outcome::Return(cpu.read(args[0])?),
+            Function::Poke => {
+                let (address, value) = (args[0], args[1]);
+                cpu.write(address, value)?;
+                done
+            }
+            Function::Alloc => match int(0) {
+                size if size < 0 => Outcome::Error(5),
+                size => self.alloc(size as u16),
+            },
+            Function::DeAlloc => {
+                self.dealloc(args[0]);
+                done
+            }
+            Function::ArrayNew => match int(0) {
+                size if size <= 0 => Outcome::Error(2),
+                size => self.alloc(size as u16),
+            },
+            Function::ArrayDispose => {
+                self.dealloc(args[0]);
+                done
+            }
+            Function::KeyPressed => Outcome::Return(cpu.ram[KBD]),
+            Function::ScreenInit => {
+                self.color = true;
+                done
+            }
+            Function::ClearScreen => {
+                cpu.ram[SCREEN..SCREEN + SCREEN_WORDS].fill(0);
+                // 書き込みとして扱い、画面を再描画させる
+                cpu.write(SCREEN as u16, 0)?;
+                done
+            }
+            Function::SetColor => {
+                self.color = args[0] != 0;
+                done
+            }
+            Function::DrawPixel => {
+                let (x, y) = (int(0), int(1));
+                if !on_screen(x, y) {
+                    return Ok(Outcome::Error(7));
+                }
+                self.draw_pixel(cpu, x, y)?;
+                done
+            }
+            Function::DrawLine => {
+                let (x1, y1, x2, y2) = (int(0), int(1), int(2), int(3));
+                if !on_screen(x1, y1) || !on_screen(x2, y2) {
+                    return Ok(Outcome::Error(8));
+                }
+                self.draw_line(cpu, x1, y1, x2, y2)?;
+                done
+            }
+            Function::DrawRectangle => {
+                let (x1, y1, x2, y2) = (int(0), int(1), int(2), int(3));
+                if x1 > x2 || y1 > y2 || !on_screen(x1, y1) || !on_screen(x2, y2) {
+                    return Ok(Outcome::Error(9));
+                }
+                for y in y1..=y2 {
+                    self.draw_horizontal(cpu, x1, x2, y)?;
+                }
+                done
+            }
+            Function::DrawCircle => {
+                let (x, y, r) = (int(0), int(1), int(2));
+                if !on_screen(x, y) {
+                    return Ok(Outcome::Error(12));
+                }
+                if !(0..=181).contains(&r) {
+                    return Ok(Outcome::Error(13));
+                }
+                for dy in -r..=r {
+                    let row = y + dy;
+                    if (0..=255).contains(&row) {
+                        let half = sqrt(r * r - dy * dy);
+                        self.draw_horizontal(cpu, (x - half).max(0), (x + half).min(511), row)?;
+                    }
+                }
+                done
+            }
+            Function::OutputInit => {
+                (self.row, self.column) = (0, 0);
+                done
+            }
+            Function::MoveCursor => {
+                let (i, j) = (int(0), int(1));
+                if !(0..TEXT_ROWS as i16).contains(&i) || !(0..TEXT_COLUMNS as i16).contains(&j) {
+                    return Ok(Outcome::Error(20));
+                }
+                (self.row, self.column) = (i as u16, j as u16);
+                done
+            }
+            Function::PrintChar => {
+                let c = args[0];
+                self.print_char(cpu, c)?;
+                done
+            }
+            Function::PrintString => {
+                let s = args[0];
+                let (chars, length) = (cpu.read(s)?, cpu.read(s.wrapping_add(1))?);
+                for i in 0..length {
+                    let c = cpu.read(chars.wrapping_add(i))?;
+                    self.print_char(cpu, c)?;
+                }
+                done
+            }
+            Function::PrintInt => {
+                for c in int(0).to_string().bytes() {
+                    self.print_char(cpu, c as u16)?;
+                }
+                done
+            }
+            Function::Println => {
+                self.println();
+                done
+            }
+            Function::BackSpace => {
+                self.back_space(cpu)?;
+                done
+            }
+            Function::StringNew => self.string_new(int(0), cpu)?,
+            Function::NewLine => Outcome::Return(128),
+            Function::BackSpaceChar => Outcome::Return(129),
+            Function::DoubleQuote => Outcome::Return(34),
+            Function::StringDispose => {
+                let s = args[0];
+                if cpu.read(s.wrapping_add(2))? as i16 > 0 {
+                    self.dealloc(cpu.read(s)?);
+                }
+                self.dealloc(s);
+                done
+            }
+            Function::Length => Outcome::Return(cpu.read(args[0].wrapping_add(1))?),
+            Function::CharAt | Function::SetCharAt => {
+                let (s, j) = (args[0], int(1));
+                let (chars, length) = (cpu.read(s)?, cpu.read(s.wrapping_add(1))?);
+                if j < 0 || j >= length as i16 {
+                    return Ok(Outcome::Error(if function == Function::CharAt {
+                        15
+                    } else {
+                        16
+                    }));
+                }
+                let address = chars.wrapping_add(j as u16);
+                if function == Function::CharAt {
+                    Outcome::Return(cpu.read(address)?)
+                } else {
+                    let c = args[2];
+                    cpu.write(address, c)?;
+                    done
+                }
+            }
+            Function::AppendChar => {
+                let (s, c) = (args[0], args[1]);
+                if !append_char(cpu, s, c)? {
+                    return Ok(Outcome::Error(17));
+                }
+                Outcome::Return(s)
+            }
+            Function::EraseLastChar => {
+                let s = args[0];
+                let length = cpu.read(s.wrapping_add(1))?;
+                if length == 0 {
+                    return Ok(Outcome::Error(18));
+                }
+                cpu.write(s.wrapping_add(1), length - 1)?;
+                done
+            }
+            Function::IntValue => {
+                let s = args[0];
+                let (chars, length) = (cpu.read(s)?, cpu.read(s.wrapping_add(1))?);
+                let mut text = Vec::new();
+                for i in 0..length {
+                    text.push(cpu.read(chars.wrapping_add(i))?);
+                }
+                Outcome::Return(int_value(&text) as u16)
+            }
+            Function::SetInt => {
+                let (s, n) = (args[0], int(1));
+                cpu.write(s.wrapping_add(1), 0)?;
+                for c in n.to_string().bytes() {
+                    if !append_char(cpu, s, c as u16)? {
+                        return Ok(Outcome::Error(19));
+                    }
+                }
+                done
+            }
+        })
+    }
+
+    // ---------- Memory ----------
+
+    fn init_heap(&mut self) {
+        self.free = BTreeMap::from([(HEAP_START, HEAP_END - HEAP_START)]);
+        self.blocks.clear();
+    }
+
+    // 最初に見つかった十分な空き領域の先頭から切り出す
+    fn alloc(&mut self, size: u16) -> Outcome {
+        let size = size.max(1);
+        let Some((&base, &length)) = self.free.iter().find(|&(_, &length)| length >= size) else {
+            return Outcome::Error(6);
+        };
+        self.free.remove(&base);
+        if length > size {
+            self.free.insert(base + size, length - size);
+        }
+        self.blocks.insert(base, size);
+        Outcome::Return(base)
+    }
+
+    // 確保したブロックでなければ何もしない。隣の空き領域とはつなげる
+    fn dealloc(&mut self, address: u16) {
+        let Some(mut size) = self.blocks.remove(&address) else {
+            return;
+        };
+        let mut base = address;
+        if let Some(next) = self.free.remove(&(base + size)) {
+            size += next;
+        }
+        if let Some((&previous, &length)) = self.free.range(..base).next_back()
+            && previous + length == base
+        {
+            base = previous;
+            size += length;
+        }
+        self.free.insert(base, size);
+    }
+
+    // ---------- String ----------
+
+    // オブジェクトは [chars, length, capacity]
+    fn string_new(&mut self, capacity: i16, cpu: &mut Cpu) -> Result<Outcome> {
+        if capacity < 0 {
+            return Ok(Outcome::Error(14));
+        }
+        let Outcome::Return(s) = self.alloc(3) else {
+            return Ok(Outcome::Error(6));
+        };
+        let chars = if capacity > 0 {
+            match self.alloc(capacity as u16) {
+                Outcome::Return(chars) => chars,
+                error => return Ok(error),
+            }
+        } else {
+            0
+        };
+        cpu.write(s, chars)?;
+        cpu.write(s + 1, 0)?;
+        cpu.write(s + 2, capacity as u16)?;
+        Ok(Outcome::Return(s))
+    }
+
+    // ---------- Screen ----------
+
+    fn draw_pixel(&self, cpu: &mut Cpu, x: i16, y: i16) -> Result<()> {
+        let address = SCREEN as u16 + y as u16 * WORDS_PER_ROW as u16 + x as u16 / 16;
+        self.fill(cpu, address, 1 << (x & 15))
+    }
+
+    fn fill(&self, cpu: &mut Cpu, address: u16, mask: u16) -> Result<()> {
+        let value = cpu.read(address)?;
+        cpu.write(
+            address,
+            if self.color {
+                value | mask
+            } else {
+                value & !mask
+            },
+        )
+    }
+
+    fn draw_horizontal(&self, cpu: &mut Cpu, x1: i16, x2: i16, y: i16) -> Result<()> {
+        for x in x1..=x2 {
+            self.draw_pixel(cpu, x, y)?;
+        }
+        Ok(())
+    }
+
+    // Screen.drawLine と同じブレゼンハムのアルゴリズム
+    fn draw_line(&self, cpu: &mut Cpu, x1: i16, y1: i16, x2: i16, y2: i16) -> Result<()> {
+        let (mut x, mut y) = (x1, y1);
+        let dx = (x2 - x1).abs();
+        let dy = -(y2 - y1).abs();
+        let sx = if x2 < x1 { -1 } else { 1 };
+        let sy = if y2 < y1 { -1 } else { 1 };
+        let mut error = dx + dy;
+        loop {
+            self.draw_pixel(cpu, x, y)?;
+            if x == x2 && y == y2 {
+                return Ok(());
+            }
+            let twice = error + error;
+            if twice >= dy {
+                error += dy;
+                x += sx;
+            }
+            if twice <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // ---------- Output ----------
+
+    // 偶数桁は語の下位 8 ビット、奇数桁は上位 8 ビット
+    fn draw_char(&self, cpu: &mut Cpu, c: u16) -> Result<()> {
+        let glyph = match c {
+            32..=126 => &self.font[c as usize - 31],
+            _ => &self.font[0],
+        };
+        let mut address =
+            SCREEN as u16 + self.row * CHAR_HEIGHT * WORDS_PER_ROW as u16 + self.column / 2;
+        for &bits in glyph {
+            let value = cpu.read(address)?;
+            let value = if self.column.is_multiple_of(2) {
+                (value & 0xff00) | bits as u16
+            } else {
+                (value & 0x00ff) | (bits as u16) << 8
+            };
+            cpu.write(address, value)?;
+            address += WORDS_PER_ROW as u16;
+        }
+        Ok(())
+    }
+
+    fn print_char(&mut self, cpu: &mut Cpu, c: u16) -> Result<()> {
+        match c {
+            128 => self.println(),
+            129 => self.back_space(cpu)?,
+            _ => {
+                self.draw_char(cpu, c)?;
+                self.column += 1;
+                if self.column == TEXT_COLUMNS {
+                    self.println();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 最後の行の次は先頭の行に戻る
+    fn println(&mut self) {
+        self.column = 0;
+        self.row = (self.row + 1) % TEXT_ROWS;
+    }
+
+    fn back_space(&mut self, cpu: &mut Cpu) -> Result<()> {
+        if self.column == 0 && self.row > 0 {
+            self.row -= 1;
+            self.column = TEXT_COLUMNS;
+        }
+        if self.column > 0 {
+            self.column -= 1;
+            self.draw_char(cpu, 32)?;
+        }
+        Ok(())
+    }
+}
+
+// VM の return と同じ: 返り値を ARG の位置に置き、呼び出し元のフレームに戻す
+fn return_from(cpu: &mut Cpu, value: u16) -> Result<()> {
+    let frame = cpu.ram[LCL];
+    let saved = |i: u16| cpu.read(frame.wrapping_sub(i));
+    let (return_address, lcl, arg, this, that) =
+        (saved(5)?, saved(4)?, saved(3)?, saved(2)?, saved(1)?);
+    let result = cpu.ram[ARG];
+    cpu.write(result, value)?;
+    cpu.ram[SP] = result.wrapping_add(1);
+    cpu.ram[LCL] = lcl;
+    cpu.ram[ARG] = arg;
+    cpu.ram[THIS] = this;
+    cpu.ram[THAT] = that;
+    cpu.pc = return_address;
+    Ok(())
+}
+
+fn on_screen(x: i16, y: i16) -> bool {
+    (0..=511).contains(&x) && (0..=255).contains(&y)
+}
+
+// 絶対値を符号なしで割り、符号を付け直す（-32768 / -1 は -32768）
+fn divide(x: i16, y: i16) -> Option<i16> {
+    if y == 0 {
+        return None;
+    }
+    let q = (x.unsigned_abs() / y.unsigned_abs()) as i16;
+    Some(if (x < 0) != (y < 0) {
+        q.wrapping_neg()
+    } else {
+        q
+    })
+}
+
+// Math.sqrt と同じく上のビットから決める
+fn sqrt(x: i16) -> i16 {
+    let mut y: i16 = 0;
+    for j in (0..8).rev() {
+        let t = y + (1 << j);
+        let square = t.wrapping_mul(t);
+        if square <= x && square > 0 {
+            y = t;
+        }
+    }
+    y
+}
+
+// 先頭の - と、続く数字だけを読む
+fn int_value(text: &[u16]) -> i16 {
+    let (negative, digits) = match text.split_first() {
+        Some((45, rest)) => (true, rest),
+        _ => (false, text),
+    };
+    let mut value: i16 = 0;
+    for &c in digits.iter().take_while(|c| (48..=57).contains(*c)) {
+        value = value.wrapping_mul(10).wrapping_add(c as i16 - 48);
+    }
+    if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+// いっぱいなら false
+fn append_char(cpu: &mut Cpu, s: u16, c: u16) -> Result<bool> {
+    let (chars, length, capacity) = (
+        cpu.read(s)?,
+        cpu.read(s.wrapping_add(1))?,
+        cpu.read(s.wrapping_add(2))?,
+    );
+    if length == capacity {
+        return Ok(false);
+    }
+    cpu.write(chars.wrapping_add(length), c)?;
+    cpu.write(s.wrapping_add(1), length + 1)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Machine, screen::SCREEN};
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    #[case(1000, -7, Some(-142))]
+    #[case(-7, 2, Some(-3))]
+    #[case(-32768, 1, Some(-32768))]
+    #[case(-32768, -1, Some(-32768))]
+    #[case(100, -32768, Some(0))]
+    #[case(5, 0, None)]
+    fn test_divide(#[case] x: i16, #[case] y: i16, #[case] expected: Option<i16>) {
+        assert_eq!(divide(x, y), expected);
+    }
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(1, 1)]
+    #[case(24, 4)]
+    #[case(25, 5)]
+    #[case(30000, 173)]
+    #[case(32767, 181)]
+    fn test_sqrt(#[case] x: i16, #[case] expected: i16) {
+        assert_eq!(sqrt(x), expected);
+    }
+
+    #[rstest]
+    #[case("-1234", -1234)]
+    #[case("12a3", 12)]
+    #[case("-", 0)]
+    #[case("", 0)]
+    fn test_int_value(#[case] text: &str, #[case] expected: i16) {
+        let text: Vec<u16> = text.bytes().map(u16::from).collect();
+        assert_eq!(int_value(&text), expected);
+    }
+
+    #[test]
+    fn test_native_functions() {
+        assert_eq!(
+            native_functions(&["Math".to_string()]).unwrap(),
+            [
+                "Math.init",
+                "Math.abs",
+                "Math.multiply",
+                "Math.divide",
+                "Math.min",
+                "Math.max",
+                "Math.sqrt"
+            ]
+        );
+        let all = native_functions(&[]).unwrap();
+        assert!(all.contains(&"Output.printString"));
+        let output = native_functions(&["Output".to_string()]).unwrap();
+        assert!(!output.contains(&"Output.printString"));
+        assert!(native_functions(&["String".to_string()]).is_err());
+        assert!(native_functions(&["Sys".to_string()]).is_err());
+    }
+
+    // 同梱の OS の VM コードと Rust の実装で、結果と画面が同じになる
+    #[test]
+    fn test_native_os_matches_jack_os() {
+        let dir = std::env::temp_dir().join(format!("emu-native-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let main = nand2tetris_jack::compile(
+            "class Main {
+                function void main() {
+                    var String s;
+                    var Array a;
+                    let a = Array.new(3);
+                    let a[0] = 300 * (-7);
+                    let a[1] = -1000 / 7;
+                    let a[2] = Math.sqrt(200);
+                    let s = String.new(8);
+                    do s.setInt(-321);
+                    do s.appendChar(65);
+                    do Output.printString(s);
+                    do Output.println();
+                    do Output.printInt(s.intValue() + a[0]);
+                    do Memory.poke(8000, a[0]);
+                    do Memory.poke(8001, a[1]);
+                    do Memory.poke(8002, a[2]);
+                    do Memory.poke(8003, s.length());
+                    do s.dispose();
+                    do a.dispose();
+                    do Screen.drawLine(10, 40, 60, 52);
+                    do Screen.drawRectangle(70, 30, 90, 45);
+                    do Screen.drawCircle(120, 40, 6);
+                    do Screen.setColor(false);
+                    do Screen.drawPixel(120, 40);
+                    do Memory.poke(8004, 1);
+                    return;
+                }
+            }",
+        )
+        .unwrap();
+        fs::write(dir.join("Main.vm"), main).unwrap();
+
+        let run = |native: bool| {
+            let mut machine = if native {
+                Machine::load_with_native_os(&dir, &[]).unwrap()
+            } else {
+                Machine::load(&dir).unwrap()
+            };
+            while machine.peek(8004).unwrap() == 0 {
+                machine.step().unwrap();
+            }
+            machine
+        };
+        let jack = run(false);
+        let native = run(true);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            &native.ram()[8000..8004],
+            &[-2100i16 as u16, -142i16 as u16, 14, 5]
+        );
+        assert_eq!(&native.ram()[8000..8004], &jack.ram()[8000..8004]);
+        assert_eq!(native.screen(), jack.screen());
+        assert!(native.screen().iter().any(|&word| word != 0));
+        assert!(native.ram()[SCREEN + 40 * 32 + 120 / 16] & (1 << 8) == 0);
+        // OS の関数の本体を除いた分だけ小さい
+        assert!(native.cpu().program_len() < jack.cpu().program_len() / 4);
+    }
+}
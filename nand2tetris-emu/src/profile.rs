@@ -0,0 +1,286 @@
+use anyhow::Result;
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    frame::Frame,
+    symbols::Symbols,
+};
+
+// 最初の関数（ラベル）より前のアドレス
+const NO_SYMBOL: &str = "(no symbol)";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    // 関数のアドレスで実行したサイクル
+    pub exclusive: u64,
+    // 呼び出してから戻るまでのサイクル（呼んだ先も含む）
+    pub inclusive: u64,
+}
+
+// 呼び出し中の関数
+struct Active {
+    function: usize,
+    frame: Frame,
+    start: u64,
+}
+
+// 実行したサイクルを関数ごとに数える。呼び出しと戻りは VM 変換器のフレームから判定する
+pub struct Profiler {
+    names: Vec<String>,
+    // 関数の入口（アドレス順）と names の添字
+    regions: Vec<(u16, usize)>,
+    entries: HashMap<u16, usize>,
+    stats: Vec<FunctionStats>,
+    stack: Vec<Active>,
+    cycles: u64,
+    // 呼び出し元からのスタック（names の添字）ごとのサイクル。今のスタックは sample に数えている
+    folded: HashMap<Vec<usize>, u64>,
+    sample: Vec<usize>,
+    sample_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new(symbols: &Symbols) -> Self {
+        let mut names = vec![NO_SYMBOL.to_string()];
+        let mut regions = Vec::new();
+        for (address, name) in symbols.regions() {
+            regions.push((address, names.len()));
+            names.push(name.to_string());
+        }
+        Profiler {
+            entries: regions.iter().copied().collect(),
+            stats: vec![FunctionStats::default(); names.len()],
+            names,
+            regions,
+            stack: Vec::new(),
+            cycles: 0,
+            folded: HashMap::new(),
+            sample: Vec::new(),
+            sample_cycles: 0,
+        }
+    }
+
+    // 呼び出し中の関数と、実行中のアドレスを含む関数（呼び出しの一番内側と違えば）
+    fn path(&self, owner: usize) -> impl Iterator<Item = usize> + '_ {
+        let top = self.stack.last().map(|active| active.function);
+        self.stack
+            .iter()
+            .map(|active| active.function)
+            .chain((top != Some(owner)).then_some(owner))
+    }
+
+    // inferno や flamegraph.pl の folded 形式（"Sys.init;Main.main;Math.multiply 1234"）
+    pub fn folded(&self) -> String {
+        let mut folded = self.folded.clone();
+        if self.sample_cycles > 0 {
+            *folded.entry(self.sample.clone()).or_default() += self.sample_cycles;
+        }
+        let mut stacks: Vec<(String, u64)> = folded
+            .into_iter()
+            .map(|(path, cycles)| {
+                let names: Vec<&str> = path.iter().map(|&i| self.names[i].as_str()).collect();
+                (names.join(";"), cycles)
+            })
+            .collect();
+        stacks.sort();
+
+        let mut out = String::new();
+        for (stack, cycles) in stacks {
+            writeln!(out, "{} {}", stack, cycles).unwrap();
+        }
+        out
+    }
+
+    fn owner(&self, address: u16) -> usize {
+        match self.regions.partition_point(|&(start, _)| start <= address) {
+            0 => 0,
+            index => self.regions[index - 1].1,
+        }
+    }
+
+    // 再帰で同じ関数が重なっているときは一番外側の呼び出しだけを数える
+    fn inclusive(&self, function: usize, active: &[Active]) -> u64 {
+        let open: u64 = active
+            .iter()
+            .enumerate()
+            .filter(|(depth, a)| {
+                a.function == function && !active[..*depth].iter().any(|b| b.function == function)
+            })
+            .map(|(_, a)| self.cycles - a.start)
+            .sum();
+        self.stats[function].inclusive + open
+    }
+
+    // 関数名と集計（実行中の呼び出しは今のサイクルまでで数える）。自身のサイクルの多い順
+    pub fn results(&self) -> Vec<(&str, FunctionStats)> {
+        let mut results: Vec<(&str, FunctionStats)> = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(function, name)| {
+                let stats = self.stats[function];
+                let inclusive = if stats.calls == 0 {
+                    stats.exclusive
+                } else {
+                    self.inclusive(function, &self.stack)
+                };
+                (name.as_str(), FunctionStats { inclusive, ..stats })
+            })
+            .filter(|(_, stats)| stats.exclusive > 0 || stats.calls > 0)
+            .collect();
+        results.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        results
+    }
+
+    pub fn report(&self) -> String {
+        let results = self.results();
+        let width = results
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or_default()
+            .max("Function".len());
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.cycles.max(1) as f64;
+
+        let mut out = format!("Profile ({} cycles)\n", self.cycles);
+        writeln!(
+            out,
+            "{:<width$}  {:>8}  {:>19}  {:>19}",
+            "Function", "Calls", "Exclusive", "Inclusive"
+        )
+        .unwrap();
+        for (name, stats) in results {
+            writeln!(
+                out,
+                "{:<width$}  {:>8}  {:>12} {:>5.1}%  {:>12} {:>5.1}%",
+                name,
+                stats.calls,
+                stats.exclusive,
+                percent(stats.exclusive),
+                stats.inclusive,
+                percent(stats.inclusive)
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+impl StepHook for Profiler {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()> {
+        self.cycles += 1;
+        let owner = self.owner(step.pc);
+        self.stats[owner].exclusive += 1;
+
+        if !self.path(owner).eq(self.sample.iter().copied()) {
+            if self.sample_cycles > 0 {
+                *self.folded.entry(self.sample.clone()).or_default() += self.sample_cycles;
+            }
+            self.sample = self.path(owner).collect();
+            self.sample_cycles = 0;
+        }
+        self.sample_cycles += 1;
+
+        while let Some(top) = self.stack.last() {
+            if !top.frame.has_returned(cpu) {
+                break;
+            }
+            let done = self.stack.pop().expect("stack is not empty");
+            if !self.stack.iter().any(|a| a.function == done.function) {
+                self.stats[done.function].inclusive += self.cycles - done.start;
+            }
+        }
+
+        // 関数の入口へのジャンプで、積まれた戻りアドレスがジャンプの次なら call
+        let next = step.pc.wrapping_add(1);
+        if cpu.pc != next
+            && let Some(&function) = self.entries.get(&cpu.pc)
+            && let Some(frame) = Frame::current(cpu)
+            && frame.return_address == next
+        {
+            self.stats[function].calls += 1;
+            self.stack.push(Active {
+                function,
+                frame,
+                start: self.cycles,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, loader, screen::Headless};
+    use std::fs;
+
+    #[test]
+    fn test_profile_vm_program() {
+        let dir = std::env::temp_dir().join(format!("emu-profile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 3\ncall Main.fact 1\npop static 0\n\
+             label END\ngoto END",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.fact 0\npush argument 0\nif-goto RECURSE\npush constant 1\nreturn\n\
+             label RECURSE\npush argument 0\npush argument 0\npush constant 1\nsub\n\
+             call Main.fact 1\ncall Main.double 1\nreturn\n\
+             function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+        let program = loader::load_program(&dir, None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut cpu = Cpu::new(&program.words).unwrap();
+        let mut profiler = Profiler::new(&program.symbols);
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut profiler])
+            .unwrap();
+
+        let results: HashMap<&str, FunctionStats> = profiler.results().into_iter().collect();
+        assert_eq!(results["Sys.init"].calls, 1);
+        assert_eq!(results["Main.fact"].calls, 4);
+        assert_eq!(results["Main.double"].calls, 3);
+        // 呼び出し先のない関数は自身のサイクルがそのまま含まれる
+        assert_eq!(
+            results["Main.double"].inclusive,
+            results["Main.double"].exclusive
+        );
+        // 再帰しても二重に数えない
+        assert!(results["Main.fact"].inclusive < results["Sys.init"].inclusive);
+        assert_eq!(
+            results.values().map(|s| s.exclusive).sum::<u64>(),
+            cpu.cycles
+        );
+
+        let folded = profiler.folded();
+        let stacks: HashMap<&str, u64> = folded
+            .lines()
+            .map(|line| {
+                let (stack, cycles) = line.rsplit_once(' ').unwrap();
+                (stack, cycles.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(stacks.values().sum::<u64>(), cpu.cycles);
+        assert!(
+            stacks.contains_key("Sys.init;Main.fact;Main.fact;Main.double"),
+            "{}",
+            folded
+        );
+        assert_eq!(
+            stacks["Sys.init;Main.fact;Main.double"],
+            results["Main.double"].exclusive / 3
+        );
+
+        let report = profiler.report();
+        let header = format!("Profile ({} cycles)\nFunction ", cpu.cycles);
+        assert!(report.starts_with(&header), "{}", report);
+    }
+}
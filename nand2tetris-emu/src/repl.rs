@@ -0,0 +1,561 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    fmt::Write as _,
+    io::{BufRead, Write},
+    mem,
+    path::Path,
+};
+
+use nand2tetris_jack::{
+    analysis::{Signatures, check_class},
+    ast::{Class, ClassVarKind, Position},
+    diagnostic::Diagnostic,
+    generate, os, parser,
+};
+use nand2tetris_vm::{TranslateOptions, VMTranslator};
+
+use crate::{
+    Cpu,
+    cpu::{RunOptions, StopReason},
+    frame::Frame,
+    loader::{class_of, commands, compile_jack, strip_native},
+    native::{NativeOs, native_functions},
+    screen::{Headless, TtyStyle, render},
+    screenshot::save_screenshot,
+    symbols::Symbols,
+};
+
+const PROMPT: &str = "jack> ";
+const CONTINUATION: &str = "  ... ";
+const HELP: &str = "\
+EXPR             evaluate a Jack expression and print its value (e.g. Math.sqrt(2 * x))
+let/do/if/while  run Jack statements (a line with an open { continues on the next lines)
+var TYPE NAMES;  declare variables that keep their values between inputs
+:vars            show the declared variables
+:screen [FILE]   show the screen (or save it as a PNG)
+:reset           clear the variables, RAM and screen and initialize the OS again
+:help            show this help
+:quit            leave the REPL
+";
+
+// Sys.init と同じ順で OS を初期化する
+const OS_INIT: &[&str] = &["Memory", "Math", "Screen", "Output", "Keyboard"];
+
+// (クラス名, VM コード)
+type Module = (String, String);
+
+const SP: usize = 0;
+const ARG: usize = 2;
+const TEMP0: usize = 5;
+
+#[derive(Debug, Clone)]
+pub struct ReplOptions {
+    // Some なら OS の関数を Rust で実行する（空ならすべてのクラス）
+    pub native_os: Option<Vec<String>>,
+    // 1つの入力で実行する最大サイクル数
+    pub max_cycles: u64,
+}
+
+impl Default for ReplOptions {
+    fn default() -> Self {
+        ReplOptions {
+            native_os: None,
+            max_cycles: 50_000_000,
+        }
+    }
+}
+
+// 入力ごとに Repl クラスを組み立ててコンパイルし、同じ RAM のまま実行する。
+// 宣言した変数は Repl の static になる
+pub struct Repl {
+    options: ReplOptions,
+    // プロジェクトのクラス（呼び出しの検査用）
+    classes: Vec<Class>,
+    // プロジェクトと OS の VM コード。Repl はこの後ろに置くので、これらの番地は入力ごとに変わらない
+    modules: Vec<Module>,
+    boot: String,
+    // `static int x, y;` の形にした宣言と、その変数（型, 名前）
+    declarations: Vec<String>,
+    variables: Vec<(String, String)>,
+    cpu: Cpu,
+    symbols: Symbols,
+}
+
+enum Input {
+    Declaration,
+    Statements,
+    Expression,
+}
+
+impl Repl {
+    // project は呼び出せるクラスの .jack ファイルかディレクトリ
+    pub fn new(project: Option<&Path>, options: ReplOptions) -> Result<Self> {
+        let (classes, mut modules) = match project {
+            Some(path) => compile_project(path)?,
+            None => (Vec::new(), Vec::new()),
+        };
+        let defined: Vec<&str> = classes
+            .iter()
+            .map(|class| class.name.name.as_str())
+            .collect();
+        let libraries: Vec<(String, String)> = os::compile_os(0)?
+            .into_iter()
+            .filter(|(name, _)| !defined.contains(&name.as_str()))
+            .collect();
+        let libraries = match &options.native_os {
+            // 入力から呼べるのはプロジェクトの関数と OS の API だけなので、それ以外の OS の
+            // 関数は native の関数の中からしか呼ばれない
+            Some(native) => {
+                let api = Signatures::new(&[]);
+                let roots = modules
+                    .iter()
+                    .chain(&libraries)
+                    .flat_map(|(_, code)| commands(code))
+                    .filter(|&(command, function)| {
+                        command == "function"
+                            && (!os::is_os_class(class_of(function))
+                                || defined.contains(&class_of(function))
+                                || function
+                                    .split_once('.')
+                                    .is_some_and(|(class, name)| api.get(class, name).is_some()))
+                    })
+                    .map(|(_, function)| function.to_string())
+                    .collect();
+                strip_native(libraries, roots, &native_functions(native)?)
+            }
+            None => libraries,
+        };
+        modules.extend(libraries);
+
+        let mut boot = String::from("function Repl.boot 0\n");
+        for class in OS_INIT {
+            let init = format!("{}.init", class);
+            if modules
+                .iter()
+                .any(|(_, code)| commands(code).any(|command| command == ("function", &init)))
+            {
+                writeln!(boot, "call {} 0\npop temp 0", init).unwrap();
+            }
+        }
+        boot.push_str("push constant 0\nreturn\n");
+
+        let mut repl = Repl {
+            options,
+            classes,
+            modules,
+            boot,
+            declarations: Vec::new(),
+            variables: Vec::new(),
+            cpu: Cpu::new(&[])?,
+            symbols: Symbols::default(),
+        };
+        repl.reset()?;
+        Ok(repl)
+    }
+
+    // 変数と RAM を消して OS を初期化し直す
+    pub fn reset(&mut self) -> Result<()> {
+        self.declarations.clear();
+        self.variables.clear();
+        self.cpu = Cpu::new(&[])?;
+        let vm = self.compile("")?.1;
+        self.execute(&vm, "Repl.boot")?;
+        Ok(())
+    }
+
+    // 式なら値を返す。文と変数の宣言は None
+    pub fn eval(&mut self, input: &str) -> Result<Option<i16>> {
+        let input = input.trim();
+        match classify(input) {
+            Input::Declaration => {
+                // var を static に変えて宣言に加え、コンパイルできるか確かめる
+                let mut declarations = self.declarations.clone();
+                declarations.push(format!("static{}", &input[3..]));
+                let (class, _) = self.compile_with(&declarations, "", Some(3))?;
+                self.declarations = declarations;
+                self.variables = static_variables(&class);
+                Ok(None)
+            }
+            Input::Statements => {
+                let vm = self.compile(input)?.1;
+                self.execute(&vm, "Repl.eval")?;
+                Ok(None)
+            }
+            Input::Expression => {
+                let body = format!("return {};", input.trim_end_matches(';'));
+                let vm = self.compile(&body)?.1;
+                Ok(Some(self.execute(&vm, "Repl.eval")? as i16))
+            }
+        }
+    }
+
+    // 宣言した変数と今の値
+    pub fn variables(&self) -> Vec<(String, String, i16)> {
+        self.variables
+            .iter()
+            .enumerate()
+            .map(|(k, (ty, name))| {
+                let value = self
+                    .symbols
+                    .resolve_variable(&format!("Repl.{}", k))
+                    .map_or(0, |address| self.cpu.ram[address as usize]);
+                (ty.clone(), name.clone(), value as i16)
+            })
+            .collect()
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // :quit か入力の終わりまでプロンプトを出して評価する
+    pub fn run(&mut self, input: &mut dyn BufRead, out: &mut dyn Write) -> Result<()> {
+        loop {
+            let Some(text) = read_input(input, out)? else {
+                writeln!(out)?;
+                return Ok(());
+            };
+            let text = text.trim();
+            let result = if let Some(command) = text.strip_prefix(':') {
+                match self.command(command, out) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            } else if text.is_empty() {
+                Ok(())
+            } else {
+                self.eval(text).and_then(|value| match value {
+                    Some(value) => Ok(writeln!(out, "{}", value)?),
+                    None => Ok(()),
+                })
+            };
+            if let Err(e) = result {
+                writeln!(out, "Error: {}", e)?;
+            }
+        }
+    }
+
+    // : で始まるコマンド。true なら終了する
+    fn command(&mut self, line: &str, out: &mut dyn Write) -> Result<bool> {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "quit" | "q" => return Ok(true),
+            "help" | "h" => write!(out, "{}", HELP)?,
+            "vars" => {
+                for (ty, name, value) in self.variables() {
+                    writeln!(out, "{} {} = {}", ty, name, value)?;
+                }
+            }
+            "screen" if arg.is_empty() => {
+                for line in render(self.cpu.screen(), TtyStyle::Braille) {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+            "screen" => {
+                save_screenshot(Path::new(arg), self.cpu.screen())?;
+                writeln!(out, "Saved the screen to '{}'", arg)?;
+            }
+            "reset" => self.reset()?,
+            _ => bail!("Unknown command ':{}' (type :help)", command),
+        }
+        Ok(false)
+    }
+
+    fn compile(&self, body: &str) -> Result<(Class, String)> {
+        self.compile_with(&self.declarations, body, None)
+    }
+
+    // Repl クラスを組み立てて検査し、VM コードにする。エラーの位置は入力の中の位置で示す。
+    // declaration は最後の宣言が入力で、static と var の長さの差
+    fn compile_with(
+        &self,
+        declarations: &[String],
+        body: &str,
+        declaration: Option<usize>,
+    ) -> Result<(Class, String)> {
+        let mut source = String::from("class Repl {\n");
+        for line in declarations {
+            writeln!(source, "{}", line).unwrap();
+        }
+        writeln!(
+            source,
+            "function int eval() {{\n{}\nreturn 0;\n}}\n}}",
+            body
+        )
+        .unwrap();
+
+        // 入力の 1 行目のソース上の行と、その行で入力の前に付けた文字数
+        let (first, shift) = match declaration {
+            Some(shift) => (declarations.len() + 1, shift),
+            None if body.starts_with("return ") && !body.contains('\n') => {
+                (declarations.len() + 3, "return ".len())
+            }
+            None => (declarations.len() + 3, 0),
+        };
+        let located = |diagnostics: &[Diagnostic]| {
+            let lines: Vec<String> = diagnostics
+                .iter()
+                .filter(|d| d.is_error())
+                .map(|d| locate(d, first, shift))
+                .collect();
+            anyhow!("{}", lines.join("\n"))
+        };
+
+        let (class, diagnostics) = parser::parse_with_diagnostics(&source);
+        let class = match class {
+            Some(class) if !diagnostics.iter().any(Diagnostic::is_error) => class,
+            _ => return Err(located(&diagnostics)),
+        };
+        let mut all = self.classes.clone();
+        all.push(class.clone());
+        let diagnostics = check_class(&class, &Signatures::new(&all));
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(located(&diagnostics));
+        }
+        let vm = generate(&class, &all, 0)?;
+        Ok((class, vm))
+    }
+
+    // Repl のコードをつないで ROM を作り直し、entry を呼んで戻り値を返す
+    fn execute(&mut self, vm: &str, entry: &str) -> Result<u16> {
+        // static の番地はアセンブラが最初に現れた順に割り当てるので、番号順に並べておく
+        let mut code = String::from("function Repl.statics 0\n");
+        for k in 0..self.variables.len() {
+            writeln!(code, "push static {}\npop static {}", k, k).unwrap();
+        }
+        code.push_str("push constant 0\nreturn\n");
+        code.push_str(&self.boot);
+        code.push_str(vm);
+        writeln!(
+            code,
+            "function Repl.main 0\ncall {} 0\npop temp 0\nlabel REPL_END\ngoto REPL_END",
+            entry
+        )
+        .unwrap();
+
+        let mut sources = self.modules.clone();
+        sources.push(("Repl".to_string(), code));
+        let translation = VMTranslator::translate_sources(&sources, &TranslateOptions::default())?;
+        let program = nand2tetris_asm::assemble_program(&translation.asm)
+            .map_err(|e| anyhow!("Failed to assemble the REPL program: {}", e))?;
+        let symbols = Symbols::from_labels(&program.labels).with_variables(&program.variables);
+
+        // RAM と native の状態は引き継ぐ
+        let mut cpu = Cpu::new(&program.words)?;
+        cpu.ram = mem::take(&mut self.cpu.ram);
+        cpu.native = self.cpu.native.take();
+        if cpu.native.is_none()
+            && let Some(classes) = &self.options.native_os
+        {
+            cpu.native = Some(Box::new(NativeOs::new(&symbols, classes)?));
+        }
+        self.cpu = cpu;
+        self.symbols = symbols;
+        self.run_from("Repl.main")
+    }
+
+    fn run_from(&mut self, entry: &str) -> Result<u16> {
+        let resolve = |name: &str| {
+            self.symbols
+                .resolve(name)
+                .context(format!("'{}' is not defined", name))
+        };
+        let end = resolve("REPL_END")?;
+        self.cpu.pc = resolve(entry)?;
+        self.cpu.ram[SP] = 256;
+
+        self.cpu.sys_halt = self.symbols.resolve("Sys.halt");
+        let sys_error = self.symbols.resolve("Sys.error");
+        let options = RunOptions {
+            max_cycles: Some(self.options.max_cycles),
+            breakpoints: sys_error.into_iter().collect(),
+            ..RunOptions::default()
+        };
+        match self
+            .cpu
+            .run_with_backend(&mut Headless, &options, &mut [])?
+        {
+            StopReason::Halted if self.cpu.pc == end => Ok(self.cpu.ram[TEMP0]),
+            StopReason::Breakpoint if Some(self.cpu.pc) == sys_error => {
+                let code = self.cpu.ram[self.cpu.ram[ARG] as usize];
+                let caller = Frame::current(&self.cpu)
+                    .and_then(|frame| {
+                        self.symbols
+                            .function_at(frame.return_address.saturating_sub(1))
+                    })
+                    .unwrap_or("??");
+                bail!("Sys.error({}) called from {}", code as i16, caller)
+            }
+            StopReason::Halted if Some(self.cpu.pc) == self.cpu.sys_halt => {
+                bail!("Sys.halt was called")
+            }
+            StopReason::MaxCycles => bail!(
+                "Stopped after {} cycles (use --max-cycles to run longer)",
+                self.options.max_cycles
+            ),
+            reason => bail!("The program stopped unexpectedly ({:?})", reason),
+        }
+    }
+}
+
+// .jack をすべて検査してコンパイルする
+fn compile_project(path: &Path) -> Result<(Vec<Class>, Vec<Module>)> {
+    let classes: Vec<Class> = compile_jack(path)?
+        .into_iter()
+        .map(|(_, class)| class)
+        .collect();
+    let modules = classes
+        .iter()
+        .map(|class| Ok((class.name.name.clone(), generate(class, &classes, 0)?)))
+        .collect::<Result<_>>()?;
+    Ok((classes, modules))
+}
+
+fn classify(input: &str) -> Input {
+    match input
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+    {
+        Some("var") => Input::Declaration,
+        Some("let" | "do" | "if" | "while" | "return") => Input::Statements,
+        _ => Input::Expression,
+    }
+}
+
+fn static_variables(class: &Class) -> Vec<(String, String)> {
+    class
+        .vars
+        .iter()
+        .filter(|dec| dec.kind == ClassVarKind::Static)
+        .flat_map(|dec| {
+            dec.names
+                .iter()
+                .map(|name| (dec.ty.name().to_string(), name.name.clone()))
+        })
+        .collect()
+}
+
+// Repl クラスの中の位置を入力の中の位置にする。入力より前の位置なら位置を付けない
+fn locate(diagnostic: &Diagnostic, first: usize, shift: usize) -> String {
+    let Position { line, column } = diagnostic.position;
+    if line < first {
+        return diagnostic.message.clone();
+    }
+    let line = line - first + 1;
+    let column = if line == 1 {
+        column.saturating_sub(shift).max(1)
+    } else {
+        column
+    };
+    Diagnostic {
+        position: Position { line, column },
+        ..diagnostic.clone()
+    }
+    .to_string()
+}
+
+// { が閉じるまで続けて読む。入力の終わりなら None
+fn read_input(input: &mut dyn BufRead, out: &mut dyn Write) -> Result<Option<String>> {
+    let mut text = String::new();
+    let mut depth = 0i32;
+    loop {
+        write!(
+            out,
+            "{}",
+            if text.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION
+            }
+        )?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok((!text.is_empty()).then_some(text));
+        }
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        text.push_str(&line);
+        if depth <= 0 || text.trim_start().starts_with(':') {
+            return Ok(Some(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Cursor};
+
+    fn native() -> ReplOptions {
+        ReplOptions {
+            native_os: Some(Vec::new()),
+            ..ReplOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_run_session() {
+        let mut repl = Repl::new(None, native()).unwrap();
+        let input = "\
+var int x, sum;
+let x = 4;
+while (x > 0) {
+  let sum = sum + x;
+  let x = x - 1;
+}
+sum * 10
+:vars
+10 / x
+let y = 1;
+";
+        let mut out = Vec::new();
+        repl.run(&mut Cursor::new(input), &mut out).unwrap();
+        let lines: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .split(PROMPT)
+            .map(|text| text.replace(CONTINUATION, "").trim().to_string())
+            .filter(|text| !text.is_empty())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "100",
+                "int x = 0\nint sum = 10",
+                "Error: Sys.error(3) called from Repl.eval",
+                "Error: Line 1, column 5: Undefined variable 'y'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_project_classes() {
+        let dir = std::env::temp_dir().join(format!("emu-repl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Counter.jack"),
+            "class Counter {\n\
+             static int count;\n\
+             function int next() { let count = count + 1; return count; }\n\
+             }\n",
+        )
+        .unwrap();
+
+        let mut repl = Repl::new(Some(&dir), native()).unwrap();
+        repl.eval("do Counter.next();").unwrap();
+        assert_eq!(repl.eval("Counter.next() * 100").unwrap(), Some(200));
+        let error = repl.eval("Counter.reset()").unwrap_err().to_string();
+        assert!(error.contains("column 9"), "{}", error);
+
+        // 変数も OS の状態もなくなる
+        repl.eval("var String s;").unwrap();
+        repl.eval("do Output.printString(\"Hi\");").unwrap();
+        repl.reset().unwrap();
+        assert!(repl.variables().is_empty());
+        assert!(repl.cpu().screen().iter().all(|&word| word == 0));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    coverage::{Coverage, CoverageSummary},
+    heatmap::{MemoryHeatmap, RegionStats},
+    profile::Profiler,
+    symbols::Symbols,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FunctionReport {
+    pub function: String,
+    pub calls: u64,
+    pub exclusive: u64,
+    pub inclusive: u64,
+}
+
+// --report に書く1回の実行の集計
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunReport {
+    pub program: String,
+    pub status: String,
+    pub cycles: u64,
+    pub profile: Vec<FunctionReport>,
+    pub coverage: CoverageSummary,
+    pub memory: Vec<RegionStats>,
+}
+
+impl RunReport {
+    pub fn new(
+        program: &Path,
+        status: &str,
+        cycles: u64,
+        profiler: &Profiler,
+        coverage: &Coverage,
+        heatmap: &MemoryHeatmap,
+        symbols: &Symbols,
+    ) -> Self {
+        RunReport {
+            program: program.display().to_string(),
+            status: status.to_string(),
+            cycles,
+            profile: profiler
+                .results()
+                .into_iter()
+                .map(|(function, stats)| FunctionReport {
+                    function: function.to_string(),
+                    calls: stats.calls,
+                    exclusive: stats.exclusive,
+                    inclusive: stats.inclusive,
+                })
+                .collect(),
+            coverage: coverage.summary(symbols),
+            memory: heatmap.regions(),
+        }
+    }
+
+    // 1行1値の section,name,metric,value
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("section,name,metric,value\n");
+        let mut row = |section: &str, name: &str, metric: &str, value: &dyn ToString| {
+            writeln!(
+                out,
+                "{},{},{},{}",
+                section,
+                csv_field(name),
+                metric,
+                csv_field(&value.to_string())
+            )
+            .unwrap();
+        };
+
+        row("run", "", "program", &self.program);
+        row("run", "", "status", &self.status);
+        row("run", "", "cycles", &self.cycles);
+        for function in &self.profile {
+            row("profile", &function.function, "calls", &function.calls);
+            row(
+                "profile",
+                &function.function,
+                "exclusive",
+                &function.exclusive,
+            );
+            row(
+                "profile",
+                &function.function,
+                "inclusive",
+                &function.inclusive,
+            );
+        }
+        let coverage = &self.coverage;
+        row("coverage", "", "instructions", &coverage.instructions);
+        row("coverage", "", "executed", &coverage.executed);
+        row("coverage", "", "branches", &coverage.branches);
+        row("coverage", "", "branches_hit", &coverage.branches_hit);
+        row("coverage", "", "lines", &coverage.lines);
+        row("coverage", "", "lines_hit", &coverage.lines_hit);
+        for name in &coverage.never_executed {
+            row("coverage", name, "never_executed", &1);
+        }
+        for region in &self.memory {
+            row("memory", region.region, "reads", &region.reads);
+            row("memory", region.region, "writes", &region.writes);
+            row("memory", region.region, "addresses", &region.addresses);
+            if let Some(highest) = region.highest {
+                row("memory", region.region, "highest", &highest);
+            }
+        }
+        out
+    }
+
+    // 拡張子で JSON か CSV を選ぶ
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::to_string_pretty(self)? + "\n",
+            Some("csv") => self.to_csv(),
+            _ => bail!(
+                "Unsupported report format '{}': use .json or .csv",
+                path.display()
+            ),
+        };
+        fs::write(path, text).context(format!("Failed to write '{}'", path.display()))
+    }
+}
+
+// カンマや引用符を含む値は引用符で囲む
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, cpu::RunOptions, loader, screen::Headless};
+
+    #[test]
+    fn test_run_report() {
+        let dir = std::env::temp_dir().join(format!("emu-report-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Sys.vm"),
+            "function Sys.init 0\npush constant 3\ncall Main.double 1\npop static 0\n\
+             label END\ngoto END\n\
+             function Main.double 0\npush argument 0\npush argument 0\nadd\nreturn",
+        )
+        .unwrap();
+        let program = loader::load_program(&dir, None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut cpu = Cpu::new(&program.words).unwrap();
+        let mut profiler = Profiler::new(&program.symbols);
+        let mut coverage = Coverage::new(&program.words);
+        let mut heatmap = MemoryHeatmap::new();
+        cpu.run_with_backend(
+            &mut Headless,
+            &RunOptions::default(),
+            &mut [&mut profiler, &mut coverage, &mut heatmap],
+        )
+        .unwrap();
+
+        let report = RunReport::new(
+            Path::new("Prog, v2"),
+            "halted",
+            cpu.cycles,
+            &profiler,
+            &coverage,
+            &heatmap,
+            &program.symbols,
+        );
+        assert!(
+            report
+                .profile
+                .iter()
+                .any(|f| f.function == "Main.double" && f.calls == 1)
+        );
+        assert_eq!(report.coverage.lines, report.coverage.lines_hit);
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["cycles"], cpu.cycles);
+        assert_eq!(json["memory"][1]["region"], "static");
+        assert_eq!(json["memory"][1]["writes"], 1);
+
+        let csv = report.to_csv();
+        assert!(
+            csv.starts_with(
+                "section,name,metric,value\nrun,,program,\"Prog, v2\"\nrun,,status,halted\n"
+            ),
+            "{}",
+            csv
+        );
+        assert!(csv.contains("\nprofile,Main.double,calls,1\n"), "{}", csv);
+        assert!(csv.contains("\nmemory,static,highest,16\n"), "{}", csv);
+    }
+}
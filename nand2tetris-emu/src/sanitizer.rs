@@ -0,0 +1,125 @@
+use anyhow::Result;
+use std::io::Write;
+
+use crate::{
+    Cpu,
+    cpu::{RAM_SIZE, StepHook, StepInfo},
+    screen::{KBD, SCREEN},
+    symbols::Symbols,
+};
+
+// 一度も書き込まれていない RAM を読んだら警告する。画面とキーボードは対象外。
+// 同じアドレスについては最初の1回だけ警告する
+pub struct UninitChecker<W: Write> {
+    out: W,
+    symbols: Symbols,
+    written: Vec<bool>,
+    reported: Vec<bool>,
+    count: usize,
+}
+
+impl<W: Write> UninitChecker<W> {
+    pub fn new(out: W, symbols: Symbols) -> Self {
+        UninitChecker {
+            out,
+            symbols,
+            written: vec![false; RAM_SIZE],
+            reported: vec![false; RAM_SIZE],
+            count: 0,
+        }
+    }
+
+    // 警告したアドレスの数
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn warn(&mut self, pc: u16, address: u16) -> Result<()> {
+        write!(self.out, "Warning: PC={}", pc)?;
+        if let Some(location) = self.symbols.locate(pc) {
+            write!(self.out, " ({})", location)?;
+        }
+        if let Some(source) = self.symbols.source_at(pc) {
+            write!(self.out, " [{}:{}]", source.file, source.line)?;
+        }
+        write!(self.out, " reads uninitialized RAM[{}]", address)?;
+        if let Some(name) = self.symbols.variable_at(address) {
+            write!(self.out, " ({})", name)?;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+}
+
+impl<W: Write> StepHook for UninitChecker<W> {
+    fn on_step(&mut self, _: &Cpu, step: &StepInfo) -> Result<()> {
+        // M=M+1 のように読んでから書く命令があるので読み込みを先に見る
+        if let Some(address) = step.read {
+            let index = address as usize;
+            // ブートストラップの call は初期化前の LCL などを保存するので除く
+            let bootstrap = self.symbols.has_sources() && self.symbols.source_at(step.pc).is_none();
+            if index < RAM_SIZE
+                && !(SCREEN..=KBD).contains(&index)
+                && !self.written[index]
+                && !self.reported[index]
+                && !bootstrap
+            {
+                self.reported[index] = true;
+                self.count += 1;
+                self.warn(step.pc, address)?;
+            }
+        }
+        if let Some((address, _)) = step.write
+            && let Some(written) = self.written.get_mut(address as usize)
+        {
+            *written = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, screen::Headless};
+    use std::collections::HashMap;
+
+    fn check(program: &[u16], symbols: Symbols) -> (String, usize) {
+        let mut cpu = Cpu::new(program).unwrap();
+        let mut checker = UninitChecker::new(Vec::new(), symbols);
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut checker])
+            .unwrap();
+        let count = checker.count();
+        (String::from_utf8(checker.into_inner()).unwrap(), count)
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        let program = [
+            16,         // @16
+            0xfdc8,     // M=M+1   （読んでから書く）
+            16,         // @16
+            0xfc10,     // D=M     （書いたあとなので警告しない）
+            17,         // @17
+            0xfc10,     // D=M
+            17,         // @17
+            0xfc10,     // D=M     （2回目は警告しない）
+            KBD as u16, // @KBD
+            0xfc10,     // D=M     （キーボードは対象外）
+        ];
+        let symbols = Symbols::from_labels(&HashMap::from([("START".to_string(), 0)]))
+            .with_variables(&HashMap::from([("counter".to_string(), 16)]));
+
+        let (out, count) = check(&program, symbols);
+        assert_eq!(count, 2);
+        assert_eq!(
+            out,
+            "Warning: PC=1 (START+1) reads uninitialized RAM[16] (counter)\n\
+             Warning: PC=5 (START+5) reads uninitialized RAM[17]\n"
+        );
+    }
+}
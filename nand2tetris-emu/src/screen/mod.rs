@@ -0,0 +1,144 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use nand2tetris_core::symbols;
+use serde::Deserialize;
+use std::{cell::Cell, rc::Rc};
+
+use crate::keyboard::KeyMap;
+
+#[cfg(feature = "tty")]
+mod terminal;
+mod tty;
+#[cfg(feature = "window")]
+mod window;
+
+#[cfg(feature = "tty")]
+pub use terminal::Tty;
+pub use tty::{TtyStyle, render};
+#[cfg(feature = "window")]
+pub use window::Window;
+
+// Hack のメモリマップ
+pub const SCREEN: usize = symbols::SCREEN as usize;
+pub const KBD: usize = symbols::KBD as usize;
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 256;
+pub const WORDS_PER_ROW: usize = SCREEN_WIDTH / 16;
+pub const SCREEN_WORDS: usize = WORDS_PER_ROW * SCREEN_HEIGHT;
+
+// 画面の描画とキーボード入力を担当するバックエンド
+pub trait ScreenBackend {
+    // screen は RAM[SCREEN..KBD] の 8192 語
+    fn refresh(&mut self, screen: &[u16]) -> Result<()>;
+
+    // 現在押されているキーのコード（押されていなければ 0）
+    fn poll_key(&mut self) -> Result<u16>;
+
+    // ウィンドウが閉じられたら false を返す
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    // スクリーンショットのキー（Ctrl+S）が押されたら一度だけ true を返す
+    fn take_screenshot_request(&mut self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScreenKind {
+    /// No rendering and no keyboard input
+    Headless,
+    /// Render into the terminal with braille or half-block characters
+    Tty,
+    /// Open a 512x256 window and read keys from it
+    Window,
+}
+
+pub struct ScreenOptions {
+    pub tty_style: TtyStyle,
+    pub refresh_hz: u32,
+    pub keymap: KeyMap,
+}
+
+#[cfg_attr(not(any(feature = "tty", feature = "window")), allow(unused_variables))]
+pub fn create_backend(kind: ScreenKind, options: &ScreenOptions) -> Result<Box<dyn ScreenBackend>> {
+    match kind {
+        ScreenKind::Headless => Ok(Box::new(Headless)),
+        #[cfg(feature = "tty")]
+        ScreenKind::Tty => Ok(Box::new(Tty::new(
+            options.tty_style,
+            options.refresh_hz,
+            options.keymap.clone(),
+        )?)),
+        #[cfg(not(feature = "tty"))]
+        ScreenKind::Tty => anyhow::bail!("This build does not include the 'tty' feature"),
+        #[cfg(feature = "window")]
+        ScreenKind::Window => Ok(Box::new(Window::new(
+            options.refresh_hz,
+            options.keymap.clone(),
+        )?)),
+        #[cfg(not(feature = "window"))]
+        ScreenKind::Window => anyhow::bail!("This build does not include the 'window' feature"),
+    }
+}
+
+pub struct Headless;
+
+impl ScreenBackend for Headless {
+    fn refresh(&mut self, _screen: &[u16]) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_key(&mut self) -> Result<u16> {
+        Ok(0)
+    }
+}
+
+// 画面は描かず、キーは組み込んだ側が set で押す（WebAssembly や C から使うとき）。
+// 複製したものは同じキーを共有する
+#[derive(Debug, Clone, Default)]
+pub struct KeyLatch(Rc<Cell<u16>>);
+
+impl KeyLatch {
+    // 押されているキーの Hack のキーコード（離したら 0）
+    pub fn set(&self, key: u16) {
+        self.0.set(key);
+    }
+}
+
+impl ScreenBackend for KeyLatch {
+    fn refresh(&mut self, _screen: &[u16]) -> Result<()> {
+        Ok(())
+    }
+
+    fn poll_key(&mut self) -> Result<u16> {
+        Ok(self.0.get())
+    }
+}
+
+// (x, y) のピクセル。各語の LSB が左端
+pub fn pixel(screen: &[u16], x: usize, y: usize) -> bool {
+    let word = screen[y * WORDS_PER_ROW + x / 16];
+    word & (1 << (x % 16)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_layout() {
+        let mut screen = vec![0u16; SCREEN_WORDS];
+        screen[0] = 0b1; // (0, 0)
+        screen[1] = 0b100; // (18, 0)
+        screen[WORDS_PER_ROW * 3 + 31] = 0x8000; // (511, 3)
+
+        assert!(pixel(&screen, 0, 0));
+        assert!(!pixel(&screen, 1, 0));
+        assert!(pixel(&screen, 18, 0));
+        assert!(pixel(&screen, 511, 3));
+        assert!(!pixel(&screen, 511, 2));
+    }
+}
@@ -0,0 +1,155 @@
+// 端末に描画し、端末からキーを読むバックエンド（tty 機能）
+
+use anyhow::Result;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal,
+};
+use std::{
+    io::{Stdout, Write, stdout},
+    time::{Duration, Instant},
+};
+
+use super::{ScreenBackend, TtyStyle, render};
+use crate::keyboard::{HostKey, KeyMap};
+
+// 端末はキーを離したイベントを送らないので、一定時間押されたままとみなす
+const KEY_HOLD: Duration = Duration::from_millis(120);
+
+pub struct Tty {
+    out: Stdout,
+    style: TtyStyle,
+    frame_interval: Duration,
+    last_draw: Option<Instant>,
+    pending: Option<Vec<u16>>,
+    drawn: Vec<String>,
+    keymap: KeyMap,
+    key: u16,
+    key_time: Instant,
+    open: bool,
+    screenshot_requested: bool,
+}
+
+impl Tty {
+    pub fn new(style: TtyStyle, refresh_hz: u32, keymap: KeyMap) -> Result<Self> {
+        let mut out = stdout();
+        terminal::enable_raw_mode()?;
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+
+        Ok(Tty {
+            out,
+            style,
+            frame_interval: Duration::from_secs(1) / refresh_hz.max(1),
+            last_draw: None,
+            pending: None,
+            drawn: Vec::new(),
+            keymap,
+            key: 0,
+            key_time: Instant::now(),
+            open: true,
+            screenshot_requested: false,
+        })
+    }
+
+    fn draw_if_due(&mut self) -> Result<()> {
+        let due = self
+            .last_draw
+            .is_none_or(|t| t.elapsed() >= self.frame_interval);
+        if !due {
+            return Ok(());
+        }
+        let Some(screen) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let lines = render(&screen, self.style);
+
+        // 変化した行だけ書き直す
+        for (row, line) in lines.iter().enumerate() {
+            if self.drawn.get(row) != Some(line) {
+                queue!(self.out, cursor::MoveTo(0, row as u16), Print(line))?;
+            }
+        }
+        self.out.flush()?;
+
+        self.drawn = lines;
+        self.last_draw = Some(Instant::now());
+        Ok(())
+    }
+}
+
+impl ScreenBackend for Tty {
+    fn refresh(&mut self, screen: &[u16]) -> Result<()> {
+        self.pending = Some(screen.to_vec());
+        self.draw_if_due()
+    }
+
+    fn poll_key(&mut self) -> Result<u16> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Release {
+                    self.key = 0;
+                    continue;
+                }
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    self.open = false;
+                    continue;
+                }
+                self.key = host_key(key.code).map_or(0, |k| self.keymap.code(k));
+                self.key_time = Instant::now();
+            }
+        }
+
+        if self.key != 0 && self.key_time.elapsed() > KEY_HOLD {
+            self.key = 0;
+        }
+
+        self.draw_if_due()?;
+        Ok(self.key)
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn take_screenshot_request(&mut self) -> bool {
+        std::mem::take(&mut self.screenshot_requested)
+    }
+}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn host_key(code: KeyCode) -> Option<HostKey> {
+    let key = match code {
+        KeyCode::Char(c) => HostKey::Char(c),
+        KeyCode::Enter => HostKey::Enter,
+        KeyCode::Backspace => HostKey::Backspace,
+        KeyCode::Left => HostKey::Left,
+        KeyCode::Up => HostKey::Up,
+        KeyCode::Right => HostKey::Right,
+        KeyCode::Down => HostKey::Down,
+        KeyCode::Home => HostKey::Home,
+        KeyCode::End => HostKey::End,
+        KeyCode::PageUp => HostKey::PageUp,
+        KeyCode::PageDown => HostKey::PageDown,
+        KeyCode::Insert => HostKey::Insert,
+        KeyCode::Delete => HostKey::Delete,
+        KeyCode::Esc => HostKey::Esc,
+        KeyCode::F(n) => HostKey::F(n),
+        _ => return None,
+    };
+    Some(key)
+}
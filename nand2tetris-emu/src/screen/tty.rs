@@ -0,0 +1,92 @@
+// 画面を端末の文字で描く（点字と半ブロック）。描画する端末は terminal.rs
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH, pixel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TtyStyle {
+    /// 2x4 pixels per cell using Unicode braille (256x64 cells)
+    Braille,
+    /// 1x2 pixels per cell using half blocks (512x128 cells)
+    HalfBlock,
+}
+
+pub fn render(screen: &[u16], style: TtyStyle) -> Vec<String> {
+    match style {
+        TtyStyle::Braille => render_braille(screen),
+        TtyStyle::HalfBlock => render_half_block(screen),
+    }
+}
+
+// 点の並び: 1 4 / 2 5 / 3 6 / 7 8
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+fn render_braille(screen: &[u16]) -> Vec<String> {
+    (0..SCREEN_HEIGHT / 4)
+        .map(|row| {
+            (0..SCREEN_WIDTH / 2)
+                .map(|col| {
+                    let mut bits = 0;
+                    for (dy, dots) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dx, dot) in dots.iter().enumerate() {
+                            if pixel(screen, col * 2 + dx, row * 4 + dy) {
+                                bits |= dot;
+                            }
+                        }
+                    }
+                    char::from_u32(0x2800 + bits).unwrap_or(' ')
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn render_half_block(screen: &[u16]) -> Vec<String> {
+    (0..SCREEN_HEIGHT / 2)
+        .map(|row| {
+            (0..SCREEN_WIDTH)
+                .map(
+                    |x| match (pixel(screen, x, row * 2), pixel(screen, x, row * 2 + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    },
+                )
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{SCREEN_WORDS, WORDS_PER_ROW};
+
+    #[test]
+    fn test_render_braille() {
+        let mut screen = vec![0u16; SCREEN_WORDS];
+        screen[0] = 0b11; // (0,0) (1,0)
+        screen[WORDS_PER_ROW * 3] = 0b1; // (0,3)
+
+        let lines = render(&screen, TtyStyle::Braille);
+        assert_eq!(lines.len(), 64);
+        assert_eq!(lines[0].chars().count(), 256);
+        assert_eq!(lines[0].chars().next(), Some('\u{2849}'));
+        assert_eq!(lines[0].chars().nth(1), Some('\u{2800}'));
+    }
+
+    #[test]
+    fn test_render_half_block() {
+        let mut screen = vec![0u16; SCREEN_WORDS];
+        screen[0] = 0b01;
+        screen[WORDS_PER_ROW] = 0b11;
+
+        let lines = render(&screen, TtyStyle::HalfBlock);
+        assert_eq!(lines.len(), 128);
+        assert!(lines[0].starts_with("█▄ "));
+    }
+}
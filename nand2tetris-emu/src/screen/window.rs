@@ -0,0 +1,207 @@
+use anyhow::{Result, anyhow};
+use minifb::{Key, KeyRepeat, Scale, Window as MiniWindow, WindowOptions};
+use std::time::{Duration, Instant};
+
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH, ScreenBackend, pixel};
+use crate::keyboard::{HostKey, KeyMap};
+
+const BLACK: u32 = 0x00_00_00;
+const WHITE: u32 = 0xff_ff_ff;
+
+pub struct Window {
+    window: MiniWindow,
+    buffer: Vec<u32>,
+    frame_interval: Duration,
+    last_update: Instant,
+    keymap: KeyMap,
+    key: u16,
+    screenshot_requested: bool,
+}
+
+impl Window {
+    pub fn new(refresh_hz: u32, keymap: KeyMap) -> Result<Self> {
+        let mut window = MiniWindow::new(
+            "Hack CPU Emulator",
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            WindowOptions {
+                scale: Scale::X2,
+                ..WindowOptions::default()
+            },
+        )
+        .map_err(|e| anyhow!("Failed to open window: {}", e))?;
+        // 更新間隔は自前で制御する
+        window.set_target_fps(0);
+
+        Ok(Window {
+            window,
+            buffer: vec![WHITE; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_interval: Duration::from_secs(1) / refresh_hz.max(1),
+            last_update: Instant::now(),
+            keymap,
+            key: 0,
+            screenshot_requested: false,
+        })
+    }
+
+    fn update(&mut self) -> Result<()> {
+        self.window
+            .update_with_buffer(&self.buffer, SCREEN_WIDTH, SCREEN_HEIGHT)
+            .map_err(|e| anyhow!("Failed to update window: {}", e))?;
+        self.last_update = Instant::now();
+
+        let ctrl =
+            self.window.is_key_down(Key::LeftCtrl) || self.window.is_key_down(Key::RightCtrl);
+        if ctrl {
+            // Ctrl との組み合わせは Hack に渡さない
+            if self.window.is_key_pressed(Key::S, KeyRepeat::No) {
+                self.screenshot_requested = true;
+            }
+            self.key = 0;
+            return Ok(());
+        }
+
+        let shift =
+            self.window.is_key_down(Key::LeftShift) || self.window.is_key_down(Key::RightShift);
+        self.key = self
+            .window
+            .get_keys()
+            .into_iter()
+            .filter_map(|k| host_key(k, shift))
+            .map(|k| self.keymap.code(k))
+            .find(|&code| code != 0)
+            .unwrap_or(0);
+        Ok(())
+    }
+}
+
+impl ScreenBackend for Window {
+    fn refresh(&mut self, screen: &[u16]) -> Result<()> {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                self.buffer[y * SCREEN_WIDTH + x] = if pixel(screen, x, y) { BLACK } else { WHITE };
+            }
+        }
+        if self.last_update.elapsed() >= self.frame_interval {
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    fn poll_key(&mut self) -> Result<u16> {
+        if self.last_update.elapsed() >= self.frame_interval {
+            self.update()?;
+        }
+        Ok(self.key)
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    fn take_screenshot_request(&mut self) -> bool {
+        std::mem::take(&mut self.screenshot_requested)
+    }
+}
+
+fn host_key(key: Key, shift: bool) -> Option<HostKey> {
+    const LETTERS: [Key; 26] = [
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::I,
+        Key::J,
+        Key::K,
+        Key::L,
+        Key::M,
+        Key::N,
+        Key::O,
+        Key::P,
+        Key::Q,
+        Key::R,
+        Key::S,
+        Key::T,
+        Key::U,
+        Key::V,
+        Key::W,
+        Key::X,
+        Key::Y,
+        Key::Z,
+    ];
+    const DIGITS: [(Key, char, char); 10] = [
+        (Key::Key0, '0', ')'),
+        (Key::Key1, '1', '!'),
+        (Key::Key2, '2', '@'),
+        (Key::Key3, '3', '#'),
+        (Key::Key4, '4', '$'),
+        (Key::Key5, '5', '%'),
+        (Key::Key6, '6', '^'),
+        (Key::Key7, '7', '&'),
+        (Key::Key8, '8', '*'),
+        (Key::Key9, '9', '('),
+    ];
+    const PUNCTUATION: [(Key, char, char); 11] = [
+        (Key::Space, ' ', ' '),
+        (Key::Minus, '-', '_'),
+        (Key::Equal, '=', '+'),
+        (Key::LeftBracket, '[', '{'),
+        (Key::RightBracket, ']', '}'),
+        (Key::Backslash, '\\', '|'),
+        (Key::Semicolon, ';', ':'),
+        (Key::Apostrophe, '\'', '"'),
+        (Key::Comma, ',', '<'),
+        (Key::Period, '.', '>'),
+        (Key::Slash, '/', '?'),
+    ];
+
+    if let Some(i) = LETTERS.iter().position(|&k| k == key) {
+        let c = (b'a' + i as u8) as char;
+        return Some(HostKey::Char(if shift {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        }));
+    }
+    if let Some(&(_, plain, shifted)) = DIGITS
+        .iter()
+        .chain(&PUNCTUATION)
+        .find(|(k, _, _)| *k == key)
+    {
+        return Some(HostKey::Char(if shift { shifted } else { plain }));
+    }
+
+    let key = match key {
+        Key::Enter | Key::NumPadEnter => HostKey::Enter,
+        Key::Backspace => HostKey::Backspace,
+        Key::Left => HostKey::Left,
+        Key::Up => HostKey::Up,
+        Key::Right => HostKey::Right,
+        Key::Down => HostKey::Down,
+        Key::Home => HostKey::Home,
+        Key::End => HostKey::End,
+        Key::PageUp => HostKey::PageUp,
+        Key::PageDown => HostKey::PageDown,
+        Key::Insert => HostKey::Insert,
+        Key::Delete => HostKey::Delete,
+        Key::Escape => HostKey::Esc,
+        Key::F1 => HostKey::F(1),
+        Key::F2 => HostKey::F(2),
+        Key::F3 => HostKey::F(3),
+        Key::F4 => HostKey::F(4),
+        Key::F5 => HostKey::F(5),
+        Key::F6 => HostKey::F(6),
+        Key::F7 => HostKey::F(7),
+        Key::F8 => HostKey::F(8),
+        Key::F9 => HostKey::F(9),
+        Key::F10 => HostKey::F(10),
+        Key::F11 => HostKey::F(11),
+        Key::F12 => HostKey::F(12),
+        _ => return None,
+    };
+    Some(key)
+}
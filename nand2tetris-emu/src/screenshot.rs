@@ -0,0 +1,154 @@
+use anyhow::{Context, Result, bail, ensure};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH, SCREEN_WORDS, WORDS_PER_ROW, pixel};
+
+const BLACK: u8 = 0x00;
+const WHITE: u8 = 0xff;
+
+// ファイル名の {cycle} をサイクル数に置き換える
+pub fn screenshot_path(template: &Path, cycle: u64) -> PathBuf {
+    PathBuf::from(
+        template
+            .to_string_lossy()
+            .replace("{cycle}", &cycle.to_string()),
+    )
+}
+
+// 拡張子で PNG か PPM を選ぶ
+pub fn save_screenshot(path: &Path, screen: &[u16]) -> Result<()> {
+    let file = File::create(path).context(format!("Failed to create '{}'", path.display()))?;
+    let mut out = BufWriter::new(file);
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("png") => write_png(&mut out, screen)?,
+        Some("ppm") => write_ppm(&mut out, screen)?,
+        _ => bail!(
+            "Unsupported screenshot format '{}': use .png or .ppm",
+            path.display()
+        ),
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn grayscale(screen: &[u16]) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT);
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            pixels.push(if pixel(screen, x, y) { BLACK } else { WHITE });
+        }
+    }
+    pixels
+}
+
+pub fn write_png<W: Write>(out: W, screen: &[u16]) -> Result<()> {
+    let mut encoder = png::Encoder::new(out, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&grayscale(screen))?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_ppm<W: Write>(mut out: W, screen: &[u16]) -> Result<()> {
+    write!(out, "P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    for value in grayscale(screen) {
+        out.write_all(&[value, value, value])?;
+    }
+    Ok(())
+}
+
+// スクリーンショットの PNG を画面の 8192 語に戻す。暗い画素を黒とする
+pub fn load_png(path: &Path) -> Result<Vec<u16>> {
+    let file = File::open(path).context(format!("Failed to read file '{}'", path.display()))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .context(format!("Invalid PNG '{}'", path.display()))?;
+    let mut buffer = vec![0; reader.output_buffer_size().context("PNG too large")?];
+    let info = reader
+        .next_frame(&mut buffer)
+        .context(format!("Invalid PNG '{}'", path.display()))?;
+    ensure!(
+        info.width as usize == SCREEN_WIDTH && info.height as usize == SCREEN_HEIGHT,
+        "'{}' is {}x{}, not {}x{}",
+        path.display(),
+        info.width,
+        info.height,
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT
+    );
+
+    let samples = info.color_type.samples();
+    let mut screen = vec![0u16; SCREEN_WORDS];
+    for y in 0..SCREEN_HEIGHT {
+        for x in 0..SCREEN_WIDTH {
+            if buffer[y * info.line_size + x * samples] < 0x80 {
+                screen[y * WORDS_PER_ROW + x / 16] |= 1 << (x % 16);
+            }
+        }
+    }
+    Ok(screen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screenshot_path() {
+        assert_eq!(
+            screenshot_path(Path::new("shots/screen-{cycle}.png"), 42),
+            PathBuf::from("shots/screen-42.png")
+        );
+        assert_eq!(
+            screenshot_path(Path::new("out.ppm"), 42),
+            PathBuf::from("out.ppm")
+        );
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut screen = vec![0u16; SCREEN_WORDS];
+        screen[0] = 0b1;
+
+        let mut out = Vec::new();
+        write_ppm(&mut out, &screen).unwrap();
+
+        let header = b"P6\n512 256\n255\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + 512 * 256 * 3);
+        assert_eq!(
+            &out[header.len()..header.len() + 6],
+            &[0, 0, 0, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_write_png() {
+        let screen = vec![0u16; SCREEN_WORDS];
+        let mut out = Vec::new();
+        write_png(&mut out, &screen).unwrap();
+        assert!(out.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_load_png() {
+        let mut screen = vec![0u16; SCREEN_WORDS];
+        screen[0] = 0b101;
+        screen[SCREEN_WORDS - 1] = 0x8000;
+        let path = std::env::temp_dir().join(format!("screen-{}.png", std::process::id()));
+        save_screenshot(&path, &screen).unwrap();
+        assert_eq!(load_png(&path).unwrap(), screen);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,690 @@
+// JSON の HTTP API（n2t serve）
+//
+// POST /assemble、/translate、/compile、/run でアセンブル・VM 変換・Jack のコンパイル・実行を
+// 行う。ソースはすべてリクエストの JSON で受け取り、ファイルは読み書きしない。実行はサイクル数と
+// 時間で打ち切り、リクエストの大きさも制限するので、信用できない入力を受けてもよい。
+// HTTP は 1 リクエストごとに接続を閉じる最小限のもの
+//
+// POST /run {"asm": "@R0\nD=M\n...", "ram": {"0": 3}, "dump": [{"start": 0, "length": 3}]}
+// → {"stop_reason": "halted", "cycles": 12, "registers": {...}, "ram": [{"start": 0, "values": [...]}]}
+
+use anyhow::{Context, Result};
+use nand2tetris_core::{
+    diagnostic::{Severity, count, line_error},
+    status,
+    timeout::{self, Budget},
+};
+use nand2tetris_jack::{
+    analysis::{Signatures, check_class},
+    ast::Class,
+    diagnostic::Diagnostic,
+    generate,
+    lint::lint_class,
+    parser,
+};
+use nand2tetris_vm::{TranslateOptions, VMTranslator};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Cpu,
+    cpu::{RAM_SIZE, RunOptions, StopReason},
+    loader::{self, LoadedProgram, parse_hack},
+    native::{NativeOs, native_functions},
+    screen::Headless,
+};
+
+const ARG: usize = 2;
+// リクエスト行とヘッダの合計の上限
+const MAX_HEADER: u64 = 16 * 1024;
+// 遅いクライアントがワーカーを占有しないよう、読み書きはこの時間で諦める
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    // /run のサイクル数の上限。リクエストの max_cycles もこれで切る。OS を使う Jack のプログラムは
+    // 文字を 1 つ書くだけで数百万サイクルかかるので大きめにし、暴走は時間の制限で止める
+    pub max_cycles: u64,
+    // 1 リクエストの時間の制限
+    pub budget: Budget,
+    // リクエストの本体の上限（バイト）
+    pub max_body: usize,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            max_cycles: 1_000_000_000,
+            budget: Budget::default(),
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+// 応答にする前のエラー
+enum Rejection {
+    // 400。JSON の形が違う
+    BadRequest(String),
+    // 422。ソースのエラーや実行時のエラー
+    Invalid {
+        message: String,
+        diagnostics: Vec<Value>,
+    },
+}
+
+// 行の分かるエラーはその行を診断にする
+impl From<anyhow::Error> for Rejection {
+    fn from(error: anyhow::Error) -> Self {
+        let diagnostics = line_error(&error)
+            .map(|e| {
+                vec![json!({
+                    "severity": Severity::Error.label(),
+                    "file": e.file,
+                    "line": e.line,
+                    "column": null,
+                    "message": e.message,
+                })]
+            })
+            .unwrap_or_default();
+        Rejection::Invalid {
+            message: error.to_string(),
+            diagnostics,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceFile {
+    name: String,
+    source: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AssembleRequest {
+    source: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TranslateRequest {
+    files: Vec<SourceFile>,
+    #[serde(default)]
+    bootstrap: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CompileRequest {
+    files: Vec<SourceFile>,
+    #[serde(default)]
+    optimize: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Range {
+    start: u16,
+    length: usize,
+}
+
+// プログラムは asm、hack、vm、jack のどれか 1 つ
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    asm: Option<String>,
+    hack: Option<String>,
+    vm: Option<Vec<SourceFile>>,
+    jack: Option<Vec<SourceFile>>,
+    max_cycles: Option<u64>,
+    // OS の関数を Rust で実行するクラス（空ならすべて）
+    native_os: Option<Vec<String>>,
+    // 実行前に書く RAM
+    #[serde(default)]
+    ram: BTreeMap<u16, u16>,
+    // 返す RAM の範囲。なければ R0..R15
+    dump: Option<Vec<Range>>,
+    #[serde(default)]
+    screen: bool,
+}
+
+// 1 つのリクエストに答える。ネットワークとは切り離してあるのでテストから直接呼べる
+pub fn handle(method: &str, path: &str, body: &[u8], options: &ServeOptions) -> Response {
+    let path = path.split('?').next().unwrap_or_default();
+    let result = match (method, path) {
+        ("GET", "/health") => Ok(json!({ "status": "ok" })),
+        ("POST", "/assemble") => parse(body).and_then(assemble),
+        ("POST", "/translate") => parse(body).and_then(translate),
+        ("POST", "/compile") => parse(body).and_then(compile_request),
+        ("POST", "/run") => parse(body).and_then(|request| run(request, options)),
+        (_, "/health" | "/assemble" | "/translate" | "/compile" | "/run") => {
+            return Response {
+                status: 405,
+                body: json!({ "error": format!("Method {} is not allowed for {}", method, path) }),
+            };
+        }
+        _ => {
+            return Response {
+                status: 404,
+                body: json!({ "error": format!("Unknown endpoint '{}'", path) }),
+            };
+        }
+    };
+    match result {
+        Ok(body) => Response { status: 200, body },
+        Err(Rejection::BadRequest(message)) => Response {
+            status: 400,
+            body: json!({ "error": message }),
+        },
+        Err(Rejection::Invalid {
+            message,
+            diagnostics,
+        }) => Response {
+            status: 422,
+            body: json!({ "error": message, "diagnostics": diagnostics }),
+        },
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &[u8]) -> Result<T, Rejection> {
+    serde_json::from_slice(body)
+        .map_err(|e| Rejection::BadRequest(format!("Invalid request: {}", e)))
+}
+
+fn assemble(request: AssembleRequest) -> Result<Value, Rejection> {
+    let program = nand2tetris_asm::assemble_program(&request.source)?;
+    Ok(json!({ "words": program.words }))
+}
+
+fn translate(request: TranslateRequest) -> Result<Value, Rejection> {
+    let options = TranslateOptions {
+        bootstrap: request.bootstrap,
+        ..TranslateOptions::default()
+    };
+    let translation = VMTranslator::translate_sources(&vm_sources(&request.files), &options)?;
+    Ok(json!({ "asm": translation.asm }))
+}
+
+// 名前は static 変数に使うので拡張子を除く
+fn vm_sources(files: &[SourceFile]) -> Vec<(String, String)> {
+    files
+        .iter()
+        .map(|file| {
+            let name = file.name.strip_suffix(".vm").unwrap_or(&file.name);
+            (name.to_string(), file.source.clone())
+        })
+        .collect()
+}
+
+fn compile_request(request: CompileRequest) -> Result<Value, Rejection> {
+    let compiled = compile(&request.files, request.optimize)?;
+    let files: Vec<Value> = compiled
+        .sources
+        .into_iter()
+        .map(|(name, vm)| json!({ "name": format!("{}.vm", name), "vm": vm }))
+        .collect();
+    Ok(json!({ "files": files, "diagnostics": compiled.diagnostics }))
+}
+
+// (クラス名, VM コード) と警告
+struct Compiled {
+    sources: Vec<(String, String)>,
+    diagnostics: Vec<Value>,
+}
+
+// すべてのクラスを解析して検査する。エラーがあれば警告も含めたすべての診断で 422 にする
+fn compile(files: &[SourceFile], level: u8) -> Result<Compiled, Rejection> {
+    let mut found: Vec<(&str, Diagnostic)> = Vec::new();
+    let mut classes: Vec<Class> = Vec::new();
+    for file in files {
+        let (class, diagnostics) = parser::parse_with_diagnostics(&file.source);
+        found.extend(diagnostics.into_iter().map(|d| (file.name.as_str(), d)));
+        classes.extend(class);
+    }
+    // 構文エラーのあるクラスは抜けているので、検査はすべて解析できたときだけ
+    if !found.iter().any(|(_, d)| d.is_error()) {
+        let signatures = Signatures::new(&classes);
+        for (file, class) in files.iter().zip(&classes) {
+            let mut diagnostics = check_class(class, &signatures);
+            diagnostics.extend(lint_class(class));
+            found.extend(diagnostics.into_iter().map(|d| (file.name.as_str(), d)));
+        }
+    }
+    let errors = found.iter().filter(|(_, d)| d.is_error()).count();
+    let diagnostics: Vec<Value> = found
+        .iter()
+        .map(|(file, d)| {
+            json!({
+                "severity": d.severity.label(),
+                "file": file,
+                "line": d.position.line,
+                "column": d.position.column,
+                "message": d.message,
+            })
+        })
+        .collect();
+    if errors > 0 {
+        return Err(Rejection::Invalid {
+            message: format!("Compilation failed with {}", count(errors, "error")),
+            diagnostics,
+        });
+    }
+    let vm = classes
+        .iter()
+        .map(|class| Ok((class.name.name.clone(), generate(class, &classes, level)?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Compiled {
+        sources: vm,
+        diagnostics,
+    })
+}
+
+fn run(request: RunRequest, options: &ServeOptions) -> Result<Value, Rejection> {
+    let native = match &request.native_os {
+        Some(classes) => native_functions(classes)?,
+        None => Vec::new(),
+    };
+    let (program, diagnostics) = match (&request.asm, &request.hack, &request.vm, &request.jack) {
+        (Some(asm), None, None, None) => {
+            let program = nand2tetris_asm::assemble_program(asm)?;
+            (program_of(program.words), Vec::new())
+        }
+        (None, Some(hack), None, None) => (program_of(parse_hack(hack)?), Vec::new()),
+        (None, None, Some(files), None) => {
+            let (program, _) = loader::link_vm_sources("vm", &vm_sources(files), &native, false)?;
+            (program, Vec::new())
+        }
+        (None, None, None, Some(files)) => {
+            let compiled = compile(files, 0)?;
+            // Jack のプログラムは OS を呼ばなくても Sys.init から始める
+            let (program, _) = loader::link_vm_sources("jack", &compiled.sources, &native, true)?;
+            (program, compiled.diagnostics)
+        }
+        _ => {
+            return Err(Rejection::BadRequest(
+                "Give exactly one of 'asm', 'hack', 'vm' and 'jack'".to_string(),
+            ));
+        }
+    };
+    let dump = request.dump.unwrap_or_else(|| {
+        vec![Range {
+            start: 0,
+            length: 16,
+        }]
+    });
+    if let Some(range) = dump
+        .iter()
+        .find(|range| range.start as usize + range.length > RAM_SIZE)
+    {
+        return Err(Rejection::BadRequest(format!(
+            "The range {}..{} is outside the RAM",
+            range.start,
+            range.start as usize + range.length
+        )));
+    }
+
+    let mut cpu = Cpu::new(&program.words)?;
+    if let Some(classes) = &request.native_os {
+        cpu.native = Some(Box::new(NativeOs::new(&program.symbols, classes)?));
+    }
+    for (&address, &value) in &request.ram {
+        cpu.write(address, value)?;
+    }
+    cpu.sys_halt = program.symbols.resolve("Sys.halt");
+    let sys_error = program.symbols.resolve("Sys.error");
+    let run_options = RunOptions {
+        max_cycles: Some(
+            request
+                .max_cycles
+                .unwrap_or(options.max_cycles)
+                .min(options.max_cycles),
+        ),
+        breakpoints: sys_error.into_iter().collect(),
+        ..RunOptions::default()
+    };
+    // 時間切れも止まった理由の 1 つとして、そこまでの状態を返す
+    let stop_reason = match options
+        .budget
+        .run(|| cpu.run_with_backend(&mut Headless, &run_options, &mut []))
+    {
+        // ブレークポイントは Sys.error だけ
+        Ok(StopReason::Breakpoint) => "sys_error",
+        Ok(StopReason::Halted) => "halted",
+        Ok(StopReason::Finished) => "finished",
+        Ok(StopReason::MaxCycles) => "max_cycles",
+        Ok(StopReason::Closed) => unreachable!("the headless screen never closes"),
+        Err(error) if timeout::is_timeout(&error) => "timeout",
+        Err(error) => return Err(error.into()),
+    };
+
+    let ram: Vec<Value> = dump
+        .iter()
+        .map(|range| {
+            let start = range.start as usize;
+            json!({ "start": range.start, "values": &cpu.ram[start..start + range.length] })
+        })
+        .collect();
+    let mut body = json!({
+        "stop_reason": stop_reason,
+        "cycles": cpu.cycles,
+        "registers": { "a": cpu.a, "d": cpu.d, "pc": cpu.pc },
+        "ram": ram,
+        "diagnostics": diagnostics,
+    });
+    // ARG はプログラムが壊せるので、RAM の外なら error_code は付けない
+    if stop_reason == "sys_error"
+        && let Some(&code) = cpu.ram.get(cpu.ram[ARG] as usize)
+    {
+        body["error_code"] = json!(code as i16);
+    }
+    if request.screen {
+        body["screen"] = json!(cpu.screen());
+    }
+    Ok(body)
+}
+
+fn program_of(words: Vec<u16>) -> LoadedProgram {
+    LoadedProgram {
+        words,
+        symbols: Default::default(),
+        source_files: Vec::new(),
+    }
+}
+
+// addr で待ち受け、jobs 個のスレッドで 1 つずつリクエストを処理する。終了しない
+pub fn serve(addr: &str, jobs: usize, options: &ServeOptions) -> Result<()> {
+    let listener = TcpListener::bind(addr).context(format!("Failed to listen on {}", addr))?;
+    status!("Listening on http://{}", listener.local_addr()?);
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                for stream in listener.incoming().flatten() {
+                    // 1 つの接続の失敗（切断など）はほかのリクエストに影響させない
+                    let _ = respond(stream, options);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, options: &ServeOptions) -> Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let start = Instant::now();
+    let (method, path, response) = match read_request(&stream, options.max_body) {
+        Ok(Request::Complete { method, path, body }) => {
+            let response = handle(&method, &path, &body, options);
+            (method, path, response)
+        }
+        Ok(Request::TooLarge { method, path }) => {
+            let body = json!({
+                "error": format!("The request body is larger than {} bytes", options.max_body),
+            });
+            (method, path, Response { status: 413, body })
+        }
+        Err(error) => {
+            let body = json!({ "error": error.to_string() });
+            (
+                "-".to_string(),
+                "-".to_string(),
+                Response { status: 400, body },
+            )
+        }
+    };
+    status!(
+        "{} {} {} {}ms",
+        method,
+        path,
+        response.status,
+        start.elapsed().as_millis()
+    );
+    write_response(&mut stream, &response)
+}
+
+enum Request {
+    Complete {
+        method: String,
+        path: String,
+        body: Vec<u8>,
+    },
+    TooLarge {
+        method: String,
+        path: String,
+    },
+}
+
+fn read_request(stream: &TcpStream, max_body: usize) -> Result<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_HEADER));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("Invalid request line '{}'", line.trim_end());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("The request headers are incomplete or too large");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("Content-Length")
+        {
+            length = value
+                .trim()
+                .parse::<usize>()
+                .context(format!("Invalid Content-Length '{}'", value.trim()))?;
+        }
+    }
+    if length > max_body {
+        return Ok(Request::TooLarge { method, path });
+    }
+    // ヘッダと一緒に読み込み済みの分は、ソケットから読む残りに数えない
+    let buffered = reader.buffer().len().min(length);
+    reader.get_mut().set_limit((length - buffered) as u64);
+    let mut body = Vec::with_capacity(length);
+    reader.read_to_end(&mut body)?;
+    body.truncate(length);
+    if body.len() < length {
+        anyhow::bail!(
+            "The request body ended after {} of {} bytes",
+            body.len(),
+            length
+        );
+    }
+    Ok(Request::Complete { method, path, body })
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    let body = response.body.to_string() + "\n";
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason(response.status),
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nand2tetris_core::timeout::TimeoutArgs;
+
+    fn post(path: &str, body: Value) -> Response {
+        handle(
+            "POST",
+            path,
+            body.to_string().as_bytes(),
+            &ServeOptions::default(),
+        )
+    }
+
+    // RAM[0] + RAM[1] を RAM[2] に入れて止まる
+    const ADD: &str = "@R0\nD=M\n@R1\nD=D+M\n@R2\nM=D\n(END)\n@END\n0;JMP\n";
+
+    #[test]
+    fn test_assemble_and_translate() {
+        let response = post("/assemble", json!({ "source": "@2\nD=A\n" }));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["words"], json!([2, 0b1110110000010000]));
+
+        let response = post("/assemble", json!({ "source": "@2\nD=Q\n" }));
+        assert_eq!(response.status, 422);
+        assert_eq!(response.body["diagnostics"][0]["line"], 2);
+
+        let response = post(
+            "/translate",
+            json!({ "files": [{ "name": "Main.vm", "source": "push constant 7\npop static 0\n" }] }),
+        );
+        assert_eq!(response.status, 200);
+        assert!(response.body["asm"].as_str().unwrap().contains("@Main.0"));
+
+        assert_eq!(post("/assemble", json!({ "text": "" })).status, 400);
+        assert_eq!(post("/nothing", json!({})).status, 404);
+        assert_eq!(
+            handle("GET", "/run", b"", &ServeOptions::default()).status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_compile() {
+        let response = post(
+            "/compile",
+            json!({ "files": [{ "name": "Main.jack", "source":
+                "class Main { function void main() { var int x; return; } }" }] }),
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["files"][0]["name"], "Main.vm");
+        assert_eq!(response.body["diagnostics"][0]["severity"], "warning");
+
+        let response = post(
+            "/compile",
+            json!({ "files": [{ "name": "Main.jack", "source":
+                "class Main { function void main() { let y = 1; return; } }" }] }),
+        );
+        assert_eq!(response.status, 422);
+        let diagnostic = &response.body["diagnostics"][0];
+        assert_eq!(diagnostic["file"], "Main.jack");
+        assert_eq!(diagnostic["line"], 1);
+        assert_eq!(diagnostic["severity"], "error");
+    }
+
+    #[test]
+    fn test_run() {
+        let response = post(
+            "/run",
+            json!({ "asm": ADD, "ram": { "0": 2, "1": 40 }, "dump": [{ "start": 0, "length": 3 }] }),
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["stop_reason"], "halted");
+        assert_eq!(response.body["ram"][0]["values"], json!([2, 40, 42]));
+        assert_eq!(response.body["registers"]["pc"], 6);
+
+        // 無限ループもサイクル数の上限で止まる
+        let response = post(
+            "/run",
+            json!({ "asm": "(L)\n@L\nD;JEQ\n", "max_cycles": 1000 }),
+        );
+        assert_eq!(response.body["stop_reason"], "max_cycles");
+        assert_eq!(response.body["cycles"], 1000);
+
+        let response = post(
+            "/run",
+            json!({ "jack": [{ "name": "Main.jack", "source":
+                "class Main { function void main() { do Memory.poke(8000, 6 * 7); do Sys.error(9); return; } }" }],
+                "native_os": [], "dump": [{ "start": 8000, "length": 1 }] }),
+        );
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(response.body["stop_reason"], "sys_error");
+        assert_eq!(response.body["error_code"], 9);
+        assert_eq!(response.body["ram"][0]["values"], json!([42]));
+
+        // プログラムが ARG を RAM の外にしても落ちない
+        let response = post(
+            "/run",
+            json!({ "vm": [{ "name": "Sys.vm", "source":
+                "function Sys.init 0\npush constant 0\npop pointer 1\npush constant 1\npop that 0\n\
+                 call Sys.error 2\nfunction Sys.error 1\npush constant 0\nreturn\n" }] }),
+        );
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(response.body["stop_reason"], "sys_error");
+        assert!(response.body.get("error_code").is_none());
+
+        assert_eq!(post("/run", json!({ "asm": ADD, "hack": "" })).status, 400);
+        let response = post(
+            "/run",
+            json!({ "asm": ADD, "dump": [{ "start": 32767, "length": 2 }] }),
+        );
+        assert_eq!(response.status, 400);
+    }
+
+    // Jack のプログラムは OS を呼ばなくてもブートストラップから始まり、同梱の OS で最後まで動く
+    #[test]
+    fn test_run_jack() {
+        for source in [
+            "class Main { function void main() { return; } }",
+            "class Main { function void main() { do Output.printString(\"hi\"); return; } }",
+        ] {
+            let response = post(
+                "/run",
+                json!({ "jack": [{ "name": "Main.jack", "source": source }] }),
+            );
+            assert_eq!(response.status, 200, "{}", response.body);
+            assert_eq!(response.body["stop_reason"], "halted", "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let options = ServeOptions {
+            max_cycles: u64::MAX,
+            budget: TimeoutArgs {
+                timeout: Some(Duration::from_millis(50)),
+                total_timeout: None,
+            }
+            .budget(),
+            ..ServeOptions::default()
+        };
+        let body = json!({ "asm": "(L)\n@L\nD;JEQ\n" }).to_string();
+        let response = handle("POST", "/run", body.as_bytes(), &options);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["stop_reason"], "timeout");
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{Result, bail};
+use std::{collections::VecDeque, fmt::Write};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    symbols::Symbols,
+    trace::TraceEntry,
+};
+
+// VM のスタックは RAM[256..2048]。SP は次に push する位置なので 2048 までは正しい
+pub const STACK_START: u16 = 256;
+pub const STACK_END: u16 = 2048;
+
+// エラーに含める直前の命令の数
+const RECENT: usize = 16;
+
+// SP がスタックの外に出たら止める。SP が最初にスタックを指すまでは何もしない
+pub struct StackGuard {
+    symbols: Symbols,
+    recent: VecDeque<TraceEntry>,
+    armed: bool,
+}
+
+impl StackGuard {
+    pub fn new(symbols: Symbols) -> Self {
+        StackGuard {
+            symbols,
+            recent: VecDeque::with_capacity(RECENT),
+            armed: false,
+        }
+    }
+
+    fn report(&self, cpu: &Cpu, pc: u16, sp: u16) -> String {
+        let mut out = if sp < STACK_START {
+            format!(
+                "Stack underflow: SP={} is below the stack ({}..{})",
+                sp, STACK_START, STACK_END
+            )
+        } else {
+            format!(
+                "Stack overflow: SP={} is past the end of the stack ({}..{})",
+                sp, STACK_START, STACK_END
+            )
+        };
+
+        write!(out, "\n  at PC={}", pc).unwrap();
+        if let Some(location) = self.symbols.locate(pc) {
+            write!(out, " ({})", location).unwrap();
+        }
+        if let Some(source) = self.symbols.source_at(pc) {
+            write!(out, " [{}:{}]", source.file, source.line).unwrap();
+        }
+        out.push_str("\nRecent instructions:");
+        for entry in &self.recent {
+            write!(out, "\n{}", entry.format(&cpu.rom, &self.symbols)).unwrap();
+        }
+        out
+    }
+}
+
+impl StepHook for StackGuard {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()> {
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(TraceEntry::new(cpu, step));
+
+        let Some((0, sp)) = step.write else {
+            return Ok(());
+        };
+        let inside = (STACK_START..=STACK_END).contains(&sp);
+        if !self.armed {
+            self.armed = inside;
+        } else if !inside {
+            bail!("{}", self.report(cpu, step.pc, sp));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, screen::Headless};
+    use rstest::rstest;
+
+    fn run(program: &[u16]) -> Result<()> {
+        let mut cpu = Cpu::new(program).unwrap();
+        let mut guard = StackGuard::new(Symbols::default());
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut guard])
+            .map(|_| ())
+    }
+
+    const INCREMENT: u16 = 0xfdc8; // M=M+1
+    const DECREMENT: u16 = 0xfc88; // M=M-1
+
+    #[rstest]
+    #[case(STACK_START + 2, DECREMENT, None)]
+    #[case(STACK_END - 2, INCREMENT, None)]
+    #[case(
+        STACK_START + 1,
+        DECREMENT,
+        Some("Stack underflow: SP=255 is below the stack (256..2048)")
+    )]
+    #[case(
+        STACK_END - 1,
+        INCREMENT,
+        Some("Stack overflow: SP=2049 is past the end of the stack (256..2048)")
+    )]
+    fn test_stack_guard(#[case] base: u16, #[case] update: u16, #[case] expected: Option<&str>) {
+        // SP = base のあと SP を2回更新する
+        let result = run(&[base, 0xec10, 0, 0xe308, 0, update, 0, update]);
+        match expected {
+            None => result.unwrap(),
+            Some(message) => {
+                let err = result.unwrap_err().to_string();
+                let (first, rest) = err.split_once('\n').unwrap();
+                assert_eq!(first, message);
+                assert!(
+                    rest.starts_with("  at PC=7\nRecent instructions:\n"),
+                    "{}",
+                    err
+                );
+                assert_eq!(rest.lines().count(), 2 + 8);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stack_guard_waits_for_sp() {
+        // SP を初期化する前の R0 としての使い方は無視する
+        assert!(run(&[5, 0xec10, 0, 0xe308]).is_ok());
+    }
+}
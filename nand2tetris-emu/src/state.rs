@@ -0,0 +1,119 @@
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::Cpu;
+
+const MAGIC: [u8; 4] = *b"HKST";
+const VERSION: u32 = 1;
+
+// RAM・ROM・レジスタ・サイクル数のスナップショット
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    magic: [u8; 4],
+    version: u32,
+    pub rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &Cpu) -> Self {
+        Snapshot {
+            magic: MAGIC,
+            version: VERSION,
+            rom: cpu.rom[..cpu.program_len()].to_vec(),
+            ram: cpu.ram.clone(),
+            a: cpu.a,
+            d: cpu.d,
+            pc: cpu.pc,
+            cycles: cpu.cycles,
+        }
+    }
+
+    pub fn restore(&self) -> Result<Cpu> {
+        let mut cpu = Cpu::new(&self.rom)?;
+        ensure!(
+            self.ram.len() == cpu.ram.len(),
+            "Snapshot RAM has {} words (expected {})",
+            self.ram.len(),
+            cpu.ram.len()
+        );
+
+        cpu.ram.copy_from_slice(&self.ram);
+        cpu.a = self.a;
+        cpu.d = self.d;
+        cpu.pc = self.pc;
+        cpu.cycles = self.cycles;
+        Ok(cpu)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serde::encode_to_vec(
+            self,
+            bincode::config::standard(),
+        )?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (snapshot, _): (Snapshot, usize) =
+            bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+                .context("Invalid state file")?;
+        ensure!(snapshot.magic == MAGIC, "Not an emulator state file");
+        ensure!(
+            snapshot.version == VERSION,
+            "Unsupported state file version {}",
+            snapshot.version
+        );
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_bytes()?).context(format!("Failed to write '{}'", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).context(format!("Failed to read '{}'", path.display()))?;
+        Self::from_bytes(&bytes).context(format!("Failed to load '{}'", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let program = [
+            0b0000000000000000, // @0
+            0b1111110111001000, // M=M+1
+            0b0000000000000000, // @0
+            0b1110101010000111, // 0;JMP
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        cpu.run(Some(10)).unwrap();
+
+        let bytes = Snapshot::capture(&cpu).to_bytes().unwrap();
+        let mut restored = Snapshot::from_bytes(&bytes).unwrap().restore().unwrap();
+        assert_eq!(restored.ram, cpu.ram);
+        assert_eq!(
+            (restored.a, restored.d, restored.pc),
+            (cpu.a, cpu.d, cpu.pc)
+        );
+        assert_eq!(restored.cycles, 10);
+
+        // 再開した実行と通しの実行が一致する
+        restored.run(Some(20)).unwrap();
+        cpu.run(Some(20)).unwrap();
+        assert_eq!(restored.ram[0], cpu.ram[0]);
+        assert_eq!(restored.ram[0], 5);
+    }
+
+    #[test]
+    fn test_invalid_state_file() {
+        assert!(Snapshot::from_bytes(b"garbage").is_err());
+    }
+}
@@ -0,0 +1,411 @@
+// 公式の projects ディレクトリにあるテストスクリプトをまとめて実行する
+//
+// projects/01..13 の下から .cmp と組になった .tst を探し、load するファイルで実行するものを
+// 選ぶ。.hdl はハードウェアシミュレータ、.hack と .asm は CPU エミュレータ、.vm と
+// ディレクトリ（`load,`）は VM エミュレータ。.cmp のない .tst（Fill.tst のように画面を
+// 見るもの）は数えない
+
+use anyhow::{Context, Result, anyhow};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    harness::{Harness, HarnessOptions},
+    tst::{Comparison, TestRunner, run_script_with},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Engine {
+    Hardware,
+    Cpu,
+    Vm,
+}
+
+const ENGINES: [Engine; 3] = [Engine::Hardware, Engine::Cpu, Engine::Vm];
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Engine::Hardware => "Hardware",
+            Engine::Cpu => "CPU",
+            Engine::Vm => "VM",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuiteTest {
+    // 2 桁のプロジェクト番号（01..13）
+    pub project: String,
+    pub script: PathBuf,
+    pub engine: Engine,
+}
+
+// path が nand2tetris のディレクトリ（projects を含む）か projects そのものなら projects
+pub fn projects_dir(path: &Path) -> Option<PathBuf> {
+    let projects = path.join("projects");
+    if projects.is_dir() {
+        return Some(projects);
+    }
+    let has_projects = fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.path().is_dir() && project_number(&entry.file_name()).is_some());
+    has_projects.then(|| path.to_path_buf())
+}
+
+// 01 でも 1 でもよい
+fn project_number(name: &OsStr) -> Option<u8> {
+    name.to_str()?
+        .parse()
+        .ok()
+        .filter(|number| (1..=13).contains(number))
+}
+
+// プロジェクト順、プロジェクトの中はパスの名前順
+pub fn discover(projects: &Path) -> Result<Vec<SuiteTest>> {
+    let mut dirs: Vec<(u8, PathBuf)> = fs::read_dir(projects)
+        .context(format!("Failed to read directory '{}'", projects.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| Some((project_number(&entry.file_name())?, entry.path())))
+        .collect();
+    dirs.sort();
+
+    let mut tests = Vec::new();
+    for (number, dir) in dirs {
+        tests.extend(discover_in(&dir, &format!("{:02}", number))?);
+    }
+    Ok(tests)
+}
+
+// dir の下の .cmp と組になった .tst をパスの名前順に。project はそのまま付ける
+pub fn discover_in(dir: &Path, project: &str) -> Result<Vec<SuiteTest>> {
+    let mut scripts = Vec::new();
+    find_scripts(dir, &mut scripts)?;
+    scripts.sort();
+    let mut tests = Vec::new();
+    for script in scripts {
+        let source = fs::read_to_string(&script)
+            .context(format!("Failed to read file '{}'", script.display()))?;
+        let script_dir = script.parent().unwrap_or(Path::new("."));
+        let compared =
+            argument(&source, "compare-to").is_some_and(|cmp| script_dir.join(cmp).is_file());
+        if compared {
+            tests.push(SuiteTest {
+                project: project.to_string(),
+                engine: engine(&source),
+                script,
+            });
+        }
+    }
+    Ok(tests)
+}
+
+fn find_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)
+        .context(format!("Failed to read directory '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok())
+    {
+        let path = entry.path();
+        if path.is_dir() {
+            find_scripts(&path, scripts)?;
+        } else if path.extension().is_some_and(|ext| ext == "tst") {
+            scripts.push(path);
+        }
+    }
+    Ok(())
+}
+
+// load するファイルの拡張子で決める。load のないスクリプトはハードウェアとみなす
+pub fn engine(source: &str) -> Engine {
+    match argument(source, "load") {
+        Some(file) if file.ends_with(".hdl") => Engine::Hardware,
+        Some(file) if file.ends_with(".hack") || file.ends_with(".asm") => Engine::Cpu,
+        Some(_) => Engine::Vm,
+        None => Engine::Hardware,
+    }
+}
+
+// 最初の command の引数。`load,` のように引数がなければ空
+pub fn argument(source: &str, command: &str) -> Option<String> {
+    let text = strip_comments(source).replace([',', ';'], " ; ");
+    let mut words = text.split_whitespace();
+    words.find(|&word| word == command)?;
+    Some(match words.next() {
+        Some(";") | None => String::new(),
+        Some(word) => word.to_string(),
+    })
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut text = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find('/') {
+        text.push_str(&rest[..start]);
+        let comment = &rest[start..];
+        rest = if comment.starts_with("//") {
+            comment.find('\n').map_or("", |end| &comment[end..])
+        } else if comment.starts_with("/*") {
+            comment.find("*/").map_or("", |end| &comment[end + 2..])
+        } else {
+            text.push('/');
+            &comment[1..]
+        };
+    }
+    text.push_str(rest);
+    text
+}
+
+pub fn run(test: &SuiteTest) -> Result<()> {
+    run_script(&test.script, true).map(|_| ())
+}
+
+// load するファイルに合わせて 1 本のスクリプトを実行する。比較が合わなければエラー。
+// translate_vm なら、ない .asm の代わりに .vm を変換する（project 7, 8 の CPU 用スクリプト）
+pub fn run_script(script: &Path, translate_vm: bool) -> Result<Comparison> {
+    let source = fs::read_to_string(script)
+        .context(format!("Failed to read file '{}'", script.display()))?;
+    let dir = script.parent().unwrap_or(Path::new("."));
+    match engine(&source) {
+        Engine::Hardware => Ok(match nand2tetris_hdl::tst::run_script(script, None)? {
+            nand2tetris_hdl::tst::Comparison::Passed => Comparison::Passed,
+            // 比較の失敗はエラーで返る
+            _ => Comparison::Skipped,
+        }),
+        Engine::Cpu if translate_vm => {
+            run_script_with(script, TestRunner::new(dir).translating_vm())
+        }
+        // project 12 のテストのように .jack しかなければコンパイルして OS とつなぐ
+        Engine::Vm if has_extension(dir, "jack")? && !has_extension(dir, "vm")? => {
+            let harness = Harness::build(dir, HarnessOptions::default())?;
+            match harness
+                .run_scripts(std::slice::from_ref(&script.to_path_buf()))
+                .remove(0)
+            {
+                result if result.error.is_none() => Ok(Comparison::Passed),
+                result => Err(anyhow!("{}", result.error.unwrap_or_default())),
+            }
+        }
+        Engine::Cpu | Engine::Vm => run_script_with(script, TestRunner::new(dir)),
+    }
+}
+
+fn has_extension(dir: &Path, extension: &str) -> Result<bool> {
+    Ok(fs::read_dir(dir)
+        .context(format!("Failed to read directory '{}'", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.path().extension().is_some_and(|ext| ext == extension)))
+}
+
+// プロジェクトとエンジンごとの（合格, 全体）
+#[derive(Debug, Default)]
+pub struct Matrix {
+    rows: BTreeMap<String, BTreeMap<Engine, (usize, usize)>>,
+}
+
+impl Matrix {
+    pub fn add(&mut self, test: &SuiteTest, passed: bool) {
+        let cell = self
+            .rows
+            .entry(test.project.clone())
+            .or_default()
+            .entry(test.engine)
+            .or_default();
+        cell.0 += passed as usize;
+        cell.1 += 1;
+    }
+
+    // Project  Hardware  CPU  VM   Total
+    // 01       15/15     -    -    15/15
+    pub fn format(&self) -> String {
+        let mut totals: BTreeMap<Engine, (usize, usize)> = BTreeMap::new();
+        let mut table = vec![
+            ["Project".to_string()]
+                .into_iter()
+                .chain(ENGINES.iter().map(Engine::to_string))
+                .chain(["Total".to_string()])
+                .collect::<Vec<String>>(),
+        ];
+        for (project, cells) in &self.rows {
+            for (engine, (passed, total)) in cells {
+                let sum = totals.entry(*engine).or_default();
+                sum.0 += passed;
+                sum.1 += total;
+            }
+            table.push(row(project, cells));
+        }
+        table.push(row("Total", &totals));
+
+        let widths: Vec<usize> = (0..table[0].len())
+            .map(|i| table.iter().map(|row| row[i].len()).max().unwrap_or(0))
+            .collect();
+        table
+            .iter()
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, &width)| format!("{:width$}", cell))
+                    .collect();
+                format!("{}\n", cells.join("  ").trim_end())
+            })
+            .collect()
+    }
+}
+
+fn row(name: &str, cells: &BTreeMap<Engine, (usize, usize)>) -> Vec<String> {
+    let cell = |&(passed, total): &(usize, usize)| format!("{}/{}", passed, total);
+    let sum = cells.values().fold((0, 0), |sum, (passed, total)| {
+        (sum.0 + passed, sum.1 + total)
+    });
+    [name.to_string()]
+        .into_iter()
+        .chain(
+            ENGINES
+                .iter()
+                .map(|engine| cells.get(engine).map_or("-".to_string(), cell)),
+        )
+        .chain([cell(&sum)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_engine() {
+        assert_eq!(
+            engine("// And\nload And.hdl,\noutput-file And.out;"),
+            Engine::Hardware
+        );
+        assert_eq!(engine("load Mult.asm, compare-to Mult.cmp;"), Engine::Cpu);
+        assert_eq!(engine("/* load X.hdl */ load SimpleAdd.vm;"), Engine::Vm);
+        assert_eq!(engine("load, // the directory\n"), Engine::Vm);
+        assert_eq!(argument("load,", "load"), Some(String::new()));
+        assert_eq!(
+            argument("compare-to SimpleAdd.cmp,", "compare-to").as_deref(),
+            Some("SimpleAdd.cmp")
+        );
+    }
+
+    fn write(dir: &Path, name: &str, text: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn test_run_suite() {
+        let root = env::temp_dir().join(format!("emu-suite-{}", std::process::id()));
+        let projects = root.join("projects");
+        write(
+            &projects,
+            "01/Not.hdl",
+            "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }",
+        );
+        write(
+            &projects,
+            "01/Not.tst",
+            "load Not.hdl, output-file Not.out, compare-to Not.cmp, output-list in out;\n\
+             set in 0, eval, output;\nset in 1, eval, output;\n",
+        );
+        write(&projects, "01/Not.cmp", "|in |out|\n| 0 | 1 |\n| 1 | 0 |\n");
+        write(&projects, "04/fill/Fill.tst", "load Fill.asm;\n");
+        // 7 の CPU 用スクリプトは .asm がなくても .vm から作る
+        write(
+            &projects,
+            "07/SimpleAdd/SimpleAdd.vm",
+            "push constant 7\npush constant 8\nadd\n",
+        );
+        let columns = "output-list RAM[0]%D2.6.2 RAM[256]%D2.6.2;";
+        write(
+            &projects,
+            "07/SimpleAdd/SimpleAdd.tst",
+            &format!(
+                "load SimpleAdd.asm, output-file SimpleAdd.out, compare-to SimpleAdd.cmp,\n\
+                 {}\nset RAM[0] 256, repeat 60 {{ ticktock; }} output;\n",
+                columns
+            ),
+        );
+        write(
+            &projects,
+            "07/SimpleAdd/SimpleAddVME.tst",
+            &format!(
+                "load SimpleAdd.vm, output-file SimpleAdd.out, compare-to SimpleAdd.cmp,\n\
+                 {}\nset sp 256, repeat 3 {{ vmstep; }} output;\n",
+                columns
+            ),
+        );
+        write(
+            &projects,
+            "07/SimpleAdd/SimpleAdd.cmp",
+            "|  RAM[0]  | RAM[256] |\n|     257  |      15  |\n",
+        );
+        write(
+            &projects,
+            "07/Wrong/Wrong.vm",
+            "push constant 1\npush constant 1\nadd\n",
+        );
+        write(
+            &projects,
+            "07/Wrong/WrongVME.tst",
+            &format!(
+                "load Wrong.vm, compare-to Wrong.cmp, {}\nset sp 256, repeat 3 {{ vmstep; }} output;\n",
+                columns
+            ),
+        );
+        write(
+            &projects,
+            "07/Wrong/Wrong.cmp",
+            "|  RAM[0]  | RAM[256] |\n|     257  |       3  |\n",
+        );
+
+        let found = projects_dir(&root);
+        let tests = discover(&projects);
+        let mut matrix = Matrix::default();
+        let mut failed = Vec::new();
+        for test in tests.as_ref().unwrap() {
+            let result = run(test);
+            if let Err(e) = &result {
+                failed.push(format!("{}: {}", test.script.display(), e));
+            }
+            matrix.add(test, result.is_ok());
+        }
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(found, Some(projects.clone()));
+        let tests = tests.unwrap();
+        let names: Vec<(&str, &Path, Engine)> = tests
+            .iter()
+            .map(|test| {
+                let script = test.script.strip_prefix(&projects).unwrap();
+                (test.project.as_str(), script, test.engine)
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("01", Path::new("01/Not.tst"), Engine::Hardware),
+                ("07", Path::new("07/SimpleAdd/SimpleAdd.tst"), Engine::Cpu),
+                ("07", Path::new("07/SimpleAdd/SimpleAddVME.tst"), Engine::Vm),
+                ("07", Path::new("07/Wrong/WrongVME.tst"), Engine::Vm),
+            ]
+        );
+        assert_eq!(failed.len(), 1, "{:?}", failed);
+        assert!(failed[0].contains("WrongVME.tst"));
+        assert_eq!(
+            matrix.format(),
+            "Project  Hardware  CPU  VM   Total\n\
+             01       1/1       -    -    1/1\n\
+             07       -         1/1  1/2  2/3\n\
+             Total    1/1       1/1  1/2  3/4\n"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use anyhow::{Context, Result, anyhow, bail};
+use nand2tetris_vm::SourceLocation;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+// ROM アドレスとラベル名・元ソースの対応と、RAM アドレスと変数名の対応。
+// .asm / .vm から読み込んだときか、アセンブラの .sym があるときだけ使える
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    by_address: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+    variables: BTreeMap<u16, String>,
+    variables_by_name: HashMap<String, u16>,
+    sources: Vec<Option<SourceLocation>>,
+}
+
+impl Symbols {
+    pub fn from_labels(labels: &HashMap<String, u16>) -> Self {
+        let mut by_address = BTreeMap::new();
+        for (name, &address) in labels {
+            // 同じアドレスに複数のラベルがあれば名前順で先のものを使う
+            by_address
+                .entry(address)
+                .and_modify(|current: &mut String| {
+                    if *name < *current {
+                        *current = name.clone();
+                    }
+                })
+                .or_insert_with(|| name.clone());
+        }
+        Symbols {
+            by_address,
+            by_name: labels.clone(),
+            ..Symbols::default()
+        }
+    }
+
+    // アセンブラの --sym が書く「label 名前 アドレス」「var 名前 アドレス」の行を読む。
+    // build が書く「source ファイル:行 アドレス」は ROM アドレスの元ソースの位置
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut labels = HashMap::new();
+        let mut variables = HashMap::new();
+        let mut sources = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [kind, name, address] = fields[..] else {
+                bail!("line {}: expected '<kind> <name> <address>'", index + 1);
+            };
+            let address: u16 = address
+                .parse()
+                .map_err(|_| anyhow!("line {}: invalid address '{}'", index + 1, address))?;
+            match kind {
+                "label" => {
+                    labels.insert(name.to_string(), address);
+                }
+                "var" => {
+                    variables.insert(name.to_string(), address);
+                }
+                "source" => {
+                    let location = name
+                        .rsplit_once(':')
+                        .and_then(|(file, line)| Some((file, line.parse().ok()?)))
+                        .map(|(file, line)| SourceLocation {
+                            file: file.to_string(),
+                            line,
+                        })
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "line {}: expected '<file>:<line>' but found '{}'",
+                                index + 1,
+                                name
+                            )
+                        })?;
+                    let address = address as usize;
+                    if sources.len() <= address {
+                        sources.resize(address + 1, None);
+                    }
+                    sources[address] = Some(location);
+                }
+                _ => bail!("line {}: unknown symbol kind '{}'", index + 1, kind),
+            }
+        }
+        Ok(Symbols::from_labels(&labels)
+            .with_variables(&variables)
+            .with_sources(sources))
+    }
+
+    // parse で読める形。ラベルと変数はアドレス順（アセンブラの --sym と同じ）、その後に元ソースの位置
+    pub fn format(&self) -> String {
+        let mut labels: Vec<_> = self.by_name.iter().collect();
+        labels.sort_by_key(|&(name, &address)| (address, name));
+        let mut variables: Vec<_> = self.variables_by_name.iter().collect();
+        variables.sort_by_key(|&(name, &address)| (address, name));
+
+        let mut out = String::from("# hack symbols v1\n");
+        for (name, address) in labels {
+            out.push_str(&format!("label {} {}\n", name, address));
+        }
+        for (name, address) in variables {
+            out.push_str(&format!("var {} {}\n", name, address));
+        }
+        for (address, source) in self.sources.iter().enumerate() {
+            if let Some(source) = source {
+                out.push_str(&format!(
+                    "source {}:{} {}\n",
+                    source.file, source.line, address
+                ));
+            }
+        }
+        out
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read file '{}'", path.display()))?;
+        Symbols::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn with_variables(mut self, variables: &HashMap<String, u16>) -> Self {
+        self.variables = variables
+            .iter()
+            .map(|(name, &address)| (address, name.clone()))
+            .collect();
+        self.variables_by_name = variables.clone();
+        self
+    }
+
+    // ROM アドレスごとの元ソースの位置
+    pub fn with_sources(mut self, sources: Vec<Option<SourceLocation>>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    pub fn has_sources(&self) -> bool {
+        self.sources.iter().any(Option::is_some)
+    }
+
+    pub fn source_at(&self, address: u16) -> Option<&SourceLocation> {
+        self.sources.get(address as usize)?.as_ref()
+    }
+
+    // ソースの行に対応する最初の ROM アドレス
+    pub fn address_of(&self, file: &str, line: usize) -> Option<u16> {
+        self.sources
+            .iter()
+            .position(|source| {
+                source
+                    .as_ref()
+                    .is_some_and(|source| source.file == file && source.line == line)
+            })
+            .map(|address| address as u16)
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.variables
+            .iter()
+            .map(|(&address, name)| (address, name.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty() && self.variables_by_name.is_empty()
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn variable_at(&self, address: u16) -> Option<&str> {
+        self.variables.get(&address).map(String::as_str)
+    }
+
+    pub fn resolve_variable(&self, name: &str) -> Option<u16> {
+        self.variables_by_name.get(name).copied()
+    }
+
+    // 関数の入口（VM 関数がなければすべてのラベル）をアドレス順に並べる
+    pub fn regions(&self) -> Vec<(u16, &str)> {
+        let functions: BTreeMap<u16, &str> = self
+            .by_name
+            .iter()
+            .filter(|(name, _)| is_function(name))
+            .fold(BTreeMap::new(), |mut functions, (name, &address)| {
+                let current = functions.entry(address).or_insert(name.as_str());
+                *current = (*current).min(name.as_str());
+                functions
+            });
+        if functions.is_empty() {
+            return self
+                .by_address
+                .iter()
+                .map(|(&address, name)| (address, name.as_str()))
+                .collect();
+        }
+        functions.into_iter().collect()
+    }
+
+    // アドレスを含む VM 関数
+    pub fn function_at(&self, address: u16) -> Option<&str> {
+        self.by_name
+            .iter()
+            .filter(|(name, base)| **base <= address && is_function(name))
+            .max_by_key(|(name, base)| (**base, std::cmp::Reverse(name.as_str())))
+            .map(|(name, _)| name.as_str())
+    }
+
+    // 直前のラベルからの相対位置（例: LOOP+3）
+    pub fn locate(&self, address: u16) -> Option<String> {
+        let (&base, name) = self.by_address.range(..=address).next_back()?;
+        Some(match address - base {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+}
+
+// VM 関数名は Class.name で、関数内のラベルと戻りアドレスには $ が付く
+fn is_function(name: &str) -> bool {
+    name.contains('.') && !name.contains('$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        let symbols = Symbols::from_labels(&HashMap::from([
+            ("LOOP".to_string(), 2),
+            ("END".to_string(), 10),
+            ("ALIAS".to_string(), 10),
+        ]));
+
+        assert_eq!(symbols.label_at(10), Some("ALIAS"));
+        assert_eq!(symbols.label_at(3), None);
+        assert_eq!(symbols.resolve("LOOP"), Some(2));
+        assert_eq!(symbols.locate(1), None);
+        assert_eq!(symbols.locate(2).as_deref(), Some("LOOP"));
+        assert_eq!(symbols.locate(5).as_deref(), Some("LOOP+3"));
+    }
+
+    #[test]
+    fn test_parse_sym_file() {
+        let symbols =
+            Symbols::parse("# hack symbols v1\nlabel LOOP 2\n\nvar counter 16\n").unwrap();
+
+        assert_eq!(symbols.resolve("LOOP"), Some(2));
+        assert_eq!(symbols.variable_at(16), Some("counter"));
+        assert_eq!(symbols.resolve_variable("counter"), Some(16));
+        assert_eq!(symbols.resolve("counter"), None);
+
+        let err = Symbols::parse("label LOOP\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: expected '<kind> <name> <address>'"
+        );
+        assert!(Symbols::parse("const X 1").is_err());
+    }
+
+    #[test]
+    fn test_format_sym_file() {
+        let text = "# hack symbols v1\nlabel Main.main 2\nvar counter 16\nsource Main:3 2\nsource Main:4 5\n";
+        let symbols = Symbols::parse(text).unwrap();
+        assert_eq!(symbols.source_at(1), None);
+        assert_eq!(symbols.source_at(5).map(|s| s.line), Some(4));
+        assert_eq!(symbols.address_of("Main", 3), Some(2));
+        assert_eq!(symbols.format(), text);
+
+        let err = Symbols::parse("source Main 2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: expected '<file>:<line>' but found 'Main'"
+        );
+    }
+}
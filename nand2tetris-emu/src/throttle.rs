@@ -0,0 +1,63 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// 目標クロック周波数に合わせて実行を遅らせる
+pub struct Throttle {
+    hz: u64,
+    start: Instant,
+    start_cycles: u64,
+    check_interval: u64,
+}
+
+impl Throttle {
+    pub fn new(hz: u64, start_cycles: u64) -> Self {
+        let hz = hz.max(1);
+        Throttle {
+            hz,
+            start: Instant::now(),
+            start_cycles,
+            // 1秒あたり約100回調整する
+            check_interval: (hz / 100).max(1),
+        }
+    }
+
+    pub fn check_interval(&self) -> u64 {
+        self.check_interval
+    }
+
+    // 実行済みサイクルに対して経過時間が足りなければ待つ
+    pub fn wait(&self, cycles: u64) {
+        let elapsed = self.start.elapsed();
+        let target = self.target_time(cycles);
+        if target > elapsed {
+            thread::sleep(target - elapsed);
+        }
+    }
+
+    fn target_time(&self, cycles: u64) -> Duration {
+        let executed = cycles.saturating_sub(self.start_cycles) as u128;
+        Duration::from_nanos((executed * 1_000_000_000 / self.hz as u128) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_time() {
+        let throttle = Throttle::new(1000, 500);
+        assert_eq!(throttle.target_time(500), Duration::ZERO);
+        assert_eq!(throttle.target_time(1500), Duration::from_secs(1));
+        assert_eq!(throttle.check_interval(), 10);
+    }
+
+    #[test]
+    fn test_wait_throttles() {
+        let throttle = Throttle::new(1000, 0);
+        throttle.wait(50);
+        assert!(throttle.start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::{io::Write, ops::RangeInclusive};
+
+use crate::{
+    Cpu,
+    cpu::{StepHook, StepInfo},
+    disasm::disassemble_at,
+    symbols::Symbols,
+};
+
+// トレースの1行分（命令の実行後の A と D）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycles: u64,
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    pub write: Option<(u16, u16)>,
+}
+
+impl TraceEntry {
+    pub fn new(cpu: &Cpu, step: &StepInfo) -> Self {
+        TraceEntry {
+            cycles: cpu.cycles,
+            pc: step.pc,
+            a: cpu.a,
+            d: cpu.d,
+            write: step.write,
+        }
+    }
+
+    pub fn format(&self, rom: &[u16], symbols: &Symbols) -> String {
+        let mut line = format!(
+            "{:>10} PC={:<5} {:<14} A={:<6} D={}",
+            self.cycles,
+            self.pc,
+            disassemble_at(rom, self.pc, symbols),
+            self.a as i16,
+            self.d as i16
+        );
+        if let Some((address, value)) = self.write {
+            line.push_str(&format!(" RAM[{}]={}", address, value as i16));
+        }
+        line
+    }
+}
+
+// 実行した命令を1行ずつ書き出す
+pub struct Tracer<W: Write> {
+    out: W,
+    filter: Vec<RangeInclusive<u16>>,
+    symbols: Symbols,
+}
+
+impl<W: Write> Tracer<W> {
+    // filter が空なら全命令を出力する
+    pub fn new(out: W, filter: Vec<RangeInclusive<u16>>) -> Self {
+        Tracer {
+            out,
+            filter,
+            symbols: Symbols::default(),
+        }
+    }
+
+    // ラベルの付いたアドレスに入ったら (LABEL) 行を挟み、@ の値を名前で表示する
+    pub fn with_symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> StepHook for Tracer<W> {
+    fn on_step(&mut self, cpu: &Cpu, step: &StepInfo) -> Result<()> {
+        if !self.filter.is_empty() && !self.filter.iter().any(|r| r.contains(&step.pc)) {
+            return Ok(());
+        }
+
+        if let Some(label) = self.symbols.label_at(step.pc) {
+            writeln!(self.out, "({})", label)?;
+        }
+        let entry = TraceEntry::new(cpu, step);
+        writeln!(self.out, "{}", entry.format(&cpu.rom, &self.symbols))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::RunOptions, screen::Headless};
+
+    fn trace(filter: Vec<RangeInclusive<u16>>) -> String {
+        trace_with(filter, Symbols::default())
+    }
+
+    fn trace_with(filter: Vec<RangeInclusive<u16>>, symbols: Symbols) -> String {
+        let program = [
+            0b0000000000000010, // @2
+            0b1110110000010000, // D=A
+            0b0000000000000000, // @0
+            0b1110001100001000, // M=D
+        ];
+        let mut cpu = Cpu::new(&program).unwrap();
+        let mut tracer = Tracer::new(Vec::new(), filter).with_symbols(symbols);
+        cpu.run_with_backend(&mut Headless, &RunOptions::default(), &mut [&mut tracer])
+            .unwrap();
+        String::from_utf8(tracer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_trace_all() {
+        let out = trace(vec![]);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].contains("PC=1") && lines[1].contains("D=A") && lines[1].contains("D=2"));
+        assert!(lines[3].ends_with("RAM[0]=2"));
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        let out = trace(vec![3..=3]);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.contains("M=D"));
+    }
+
+    #[test]
+    fn test_trace_labels() {
+        let symbols = Symbols::from_labels(&[("STORE".to_string(), 2)].into());
+        let out = trace_with(vec![2..=3], symbols);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "(STORE)");
+    }
+}
@@ -0,0 +1,413 @@
+// CPU エミュレータ用テストスクリプト（.tst）
+mod output;
+mod parser;
+pub mod record;
+
+pub use output::{format_header, format_row};
+pub use parser::{Column, Command, Condition, Op, Radix, Variable, parse_script};
+
+use anyhow::{Context, Result, anyhow};
+use nand2tetris_core::{
+    cli::{Fail, Failure},
+    timeout,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Cpu,
+    loader::{self, LoadedProgram},
+    native::NativeOs,
+    symbols::Symbols,
+};
+
+pub struct TestRunner {
+    dir: PathBuf,
+    cpu: Cpu,
+    // スクリプトのディレクトリを load したときに使うプログラム（Jack のテスト）
+    program: Option<LoadedProgram>,
+    native_os: Option<Vec<String>>,
+    // load する .asm がなければ、同じ名前の .vm かスクリプトのディレクトリを変換して使う
+    translate_vm: bool,
+    // VM コマンド（Jack の行）の先頭の ROM アドレス
+    vm_starts: Vec<bool>,
+    columns: Vec<Column>,
+    output: Vec<String>,
+    output_file: Option<PathBuf>,
+    compare_to: Option<(PathBuf, Vec<String>)>,
+    mismatch: Option<Mismatch>,
+}
+
+// .cmp と最初に食い違った行（line は 1 始まり）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    // compare-to が指定されていない
+    Skipped,
+    Passed,
+    Failed(Mismatch),
+}
+
+impl TestRunner {
+    // ファイル名はスクリプトのディレクトリからの相対パスとして解決する
+    pub fn new(dir: &Path) -> Self {
+        TestRunner {
+            dir: dir.to_path_buf(),
+            cpu: Cpu::new(&[]).expect("empty program fits in ROM"),
+            program: None,
+            native_os: None,
+            translate_vm: false,
+            vm_starts: Vec::new(),
+            columns: Vec::new(),
+            output: Vec::new(),
+            output_file: None,
+            compare_to: None,
+            mismatch: None,
+        }
+    }
+
+    // `load,` と `load <dir>` で program を使う。native_os は run --native-os と同じ
+    pub fn with_program(
+        dir: &Path,
+        program: LoadedProgram,
+        native_os: Option<Vec<String>>,
+    ) -> Self {
+        TestRunner {
+            program: Some(program),
+            native_os,
+            ..TestRunner::new(dir)
+        }
+    }
+
+    // VM 変換器の書いた .asm を読むスクリプト（project 7, 8）を、変換せずに実行する
+    pub fn translating_vm(mut self) -> Self {
+        self.translate_vm = true;
+        self
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    // 比較に失敗したらその時点でスクリプトを止める
+    pub fn execute(&mut self, commands: &[Command]) -> Result<()> {
+        for command in commands {
+            if self.mismatch.is_some() {
+                break;
+            }
+            self.execute_command(command)?;
+        }
+        Ok(())
+    }
+
+    fn execute_command(&mut self, command: &Command) -> Result<()> {
+        timeout::check()?;
+        match command {
+            Command::Load(file) => {
+                let path = self.dir.join(file);
+                let loaded;
+                let program = match &self.program {
+                    Some(program) if file.as_os_str().is_empty() || path == self.dir => program,
+                    _ if self.translate_vm
+                        && path.extension().is_some_and(|ext| ext == "asm")
+                        && !path.exists() =>
+                    {
+                        let vm = path.with_extension("vm");
+                        let source = if vm.is_file() { &vm } else { &self.dir };
+                        loaded = loader::load_program(source, None)?;
+                        &loaded
+                    }
+                    _ => {
+                        loaded = loader::load_program(&path, None)?;
+                        &loaded
+                    }
+                };
+                self.cpu = Cpu::new(&program.words)?;
+                if let Some(classes) = &self.native_os {
+                    self.cpu.native = Some(Box::new(NativeOs::new(&program.symbols, classes)?));
+                }
+                self.vm_starts = vm_starts(&program.symbols, program.words.len());
+            }
+            Command::OutputFile(file) => self.output_file = Some(self.dir.join(file)),
+            Command::CompareTo(file) => {
+                let path = self.dir.join(file);
+                let expected = fs::read_to_string(&path)
+                    .context(format!("Failed to read file '{}'", path.display()))?;
+                // CRLF の .cmp もそのまま比較できるようにする
+                let lines = expected
+                    .lines()
+                    .map(|line| line.trim_end_matches('\r').to_string())
+                    .collect();
+                self.compare_to = Some((path, lines));
+            }
+            Command::OutputList(columns) => {
+                self.columns = columns.clone();
+                self.push_line(format_header(&self.columns));
+            }
+            Command::Set(variable, value) => self.set(*variable, *value),
+            Command::TickTock => {
+                self.cpu.step()?;
+            }
+            Command::VmStep => {
+                // 次の VM コマンドの先頭か、後ろへのジャンプ（ループ）まで進める
+                let mut pc = self.cpu.pc;
+                self.cpu.step()?;
+                while self.vm_starts.get(self.cpu.pc as usize) == Some(&false) && self.cpu.pc > pc {
+                    pc = self.cpu.pc;
+                    self.cpu.step()?;
+                }
+            }
+            Command::Output => {
+                let row = format_row(&self.columns, |variable| self.get(variable));
+                self.push_line(row);
+            }
+            Command::Echo(text) => println!("{}", text),
+            // ブレークポイントはバッチ実行では意味を持たない
+            Command::ClearEcho | Command::Breakpoint(..) | Command::ClearBreakpoints => {}
+            Command::Repeat(Some(count), body) => {
+                for _ in 0..*count {
+                    self.execute(body)?;
+                }
+            }
+            Command::Repeat(None, body) => {
+                while self.mismatch.is_none() {
+                    self.execute(body)?;
+                }
+            }
+            Command::While(condition, body) => {
+                while self.mismatch.is_none() && self.holds(condition) {
+                    self.execute(body)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push_line(&mut self, line: String) {
+        if let Some((_, expected)) = &self.compare_to
+            && self.mismatch.is_none()
+        {
+            let index = self.output.len();
+            let expected = expected.get(index);
+            if expected != Some(&line) {
+                self.mismatch = Some(Mismatch {
+                    line: index + 1,
+                    expected: expected.cloned(),
+                    actual: line.clone(),
+                });
+            }
+        }
+        self.output.push(line);
+    }
+
+    pub fn get(&self, variable: Variable) -> u16 {
+        match variable {
+            Variable::A => self.cpu.a,
+            Variable::D => self.cpu.d,
+            Variable::Pc => self.cpu.pc,
+            Variable::Time => self.cpu.cycles as u16,
+            Variable::Ram(address) => self.cpu.ram[address as usize],
+            Variable::Rom(address) => self.cpu.rom[address as usize],
+        }
+    }
+
+    fn set(&mut self, variable: Variable, value: u16) {
+        match variable {
+            Variable::A => self.cpu.a = value,
+            Variable::D => self.cpu.d = value,
+            Variable::Pc => self.cpu.pc = value,
+            Variable::Time => self.cpu.cycles = value as u64,
+            Variable::Ram(address) => self.cpu.ram[address as usize] = value,
+            Variable::Rom(address) => self.cpu.rom[address as usize] = value,
+        }
+    }
+
+    // 比較は符号付き16ビットで行う
+    fn holds(&self, condition: &Condition) -> bool {
+        let left = self.get(condition.variable) as i16;
+        let right = condition.value as i16;
+        match condition.op {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Gt => left > right,
+            Op::Le => left <= right,
+            Op::Ge => left >= right,
+        }
+    }
+
+    // 出力ファイルを書き、比較の結果を返す
+    pub fn finish(&self) -> Result<Comparison> {
+        if let Some(path) = &self.output_file {
+            let text: String = self
+                .output
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            fs::write(path, text).context(format!("Failed to write '{}'", path.display()))?;
+        }
+
+        Ok(match (&self.compare_to, &self.mismatch) {
+            (None, _) => Comparison::Skipped,
+            (Some(_), Some(mismatch)) => Comparison::Failed(mismatch.clone()),
+            (Some(_), None) => Comparison::Passed,
+        })
+    }
+
+    pub fn compare_path(&self) -> Option<&Path> {
+        self.compare_to.as_ref().map(|(path, _)| path.as_path())
+    }
+}
+
+// 行の分からないプログラムは空（vmstep は 1 命令ずつ）
+fn vm_starts(symbols: &Symbols, len: usize) -> Vec<bool> {
+    if !symbols.has_sources() {
+        return Vec::new();
+    }
+    (0..len as u16)
+        .map(|address| address == 0 || symbols.source_at(address) != symbols.source_at(address - 1))
+        .collect()
+}
+
+pub fn run_script(path: &Path) -> Result<Comparison> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    run_script_with(path, TestRunner::new(dir))
+}
+
+pub fn run_script_with(path: &Path, mut runner: TestRunner) -> Result<Comparison> {
+    let source =
+        fs::read_to_string(path).context(format!("Failed to read file '{}'", path.display()))?;
+    let commands = parse_script(&source)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))
+        .fail(Failure::Parse)?;
+    runner.execute(&commands)?;
+
+    let comparison = runner.finish()?;
+    if let Comparison::Failed(mismatch) = &comparison {
+        return Err(Failure::Test.wrap(anyhow!(
+            "Comparison failure at line {} of '{}'\n  expected: {}\n  actual:   {}",
+            mismatch.line,
+            runner.compare_path().unwrap_or(path).display(),
+            mismatch.expected.as_deref().unwrap_or("<end of file>"),
+            mismatch.actual
+        )));
+    }
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RAM[0] * RAM[1] を RAM[2] に求める
+    const MULT: &[u16] = &[
+        0b0000000000000010, // @2
+        0b1110101010001000, // M=0
+        0b0000000000000000, // (LOOP) @0
+        0b1111110000010000, // D=M
+        0b0000000000001110, // @END
+        0b1110001100000010, // D;JEQ
+        0b0000000000000001, // @1
+        0b1111110000010000, // D=M
+        0b0000000000000010, // @2
+        0b1111000010001000, // M=D+M
+        0b0000000000000000, // @0
+        0b1111110010001000, // M=M-1
+        0b0000000000000010, // @LOOP
+        0b1110101010000111, // 0;JMP
+        0b0000000000001110, // (END) @END
+        0b1110101010000111, // 0;JMP
+    ];
+
+    fn runner() -> TestRunner {
+        let mut runner = TestRunner::new(Path::new("."));
+        runner.cpu = Cpu::new(MULT).unwrap();
+        runner
+    }
+
+    #[test]
+    fn test_execute_script() {
+        let script = "\
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+set PC 0, set RAM[0] 3, set RAM[1] 5, set RAM[2] -1;
+repeat 100 { ticktock; }
+set RAM[0] 3;
+output;
+";
+        let mut runner = runner();
+        runner.execute(&parse_script(script).unwrap()).unwrap();
+
+        assert_eq!(
+            runner.output(),
+            [
+                "|  RAM[0]  |  RAM[1]  |  RAM[2]  |",
+                "|       3  |       5  |      15  |",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_execute_while() {
+        let script = "set RAM[0] 4, set RAM[1] 2; while PC <> 14 { ticktock; }";
+        let mut runner = runner();
+        runner.execute(&parse_script(script).unwrap()).unwrap();
+
+        assert_eq!(runner.cpu().ram[2], 8);
+        assert_eq!(runner.get(Variable::Pc), 14);
+    }
+
+    #[test]
+    fn test_compare_stops_at_first_mismatch() {
+        let dir = std::env::temp_dir().join(format!("tst-compare-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Mult.cmp"),
+            "|  RAM[2]  |\r\n|       6  |\r\n|      99  |\r\n",
+        )
+        .unwrap();
+
+        let script = "\
+compare-to Mult.cmp,
+output-file Mult.out,
+output-list RAM[2]%D2.6.2;
+set RAM[0] 2, set RAM[1] 3;
+while PC <> 14 { ticktock; }
+output;
+set RAM[2] 7;
+output;
+set RAM[2] 8;
+output;
+";
+        let mut runner = runner();
+        runner.dir = dir.clone();
+        runner.execute(&parse_script(script).unwrap()).unwrap();
+
+        assert_eq!(
+            runner.finish().unwrap(),
+            Comparison::Failed(Mismatch {
+                line: 3,
+                expected: Some("|      99  |".to_string()),
+                actual: "|       7  |".to_string(),
+            })
+        );
+        // 失敗した行までが .out に書かれる
+        assert_eq!(
+            fs::read_to_string(dir.join("Mult.out")).unwrap(),
+            "|  RAM[2]  |\n|       6  |\n|       7  |\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use super::parser::{Column, Radix, Variable};
+
+// 列名は列幅の中央に置き、はみ出す分は切り捨てる
+pub fn format_header(columns: &[Column]) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        let width = column.pad_left + column.len + column.pad_right;
+        let name: String = column.variable.name().chars().take(width).collect();
+        let left = (width - name.len()) / 2;
+        let right = width - name.len() - left;
+        line.push_str(&format!(
+            "{}{}{}|",
+            " ".repeat(left),
+            name,
+            " ".repeat(right)
+        ));
+    }
+    line
+}
+
+pub fn format_row(columns: &[Column], value: impl Fn(Variable) -> u16) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        let text = format_value(value(column.variable), column.radix, column.len);
+        line.push_str(&format!(
+            "{}{}{}|",
+            " ".repeat(column.pad_left),
+            text,
+            " ".repeat(column.pad_right)
+        ));
+    }
+    line
+}
+
+// 2進・16進は len 桁にゼロ詰め、10進は右寄せ、文字列は左寄せ
+fn format_value(value: u16, radix: Radix, len: usize) -> String {
+    let text = match radix {
+        Radix::Binary => fit_digits(&format!("{:016b}", value), len),
+        Radix::Hex => fit_digits(&format!("{:04X}", value), len),
+        Radix::Decimal => format!("{:>len$}", value as i16),
+        Radix::String => format!("{:<len$}", value as i16),
+    };
+    text.chars().take(len).collect()
+}
+
+// 桁数が len より多ければ下位桁を残し、少なければゼロで埋める
+fn fit_digits(digits: &str, len: usize) -> String {
+    if digits.len() >= len {
+        digits[digits.len() - len..].to_string()
+    } else {
+        format!("{}{}", "0".repeat(len - digits.len()), digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn column(
+        variable: Variable,
+        radix: Radix,
+        pad_left: usize,
+        len: usize,
+        pad_right: usize,
+    ) -> Column {
+        Column {
+            variable,
+            radix,
+            pad_left,
+            len,
+            pad_right,
+        }
+    }
+
+    #[rstest]
+    #[case(column(Variable::Ram(0), Radix::Decimal, 2, 6, 2), "|  RAM[0]  |")]
+    #[case(column(Variable::Ram(261), Radix::Decimal, 1, 6, 1), "|RAM[261]|")]
+    #[case(column(Variable::Ram(16384), Radix::Decimal, 1, 6, 1), "|RAM[1638|")]
+    #[case(column(Variable::D, Radix::Binary, 1, 16, 1), "|        D         |")]
+    fn test_format_header(#[case] column: Column, #[case] expected: &str) {
+        assert_eq!(format_header(&[column]), expected);
+    }
+
+    #[rstest]
+    #[case(Radix::Decimal, 2, 6, 2, 0xffff, "|      -1  |")]
+    #[case(Radix::Decimal, 1, 6, 1, 262, "|    262 |")]
+    #[case(Radix::Binary, 1, 16, 1, 5, "| 0000000000000101 |")]
+    #[case(Radix::Binary, 2, 1, 2, 1, "|  1  |")]
+    #[case(Radix::Hex, 1, 4, 1, 0x7fff, "| 7FFF |")]
+    #[case(Radix::String, 0, 4, 0, 12, "|12  |")]
+    fn test_format_row(
+        #[case] radix: Radix,
+        #[case] pad_left: usize,
+        #[case] len: usize,
+        #[case] pad_right: usize,
+        #[case] value: u16,
+        #[case] expected: &str,
+    ) {
+        let columns = [column(Variable::A, radix, pad_left, len, pad_right)];
+        assert_eq!(format_row(&columns, |_| value), expected);
+    }
+}
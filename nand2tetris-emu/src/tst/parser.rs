@@ -0,0 +1,566 @@
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use std::path::PathBuf;
+
+use crate::cpu::{RAM_SIZE, ROM_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    A,
+    D,
+    Pc,
+    Time,
+    Ram(u16),
+    Rom(u16),
+}
+
+impl Variable {
+    pub fn name(&self) -> String {
+        match self {
+            Variable::A => "A".to_string(),
+            Variable::D => "D".to_string(),
+            Variable::Pc => "PC".to_string(),
+            Variable::Time => "time".to_string(),
+            Variable::Ram(address) => format!("RAM[{}]", address),
+            Variable::Rom(address) => format!("ROM[{}]", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Hex,
+    Decimal,
+    String,
+}
+
+// output-list の1列（例: RAM[0]%D2.6.2）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub variable: Variable,
+    pub radix: Radix,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub variable: Variable,
+    pub op: Op,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    // 空ならスクリプトのディレクトリ（VM エミュレータの `load,`）
+    Load(PathBuf),
+    OutputFile(PathBuf),
+    CompareTo(PathBuf),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    TickTock,
+    // VM コマンドを 1 つ実行する（行が分かるときだけ。なければ 1 命令）
+    VmStep,
+    Output,
+    Echo(String),
+    ClearEcho,
+    Breakpoint(Variable, u16),
+    ClearBreakpoints,
+    // None なら無限に繰り返す
+    Repeat(Option<u64>, Vec<Command>),
+    While(Condition, Vec<Command>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Symbol(char),
+}
+
+struct Tokenizer {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Tokenizer {
+    fn new(source: &str) -> Result<Self> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut line = 1;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\n' => {
+                    line += 1;
+                    i += 1;
+                }
+                _ if c.is_whitespace() => i += 1,
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    let start = line;
+                    i += 2;
+                    loop {
+                        ensure!(i < chars.len(), "line {}: unterminated comment", start);
+                        if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                            i += 2;
+                            break;
+                        }
+                        if chars[i] == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                }
+                '"' => {
+                    let start = i + 1;
+                    i = start;
+                    while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    ensure!(
+                        chars.get(i) == Some(&'"'),
+                        "line {}: unterminated string",
+                        line
+                    );
+                    tokens.push((Token::Str(chars[start..i].iter().collect()), line));
+                    i += 1;
+                }
+                ',' | ';' | '!' | '{' | '}' | '<' | '>' | '=' => {
+                    tokens.push((Token::Symbol(c), line));
+                    i += 1;
+                }
+                _ => {
+                    let start = i;
+                    while i < chars.len() && !is_delimiter(chars[i]) {
+                        i += 1;
+                    }
+                    tokens.push((Token::Word(chars[start..i].iter().collect()), line));
+                }
+            }
+        }
+
+        Ok(Tokenizer { tokens, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |&(_, line)| line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_word(&mut self) -> Result<String> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => bail!("line {}: unexpected {:?}", line, token),
+            None => bail!("line {}: unexpected end of script", line),
+        }
+    }
+
+    // 次のトークンがこの記号なら読み進める
+    fn accept(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        ensure!(
+            self.accept(symbol),
+            "line {}: expected '{}'",
+            self.line(),
+            symbol
+        );
+        Ok(())
+    }
+
+    // 区切り記号までのワードを集める（output-list 用）
+    fn words_until_separator(&mut self) -> Vec<String> {
+        let mut words = Vec::new();
+        while let Some(Token::Word(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        words
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | ';' | '!' | '{' | '}' | '<' | '>' | '=' | '"')
+}
+
+pub fn parse_script(source: &str) -> Result<Vec<Command>> {
+    let mut tokens = Tokenizer::new(source)?;
+    let commands = parse_block(&mut tokens, false)?;
+    Ok(commands)
+}
+
+fn parse_block(tokens: &mut Tokenizer, nested: bool) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+
+    loop {
+        // コマンドの区切り（, ; !）は実行上の意味を持たない
+        while tokens.accept(',') || tokens.accept(';') || tokens.accept('!') {}
+
+        match tokens.peek() {
+            None if nested => bail!("line {}: missing '}}'", tokens.line()),
+            None => break,
+            Some(Token::Symbol('}')) if nested => {
+                tokens.next();
+                break;
+            }
+            _ => {
+                commands.push(parse_command(tokens)?);
+            }
+        }
+    }
+
+    Ok(commands)
+}
+
+fn parse_command(tokens: &mut Tokenizer) -> Result<Command> {
+    let line = tokens.line();
+    let at = |e: anyhow::Error| anyhow!("line {}: {}", line, e);
+
+    let name = match tokens.next() {
+        Some(Token::Word(word)) => word,
+        Some(Token::Str(_)) => bail!("line {}: unexpected string", line),
+        Some(Token::Symbol(c)) => bail!("line {}: unexpected '{}'", line, c),
+        None => bail!("line {}: unexpected end of script", line),
+    };
+
+    Ok(match name.as_str() {
+        "load" => match tokens.peek() {
+            Some(Token::Word(_)) => Command::Load(PathBuf::from(tokens.next_word()?)),
+            _ => Command::Load(PathBuf::new()),
+        },
+        "output-file" => Command::OutputFile(PathBuf::from(tokens.next_word()?)),
+        "compare-to" => Command::CompareTo(PathBuf::from(tokens.next_word()?)),
+        "output-list" => {
+            let columns = tokens
+                .words_until_separator()
+                .iter()
+                .map(|word| parse_column(word))
+                .collect::<Result<Vec<_>>>()
+                .map_err(at)?;
+            Command::OutputList(columns)
+        }
+        "set" => {
+            let variable = parse_variable(&tokens.next_word()?).map_err(at)?;
+            let value = parse_value(&tokens.next_word()?).map_err(at)?;
+            Command::Set(variable, value)
+        }
+        "ticktock" => Command::TickTock,
+        "vmstep" => Command::VmStep,
+        "output" => Command::Output,
+        "echo" => match tokens.next() {
+            Some(Token::Str(text)) | Some(Token::Word(text)) => Command::Echo(text),
+            _ => bail!("line {}: echo expects a string", line),
+        },
+        "clear-echo" => Command::ClearEcho,
+        "breakpoint" => {
+            let variable = parse_variable(&tokens.next_word()?).map_err(at)?;
+            let value = parse_value(&tokens.next_word()?).map_err(at)?;
+            Command::Breakpoint(variable, value)
+        }
+        "clear-breakpoints" => Command::ClearBreakpoints,
+        "repeat" => {
+            let count = match tokens.peek() {
+                Some(Token::Word(_)) => {
+                    let word = tokens.next_word()?;
+                    let count = word.parse().ok();
+                    ensure!(
+                        count.is_some(),
+                        "line {}: invalid repeat count '{}'",
+                        line,
+                        word
+                    );
+                    count
+                }
+                _ => None,
+            };
+            tokens.expect('{')?;
+            Command::Repeat(count, parse_block(tokens, true)?)
+        }
+        "while" => {
+            let variable = parse_variable(&tokens.next_word()?).map_err(at)?;
+            let op = parse_op(tokens).map_err(at)?;
+            let value = parse_value(&tokens.next_word()?).map_err(at)?;
+            tokens.expect('{')?;
+            Command::While(
+                Condition {
+                    variable,
+                    op,
+                    value,
+                },
+                parse_block(tokens, true)?,
+            )
+        }
+        "tick" | "tock" | "eval" => {
+            bail!(
+                "line {}: '{}' is not supported by the CPU emulator",
+                line,
+                name
+            )
+        }
+        _ => bail!("line {}: unknown command '{}'", line, name),
+    })
+}
+
+fn parse_op(tokens: &mut Tokenizer) -> Result<Op> {
+    Ok(if tokens.accept('=') {
+        Op::Eq
+    } else if tokens.accept('<') {
+        if tokens.accept('>') {
+            Op::Ne
+        } else if tokens.accept('=') {
+            Op::Le
+        } else {
+            Op::Lt
+        }
+    } else if tokens.accept('>') {
+        if tokens.accept('=') { Op::Ge } else { Op::Gt }
+    } else {
+        bail!("expected a comparison operator")
+    })
+}
+
+pub fn parse_variable(text: &str) -> Result<Variable> {
+    Ok(match text {
+        "A" => Variable::A,
+        "D" => Variable::D,
+        "PC" => Variable::Pc,
+        "time" => Variable::Time,
+        // VM エミュレータのスクリプトのセグメントのポインタ（project 7, 8）
+        "sp" => Variable::Ram(0),
+        "local" => Variable::Ram(1),
+        "argument" => Variable::Ram(2),
+        "this" => Variable::Ram(3),
+        "that" => Variable::Ram(4),
+        _ => {
+            let (name, index) = text
+                .strip_suffix(']')
+                .and_then(|rest| rest.split_once('['))
+                .context(format!("unknown variable '{}'", text))?;
+            let index: usize = index
+                .parse()
+                .context(format!("invalid index in '{}'", text))?;
+            match name {
+                "RAM" if index < RAM_SIZE => Variable::Ram(index as u16),
+                "ROM" | "ROM32K" if index < ROM_SIZE => Variable::Rom(index as u16),
+                "temp" if index < 8 => Variable::Ram(5 + index as u16),
+                "RAM" | "ROM" | "ROM32K" | "temp" => bail!("index out of range in '{}'", text),
+                _ => bail!("unknown variable '{}'", text),
+            }
+        }
+    })
+}
+
+// 10進（負数可）、%D、%X、%B の値
+pub fn parse_value(text: &str) -> Result<u16> {
+    let parsed = match text.get(..2) {
+        Some("%X") => i32::from_str_radix(&text[2..], 16).ok(),
+        Some("%B") => i32::from_str_radix(&text[2..], 2).ok(),
+        Some("%D") => text[2..].parse().ok(),
+        _ => text.parse().ok(),
+    };
+    match parsed {
+        Some(value) if (i16::MIN as i32..=u16::MAX as i32).contains(&value) => Ok(value as u16),
+        Some(_) => bail!("value out of range: '{}'", text),
+        None => bail!("invalid value '{}'", text),
+    }
+}
+
+// RAM[0]%D2.6.2 → 変数と書式。書式を省略したら %D1.6.1
+fn parse_column(text: &str) -> Result<Column> {
+    let (name, format) = match text.split_once('%') {
+        Some((name, format)) => (name, Some(format)),
+        None => (text, None),
+    };
+    let variable = parse_variable(name)?;
+
+    let Some(format) = format else {
+        return Ok(Column {
+            variable,
+            radix: Radix::Decimal,
+            pad_left: 1,
+            len: 6,
+            pad_right: 1,
+        });
+    };
+
+    let radix = match format.chars().next() {
+        Some('B') => Radix::Binary,
+        Some('X') => Radix::Hex,
+        Some('D') => Radix::Decimal,
+        Some('S') => Radix::String,
+        _ => bail!("invalid output format '{}'", text),
+    };
+    let widths = format[1..]
+        .split('.')
+        .map(|n| n.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|widths| widths.len() == 3)
+        .context(format!("invalid output format '{}'", text))?;
+
+    Ok(Column {
+        variable,
+        radix,
+        pad_left: widths[0],
+        len: widths[1],
+        pad_right: widths[2],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_parse_script() {
+        let script = "\
+// Mult.tst
+load Mult.hack,
+output-file Mult.out,
+compare-to Mult.cmp,
+output-list RAM[0]%D2.6.2 RAM[2]%D2.6.2;
+
+set RAM[0] 0,   // comment
+set RAM[1] -1;
+repeat 20 {
+  ticktock;
+}
+/* block
+   comment */
+output;
+";
+        let commands = parse_script(script).unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Load(PathBuf::from("Mult.hack")),
+                Command::OutputFile(PathBuf::from("Mult.out")),
+                Command::CompareTo(PathBuf::from("Mult.cmp")),
+                Command::OutputList(vec![
+                    Column {
+                        variable: Variable::Ram(0),
+                        radix: Radix::Decimal,
+                        pad_left: 2,
+                        len: 6,
+                        pad_right: 2,
+                    },
+                    Column {
+                        variable: Variable::Ram(2),
+                        radix: Radix::Decimal,
+                        pad_left: 2,
+                        len: 6,
+                        pad_right: 2,
+                    },
+                ]),
+                Command::Set(Variable::Ram(0), 0),
+                Command::Set(Variable::Ram(1), 0xffff),
+                Command::Repeat(Some(20), vec![Command::TickTock]),
+                Command::Output,
+            ]
+        );
+    }
+
+    // VM エミュレータのスクリプト（project 12）
+    #[test]
+    fn test_parse_vm_script() {
+        let commands = parse_script("load, repeat 1000000 { vmstep; } output;").unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Load(PathBuf::new()),
+                Command::Repeat(Some(1000000), vec![Command::VmStep]),
+                Command::Output,
+            ]
+        );
+
+        let commands = parse_script("set sp 256, set that 3010, set temp[6] 1;").unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Set(Variable::Ram(0), 256),
+                Command::Set(Variable::Ram(4), 3010),
+                Command::Set(Variable::Ram(11), 1),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("while RAM[0] <> 0 { ticktock; }", Op::Ne)]
+    #[case("while RAM[0]<=0 { ticktock; }", Op::Le)]
+    #[case("while RAM[0] > 0 { ticktock; }", Op::Gt)]
+    fn test_parse_while(#[case] script: &str, #[case] op: Op) {
+        let commands = parse_script(script).unwrap();
+        assert_eq!(
+            commands,
+            vec![Command::While(
+                Condition {
+                    variable: Variable::Ram(0),
+                    op,
+                    value: 0,
+                },
+                vec![Command::TickTock],
+            )]
+        );
+    }
+
+    #[rstest]
+    #[case("12", 12)]
+    #[case("-1", 0xffff)]
+    #[case("%X7FFF", 0x7fff)]
+    #[case("%B101", 5)]
+    #[case("%D-2", 0xfffe)]
+    fn test_parse_value(#[case] text: &str, #[case] expected: u16) {
+        assert_eq!(parse_value(text).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("repeat 3 { ticktock;")]
+    #[case("set RAM[40000] 1;")]
+    #[case("set temp[8] 1;")]
+    #[case("set RAM[0] 70000;")]
+    #[case("output-list RAM[0]%Q1.6.1;")]
+    #[case("echo \"unterminated;")]
+    #[case("frobnicate;")]
+    fn test_parse_script_invalid(#[case] script: &str) {
+        assert!(parse_script(script).is_err());
+    }
+}
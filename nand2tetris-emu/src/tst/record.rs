@@ -0,0 +1,220 @@
+// プログラムを実行して RAM の値を記録し、同じ結果を確かめる .tst と .cmp を作る
+//
+// .cmp は作ったスクリプトをそのまま TestRunner で実行した出力なので、スクリプトと必ず合う
+
+use anyhow::{Context, Result, bail, ensure};
+use std::path::{Path, PathBuf};
+
+use super::{Command, TestRunner, parse_script};
+use crate::{
+    Cpu,
+    assertion::{Assertion, Target},
+    cpu::StopReason,
+    loader,
+};
+
+// サイクル数を指定しないとき、止まるまで待つ上限
+pub const MAX_CYCLES: u64 = 10_000_000;
+
+pub struct Recording {
+    // .asm か .hack。スクリプトはディレクトリからの相対パスで load する
+    pub program: PathBuf,
+    pub cells: Vec<u16>,
+    // 実行前に入れる値
+    pub set: Vec<Assertion>,
+    // None なら最後に 1 行だけ
+    pub every: Option<u64>,
+    pub cycles: u64,
+}
+
+impl Recording {
+    pub fn script(&self, name: &str) -> String {
+        let columns: Vec<String> = self.cells.iter().map(|&address| column(address)).collect();
+        let mut lines = vec![
+            format!(
+                "// Generated by `n2t record-test` from {}",
+                self.program.display()
+            ),
+            String::new(),
+            format!("load {},", self.program.display()),
+            format!("output-file {}.out,", name),
+            format!("compare-to {}.cmp,", name),
+            format!("output-list {};", columns.join(" ")),
+            String::new(),
+        ];
+        for (i, assertion) in self.set.iter().enumerate() {
+            let end = if i + 1 == self.set.len() { ';' } else { ',' };
+            lines.push(format!(
+                "set {} {}{}",
+                assertion.target, assertion.expected as i16, end
+            ));
+        }
+
+        let every = self.every.unwrap_or(self.cycles).max(1);
+        let (rows, rest) = (self.cycles / every, self.cycles % every);
+        match rows {
+            0 => {}
+            1 => lines.extend(ticks(every, "")),
+            _ => {
+                lines.push(format!("repeat {} {{", rows));
+                lines.extend(ticks(every, "  "));
+                lines.push("}".to_string());
+            }
+        }
+        // 最後の区切りが半端でも、最後の状態は必ず出す
+        if rest > 0 || self.cycles == 0 {
+            lines.extend(ticks(rest, ""));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    // (.tst, .cmp) の内容。program は dir からの相対パス
+    pub fn record(&self, dir: &Path, name: &str) -> Result<(String, String)> {
+        let script = self.script(name);
+        let mut commands = parse_script(&script)?;
+        // まだない .cmp と比べたり .out を書いたりしない
+        commands
+            .retain(|command| !matches!(command, Command::CompareTo(_) | Command::OutputFile(_)));
+        let mut runner = TestRunner::new(dir);
+        runner.execute(&commands)?;
+        let compare: String = runner
+            .output()
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect();
+        Ok((script, compare))
+    }
+}
+
+// set の値を入れてから止まるまでのサイクル数
+pub fn cycles_to_halt(path: &Path, set: &[Assertion]) -> Result<u64> {
+    let program = loader::load_program(path, None)?;
+    let mut cpu = Cpu::new(&program.words)?;
+    for assertion in set {
+        match assertion.target {
+            Target::A => cpu.a = assertion.expected,
+            Target::D => cpu.d = assertion.expected,
+            Target::Pc => cpu.pc = assertion.expected,
+            Target::Ram(address) => cpu.ram[address as usize] = assertion.expected,
+        }
+    }
+    match cpu
+        .run(Some(MAX_CYCLES))
+        .context(format!("{}", path.display()))?
+    {
+        StopReason::MaxCycles => bail!(
+            "{} did not halt within {} cycles; give the number of cycles to record with --cycles",
+            path.display(),
+            MAX_CYCLES
+        ),
+        _ => Ok(cpu.cycles),
+    }
+}
+
+// 記録できるプログラムか（CPU エミュレータのスクリプトは .asm と .hack しか load しない）
+pub fn check_program(path: &Path) -> Result<()> {
+    ensure!(
+        path.extension()
+            .is_some_and(|ext| ext == "asm" || ext == "hack"),
+        "'{}' is not an .asm or .hack file",
+        path.display()
+    );
+    Ok(())
+}
+
+// 名前が収まる幅にする（RAM[0] は %D2.6.2、RAM[16384] は %D3.6.3）
+fn column(address: u16) -> String {
+    let width = (format!("RAM[{}]", address).len() + 2).max(10);
+    let left = (width - 6) / 2;
+    format!("RAM[{}]%D{}.6.{}", address, left, width - 6 - left)
+}
+
+// count 回 ticktock してから output
+fn ticks(count: u64, indent: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    if count > 0 {
+        lines.push(format!("{}repeat {} {{", indent, count));
+        lines.push(format!("{}  ticktock;", indent));
+        lines.push(format!("{}}}", indent));
+    }
+    lines.push(format!("{}output;", indent));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assertion::parse_assertions,
+        tst::{Comparison, run_script},
+    };
+    use std::fs;
+
+    const MULT: &str = "@2\nM=0\n(LOOP)\n@0\nD=M\n@END\nD;JEQ\n@1\nD=M\n@2\nM=D+M\n@0\nM=M-1\n\
+                        @LOOP\n0;JMP\n(END)\n@END\n0;JMP\n";
+
+    #[test]
+    fn test_script() {
+        let recording = Recording {
+            program: PathBuf::from("Mult.asm"),
+            cells: vec![2, 16384],
+            set: parse_assertions("RAM[0]=3, RAM[1]=-5").unwrap(),
+            every: Some(10),
+            cycles: 25,
+        };
+        assert_eq!(
+            recording.script("MultRec"),
+            "\
+// Generated by `n2t record-test` from Mult.asm
+
+load Mult.asm,
+output-file MultRec.out,
+compare-to MultRec.cmp,
+output-list RAM[2]%D2.6.2 RAM[16384]%D3.6.3;
+
+set RAM[0] 3,
+set RAM[1] -5;
+repeat 2 {
+  repeat 10 {
+    ticktock;
+  }
+  output;
+}
+repeat 5 {
+  ticktock;
+}
+output;
+"
+        );
+    }
+
+    #[test]
+    fn test_recorded_script_passes() {
+        let dir = std::env::temp_dir().join(format!("tst-record-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Mult.asm"), MULT).unwrap();
+
+        let set = parse_assertions("R0=3, R1=5").unwrap();
+        let cycles = cycles_to_halt(&dir.join("Mult.asm"), &set).unwrap();
+        let recording = Recording {
+            program: PathBuf::from("Mult.asm"),
+            cells: vec![0, 1, 2],
+            set,
+            every: Some(10),
+            cycles,
+        };
+        let (script, compare) = recording.record(&dir, "MultRec").unwrap();
+        fs::write(dir.join("MultRec.tst"), script).unwrap();
+        fs::write(dir.join("MultRec.cmp"), &compare).unwrap();
+        let result = run_script(&dir.join("MultRec.tst"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap(), Comparison::Passed);
+        // 見出しと、10 サイクルごとの行と、止まったときの行
+        assert_eq!(compare.lines().count() as u64, 1 + cycles.div_ceil(10));
+        assert_eq!(
+            compare.lines().last(),
+            Some("|       0  |       5  |      15  |")
+        );
+    }
+}
@@ -0,0 +1,39 @@
+// プロジェクトのディレクトリの中から `build .` と `build` を実行する
+
+use std::{env, fs, path::Path, process::Command};
+
+const MAIN: &str = "class Main { function void main() { do Output.printInt(1); return; } }";
+
+fn build(dir: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_nand2tetris-emu"))
+        .arg("build")
+        .args(args)
+        .arg("--quiet")
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_build_current_dir() {
+    let root = env::temp_dir().join(format!("emu-build-cwd-{}", std::process::id()));
+    let plain = root.join("Plain");
+    let project = root.join("Project");
+    fs::create_dir_all(&plain).unwrap();
+    fs::create_dir_all(&project).unwrap();
+    fs::write(plain.join("Main.jack"), MAIN).unwrap();
+    fs::write(project.join("Main.jack"), MAIN).unwrap();
+    fs::write(project.join("n2t.toml"), "").unwrap();
+
+    build(&plain, &["."]);
+    build(&project, &[]);
+    build(&project, &["."]);
+    let built = [plain.join("Plain.hack"), project.join("Project.hack")].map(|path| path.is_file());
+    fs::remove_dir_all(&root).unwrap();
+    assert_eq!(built, [true, true]);
+}
@@ -0,0 +1,31 @@
+// OS をつないだ VM プログラムが Sys.halt を呼んだら止まったと報告する
+
+use std::{env, fs, process::Command};
+
+#[test]
+fn test_run_vm_sys_halt() {
+    let dir = env::temp_dir().join(format!("emu-run-halt-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("Main.vm"),
+        "function Main.main 0\npush constant 8000\npush constant 42\ncall Memory.poke 2\n\
+         pop temp 0\ncall Sys.halt 0\npop temp 0\npush constant 0\nreturn",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_nand2tetris-emu"))
+        .arg("run")
+        .arg(&dir)
+        .args(["--max-cycles", "20000000", "--dump", "8000"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("Execution halted"), "{}", stdout);
+    assert!(stdout.contains("RAM[8000]=42"), "{}", stdout);
+}
@@ -0,0 +1,89 @@
+/*
+ * C interface to the nand2tetris toolchain: the Hack assembler, the VM
+ * translator and the CPU emulator. Link with libn2t (nand2tetris-ffi).
+ *
+ * Functions that can fail return N2T_OK or the exit code of the matching
+ * command-line failure (3 syntax error, 4 semantic error, 1 anything else).
+ * n2t_last_error() then gives the message on the calling thread.
+ *
+ * Strings are NUL-terminated UTF-8. Memory returned by the library is
+ * released with the matching n2t_free_* function, never with free().
+ */
+#ifndef N2T_H
+#define N2T_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Bumped on every incompatible change to this header. */
+#define N2T_ABI_VERSION 1
+
+#define N2T_OK 0
+/* A required pointer was NULL or a string was not valid UTF-8. */
+#define N2T_INVALID_ARGUMENT 2
+
+/* N2T_ABI_VERSION of the library that was linked. */
+uint32_t n2t_abi_version(void);
+
+/* The message of the last failure on this thread, or NULL. Valid until the
+ * next failure on the same thread. */
+const char *n2t_last_error(void);
+
+/* Assembles Hack assembly. On success *words holds *len machine words;
+ * release them with n2t_free_words. */
+int32_t n2t_assemble(const char *source, uint16_t **words, size_t *len);
+void n2t_free_words(uint16_t *words, size_t len);
+
+/* Translates count VM files to Hack assembly. names[i] is the file name of
+ * sources[i] without ".vm" and prefixes its static variables. bootstrap adds
+ * the code that sets SP and calls Sys.init. On success *assembly holds the
+ * assembly; release it with n2t_free_string. */
+int32_t n2t_translate(const char *const *names, const char *const *sources,
+                      size_t count, bool bootstrap, char **assembly);
+void n2t_free_string(char *text);
+
+/* A Hack computer. Not thread-safe: use each machine from one thread at a
+ * time. */
+typedef struct N2tMachine N2tMachine;
+
+/* A machine with the len words of rom in its ROM, or NULL on failure.
+ * Release it with n2t_machine_free. */
+N2tMachine *n2t_machine_new(const uint16_t *rom, size_t len);
+void n2t_machine_free(N2tMachine *machine);
+
+/* Runs at most cycles instructions and stores how many ran in *executed
+ * (which may be NULL). Fewer run when the program halts (jumps to itself)
+ * or runs past its end. */
+int32_t n2t_machine_step(N2tMachine *machine, uint64_t cycles,
+                         uint64_t *executed);
+bool n2t_machine_halted(const N2tMachine *machine);
+/* Sets PC and the cycle count to 0 and keeps RAM, like the reset pin. */
+void n2t_machine_reset(N2tMachine *machine);
+
+/* Addresses above KBD (24576) fail. */
+int32_t n2t_machine_read_ram(const N2tMachine *machine, uint16_t address,
+                             uint16_t *value);
+int32_t n2t_machine_write_ram(N2tMachine *machine, uint16_t address,
+                              uint16_t value);
+
+/* Holds the key with this Hack key code down; 0 releases it. */
+void n2t_machine_set_key(N2tMachine *machine, uint16_t key);
+
+/* The 8192 words of screen memory: 32 words per row, and the lowest bit of
+ * a word is its leftmost pixel. The pointer stays valid until the machine is
+ * freed and always shows the current screen. */
+const uint16_t *n2t_machine_screen(const N2tMachine *machine);
+
+uint16_t n2t_machine_pc(const N2tMachine *machine);
+uint64_t n2t_machine_cycles(const N2tMachine *machine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,358 @@
+// C から使うための関数（C++ の可視化ツールや Unity などに組み込む）
+//
+// 宣言は include/n2t.h。関数は状態のコード（0 が成功、それ以外は CLI の終了コードと同じ
+// 失敗の種類）を返し、失敗したときの文面は同じスレッドの n2t_last_error で読む。
+// Rust が確保したものは対応する n2t_free_* で解放する。互換性を壊す変更をしたら
+// N2T_ABI_VERSION を上げる。ポインタの条件（NULL を渡せるかなど）はヘッダーに書く
+
+#![allow(clippy::missing_safety_doc)]
+
+use anyhow::{Result, anyhow};
+use nand2tetris_core::cli::exit_code;
+use nand2tetris_emu::screen::KeyLatch;
+use nand2tetris_vm::{TranslateOptions, VMTranslator};
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char},
+    ptr, slice,
+};
+
+pub const N2T_ABI_VERSION: u32 = 1;
+
+pub const N2T_OK: i32 = 0;
+// NULL や UTF-8 でない文字列を渡された（CLI の使い方の誤りと同じ 2）
+pub const N2T_INVALID_ARGUMENT: i32 = 2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// 失敗を記録して状態のコードにする
+fn fail(error: anyhow::Error, code: i32) -> i32 {
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+fn status(result: Result<()>) -> i32 {
+    match result {
+        Ok(()) => N2T_OK,
+        Err(error) => {
+            let code = exit_code(&error) as i32;
+            fail(error, code)
+        }
+    }
+}
+
+unsafe fn text<'a>(pointer: *const c_char, name: &str) -> Result<&'a str> {
+    if pointer.is_null() {
+        return Err(anyhow!("'{}' is NULL", name));
+    }
+    unsafe { CStr::from_ptr(pointer) }
+        .to_str()
+        .map_err(|_| anyhow!("'{}' is not valid UTF-8", name))
+}
+
+macro_rules! argument {
+    ($expr:expr) => {
+        match $expr {
+            Ok(value) => value,
+            Err(error) => return fail(error, N2T_INVALID_ARGUMENT),
+        }
+    };
+}
+
+macro_rules! non_null {
+    ($($pointer:ident),+) => {
+        $(
+            if $pointer.is_null() {
+                return fail(anyhow!("'{}' is NULL", stringify!($pointer)), N2T_INVALID_ARGUMENT);
+            }
+        )+
+    };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn n2t_abi_version() -> u32 {
+    N2T_ABI_VERSION
+}
+
+// このスレッドで最後に失敗したときの文面。なければ NULL。次に失敗するまで有効
+#[unsafe(no_mangle)]
+pub extern "C" fn n2t_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_assemble(
+    source: *const c_char,
+    words: *mut *mut u16,
+    len: *mut usize,
+) -> i32 {
+    non_null!(words, len);
+    let source = argument!(unsafe { text(source, "source") });
+    status(nand2tetris_asm::assemble_program(source).map(|program| {
+        let boxed = program.words.into_boxed_slice();
+        unsafe {
+            *len = boxed.len();
+            *words = Box::into_raw(boxed) as *mut u16;
+        }
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_free_words(words: *mut u16, len: usize) {
+    if !words.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(words, len)) });
+    }
+}
+
+// names[i] の VM コード sources[i] を順に変換する。names は拡張子のないファイル名
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_translate(
+    names: *const *const c_char,
+    sources: *const *const c_char,
+    count: usize,
+    bootstrap: bool,
+    assembly: *mut *mut c_char,
+) -> i32 {
+    non_null!(assembly);
+    if count > 0 {
+        non_null!(names, sources);
+    }
+    let mut files = Vec::new();
+    for i in 0..count {
+        let name = argument!(unsafe { text(*names.add(i), "names[i]") });
+        let source = argument!(unsafe { text(*sources.add(i), "sources[i]") });
+        files.push((name.to_string(), source.to_string()));
+    }
+    let options = TranslateOptions {
+        bootstrap,
+        ..TranslateOptions::default()
+    };
+    status(
+        VMTranslator::translate_sources(&files, &options).and_then(|translation| {
+            let text = CString::new(translation.asm)?;
+            unsafe { *assembly = text.into_raw() };
+            Ok(())
+        }),
+    )
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_free_string(text: *mut c_char) {
+    if !text.is_null() {
+        drop(unsafe { CString::from_raw(text) });
+    }
+}
+
+// ROM に機械語を読み込んだ Hack コンピュータ。C からは中身の見えない N2tMachine
+pub struct N2tMachine {
+    machine: nand2tetris_emu::Machine,
+    // 画面は呼び出し側が n2t_machine_screen で読むので描かない
+    key: KeyLatch,
+}
+
+// 失敗したら NULL
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_new(rom: *const u16, len: usize) -> *mut N2tMachine {
+    if rom.is_null() && len > 0 {
+        fail(anyhow!("'rom' is NULL"), N2T_INVALID_ARGUMENT);
+        return ptr::null_mut();
+    }
+    let rom = if len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(rom, len) }
+    };
+    let key = KeyLatch::default();
+    match nand2tetris_emu::Machine::new(rom) {
+        Ok(machine) => Box::into_raw(Box::new(N2tMachine {
+            machine: machine.with_io(key.clone()),
+            key,
+        })),
+        Err(error) => {
+            let code = exit_code(&error) as i32;
+            fail(error, code);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_free(machine: *mut N2tMachine) {
+    if !machine.is_null() {
+        drop(unsafe { Box::from_raw(machine) });
+    }
+}
+
+// 最大 cycles 命令を実行し、実行した数を executed に書く（NULL なら書かない）。
+// 止まったら（halt かプログラムの末尾）それより少なくなる
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_step(
+    machine: *mut N2tMachine,
+    cycles: u64,
+    executed: *mut u64,
+) -> i32 {
+    non_null!(machine);
+    let machine = unsafe { &mut *machine };
+    status(machine.machine.run_cycles(cycles).map(|count| {
+        if !executed.is_null() {
+            unsafe { *executed = count };
+        }
+    }))
+}
+
+// 無限ループ（0;JMP で自分に飛ぶ）に入ったか、PC がプログラムの末尾を越えた
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_halted(machine: *const N2tMachine) -> bool {
+    !machine.is_null() && unsafe { &*machine }.machine.stop_reason(None).is_some()
+}
+
+// RAM はそのままで PC とサイクル数を戻す
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_reset(machine: *mut N2tMachine) {
+    if let Some(machine) = unsafe { machine.as_mut() } {
+        machine.machine.reset();
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_read_ram(
+    machine: *const N2tMachine,
+    address: u16,
+    value: *mut u16,
+) -> i32 {
+    non_null!(machine, value);
+    let machine = unsafe { &*machine };
+    status(machine.machine.peek(address).map(|word| unsafe {
+        *value = word;
+    }))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_write_ram(
+    machine: *mut N2tMachine,
+    address: u16,
+    value: u16,
+) -> i32 {
+    non_null!(machine);
+    status(unsafe { &mut *machine }.machine.poke(address, value))
+}
+
+// 押されているキーの Hack のキーコード（離したら 0）
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_set_key(machine: *mut N2tMachine, key: u16) {
+    if let Some(machine) = unsafe { machine.as_mut() } {
+        machine.key.set(key);
+        machine.machine.set_key(key);
+    }
+}
+
+// RAM[SCREEN..KBD] の 8192 語。1 行 32 語、各語の LSB が左端のピクセル。
+// RAM は動かないので、マシンを解放するまで有効で、いつも今の画面を指す
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_screen(machine: *const N2tMachine) -> *const u16 {
+    match unsafe { machine.as_ref() } {
+        Some(machine) => machine.machine.screen().as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_pc(machine: *const N2tMachine) -> u16 {
+    unsafe { machine.as_ref() }.map_or(0, |machine| machine.machine.pc())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn n2t_machine_cycles(machine: *const N2tMachine) -> u64 {
+    unsafe { machine.as_ref() }.map_or(0, |machine| machine.machine.cycles())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(n2t_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_assemble_and_run() {
+        let source = c"@R0\nD=M\n@R1\nD=D+M\n@R2\nM=D\n(END)\n@END\n0;JMP\n";
+        let mut words = ptr::null_mut();
+        let mut len = 0;
+        unsafe {
+            assert_eq!(n2t_assemble(source.as_ptr(), &mut words, &mut len), N2T_OK);
+            assert_eq!(len, 8);
+            let machine = n2t_machine_new(words, len);
+            n2t_free_words(words, len);
+            assert!(!machine.is_null());
+            assert_eq!(n2t_machine_write_ram(machine, 0, 2), N2T_OK);
+            assert_eq!(n2t_machine_write_ram(machine, 1, 40), N2T_OK);
+            let mut executed = 0;
+            assert_eq!(n2t_machine_step(machine, 100, &mut executed), N2T_OK);
+            assert_eq!(executed, 6);
+            assert!(n2t_machine_halted(machine));
+            let mut value = 0;
+            assert_eq!(n2t_machine_read_ram(machine, 2, &mut value), N2T_OK);
+            assert_eq!(value, 42);
+            assert_eq!(n2t_machine_pc(machine), 6);
+            assert_eq!(n2t_machine_cycles(machine), 6);
+
+            n2t_machine_set_key(machine, 65);
+            assert_eq!(n2t_machine_read_ram(machine, 24576, &mut value), N2T_OK);
+            assert_eq!(value, 65);
+            assert_eq!(*n2t_machine_screen(machine), 0);
+
+            // 範囲外の番地は CLI と同じ「その他のエラー」
+            assert_eq!(n2t_machine_read_ram(machine, 40000, &mut value), 1);
+            n2t_machine_free(machine);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut words = ptr::null_mut();
+        let mut len = 0;
+        unsafe {
+            assert_eq!(n2t_assemble(c"D=Q".as_ptr(), &mut words, &mut len), 3);
+            assert_eq!(last_error(), "Line 1: invalid comp pattern: Q");
+            assert_eq!(
+                n2t_assemble(ptr::null(), &mut words, &mut len),
+                N2T_INVALID_ARGUMENT
+            );
+            assert_eq!(last_error(), "'source' is NULL");
+            assert!(words.is_null());
+        }
+    }
+
+    #[test]
+    fn test_translate() {
+        let names = [c"Main".as_ptr()];
+        let sources = [c"push constant 7\npop static 0\n".as_ptr()];
+        let mut assembly = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                n2t_translate(names.as_ptr(), sources.as_ptr(), 1, false, &mut assembly),
+                N2T_OK
+            );
+            let text = CStr::from_ptr(assembly).to_str().unwrap().to_string();
+            n2t_free_string(assembly);
+            assert!(text.contains("@Main.0"), "{}", text);
+
+            let sources = [c"push nowhere 7\n".as_ptr()];
+            assert_eq!(
+                n2t_translate(names.as_ptr(), sources.as_ptr(), 1, false, &mut assembly),
+                3
+            );
+        }
+        assert!(
+            last_error().starts_with("Main.vm: Line 1"),
+            "{}",
+            last_error()
+        );
+    }
+}
@@ -0,0 +1,142 @@
+// HDL の構文木。名前や接続には元のソースの範囲を持たせる
+use std::fmt;
+
+// line と column は 1 始まり
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}, column {}", self.line, self.column)
+    }
+}
+
+// end は最後の文字の次
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chip {
+    pub name: Identifier,
+    pub inputs: Vec<PinDecl>,
+    pub outputs: Vec<PinDecl>,
+    pub body: ChipBody,
+}
+
+// IN a[16] の a と 16。[] がなければ幅 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinDecl {
+    pub name: Identifier,
+    pub width: u16,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChipBody {
+    Parts(Vec<Part>),
+    // BUILTIN Register; CLOCKED in, load;
+    Builtin {
+        name: Identifier,
+        clocked: Vec<Identifier>,
+    },
+}
+
+// Nand(a=x, b=y, out=z);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    pub chip: Identifier,
+    pub connections: Vec<Connection>,
+    pub span: Span,
+}
+
+// 左が部品のピン、右がこのチップのピンか内部ピンか定数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub pin: PinRef,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinRef {
+    pub name: Identifier,
+    pub range: Option<BitRange>,
+    pub span: Span,
+}
+
+// a[3] は 3..3、a[0..7] は 0..7。両端を含む
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl BitRange {
+    pub fn width(self) -> u16 {
+        self.end - self.start + 1
+    }
+}
+
+impl fmt::Display for BitRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "[{}]", self.start)
+        } else {
+            write!(f, "[{}..{}]", self.start, self.end)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Pin(PinRef),
+    Constant(bool, Span),
+}
+
+impl Value {
+    pub fn span(&self) -> Span {
+        match self {
+            Value::Pin(pin) => pin.span,
+            Value::Constant(_, span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for PinRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name.name)?;
+        if let Some(range) = self.range {
+            write!(f, "{}", range)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Pin(pin) => write!(f, "{}", pin),
+            Value::Constant(value, _) => write!(f, "{}", value),
+        }
+    }
+}
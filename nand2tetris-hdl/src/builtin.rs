@@ -0,0 +1,956 @@
+// Rust で書いた組み込みチップ。HDL のチップは最後はすべてこれの組み合わせになる
+//
+// 公式のシミュレーターと同じく、クロック付きのチップは tick（立ち上がり）で入力を
+// 取り込み、tock（立ち下がり）で出力に出す。tick と tock の間の出力は前の値のまま
+//
+// 標準ライブラリのチップ（And16 から RAM16K、CPU、Memory、Computer まで）もすべてここにあり、
+// 部品の .hdl が見つからなければこちらを使う
+
+// 値は下位 width ビットを使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub name: &'static str,
+    pub width: u16,
+}
+
+const fn pin(name: &'static str, width: u16) -> Pin {
+    Pin { name, width }
+}
+
+pub fn mask(width: u16) -> u16 {
+    if width >= 16 {
+        u16::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+pub trait Builtin {
+    fn inputs(&self) -> &'static [Pin];
+
+    fn outputs(&self) -> &'static [Pin];
+
+    // CLOCKED の入力。tock まで出力に現れないので、組み合わせ回路のループには数えない
+    fn clocked(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    // 入力と内部の状態から出力を計算する。inputs と outputs は inputs()、outputs() の順
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]);
+
+    fn tick(&mut self, _inputs: &[u16]) {}
+
+    fn tock(&mut self, _inputs: &[u16]) {}
+
+    // レジスターや RAM の中身。part は DRegister[] の DRegister のような名前で、
+    // CPU や Memory は中の部品（ARegister、RAM16K など）の名前にも答える
+    fn memory(&self, _part: &str) -> Option<&[u16]> {
+        None
+    }
+
+    fn memory_mut(&mut self, _part: &str) -> Option<&mut [u16]> {
+        None
+    }
+}
+
+// 組み込みチップの名前から新しい部品を作る
+pub fn create(name: &str) -> Option<Box<dyn Builtin>> {
+    let chip: Box<dyn Builtin> = match name {
+        "Nand" => Box::new(Nand),
+        "DFF" => Box::new(Dff::default()),
+        "Bit" => Box::new(Register::new("Bit", 1)),
+        "Register" => Box::new(Register::new("Register", 16)),
+        "ARegister" => Box::new(Register::new("ARegister", 16)),
+        "DRegister" => Box::new(Register::new("DRegister", 16)),
+        "PC" => Box::new(Pc::default()),
+        "RAM8" => Box::new(Ram::new("RAM8", 3)),
+        "RAM64" => Box::new(Ram::new("RAM64", 6)),
+        "RAM512" => Box::new(Ram::new("RAM512", 9)),
+        "RAM4K" => Box::new(Ram::new("RAM4K", 12)),
+        "RAM16K" => Box::new(Ram::new("RAM16K", 14)),
+        "Screen" => Box::new(Ram::new("Screen", 13)),
+        "ROM32K" => Box::new(Rom::default()),
+        "Keyboard" => Box::new(Keyboard::default()),
+        "Memory" => Box::new(Memory::default()),
+        "CPU" => Box::new(Cpu::default()),
+        "Computer" => Box::new(Computer::default()),
+        _ => return Some(Box::new(LOGIC.iter().find(|logic| logic.name == name)?)),
+    };
+    Some(chip)
+}
+
+pub struct Nand;
+
+impl Builtin for Nand {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[pin("a", 1), pin("b", 1)];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        const OUTPUTS: &[Pin] = &[pin("out", 1)];
+        OUTPUTS
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = !(inputs[0] & inputs[1]) & 1;
+    }
+}
+
+// 状態を持たないチップ。表から引く
+pub struct Logic {
+    name: &'static str,
+    inputs: &'static [Pin],
+    outputs: &'static [Pin],
+    eval: fn(&[u16], &mut [u16]),
+}
+
+impl Builtin for &'static Logic {
+    fn inputs(&self) -> &'static [Pin] {
+        self.inputs
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        self.outputs
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        (self.eval)(inputs, outputs);
+    }
+}
+
+const IN: &[Pin] = &[pin("in", 1)];
+const IN16: &[Pin] = &[pin("in", 16)];
+const OUT: &[Pin] = &[pin("out", 1)];
+const OUT16: &[Pin] = &[pin("out", 16)];
+const AB: &[Pin] = &[pin("a", 1), pin("b", 1)];
+const AB16: &[Pin] = &[pin("a", 16), pin("b", 16)];
+const SUM_CARRY: &[Pin] = &[pin("sum", 1), pin("carry", 1)];
+
+const LOGIC: &[Logic] = &[
+    Logic {
+        name: "Not",
+        inputs: IN,
+        outputs: OUT,
+        eval: |i, o| o[0] = !i[0] & 1,
+    },
+    Logic {
+        name: "And",
+        inputs: AB,
+        outputs: OUT,
+        eval: |i, o| o[0] = i[0] & i[1],
+    },
+    Logic {
+        name: "Or",
+        inputs: AB,
+        outputs: OUT,
+        eval: |i, o| o[0] = i[0] | i[1],
+    },
+    Logic {
+        name: "Xor",
+        inputs: AB,
+        outputs: OUT,
+        eval: |i, o| o[0] = i[0] ^ i[1],
+    },
+    Logic {
+        name: "Mux",
+        inputs: &[pin("a", 1), pin("b", 1), pin("sel", 1)],
+        outputs: OUT,
+        eval: |i, o| o[0] = i[i[2] as usize],
+    },
+    Logic {
+        name: "DMux",
+        inputs: &[pin("in", 1), pin("sel", 1)],
+        outputs: AB,
+        eval: |i, o| o[i[1] as usize] = i[0],
+    },
+    Logic {
+        name: "Not16",
+        inputs: IN16,
+        outputs: OUT16,
+        eval: |i, o| o[0] = !i[0],
+    },
+    Logic {
+        name: "And16",
+        inputs: AB16,
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[0] & i[1],
+    },
+    Logic {
+        name: "Or16",
+        inputs: AB16,
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[0] | i[1],
+    },
+    Logic {
+        name: "Mux16",
+        inputs: &[pin("a", 16), pin("b", 16), pin("sel", 1)],
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[i[2] as usize],
+    },
+    Logic {
+        name: "Or8Way",
+        inputs: &[pin("in", 8)],
+        outputs: OUT,
+        eval: |i, o| o[0] = (i[0] != 0) as u16,
+    },
+    Logic {
+        name: "Mux4Way16",
+        inputs: &[
+            pin("a", 16),
+            pin("b", 16),
+            pin("c", 16),
+            pin("d", 16),
+            pin("sel", 2),
+        ],
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[i[4] as usize],
+    },
+    Logic {
+        name: "Mux8Way16",
+        inputs: &[
+            pin("a", 16),
+            pin("b", 16),
+            pin("c", 16),
+            pin("d", 16),
+            pin("e", 16),
+            pin("f", 16),
+            pin("g", 16),
+            pin("h", 16),
+            pin("sel", 3),
+        ],
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[i[8] as usize],
+    },
+    Logic {
+        name: "DMux4Way",
+        inputs: &[pin("in", 1), pin("sel", 2)],
+        outputs: &[pin("a", 1), pin("b", 1), pin("c", 1), pin("d", 1)],
+        eval: |i, o| o[i[1] as usize] = i[0],
+    },
+    Logic {
+        name: "DMux8Way",
+        inputs: &[pin("in", 1), pin("sel", 3)],
+        outputs: &[
+            pin("a", 1),
+            pin("b", 1),
+            pin("c", 1),
+            pin("d", 1),
+            pin("e", 1),
+            pin("f", 1),
+            pin("g", 1),
+            pin("h", 1),
+        ],
+        eval: |i, o| o[i[1] as usize] = i[0],
+    },
+    Logic {
+        name: "HalfAdder",
+        inputs: AB,
+        outputs: SUM_CARRY,
+        eval: |i, o| {
+            o[0] = i[0] ^ i[1];
+            o[1] = i[0] & i[1];
+        },
+    },
+    Logic {
+        name: "FullAdder",
+        inputs: &[pin("a", 1), pin("b", 1), pin("c", 1)],
+        outputs: SUM_CARRY,
+        eval: |i, o| {
+            let sum = i[0] + i[1] + i[2];
+            o[0] = sum & 1;
+            o[1] = sum >> 1;
+        },
+    },
+    Logic {
+        name: "Add16",
+        inputs: AB16,
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[0].wrapping_add(i[1]),
+    },
+    Logic {
+        name: "Inc16",
+        inputs: IN16,
+        outputs: OUT16,
+        eval: |i, o| o[0] = i[0].wrapping_add(1),
+    },
+    Logic {
+        name: "ALU",
+        inputs: &[
+            pin("x", 16),
+            pin("y", 16),
+            pin("zx", 1),
+            pin("nx", 1),
+            pin("zy", 1),
+            pin("ny", 1),
+            pin("f", 1),
+            pin("no", 1),
+        ],
+        outputs: &[pin("out", 16), pin("zr", 1), pin("ng", 1)],
+        eval: |i, o| {
+            let control = i[2..].iter().fold(0, |bits, &bit| bits << 1 | bit);
+            o[0] = alu(i[0], i[1], control);
+            o[1] = (o[0] == 0) as u16;
+            o[2] = o[0] >> 15;
+        },
+    },
+];
+
+// control は zx nx zy ny f no の順に上位から並べた 6 ビット
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control >> (5 - n) & 1 == 1;
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+    if bit(5) { !out } else { out }
+}
+
+// 部品の名前が合えば 1 ワードの状態を返す（DRegister[] と DRegister[0] のどちらでも読める）
+fn word<'a>(name: &str, part: &str, word: &'a mut u16) -> Option<&'a mut [u16]> {
+    (name == part).then(|| std::slice::from_mut(word))
+}
+
+// out(t) = in(t-1)
+#[derive(Default)]
+pub struct Dff {
+    // tick で取り込んだ値
+    latched: u16,
+    out: u16,
+}
+
+impl Builtin for Dff {
+    fn inputs(&self) -> &'static [Pin] {
+        IN
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["in"]
+    }
+
+    fn eval(&mut self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.out;
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.latched = inputs[0] & 1;
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.out = self.latched;
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == "DFF").then(|| std::slice::from_ref(&self.out))
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        word("DFF", part, &mut self.out)
+    }
+}
+
+// Bit、Register、ARegister、DRegister。load が 1 なら in を取り込む
+pub struct Register {
+    name: &'static str,
+    width: u16,
+    latched: u16,
+    out: u16,
+}
+
+impl Register {
+    fn new(name: &'static str, width: u16) -> Self {
+        Register {
+            name,
+            width,
+            latched: 0,
+            out: 0,
+        }
+    }
+}
+
+impl Builtin for Register {
+    fn inputs(&self) -> &'static [Pin] {
+        if self.width == 1 {
+            const INPUTS: &[Pin] = &[pin("in", 1), pin("load", 1)];
+            INPUTS
+        } else {
+            const INPUTS: &[Pin] = &[pin("in", 16), pin("load", 1)];
+            INPUTS
+        }
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        if self.width == 1 { OUT } else { OUT16 }
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["in", "load"]
+    }
+
+    fn eval(&mut self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.out;
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.latched = if inputs[1] == 1 { inputs[0] } else { self.out };
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.out = self.latched;
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == self.name).then(|| std::slice::from_ref(&self.out))
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        word(self.name, part, &mut self.out)
+    }
+}
+
+// reset、load、inc の順に優先する
+#[derive(Default)]
+pub struct Pc {
+    latched: u16,
+    out: u16,
+}
+
+impl Pc {
+    fn next(out: u16, input: u16, load: bool, inc: bool, reset: bool) -> u16 {
+        if reset {
+            0
+        } else if load {
+            input
+        } else if inc {
+            out.wrapping_add(1)
+        } else {
+            out
+        }
+    }
+}
+
+impl Builtin for Pc {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[
+            pin("in", 16),
+            pin("load", 1),
+            pin("inc", 1),
+            pin("reset", 1),
+        ];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT16
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["in", "load", "inc", "reset"]
+    }
+
+    fn eval(&mut self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.out;
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.latched = Pc::next(
+            self.out,
+            inputs[0],
+            inputs[1] == 1,
+            inputs[2] == 1,
+            inputs[3] == 1,
+        );
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.out = self.latched;
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == "PC").then(|| std::slice::from_ref(&self.out))
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        word("PC", part, &mut self.out)
+    }
+}
+
+// RAM8 から RAM16K と Screen。読み出しは address だけで決まる
+pub struct Ram {
+    name: &'static str,
+    address_bits: u16,
+    words: Vec<u16>,
+    // tick で取り込んだ書き込み
+    write: Option<(usize, u16)>,
+}
+
+impl Ram {
+    fn new(name: &'static str, address_bits: u16) -> Self {
+        Ram {
+            name,
+            address_bits,
+            words: vec![0; 1 << address_bits],
+            write: None,
+        }
+    }
+}
+
+impl Builtin for Ram {
+    fn inputs(&self) -> &'static [Pin] {
+        macro_rules! inputs {
+            ($bits:expr) => {{
+                const INPUTS: &[Pin] = &[pin("in", 16), pin("load", 1), pin("address", $bits)];
+                INPUTS
+            }};
+        }
+        match self.address_bits {
+            3 => inputs!(3),
+            6 => inputs!(6),
+            9 => inputs!(9),
+            12 => inputs!(12),
+            13 => inputs!(13),
+            _ => inputs!(14),
+        }
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT16
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["in", "load"]
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.words[inputs[2] as usize];
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.write = (inputs[1] == 1).then_some((inputs[2] as usize, inputs[0]));
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        if let Some((address, value)) = self.write.take() {
+            self.words[address] = value;
+        }
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == self.name).then_some(&self.words[..])
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        (part == self.name).then_some(&mut self.words[..])
+    }
+}
+
+// プログラムは ROM32K load Prog.hack で読み込む
+pub struct Rom {
+    words: Vec<u16>,
+}
+
+impl Default for Rom {
+    fn default() -> Self {
+        Rom {
+            words: vec![0; 1 << 15],
+        }
+    }
+}
+
+impl Builtin for Rom {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[pin("address", 15)];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT16
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.words[inputs[0] as usize];
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == "ROM32K").then_some(&self.words[..])
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        (part == "ROM32K").then_some(&mut self.words[..])
+    }
+}
+
+// 押されているキーのコード。テストスクリプトからは set Keyboard[] で変える
+#[derive(Default)]
+pub struct Keyboard {
+    key: u16,
+}
+
+impl Builtin for Keyboard {
+    fn inputs(&self) -> &'static [Pin] {
+        &[]
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT16
+    }
+
+    fn eval(&mut self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.key;
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        (part == "Keyboard").then(|| std::slice::from_ref(&self.key))
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        word("Keyboard", part, &mut self.key)
+    }
+}
+
+const SCREEN: usize = 0x4000;
+const KEYBOARD: usize = 0x6000;
+
+// 0x0000-0x3FFF が RAM16K、0x4000-0x5FFF が Screen、0x6000 が Keyboard
+pub struct Memory {
+    words: Vec<u16>,
+    write: Option<(usize, u16)>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            words: vec![0; KEYBOARD + 1],
+            write: None,
+        }
+    }
+}
+
+impl Memory {
+    fn read(&self, address: u16) -> u16 {
+        self.words.get(address as usize).copied().unwrap_or(0)
+    }
+
+    // キーボードと範囲外には書けない
+    fn latch(&mut self, value: u16, load: bool, address: u16) {
+        self.write = (load && (address as usize) < KEYBOARD).then_some((address as usize, value));
+    }
+
+    fn commit(&mut self) {
+        if let Some((address, value)) = self.write.take() {
+            self.words[address] = value;
+        }
+    }
+
+    fn range(part: &str) -> Option<std::ops::Range<usize>> {
+        match part {
+            "Memory" => Some(0..KEYBOARD + 1),
+            "RAM16K" => Some(0..SCREEN),
+            "Screen" => Some(SCREEN..KEYBOARD),
+            "Keyboard" => Some(KEYBOARD..KEYBOARD + 1),
+            _ => None,
+        }
+    }
+}
+
+impl Builtin for Memory {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[pin("in", 16), pin("load", 1), pin("address", 15)];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        OUT16
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["in", "load"]
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.read(inputs[2]);
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.latch(inputs[0], inputs[1] == 1, inputs[2]);
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.commit();
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        Memory::range(part).map(|range| &self.words[range])
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        Memory::range(part).map(|range| &mut self.words[range])
+    }
+}
+
+// Hack の CPU。A、D、PC の各レジスターは tick で次の値を求め、tock で入れ替える
+#[derive(Default)]
+pub struct Cpu {
+    a: u16,
+    d: u16,
+    pc: u16,
+    latched: (u16, u16, u16),
+}
+
+// CPU の出力
+struct CpuOutputs {
+    out_m: u16,
+    write_m: bool,
+    address_m: u16,
+    pc: u16,
+}
+
+impl Cpu {
+    fn outputs(&self, in_m: u16, instruction: u16) -> CpuOutputs {
+        let bit = |n: u16| instruction >> n & 1 == 1;
+        let compute = bit(15);
+        let y = if bit(12) { in_m } else { self.a };
+        CpuOutputs {
+            out_m: alu(self.d, y, instruction >> 6 & 0x3f),
+            write_m: compute && bit(3),
+            address_m: self.a & mask(15),
+            pc: self.pc & mask(15),
+        }
+    }
+
+    fn latch(&mut self, in_m: u16, instruction: u16, reset: bool) {
+        let bit = |n: u16| instruction >> n & 1 == 1;
+        if !bit(15) {
+            self.latched = (
+                instruction,
+                self.d,
+                Pc::next(self.pc, 0, false, true, reset),
+            );
+            return;
+        }
+        let out = self.outputs(in_m, instruction).out_m;
+        let (zero, negative) = (out == 0, out >> 15 == 1);
+        let jump = (bit(2) && negative) || (bit(1) && zero) || (bit(0) && !zero && !negative);
+        self.latched = (
+            if bit(5) { out } else { self.a },
+            if bit(4) { out } else { self.d },
+            Pc::next(self.pc, self.a, jump, true, reset),
+        );
+    }
+
+    fn commit(&mut self) {
+        (self.a, self.d, self.pc) = self.latched;
+    }
+}
+
+impl Builtin for Cpu {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[pin("inM", 16), pin("instruction", 16), pin("reset", 1)];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        const OUTPUTS: &[Pin] = &[
+            pin("outM", 16),
+            pin("writeM", 1),
+            pin("addressM", 15),
+            pin("pc", 15),
+        ];
+        OUTPUTS
+    }
+
+    // outM は inM と instruction でも変わるが、Computer の中では inM が addressM から
+    // Memory を通って戻ってくるので、公式と同じくすべての入力をクロック付きとして扱う。
+    // tick と tock はその前に回路を落ち着かせるので、取り込む値は正しい
+    fn clocked(&self) -> &'static [&'static str] {
+        &["inM", "instruction", "reset"]
+    }
+
+    fn eval(&mut self, inputs: &[u16], outputs: &mut [u16]) {
+        let cpu = self.outputs(inputs[0], inputs[1]);
+        outputs.copy_from_slice(&[cpu.out_m, cpu.write_m as u16, cpu.address_m, cpu.pc]);
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.latch(inputs[0], inputs[1], inputs[2] == 1);
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.commit();
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        match part {
+            "ARegister" => Some(std::slice::from_ref(&self.a)),
+            "DRegister" => Some(std::slice::from_ref(&self.d)),
+            "PC" => Some(std::slice::from_ref(&self.pc)),
+            _ => None,
+        }
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        word("ARegister", part, &mut self.a)
+            .or_else(|| word("DRegister", part, &mut self.d))
+            .or_else(|| word("PC", part, &mut self.pc))
+    }
+}
+
+// CPU、Memory、ROM32K をつないだもの。出力はない
+#[derive(Default)]
+pub struct Computer {
+    cpu: Cpu,
+    memory: Memory,
+    rom: Rom,
+}
+
+impl Builtin for Computer {
+    fn inputs(&self) -> &'static [Pin] {
+        const INPUTS: &[Pin] = &[pin("reset", 1)];
+        INPUTS
+    }
+
+    fn outputs(&self) -> &'static [Pin] {
+        &[]
+    }
+
+    fn clocked(&self) -> &'static [&'static str] {
+        &["reset"]
+    }
+
+    fn eval(&mut self, _inputs: &[u16], _outputs: &mut [u16]) {}
+
+    fn tick(&mut self, inputs: &[u16]) {
+        let instruction = self.rom.words[self.cpu.pc as usize & mask(15) as usize];
+        let in_m = self.memory.read(self.cpu.a & mask(15));
+        let cpu = self.cpu.outputs(in_m, instruction);
+        self.memory.latch(cpu.out_m, cpu.write_m, cpu.address_m);
+        self.cpu.latch(in_m, instruction, inputs[0] == 1);
+    }
+
+    fn tock(&mut self, _inputs: &[u16]) {
+        self.cpu.commit();
+        self.memory.commit();
+    }
+
+    fn memory(&self, part: &str) -> Option<&[u16]> {
+        self.cpu
+            .memory(part)
+            .or_else(|| self.memory.memory(part))
+            .or_else(|| self.rom.memory(part))
+    }
+
+    fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        self.cpu
+            .memory_mut(part)
+            .or_else(|| self.memory.memory_mut(part))
+            .or_else(|| self.rom.memory_mut(part))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(chip: &mut dyn Builtin, inputs: &[u16]) -> u16 {
+        let mut outputs = vec![0; chip.outputs().len()];
+        chip.eval(inputs, &mut outputs);
+        outputs[0]
+    }
+
+    #[test]
+    fn test_nand_and_dff() {
+        let mut nand = create("Nand").unwrap();
+        let table: Vec<u16> = [[0, 0], [0, 1], [1, 0], [1, 1]]
+            .iter()
+            .map(|inputs| eval(nand.as_mut(), inputs))
+            .collect();
+        assert_eq!(table, [1, 1, 1, 0]);
+
+        let mut dff = create("DFF").unwrap();
+        assert_eq!(dff.clocked(), ["in"]);
+        assert_eq!(eval(dff.as_mut(), &[1]), 0);
+        dff.tick(&[1]);
+        // tock までは前の値
+        assert_eq!(eval(dff.as_mut(), &[0]), 0);
+        dff.tock(&[0]);
+        assert_eq!(eval(dff.as_mut(), &[0]), 1);
+        dff.tick(&[0]);
+        dff.tock(&[1]);
+        assert_eq!(eval(dff.as_mut(), &[1]), 0);
+
+        assert!(create("Nor").is_none());
+    }
+
+    #[test]
+    fn test_logic() {
+        let mut mux = create("Mux4Way16").unwrap();
+        assert_eq!(eval(mux.as_mut(), &[10, 20, 30, 40, 2]), 30);
+
+        let mut dmux = create("DMux8Way").unwrap();
+        let mut outputs = [0; 8];
+        dmux.eval(&[1, 5], &mut outputs);
+        assert_eq!(outputs, [0, 0, 0, 0, 0, 1, 0, 0]);
+
+        // x - y（zx nx zy ny f no = 0 1 0 0 1 1）
+        let mut alu = create("ALU").unwrap();
+        let mut outputs = [0; 3];
+        alu.eval(&[3, 5, 0, 1, 0, 0, 1, 1], &mut outputs);
+        assert_eq!(outputs, [(-2i16) as u16, 0, 1]);
+    }
+
+    #[test]
+    fn test_ram_and_memory() {
+        let mut ram = create("RAM8").unwrap();
+        assert_eq!(ram.inputs()[2], pin("address", 3));
+        ram.tick(&[42, 1, 5]);
+        assert_eq!(eval(ram.as_mut(), &[0, 0, 5]), 0);
+        ram.tock(&[42, 1, 5]);
+        assert_eq!(eval(ram.as_mut(), &[0, 0, 5]), 42);
+        assert_eq!(ram.memory("RAM8").unwrap()[5], 42);
+
+        let mut memory = create("Memory").unwrap();
+        memory.tick(&[7, 1, 0x4001]);
+        memory.tock(&[7, 1, 0x4001]);
+        assert_eq!(memory.memory("Screen").unwrap()[1], 7);
+        memory.memory_mut("Keyboard").unwrap()[0] = 75;
+        assert_eq!(eval(memory.as_mut(), &[0, 0, 0x6000]), 75);
+        // キーボードには書けない
+        memory.tick(&[1, 1, 0x6000]);
+        memory.tock(&[1, 1, 0x6000]);
+        assert_eq!(eval(memory.as_mut(), &[0, 0, 0x6000]), 75);
+    }
+
+    #[test]
+    fn test_computer() {
+        // RAM[2] = RAM[0] + RAM[1]
+        let program = [
+            0b0000000000000000, // @0
+            0b1111110000010000, // D=M
+            0b0000000000000001, // @1
+            0b1111000010010000, // D=D+M
+            0b0000000000000010, // @2
+            0b1110001100001000, // M=D
+            0b0000000000000110, // (END) @END
+            0b1110101010000111, // 0;JMP
+        ];
+        let mut computer = create("Computer").unwrap();
+        computer.memory_mut("ROM32K").unwrap()[..8].copy_from_slice(&program);
+        computer.memory_mut("RAM16K").unwrap()[..2].copy_from_slice(&[3, 5]);
+        for _ in 0..10 {
+            computer.tick(&[0]);
+            computer.tock(&[0]);
+        }
+        assert_eq!(computer.memory("RAM16K").unwrap()[2], 8);
+        assert_eq!(computer.memory("PC").unwrap(), [6]);
+        assert_eq!(computer.memory("DRegister").unwrap(), [8]);
+
+        computer.tick(&[1]);
+        computer.tock(&[1]);
+        assert_eq!(computer.memory("PC").unwrap(), [0]);
+    }
+}
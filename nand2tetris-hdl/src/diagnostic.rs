@@ -0,0 +1,175 @@
+// 展開のエラーと警告。1 行の表示（Display）のほかに、ソースの行に印を付けた表示を作れる
+//
+// Top.hdl: Line 5, column 23: 'x' is driven by more than one part
+//   |
+// 4 |     Nand(a=a, b=b, out=x);
+//   |                        - first driven here
+// 5 |     Nand(a=a, b=a, out=x);
+//   |                        ^
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::ast::Span;
+
+use nand2tetris_core::{diagnostic::header, sarif::Finding};
+
+pub use nand2tetris_core::diagnostic::Severity;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub span: Span,
+    pub message: String,
+    // 同じファイルの関係する場所（最初に駆動したところなど）
+    pub notes: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn error(file: &Path, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            file: file.to_path_buf(),
+            span,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(file: &Path, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(file, span, message)
+        }
+    }
+
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push((span, message.into()));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Top.hdl: Line 5, column 23
+    pub fn location(&self) -> String {
+        format!("{}: {}", self.file.display(), self.span.start)
+    }
+
+    // --sarif に書く形。注記は含めない
+    pub fn finding(&self) -> Finding {
+        Finding {
+            severity: self.severity,
+            file: self.file.display().to_string(),
+            line: self.span.start.line,
+            column: Some(self.span.start.column),
+            message: self.message.clone(),
+        }
+    }
+
+    // 端末向け。1 行目はほかのツールの診断と同じ形。
+    // source は self.file の中身。行が見つからない場所は印を付けずに飛ばす
+    pub fn render(&self, source: &str) -> String {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut labels: Vec<(Span, char, &str)> = vec![(self.span, '^', "")];
+        labels.extend(
+            self.notes
+                .iter()
+                .map(|(span, message)| (*span, '-', message.as_str())),
+        );
+        labels.sort_by_key(|(span, ..)| (span.start.line, span.start.column));
+        labels.retain(|(span, ..)| (1..=lines.len()).contains(&span.start.line));
+
+        let gutter = labels
+            .iter()
+            .map(|(span, ..)| span.start.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let header = header(self.severity, &self.location(), &self.message);
+        let mut text = format!("{}\n{:gutter$} |", header, "");
+        for (span, mark, message) in labels {
+            let line = lines[span.start.line - 1].trim_end();
+            let start = span.start.column.max(1);
+            // 複数行にまたがる場所は最初の行の終わりまで
+            let end = if span.end.line == span.start.line {
+                span.end.column
+            } else {
+                line.chars().count() + 1
+            };
+            let underline = mark.to_string().repeat(end.saturating_sub(start).max(1));
+            text.push_str(&format!("\n{:>gutter$} | {}", span.start.line, line));
+            text.push_str(&format!(
+                "\n{:gutter$} | {}{}",
+                "",
+                " ".repeat(start - 1),
+                underline
+            ));
+            if !message.is_empty() {
+                text.push_str(&format!(" {}", message));
+            }
+        }
+        text
+    }
+}
+
+// Top.hdl: Line 3, column 5: warning: Input pin 'b' of 'Nand' is not connected and reads as false
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: ", self.file.display(), self.span.start)?;
+        if self.severity == Severity::Warning {
+            f.write_str("warning: ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Position;
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span {
+            start: Position {
+                line,
+                column: start,
+            },
+            end: Position { line, column: end },
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "CHIP Top {\n    IN a;\n    OUT out;\n    PARTS:\n    Nand(a=a, b=a, out=x);\n    Nand(a=a, b=a, out=x);\n}\n";
+        let diagnostic = Diagnostic::error(
+            Path::new("Top.hdl"),
+            span(6, 24, 25),
+            "'x' is driven by more than one part",
+        )
+        .with_note(span(5, 24, 25), "first driven here");
+        assert_eq!(
+            diagnostic.render(source),
+            [
+                "Top.hdl: Line 6, column 24: error: 'x' is driven by more than one part",
+                "  |",
+                "5 |     Nand(a=a, b=a, out=x);",
+                "  |                        - first driven here",
+                "6 |     Nand(a=a, b=a, out=x);",
+                "  |                        ^",
+            ]
+            .join("\n")
+        );
+
+        let warning = Diagnostic::warning(Path::new("Top.hdl"), span(3, 9, 12), "unused");
+        assert_eq!(
+            warning.render(source),
+            "Top.hdl: Line 3, column 9: warning: unused\n  |\n3 |     OUT out;\n  |         ^^^"
+        );
+    }
+}
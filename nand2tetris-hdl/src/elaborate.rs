@@ -0,0 +1,907 @@
+// チップの階層を組み込みチップの部品と、それらをつなぐ 1 ビットのネットに展開する
+//
+// 公式のシミュレーターと同じ規則:
+//   - 内部ピンは最初に使ったところで作られ、幅はそのときの部品のピンの幅。添字は付けられない
+//   - true と false はピンの幅すべてを埋める。つながっていない部品の入力は false
+//   - 部品の出力はいくつのピンにつないでもよいが、1 つのピンを駆動できるのは 1 つの出力だけ
+//   - チップの入力ピンは駆動できず、出力ピンは部品の入力に使えない
+//
+// 公式では黙って false になる、つながっていない部品の入力と駆動されていない出力ピンは警告にする
+
+use anyhow::{Result, anyhow};
+use nand2tetris_core::cli::{Fail, Failure};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{BitRange, Chip, ChipBody, Part, PinRef, Span, Value},
+    builtin,
+    diagnostic::Diagnostic,
+    parse_file,
+};
+
+pub type Net = usize;
+
+// 定数のネット
+pub const FALSE: Net = 0;
+pub const TRUE: Net = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinSpec {
+    pub name: String,
+    pub width: u16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Interface {
+    pub inputs: Vec<PinSpec>,
+    pub outputs: Vec<PinSpec>,
+}
+
+impl Interface {
+    fn from_builtin(chip: &dyn builtin::Builtin) -> Self {
+        let specs = |pins: &[builtin::Pin]| {
+            pins.iter()
+                .map(|pin| PinSpec {
+                    name: pin.name.to_string(),
+                    width: pin.width,
+                })
+                .collect()
+        };
+        Interface {
+            inputs: specs(chip.inputs()),
+            outputs: specs(chip.outputs()),
+        }
+    }
+
+    fn from_chip(chip: &Chip) -> Self {
+        let specs = |pins: &[crate::ast::PinDecl]| {
+            pins.iter()
+                .map(|pin| PinSpec {
+                    name: pin.name.name.clone(),
+                    width: pin.width,
+                })
+                .collect()
+        };
+        Interface {
+            inputs: specs(&chip.inputs),
+            outputs: specs(&chip.outputs),
+        }
+    }
+
+    // (出力かどうか, inputs か outputs の番号)
+    fn find(&self, name: &str) -> Option<(bool, usize)> {
+        let position = |pins: &[PinSpec]| pins.iter().position(|pin| pin.name == name);
+        position(&self.inputs)
+            .map(|index| (false, index))
+            .or_else(|| position(&self.outputs).map(|index| (true, index)))
+    }
+
+    fn pin(&self, output: bool, index: usize) -> &PinSpec {
+        if output {
+            &self.outputs[index]
+        } else {
+            &self.inputs[index]
+        }
+    }
+}
+
+pub enum Definition {
+    Hdl {
+        chip: Chip,
+        path: PathBuf,
+        interface: Interface,
+    },
+    // BUILTIN の .hdl か、.hdl が見つからなかったときの組み込みチップ
+    Builtin {
+        name: String,
+        interface: Interface,
+    },
+}
+
+impl Definition {
+    pub fn interface(&self) -> &Interface {
+        match self {
+            Definition::Hdl { interface, .. } | Definition::Builtin { interface, .. } => interface,
+        }
+    }
+}
+
+// チップの名前から定義を探す。dir の Name.hdl を先に見て、なければ組み込みチップ
+pub struct Library {
+    dir: Option<PathBuf>,
+    chips: HashMap<String, Rc<Definition>>,
+}
+
+impl Library {
+    pub fn new(dir: Option<&Path>) -> Self {
+        Library {
+            dir: dir.map(Path::to_path_buf),
+            chips: HashMap::new(),
+        }
+    }
+
+    // 読み込み済みのチップを足す。同じ名前のファイルより優先する
+    pub fn insert(&mut self, chip: Chip, path: PathBuf) -> Result<Rc<Definition>> {
+        let name = chip.name.name.clone();
+        let interface = Interface::from_chip(&chip);
+        let definition = match &chip.body {
+            ChipBody::Parts(_) => Definition::Hdl {
+                chip,
+                path,
+                interface,
+            },
+            ChipBody::Builtin { name: builtin, .. } => {
+                let Some(implementation) = builtin::create(&builtin.name) else {
+                    return Err(error_at(
+                        &path,
+                        builtin.span,
+                        format!("Built-in chip '{}' is not available", builtin.name),
+                    ));
+                };
+                if Interface::from_builtin(implementation.as_ref()) != interface {
+                    return Err(error_at(
+                        &path,
+                        chip.name.span,
+                        format!(
+                            "The pins of '{}' do not match the built-in chip '{}'",
+                            name, builtin.name
+                        ),
+                    ));
+                }
+                Definition::Builtin {
+                    name: builtin.name.clone(),
+                    interface,
+                }
+            }
+        };
+        let definition = Rc::new(definition);
+        self.chips.insert(name, definition.clone());
+        Ok(definition)
+    }
+
+    pub fn get(&mut self, name: &str) -> Result<Option<Rc<Definition>>> {
+        if let Some(definition) = self.chips.get(name) {
+            return Ok(Some(definition.clone()));
+        }
+        if let Some(path) = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.hdl", name)))
+            .filter(|path| path.is_file())
+        {
+            let chip = parse_file(&path)?;
+            if chip.name.name != name {
+                return Err(error_at(
+                    &path,
+                    chip.name.span,
+                    format!("Expected chip '{}' but found '{}'", name, chip.name.name),
+                ));
+            }
+            return self.insert(chip, path).map(Some);
+        }
+        let Some(implementation) = builtin::create(name) else {
+            return Ok(None);
+        };
+        let definition = Rc::new(Definition::Builtin {
+            name: name.to_string(),
+            interface: Interface::from_builtin(implementation.as_ref()),
+        });
+        self.chips.insert(name.to_string(), definition.clone());
+        Ok(Some(definition))
+    }
+}
+
+// ピンの各ビットのネット。nets[0] が最下位ビット
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub name: String,
+    pub nets: Vec<Net>,
+}
+
+// 組み込みチップの部品。inputs と outputs は builtin::Builtin の inputs()、outputs() の順
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gate {
+    pub chip: String,
+    // Xor/Nand[2] のような部品の位置。番号は PARTS の中の順番
+    pub path: String,
+    pub inputs: Vec<Vec<Net>>,
+    pub outputs: Vec<Vec<Net>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Design {
+    pub name: String,
+    pub inputs: Vec<Port>,
+    pub outputs: Vec<Port>,
+    // 一番外のチップの内部ピン
+    pub internals: Vec<Port>,
+    pub gates: Vec<Gate>,
+    // ネットの数。FALSE と TRUE を含む
+    pub nets: usize,
+    // 同じ場所の警告は、チップが何度使われても 1 つだけ
+    pub warnings: Vec<Diagnostic>,
+}
+
+// path の .hdl を読んで展開する。部品のチップは同じディレクトリから探す
+pub fn elaborate_file(path: &Path) -> Result<Design> {
+    let chip = parse_file(path)?;
+    let name = chip.name.name.clone();
+    let mut library = Library::new(path.parent());
+    library.insert(chip, path.to_path_buf())?;
+    elaborate(&mut library, &name)
+}
+
+pub fn elaborate(library: &mut Library, name: &str) -> Result<Design> {
+    let definition = library
+        .get(name)?
+        .ok_or_else(|| anyhow!("Chip '{}' not found", name))?;
+    let mut elaborator = Elaborator {
+        library,
+        parents: vec![FALSE, TRUE],
+        driven: vec![true, true],
+        gates: Vec::new(),
+        stack: Vec::new(),
+        warnings: Vec::new(),
+    };
+    // 部品の .hdl の構文エラーはそのまま
+    let instance = elaborator
+        .instantiate(name, &definition, name.to_string())
+        .fail(Failure::Semantic)?;
+    Ok(elaborator.finish(name, &definition, instance))
+}
+
+// 部品 1 つ分のピンのネット
+struct Instance {
+    inputs: Vec<Vec<Net>>,
+    outputs: Vec<Vec<Net>>,
+    internals: Vec<Port>,
+}
+
+// つないだピンは union-find で同じネットにまとめる
+struct Elaborator<'a> {
+    library: &'a mut Library,
+    parents: Vec<Net>,
+    // 組み込みチップの出力か定数に駆動されているか（根だけ正しい）
+    driven: Vec<bool>,
+    gates: Vec<Gate>,
+    // 展開中のチップ（自分自身を部品にしていないか調べる）
+    stack: Vec<String>,
+    warnings: Vec<Diagnostic>,
+}
+
+// 展開中の HDL チップ 1 つ分のピン
+struct Scope<'c> {
+    chip: &'c Chip,
+    file: &'c Path,
+    interface: &'c Interface,
+    inputs: Vec<Vec<Net>>,
+    outputs: Vec<Vec<Net>>,
+    internals: Vec<Internal>,
+    // 部品の出力につないだネットと、つないだ場所
+    drivers: HashMap<Net, Span>,
+}
+
+struct Internal {
+    port: Port,
+    driven: bool,
+    // 最初に使った場所
+    span: Span,
+}
+
+impl Elaborator<'_> {
+    fn net(&mut self, driven: bool) -> Net {
+        self.parents.push(self.parents.len());
+        self.driven.push(driven);
+        self.parents.len() - 1
+    }
+
+    fn nets(&mut self, width: u16, driven: bool) -> Vec<Net> {
+        (0..width).map(|_| self.net(driven)).collect()
+    }
+
+    fn find(&mut self, mut net: Net) -> Net {
+        while self.parents[net] != net {
+            self.parents[net] = self.parents[self.parents[net]];
+            net = self.parents[net];
+        }
+        net
+    }
+
+    // 両方が駆動されていればつながない
+    fn union(&mut self, a: Net, b: Net) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return true;
+        }
+        if self.driven[a] && self.driven[b] {
+            return false;
+        }
+        // 定数が根に残るように小さい方を根にする
+        let (root, child) = if a < b { (a, b) } else { (b, a) };
+        self.parents[child] = root;
+        self.driven[root] |= self.driven[child];
+        true
+    }
+
+    fn instantiate(
+        &mut self,
+        name: &str,
+        definition: &Definition,
+        path: String,
+    ) -> Result<Instance> {
+        let interface = definition.interface();
+        let (chip, file) = match definition {
+            Definition::Builtin { name, interface } => {
+                let inputs: Vec<Vec<Net>> = interface
+                    .inputs
+                    .iter()
+                    .map(|pin| self.nets(pin.width, false))
+                    .collect();
+                let outputs: Vec<Vec<Net>> = interface
+                    .outputs
+                    .iter()
+                    .map(|pin| self.nets(pin.width, true))
+                    .collect();
+                self.gates.push(Gate {
+                    chip: name.clone(),
+                    path,
+                    inputs: inputs.clone(),
+                    outputs: outputs.clone(),
+                });
+                return Ok(Instance {
+                    inputs,
+                    outputs,
+                    internals: Vec::new(),
+                });
+            }
+            Definition::Hdl { chip, path, .. } => (chip, path),
+        };
+        let ChipBody::Parts(parts) = &chip.body else {
+            unreachable!("built-in bodies become Definition::Builtin");
+        };
+
+        let mut scope = Scope {
+            chip,
+            file,
+            interface,
+            inputs: interface
+                .inputs
+                .iter()
+                .map(|pin| self.nets(pin.width, false))
+                .collect(),
+            outputs: interface
+                .outputs
+                .iter()
+                .map(|pin| self.nets(pin.width, false))
+                .collect(),
+            internals: Vec::new(),
+            drivers: HashMap::new(),
+        };
+        self.stack.push(name.to_string());
+        for (index, part) in parts.iter().enumerate() {
+            self.part(
+                &mut scope,
+                part,
+                format!("{}/{}[{}]", path, part.chip.name, index),
+            )?;
+        }
+        self.stack.pop();
+
+        if let Some(internal) = scope.internals.iter().find(|internal| !internal.driven) {
+            return Err(error_at(
+                file,
+                internal.span,
+                format!(
+                    "Internal pin '{}' is not driven by any part",
+                    internal.port.name
+                ),
+            ));
+        }
+        for (pin, nets) in chip.outputs.iter().zip(&scope.outputs) {
+            let undriven: Vec<bool> = nets
+                .iter()
+                .map(|&net| {
+                    let root = self.find(net);
+                    !self.driven[root]
+                })
+                .collect();
+            for range in bit_ranges(&undriven) {
+                self.warn(Diagnostic::warning(
+                    file,
+                    pin.span,
+                    format!(
+                        "Output pin '{}{}' of '{}' is not driven by any part and reads as false",
+                        pin.name.name, range, chip.name.name
+                    ),
+                ));
+            }
+        }
+        Ok(Instance {
+            inputs: scope.inputs,
+            outputs: scope.outputs,
+            internals: scope
+                .internals
+                .into_iter()
+                .map(|internal| internal.port)
+                .collect(),
+        })
+    }
+
+    fn part(&mut self, scope: &mut Scope, part: &Part, path: String) -> Result<()> {
+        let Some(definition) = self.library.get(&part.chip.name)? else {
+            return Err(error_at(
+                scope.file,
+                part.chip.span,
+                format!("Chip '{}' not found", part.chip.name),
+            ));
+        };
+        if self.stack.contains(&part.chip.name) {
+            return Err(error_at(
+                scope.file,
+                part.chip.span,
+                format!("Chip '{}' contains itself", part.chip.name),
+            ));
+        }
+        let instance = self.instantiate(&part.chip.name, &definition, path)?;
+        let interface = definition.interface();
+        // 部品の入力のうちつないだビット
+        let mut connected: Vec<Vec<bool>> = interface
+            .inputs
+            .iter()
+            .map(|pin| vec![false; pin.width as usize])
+            .collect();
+
+        for connection in &part.connections {
+            let pin = &connection.pin;
+            let Some((is_output, index)) = interface.find(&pin.name.name) else {
+                return Err(error_at(
+                    scope.file,
+                    pin.name.span,
+                    format!("Chip '{}' has no pin '{}'", part.chip.name, pin.name.name),
+                ));
+            };
+            let (start, width) = sub_bus(scope.file, pin, interface.pin(is_output, index).width)?;
+            let part_nets = if is_output {
+                &instance.outputs[index][start..start + width]
+            } else {
+                let bits = &mut connected[index][start..start + width];
+                if bits.iter().any(|&bit| bit) {
+                    return Err(error_at(
+                        scope.file,
+                        pin.span,
+                        format!(
+                            "Pin '{}' of '{}' is connected more than once",
+                            pin.name.name, part.chip.name
+                        ),
+                    ));
+                }
+                bits.fill(true);
+                &instance.inputs[index][start..start + width]
+            };
+
+            let nets = self.value(scope, pin, is_output, width, &connection.value)?;
+            let span = connection.value.span();
+            for (&part_net, &net) in part_nets.iter().zip(&nets) {
+                if !self.union(part_net, net) {
+                    let mut error = Diagnostic::error(
+                        scope.file,
+                        span,
+                        format!("'{}' is driven by more than one part", connection.value),
+                    );
+                    if let Some(&first) = scope.drivers.get(&net) {
+                        error = error.with_note(first, "first driven here");
+                    }
+                    return Err(error.into());
+                }
+                if is_output {
+                    scope.drivers.entry(net).or_insert(span);
+                }
+            }
+        }
+
+        // つながっていない入力は false
+        for ((nets, bits), spec) in instance
+            .inputs
+            .iter()
+            .zip(&connected)
+            .zip(&interface.inputs)
+        {
+            for (&net, &bit) in nets.iter().zip(bits) {
+                if !bit {
+                    self.union(net, FALSE);
+                }
+            }
+            let unconnected: Vec<bool> = bits.iter().map(|&bit| !bit).collect();
+            for range in bit_ranges(&unconnected) {
+                self.warn(Diagnostic::warning(
+                    scope.file,
+                    part.chip.span,
+                    format!(
+                        "Input pin '{}{}' of '{}' is not connected and reads as false",
+                        spec.name, range, part.chip.name
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn warn(&mut self, warning: Diagnostic) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    // 部品のピン pin（幅 width）につなぐ value のネット
+    fn value(
+        &mut self,
+        scope: &mut Scope,
+        pin: &PinRef,
+        is_output: bool,
+        width: usize,
+        value: &Value,
+    ) -> Result<Vec<Net>> {
+        let value = match value {
+            Value::Constant(value, span) if is_output => {
+                return Err(error_at(
+                    scope.file,
+                    *span,
+                    format!(
+                        "Output pin '{}' cannot be connected to {}",
+                        pin.name.name, value
+                    ),
+                ));
+            }
+            Value::Constant(value, _) => return Ok(vec![if *value { TRUE } else { FALSE }; width]),
+            Value::Pin(value) => value,
+        };
+        let chip = &scope.chip.name.name;
+        match scope.interface.find(&value.name.name) {
+            Some((false, _)) if is_output => Err(error_at(
+                scope.file,
+                value.span,
+                format!(
+                    "Output pin '{}' cannot drive input pin '{}' of '{}'",
+                    pin.name.name, value.name.name, chip
+                ),
+            )),
+            Some((true, _)) if !is_output => Err(error_at(
+                scope.file,
+                value.span,
+                format!(
+                    "Output pin '{}' of '{}' cannot be used as an input of a part",
+                    value.name.name, chip
+                ),
+            )),
+            Some((output, index)) => {
+                let spec = scope.interface.pin(output, index);
+                let (start, value_width) = sub_bus(scope.file, value, spec.width)?;
+                if value_width != width {
+                    return Err(width_mismatch(scope.file, pin, width, value, value_width));
+                }
+                let nets = if output {
+                    &scope.outputs[index]
+                } else {
+                    &scope.inputs[index]
+                };
+                Ok(nets[start..start + width].to_vec())
+            }
+            None => {
+                if value.range.is_some() {
+                    return Err(error_at(
+                        scope.file,
+                        value.span,
+                        format!("Internal pin '{}' cannot be subscripted", value.name.name),
+                    ));
+                }
+                let index = scope
+                    .internals
+                    .iter()
+                    .position(|internal| internal.port.name == value.name.name);
+                let internal = match index {
+                    Some(index) => &mut scope.internals[index],
+                    None => {
+                        let nets = self.nets(width as u16, false);
+                        scope.internals.push(Internal {
+                            port: Port {
+                                name: value.name.name.clone(),
+                                nets,
+                            },
+                            driven: false,
+                            span: value.name.span,
+                        });
+                        scope.internals.last_mut().unwrap()
+                    }
+                };
+                if internal.port.nets.len() != width {
+                    return Err(width_mismatch(
+                        scope.file,
+                        pin,
+                        width,
+                        value,
+                        internal.port.nets.len(),
+                    ));
+                }
+                internal.driven |= is_output;
+                Ok(internal.port.nets.clone())
+            }
+        }
+    }
+
+    // ネットを根にまとめて 0 から番号を振り直す
+    fn finish(mut self, name: &str, definition: &Definition, instance: Instance) -> Design {
+        let roots: Vec<Net> = (0..self.parents.len()).map(|net| self.find(net)).collect();
+        let mut numbers: HashMap<Net, Net> = HashMap::from([(FALSE, FALSE), (TRUE, TRUE)]);
+        let mut renumber = |nets: &[Net]| -> Vec<Net> {
+            nets.iter()
+                .map(|&net| {
+                    let next = numbers.len();
+                    *numbers.entry(roots[net]).or_insert(next)
+                })
+                .collect()
+        };
+        let interface = definition.interface();
+        let mut ports = |specs: &[PinSpec], nets: &[Vec<Net>]| -> Vec<Port> {
+            specs
+                .iter()
+                .zip(nets)
+                .map(|(spec, nets)| Port {
+                    name: spec.name.clone(),
+                    nets: renumber(nets),
+                })
+                .collect()
+        };
+        let inputs = ports(&interface.inputs, &instance.inputs);
+        let outputs = ports(&interface.outputs, &instance.outputs);
+        let internals = instance
+            .internals
+            .iter()
+            .map(|port| Port {
+                name: port.name.clone(),
+                nets: renumber(&port.nets),
+            })
+            .collect();
+        let gates = self
+            .gates
+            .into_iter()
+            .map(|gate| Gate {
+                inputs: gate.inputs.iter().map(|nets| renumber(nets)).collect(),
+                outputs: gate.outputs.iter().map(|nets| renumber(nets)).collect(),
+                ..gate
+            })
+            .collect();
+        Design {
+            name: name.to_string(),
+            inputs,
+            outputs,
+            internals,
+            gates,
+            nets: numbers.len(),
+            warnings: self.warnings,
+        }
+    }
+}
+
+// pin[a..b] の (a, 幅)。添字がなければピン全体
+fn sub_bus(file: &Path, pin: &PinRef, width: u16) -> Result<(usize, usize)> {
+    match pin.range {
+        None => Ok((0, width as usize)),
+        Some(range) if range.end < width => Ok((range.start as usize, range.width() as usize)),
+        Some(range) => Err(error_at(
+            file,
+            pin.span,
+            format!(
+                "Sub-bus {}{} is out of range for the {}-bit pin '{}'",
+                pin.name.name, range, width, pin.name.name
+            ),
+        )),
+    }
+}
+
+fn width_mismatch(
+    file: &Path,
+    pin: &PinRef,
+    pin_width: usize,
+    value: &PinRef,
+    value_width: usize,
+) -> anyhow::Error {
+    error_at(
+        file,
+        value.span,
+        format!(
+            "Width mismatch: '{}' has width {} but the part pin '{}' has width {}",
+            value, value_width, pin, pin_width
+        ),
+    )
+}
+
+fn error_at(file: &Path, span: Span, message: String) -> anyhow::Error {
+    Diagnostic::error(file, span, message).into()
+}
+
+// bits が立っている連続した範囲。ピン全体なら添字を付けないので空の文字列
+fn bit_ranges(bits: &[bool]) -> Vec<String> {
+    if bits.iter().all(|&bit| bit) {
+        return vec![String::new()];
+    }
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (index, &bit) in bits.iter().chain([&false]).enumerate() {
+        match (bit, start) {
+            (true, None) => start = Some(index),
+            (false, Some(first)) => {
+                ranges.push(
+                    BitRange {
+                        start: first as u16,
+                        end: index as u16 - 1,
+                    }
+                    .to_string(),
+                );
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use rstest::rstest;
+
+    const NOT: &str = "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }";
+    const AND: &str =
+        "CHIP And { IN a, b; OUT out; PARTS: Not(in=x, out=out); Nand(a=a, b=b, out=x); }";
+
+    // 最後のチップを展開する
+    fn design(sources: &[&str]) -> Result<Design> {
+        let mut library = Library::new(None);
+        let mut name = String::new();
+        for source in sources {
+            let chip = parse(source)?;
+            name = chip.name.name.clone();
+            library.insert(chip, PathBuf::from(format!("{}.hdl", name)))?;
+        }
+        elaborate(&mut library, &name)
+    }
+
+    #[test]
+    fn test_elaborate() {
+        let design = design(&[
+            NOT,
+            AND,
+            "CHIP Top {
+                IN a[2];
+                OUT out[2], b;
+                PARTS:
+                And(a=a[0], b=true, out=out[0], out=b);
+                Nand(a=a[1], out=out[1]);
+            }",
+        ])
+        .unwrap();
+        let paths: Vec<&str> = design.gates.iter().map(|gate| gate.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "Top/And[0]/Not[0]/Nand[0]",
+                "Top/And[0]/Nand[1]",
+                "Top/Nand[1]"
+            ]
+        );
+        let [not, nand, top_nand] = &design.gates[..] else {
+            panic!("{:?}", design.gates);
+        };
+        let (a, out, b) = (
+            &design.inputs[0].nets,
+            &design.outputs[0].nets,
+            &design.outputs[1].nets,
+        );
+        // Not の入力は両方とも And の内部ピン x
+        assert_eq!(
+            not.inputs,
+            [nand.outputs[0].clone(), nand.outputs[0].clone()]
+        );
+        assert_eq!(nand.inputs, [vec![a[0]], vec![TRUE]]);
+        assert_eq!(top_nand.inputs, [vec![a[1]], vec![FALSE]]);
+        assert_eq!(not.outputs[0], [out[0]]);
+        assert_eq!(b[..], out[..1]);
+        assert_eq!(top_nand.outputs[0], [out[1]]);
+        assert_eq!(design.nets, 7);
+    }
+
+    #[test]
+    fn test_warnings() {
+        let top = design(&[
+            NOT,
+            "CHIP Top {
+                IN a;
+                OUT out[2], z;
+                PARTS:
+                Nand(a=a, out=out[0]);
+                Not(in=a, out=x);
+                Not(in=x);
+            }",
+        ])
+        .unwrap();
+        let warnings: Vec<String> = top
+            .warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect();
+        // Not の中の警告はない。同じ場所の警告は 1 つ
+        assert_eq!(
+            warnings,
+            [
+                "Top.hdl: Line 5, column 17: warning: Input pin 'b' of 'Nand' is not connected and reads as false",
+                "Top.hdl: Line 3, column 21: warning: Output pin 'out[1]' of 'Top' is not driven by any part and reads as false",
+                "Top.hdl: Line 3, column 29: warning: Output pin 'z' of 'Top' is not driven by any part and reads as false",
+            ]
+        );
+
+        let error =
+            design(&["CHIP Top { IN a; OUT out; PARTS: Nand(a=a, out=out); Nand(a=a, out=out); }"])
+                .unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.notes.len(), 1);
+        assert_eq!(diagnostic.notes[0].0.start.column, 48);
+    }
+
+    #[rstest]
+    #[case(
+        "CHIP Top { IN a[2]; OUT out; PARTS: Nand(a=a, b=a[1], out=out); }",
+        "Top.hdl: Line 1, column 44: Width mismatch: 'a' has width 2 but the part pin 'a' has width 1"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, out=x); Nand(a=x[0], out=out); }",
+        "Top.hdl: Line 1, column 59: Internal pin 'x' cannot be subscripted"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, out=a); }",
+        "Top.hdl: Line 1, column 48: Output pin 'out' cannot drive input pin 'a' of 'Top'"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, out=out); Nand(a=out, out=x); }",
+        "Top.hdl: Line 1, column 61: Output pin 'out' of 'Top' cannot be used as an input of a part"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, out=out); Nand(a=a, out=out); }",
+        "Top.hdl: Line 1, column 68: 'out' is driven by more than one part"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=true, out=true); }",
+        "Top.hdl: Line 1, column 51: Output pin 'out' cannot be connected to true"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, b=x, out=out); }",
+        "Top.hdl: Line 1, column 46: Internal pin 'x' is not driven by any part"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Xor3(a=a, out=out); }",
+        "Top.hdl: Line 1, column 34: Chip 'Xor3' not found"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(in=a, out=out); }",
+        "Top.hdl: Line 1, column 39: Chip 'Nand' has no pin 'in'"
+    )]
+    #[case(
+        "CHIP Top { IN a[2]; OUT out; PARTS: Nand(a=a[2], out=out); }",
+        "Top.hdl: Line 1, column 44: Sub-bus a[2] is out of range for the 2-bit pin 'a'"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Nand(a=a, a=true, out=out); }",
+        "Top.hdl: Line 1, column 44: Pin 'a' of 'Nand' is connected more than once"
+    )]
+    #[case(
+        "CHIP Top { IN a; OUT out; PARTS: Top(a=a, out=out); }",
+        "Top.hdl: Line 1, column 34: Chip 'Top' contains itself"
+    )]
+    fn test_elaborate_error(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(design(&[source]).unwrap_err().to_string(), expected);
+    }
+}
@@ -0,0 +1,557 @@
+// HDL のフォーマッター
+//
+//   - インデントは 4 つの空白。IN、OUT、PARTS: と部品はチップの中に 1 段下げる
+//   - 続けて並んだ同じチップの部品は、同じピンの接続が縦にそろうように空白で埋める
+//   - 幅を超える部品は接続の区切りで折り返し、続きの行は ( の次の桁にそろえる
+//   - 空行は 1 行にまとめて残す
+//
+// コメントは消さない。文の前の行にあるものはその文の前に、同じ行の後ろにあるものは文の後ろに置く。
+// 公式の CPU.hdl のようにピンや接続の間にコメントがある文は、1 行に 1 つずつ並べてコメントをそろえる
+//
+//     IN  inM[16],         // M value input
+//         instruction[16], // Instruction for execution
+//         reset;           // Signals whether to re-start the current
+//                          // program (reset==1) or continue executing
+
+use anyhow::Result;
+
+use crate::{
+    ast::{Chip, ChipBody, Part, PinDecl, Span},
+    parser::parse_lossless,
+    tokenizer::{Comment, Token, TokenKind},
+};
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    // CHIP Name {
+    Header,
+    Inputs,
+    Outputs,
+    Parts,
+    // PARTS の中の順番
+    Part(usize),
+    Builtin,
+    Clocked,
+    Close,
+}
+
+struct Statement<'a> {
+    kind: Kind,
+    span: Span,
+    leading: Vec<&'a Comment>,
+    // 文の途中と後ろのコメント。後ろのコメントの次の行から同じ桁で続くものも含む
+    trailing: Vec<&'a Comment>,
+}
+
+impl Statement<'_> {
+    // 後ろのコメントも含めた最後の行
+    fn end_line(&self) -> usize {
+        self.trailing
+            .iter()
+            .map(|comment| comment.span.end.line)
+            .fold(self.span.end.line, usize::max)
+    }
+
+    // ピンや接続の間にコメントがあれば 1 行に 1 つずつ並べる
+    fn is_list(&self) -> bool {
+        matches!(self.kind, Kind::Inputs | Kind::Outputs | Kind::Part(_))
+            && self
+                .trailing
+                .iter()
+                .any(|comment| comment.span.start < self.span.end)
+    }
+}
+
+pub fn format(source: &str, width: usize) -> Result<String> {
+    let file = parse_lossless(source)?;
+    let mut statements = statements(&file.tokens);
+    // } より後ろの行のコメント
+    let mut after = Vec::new();
+    for comment in &file.comments {
+        let start = comment.span.start;
+        let Some(next) = statements
+            .iter()
+            .position(|statement| statement.span.end > start)
+        else {
+            let close = statements.last_mut().unwrap();
+            if close.span.end.line == start.line {
+                close.trailing.push(comment);
+            } else {
+                after.push(comment);
+            }
+            continue;
+        };
+        if statements[next].span.start < start {
+            statements[next].trailing.push(comment);
+            continue;
+        }
+        if next > 0 {
+            let previous = &mut statements[next - 1];
+            let continues = previous.trailing.last().is_some_and(|last| {
+                previous.end_line() + 1 == start.line && last.span.start.column == start.column
+            });
+            if previous.span.end.line == start.line || continues {
+                previous.trailing.push(comment);
+                continue;
+            }
+        }
+        statements[next].leading.push(comment);
+    }
+
+    let parts: &[Part] = match &file.chip.body {
+        ChipBody::Parts(parts) => parts,
+        ChipBody::Builtin { .. } => &[],
+    };
+    let part_lines = part_lines(parts, &statements, width);
+
+    let mut text = String::new();
+    let mut last_line: Option<usize> = None;
+    for statement in &statements {
+        let indent = match statement.kind {
+            Kind::Header | Kind::Close => "",
+            _ => INDENT,
+        };
+        for comment in &statement.leading {
+            blank_line(&mut text, last_line, comment.span.start.line);
+            text.push_str(&format!("{}{}\n", indent, comment.text));
+            last_line = Some(comment.span.end.line);
+        }
+        // } の直前の空行は残さない
+        if statement.kind != Kind::Close || !statement.leading.is_empty() {
+            blank_line(&mut text, last_line, statement.span.start.line);
+        }
+        for line in render(statement, &file.chip, &part_lines) {
+            text.push_str(indent);
+            text.push_str(&line);
+            text.push('\n');
+        }
+        last_line = Some(statement.end_line());
+    }
+    for comment in after {
+        blank_line(&mut text, last_line, comment.span.start.line);
+        text.push_str(&comment.text);
+        text.push('\n');
+        last_line = Some(comment.span.end.line);
+    }
+    Ok(text)
+}
+
+// 前に書いたものとの間が空いていれば空行を 1 行入れる
+fn blank_line(text: &mut String, last_line: Option<usize>, line: usize) {
+    if last_line.is_some_and(|last| line > last + 1) {
+        text.push('\n');
+    }
+}
+
+// 構文木と同じ順に並んだ文の範囲。構文解析が通ったトークン列なので形はわかっている
+fn statements(tokens: &[Token]) -> Vec<Statement<'_>> {
+    let mut statements = Vec::new();
+    let mut parts = 0;
+    let mut start = 0;
+    while start < tokens.len() {
+        let word = match &tokens[start].kind {
+            TokenKind::Identifier(name) => name.as_str(),
+            _ => "",
+        };
+        let semicolon = || {
+            start
+                + tokens[start..]
+                    .iter()
+                    .position(|token| token.kind == TokenKind::Symbol(';'))
+                    .unwrap()
+        };
+        let (kind, end) = match word {
+            "CHIP" => (Kind::Header, start + 2),
+            "" => (Kind::Close, start),
+            "PARTS" => (Kind::Parts, start + 1),
+            "IN" => (Kind::Inputs, semicolon()),
+            "OUT" => (Kind::Outputs, semicolon()),
+            "BUILTIN" => (Kind::Builtin, semicolon()),
+            "CLOCKED" => (Kind::Clocked, semicolon()),
+            _ => {
+                parts += 1;
+                (Kind::Part(parts - 1), semicolon())
+            }
+        };
+        statements.push(Statement {
+            kind,
+            span: tokens[start].span.to(tokens[end].span),
+            leading: Vec::new(),
+            trailing: Vec::new(),
+        });
+        start = end + 1;
+    }
+    statements
+}
+
+// 文の行（インデントなし）。コメントも付ける
+fn render(statement: &Statement, chip: &Chip, part_lines: &[Vec<String>]) -> Vec<String> {
+    let pins = |keyword: &str, pins: &[PinDecl]| -> Vec<String> {
+        let names: Vec<String> = pins.iter().map(pin_decl).collect();
+        if !statement.is_list() {
+            return vec![format!("{} {};", keyword, names.join(", "))];
+        }
+        // IN と OUT の後ろのピンの桁と、コメントの桁をそろえる
+        let spans: Vec<Span> = pins.iter().map(|pin| pin.span).collect();
+        let column = chip
+            .inputs
+            .iter()
+            .chain(&chip.outputs)
+            .map(|pin| pin_decl(pin).len())
+            .max()
+            .unwrap()
+            + 6;
+        list(
+            &format!("{:4}", keyword),
+            &names,
+            ";",
+            &spans,
+            statement,
+            column,
+        )
+    };
+    let mut lines = match statement.kind {
+        Kind::Header => vec![format!("CHIP {} {{", chip.name.name)],
+        Kind::Inputs => pins("IN", &chip.inputs),
+        Kind::Outputs => pins("OUT", &chip.outputs),
+        Kind::Parts => vec!["PARTS:".to_string()],
+        Kind::Part(index) if statement.is_list() => {
+            let ChipBody::Parts(parts) = &chip.body else {
+                unreachable!()
+            };
+            let part = &parts[index];
+            let spans: Vec<Span> = part
+                .connections
+                .iter()
+                .map(|connection| connection.pin.span.to(connection.value.span()))
+                .collect();
+            let prefix = format!("{}(", part.chip.name);
+            list(&prefix, &connections(part), ");", &spans, statement, 0)
+        }
+        Kind::Part(index) => part_lines[index].clone(),
+        Kind::Builtin | Kind::Clocked => {
+            let ChipBody::Builtin { name, clocked } = &chip.body else {
+                unreachable!()
+            };
+            if statement.kind == Kind::Builtin {
+                vec![format!("BUILTIN {};", name.name)]
+            } else {
+                let names: Vec<&str> = clocked.iter().map(|name| name.name.as_str()).collect();
+                vec![format!("CLOCKED {};", names.join(", "))]
+            }
+        }
+        Kind::Close => vec!["}".to_string()],
+    };
+    if !statement.is_list() {
+        let column = lines.last().unwrap().len() + 1;
+        append_comments(&mut lines, &statement.trailing, column);
+    }
+    lines
+}
+
+// 1 行に 1 つずつ並べた項目。項目の後ろから次の項目までのコメントをその項目の行に付ける。
+// コメントは column 桁目より左には書かない
+fn list(
+    prefix: &str,
+    items: &[String],
+    end: &str,
+    spans: &[Span],
+    statement: &Statement,
+    column: usize,
+) -> Vec<String> {
+    let continuation = " ".repeat(prefix.len());
+    let lines: Vec<String> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let start = if index == 0 { prefix } else { &continuation };
+            let separator = if index + 1 < items.len() { "," } else { end };
+            format!("{}{}{}", start, item, separator)
+        })
+        .collect();
+    // 最初の項目より前のコメントも最初の項目に付ける
+    let comments: Vec<Vec<&Comment>> = (0..items.len())
+        .map(|index| {
+            statement
+                .trailing
+                .iter()
+                .filter(|comment| {
+                    let start = comment.span.start;
+                    (index == 0 || start >= spans[index].end)
+                        && spans.get(index + 1).is_none_or(|next| start < next.start)
+                })
+                .copied()
+                .collect()
+        })
+        .collect();
+    let column = lines
+        .iter()
+        .zip(&comments)
+        .filter(|(_, comments)| !comments.is_empty())
+        .map(|(line, _)| line.len() + 1)
+        .fold(column, usize::max);
+
+    let mut result = Vec::new();
+    for (line, comments) in lines.into_iter().zip(comments) {
+        result.push(line);
+        append_comments(&mut result, &comments, column);
+    }
+    if items.is_empty() {
+        result.push(format!("{}{}", prefix, end));
+        append_comments(
+            &mut result,
+            &statement.trailing,
+            prefix.len() + end.len() + 1,
+        );
+    }
+    result
+}
+
+// 最後の行の column 桁目（0 始まり）からコメントを書く。元で別の行にあったコメントは次の行の同じ桁に
+fn append_comments(lines: &mut Vec<String>, comments: &[&Comment], column: usize) {
+    let mut previous: Option<usize> = None;
+    for comment in comments {
+        if previous.is_some_and(|line| comment.span.start.line > line) {
+            lines.push(String::new());
+        }
+        let last = lines.last_mut().unwrap();
+        if last.len() < column {
+            last.push_str(&" ".repeat(column - last.len()));
+        } else {
+            last.push(' ');
+        }
+        last.push_str(&comment.text);
+        previous = Some(comment.span.end.line);
+    }
+}
+
+fn pin_decl(pin: &PinDecl) -> String {
+    match pin.width {
+        1 => pin.name.name.clone(),
+        width => format!("{}[{}]", pin.name.name, width),
+    }
+}
+
+fn connections(part: &Part) -> Vec<String> {
+    part.connections
+        .iter()
+        .map(|connection| format!("{}={}", connection.pin, connection.value))
+        .collect()
+}
+
+// 部品ごとの行（インデントなし）
+fn part_lines(parts: &[Part], statements: &[Statement], width: usize) -> Vec<Vec<String>> {
+    let connections: Vec<Vec<String>> = parts.iter().map(connections).collect();
+    let pins = |index: usize| -> Vec<String> {
+        parts[index]
+            .connections
+            .iter()
+            .map(|connection| connection.pin.to_string())
+            .collect()
+    };
+
+    // 空行やコメントをはさまずに続く、同じチップで同じピンを並べた部品の組
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (position, statement) in statements.iter().enumerate() {
+        let Kind::Part(index) = statement.kind else {
+            continue;
+        };
+        let previous = &statements[position - 1];
+        let joins = previous.kind == Kind::Part(index.wrapping_sub(1))
+            && statement.leading.is_empty()
+            && !statement.is_list()
+            && !previous.is_list()
+            && statement.span.start.line <= previous.end_line() + 1
+            && parts[index - 1].chip.name == parts[index].chip.name
+            && pins(index - 1) == pins(index);
+        match groups.last_mut() {
+            Some(group) if joins => group.push(index),
+            _ => groups.push(vec![index]),
+        }
+    }
+
+    let mut lines = vec![Vec::new(); parts.len()];
+    for group in groups {
+        let count = connections[group[0]].len();
+        let widths: Vec<usize> = (0..count)
+            .map(|column| {
+                group
+                    .iter()
+                    .map(|&index| connections[index][column].len())
+                    .max()
+                    .unwrap()
+            })
+            .collect();
+        let aligned: Vec<String> = group
+            .iter()
+            .map(|&index| {
+                let mut line = format!("{}(", parts[index].chip.name);
+                for (column, connection) in connections[index].iter().enumerate() {
+                    if column + 1 < count {
+                        line.push_str(&format!(
+                            "{:<1$} ",
+                            format!("{},", connection),
+                            widths[column] + 1
+                        ));
+                    } else {
+                        line.push_str(connection);
+                    }
+                }
+                line + ");"
+            })
+            .collect();
+        let fits = |line: &String| INDENT.len() + line.len() <= width;
+        if group.len() > 1 && aligned.iter().all(fits) {
+            for (&index, line) in group.iter().zip(aligned) {
+                lines[index] = vec![line];
+            }
+            continue;
+        }
+        for &index in &group {
+            lines[index] = wrap(&parts[index].chip.name, &connections[index], width);
+        }
+    }
+    lines
+}
+
+// Chip(a=x, b=y, out=z); が幅を超えたら接続の区切りで折り返す
+fn wrap(chip: &str, connections: &[String], width: usize) -> Vec<String> {
+    let prefix = format!("{}(", chip);
+    if connections.is_empty() {
+        return vec![format!("{});", prefix)];
+    }
+    let continuation = " ".repeat(prefix.len());
+    let mut lines = Vec::new();
+    let mut line = prefix.clone();
+    for (index, connection) in connections.iter().enumerate() {
+        let suffix = if index + 1 < connections.len() {
+            ","
+        } else {
+            ");"
+        };
+        let piece = format!("{}{}", connection, suffix);
+        let empty = line == prefix || line == continuation;
+        if !empty && INDENT.len() + line.len() + 1 + piece.len() > width {
+            lines.push(line);
+            line = continuation.clone();
+        } else if !empty {
+            line.push(' ');
+        }
+        line.push_str(&piece);
+    }
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "\
+// Mux
+/** Multiplexor */
+CHIP Mux {   IN a,b,
+  sel; // select
+OUT out;
+PARTS:
+    // Put your code here:
+Not(in=sel,out=nsel);
+And(a=a,b=nsel,out=x);  And(a=b, b=sel, out=y); /* y */
+
+
+        Or(a=x,b=y,out=out);
+}
+// end
+";
+        let expected = "\
+// Mux
+/** Multiplexor */
+CHIP Mux {
+    IN a, b, sel; // select
+    OUT out;
+    PARTS:
+    // Put your code here:
+    Not(in=sel, out=nsel);
+    And(a=a, b=nsel, out=x);
+    And(a=b, b=sel,  out=y); /* y */
+
+    Or(a=x, b=y, out=out);
+}
+// end
+";
+        assert_eq!(format(source, DEFAULT_WIDTH).unwrap(), expected);
+        assert_eq!(format(expected, DEFAULT_WIDTH).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_inner_comments() {
+        // 公式の CPU.hdl の形
+        let source = "\
+CHIP CPU {
+    IN inM[16],   // M value input
+        instruction[16], // Instruction for execution
+        reset; // Signals whether to re-start the current
+               // program (reset==1) or continue executing
+    OUT outM[16], // M value output
+        writeM; // Write to M?
+    PARTS:
+    ARegister(in=ain, // from the mux
+        load=loada, out=areg);
+}
+";
+        let expected = "\
+CHIP CPU {
+    IN  inM[16],         // M value input
+        instruction[16], // Instruction for execution
+        reset;           // Signals whether to re-start the current
+                         // program (reset==1) or continue executing
+    OUT outM[16],        // M value output
+        writeM;          // Write to M?
+    PARTS:
+    ARegister(in=ain, // from the mux
+              load=loada,
+              out=areg);
+}
+";
+        assert_eq!(format(source, DEFAULT_WIDTH).unwrap(), expected);
+        assert_eq!(format(expected, DEFAULT_WIDTH).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let source = "CHIP Add16 { IN a[16], b[16]; OUT out[16]; PARTS:
+            FullAdder(a=a[1], b=b[1], c=carry0, sum=out[1], carry=carry1);
+            Not16(in=true, out=x); }";
+        assert_eq!(
+            format(source, 40).unwrap(),
+            "\
+CHIP Add16 {
+    IN a[16], b[16];
+    OUT out[16];
+    PARTS:
+    FullAdder(a=a[1], b=b[1], c=carry0,
+              sum=out[1], carry=carry1);
+    Not16(in=true, out=x);
+}
+"
+        );
+
+        // 一覧にしない文の途中のコメントは後ろに置く
+        assert_eq!(
+            format("CHIP /* bit */ Bit { BUILTIN Bit; }", DEFAULT_WIDTH).unwrap(),
+            "CHIP Bit { /* bit */\n    BUILTIN Bit;\n}\n"
+        );
+
+        let builtin = "CHIP Bit { IN in, load; OUT out;\n\n BUILTIN Bit;   CLOCKED in, load; }";
+        assert_eq!(
+            format(builtin, DEFAULT_WIDTH).unwrap(),
+            "CHIP Bit {\n    IN in, load;\n    OUT out;\n\n    BUILTIN Bit;\n    CLOCKED in, load;\n}\n"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// どのチップがどのチップを部品に使っているかのグラフ。Graphviz の DOT で書き出す
+//
+// 展開はせず構文だけを見るので、エラーのあるチップがあっても全体の構造が分かる。
+// ディレクトリに .hdl のないチップは組み込みチップ（点線）か、見つからないチップ（赤）になる
+
+use anyhow::{Result, bail};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Chip, ChipBody},
+    builtin, hdl_files, parse_file,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Hdl,
+    // PARTS: の中が空の .hdl（公式の課題のひな形）
+    Empty,
+    // BUILTIN の .hdl か、.hdl がなくて組み込みチップを使うもの
+    Builtin,
+    Missing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    // 部品として使っている数
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    // 名前順
+    pub nodes: Vec<(String, Kind)>,
+    // (from, to) の名前順
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    pub fn new(chips: &[Chip]) -> Self {
+        let mut nodes: BTreeMap<String, Kind> = BTreeMap::new();
+        let mut edges: BTreeMap<(String, String), usize> = BTreeMap::new();
+        for chip in chips {
+            let kind = match &chip.body {
+                ChipBody::Parts(parts) if parts.is_empty() => Kind::Empty,
+                ChipBody::Parts(_) => Kind::Hdl,
+                ChipBody::Builtin { .. } => Kind::Builtin,
+            };
+            nodes.insert(chip.name.name.clone(), kind);
+        }
+        for chip in chips {
+            let ChipBody::Parts(parts) = &chip.body else {
+                continue;
+            };
+            for part in parts {
+                let name = &part.chip.name;
+                if !nodes.contains_key(name) {
+                    let kind = if builtin::create(name).is_some() {
+                        Kind::Builtin
+                    } else {
+                        Kind::Missing
+                    };
+                    nodes.insert(name.clone(), kind);
+                }
+                *edges
+                    .entry((chip.name.name.clone(), name.clone()))
+                    .or_default() += 1;
+            }
+        }
+        Graph {
+            nodes: nodes.into_iter().collect(),
+            edges: edges
+                .into_iter()
+                .map(|((from, to), count)| Edge { from, to, count })
+                .collect(),
+        }
+    }
+
+    // 見つからないチップと、それを使っているチップ
+    pub fn missing(&self) -> Vec<(&str, Vec<&str>)> {
+        self.nodes
+            .iter()
+            .filter(|(_, kind)| *kind == Kind::Missing)
+            .map(|(name, _)| {
+                let users = self
+                    .edges
+                    .iter()
+                    .filter(|edge| edge.to == *name)
+                    .map(|edge| edge.from.as_str())
+                    .collect();
+                (name.as_str(), users)
+            })
+            .collect()
+    }
+
+    pub fn format(&self) -> String {
+        let mut text = String::from("digraph chips {\n    node [shape=box];\n");
+        for (name, kind) in &self.nodes {
+            let attributes = match kind {
+                Kind::Hdl => "",
+                Kind::Empty => " [style=filled, fillcolor=lightgray]",
+                Kind::Builtin => " [style=dashed]",
+                Kind::Missing => " [color=red, fontcolor=red]",
+            };
+            text.push_str(&format!("    {}{};\n", name, attributes));
+        }
+        for edge in &self.edges {
+            text.push_str(&format!("    {} -> {}", edge.from, edge.to));
+            if edge.count > 1 {
+                text.push_str(&format!(" [label={}]", edge.count));
+            }
+            text.push_str(";\n");
+        }
+        text.push_str("}\n");
+        text
+    }
+}
+
+// ディレクトリなら中の .hdl 全部、ファイルならそれと同じディレクトリから使っているチップをたどる
+pub fn load(path: &Path) -> Result<Vec<Chip>> {
+    let mut chips: Vec<(Chip, PathBuf)> = Vec::new();
+    let mut queue: VecDeque<PathBuf> = VecDeque::new();
+    let dir = if path.is_dir() {
+        queue.extend(hdl_files(path)?);
+        None
+    } else {
+        queue.push_back(path.to_path_buf());
+        path.parent()
+    };
+    while let Some(file) = queue.pop_front() {
+        let chip = parse_file(&file)?;
+        if let Some((_, other)) = chips
+            .iter()
+            .find(|(other, _)| other.name.name == chip.name.name)
+        {
+            bail!(
+                "Chip '{}' is defined in both '{}' and '{}'",
+                chip.name.name,
+                other.display(),
+                file.display()
+            );
+        }
+        if let (Some(dir), ChipBody::Parts(parts)) = (dir, &chip.body) {
+            for part in parts {
+                let file = dir.join(format!("{}.hdl", part.chip.name));
+                if file.is_file()
+                    && !queue.contains(&file)
+                    && !chips.iter().any(|(_, path)| *path == file)
+                {
+                    queue.push_back(file);
+                }
+            }
+        }
+        chips.push((chip, file));
+    }
+    Ok(chips.into_iter().map(|(chip, _)| chip).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_graph() {
+        let chips: Vec<Chip> = [
+            "CHIP Xor { IN a, b; OUT out;
+                PARTS:
+                Nand(a=a, b=b, out=n);
+                Nand(a=a, b=n, out=x);
+                Nand(a=n, b=b, out=y);
+                Nand(a=x, b=y, out=out);
+            }",
+            "CHIP Adder { IN a, b; OUT sum, carry;
+                PARTS:
+                Xor(a=a, b=b, out=sum);
+                HalfAnd(a=a, b=b, out=carry);
+            }",
+            "CHIP Or { IN a, b; OUT out; PARTS: }",
+        ]
+        .iter()
+        .map(|source| parse(source).unwrap())
+        .collect();
+        let graph = Graph::new(&chips);
+        assert_eq!(
+            graph.format(),
+            [
+                "digraph chips {",
+                "    node [shape=box];",
+                "    Adder;",
+                "    HalfAnd [color=red, fontcolor=red];",
+                "    Nand [style=dashed];",
+                "    Or [style=filled, fillcolor=lightgray];",
+                "    Xor;",
+                "    Adder -> HalfAnd;",
+                "    Adder -> Xor;",
+                "    Xor -> Nand [label=4];",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(graph.missing(), vec![("HalfAnd", vec!["Adder"])]);
+    }
+}
@@ -0,0 +1,48 @@
+pub mod ast;
+pub mod builtin;
+pub mod diagnostic;
+pub mod elaborate;
+pub mod format;
+pub mod graph;
+pub mod netlist;
+pub mod parser;
+pub mod simulate;
+pub mod tokenizer;
+pub mod truth_table;
+pub mod tst;
+pub mod vcd;
+pub mod verify;
+pub mod verilog;
+
+use anyhow::{Context, Result, bail};
+use nand2tetris_core::cli::{Fail, Failure};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// .hdl を読んで構文木にする。エラーにはファイル名を付ける
+pub fn parse_file(path: &Path) -> Result<ast::Chip> {
+    let source =
+        fs::read_to_string(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    parser::parse(&source)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+        .fail(Failure::Parse)
+}
+
+// ファイルならそれだけ、ディレクトリなら中の .hdl を名前順に
+pub fn hdl_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .with_context(|| format!("Failed to read directory '{}'", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "hdl"))
+        .collect();
+    if files.is_empty() {
+        bail!("No .hdl files found in '{}'", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
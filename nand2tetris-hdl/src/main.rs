@@ -0,0 +1,548 @@
+use anyhow::{Context, Result, anyhow, bail};
+
+use clap::{Args, Parser, Subcommand};
+use nand2tetris_core::{
+    cli::{ColorChoice, Fail, Failure, failure, finish, is_quiet, set_color, set_quiet},
+    diagnostic::{Severity, bare, emit},
+    sarif::{self, set_sarif},
+    status,
+    test_report::{ReportArgs, TestCase},
+    timeout::{TimeoutArgs, is_timeout},
+    warning,
+};
+use nand2tetris_hdl::{
+    diagnostic::Diagnostic,
+    elaborate::elaborate_file,
+    format::{DEFAULT_WIDTH, format},
+    graph::{self, Graph},
+    hdl_files,
+    netlist::{self, Netlist},
+    simulate::Simulator,
+    truth_table::{self, DEFAULT_MAX_BITS, truth_table},
+    tst::{self, Comparison},
+    vcd::{Dump, Recorder},
+    verify::{self, Mode, verify},
+    verilog::{DEFAULT_ROM_FILE, verilog},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::Instant,
+};
+
+#[derive(Parser)]
+#[command(about = "Nand2Tetris Hardware Simulator")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Print nothing but errors and the output asked for
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// When to color errors and warnings
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: ColorChoice,
+    /// Also write the errors and warnings of `check` with their locations as SARIF to this file,
+    /// for GitHub code scanning and other tools that annotate the source
+    #[arg(long, value_name = "FILE", global = true)]
+    sarif: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Parse and elaborate .hdl files (or every .hdl file in a directory) and report errors
+    Check(CheckArgs),
+    /// Evaluate a chip with the given input values and print its outputs
+    Eval(EvalArgs),
+    /// Format .hdl files (or every .hdl file in a directory) in place
+    Fmt(FmtArgs),
+    /// Print which chips use which as a Graphviz DOT graph, for a directory or a chip
+    Graph(GraphArgs),
+    /// Flatten a chip to Nand and DFF gates and print the netlist or the gate counts per chip
+    Netlist(NetlistArgs),
+    /// Run a .tst test script, or every .tst script in a directory
+    Test(TestArgs),
+    /// Print the truth table of a combinational chip in the .cmp format
+    TruthTable(TruthTableArgs),
+    /// Compare a chip with another implementation (by default the built-in chip) on the same inputs
+    Verify(VerifyArgs),
+    /// Translate a chip to synthesizable Verilog
+    Verilog(VerilogArgs),
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct EvalArgs {
+    input: PathBuf,
+    /// Input values as NAME=VALUE (decimal; negative values are two's complement)
+    values: Vec<String>,
+    /// Run this many clock cycles (tick and tock) with the inputs before printing
+    #[arg(long, default_value_t = 0)]
+    cycles: u64,
+    #[command(flatten)]
+    waveform: WaveformArgs,
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Only report the files that are not formatted, and fail if there are any
+    #[arg(long)]
+    check: bool,
+    /// Wrap parts that are longer than this many characters
+    #[arg(long, default_value_t = DEFAULT_WIDTH)]
+    width: usize,
+}
+
+#[derive(Args)]
+struct GraphArgs {
+    input: PathBuf,
+    /// Write the graph to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct NetlistArgs {
+    input: PathBuf,
+    #[arg(long, value_enum, default_value = "counts")]
+    format: netlist::Format,
+    /// Write to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TestArgs {
+    script: PathBuf,
+    /// Run the scripts of a directory on this many threads (default: the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    waveform: WaveformArgs,
+    #[command(flatten)]
+    report: ReportArgs,
+    #[command(flatten)]
+    timeout: TimeoutArgs,
+}
+
+#[derive(Args)]
+struct WaveformArgs {
+    /// Write the waveforms of the chip's pins to this VCD file
+    #[arg(long, value_name = "FILE")]
+    vcd: Option<PathBuf>,
+    /// Also record the chip's internal pins in the VCD file
+    #[arg(long, requires = "vcd")]
+    internals: bool,
+}
+
+impl WaveformArgs {
+    fn dump(&self) -> Option<Dump> {
+        self.vcd.as_ref().map(|path| Dump {
+            path: path.clone(),
+            internals: self.internals,
+        })
+    }
+}
+
+#[derive(Args)]
+struct TruthTableArgs {
+    input: PathBuf,
+    /// Write the table to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Compare the table with the built-in chip of the same name
+    #[arg(long)]
+    compare_builtin: bool,
+    /// Refuse chips with more input bits than this
+    #[arg(long, default_value_t = DEFAULT_MAX_BITS)]
+    max_bits: u32,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    input: PathBuf,
+    /// builtin:NAME for a built-in chip, or another .hdl file (default: the built-in chip of the same name)
+    #[arg(long, value_name = "CHIP")]
+    against: Option<String>,
+    /// Try this many random inputs (clock cycles for clocked chips)
+    #[arg(long, conflicts_with = "exhaustive")]
+    samples: Option<u64>,
+    /// Try every combination of inputs (the default for combinational chips with up to 16 input bits)
+    #[arg(long)]
+    exhaustive: bool,
+    /// Seed for the random inputs
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Args)]
+struct VerilogArgs {
+    input: PathBuf,
+    /// Write the Verilog to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// The .hack program that ROM32K loads with $readmemb
+    #[arg(long, default_value = DEFAULT_ROM_FILE)]
+    rom: String,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    set_quiet(cli.quiet);
+    set_color(cli.color);
+    set_sarif(env!("CARGO_PKG_NAME"), cli.sarif.clone());
+    finish(match cli.command {
+        Command::Check(args) => check(&args),
+        Command::Eval(args) => eval(&args),
+        Command::Fmt(args) => fmt(&args),
+        Command::Graph(args) => graph(&args),
+        Command::Netlist(args) => netlist(&args),
+        Command::Test(args) => test(&args),
+        Command::TruthTable(args) => print_truth_table(&args),
+        Command::Verify(args) => check_equivalence(&args),
+        Command::Verilog(args) => print_verilog(&args),
+    })
+}
+
+fn check(args: &CheckArgs) -> Result<()> {
+    let (mut checked, mut failed) = (0, 0);
+    // 構文エラーのあるファイルがあれば Parse、なければ最初のエラーの種類
+    let mut failures = Vec::new();
+    for input in &args.inputs {
+        for file in hdl_files(input)? {
+            checked += 1;
+            let design = match elaborate_file(&file) {
+                Ok(design) => design,
+                Err(e) => {
+                    failures.extend(failure(&e));
+                    let diagnostic = e.downcast_ref::<Diagnostic>();
+                    if let Some(diagnostic) = diagnostic {
+                        sarif::record(diagnostic.finding());
+                    }
+                    emit(
+                        Severity::Error,
+                        &match diagnostic {
+                            Some(diagnostic) => render(diagnostic),
+                            None => bare(Severity::Error, &e.to_string()),
+                        },
+                    );
+                    failed += 1;
+                    continue;
+                }
+            };
+            for warning in &design.warnings {
+                sarif::record(warning.finding());
+                if !is_quiet() {
+                    emit(Severity::Warning, &render(warning));
+                }
+            }
+            status!(
+                "{}: CHIP {} ({} in, {} out, {} gates)",
+                file.display(),
+                design.name,
+                design.inputs.len(),
+                design.outputs.len(),
+                design.gates.len()
+            );
+        }
+    }
+    if failed > 0 {
+        let failure = match failures.contains(&Failure::Parse) {
+            true => Failure::Parse,
+            false => failures.first().copied().unwrap_or(Failure::Semantic),
+        };
+        return Err(failure.wrap(anyhow!("{} of {} files have errors", failed, checked)));
+    }
+    Ok(())
+}
+
+// ソースが読めなければ 1 行の表示だけ
+fn render(diagnostic: &Diagnostic) -> String {
+    match fs::read_to_string(&diagnostic.file) {
+        Ok(source) => diagnostic.render(&source),
+        Err(_) => diagnostic.to_string(),
+    }
+}
+
+fn eval(args: &EvalArgs) -> Result<()> {
+    let mut simulator = Simulator::new(elaborate_file(&args.input)?)?;
+    for assignment in &args.values {
+        let (name, value) = assignment
+            .split_once('=')
+            .with_context(|| format!("Expected NAME=VALUE but found '{}'", assignment))?;
+        let value: i32 = value
+            .parse()
+            .ok()
+            .filter(|value| (-32768..=65535).contains(value))
+            .with_context(|| format!("Invalid value '{}' for '{}'", value, name))?;
+        simulator.set(name, value as u16)?;
+    }
+    simulator.eval();
+    // 時刻は半クロック単位（tst と同じ）
+    let dump = args.waveform.dump();
+    let mut recorder = dump
+        .as_ref()
+        .map(|dump| Recorder::new(&simulator, dump.internals));
+    if let Some(recorder) = &mut recorder {
+        recorder.sample(0, &simulator);
+    }
+    for cycle in 0..args.cycles {
+        simulator.tick();
+        if let Some(recorder) = &mut recorder {
+            recorder.sample(cycle * 2 + 1, &simulator);
+        }
+        simulator.tock();
+        if let Some(recorder) = &mut recorder {
+            recorder.sample(cycle * 2 + 2, &simulator);
+        }
+    }
+    if let (Some(dump), Some(recorder)) = (&dump, &recorder) {
+        recorder.write(&dump.path)?;
+    }
+    // 16 ビットのバスは公式の %D と同じく符号付き
+    for port in &simulator.design().outputs {
+        let value = simulator.get(&port.name).unwrap();
+        if port.nets.len() == 16 {
+            println!("{}={}", port.name, value as i16);
+        } else {
+            println!("{}={}", port.name, value);
+        }
+    }
+    Ok(())
+}
+
+fn fmt(args: &FmtArgs) -> Result<()> {
+    let mut unformatted = Vec::new();
+    for input in &args.inputs {
+        for file in hdl_files(input)? {
+            let source = fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read '{}'", file.display()))?;
+            let formatted = format(&source, args.width)
+                .map_err(|e| anyhow!("{}: {}", file.display(), e))
+                .fail(Failure::Parse)?;
+            if formatted == source {
+                continue;
+            }
+            if args.check {
+                println!("{}", file.display());
+                unformatted.push(file);
+            } else {
+                fs::write(&file, formatted)
+                    .with_context(|| format!("Failed to write '{}'", file.display()))?;
+            }
+        }
+    }
+    if !unformatted.is_empty() {
+        bail!("{} files are not formatted", unformatted.len());
+    }
+    Ok(())
+}
+
+fn graph(args: &GraphArgs) -> Result<()> {
+    let graph = Graph::new(&graph::load(&args.input)?);
+    let text = graph.format();
+    match &args.output {
+        Some(output) => fs::write(output, text)
+            .with_context(|| format!("Failed to write '{}'", output.display()))?,
+        None => print!("{}", text),
+    }
+    // グラフは出してから、見つからないチップを知らせる
+    for (name, users) in graph.missing() {
+        warning!("Missing chip '{}' (used by {})", name, users.join(", "));
+    }
+    Ok(())
+}
+
+fn netlist(args: &NetlistArgs) -> Result<()> {
+    let netlist = Netlist::new(&elaborate_file(&args.input)?);
+    let text = netlist.format(args.format)?;
+    match &args.output {
+        Some(output) => fs::write(output, text)
+            .with_context(|| format!("Failed to write '{}'", output.display()))?,
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn test(args: &TestArgs) -> Result<()> {
+    let dump = args.waveform.dump();
+    let budget = args.timeout.budget();
+    if !args.script.is_dir() {
+        let start = Instant::now();
+        let result = budget.run(|| tst::run_script(&args.script, dump.as_ref()));
+        args.report.write(&[TestCase {
+            suite: file_name(args.script.parent().unwrap_or(Path::new(""))),
+            name: file_name(&args.script),
+            duration: start.elapsed(),
+            failure: result.as_ref().err().map(|e| e.to_string()),
+        }])?;
+        match result? {
+            Comparison::Passed => status!("End of script - Comparison ended successfully"),
+            Comparison::Skipped | Comparison::Failed(_) => status!("End of script"),
+        }
+        return Ok(());
+    }
+
+    // ディレクトリなら全部のスクリプトを実行してから失敗をまとめる
+    if dump.is_some() {
+        bail!("--vcd needs a single script, not a directory");
+    }
+    let scripts = tst::tst_files(&args.script)?;
+    let jobs = args.jobs.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|jobs| jobs.get())
+            .unwrap_or(1)
+    });
+    let mut failures = Vec::new();
+    // どれかのチップやスクリプトが読めなければその種類、すべて比較の失敗なら Test
+    let mut kind = Failure::Test;
+    let results = tst::run_scripts(&scripts, jobs, &budget);
+    let cases: Vec<TestCase> = scripts
+        .iter()
+        .zip(&results)
+        .map(|(script, (result, duration))| TestCase {
+            suite: file_name(&args.script),
+            name: script
+                .strip_prefix(&args.script)
+                .unwrap_or(script)
+                .display()
+                .to_string(),
+            duration: *duration,
+            failure: result.as_ref().err().map(|e| e.to_string()),
+        })
+        .collect();
+    args.report.write(&cases)?;
+    for (script, (result, _)) in scripts.iter().zip(results) {
+        match result {
+            Ok(_) => status!("PASS {}", script.display()),
+            // 時間切れのエラーにはスクリプトの名前がない
+            Err(e) if is_timeout(&e) => {
+                status!("TIMEOUT {}", script.display());
+                failures.push(format!("{}: {}", script.display(), e));
+            }
+            Err(e) => {
+                status!("FAIL {}", script.display());
+                if kind == Failure::Test {
+                    kind = failure(&e).unwrap_or(Failure::Test);
+                }
+                failures.push(e.to_string());
+            }
+        }
+    }
+    if !failures.is_empty() {
+        return Err(kind.wrap(anyhow!(
+            "{} of {} scripts failed\n{}",
+            failures.len(),
+            scripts.len(),
+            failures.join("\n")
+        )));
+    }
+    Ok(())
+}
+
+// JUnit の testsuite の名前
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// 違う行が多くても最初のいくつかだけ表示する
+const MAX_DIFFERENCES: usize = 10;
+
+fn print_truth_table(args: &TruthTableArgs) -> Result<()> {
+    let mut simulator = Simulator::new(elaborate_file(&args.input)?)?;
+    let table = truth_table(&mut simulator, args.max_bits)?;
+    let text = table.format()?;
+    match &args.output {
+        Some(output) => fs::write(output, text)
+            .with_context(|| format!("Failed to write '{}'", output.display()))?,
+        None => print!("{}", text),
+    }
+    if !args.compare_builtin {
+        return Ok(());
+    }
+
+    let reference = truth_table(&mut truth_table::builtin(&table.chip)?, args.max_bits)?;
+    let differences = table.differences(&reference)?;
+    if !differences.is_empty() {
+        let mut lines: Vec<&str> = differences
+            .iter()
+            .take(MAX_DIFFERENCES)
+            .map(String::as_str)
+            .collect();
+        if differences.len() > MAX_DIFFERENCES {
+            lines.push("...");
+        }
+        return Err(Failure::Test.wrap(anyhow!(
+            "{} of {} rows differ from the built-in '{}'\n{}",
+            differences.len(),
+            table.rows.len(),
+            reference.chip,
+            lines.join("\n")
+        )));
+    }
+    // 表は標準出力に出すので、こちらは標準エラー
+    if !is_quiet() {
+        eprintln!(
+            "All {} rows match the built-in '{}'",
+            table.rows.len(),
+            reference.chip
+        );
+    }
+    Ok(())
+}
+
+fn check_equivalence(args: &VerifyArgs) -> Result<()> {
+    let mut chip = Simulator::new(elaborate_file(&args.input)?)?;
+    let against = match &args.against {
+        Some(against) => against.clone(),
+        None => format!("builtin:{}", chip.design().name),
+    };
+    let mut reference = verify::reference(&against)?;
+    let mode = match (args.exhaustive, args.samples) {
+        (true, _) => Mode::Exhaustive,
+        (false, Some(samples)) => Mode::Random {
+            samples,
+            seed: args.seed,
+        },
+        (false, None) => verify::default_mode(&chip, args.seed),
+    };
+    let verification = verify(&mut chip, &mut reference, mode)?;
+    if let Some(divergence) = &verification.divergence {
+        return Err(Failure::Test.wrap(anyhow!(
+            "'{}' differs from {} after {} matching samples\n{}",
+            chip.design().name,
+            against,
+            verification.checked,
+            divergence.report()
+        )));
+    }
+    let what = match mode {
+        Mode::Exhaustive => format!("all {} inputs", verification.checked),
+        Mode::Random { .. } if chip.is_clocked() || reference.is_clocked() => {
+            format!("{} random clock cycles", verification.checked)
+        }
+        Mode::Random { .. } => format!("{} random inputs", verification.checked),
+    };
+    status!("'{}' matches {} on {}", chip.design().name, against, what);
+    Ok(())
+}
+
+fn print_verilog(args: &VerilogArgs) -> Result<()> {
+    let text = verilog(&elaborate_file(&args.input)?, &args.rom)?;
+    match &args.output {
+        Some(output) => fs::write(output, text)
+            .with_context(|| format!("Failed to write '{}'", output.display()))?,
+        None => print!("{}", text),
+    }
+    Ok(())
+}
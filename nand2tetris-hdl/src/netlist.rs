@@ -0,0 +1,255 @@
+// 展開したチップのゲートレベルのネットリスト。部品は Nand と DFF（と .hdl の見つからなかった
+// 組み込みチップ）で、ネットは 1 ビットごとの番号。0 は false、1 は true につながっている
+//
+// JSON と、EDIF に似た S 式のテキストに書き出せる。チップごとのゲート数も数える
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    builtin::{self, Pin},
+    elaborate::{Design, Net, Port},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    // チップごとのゲート数の表
+    Counts,
+    Json,
+    Edif,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Connection {
+    pub pin: String,
+    // 下位ビットから
+    pub nets: Vec<Net>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Cell {
+    pub kind: String,
+    pub path: String,
+    pub inputs: Vec<Connection>,
+    pub outputs: Vec<Connection>,
+}
+
+// chip 1 つの中にある部品の数。部品の種類ごと
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GateCount {
+    pub chip: String,
+    pub instances: usize,
+    pub gates: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Netlist {
+    pub chip: String,
+    pub nets: usize,
+    pub inputs: Vec<Connection>,
+    pub outputs: Vec<Connection>,
+    pub cells: Vec<Cell>,
+    // 一番外のチップが先頭。あとは最初に現れた順
+    pub counts: Vec<GateCount>,
+}
+
+impl Netlist {
+    pub fn new(design: &Design) -> Self {
+        let ports = |ports: &[Port]| -> Vec<Connection> {
+            ports
+                .iter()
+                .map(|port| Connection {
+                    pin: port.name.clone(),
+                    nets: port.nets.clone(),
+                })
+                .collect()
+        };
+        let connections = |pins: &[Pin], nets: &[Vec<Net>]| -> Vec<Connection> {
+            pins.iter()
+                .zip(nets)
+                .map(|(pin, nets)| Connection {
+                    pin: pin.name.to_string(),
+                    nets: nets.clone(),
+                })
+                .collect()
+        };
+        let cells = design
+            .gates
+            .iter()
+            .map(|gate| {
+                // 展開できた部品は必ず組み込みチップ
+                let chip = builtin::create(&gate.chip).unwrap();
+                Cell {
+                    kind: gate.chip.clone(),
+                    path: gate.path.clone(),
+                    inputs: connections(chip.inputs(), &gate.inputs),
+                    outputs: connections(chip.outputs(), &gate.outputs),
+                }
+            })
+            .collect();
+        Netlist {
+            chip: design.name.clone(),
+            nets: design.nets,
+            inputs: ports(&design.inputs),
+            outputs: ports(&design.outputs),
+            cells,
+            counts: gate_counts(design),
+        }
+    }
+
+    pub fn format(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Counts => self.format_counts(),
+            Format::Json => serde_json::to_string_pretty(self)? + "\n",
+            Format::Edif => self.format_edif(),
+        })
+    }
+
+    // Xor: 4 Nand
+    // Bit: 1 DFF, 4 Nand (2 instances)
+    fn format_counts(&self) -> String {
+        let mut text = String::new();
+        for count in &self.counts {
+            let gates: Vec<String> = count
+                .gates
+                .iter()
+                .map(|(kind, count)| format!("{} {}", count, kind))
+                .collect();
+            text.push_str(&format!("{}: {}", count.chip, gates.join(", ")));
+            if count.instances > 1 {
+                text.push_str(&format!(" ({} instances)", count.instances));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn format_edif(&self) -> String {
+        let nets = |nets: &[Net]| -> String {
+            nets.iter()
+                .map(Net::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut text = format!("(netlist {}\n  (nets {})\n", self.chip, self.nets);
+        for (direction, ports) in [("input", &self.inputs), ("output", &self.outputs)] {
+            for port in ports {
+                text.push_str(&format!(
+                    "  (port {} (direction {}) (nets {}))\n",
+                    port.pin,
+                    direction,
+                    nets(&port.nets)
+                ));
+            }
+        }
+        for cell in &self.cells {
+            text.push_str(&format!("  (instance {} (cell {})", cell.path, cell.kind));
+            for pin in cell.inputs.iter().chain(&cell.outputs) {
+                text.push_str(&format!(" (pin {} (nets {}))", pin.pin, nets(&pin.nets)));
+            }
+            text.push_str(")\n");
+        }
+        text.push_str(")\n");
+        text
+    }
+}
+
+// 部品の位置 Top/Xor[2]/Nand[0] の前の方はそれを含むチップの実体。実体ごとに数えて、
+// 同じチップの実体はどれも同じ数なので最初のものを使う
+fn gate_counts(design: &Design) -> Vec<GateCount> {
+    let mut instances: Vec<(&str, &str, BTreeMap<String, usize>)> = Vec::new();
+    for gate in &design.gates {
+        let segments: Vec<&str> = gate.path.split('/').collect();
+        let mut end = 0;
+        for segment in &segments[..segments.len() - 1] {
+            end += segment.len();
+            let instance = &gate.path[..end];
+            end += 1;
+            let chip = segment.split('[').next().unwrap();
+            let index = match instances.iter().position(|(path, ..)| *path == instance) {
+                Some(index) => index,
+                None => {
+                    instances.push((instance, chip, BTreeMap::new()));
+                    instances.len() - 1
+                }
+            };
+            *instances[index].2.entry(gate.chip.clone()).or_default() += 1;
+        }
+    }
+
+    let mut counts: Vec<GateCount> = Vec::new();
+    for (_, chip, gates) in instances {
+        match counts.iter_mut().find(|count| count.chip == chip) {
+            Some(count) => count.instances += 1,
+            None => counts.push(GateCount {
+                chip: chip.to_string(),
+                instances: 1,
+                gates,
+            }),
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elaborate::{Library, elaborate},
+        parser::parse,
+    };
+    use std::path::PathBuf;
+
+    fn design(sources: &[&str]) -> Design {
+        let mut library = Library::new(None);
+        let mut name = String::new();
+        for source in sources {
+            let chip = parse(source).unwrap();
+            name = chip.name.name.clone();
+            library
+                .insert(chip, PathBuf::from(format!("{}.hdl", name)))
+                .unwrap();
+        }
+        elaborate(&mut library, &name).unwrap()
+    }
+
+    const NOT: &str = "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }";
+
+    #[test]
+    fn test_netlist() {
+        let netlist = Netlist::new(&design(&[
+            NOT,
+            "CHIP Bit2 {
+                IN in[2];
+                OUT out[2];
+                PARTS:
+                Not(in=in[0], out=n0);
+                Not(in=in[1], out=n1);
+                DFF(in=n0, out=out[0]);
+                DFF(in=n1, out=out[1]);
+            }",
+        ]));
+        assert_eq!(
+            netlist.format(Format::Counts).unwrap(),
+            "Bit2: 2 DFF, 2 Nand\nNot: 1 Nand (2 instances)\n"
+        );
+        assert_eq!(netlist.cells.len(), 4);
+        assert_eq!(netlist.cells[0].path, "Bit2/Not[0]/Nand[0]");
+
+        let edif = netlist.format(Format::Edif).unwrap();
+        let lines: Vec<&str> = edif.lines().collect();
+        assert_eq!(lines[0], "(netlist Bit2");
+        assert_eq!(lines[2], "  (port in (direction input) (nets 2 3))");
+        assert_eq!(
+            lines[4],
+            "  (instance Bit2/Not[0]/Nand[0] (cell Nand) (pin a (nets 2)) (pin b (nets 2)) (pin out (nets 6)))"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&netlist.format(Format::Json).unwrap()).unwrap();
+        assert_eq!(json["cells"][2]["kind"], "DFF");
+        assert_eq!(json["counts"][1]["gates"]["Nand"], 1);
+    }
+}
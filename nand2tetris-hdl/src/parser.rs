@@ -0,0 +1,381 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    ast::*,
+    tokenizer::{Comment, Token, TokenKind, tokenize_lossless},
+};
+
+// 公式のシミュレーターと同じくバスは 16 ビットまで
+pub const MAX_WIDTH: u16 = 16;
+
+pub fn parse(source: &str) -> Result<Chip> {
+    parse_lossless(source).map(|file| file.chip)
+}
+
+// 構文木に加えて、トークンとコメントも残した結果（フォーマッター用）
+pub struct SourceFile {
+    pub chip: Chip,
+    pub tokens: Vec<Token>,
+    pub comments: Vec<Comment>,
+}
+
+pub fn parse_lossless(source: &str) -> Result<SourceFile> {
+    let (tokens, comments) = tokenize_lossless(source)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let chip = parser.chip()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("end of file"));
+    }
+    Ok(SourceFile {
+        chip,
+        tokens: parser.tokens,
+        comments,
+    })
+}
+
+// 再帰下降。最初のエラーで止める
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn span(&self) -> Span {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
+
+    // 直前に読んだトークン
+    fn last_span(&self) -> Span {
+        self.tokens[self.pos - 1].span
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        let position = self.span().start;
+        match self.tokens.get(self.pos) {
+            Some(token) => anyhow::anyhow!(
+                "{}: Expected {} but found '{}'",
+                position,
+                expected,
+                token.text()
+            ),
+            None => anyhow::anyhow!(
+                "{}: Expected {} but reached the end of the file",
+                position,
+                expected
+            ),
+        }
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(TokenKind::Identifier(name)) if name == word)
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.is_word(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<()> {
+        if !self.eat_symbol(symbol) {
+            return Err(self.error(&format!("'{}'", symbol)));
+        }
+        Ok(())
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<()> {
+        if !self.eat_word(word) {
+            return Err(self.error(&format!("'{}'", word)));
+        }
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Result<Identifier> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let identifier = Identifier {
+                    name: name.clone(),
+                    span: self.span(),
+                };
+                self.pos += 1;
+                Ok(identifier)
+            }
+            _ => Err(self.error("a name")),
+        }
+    }
+
+    fn number(&mut self) -> Result<u16> {
+        match self.peek() {
+            Some(TokenKind::Number(value)) => {
+                let value = *value;
+                self.pos += 1;
+                Ok(value)
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+
+    // 'CHIP' name '{' ('IN' pins ';')? ('OUT' pins ';')? body '}'
+    fn chip(&mut self) -> Result<Chip> {
+        self.expect_word("CHIP")?;
+        let name = self.identifier()?;
+        self.expect_symbol('{')?;
+        let inputs = if self.eat_word("IN") {
+            self.pin_decls()?
+        } else {
+            Vec::new()
+        };
+        let outputs = if self.eat_word("OUT") {
+            self.pin_decls()?
+        } else {
+            Vec::new()
+        };
+        let body = if self.eat_word("BUILTIN") {
+            let name = self.identifier()?;
+            self.expect_symbol(';')?;
+            let mut clocked = Vec::new();
+            if self.eat_word("CLOCKED") {
+                clocked = self.names()?;
+            }
+            ChipBody::Builtin { name, clocked }
+        } else {
+            self.expect_word("PARTS")?;
+            self.expect_symbol(':')?;
+            let mut parts = Vec::new();
+            while self.peek().is_some() && !self.is_symbol('}') {
+                parts.push(self.part()?);
+            }
+            ChipBody::Parts(parts)
+        };
+        self.expect_symbol('}')?;
+        Ok(Chip {
+            name,
+            inputs,
+            outputs,
+            body,
+        })
+    }
+
+    // a, b[16], sel[3];
+    fn pin_decls(&mut self) -> Result<Vec<PinDecl>> {
+        let mut pins = Vec::new();
+        loop {
+            let name = self.identifier()?;
+            let mut width = 1;
+            if self.eat_symbol('[') {
+                let position = self.span().start;
+                width = self.number()?;
+                if !(1..=MAX_WIDTH).contains(&width) {
+                    bail!(
+                        "{}: Bus width must be between 1 and {}",
+                        position,
+                        MAX_WIDTH
+                    );
+                }
+                self.expect_symbol(']')?;
+            }
+            let span = name.span.to(self.last_span());
+            pins.push(PinDecl { name, width, span });
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+        self.expect_symbol(';')?;
+        Ok(pins)
+    }
+
+    // in, load;
+    fn names(&mut self) -> Result<Vec<Identifier>> {
+        let mut names = vec![self.identifier()?];
+        while self.eat_symbol(',') {
+            names.push(self.identifier()?);
+        }
+        self.expect_symbol(';')?;
+        Ok(names)
+    }
+
+    // Chip '(' (connection (',' connection)*)? ')' ';'
+    fn part(&mut self) -> Result<Part> {
+        let chip = self.identifier()?;
+        self.expect_symbol('(')?;
+        let mut connections = Vec::new();
+        if !self.is_symbol(')') {
+            loop {
+                let pin = self.pin_ref()?;
+                self.expect_symbol('=')?;
+                let value = if self.is_word("true") || self.is_word("false") {
+                    let value = self.is_word("true");
+                    self.pos += 1;
+                    Value::Constant(value, self.last_span())
+                } else {
+                    Value::Pin(self.pin_ref()?)
+                };
+                connections.push(Connection { pin, value });
+                if !self.eat_symbol(',') {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(')')?;
+        self.expect_symbol(';')?;
+        let span = chip.span.to(self.last_span());
+        Ok(Part {
+            chip,
+            connections,
+            span,
+        })
+    }
+
+    // name ('[' n ']' | '[' n '..' m ']')?
+    fn pin_ref(&mut self) -> Result<PinRef> {
+        let name = self.identifier()?;
+        let mut range = None;
+        if self.eat_symbol('[') {
+            let position = self.span().start;
+            let start = self.number()?;
+            let end = if self.peek() == Some(&TokenKind::Range) {
+                self.pos += 1;
+                self.number()?
+            } else {
+                start
+            };
+            if start > end || end >= MAX_WIDTH {
+                bail!(
+                    "{}: Invalid sub-bus {}[{}..{}]",
+                    position,
+                    name.name,
+                    start,
+                    end
+                );
+            }
+            self.expect_symbol(']')?;
+            range = Some(BitRange { start, end });
+        }
+        let span = name.span.to(self.last_span());
+        Ok(PinRef { name, range, span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span {
+            start: Position {
+                line,
+                column: start,
+            },
+            end: Position { line, column: end },
+        }
+    }
+
+    #[test]
+    fn test_parse_chip() {
+        let chip = parse(
+            "// Mux16\n\
+             CHIP Mux16 {\n\
+             IN a[16], b[16], sel;\n\
+             OUT out[16];\n\
+             PARTS:\n\
+             Mux(a=a[0], b=b[0], sel=sel, out=out[0]);\n\
+             Or8Way(in=a[8..15], out=x);\n\
+             Foo(a=true, b[0..1]=false);\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!(chip.name.name, "Mux16");
+        assert_eq!(chip.inputs.len(), 3);
+        assert_eq!(chip.inputs[1].width, 16);
+        assert_eq!(chip.inputs[1].span, span(3, 11, 16));
+        assert_eq!(chip.inputs[2].width, 1);
+        assert_eq!(chip.outputs[0].name.name, "out");
+
+        let ChipBody::Parts(parts) = &chip.body else {
+            panic!("{:?}", chip.body);
+        };
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].chip.name, "Mux");
+        assert_eq!(parts[0].span, span(6, 1, 42));
+        let connection = &parts[0].connections[3];
+        assert_eq!(connection.pin.name.name, "out");
+        assert_eq!(connection.value.to_string(), "out[0]");
+
+        let connection = &parts[1].connections[0];
+        let Value::Pin(pin) = &connection.value else {
+            panic!("{:?}", connection.value);
+        };
+        assert_eq!(pin.range, Some(BitRange { start: 8, end: 15 }));
+        assert_eq!(pin.span, span(7, 11, 19));
+
+        let connection = &parts[2].connections[1];
+        assert_eq!(connection.pin.range, Some(BitRange { start: 0, end: 1 }));
+        assert_eq!(connection.value, Value::Constant(false, span(8, 21, 26)));
+    }
+
+    #[test]
+    fn test_parse_builtin() {
+        let chip = parse(
+            "CHIP Register { IN in[16], load; OUT out[16]; BUILTIN Register; CLOCKED in, load; }",
+        )
+        .unwrap();
+        let ChipBody::Builtin { name, clocked } = &chip.body else {
+            panic!("{:?}", chip.body);
+        };
+        assert_eq!(name.name, "Register");
+        let clocked: Vec<&str> = clocked.iter().map(|name| name.name.as_str()).collect();
+        assert_eq!(clocked, ["in", "load"]);
+    }
+
+    #[rstest]
+    #[case(
+        "CHIP And { IN a, b; OUT out; PARTS: Nand(a=a, b=b out=x); }",
+        "Line 1, column 51: Expected ')' but found 'out'"
+    )]
+    #[case(
+        "CHIP And { IN a b; }",
+        "Line 1, column 17: Expected ';' but found 'b'"
+    )]
+    #[case(
+        "CHIP A { IN a[17]; PARTS: }",
+        "Line 1, column 15: Bus width must be between 1 and 16"
+    )]
+    #[case(
+        "CHIP A { PARTS: B(a=x[7..3]); }",
+        "Line 1, column 23: Invalid sub-bus x[7..3]"
+    )]
+    #[case(
+        "CHIP A { OUT x; IN a; PARTS: }",
+        "Line 1, column 17: Expected 'PARTS' but found 'IN'"
+    )]
+    #[case(
+        "CHIP A { PARTS: B(a=x);",
+        "Line 1, column 23: Expected '}' but reached the end of the file"
+    )]
+    fn test_parse_error(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse(source).unwrap_err().to_string(), expected);
+    }
+}
@@ -0,0 +1,429 @@
+// 展開したチップのシミュレーション。組み込みチップの部品を入力から出力の順に並べた
+// 命令の列にしておき、eval で 1 回ずつ実行すればすべてのネットが落ち着く
+//
+// 部品のほとんどは Nand なので、Nand はネットの番号だけを持つ命令にしてその場で計算する。
+// ほかの組み込みチップはバスの値を組み立てて呼び出す
+//
+// クロック付きの入力（DFF の in など）は順番の依存に数えないので、
+// それを通らないループだけが組み合わせ回路のループになる
+//
+// tick（立ち上がり）と tock（立ち下がり）は公式のシミュレーターと同じく、その時点の入力で
+// 回路を落ち着かせてからクロック付きの部品に渡し、もう一度 eval する
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    builtin::{self, Builtin, mask},
+    elaborate::{Design, FALSE, Gate, Net, Port, TRUE},
+};
+
+enum Op {
+    Nand { a: Net, b: Net, out: Net },
+    // design.gates と chips の番号
+    Chip(usize),
+}
+
+pub struct Simulator {
+    design: Design,
+    chips: Vec<Box<dyn Builtin>>,
+    // eval で実行する命令
+    ops: Vec<Op>,
+    // tick と tock を渡す部品
+    clocked: Vec<usize>,
+    values: Vec<bool>,
+    // 組み込みチップに渡す値の置き場。eval のたびに確保しない
+    inputs: Vec<u16>,
+    outputs: Vec<u16>,
+}
+
+impl Simulator {
+    pub fn new(design: Design) -> Result<Self> {
+        let chips = design
+            .gates
+            .iter()
+            .map(|gate| {
+                builtin::create(&gate.chip)
+                    .ok_or_else(|| anyhow!("Built-in chip '{}' is not available", gate.chip))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let ops = evaluation_order(&design.gates, &chips, design.nets)?
+            .into_iter()
+            .map(|index| {
+                let gate = &design.gates[index];
+                match gate.chip.as_str() {
+                    "Nand" => Op::Nand {
+                        a: gate.inputs[0][0],
+                        b: gate.inputs[1][0],
+                        out: gate.outputs[0][0],
+                    },
+                    _ => Op::Chip(index),
+                }
+            })
+            .collect();
+        let clocked = (0..chips.len())
+            .filter(|&index| !chips[index].clocked().is_empty())
+            .collect();
+        let mut values = vec![false; design.nets];
+        values[TRUE] = true;
+        values[FALSE] = false;
+        Ok(Simulator {
+            design,
+            chips,
+            ops,
+            clocked,
+            values,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        })
+    }
+
+    pub fn design(&self) -> &Design {
+        &self.design
+    }
+
+    // tick と tock で状態の変わる部品があるか
+    pub fn is_clocked(&self) -> bool {
+        !self.clocked.is_empty()
+    }
+
+    // 一番外のチップの入力、出力、内部ピン
+    pub fn port(&self, name: &str) -> Option<&Port> {
+        let design = &self.design;
+        design
+            .inputs
+            .iter()
+            .chain(&design.outputs)
+            .chain(&design.internals)
+            .find(|port| port.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.port(name).map(|port| read(&self.values, &port.nets))
+    }
+
+    // 入力ピンに値を置く。出力に反映するのは eval のとき
+    pub fn set(&mut self, name: &str, value: u16) -> Result<()> {
+        let Some(port) = self.design.inputs.iter().find(|port| port.name == name) else {
+            bail!("'{}' is not an input pin of '{}'", name, self.design.name);
+        };
+        write(&mut self.values, &port.nets, value);
+        Ok(())
+    }
+
+    // ARegister や RAM16K のような組み込みチップの中身。同じ名前の部品が複数あれば最初のもの
+    pub fn memory(&self, part: &str) -> Option<&[u16]> {
+        self.chips.iter().find_map(|chip| chip.memory(part))
+    }
+
+    pub fn memory_mut(&mut self, part: &str) -> Option<&mut [u16]> {
+        self.chips.iter_mut().find_map(|chip| chip.memory_mut(part))
+    }
+
+    pub fn eval(&mut self) {
+        let values = &mut self.values;
+        for op in &self.ops {
+            match *op {
+                // && だと分岐が入って遅くなる
+                Op::Nand { a, b, out } => values[out] = !(values[a] & values[b]),
+                Op::Chip(index) => {
+                    let gate = &self.design.gates[index];
+                    self.inputs.clear();
+                    self.inputs
+                        .extend(gate.inputs.iter().map(|nets| read(values, nets)));
+                    self.outputs.clear();
+                    self.outputs.resize(gate.outputs.len(), 0);
+                    self.chips[index].eval(&self.inputs, &mut self.outputs);
+                    for (nets, &value) in gate.outputs.iter().zip(&self.outputs) {
+                        write(values, nets, value);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.clock(|chip, inputs| chip.tick(inputs));
+    }
+
+    pub fn tock(&mut self) {
+        self.clock(|chip, inputs| chip.tock(inputs));
+    }
+
+    fn clock(&mut self, edge: impl Fn(&mut dyn Builtin, &[u16])) {
+        self.eval();
+        for &index in &self.clocked {
+            let gate = &self.design.gates[index];
+            self.inputs.clear();
+            self.inputs
+                .extend(gate.inputs.iter().map(|nets| read(&self.values, nets)));
+            edge(self.chips[index].as_mut(), &self.inputs);
+        }
+        self.eval();
+    }
+}
+
+fn read(values: &[bool], nets: &[Net]) -> u16 {
+    nets.iter()
+        .enumerate()
+        .fold(0, |value, (bit, &net)| value | (values[net] as u16) << bit)
+}
+
+fn write(values: &mut [bool], nets: &[Net], value: u16) {
+    let value = value & mask(nets.len() as u16);
+    for (bit, &net) in nets.iter().enumerate() {
+        values[net] = value >> bit & 1 == 1;
+    }
+}
+
+// 部品のトポロジカル順。ループがあればそれを通る部品を並べてエラーにする
+fn evaluation_order(gates: &[Gate], chips: &[Box<dyn Builtin>], nets: usize) -> Result<Vec<usize>> {
+    let mut drivers: Vec<Option<usize>> = vec![None; nets];
+    for (index, gate) in gates.iter().enumerate() {
+        for &net in gate.outputs.iter().flatten() {
+            drivers[net] = Some(index);
+        }
+    }
+    // dependencies[i] は部品 i の入力を駆動している部品
+    let dependencies: Vec<Vec<usize>> = gates
+        .iter()
+        .zip(chips)
+        .map(|(gate, chip)| {
+            let mut dependencies: Vec<usize> = chip
+                .inputs()
+                .iter()
+                .zip(&gate.inputs)
+                .filter(|(pin, _)| !chip.clocked().contains(&pin.name))
+                .flat_map(|(_, nets)| nets.iter().filter_map(|&net| drivers[net]))
+                .collect();
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            dependencies
+        })
+        .collect();
+
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); gates.len()];
+    let mut waiting: Vec<usize> = vec![0; gates.len()];
+    for (index, dependencies) in dependencies.iter().enumerate() {
+        waiting[index] = dependencies.len();
+        for &dependency in dependencies {
+            dependents[dependency].push(index);
+        }
+    }
+    let mut order: Vec<usize> = (0..gates.len()).filter(|&i| waiting[i] == 0).collect();
+    let mut next = 0;
+    while next < order.len() {
+        for &dependent in &dependents[order[next]] {
+            waiting[dependent] -= 1;
+            if waiting[dependent] == 0 {
+                order.push(dependent);
+            }
+        }
+        next += 1;
+    }
+    if order.len() == gates.len() {
+        return Ok(order);
+    }
+
+    // 残った部品は必ずループの上か、その先にある。依存を遡ると同じ部品に戻る
+    let mut path = vec![(0..gates.len()).find(|&i| waiting[i] > 0).unwrap()];
+    loop {
+        let last = *path.last().unwrap();
+        let previous = dependencies[last]
+            .iter()
+            .copied()
+            .find(|&dependency| waiting[dependency] > 0)
+            .unwrap();
+        if let Some(start) = path.iter().position(|&index| index == previous) {
+            // 信号の流れる向きに、先に並んでいる部品から
+            let mut cycle: Vec<usize> = path[start..].iter().rev().copied().collect();
+            let first = (0..cycle.len()).min_by_key(|&i| cycle[i]).unwrap();
+            cycle.rotate_left(first);
+            cycle.push(cycle[0]);
+            let cycle: Vec<&str> = cycle
+                .iter()
+                .map(|&index| gates[index].path.as_str())
+                .collect();
+            bail!("Combinational loop: {}", cycle.join(" -> "));
+        }
+        path.push(previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elaborate::{Library, elaborate},
+        parser::parse,
+    };
+    use std::path::PathBuf;
+
+    fn simulator(sources: &[&str]) -> Result<Simulator> {
+        let mut library = Library::new(None);
+        let mut name = String::new();
+        for source in sources {
+            let chip = parse(source)?;
+            name = chip.name.name.clone();
+            library.insert(chip, PathBuf::from(format!("{}.hdl", name)))?;
+        }
+        Simulator::new(elaborate(&mut library, &name)?)
+    }
+
+    const NOT: &str = "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }";
+    const XOR: &str = "CHIP Xor {
+        IN a, b;
+        OUT out;
+        PARTS:
+        Nand(a=x, b=y, out=out);
+        Nand(a=a, b=nab, out=x);
+        Nand(a=nab, b=b, out=y);
+        Nand(a=a, b=b, out=nab);
+    }";
+
+    #[test]
+    fn test_eval() {
+        let mut xor = simulator(&[XOR]).unwrap();
+        let mut table = Vec::new();
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+            xor.set("a", a).unwrap();
+            xor.set("b", b).unwrap();
+            xor.eval();
+            table.push(xor.get("out").unwrap());
+        }
+        assert_eq!(table, [0, 1, 1, 0]);
+        assert_eq!(xor.get("nab"), Some(0));
+
+        // 4 ビットの Not。上位 2 ビットは下位 2 ビットを逆順にしたもの
+        let mut not4 = simulator(&[
+            NOT,
+            "CHIP Not4 {
+                IN in[4];
+                OUT out[4];
+                PARTS:
+                Not(in=in[0], out=out[0]);
+                Not(in=in[1], out=out[1]);
+                Not(in=in[1], out=out[2]);
+                Not(in=in[0], out=out[3]);
+            }",
+        ])
+        .unwrap();
+        not4.set("in", 0b1110).unwrap();
+        not4.eval();
+        assert_eq!(not4.get("out"), Some(0b1001));
+        assert_eq!(
+            not4.set("out", 1).unwrap_err().to_string(),
+            "'out' is not an input pin of 'Not4'"
+        );
+    }
+
+    // Nand だけで作った部品（Op::Nand）と組み込みチップ（Op::Chip）で同じ結果になる
+    #[test]
+    fn test_nand_and_builtin_agree() {
+        const AND: &str = "CHIP And {
+            IN a, b;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=b, out=x);
+            Nand(a=x, b=x, out=out);
+        }";
+        const OR: &str = "CHIP Or {
+            IN a, b;
+            OUT out;
+            PARTS:
+            Nand(a=a, b=a, out=na);
+            Nand(a=b, b=b, out=nb);
+            Nand(a=na, b=nb, out=out);
+        }";
+        const MUX: &str = "CHIP Mux {
+            IN a, b, sel;
+            OUT out;
+            PARTS:
+            Nand(a=sel, b=sel, out=nsel);
+            Nand(a=a, b=nsel, out=x);
+            Nand(a=b, b=sel, out=y);
+            Nand(a=x, b=y, out=out);
+        }";
+        const MIX: &str = "CHIP Mix {
+            IN a, b, c;
+            OUT sum, carry, mux;
+            PARTS:
+            Xor(a=a, b=b, out=ab);
+            Xor(a=ab, b=c, out=sum);
+            And(a=a, b=b, out=x);
+            And(a=ab, b=c, out=y);
+            Or(a=x, b=y, out=carry);
+            Mux(a=a, b=b, sel=c, out=mux);
+        }";
+        let mut gates = simulator(&[XOR, AND, OR, MUX, MIX]).unwrap();
+        let mut builtin = simulator(&[MIX]).unwrap();
+        assert!(gates.ops.iter().all(|op| matches!(op, Op::Nand { .. })));
+        assert!(builtin.ops.iter().all(|op| matches!(op, Op::Chip(_))));
+
+        for inputs in 0..8 {
+            let mut outputs = Vec::new();
+            for simulator in [&mut gates, &mut builtin] {
+                for (bit, pin) in ["a", "b", "c"].iter().enumerate() {
+                    simulator.set(pin, inputs >> bit & 1).unwrap();
+                }
+                simulator.eval();
+                outputs.push(["sum", "carry", "mux"].map(|pin| simulator.get(pin).unwrap()));
+            }
+            assert_eq!(outputs[0], outputs[1], "inputs {:03b}", inputs);
+        }
+    }
+
+    #[test]
+    fn test_tick_tock() {
+        let mut bit = simulator(&[
+            NOT,
+            "CHIP Mux {
+                IN a, b, sel;
+                OUT out;
+                PARTS:
+                Not(in=sel, out=nsel);
+                Nand(a=a, b=nsel, out=x);
+                Nand(a=b, b=sel, out=y);
+                Nand(a=x, b=y, out=out);
+            }",
+            "CHIP Bit {
+                IN in, load;
+                OUT out;
+                PARTS:
+                Mux(a=dff, b=in, sel=load, out=next);
+                DFF(in=next, out=dff, out=out);
+            }",
+        ])
+        .unwrap();
+        let mut outputs = Vec::new();
+        for (input, load) in [(1, 1), (0, 0), (0, 1), (1, 0)] {
+            bit.set("in", input).unwrap();
+            bit.set("load", load).unwrap();
+            bit.tick();
+            outputs.push(bit.get("out").unwrap());
+            bit.tock();
+            outputs.push(bit.get("out").unwrap());
+        }
+        // tick の後は前の値のまま
+        assert_eq!(outputs, [0, 1, 1, 1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_combinational_loop() {
+        let error = simulator(&[
+            NOT,
+            "CHIP Loop {
+                IN a;
+                OUT out;
+                PARTS:
+                Nand(a=a, b=y, out=x);
+                Not(in=x, out=y, out=out);
+            }",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Combinational loop: Loop/Nand[0] -> Loop/Not[1]/Nand[0] -> Loop/Nand[0]"
+        );
+    }
+}
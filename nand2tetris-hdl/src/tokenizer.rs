@@ -0,0 +1,236 @@
+use anyhow::{Result, bail};
+
+use crate::ast::{Position, Span};
+
+pub const SYMBOLS: &str = "{}()[],;=:";
+
+// CHIP や IN などのキーワードも Identifier で、パーサーが名前で見分ける
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    Number(u16),
+    Symbol(char),
+    // a[0..7] の ..
+    Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Identifier(name) => name.clone(),
+            TokenKind::Number(value) => value.to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::Range => "..".to_string(),
+        }
+    }
+}
+
+// // や /* */ のコメント。text は記号も含めた元の文字列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+struct Scanner<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    position: Position,
+    comments: Vec<Comment>,
+}
+
+impl Scanner<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    // 空白とコメントを読み飛ばす
+    fn skip_trivia(&mut self) -> Result<()> {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.next();
+                }
+                (Some('/'), Some('/')) => {
+                    let start = self.position;
+                    let mut text = String::new();
+                    while let Some(c) = self.peek().filter(|&c| c != '\n') {
+                        text.push(c);
+                        self.next();
+                    }
+                    self.comment(text.trim_end(), start);
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.position;
+                    let mut text = String::from("/*");
+                    self.next();
+                    self.next();
+                    loop {
+                        match self.next() {
+                            Some('*') if self.peek() == Some('/') => {
+                                self.next();
+                                text.push_str("*/");
+                                break;
+                            }
+                            Some(c) => text.push(c),
+                            None => bail!("{}: Unterminated comment", start),
+                        }
+                    }
+                    self.comment(&text, start);
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn comment(&mut self, text: &str, start: Position) {
+        self.comments.push(Comment {
+            text: text.to_string(),
+            span: Span {
+                start,
+                end: self.position,
+            },
+        });
+    }
+
+    fn take_while(&mut self, first: char, pred: impl Fn(char) -> bool) -> String {
+        let mut word = String::from(first);
+        while let Some(c) = self.peek().filter(|&c| pred(c)) {
+            word.push(c);
+            self.next();
+        }
+        word
+    }
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>> {
+    tokenize_lossless(source).map(|(tokens, _)| tokens)
+}
+
+// コメントも返す（フォーマッター用）
+pub fn tokenize_lossless(source: &str) -> Result<(Vec<Token>, Vec<Comment>)> {
+    let mut scanner = Scanner {
+        chars: source.chars().peekable(),
+        position: Position { line: 1, column: 1 },
+        comments: Vec::new(),
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        scanner.skip_trivia()?;
+        let start = scanner.position;
+        let Some(c) = scanner.next() else {
+            break;
+        };
+
+        let kind = if SYMBOLS.contains(c) {
+            TokenKind::Symbol(c)
+        } else if c == '.' && scanner.peek() == Some('.') {
+            scanner.next();
+            TokenKind::Range
+        } else if c.is_ascii_digit() {
+            let digits = scanner.take_while(c, |c| c.is_ascii_digit());
+            match digits.parse::<u16>() {
+                Ok(value) => TokenKind::Number(value),
+                Err(_) => bail!("{}: Number {} is too large", start, digits),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            TokenKind::Identifier(scanner.take_while(c, |c| c.is_ascii_alphanumeric() || c == '_'))
+        } else {
+            bail!("{}: Unexpected character '{}'", start, c);
+        };
+        tokens.push(Token {
+            kind,
+            span: Span {
+                start,
+                end: scanner.position,
+            },
+        });
+    }
+
+    Ok((tokens, scanner.comments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens =
+            tokenize("/** Not */\nCHIP Not {\n  IN a[16]; // in\n  PARTS: Nand(a=a[0..7]);\n}")
+                .unwrap();
+        let kinds: Vec<TokenKind> = tokens.iter().map(|token| token.kind.clone()).collect();
+        let identifier = |name: &str| TokenKind::Identifier(name.to_string());
+        assert_eq!(
+            kinds,
+            [
+                identifier("CHIP"),
+                identifier("Not"),
+                TokenKind::Symbol('{'),
+                identifier("IN"),
+                identifier("a"),
+                TokenKind::Symbol('['),
+                TokenKind::Number(16),
+                TokenKind::Symbol(']'),
+                TokenKind::Symbol(';'),
+                identifier("PARTS"),
+                TokenKind::Symbol(':'),
+                identifier("Nand"),
+                TokenKind::Symbol('('),
+                identifier("a"),
+                TokenKind::Symbol('='),
+                identifier("a"),
+                TokenKind::Symbol('['),
+                TokenKind::Number(0),
+                TokenKind::Range,
+                TokenKind::Number(7),
+                TokenKind::Symbol(']'),
+                TokenKind::Symbol(')'),
+                TokenKind::Symbol(';'),
+                TokenKind::Symbol('}'),
+            ]
+        );
+        assert_eq!(
+            tokens[4].span,
+            Span {
+                start: Position { line: 3, column: 6 },
+                end: Position { line: 3, column: 7 },
+            }
+        );
+        let (_, comments) = tokenize_lossless("/** Not\n */ CHIP Not { // in  \n}").unwrap();
+        let texts: Vec<&str> = comments.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["/** Not\n */", "// in"]);
+        assert_eq!(
+            comments[1].span.start,
+            Position {
+                line: 2,
+                column: 16
+            }
+        );
+        assert_eq!(
+            tokenize("CHIP A { IN a.b; }").unwrap_err().to_string(),
+            "Line 1, column 14: Unexpected character '.'"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// 組み合わせ回路のチップの真理値表。入力の組み合わせをすべて試す
+//
+// 行の順番は公式の .cmp と同じく、入力を並べた順に上位から数えた 2 進数の順。
+// 表は公式の .cmp と同じ書式（1 ビットは %B3.1.3、バスは %B1.16.1 など）で出力する
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    elaborate::{Library, Port, elaborate},
+    simulate::Simulator,
+    tst::{Cell, Column, Radix, Variable, format_header, format_row},
+};
+
+// 入力のビット数の上限の既定値（65536 行）
+pub const DEFAULT_MAX_BITS: u32 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruthTable {
+    pub chip: String,
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+    // 入力の値と出力の値
+    pub rows: Vec<(Vec<u16>, Vec<u16>)>,
+}
+
+pub fn truth_table(simulator: &mut Simulator, max_bits: u32) -> Result<TruthTable> {
+    let design = simulator.design();
+    if simulator.is_clocked() {
+        bail!(
+            "'{}' has clocked parts; a truth table needs a combinational chip",
+            design.name
+        );
+    }
+    let pins = |ports: &[Port]| -> Vec<(String, usize)> {
+        ports
+            .iter()
+            .map(|port| (port.name.clone(), port.nets.len()))
+            .collect()
+    };
+    let inputs = pins(&design.inputs);
+    let outputs = pins(&design.outputs);
+    let bits: usize = inputs.iter().map(|(_, width)| width).sum();
+    if bits > max_bits as usize {
+        bail!(
+            "'{}' has {} input bits; its truth table would have 2^{} rows (the limit is 2^{})",
+            design.name,
+            bits,
+            bits,
+            max_bits
+        );
+    }
+    let chip = design.name.clone();
+
+    let mut rows = Vec::with_capacity(1 << bits);
+    for combination in 0..1u64 << bits {
+        // 最後の入力が最下位
+        let mut shift = bits;
+        let values: Vec<u16> = inputs
+            .iter()
+            .map(|(_, width)| {
+                shift -= width;
+                (combination >> shift & ((1 << width) - 1)) as u16
+            })
+            .collect();
+        for ((name, _), &value) in inputs.iter().zip(&values) {
+            simulator.set(name, value)?;
+        }
+        simulator.eval();
+        let results = outputs
+            .iter()
+            .map(|(name, _)| simulator.get(name).unwrap())
+            .collect();
+        rows.push((values, results));
+    }
+    Ok(TruthTable {
+        chip,
+        inputs,
+        outputs,
+        rows,
+    })
+}
+
+// 組み込みチップだけの Library で name を展開する（比べる相手に使う）
+pub fn builtin(name: &str) -> Result<Simulator> {
+    let mut library = Library::new(None);
+    if library.get(name)?.is_none() {
+        bail!("There is no built-in chip '{}'", name);
+    }
+    Simulator::new(elaborate(&mut library, name)?)
+}
+
+impl TruthTable {
+    pub fn format(&self) -> Result<String> {
+        let columns: Vec<Column> = self
+            .inputs
+            .iter()
+            .chain(&self.outputs)
+            .map(|(name, width)| {
+                let pad = if *width == 1 { 3 } else { 1 };
+                Column {
+                    variable: Variable::Pin(name.clone()),
+                    radix: Radix::Binary,
+                    pad_left: pad,
+                    len: *width,
+                    pad_right: pad,
+                }
+            })
+            .collect();
+        let mut text = format_header(&columns);
+        text.push('\n');
+        for (inputs, outputs) in &self.rows {
+            let values: Vec<u16> = inputs.iter().chain(outputs).copied().collect();
+            let row = format_row(&columns, |variable| {
+                let index = columns
+                    .iter()
+                    .position(|column| &column.variable == variable)
+                    .unwrap();
+                Ok(Cell::Pin {
+                    value: values[index],
+                    width: columns[index].len,
+                })
+            })?;
+            text.push_str(&row);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    // reference と出力が違う行（a=1 b=0 sel=1: out=0, expected out=1 のように書く）
+    pub fn differences(&self, reference: &TruthTable) -> Result<Vec<String>> {
+        if self.inputs != reference.inputs || self.outputs != reference.outputs {
+            return Err(anyhow!(
+                "The pins of '{}' do not match the built-in chip '{}'",
+                self.chip,
+                reference.chip
+            ));
+        }
+        let assignment = |pins: &[(String, usize)], values: &[u16]| -> String {
+            pins.iter()
+                .zip(values)
+                .map(|((name, _), value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        Ok(self
+            .rows
+            .iter()
+            .zip(&reference.rows)
+            .filter(|((_, actual), (_, expected))| actual != expected)
+            .map(|((inputs, actual), (_, expected))| {
+                format!(
+                    "{}: {}, expected {}",
+                    assignment(&self.inputs, inputs),
+                    assignment(&self.outputs, actual),
+                    assignment(&self.outputs, expected)
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use std::path::PathBuf;
+
+    fn simulator(source: &str) -> Simulator {
+        let chip = parse(source).unwrap();
+        let name = chip.name.name.clone();
+        let mut library = Library::new(None);
+        library
+            .insert(chip, PathBuf::from(format!("{}.hdl", name)))
+            .unwrap();
+        Simulator::new(elaborate(&mut library, &name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_truth_table() {
+        let mut mux = simulator(
+            "CHIP Mux {
+                IN a, b, sel;
+                OUT out;
+                PARTS:
+                Not(in=sel, out=nsel);
+                And(a=a, b=nsel, out=x);
+                And(a=b, b=sel, out=y);
+                Or(a=x, b=y, out=out);
+            }",
+        );
+        let table = truth_table(&mut mux, DEFAULT_MAX_BITS).unwrap();
+        let text = table.format().unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert_eq!(lines[0], "|   a   |   b   |  sel  |  out  |");
+        assert_eq!(lines[2], "|   0   |   0   |   1   |   0   |");
+        assert_eq!(lines[7], "|   1   |   1   |   0   |   1   |");
+
+        let reference = truth_table(&mut builtin("Mux").unwrap(), DEFAULT_MAX_BITS).unwrap();
+        assert!(table.differences(&reference).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_differences() {
+        // sel を逆に使った Mux
+        let mut mux = simulator(
+            "CHIP BadMux {
+                IN a, b, sel;
+                OUT out;
+                PARTS:
+                Mux(a=b, b=a, sel=sel, out=out);
+            }",
+        );
+        let table = truth_table(&mut mux, DEFAULT_MAX_BITS).unwrap();
+        let reference = truth_table(&mut builtin("Mux").unwrap(), DEFAULT_MAX_BITS).unwrap();
+        let differences = table.differences(&reference).unwrap();
+        assert_eq!(differences.len(), 4);
+        assert_eq!(differences[0], "a=0 b=1 sel=0: out=1, expected out=0");
+
+        let error = truth_table(&mut builtin("Add16").unwrap(), DEFAULT_MAX_BITS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'Add16' has 32 input bits; its truth table would have 2^32 rows (the limit is 2^16)"
+        );
+        let error = truth_table(&mut builtin("Bit").unwrap(), DEFAULT_MAX_BITS).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'Bit' has clocked parts; a truth table needs a combinational chip"
+        );
+    }
+}
@@ -0,0 +1,755 @@
+// ハードウェアシミュレーター用テストスクリプト（.tst）
+mod output;
+mod parser;
+
+pub use output::{Cell, format_header, format_row};
+pub use parser::{Column, Command, Condition, Op, Radix, Variable, parse_script};
+
+use anyhow::{Context, Result, anyhow, bail};
+use nand2tetris_core::{
+    cli::{Fail, Failure},
+    timeout::{self, Budget},
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    elaborate::elaborate_file,
+    simulate::Simulator,
+    vcd::{Dump, Recorder},
+};
+
+pub struct TestRunner {
+    dir: PathBuf,
+    simulator: Option<Simulator>,
+    // tock の回数。tick の後は ticked が立ち、time は 0+ のように表示する
+    time: u64,
+    ticked: bool,
+    columns: Vec<Column>,
+    output: Vec<String>,
+    output_file: Option<PathBuf>,
+    compare_to: Option<(PathBuf, Vec<String>)>,
+    mismatch: Option<Mismatch>,
+    // 波形を記録するなら内部ピンも含めるか。記録は load のたびにやり直す
+    record: Option<bool>,
+    recorder: Option<Recorder>,
+}
+
+// 食い違った行の前に見せる一致した行の数（ヘッダーは別に必ず見せる）
+const CONTEXT_LINES: usize = 2;
+
+// .cmp と最初に食い違った行（line は 1 始まり）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: String,
+    // 前にある一致した行（行番号と内容）
+    pub context: Vec<(usize, String)>,
+    // 値の違う列の名前。列の数が違えば空
+    pub columns: Vec<String>,
+}
+
+impl Mismatch {
+    // 前後の行と違う列を添えたエラーメッセージ
+    pub fn report(&self, path: &Path) -> String {
+        let mut report = format!(
+            "Comparison failure at line {} of '{}'",
+            self.line,
+            path.display()
+        );
+        if !self.columns.is_empty() {
+            let columns: Vec<String> = self
+                .columns
+                .iter()
+                .map(|name| format!("'{}'", name))
+                .collect();
+            let noun = if columns.len() == 1 {
+                "column"
+            } else {
+                "columns"
+            };
+            report.push_str(&format!(" ({} {})", noun, columns.join(", ")));
+        }
+        let mut previous = 0;
+        for (line, text) in &self.context {
+            if *line > previous + 1 {
+                report.push_str(&format!("\n  {:>8}", "..."));
+            }
+            report.push_str(&format!("\n  {:>8}: {}", line, text));
+            previous = *line;
+        }
+        if self.line > previous + 1 {
+            report.push_str(&format!("\n  {:>8}", "..."));
+        }
+        report.push_str(&format!(
+            "\n  expected: {}\n  actual:   {}",
+            self.expected.as_deref().unwrap_or("<end of file>"),
+            self.actual
+        ));
+        report
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    // compare-to が指定されていない
+    Skipped,
+    Passed,
+    Failed(Mismatch),
+}
+
+impl TestRunner {
+    // ファイル名はスクリプトのディレクトリからの相対パスとして解決する
+    pub fn new(dir: &Path) -> Self {
+        TestRunner {
+            dir: dir.to_path_buf(),
+            simulator: None,
+            time: 0,
+            ticked: false,
+            columns: Vec::new(),
+            output: Vec::new(),
+            output_file: None,
+            compare_to: None,
+            mismatch: None,
+            record: None,
+            recorder: None,
+        }
+    }
+
+    // 以降に load したチップの波形を eval、tick、tock のたびに記録する
+    pub fn record(&mut self, internals: bool) {
+        self.record = Some(internals);
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    // 比較に失敗したらその時点でスクリプトを止める
+    pub fn execute(&mut self, commands: &[Command]) -> Result<()> {
+        for command in commands {
+            if self.mismatch.is_some() {
+                break;
+            }
+            self.execute_command(command)?;
+        }
+        Ok(())
+    }
+
+    fn execute_command(&mut self, command: &Command) -> Result<()> {
+        timeout::check()?;
+        match command {
+            Command::Load(file) => {
+                let design = elaborate_file(&self.dir.join(file))?;
+                let simulator = Simulator::new(design)?;
+                self.recorder = self
+                    .record
+                    .map(|internals| Recorder::new(&simulator, internals));
+                self.simulator = Some(simulator);
+                self.time = 0;
+                self.ticked = false;
+            }
+            Command::OutputFile(file) => self.output_file = Some(self.dir.join(file)),
+            Command::CompareTo(file) => {
+                let path = self.dir.join(file);
+                let expected = fs::read_to_string(&path)
+                    .context(format!("Failed to read file '{}'", path.display()))?;
+                // CRLF の .cmp もそのまま比較できるようにする
+                let lines = expected
+                    .lines()
+                    .map(|line| line.trim_end_matches('\r').to_string())
+                    .collect();
+                self.compare_to = Some((path, lines));
+            }
+            Command::OutputList(columns) => {
+                self.columns = columns.clone();
+                self.push_line(format_header(&self.columns));
+            }
+            Command::Set(Variable::Pin(name), value) => self.simulator()?.set(name, *value)?,
+            Command::Set(Variable::Time, _) => bail!("'time' cannot be set"),
+            Command::Set(Variable::Memory { part, index }, value) => {
+                let memory = self.simulator()?.memory_mut(part);
+                let index = memory_index(part, memory.as_ref().map(|memory| memory.len()), *index)?;
+                memory.unwrap()[index] = *value;
+            }
+            Command::LoadMemory(part, file) => {
+                let path = self.dir.join(file);
+                let words = load_hack(&path)?;
+                let Some(memory) = self.simulator()?.memory_mut(part) else {
+                    bail!(
+                        "No built-in part '{}' to load '{}' into",
+                        part,
+                        path.display()
+                    );
+                };
+                if words.len() > memory.len() {
+                    bail!(
+                        "'{}' has {} words but '{}' holds {}",
+                        path.display(),
+                        words.len(),
+                        part,
+                        memory.len()
+                    );
+                }
+                memory.fill(0);
+                memory[..words.len()].copy_from_slice(&words);
+            }
+            Command::Eval => {
+                self.simulator()?.eval();
+                self.sample();
+            }
+            Command::Tick => {
+                self.simulator()?.tick();
+                self.ticked = true;
+                self.sample();
+            }
+            Command::Tock => {
+                self.simulator()?.tock();
+                self.time += 1;
+                self.ticked = false;
+                self.sample();
+            }
+            Command::Output => {
+                let row = format_row(&self.columns, |variable| self.get(variable))?;
+                self.push_line(row);
+            }
+            Command::Echo(text) => println!("{}", text),
+            Command::ClearEcho => {}
+            Command::Repeat(Some(count), body) => {
+                for _ in 0..*count {
+                    self.execute(body)?;
+                }
+            }
+            Command::Repeat(None, body) => {
+                while self.mismatch.is_none() {
+                    self.execute(body)?;
+                }
+            }
+            Command::While(condition, body) => {
+                while self.mismatch.is_none() && self.holds(condition)? {
+                    self.execute(body)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn simulator(&mut self) -> Result<&mut Simulator> {
+        self.simulator
+            .as_mut()
+            .context("No chip is loaded; the script must start with 'load'")
+    }
+
+    // 波形の時刻は半クロック単位
+    fn sample(&mut self) {
+        if let (Some(recorder), Some(simulator)) = (&mut self.recorder, &self.simulator) {
+            recorder.sample(self.time * 2 + self.ticked as u64, simulator);
+        }
+    }
+
+    fn push_line(&mut self, line: String) {
+        if let Some((_, expected)) = &self.compare_to
+            && self.mismatch.is_none()
+        {
+            let index = self.output.len();
+            let expected = expected.get(index);
+            if !expected.is_some_and(|expected| matches(expected, &line)) {
+                let start = index.saturating_sub(CONTEXT_LINES).max(1);
+                let context = (0..index.min(1))
+                    .chain(start..index)
+                    .map(|i| (i + 1, self.output[i].clone()))
+                    .collect();
+                let columns = match expected {
+                    Some(expected) => differing_columns(&self.columns, expected, &line),
+                    None => Vec::new(),
+                };
+                self.mismatch = Some(Mismatch {
+                    line: index + 1,
+                    expected: expected.cloned(),
+                    actual: line.clone(),
+                    context,
+                    columns,
+                });
+            }
+        }
+        self.output.push(line);
+    }
+
+    pub fn get(&self, variable: &Variable) -> Result<Cell> {
+        if variable == &Variable::Time {
+            let plus = if self.ticked { "+" } else { "" };
+            return Ok(Cell::Text(format!("{}{}", self.time, plus)));
+        }
+        let Some(simulator) = &self.simulator else {
+            bail!("No chip is loaded; the script must start with 'load'");
+        };
+        let name = match variable {
+            Variable::Pin(name) => name,
+            Variable::Memory { part, index } => {
+                let memory = simulator.memory(part);
+                let index = memory_index(part, memory.map(<[u16]>::len), *index)?;
+                return Ok(Cell::Pin {
+                    value: memory.unwrap()[index],
+                    width: 16,
+                });
+            }
+            Variable::Time => unreachable!(),
+        };
+        let port = simulator
+            .port(name)
+            .ok_or_else(|| anyhow!("'{}' is not a pin of '{}'", name, simulator.design().name))?;
+        Ok(Cell::Pin {
+            value: simulator.get(name).unwrap(),
+            width: port.nets.len(),
+        })
+    }
+
+    // 16 ビットのピンは符号付きで比べる
+    fn holds(&self, condition: &Condition) -> Result<bool> {
+        let (left, right) = match self.get(&condition.variable)? {
+            Cell::Text(_) => (self.time as i64, condition.value as i64),
+            Cell::Pin { value, width } if width >= 16 => {
+                (value as i16 as i64, condition.value as i16 as i64)
+            }
+            Cell::Pin { value, .. } => (value as i64, condition.value as i64),
+        };
+        Ok(match condition.op {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Gt => left > right,
+            Op::Le => left <= right,
+            Op::Ge => left >= right,
+        })
+    }
+
+    // 出力ファイルを書き、比較の結果を返す
+    pub fn finish(&self) -> Result<Comparison> {
+        if let Some(path) = &self.output_file {
+            let text: String = self
+                .output
+                .iter()
+                .map(|line| format!("{}\n", line))
+                .collect();
+            fs::write(path, text).context(format!("Failed to write '{}'", path.display()))?;
+        }
+
+        Ok(match (&self.compare_to, &self.mismatch) {
+            (None, _) => Comparison::Skipped,
+            (Some(_), Some(mismatch)) => Comparison::Failed(mismatch.clone()),
+            (Some(_), None) => Comparison::Passed,
+        })
+    }
+
+    pub fn compare_path(&self) -> Option<&Path> {
+        self.compare_to.as_ref().map(|(path, _)| path.as_path())
+    }
+}
+
+// dump があれば比較に失敗しても波形を書き出す
+pub fn run_script(path: &Path, dump: Option<&Dump>) -> Result<Comparison> {
+    let source =
+        fs::read_to_string(path).context(format!("Failed to read file '{}'", path.display()))?;
+    let commands = parse_script(&source)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))
+        .fail(Failure::Parse)?;
+    let mut runner = TestRunner::new(path.parent().unwrap_or(Path::new(".")));
+    if let Some(dump) = dump {
+        runner.record(dump.internals);
+    }
+    runner.execute(&commands)?;
+    if let (Some(dump), Some(recorder)) = (dump, runner.recorder()) {
+        recorder.write(&dump.path)?;
+    }
+
+    let comparison = runner.finish()?;
+    if let Comparison::Failed(mismatch) = &comparison {
+        return Err(Failure::Test.wrap(anyhow!(
+            "{}",
+            mismatch.report(runner.compare_path().unwrap_or(path))
+        )));
+    }
+    Ok(comparison)
+}
+
+pub type ScriptResult = (Result<Comparison>, Duration);
+
+// スクリプトを jobs 個のスレッドで並べて実行する。スクリプトどうしは出力ファイルしか
+// 共有しないので独立している。結果はかかった時間と一緒に、終わった順ではなく scripts の順に返す。
+// 時間切れのスクリプトは budget の時間切れのエラーになる
+pub fn run_scripts(scripts: &[PathBuf], jobs: usize, budget: &Budget) -> Vec<ScriptResult> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<ScriptResult>>> =
+        Mutex::new(scripts.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, scripts.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(script) = scripts.get(index) else {
+                        break;
+                    };
+                    let start = Instant::now();
+                    let result = budget.run(|| run_script(script, None));
+                    results.lock().unwrap()[index] = Some((result, start.elapsed()));
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect()
+}
+
+// .cmp の * はどの文字とも一致する（公式の CPU.cmp は outM の不定な値を * にしている）
+fn matches(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+}
+
+// DRegister[] は 1 ワードの部品、RAM16K[5] は番地を指定する。len は部品の中身のワード数
+fn memory_index(part: &str, len: Option<usize>, index: Option<u16>) -> Result<usize> {
+    let Some(len) = len else {
+        bail!("The loaded chip has no built-in part '{}'", part);
+    };
+    match index {
+        None if len == 1 => Ok(0),
+        None => bail!("'{}[]' needs an address", part),
+        Some(index) if (index as usize) < len => Ok(index as usize),
+        Some(index) => bail!("Address {} is out of range for '{}'", index, part),
+    }
+}
+
+// 1 行に 16 桁の 2 進数を書いた .hack
+fn load_hack(path: &Path) -> Result<Vec<u16>> {
+    let source =
+        fs::read_to_string(path).context(format!("Failed to read file '{}'", path.display()))?;
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let line = line.trim();
+            if line.len() != 16 {
+                bail!(
+                    "{}: line {}: expected 16 binary digits",
+                    path.display(),
+                    i + 1
+                );
+            }
+            u16::from_str_radix(line, 2).map_err(|_| {
+                anyhow!(
+                    "{}: line {}: expected 16 binary digits",
+                    path.display(),
+                    i + 1
+                )
+            })
+        })
+        .collect()
+}
+
+// | で区切った欄を比べて、違う欄の列名を返す
+fn differing_columns(columns: &[Column], expected: &str, actual: &str) -> Vec<String> {
+    let expected: Vec<&str> = expected.split('|').collect();
+    let actual: Vec<&str> = actual.split('|').collect();
+    // 先頭と末尾の | の外側の空の欄を除くと列の数になる
+    if expected.len() != actual.len() || expected.len() != columns.len() + 2 {
+        return Vec::new();
+    }
+    columns
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| !matches(expected[i + 1], actual[i + 1]))
+        .map(|(_, column)| column.variable.to_string())
+        .collect()
+}
+
+// ファイルならそれだけ、ディレクトリなら中の .tst を名前順に
+pub fn tst_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .with_context(|| format!("Failed to read directory '{}'", path.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "tst"))
+        .collect();
+    if files.is_empty() {
+        bail!("No .tst files found in '{}'", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nand2tetris_core::timeout::TimeoutArgs;
+
+    const NOT: &str = "CHIP Not { IN in; OUT out; PARTS: Nand(a=in, b=in, out=out); }";
+    const AND: &str = "CHIP And {
+        IN a, b;
+        OUT out;
+        PARTS:
+        Nand(a=a, b=b, out=x);
+        Not(in=x, out=out);
+    }";
+    const BIT: &str = "CHIP Bit {
+        IN in, load;
+        OUT out;
+        PARTS:
+        Not(in=load, out=nload);
+        Nand(a=dff, b=nload, out=x);
+        Nand(a=in, b=load, out=y);
+        Nand(a=x, b=y, out=next);
+        DFF(in=next, out=dff, out=out);
+    }";
+
+    // テストごとに別のディレクトリにチップを書く
+    fn chip_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hdl-tst-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, source) in [("Not.hdl", NOT), ("And.hdl", AND), ("Bit.hdl", BIT)] {
+            fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_execute_script() {
+        let dir = chip_dir("execute");
+        let script = "\
+load And.hdl,
+output-list a%B3.1.3 b%B3.1.3 out%B3.1.3;
+set a 0, set b 0, eval, output;
+set a 1, eval, output;
+set b 1, eval, output;
+";
+        let mut runner = TestRunner::new(&dir);
+        runner.execute(&parse_script(script).unwrap()).unwrap();
+        assert_eq!(
+            runner.output(),
+            [
+                "|   a   |   b   |  out  |",
+                "|   0   |   0   |   0   |",
+                "|   1   |   0   |   0   |",
+                "|   1   |   1   |   1   |",
+            ]
+        );
+
+        let error = TestRunner::new(&dir)
+            .execute(&parse_script("load And.hdl, output-list c%B1.1.1; output;").unwrap())
+            .unwrap_err();
+        assert_eq!(error.to_string(), "'c' is not a pin of 'And'");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_execute_clocked() {
+        let dir = chip_dir("clocked");
+        fs::write(
+            dir.join("Bit.cmp"),
+            "|time |in |load|out|\r\n\
+             | 0+  | 1 |  1 | 0 |\r\n\
+             | 1   | 1 |  1 | 1 |\r\n\
+             | 1+  | 0 |  0 | 1 |\r\n\
+             | 2   | 0 |  0 | 1 |\r\n\
+             | 2+  | 0 |  1 | 0 |\r\n",
+        )
+        .unwrap();
+        let script = "\
+load Bit.hdl,
+output-file Bit.out,
+compare-to Bit.cmp,
+output-list time%S1.3.1 in%B1.1.1 load%B2.1.1 out%B1.1.1;
+set in 1, set load 1, tick, output, tock, output;
+set in 0, set load 0, tick, output, tock, output;
+repeat { set load 1, tick, output, tock, output; }
+";
+        fs::write(dir.join("Bit.tst"), script).unwrap();
+        let error = run_script(&dir.join("Bit.tst"), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            [
+                &format!(
+                    "Comparison failure at line 6 of '{}' (column 'out')",
+                    dir.join("Bit.cmp").display()
+                ),
+                "         1: |time |in |load|out|",
+                "       ...",
+                "         4: | 1+  | 0 |  0 | 1 |",
+                "         5: | 2   | 0 |  0 | 1 |",
+                "  expected: | 2+  | 0 |  1 | 0 |",
+                "  actual:   | 2+  | 0 |  1 | 1 |",
+            ]
+            .join("\n")
+        );
+        // 失敗した行までが .out に書かれる
+        assert_eq!(
+            fs::read_to_string(dir.join("Bit.out"))
+                .unwrap()
+                .lines()
+                .count(),
+            6
+        );
+
+        // 最後の行を直せば repeat は .cmp の終わりで止まる
+        let expected = fs::read_to_string(dir.join("Bit.out")).unwrap();
+        fs::write(dir.join("Bit.cmp"), expected).unwrap();
+        // 失敗しても波形は書き出す
+        let dump = Dump {
+            path: dir.join("Bit.vcd"),
+            internals: true,
+        };
+        let error = run_script(&dir.join("Bit.tst"), Some(&dump)).unwrap_err();
+        assert!(error.to_string().contains("expected: <end of file>"));
+        let vcd = fs::read_to_string(&dump.path).unwrap();
+        assert!(vcd.contains(" dff $end\n"));
+        // time が 3 になる tock は 6、そこで out が 0 になる
+        assert!(vcd.contains("\n#6\n0#\n"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_execute_computer() {
+        let dir = chip_dir("computer");
+        fs::write(
+            dir.join("Computer.hdl"),
+            "CHIP Computer {
+                IN reset;
+                PARTS:
+                ROM32K(address=pc, out=instruction);
+                CPU(inM=inM, instruction=instruction, reset=reset,
+                    outM=outM, writeM=writeM, addressM=addressM, pc=pc);
+                Memory(in=outM, load=writeM, address=addressM, out=inM);
+            }",
+        )
+        .unwrap();
+        // RAM[0] = 2 + 3
+        fs::write(
+            dir.join("Add.hack"),
+            "0000000000000010\n1110110000010000\n0000000000000011\n\
+             1110000010010000\n0000000000000000\n1110001100001000\n",
+        )
+        .unwrap();
+        // * は何にでも一致する
+        fs::write(
+            dir.join("ComputerAdd.cmp"),
+            "|time |ARegi|DRegi|PC[]|RAM16K[0]|\n\
+             |1    |    2|    0|   1|        0|\n\
+             |2    |    2|    2|   2|        0|\n\
+             |3    |    3|    2|   3|        0|\n\
+             |4    |    3|    5|   4|        0|\n\
+             |5    |  ***|    5|   5|        0|\n\
+             |6    |    0|    5|   6|        5|\n",
+        )
+        .unwrap();
+        let script = "\
+load Computer.hdl,
+compare-to ComputerAdd.cmp,
+output-list time%S0.5.0 ARegister[0]%D0.5.0 DRegister[]%D0.5.0 PC[]%D0.4.0 RAM16K[0]%D0.9.0;
+ROM32K load Add.hack,
+set reset 0, set RAM16K[0] 0,
+repeat 6 { tick, tock, output; }
+";
+        fs::write(dir.join("ComputerAdd.tst"), script).unwrap();
+        assert_eq!(
+            run_script(&dir.join("ComputerAdd.tst"), None).unwrap(),
+            Comparison::Passed
+        );
+
+        let error = TestRunner::new(&dir)
+            .execute(&parse_script("load Computer.hdl, set RAM16K[16384] 1;").unwrap())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Address 16384 is out of range for 'RAM16K'"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_scripts() {
+        let dir = chip_dir("parallel");
+        let mut scripts = Vec::new();
+        for (index, expected) in [1, 0, 1, 1, 0, 1].into_iter().enumerate() {
+            let name = format!("And{}", index);
+            fs::write(
+                dir.join(format!("{}.cmp", name)),
+                format!("|out|\n| {} |\n", expected),
+            )
+            .unwrap();
+            fs::write(
+                dir.join(format!("{}.tst", name)),
+                format!(
+                    "load And.hdl, output-file {0}.out, compare-to {0}.cmp, \
+                     output-list out%B1.1.1; set a 1, set b 1, eval, output;",
+                    name
+                ),
+            )
+            .unwrap();
+            scripts.push(dir.join(format!("{}.tst", name)));
+        }
+        // 結果はスレッドの数によらずスクリプトの順
+        for jobs in [1, 4, 100] {
+            let passed: Vec<bool> = run_scripts(&scripts, jobs, &Budget::default())
+                .iter()
+                .map(|(result, _)| result.is_ok())
+                .collect();
+            assert_eq!(passed, [true, false, true, true, false, true]);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let dir = chip_dir("timeout");
+        let script = dir.join("Forever.tst");
+        fs::write(&script, "load Bit.hdl; repeat { tick, tock; }").unwrap();
+        let budget = TimeoutArgs {
+            timeout: Some(Duration::from_millis(50)),
+            total_timeout: None,
+        }
+        .budget();
+        let (result, duration) = run_scripts(&[script], 1, &budget).remove(0);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Timed out after 50ms (--timeout)"
+        );
+        assert!(duration < Duration::from_secs(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_while() {
+        let dir = chip_dir("while");
+        let script = "load Bit.hdl, set in 1, set load 1; while out = 0 { tick, tock; }";
+        let mut runner = TestRunner::new(&dir);
+        runner.execute(&parse_script(script).unwrap()).unwrap();
+        assert_eq!(runner.time, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Result;
+
+use super::parser::{Column, Radix, Variable};
+
+// 1 列の値。ピンの値は幅によって符号の扱いが変わる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cell {
+    Pin { value: u16, width: usize },
+    // time の 0+、1 など
+    Text(String),
+}
+
+// 列名は列幅の中央に置き、はみ出す分は切り捨てる
+pub fn format_header(columns: &[Column]) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        let width = column.pad_left + column.len + column.pad_right;
+        let name: String = column.variable.to_string().chars().take(width).collect();
+        let left = (width - name.len()) / 2;
+        let right = width - name.len() - left;
+        line.push_str(&format!(
+            "{}{}{}|",
+            " ".repeat(left),
+            name,
+            " ".repeat(right)
+        ));
+    }
+    line
+}
+
+pub fn format_row(columns: &[Column], value: impl Fn(&Variable) -> Result<Cell>) -> Result<String> {
+    let mut line = String::from("|");
+    for column in columns {
+        let text = format_cell(&value(&column.variable)?, column.radix, column.len);
+        line.push_str(&format!(
+            "{}{}{}|",
+            " ".repeat(column.pad_left),
+            text,
+            " ".repeat(column.pad_right)
+        ));
+    }
+    Ok(line)
+}
+
+// 2進・16進は len 桁にゼロ詰め、10進は右寄せ、文字列は左寄せ。
+// 10進は 16 ビットのバスだけ符号付き
+fn format_cell(cell: &Cell, radix: Radix, len: usize) -> String {
+    let text = match (cell, radix) {
+        (Cell::Text(text), _) => format!("{:<len$}", text),
+        (&Cell::Pin { value, .. }, Radix::Binary) => fit_digits(&format!("{:016b}", value), len),
+        (&Cell::Pin { value, .. }, Radix::Hex) => fit_digits(&format!("{:04X}", value), len),
+        (&Cell::Pin { value, width }, Radix::Decimal) => {
+            format!("{:>len$}", decimal(value, width))
+        }
+        (&Cell::Pin { value, width }, Radix::String) => {
+            format!("{:<len$}", decimal(value, width))
+        }
+    };
+    text.chars().take(len).collect()
+}
+
+fn decimal(value: u16, width: usize) -> i32 {
+    if width >= 16 {
+        value as i16 as i32
+    } else {
+        value as i32
+    }
+}
+
+// 桁数が len より多ければ下位桁を残し、少なければゼロで埋める
+fn fit_digits(digits: &str, len: usize) -> String {
+    if digits.len() >= len {
+        digits[digits.len() - len..].to_string()
+    } else {
+        format!("{}{}", "0".repeat(len - digits.len()), digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn column(name: &str, radix: Radix, pad_left: usize, len: usize, pad_right: usize) -> Column {
+        Column {
+            variable: super::super::parser::parse_variable(name).unwrap(),
+            radix,
+            pad_left,
+            len,
+            pad_right,
+        }
+    }
+
+    #[test]
+    fn test_format_header() {
+        let columns = [
+            column("time", Radix::String, 1, 4, 1),
+            column("in", Radix::Decimal, 1, 6, 1),
+            column("load", Radix::Binary, 2, 1, 2),
+            column("address", Radix::Binary, 0, 3, 0),
+        ];
+        assert_eq!(format_header(&columns), "| time |   in   |load |add|");
+    }
+
+    // 公式の ALU.cmp の 1 行目と 2 行目
+    #[test]
+    fn test_format_official_alu() {
+        let mut columns = vec![
+            column("x", Radix::Binary, 1, 16, 1),
+            column("y", Radix::Binary, 1, 16, 1),
+        ];
+        for name in ["zx", "nx", "zy", "ny", "f", "no"] {
+            columns.push(column(name, Radix::Binary, 1, 1, 1));
+        }
+        columns.push(column("out", Radix::Binary, 1, 16, 1));
+        columns.push(column("zr", Radix::Binary, 1, 1, 1));
+        columns.push(column("ng", Radix::Binary, 1, 1, 1));
+        assert_eq!(
+            format_header(&columns),
+            "|        x         |        y         |zx |nx |zy |ny | f |no |       out        |zr |ng |"
+        );
+
+        let values = [0, 0xffff, 1, 0, 1, 0, 1, 0, 0, 1, 0];
+        let row = format_row(&columns, |variable| {
+            let index = columns
+                .iter()
+                .position(|column| &column.variable == variable)
+                .unwrap();
+            let width = if columns[index].len == 16 { 16 } else { 1 };
+            Ok(Cell::Pin {
+                value: values[index],
+                width,
+            })
+        })
+        .unwrap();
+        assert_eq!(
+            row,
+            "| 0000000000000000 | 1111111111111111 | 1 | 0 | 1 | 0 | 1 | 0 | 0000000000000000 | 1 | 0 |"
+        );
+    }
+
+    #[rstest]
+    #[case(Cell::Text("0+".to_string()), Radix::String, 1, 4, 1, "| 0+   |")]
+    #[case(Cell::Pin { value: 0xffff, width: 16 }, Radix::Decimal, 1, 6, 1, "|     -1 |")]
+    #[case(Cell::Pin { value: 5, width: 3 }, Radix::Decimal, 1, 6, 1, "|      5 |")]
+    #[case(Cell::Pin { value: 5, width: 16 }, Radix::Binary, 1, 16, 1, "| 0000000000000101 |")]
+    #[case(Cell::Pin { value: 1, width: 1 }, Radix::Binary, 2, 1, 2, "|  1  |")]
+    #[case(Cell::Pin { value: 0x7fff, width: 16 }, Radix::Hex, 1, 4, 1, "| 7FFF |")]
+    fn test_format_row(
+        #[case] cell: Cell,
+        #[case] radix: Radix,
+        #[case] pad_left: usize,
+        #[case] len: usize,
+        #[case] pad_right: usize,
+        #[case] expected: &str,
+    ) {
+        let columns = [column("out", radix, pad_left, len, pad_right)];
+        assert_eq!(
+            format_row(&columns, |_| Ok(cell.clone())).unwrap(),
+            expected
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Variable {
+    // 読み込んだチップの入力、出力、内部ピン
+    Pin(String),
+    // 0、0+、1、1+ …（tick の後に + が付く）
+    Time,
+    // DRegister[] や RAM16K[5] のような組み込みチップの中身
+    Memory { part: String, index: Option<u16> },
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variable::Pin(name) => write!(f, "{}", name),
+            Variable::Time => write!(f, "time"),
+            Variable::Memory { part, index: None } => write!(f, "{}[]", part),
+            Variable::Memory {
+                part,
+                index: Some(index),
+            } => write!(f, "{}[{}]", part, index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Binary,
+    Hex,
+    Decimal,
+    String,
+}
+
+// output-list の1列（例: out%B3.1.3）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub variable: Variable,
+    pub radix: Radix,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub variable: Variable,
+    pub op: Op,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Load(PathBuf),
+    OutputFile(PathBuf),
+    CompareTo(PathBuf),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    // ROM32K load Prog.hack
+    LoadMemory(String, PathBuf),
+    Eval,
+    Tick,
+    Tock,
+    Output,
+    Echo(String),
+    ClearEcho,
+    // None なら無限に繰り返す
+    Repeat(Option<u64>, Vec<Command>),
+    While(Condition, Vec<Command>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Symbol(char),
+}
+
+struct Tokenizer {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Tokenizer {
+    fn new(source: &str) -> Result<Self> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut line = 1;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\n' => {
+                    line += 1;
+                    i += 1;
+                }
+                _ if c.is_whitespace() => i += 1,
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    let start = line;
+                    i += 2;
+                    loop {
+                        ensure!(i < chars.len(), "line {}: unterminated comment", start);
+                        if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                            i += 2;
+                            break;
+                        }
+                        if chars[i] == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                }
+                '"' => {
+                    let start = i + 1;
+                    i = start;
+                    while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    ensure!(
+                        chars.get(i) == Some(&'"'),
+                        "line {}: unterminated string",
+                        line
+                    );
+                    tokens.push((Token::Str(chars[start..i].iter().collect()), line));
+                    i += 1;
+                }
+                ',' | ';' | '!' | '{' | '}' | '<' | '>' | '=' => {
+                    tokens.push((Token::Symbol(c), line));
+                    i += 1;
+                }
+                _ => {
+                    let start = i;
+                    while i < chars.len() && !is_delimiter(chars[i]) {
+                        i += 1;
+                    }
+                    tokens.push((Token::Word(chars[start..i].iter().collect()), line));
+                }
+            }
+        }
+
+        Ok(Tokenizer { tokens, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |&(_, line)| line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_word(&mut self) -> Result<String> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => bail!("line {}: unexpected {:?}", line, token),
+            None => bail!("line {}: unexpected end of script", line),
+        }
+    }
+
+    fn accept(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        ensure!(
+            self.accept(symbol),
+            "line {}: expected '{}'",
+            self.line(),
+            symbol
+        );
+        Ok(())
+    }
+
+    fn words_until_separator(&mut self) -> Vec<String> {
+        let mut words = Vec::new();
+        while let Some(Token::Word(word)) = self.peek() {
+            words.push(word.clone());
+            self.pos += 1;
+        }
+        words
+    }
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ',' | ';' | '!' | '{' | '}' | '<' | '>' | '=' | '"')
+}
+
+pub fn parse_script(source: &str) -> Result<Vec<Command>> {
+    let mut tokens = Tokenizer::new(source)?;
+    parse_block(&mut tokens, false)
+}
+
+fn parse_block(tokens: &mut Tokenizer, nested: bool) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+
+    loop {
+        // コマンドの区切り（, ; !）は実行上の意味を持たない
+        while tokens.accept(',') || tokens.accept(';') || tokens.accept('!') {}
+
+        match tokens.peek() {
+            None if nested => bail!("line {}: missing '}}'", tokens.line()),
+            None => break,
+            Some(Token::Symbol('}')) if nested => {
+                tokens.next();
+                break;
+            }
+            _ => commands.extend(parse_command(tokens)?),
+        }
+    }
+
+    Ok(commands)
+}
+
+// ticktock は tick と tock の 2 つになる
+fn parse_command(tokens: &mut Tokenizer) -> Result<Vec<Command>> {
+    let line = tokens.line();
+    let at = |e: anyhow::Error| anyhow!("line {}: {}", line, e);
+
+    let name = match tokens.next() {
+        Some(Token::Word(word)) => word,
+        Some(Token::Str(_)) => bail!("line {}: unexpected string", line),
+        Some(Token::Symbol(c)) => bail!("line {}: unexpected '{}'", line, c),
+        None => bail!("line {}: unexpected end of script", line),
+    };
+
+    let command = match name.as_str() {
+        "load" => Command::Load(PathBuf::from(tokens.next_word()?)),
+        "output-file" => Command::OutputFile(PathBuf::from(tokens.next_word()?)),
+        "compare-to" => Command::CompareTo(PathBuf::from(tokens.next_word()?)),
+        "output-list" => {
+            let columns = tokens
+                .words_until_separator()
+                .iter()
+                .map(|word| parse_column(word))
+                .collect::<Result<Vec<_>>>()
+                .map_err(at)?;
+            Command::OutputList(columns)
+        }
+        "set" => {
+            let variable = parse_variable(&tokens.next_word()?).map_err(at)?;
+            let value = parse_value(&tokens.next_word()?).map_err(at)?;
+            Command::Set(variable, value)
+        }
+        "eval" => Command::Eval,
+        "tick" => Command::Tick,
+        "tock" => Command::Tock,
+        "ticktock" => return Ok(vec![Command::Tick, Command::Tock]),
+        "output" => Command::Output,
+        "echo" => match tokens.next() {
+            Some(Token::Str(text)) | Some(Token::Word(text)) => Command::Echo(text),
+            _ => bail!("line {}: echo expects a string", line),
+        },
+        "clear-echo" => Command::ClearEcho,
+        "repeat" => {
+            let count = match tokens.peek() {
+                Some(Token::Word(_)) => {
+                    let word = tokens.next_word()?;
+                    let count = word.parse().ok();
+                    ensure!(
+                        count.is_some(),
+                        "line {}: invalid repeat count '{}'",
+                        line,
+                        word
+                    );
+                    count
+                }
+                _ => None,
+            };
+            tokens.expect('{')?;
+            Command::Repeat(count, parse_block(tokens, true)?)
+        }
+        "while" => {
+            let variable = parse_variable(&tokens.next_word()?).map_err(at)?;
+            let op = parse_op(tokens).map_err(at)?;
+            let value = parse_value(&tokens.next_word()?).map_err(at)?;
+            tokens.expect('{')?;
+            Command::While(
+                Condition {
+                    variable,
+                    op,
+                    value,
+                },
+                parse_block(tokens, true)?,
+            )
+        }
+        _ if tokens.peek() == Some(&Token::Word("load".to_string())) => {
+            tokens.next();
+            Command::LoadMemory(name, PathBuf::from(tokens.next_word()?))
+        }
+        _ => bail!("line {}: unknown command '{}'", line, name),
+    };
+    Ok(vec![command])
+}
+
+fn parse_op(tokens: &mut Tokenizer) -> Result<Op> {
+    Ok(if tokens.accept('=') {
+        Op::Eq
+    } else if tokens.accept('<') {
+        if tokens.accept('>') {
+            Op::Ne
+        } else if tokens.accept('=') {
+            Op::Le
+        } else {
+            Op::Lt
+        }
+    } else if tokens.accept('>') {
+        if tokens.accept('=') { Op::Ge } else { Op::Gt }
+    } else {
+        bail!("expected a comparison operator")
+    })
+}
+
+// ピンや部品があるかどうかは load したチップで決まるので、実行するときに確かめる
+pub fn parse_variable(text: &str) -> Result<Variable> {
+    if text == "time" {
+        return Ok(Variable::Time);
+    }
+    let Some((part, index)) = text.strip_suffix(']').and_then(|text| text.split_once('[')) else {
+        return Ok(Variable::Pin(text.to_string()));
+    };
+    let index = match index {
+        "" => None,
+        _ => Some(
+            index
+                .parse()
+                .ok()
+                .context(format!("invalid variable '{}'", text))?,
+        ),
+    };
+    Ok(Variable::Memory {
+        part: part.to_string(),
+        index,
+    })
+}
+
+// 10進（負数可）、%D、%X、%B の値
+pub fn parse_value(text: &str) -> Result<u16> {
+    let parsed = match text.get(..2) {
+        Some("%X") => i32::from_str_radix(&text[2..], 16).ok(),
+        Some("%B") => i32::from_str_radix(&text[2..], 2).ok(),
+        Some("%D") => text[2..].parse().ok(),
+        _ => text.parse().ok(),
+    };
+    match parsed {
+        Some(value) if (i16::MIN as i32..=u16::MAX as i32).contains(&value) => Ok(value as u16),
+        Some(_) => bail!("value out of range: '{}'", text),
+        None => bail!("invalid value '{}'", text),
+    }
+}
+
+// out%B3.1.3 → 変数と書式。書式を省略したら %B1.1.1
+fn parse_column(text: &str) -> Result<Column> {
+    let (name, format) = match text.split_once('%') {
+        Some((name, format)) => (name, Some(format)),
+        None => (text, None),
+    };
+    let variable = parse_variable(name)?;
+
+    let Some(format) = format else {
+        return Ok(Column {
+            variable,
+            radix: Radix::Binary,
+            pad_left: 1,
+            len: 1,
+            pad_right: 1,
+        });
+    };
+
+    let radix = match format.chars().next() {
+        Some('B') => Radix::Binary,
+        Some('X') => Radix::Hex,
+        Some('D') => Radix::Decimal,
+        Some('S') => Radix::String,
+        _ => bail!("invalid output format '{}'", text),
+    };
+    let widths = format[1..]
+        .split('.')
+        .map(|n| n.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|widths| widths.len() == 3)
+        .context(format!("invalid output format '{}'", text))?;
+
+    Ok(Column {
+        variable,
+        radix,
+        pad_left: widths[0],
+        len: widths[1],
+        pad_right: widths[2],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_parse_script() {
+        let script = "\
+// Bit.tst
+load Bit.hdl,
+output-file Bit.out,
+compare-to Bit.cmp,
+output-list time%S1.4.1 in%B2.1.2 out%D1.6.1;
+
+set in 0, set load %B1,
+tick, output; tock, output;
+repeat 2 { ticktock; }
+while out <> 0 { eval; }
+";
+        let column = |variable, radix, pad_left, len, pad_right| Column {
+            variable,
+            radix,
+            pad_left,
+            len,
+            pad_right,
+        };
+        let pin = |name: &str| Variable::Pin(name.to_string());
+        assert_eq!(
+            parse_script(script).unwrap(),
+            vec![
+                Command::Load(PathBuf::from("Bit.hdl")),
+                Command::OutputFile(PathBuf::from("Bit.out")),
+                Command::CompareTo(PathBuf::from("Bit.cmp")),
+                Command::OutputList(vec![
+                    column(Variable::Time, Radix::String, 1, 4, 1),
+                    column(pin("in"), Radix::Binary, 2, 1, 2),
+                    column(pin("out"), Radix::Decimal, 1, 6, 1),
+                ]),
+                Command::Set(pin("in"), 0),
+                Command::Set(pin("load"), 1),
+                Command::Tick,
+                Command::Output,
+                Command::Tock,
+                Command::Output,
+                Command::Repeat(Some(2), vec![Command::Tick, Command::Tock]),
+                Command::While(
+                    Condition {
+                        variable: pin("out"),
+                        op: Op::Ne,
+                        value: 0,
+                    },
+                    vec![Command::Eval],
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        let script = "ROM32K load Add.hack, set RAM16K[2] -1, output-list DRegister[]%D1.6.1;";
+        let [load, set, output_list] = &parse_script(script).unwrap()[..] else {
+            panic!();
+        };
+        assert_eq!(
+            load,
+            &Command::LoadMemory("ROM32K".to_string(), PathBuf::from("Add.hack"))
+        );
+        assert_eq!(
+            set,
+            &Command::Set(
+                Variable::Memory {
+                    part: "RAM16K".to_string(),
+                    index: Some(2)
+                },
+                0xffff
+            )
+        );
+        let Command::OutputList(columns) = output_list else {
+            panic!("{:?}", output_list);
+        };
+        assert_eq!(columns[0].variable.to_string(), "DRegister[]");
+    }
+
+    #[rstest]
+    #[case("repeat 3 { eval;", "line 1: missing '}'")]
+    #[case("set in 70000;", "line 1: value out of range: '70000'")]
+    #[case(
+        "output-list out%Q1.1.1;",
+        "line 1: invalid output format 'out%Q1.1.1'"
+    )]
+    #[case("eval;\nvmstep;", "line 2: unknown command 'vmstep'")]
+    #[case("set RAM16K[x] 1;", "line 1: invalid variable 'RAM16K[x]'")]
+    fn test_parse_script_invalid(#[case] script: &str, #[case] expected: &str) {
+        assert_eq!(parse_script(script).unwrap_err().to_string(), expected);
+    }
+}
@@ -0,0 +1,173 @@
+// シミュレーションの波形を VCD（Value Change Dump）に書き出す。GTKWave などで見られる
+//
+// 時刻の単位は半クロックで、tick の後が奇数、tock の後が偶数になる（time が 1+ なら 3）。
+// 記録するのは一番外のチップの入力と出力で、internals なら内部ピンも加える
+
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::simulate::Simulator;
+
+// 波形を書き出す先と、内部ピンも記録するか
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub path: PathBuf,
+    pub internals: bool,
+}
+
+struct Signal {
+    name: String,
+    width: usize,
+    id: String,
+}
+
+pub struct Recorder {
+    chip: String,
+    signals: Vec<Signal>,
+    // 最後に記録した値。最初の sample まではどれも None
+    values: Vec<Option<u16>>,
+    time: Option<u64>,
+    changes: String,
+}
+
+impl Recorder {
+    pub fn new(simulator: &Simulator, internals: bool) -> Self {
+        let design = simulator.design();
+        let mut ports: Vec<_> = design.inputs.iter().chain(&design.outputs).collect();
+        if internals {
+            ports.extend(&design.internals);
+        }
+        let signals: Vec<Signal> = ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| Signal {
+                name: port.name.clone(),
+                width: port.nets.len(),
+                id: identifier(index),
+            })
+            .collect();
+        Recorder {
+            chip: design.name.clone(),
+            values: vec![None; signals.len()],
+            signals,
+            time: None,
+            changes: String::new(),
+        }
+    }
+
+    // time の時点の値のうち、前と変わったものを記録する。同じ時刻に何度呼んでもよい
+    pub fn sample(&mut self, time: u64, simulator: &Simulator) {
+        for (signal, last) in self.signals.iter().zip(&mut self.values) {
+            let value = simulator.get(&signal.name).unwrap();
+            if *last == Some(value) {
+                continue;
+            }
+            if self.time != Some(time) {
+                self.changes.push_str(&format!("#{}\n", time));
+                self.time = Some(time);
+            }
+            *last = Some(value);
+            if signal.width == 1 {
+                self.changes.push_str(&format!("{}{}\n", value, signal.id));
+            } else {
+                self.changes
+                    .push_str(&format!("b{:b} {}\n", value, signal.id));
+            }
+        }
+    }
+
+    pub fn format(&self) -> String {
+        let mut text = String::from("$timescale 1 ns $end\n");
+        text.push_str(&format!("$scope module {} $end\n", self.chip));
+        for signal in &self.signals {
+            let range = if signal.width == 1 {
+                String::new()
+            } else {
+                format!(" [{}:0]", signal.width - 1)
+            };
+            text.push_str(&format!(
+                "$var wire {} {} {}{} $end\n",
+                signal.width, signal.id, signal.name, range
+            ));
+        }
+        text.push_str("$upscope $end\n$enddefinitions $end\n");
+        text.push_str(&self.changes);
+        text
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.format()).context(format!("Failed to write '{}'", path.display()))
+    }
+}
+
+// VCD の識別子は ! から ~ までの文字を並べたもの
+fn identifier(mut index: usize) -> String {
+    const DIGITS: usize = (b'~' - b'!' + 1) as usize;
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (index % DIGITS) as u8) as char);
+        index /= DIGITS;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::truth_table::builtin;
+
+    #[test]
+    fn test_recorder() {
+        let mut register = builtin("Register").unwrap();
+        let mut recorder = Recorder::new(&register, false);
+        register.eval();
+        recorder.sample(0, &register);
+        register.set("in", 5).unwrap();
+        register.set("load", 1).unwrap();
+        register.tick();
+        recorder.sample(1, &register);
+        register.tock();
+        recorder.sample(2, &register);
+        // 値が変わらなければ時刻も書かない
+        register.tick();
+        recorder.sample(3, &register);
+
+        assert_eq!(
+            recorder.format(),
+            [
+                "$timescale 1 ns $end",
+                "$scope module Register $end",
+                "$var wire 16 ! in [15:0] $end",
+                "$var wire 1 \" load $end",
+                "$var wire 16 # out [15:0] $end",
+                "$upscope $end",
+                "$enddefinitions $end",
+                "#0",
+                "b0 !",
+                "0\"",
+                "b0 #",
+                "#1",
+                "b101 !",
+                "1\"",
+                "#2",
+                "b101 #",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+        assert_eq!(identifier(95), "\"!");
+    }
+}
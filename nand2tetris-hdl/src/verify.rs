@@ -0,0 +1,339 @@
+// 2 つのチップに同じ入力を与えて出力を比べる。ふつうは自分のチップと同じ名前の組み込みチップ
+//
+// 入力のビット数が少なければすべての組み合わせを試し、多ければ乱数で選ぶ。
+// クロック付きのチップは 1 回ごとに乱数の入力で 1 クロック進め、eval と tock の後で比べる
+
+use anyhow::{Result, bail};
+
+use crate::{
+    builtin::mask,
+    elaborate::{Port, elaborate_file},
+    simulate::Simulator,
+    truth_table::{self, DEFAULT_MAX_BITS},
+};
+
+// 乱数で試す回数の既定値
+pub const DEFAULT_SAMPLES: u64 = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Exhaustive,
+    Random { samples: u64, seed: u64 },
+}
+
+// 最初に出力が食い違った入力。clocked なら sample 回目のクロックの tock の後か前か
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub sample: u64,
+    pub after_tock: Option<bool>,
+    pub inputs: Vec<(String, u16)>,
+    pub actual: Vec<(String, u16)>,
+    pub expected: Vec<(String, u16)>,
+}
+
+impl Divergence {
+    // Sample 2: a=0 b=1: out=0, expected out=1
+    pub fn report(&self) -> String {
+        let assignment = |values: &[(String, u16)]| -> String {
+            values
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let when = match self.after_tock {
+            None => format!("Sample {}", self.sample),
+            Some(false) => format!("Cycle {}, before the clock", self.sample),
+            Some(true) => format!("Cycle {}, after the clock", self.sample),
+        };
+        format!(
+            "{}: {}: {}, expected {}",
+            when,
+            assignment(&self.inputs),
+            assignment(&self.actual),
+            assignment(&self.expected)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    // 試した入力の数（clocked ならクロックの数）
+    pub checked: u64,
+    pub divergence: Option<Divergence>,
+}
+
+// builtin:ALU なら組み込みチップ、それ以外は .hdl のパス
+pub fn reference(spec: &str) -> Result<Simulator> {
+    match spec.strip_prefix("builtin:") {
+        Some(name) => truth_table::builtin(name),
+        None => Simulator::new(elaborate_file(spec.as_ref())?),
+    }
+}
+
+// 入力のビット数が少ない組み合わせ回路ならすべて試す
+pub fn default_mode(chip: &Simulator, seed: u64) -> Mode {
+    let bits: usize = chip
+        .design()
+        .inputs
+        .iter()
+        .map(|port| port.nets.len())
+        .sum();
+    if !chip.is_clocked() && bits <= DEFAULT_MAX_BITS as usize {
+        Mode::Exhaustive
+    } else {
+        Mode::Random {
+            samples: DEFAULT_SAMPLES,
+            seed,
+        }
+    }
+}
+
+pub fn verify(chip: &mut Simulator, reference: &mut Simulator, mode: Mode) -> Result<Verification> {
+    let pins = |ports: &[Port]| -> Vec<(String, usize)> {
+        ports
+            .iter()
+            .map(|port| (port.name.clone(), port.nets.len()))
+            .collect()
+    };
+    // 並び順は違ってもよい
+    let sorted = |ports: &[Port]| {
+        let mut pins = pins(ports);
+        pins.sort();
+        pins
+    };
+    let (design, other) = (chip.design(), reference.design());
+    if sorted(&design.inputs) != sorted(&other.inputs)
+        || sorted(&design.outputs) != sorted(&other.outputs)
+    {
+        bail!(
+            "The pins of '{}' do not match '{}'",
+            design.name,
+            other.name
+        );
+    }
+    let inputs = pins(&design.inputs);
+    let outputs: Vec<String> = design
+        .outputs
+        .iter()
+        .map(|port| port.name.clone())
+        .collect();
+    let clocked = chip.is_clocked() || reference.is_clocked();
+
+    let samples: Box<dyn Iterator<Item = Vec<u16>>> = match mode {
+        Mode::Exhaustive => {
+            if clocked {
+                bail!(
+                    "'{}' has clocked parts, so its inputs cannot be tried exhaustively",
+                    design.name
+                );
+            }
+            let bits: usize = inputs.iter().map(|(_, width)| width).sum();
+            if bits > DEFAULT_MAX_BITS as usize {
+                bail!(
+                    "'{}' has {} input bits; trying all 2^{} inputs would take too long",
+                    design.name,
+                    bits,
+                    bits
+                );
+            }
+            let widths: Vec<usize> = inputs.iter().map(|(_, width)| *width).collect();
+            Box::new((0..1u64 << bits).map(move |combination| {
+                let mut shift = bits;
+                widths
+                    .iter()
+                    .map(|width| {
+                        shift -= width;
+                        (combination >> shift & ((1 << width) - 1)) as u16
+                    })
+                    .collect()
+            }))
+        }
+        Mode::Random { samples, seed } => {
+            let mut random = Random::new(seed);
+            let widths: Vec<u16> = inputs.iter().map(|(_, width)| *width as u16).collect();
+            Box::new(
+                (0..samples)
+                    .map(move |_| widths.iter().map(|&width| random.value(width)).collect()),
+            )
+        }
+    };
+
+    let mut checked = 0;
+    for values in samples {
+        for ((name, _), &value) in inputs.iter().zip(&values) {
+            chip.set(name, value)?;
+            reference.set(name, value)?;
+        }
+        let phases: &[Option<bool>] = if clocked {
+            &[Some(false), Some(true)]
+        } else {
+            &[None]
+        };
+        for &after_tock in phases {
+            if after_tock == Some(true) {
+                chip.tick();
+                reference.tick();
+                chip.tock();
+                reference.tock();
+            } else {
+                chip.eval();
+                reference.eval();
+            }
+            let read = |simulator: &Simulator| -> Vec<(String, u16)> {
+                outputs
+                    .iter()
+                    .map(|name| (name.clone(), simulator.get(name).unwrap()))
+                    .collect()
+            };
+            let (actual, expected) = (read(chip), read(reference));
+            if actual != expected {
+                return Ok(Verification {
+                    checked,
+                    divergence: Some(Divergence {
+                        sample: checked,
+                        after_tock,
+                        inputs: inputs
+                            .iter()
+                            .map(|(name, _)| name.clone())
+                            .zip(values)
+                            .collect(),
+                        actual,
+                        expected,
+                    }),
+                });
+            }
+        }
+        checked += 1;
+    }
+    Ok(Verification {
+        checked,
+        divergence: None,
+    })
+}
+
+// xorshift64*。同じ seed なら同じ入力の列になる
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // 0 のままだとずっと 0
+        Random(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // 一様な乱数だけでは 0 や -1 のような境目の値がほとんど出ないので、8 回に 1 回はそこから選ぶ
+    fn value(&mut self, width: u16) -> u16 {
+        const EDGES: [u16; 5] = [0, 1, 0x7fff, 0x8000, 0xffff];
+        let random = self.next();
+        let value = if random & 7 == 0 {
+            EDGES[(random >> 3) as usize % EDGES.len()]
+        } else {
+            (random >> 32) as u16
+        };
+        value & mask(width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elaborate::{Library, elaborate},
+        parser::parse,
+    };
+    use std::path::PathBuf;
+
+    fn simulator(source: &str) -> Simulator {
+        let chip = parse(source).unwrap();
+        let name = chip.name.name.clone();
+        let mut library = Library::new(None);
+        library
+            .insert(chip, PathBuf::from(format!("{}.hdl", name)))
+            .unwrap();
+        Simulator::new(elaborate(&mut library, &name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_verify() {
+        // zr を out の最下位ビットだけで決めている
+        let source = "CHIP BadALU {
+            IN x[16], y[16], zx, nx, zy, ny, f, no;
+            OUT out[16], zr, ng;
+            PARTS:
+            ALU(x=x, y=y, zx=zx, nx=nx, zy=zy, ny=ny, f=f, no=no,
+                out=out, out[0]=low, ng=ng);
+            Not(in=low, out=zr);
+        }";
+        let mut alu = simulator(source);
+        let mode = default_mode(&alu, 0);
+        assert_eq!(
+            mode,
+            Mode::Random {
+                samples: DEFAULT_SAMPLES,
+                seed: 0
+            }
+        );
+        let verification = verify(&mut alu, &mut reference("builtin:ALU").unwrap(), mode).unwrap();
+        let divergence = verification.divergence.unwrap();
+        assert!(
+            divergence
+                .report()
+                .starts_with(&format!("Sample {}: ", verification.checked))
+        );
+        assert_eq!(divergence.actual[1].0, "zr");
+        assert_ne!(divergence.actual[1], divergence.expected[1]);
+
+        let mut and = simulator(
+            "CHIP And2 { IN a, b; OUT out; PARTS: Nand(a=a, b=b, out=x); Not(in=x, out=out); }",
+        );
+        assert_eq!(default_mode(&and, 0), Mode::Exhaustive);
+        let verification = verify(
+            &mut and,
+            &mut reference("builtin:And").unwrap(),
+            Mode::Exhaustive,
+        )
+        .unwrap();
+        assert_eq!(verification.checked, 4);
+        assert_eq!(verification.divergence, None);
+        assert_eq!(
+            verify(
+                &mut and,
+                &mut reference("builtin:Or").unwrap(),
+                Mode::Exhaustive
+            )
+            .unwrap()
+            .divergence
+            .unwrap()
+            .report(),
+            "Sample 1: a=0 b=1: out=0, expected out=1"
+        );
+    }
+
+    #[test]
+    fn test_verify_clocked() {
+        // load を無視して毎回取り込む
+        let mut bit =
+            simulator("CHIP BadBit { IN in, load; OUT out; PARTS: DFF(in=in, out=out); }");
+        let mode = default_mode(&bit, 1);
+        let divergence = verify(&mut bit, &mut reference("builtin:Bit").unwrap(), mode)
+            .unwrap()
+            .divergence
+            .unwrap();
+        assert_eq!(divergence.after_tock, Some(true));
+        assert_eq!(divergence.inputs[1], ("load".to_string(), 0));
+
+        assert_eq!(
+            verify(&mut bit, &mut reference("builtin:DFF").unwrap(), mode)
+                .unwrap_err()
+                .to_string(),
+            "The pins of 'BadBit' do not match 'DFF'"
+        );
+    }
+}
@@ -0,0 +1,741 @@
+// 展開したチップを合成できる Verilog にする
+//
+// 一番外のチップが 1 つのモジュールになり、中の組み込みチップの部品はそれぞれ同じ名前の
+// モジュールの実体になる。ネットは net[n] の 1 ビットで、net[0] が false、net[1] が true。
+// 組み込みチップのモジュールは振る舞いで書いたもので、使ったものだけを後ろに付ける
+//
+// 状態を持つ部品は clk の立ち上がりで更新する（tick で取り込み tock で出すのと同じ）。
+// ROM32K は ROM_FILE の .hack を $readmemb で読む。Keyboard の値は key から入れる
+
+use anyhow::{Result, bail};
+use std::collections::BTreeSet;
+
+use crate::{
+    builtin,
+    elaborate::{Design, FALSE, Net, Port, TRUE},
+};
+
+// ROM_FILE の既定値
+pub const DEFAULT_ROM_FILE: &str = "rom.hack";
+
+// ピンの名前に使えない Verilog の予約語（よく使われる名前に近いもの）と、出力で使う名前
+const RESERVED: &[&str] = &[
+    "always",
+    "and",
+    "assign",
+    "begin",
+    "buf",
+    "case",
+    "clk",
+    "default",
+    "else",
+    "end",
+    "for",
+    "function",
+    "if",
+    "initial",
+    "inout",
+    "input",
+    "integer",
+    "key",
+    "module",
+    "nand",
+    "net",
+    "nor",
+    "not",
+    "or",
+    "output",
+    "parameter",
+    "reg",
+    "wire",
+    "xnor",
+    "xor",
+];
+
+pub fn verilog(design: &Design, rom_file: &str) -> Result<String> {
+    for port in design.inputs.iter().chain(&design.outputs) {
+        if RESERVED.contains(&port.name.as_str()) {
+            bail!(
+                "Pin '{}' of '{}' cannot be used as a Verilog port name",
+                port.name,
+                design.name
+            );
+        }
+    }
+
+    let chips: Vec<Box<dyn builtin::Builtin>> = design
+        .gates
+        .iter()
+        .map(|gate| builtin::create(&gate.chip).unwrap())
+        .collect();
+    let mut used = BTreeSet::new();
+    for gate in &design.gates {
+        add_module(&mut used, &gate.chip)?;
+    }
+    if used.contains(design.name.as_str()) {
+        bail!(
+            "'{}' uses the built-in chip of the same name, so the Verilog modules would clash",
+            design.name
+        );
+    }
+    let clocked = design
+        .gates
+        .iter()
+        .any(|gate| module(&gate.chip).is_some_and(|module| module.clocked));
+    let keyboard = used.iter().any(|name| module(name).unwrap().keyboard);
+    let rom = used.iter().any(|name| module(name).unwrap().rom);
+
+    let mut ports = Vec::new();
+    if clocked {
+        ports.push("input clk".to_string());
+    }
+    if keyboard {
+        ports.push("input [15:0] key".to_string());
+    }
+    let declaration = |direction: &str, port: &Port| match port.nets.len() {
+        1 => format!("{} {}", direction, port.name),
+        width => format!("{} [{}:0] {}", direction, width - 1, port.name),
+    };
+    ports.extend(design.inputs.iter().map(|port| declaration("input", port)));
+    ports.extend(
+        design
+            .outputs
+            .iter()
+            .map(|port| declaration("output", port)),
+    );
+
+    let mut text = format!("module {}", design.name);
+    if rom {
+        text.push_str(&format!(" #(parameter ROM_FILE = \"{}\")", rom_file));
+    }
+    text.push_str(" (\n");
+    text.push_str(&format!("    {}\n);\n", ports.join(",\n    ")));
+    text.push_str(&format!("    wire [{}:0] net;\n", design.nets - 1));
+    text.push_str(&format!("    assign net[{}] = 1'b0;\n", FALSE));
+    text.push_str(&format!("    assign net[{}] = 1'b1;\n", TRUE));
+
+    // 何にも駆動されないネットは false（シミュレーターと同じ）
+    let mut driven = vec![false; design.nets];
+    driven[FALSE] = true;
+    driven[TRUE] = true;
+    for port in &design.inputs {
+        for (bit, &net) in port.nets.iter().enumerate() {
+            driven[net] = true;
+            let source = match port.nets.len() {
+                1 => port.name.clone(),
+                _ => format!("{}[{}]", port.name, bit),
+            };
+            text.push_str(&format!("    assign net[{}] = {};\n", net, source));
+        }
+    }
+    for gate in &design.gates {
+        for &net in gate.outputs.iter().flatten() {
+            driven[net] = true;
+        }
+    }
+    for (net, _) in driven.iter().enumerate().filter(|(_, driven)| !**driven) {
+        text.push_str(&format!("    assign net[{}] = 1'b0;\n", net));
+    }
+
+    for (index, (gate, chip)) in design.gates.iter().zip(&chips).enumerate() {
+        let module = module(&gate.chip).unwrap();
+        let mut connections = Vec::new();
+        if module.clocked {
+            connections.push(".clk(clk)".to_string());
+        }
+        if module.keyboard {
+            connections.push(".key(key)".to_string());
+        }
+        let pins = chip.inputs().iter().zip(&gate.inputs);
+        let pins = pins.chain(chip.outputs().iter().zip(&gate.outputs));
+        connections.extend(pins.map(|(pin, nets)| format!(".{}({})", pin.name, bits(nets))));
+        let parameters = if module.rom {
+            " #(.ROM_FILE(ROM_FILE))"
+        } else {
+            ""
+        };
+        text.push_str(&format!(
+            "    // {}\n    {}{} g{} ({});\n",
+            gate.path,
+            gate.chip,
+            parameters,
+            index,
+            connections.join(", ")
+        ));
+    }
+    for port in &design.outputs {
+        text.push_str(&format!(
+            "    assign {} = {};\n",
+            port.name,
+            bits(&port.nets)
+        ));
+    }
+    text.push_str("endmodule\n");
+
+    for name in used {
+        text.push('\n');
+        text.push_str(module(name).unwrap().source);
+    }
+    Ok(text)
+}
+
+// 上位ビットから並べた net の連結
+fn bits(nets: &[Net]) -> String {
+    if let [net] = nets {
+        return format!("net[{}]", net);
+    }
+    let bits: Vec<String> = nets
+        .iter()
+        .rev()
+        .map(|net| format!("net[{}]", net))
+        .collect();
+    format!("{{{}}}", bits.join(", "))
+}
+
+fn add_module(used: &mut BTreeSet<&'static str>, name: &str) -> Result<()> {
+    let Some(module) = module(name) else {
+        bail!("Built-in chip '{}' has no Verilog equivalent", name);
+    };
+    if used.insert(module.name) {
+        for dependency in module.uses {
+            add_module(used, dependency)?;
+        }
+    }
+    Ok(())
+}
+
+fn module(name: &str) -> Option<&'static Module> {
+    MODULES.iter().find(|module| module.name == name)
+}
+
+// 組み込みチップの Verilog。ポートは builtin の inputs()、outputs() と同じ名前
+struct Module {
+    name: &'static str,
+    // clk を受け取る
+    clocked: bool,
+    // key を受け取る
+    keyboard: bool,
+    // ROM_FILE を受け取る
+    rom: bool,
+    // 中で使うほかのモジュール
+    uses: &'static [&'static str],
+    source: &'static str,
+}
+
+const fn logic(name: &'static str, source: &'static str) -> Module {
+    Module {
+        name,
+        clocked: false,
+        keyboard: false,
+        rom: false,
+        uses: &[],
+        source,
+    }
+}
+
+const fn sequential(name: &'static str, source: &'static str) -> Module {
+    Module {
+        clocked: true,
+        ..logic(name, source)
+    }
+}
+
+const MODULES: &[Module] = &[
+    logic(
+        "Nand",
+        "module Nand (input a, input b, output out);
+    assign out = ~(a & b);
+endmodule
+",
+    ),
+    logic(
+        "Not",
+        "module Not (input in, output out);
+    assign out = ~in;
+endmodule
+",
+    ),
+    logic(
+        "And",
+        "module And (input a, input b, output out);
+    assign out = a & b;
+endmodule
+",
+    ),
+    logic(
+        "Or",
+        "module Or (input a, input b, output out);
+    assign out = a | b;
+endmodule
+",
+    ),
+    logic(
+        "Xor",
+        "module Xor (input a, input b, output out);
+    assign out = a ^ b;
+endmodule
+",
+    ),
+    logic(
+        "Mux",
+        "module Mux (input a, input b, input sel, output out);
+    assign out = sel ? b : a;
+endmodule
+",
+    ),
+    logic(
+        "DMux",
+        "module DMux (input in, input sel, output a, output b);
+    assign a = sel ? 1'b0 : in;
+    assign b = sel ? in : 1'b0;
+endmodule
+",
+    ),
+    logic(
+        "Not16",
+        "module Not16 (input [15:0] in, output [15:0] out);
+    assign out = ~in;
+endmodule
+",
+    ),
+    logic(
+        "And16",
+        "module And16 (input [15:0] a, input [15:0] b, output [15:0] out);
+    assign out = a & b;
+endmodule
+",
+    ),
+    logic(
+        "Or16",
+        "module Or16 (input [15:0] a, input [15:0] b, output [15:0] out);
+    assign out = a | b;
+endmodule
+",
+    ),
+    logic(
+        "Mux16",
+        "module Mux16 (input [15:0] a, input [15:0] b, input sel, output [15:0] out);
+    assign out = sel ? b : a;
+endmodule
+",
+    ),
+    logic(
+        "Or8Way",
+        "module Or8Way (input [7:0] in, output out);
+    assign out = |in;
+endmodule
+",
+    ),
+    logic(
+        "Mux4Way16",
+        "module Mux4Way16 (
+    input [15:0] a, input [15:0] b, input [15:0] c, input [15:0] d,
+    input [1:0] sel, output [15:0] out
+);
+    assign out = sel[1] ? (sel[0] ? d : c) : (sel[0] ? b : a);
+endmodule
+",
+    ),
+    logic(
+        "Mux8Way16",
+        "module Mux8Way16 (
+    input [15:0] a, input [15:0] b, input [15:0] c, input [15:0] d,
+    input [15:0] e, input [15:0] f, input [15:0] g, input [15:0] h,
+    input [2:0] sel, output [15:0] out
+);
+    assign out = sel[2]
+        ? (sel[1] ? (sel[0] ? h : g) : (sel[0] ? f : e))
+        : (sel[1] ? (sel[0] ? d : c) : (sel[0] ? b : a));
+endmodule
+",
+    ),
+    logic(
+        "DMux4Way",
+        "module DMux4Way (input in, input [1:0] sel, output a, output b, output c, output d);
+    assign {d, c, b, a} = {3'b000, in} << sel;
+endmodule
+",
+    ),
+    logic(
+        "DMux8Way",
+        "module DMux8Way (
+    input in, input [2:0] sel,
+    output a, output b, output c, output d, output e, output f, output g, output h
+);
+    assign {h, g, f, e, d, c, b, a} = {7'b0000000, in} << sel;
+endmodule
+",
+    ),
+    logic(
+        "HalfAdder",
+        "module HalfAdder (input a, input b, output sum, output carry);
+    assign {carry, sum} = a + b;
+endmodule
+",
+    ),
+    logic(
+        "FullAdder",
+        "module FullAdder (input a, input b, input c, output sum, output carry);
+    assign {carry, sum} = a + b + c;
+endmodule
+",
+    ),
+    logic(
+        "Add16",
+        "module Add16 (input [15:0] a, input [15:0] b, output [15:0] out);
+    assign out = a + b;
+endmodule
+",
+    ),
+    logic(
+        "Inc16",
+        "module Inc16 (input [15:0] in, output [15:0] out);
+    assign out = in + 16'd1;
+endmodule
+",
+    ),
+    logic(
+        "ALU",
+        "module ALU (
+    input [15:0] x, input [15:0] y,
+    input zx, input nx, input zy, input ny, input f, input no,
+    output [15:0] out, output zr, output ng
+);
+    wire [15:0] x1 = zx ? 16'd0 : x;
+    wire [15:0] x2 = nx ? ~x1 : x1;
+    wire [15:0] y1 = zy ? 16'd0 : y;
+    wire [15:0] y2 = ny ? ~y1 : y1;
+    wire [15:0] result = f ? x2 + y2 : x2 & y2;
+    assign out = no ? ~result : result;
+    assign zr = out == 16'd0;
+    assign ng = out[15];
+endmodule
+",
+    ),
+    sequential(
+        "DFF",
+        "module DFF (input clk, input in, output reg out);
+    initial out = 1'b0;
+    always @(posedge clk) out <= in;
+endmodule
+",
+    ),
+    sequential(
+        "Bit",
+        "module Bit (input clk, input in, input load, output reg out);
+    initial out = 1'b0;
+    always @(posedge clk) if (load) out <= in;
+endmodule
+",
+    ),
+    sequential(
+        "Register",
+        "module Register (input clk, input [15:0] in, input load, output reg [15:0] out);
+    initial out = 16'd0;
+    always @(posedge clk) if (load) out <= in;
+endmodule
+",
+    ),
+    sequential(
+        "ARegister",
+        "module ARegister (input clk, input [15:0] in, input load, output reg [15:0] out);
+    initial out = 16'd0;
+    always @(posedge clk) if (load) out <= in;
+endmodule
+",
+    ),
+    sequential(
+        "DRegister",
+        "module DRegister (input clk, input [15:0] in, input load, output reg [15:0] out);
+    initial out = 16'd0;
+    always @(posedge clk) if (load) out <= in;
+endmodule
+",
+    ),
+    sequential(
+        "PC",
+        "module PC (
+    input clk, input [15:0] in, input load, input inc, input reset,
+    output reg [15:0] out
+);
+    initial out = 16'd0;
+    always @(posedge clk)
+        if (reset) out <= 16'd0;
+        else if (load) out <= in;
+        else if (inc) out <= out + 16'd1;
+endmodule
+",
+    ),
+    sequential(
+        "RAM8",
+        "module RAM8 (input clk, input [15:0] in, input load, input [2:0] address, output [15:0] out);
+    reg [15:0] words [0:7];
+    integer i;
+    initial for (i = 0; i < 8; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    sequential(
+        "RAM64",
+        "module RAM64 (input clk, input [15:0] in, input load, input [5:0] address, output [15:0] out);
+    reg [15:0] words [0:63];
+    integer i;
+    initial for (i = 0; i < 64; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    sequential(
+        "RAM512",
+        "module RAM512 (input clk, input [15:0] in, input load, input [8:0] address, output [15:0] out);
+    reg [15:0] words [0:511];
+    integer i;
+    initial for (i = 0; i < 512; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    sequential(
+        "RAM4K",
+        "module RAM4K (input clk, input [15:0] in, input load, input [11:0] address, output [15:0] out);
+    reg [15:0] words [0:4095];
+    integer i;
+    initial for (i = 0; i < 4096; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    sequential(
+        "RAM16K",
+        "module RAM16K (input clk, input [15:0] in, input load, input [13:0] address, output [15:0] out);
+    reg [15:0] words [0:16383];
+    integer i;
+    initial for (i = 0; i < 16384; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    sequential(
+        "Screen",
+        "module Screen (input clk, input [15:0] in, input load, input [12:0] address, output [15:0] out);
+    reg [15:0] words [0:8191];
+    integer i;
+    initial for (i = 0; i < 8192; i = i + 1) words[i] = 16'd0;
+    assign out = words[address];
+    always @(posedge clk) if (load) words[address] <= in;
+endmodule
+",
+    ),
+    Module {
+        rom: true,
+        ..logic(
+            "ROM32K",
+            "module ROM32K #(parameter ROM_FILE = \"rom.hack\") (input [14:0] address, output [15:0] out);
+    reg [15:0] words [0:32767];
+    integer i;
+    initial begin
+        for (i = 0; i < 32768; i = i + 1) words[i] = 16'd0;
+        $readmemb(ROM_FILE, words);
+    end
+    assign out = words[address];
+endmodule
+",
+        )
+    },
+    Module {
+        keyboard: true,
+        ..logic(
+            "Keyboard",
+            "module Keyboard (input [15:0] key, output [15:0] out);
+    assign out = key;
+endmodule
+",
+        )
+    },
+    Module {
+        keyboard: true,
+        ..sequential(
+            "Memory",
+            "module Memory (
+    input clk, input [15:0] key,
+    input [15:0] in, input load, input [14:0] address, output [15:0] out
+);
+    reg [15:0] ram [0:16383];
+    reg [15:0] screen [0:8191];
+    integer i;
+    initial begin
+        for (i = 0; i < 16384; i = i + 1) ram[i] = 16'd0;
+        for (i = 0; i < 8192; i = i + 1) screen[i] = 16'd0;
+    end
+    assign out = !address[14] ? ram[address[13:0]]
+        : !address[13] ? screen[address[12:0]]
+        : address == 15'h6000 ? key : 16'd0;
+    always @(posedge clk)
+        if (load && !address[14]) ram[address[13:0]] <= in;
+        else if (load && !address[13]) screen[address[12:0]] <= in;
+endmodule
+",
+        )
+    },
+    Module {
+        uses: &["ALU"],
+        ..sequential(
+            "CPU",
+            "module CPU (
+    input clk, input [15:0] inM, input [15:0] instruction, input reset,
+    output [15:0] outM, output writeM, output [14:0] addressM, output [14:0] pc
+);
+    reg [15:0] a = 16'd0, d = 16'd0, counter = 16'd0;
+    wire compute = instruction[15];
+    wire zr, ng;
+    ALU alu (
+        .x(d), .y(instruction[12] ? inM : a),
+        .zx(instruction[11]), .nx(instruction[10]), .zy(instruction[9]),
+        .ny(instruction[8]), .f(instruction[7]), .no(instruction[6]),
+        .out(outM), .zr(zr), .ng(ng)
+    );
+    wire jump = compute
+        && ((instruction[2] && ng) || (instruction[1] && zr) || (instruction[0] && !zr && !ng));
+    assign writeM = compute && instruction[3];
+    assign addressM = a[14:0];
+    assign pc = counter[14:0];
+    always @(posedge clk) begin
+        if (!compute) a <= instruction;
+        else if (instruction[5]) a <= outM;
+        if (compute && instruction[4]) d <= outM;
+        if (reset) counter <= 16'd0;
+        else if (jump) counter <= a;
+        else counter <= counter + 16'd1;
+    end
+endmodule
+",
+        )
+    },
+    Module {
+        keyboard: true,
+        rom: true,
+        uses: &["CPU", "Memory", "ROM32K"],
+        ..sequential(
+            "Computer",
+            "module Computer #(parameter ROM_FILE = \"rom.hack\") (
+    input clk, input [15:0] key, input reset
+);
+    wire [15:0] inM, instruction, outM;
+    wire writeM;
+    wire [14:0] addressM, pc;
+    ROM32K #(.ROM_FILE(ROM_FILE)) rom (.address(pc), .out(instruction));
+    CPU cpu (
+        .clk(clk), .inM(inM), .instruction(instruction), .reset(reset),
+        .outM(outM), .writeM(writeM), .addressM(addressM), .pc(pc)
+    );
+    Memory memory (
+        .clk(clk), .key(key), .in(outM), .load(writeM), .address(addressM), .out(inM)
+    );
+endmodule
+",
+        )
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        elaborate::{Library, elaborate},
+        parser::parse,
+    };
+    use std::path::PathBuf;
+
+    fn design(source: &str) -> Design {
+        let chip = parse(source).unwrap();
+        let name = chip.name.name.clone();
+        let mut library = Library::new(None);
+        library
+            .insert(chip, PathBuf::from(format!("{}.hdl", name)))
+            .unwrap();
+        elaborate(&mut library, &name).unwrap()
+    }
+
+    #[test]
+    fn test_verilog() {
+        let bit = design(
+            "CHIP MyBit {
+                IN in, load;
+                OUT out;
+                PARTS:
+                Mux(a=dff, b=in, sel=load, out=next);
+                DFF(in=next, out=dff, out=out);
+            }",
+        );
+        let text = verilog(&bit, DEFAULT_ROM_FILE).unwrap();
+        let top: Vec<&str> = text
+            .lines()
+            .take_while(|&line| line != "endmodule")
+            .collect();
+        assert_eq!(
+            top,
+            [
+                "module MyBit (",
+                "    input clk,",
+                "    input in,",
+                "    input load,",
+                "    output out",
+                ");",
+                "    wire [5:0] net;",
+                "    assign net[0] = 1'b0;",
+                "    assign net[1] = 1'b1;",
+                "    assign net[2] = in;",
+                "    assign net[3] = load;",
+                "    // MyBit/Mux[0]",
+                "    Mux g0 (.a(net[4]), .b(net[2]), .sel(net[3]), .out(net[5]));",
+                "    // MyBit/DFF[1]",
+                "    DFF g1 (.clk(clk), .in(net[5]), .out(net[4]));",
+                "    assign out = net[4];",
+            ]
+        );
+        // 使った組み込みチップだけを名前順に付ける
+        let modules: Vec<&str> = text
+            .lines()
+            .filter(|line| line.starts_with("module "))
+            .collect();
+        assert_eq!(
+            modules,
+            [
+                "module MyBit (",
+                "module DFF (input clk, input in, output reg out);",
+                "module Mux (input a, input b, input sel, output out);",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verilog_computer() {
+        let computer = design("CHIP Top { IN reset; PARTS: Computer(reset=reset); }");
+        let text = verilog(&computer, "Pong.hack").unwrap();
+        assert!(text.starts_with(
+            "module Top #(parameter ROM_FILE = \"Pong.hack\") (\n    input clk,\n    input [15:0] key,\n    input reset\n);"
+        ));
+        assert!(text.contains(
+            "Computer #(.ROM_FILE(ROM_FILE)) g0 (.clk(clk), .key(key), .reset(net[2]));"
+        ));
+        for module in ["ALU", "CPU", "Memory", "ROM32K"] {
+            assert!(text.contains(&format!("\nmodule {} ", module)));
+        }
+
+        let error = verilog(
+            &design("CHIP Top { IN and; OUT out; PARTS: Not(in=and, out=out); }"),
+            DEFAULT_ROM_FILE,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Pin 'and' of 'Top' cannot be used as a Verilog port name"
+        );
+    }
+}
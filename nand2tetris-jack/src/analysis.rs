@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::*,
+    diagnostic::Diagnostic,
+    symbols::{Kind, SymbolTable},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub kind: SubroutineKind,
+    pub parameters: usize,
+}
+
+use SubroutineKind::{Constructor as C, Function as F, Method as M};
+
+// Jack OS の API（クラス, 名前, 種類, 引数の数）
+const OS_API: &[(&str, &str, SubroutineKind, usize)] = &[
+    ("Math", "init", F, 0),
+    ("Math", "abs", F, 1),
+    ("Math", "multiply", F, 2),
+    ("Math", "divide", F, 2),
+    ("Math", "min", F, 2),
+    ("Math", "max", F, 2),
+    ("Math", "sqrt", F, 1),
+    ("String", "new", C, 1),
+    ("String", "dispose", M, 0),
+    ("String", "length", M, 0),
+    ("String", "charAt", M, 1),
+    ("String", "setCharAt", M, 2),
+    ("String", "appendChar", M, 1),
+    ("String", "eraseLastChar", M, 0),
+    ("String", "intValue", M, 0),
+    ("String", "setInt", M, 1),
+    ("String", "backSpace", F, 0),
+    ("String", "doubleQuote", F, 0),
+    ("String", "newLine", F, 0),
+    ("Array", "new", F, 1),
+    ("Array", "dispose", M, 0),
+    ("Output", "init", F, 0),
+    ("Output", "moveCursor", F, 2),
+    ("Output", "printChar", F, 1),
+    ("Output", "printString", F, 1),
+    ("Output", "printInt", F, 1),
+    ("Output", "println", F, 0),
+    ("Output", "backSpace", F, 0),
+    ("Screen", "init", F, 0),
+    ("Screen", "clearScreen", F, 0),
+    ("Screen", "setColor", F, 1),
+    ("Screen", "drawPixel", F, 2),
+    ("Screen", "drawLine", F, 4),
+    ("Screen", "drawRectangle", F, 4),
+    ("Screen", "drawCircle", F, 3),
+    ("Keyboard", "init", F, 0),
+    ("Keyboard", "keyPressed", F, 0),
+    ("Keyboard", "readChar", F, 0),
+    ("Keyboard", "readLine", F, 1),
+    ("Keyboard", "readInt", F, 1),
+    ("Memory", "init", F, 0),
+    ("Memory", "peek", F, 1),
+    ("Memory", "poke", F, 2),
+    ("Memory", "alloc", F, 1),
+    ("Memory", "deAlloc", F, 1),
+    ("Sys", "init", F, 0),
+    ("Sys", "halt", F, 0),
+    ("Sys", "error", F, 1),
+    ("Sys", "wait", F, 1),
+];
+
+// 呼び出しを確かめられるクラス。プログラムのクラスは同名の OS クラスを置き換える
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    classes: HashMap<String, HashMap<String, Signature>>,
+}
+
+impl Signatures {
+    pub fn new(classes: &[Class]) -> Self {
+        let mut signatures = Signatures::default();
+        for &(class, name, kind, parameters) in OS_API {
+            signatures
+                .classes
+                .entry(class.to_string())
+                .or_default()
+                .insert(name.to_string(), Signature { kind, parameters });
+        }
+        for class in classes {
+            let subroutines = class
+                .subroutines
+                .iter()
+                .map(|subroutine| {
+                    (
+                        subroutine.name.name.clone(),
+                        Signature {
+                            kind: subroutine.kind,
+                            parameters: subroutine.parameters.len(),
+                        },
+                    )
+                })
+                .collect();
+            signatures
+                .classes
+                .insert(class.name.name.clone(), subroutines);
+        }
+        signatures
+    }
+
+    pub fn get(&self, class: &str, name: &str) -> Option<&Signature> {
+        self.classes.get(class)?.get(name)
+    }
+
+    pub fn is_known(&self, class: &str) -> bool {
+        self.classes.contains_key(class)
+    }
+}
+
+// 未定義の変数、存在しないサブルーチンの呼び出し、引数の数の違い、
+// void と戻り値の食い違い、関数からのフィールドや this の使用を調べる
+pub fn check_class(class: &Class, signatures: &Signatures) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        class_name: &class.name.name,
+        signatures,
+        symbols: SymbolTable::new(),
+        subroutine: None,
+        statement: Position::default(),
+        diagnostics: Vec::new(),
+    };
+    checker.class(class);
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    class_name: &'a str,
+    signatures: &'a Signatures,
+    symbols: SymbolTable,
+    subroutine: Option<&'a Subroutine>,
+    // 今の文の位置。位置を持たない this の報告に使う
+    statement: Position,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, position: Position, message: String) {
+        self.diagnostics.push(Diagnostic::error(position, message));
+    }
+
+    // anyhow のエラーの "Line L, column C: " はここで付け直す
+    fn error_from(&mut self, position: Position, error: anyhow::Error) {
+        let text = error.to_string();
+        let message = match text.split_once(": ") {
+            Some((prefix, message)) if prefix.starts_with("Line ") => message.to_string(),
+            _ => text,
+        };
+        self.error(position, message);
+    }
+
+    fn function_name(&self) -> String {
+        let name = self.subroutine.map_or("", |s| s.name.name.as_str());
+        format!("{}.{}", self.class_name, name)
+    }
+
+    fn in_function(&self) -> bool {
+        self.subroutine
+            .is_some_and(|s| s.kind == SubroutineKind::Function)
+    }
+
+    fn class(&mut self, class: &'a Class) {
+        for var in &class.vars {
+            let kind = match var.kind {
+                ClassVarKind::Static => Kind::Static,
+                ClassVarKind::Field => Kind::Field,
+            };
+            for name in &var.names {
+                if let Err(e) = self.symbols.define(name, &var.ty, kind) {
+                    self.error_from(name.position, e);
+                }
+            }
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+    }
+
+    fn subroutine(&mut self, subroutine: &'a Subroutine) {
+        self.subroutine = Some(subroutine);
+        // 重複した名前があっても、残りの名前で続ける
+        self.symbols
+            .start_subroutine(self.class_name, subroutine.kind);
+        for parameter in &subroutine.parameters {
+            if let Err(e) = self
+                .symbols
+                .define(&parameter.name, &parameter.ty, Kind::Argument)
+            {
+                self.error_from(parameter.name.position, e);
+            }
+        }
+        for var in &subroutine.locals {
+            for name in &var.names {
+                if let Err(e) = self.symbols.define(name, &var.ty, Kind::Var) {
+                    self.error_from(name.position, e);
+                }
+            }
+        }
+        self.statements(&subroutine.body);
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.statement = statement.position;
+        match &statement.kind {
+            StatementKind::Let { name, index, value } => {
+                self.variable(name);
+                if let Some(index) = index {
+                    self.expression(index);
+                }
+                self.expression(value);
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statements(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statements(else_branch);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.expression(condition);
+                self.statements(body);
+            }
+            StatementKind::Do(call) => self.call(call),
+            StatementKind::Error => {}
+            StatementKind::Return(value) => {
+                let void = self.subroutine.is_some_and(|s| s.return_type.is_none());
+                match value {
+                    Some(value) => {
+                        self.expression(value);
+                        if void {
+                            let message = format!(
+                                "Void subroutine '{}' returns a value",
+                                self.function_name()
+                            );
+                            self.error(statement.position, message);
+                        }
+                    }
+                    None if !void => {
+                        let message =
+                            format!("Subroutine '{}' must return a value", self.function_name());
+                        self.error(statement.position, message);
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    fn variable(&mut self, name: &Identifier) {
+        match self.symbols.lookup(&name.name) {
+            None => self.error(name.position, format!("Undefined variable '{}'", name.name)),
+            Some(symbol) if symbol.kind == Kind::Field && self.in_function() => {
+                let message = format!(
+                    "Field '{}' cannot be used in function '{}'",
+                    name.name,
+                    self.function_name()
+                );
+                self.error(name.position, message);
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.term(&expression.term);
+        for (_, term) in &expression.rest {
+            self.term(term);
+        }
+    }
+
+    fn term(&mut self, term: &Term) {
+        match term {
+            Term::Integer(_) | Term::String(_) | Term::Error(_) => {}
+            Term::Keyword(KeywordConstant::This) if self.in_function() => {
+                let message = format!(
+                    "'this' cannot be used in function '{}'",
+                    self.function_name()
+                );
+                self.error(self.statement, message);
+            }
+            Term::Keyword(_) => {}
+            Term::Variable(name) => self.variable(name),
+            Term::Index(name, index) => {
+                self.variable(name);
+                self.expression(index);
+            }
+            Term::Call(call) => self.call(call),
+            Term::Parenthesized(expression) => self.expression(expression),
+            Term::Unary(_, term) => self.term(term),
+        }
+    }
+
+    fn call(&mut self, call: &SubroutineCall) {
+        for argument in &call.arguments {
+            self.expression(argument);
+        }
+
+        // 呼び出すクラスと、オブジェクトを渡すかどうか
+        let (class_name, on_object) = match &call.receiver {
+            Some(receiver) => match self.symbols.lookup(&receiver.name) {
+                Some(symbol) => {
+                    let Type::Class(class_name) = symbol.ty.clone() else {
+                        let message = format!(
+                            "Cannot call '{}' on '{}' of type {}",
+                            call.name.name,
+                            receiver.name,
+                            symbol.ty.name()
+                        );
+                        self.error(call.name.position, message);
+                        return;
+                    };
+                    self.variable(receiver);
+                    (class_name, true)
+                }
+                None => (receiver.name.clone(), false),
+            },
+            None => (self.class_name.to_string(), true),
+        };
+        if !self.signatures.is_known(&class_name) {
+            return;
+        }
+        let Some(&signature) = self.signatures.get(&class_name, &call.name.name) else {
+            let message = format!(
+                "Class '{}' has no subroutine '{}'",
+                class_name, call.name.name
+            );
+            self.error(call.name.position, message);
+            return;
+        };
+
+        let full_name = format!("{}.{}", class_name, call.name.name);
+        let is_method = signature.kind == SubroutineKind::Method;
+        if is_method && call.receiver.is_none() && self.in_function() {
+            let message = format!(
+                "Method '{}' cannot be called from function '{}' without an object",
+                full_name,
+                self.function_name()
+            );
+            self.error(call.name.position, message);
+        } else if is_method && !on_object {
+            let message = format!(
+                "Method '{}' must be called on an object, not on the class",
+                full_name
+            );
+            self.error(call.name.position, message);
+        } else if !is_method && on_object && call.receiver.is_some() {
+            let message = format!(
+                "'{}' is not a method: call it as {}(...)",
+                full_name, full_name
+            );
+            self.error(call.name.position, message);
+        }
+
+        if call.arguments.len() != signature.parameters {
+            let message = format!(
+                "'{}' expects {} arguments but got {}",
+                full_name,
+                signature.parameters,
+                call.arguments.len()
+            );
+            self.error(call.name.position, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn check(sources: &[&str]) -> Vec<String> {
+        let classes: Vec<Class> = sources.iter().map(|s| parse(s).unwrap()).collect();
+        let signatures = Signatures::new(&classes);
+        classes
+            .iter()
+            .flat_map(|class| check_class(class, &signatures))
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let diagnostics = check(&[
+            "class Main {\n\
+             function void main() {\n\
+             var Point p; var String s;\n\
+             let p = Point.new(1);\n\
+             let s = \"x\";\n\
+             do Output.printInt(p.getX() + s.length());\n\
+             return;\n\
+             }\n\
+             }",
+            "class Point {\n\
+             field int x;\n\
+             constructor Point new(int ax) { let x = ax; return this; }\n\
+             method int getX() { do helper(); return x; }\n\
+             method void helper() { return; }\n\
+             }",
+        ]);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_semantic_errors() {
+        let diagnostics = check(&[
+            "class Main {\n\
+             field int count;\n\
+             function void main() {\n\
+             var Point p;\n\
+             let q = 1;\n\
+             let count = 2;\n\
+             do Point.draw();\n\
+             do p.getX(1);\n\
+             do Output.printInt();\n\
+             do tick();\n\
+             do Point.getX();\n\
+             return 5;\n\
+             }\n\
+             method int size() { return; }\n\
+             method void tick() { return; }\n\
+             function int self() { return this; }\n\
+             }",
+            "class Point {\n\
+             method int getX() { return 0; }\n\
+             }",
+        ]);
+        assert_eq!(
+            diagnostics,
+            [
+                "Line 5, column 5: Undefined variable 'q'",
+                "Line 6, column 5: Field 'count' cannot be used in function 'Main.main'",
+                "Line 7, column 10: Class 'Point' has no subroutine 'draw'",
+                "Line 8, column 6: 'Point.getX' expects 0 arguments but got 1",
+                "Line 9, column 11: 'Output.printInt' expects 1 arguments but got 0",
+                "Line 10, column 4: Method 'Main.tick' cannot be called from function 'Main.main' without an object",
+                "Line 11, column 10: Method 'Point.getX' must be called on an object, not on the class",
+                "Line 12, column 1: Void subroutine 'Main.main' returns a value",
+                "Line 14, column 21: Subroutine 'Main.size' must return a value",
+                "Line 16, column 23: 'this' cannot be used in function 'Main.self'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_class_is_not_checked() {
+        assert!(
+            check(&["class Main { function void f() { do Lib.anything(1, 2); return; } }"])
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,727 @@
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+use crate::{
+    ast::*,
+    optimize::Inline,
+    symbols::{Kind, Symbol, SymbolTable},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // 2 の累乗の掛け算を足し算にする
+    pub optimize: bool,
+    // 呼び出しを展開するサブルーチン（Class.name）
+    pub inline: HashMap<String, Inline>,
+}
+
+// クラス1つをコンパイルする
+pub fn compile_class(class: &Class, options: &Options) -> Result<Vec<String>> {
+    Ok(compile_class_with_lines(class, options)?.0)
+}
+
+// VM コマンドと、それぞれを生成した Jack の行
+pub fn compile_class_with_lines(
+    class: &Class,
+    options: &Options,
+) -> Result<(Vec<String>, Vec<usize>)> {
+    let symbols = SymbolTable::for_class(class)?;
+    let mut generator = CodeGenerator::new(&class.name.name, symbols);
+    generator.optimize = options.optimize;
+    generator.inline = options.inline.clone();
+    generator.subroutines = class
+        .subroutines
+        .iter()
+        .map(|subroutine| (subroutine.name.name.clone(), subroutine.kind))
+        .collect();
+    for subroutine in &class.subroutines {
+        generator.subroutine(subroutine)?;
+    }
+    Ok((generator.commands, generator.lines))
+}
+
+// 構文木から VM コマンドを1行ずつ作る
+pub struct CodeGenerator {
+    class_name: String,
+    symbols: SymbolTable,
+    commands: Vec<String>,
+    // コマンドごとの Jack の行と、今生成している行
+    lines: Vec<usize>,
+    line: usize,
+    // このクラスのサブルーチンの種類。name() の呼び出しで this を渡すかを決める
+    subroutines: HashMap<String, SubroutineKind>,
+    optimize: bool,
+    inline: HashMap<String, Inline>,
+    // 展開中の式では引数と this を temp 2 から読む
+    inlining: bool,
+    // 今のサブルーチン（Class.name）と、ラベルの通し番号
+    function: String,
+    if_count: usize,
+    while_count: usize,
+}
+
+impl CodeGenerator {
+    pub fn new(class_name: &str, symbols: SymbolTable) -> Self {
+        CodeGenerator {
+            class_name: class_name.to_string(),
+            symbols,
+            commands: Vec::new(),
+            lines: Vec::new(),
+            line: 0,
+            subroutines: HashMap::new(),
+            optimize: false,
+            inline: HashMap::new(),
+            inlining: false,
+            function: String::new(),
+            if_count: 0,
+            while_count: 0,
+        }
+    }
+
+    pub fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    pub fn into_commands(self) -> Vec<String> {
+        self.commands
+    }
+
+    fn emit(&mut self, command: String) {
+        self.commands.push(command);
+        self.lines.push(self.line);
+    }
+
+    fn lookup(&self, name: &Identifier) -> Result<&Symbol> {
+        match self.symbols.lookup(&name.name) {
+            Some(symbol) => Ok(symbol),
+            None => bail!(
+                "Line {}, column {}: Undefined variable '{}'",
+                name.position.line,
+                name.position.column,
+                name.name
+            ),
+        }
+    }
+
+    // VM 変換器はラベルを関数ごとに分けないので、関数名を付けて全体で一意にする
+    fn label(&self, name: &str, n: usize) -> String {
+        format!("{}.{}{}", self.function, name, n)
+    }
+
+    pub fn subroutine(&mut self, subroutine: &Subroutine) -> Result<()> {
+        self.symbols
+            .enter_subroutine(&self.class_name, subroutine)?;
+        self.function = format!("{}.{}", self.class_name, subroutine.name.name);
+        self.if_count = 0;
+        self.while_count = 0;
+        self.line = subroutine.name.position.line;
+
+        self.emit(format!(
+            "function {} {}",
+            self.function,
+            self.symbols.var_count(Kind::Var)
+        ));
+        match subroutine.kind {
+            // フィールドの数だけ確保して this にする
+            SubroutineKind::Constructor => {
+                self.emit(format!(
+                    "push constant {}",
+                    self.symbols.var_count(Kind::Field)
+                ));
+                self.emit("call Memory.alloc 1".to_string());
+                self.emit("pop pointer 0".to_string());
+            }
+            // argument 0 が this
+            SubroutineKind::Method => {
+                self.emit("push argument 0".to_string());
+                self.emit("pop pointer 0".to_string());
+            }
+            SubroutineKind::Function => {}
+        }
+        self.statements(&subroutine.body)
+    }
+
+    pub fn statements(&mut self, statements: &[Statement]) -> Result<()> {
+        // 入れ子の文が終わったら、外側の文の行に戻す（if の後半のラベルなど）
+        for statement in statements {
+            let line = std::mem::replace(&mut self.line, statement.position.line);
+            self.statement(statement)?;
+            self.line = line;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<()> {
+        match &statement.kind {
+            StatementKind::Let {
+                name,
+                index: None,
+                value,
+            } => {
+                self.expression(value)?;
+                let symbol = self.lookup(name)?;
+                let command = format!("pop {} {}", symbol.kind.segment(), symbol.index);
+                self.emit(command);
+            }
+            StatementKind::Let {
+                name,
+                index: Some(index),
+                value,
+            } => {
+                // アドレスを先に計算し、右辺を評価してから書く。
+                // 右辺が that を使ってもいいように値は temp 0 に逃がす
+                self.push_variable(name)?;
+                self.expression(index)?;
+                self.emit("add".to_string());
+                self.expression(value)?;
+                self.emit("pop temp 0".to_string());
+                self.emit("pop pointer 1".to_string());
+                self.emit("push temp 0".to_string());
+                self.emit("pop that 0".to_string());
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let n = self.if_count;
+                self.if_count += 1;
+                let else_label = self.label("IF_FALSE", n);
+                self.expression(condition)?;
+                self.emit("not".to_string());
+                self.emit(format!("if-goto {}", else_label));
+                self.statements(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        let end_label = self.label("IF_END", n);
+                        self.emit(format!("goto {}", end_label));
+                        self.emit(format!("label {}", else_label));
+                        self.statements(else_branch)?;
+                        self.emit(format!("label {}", end_label));
+                    }
+                    None => self.emit(format!("label {}", else_label)),
+                }
+            }
+            StatementKind::While { condition, body } => {
+                let n = self.while_count;
+                self.while_count += 1;
+                let (loop_label, end_label) =
+                    (self.label("WHILE_EXP", n), self.label("WHILE_END", n));
+                self.emit(format!("label {}", loop_label));
+                self.expression(condition)?;
+                self.emit("not".to_string());
+                self.emit(format!("if-goto {}", end_label));
+                self.statements(body)?;
+                self.emit(format!("goto {}", loop_label));
+                self.emit(format!("label {}", end_label));
+            }
+            StatementKind::Do(call) => {
+                // 戻り値は捨てる
+                self.call(call)?;
+                self.emit("pop temp 0".to_string());
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    // void でも何か返す決まり
+                    None => self.emit("push constant 0".to_string()),
+                }
+                self.emit("return".to_string());
+            }
+            StatementKind::Error => bail!(
+                "Line {}, column {}: Cannot compile a statement with a syntax error",
+                statement.position.line,
+                statement.position.column
+            ),
+        }
+        Ok(())
+    }
+
+    fn push_variable(&mut self, name: &Identifier) -> Result<()> {
+        let symbol = self.lookup(name)?.clone();
+        match (self.inlining, symbol.kind) {
+            (true, Kind::Argument) => self.emit(format!("push temp {}", 2 + symbol.index)),
+            (true, Kind::Field) => {
+                self.emit("push temp 2".to_string());
+                self.emit("pop pointer 1".to_string());
+                self.emit(format!("push that {}", symbol.index));
+            }
+            _ => self.emit(format!("push {} {}", symbol.kind.segment(), symbol.index)),
+        }
+        Ok(())
+    }
+
+    // Jack には優先順位がないので左から順に計算する
+    pub fn expression(&mut self, expression: &Expression) -> Result<()> {
+        self.term(&expression.term)?;
+        for (op, term) in &expression.rest {
+            if let (true, BinaryOp::Mul, Term::Integer(value @ (2 | 4 | 8 | 16))) =
+                (self.optimize, op, term)
+            {
+                self.double(value.trailing_zeros());
+                continue;
+            }
+            self.term(term)?;
+            self.emit(
+                match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Sub => "sub",
+                    BinaryOp::And => "and",
+                    BinaryOp::Or => "or",
+                    BinaryOp::Lt => "lt",
+                    BinaryOp::Gt => "gt",
+                    BinaryOp::Eq => "eq",
+                    BinaryOp::Mul => "call Math.multiply 2",
+                    BinaryOp::Div => "call Math.divide 2",
+                }
+                .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    // Math.multiply を呼ぶ代わりに、スタックの値を temp 1 に写して times 回足す
+    fn double(&mut self, times: u32) {
+        for _ in 0..times {
+            self.emit("pop temp 1".to_string());
+            self.emit("push temp 1".to_string());
+            self.emit("push temp 1".to_string());
+            self.emit("add".to_string());
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> Result<()> {
+        match term {
+            Term::Integer(value) => self.emit(format!("push constant {}", value)),
+            Term::String(text) => {
+                self.emit(format!("push constant {}", text.chars().count()));
+                self.emit("call String.new 1".to_string());
+                for c in text.chars() {
+                    self.emit(format!("push constant {}", c as u32));
+                    self.emit("call String.appendChar 2".to_string());
+                }
+            }
+            Term::Keyword(KeywordConstant::True) => {
+                self.emit("push constant 0".to_string());
+                self.emit("not".to_string());
+            }
+            Term::Keyword(KeywordConstant::False | KeywordConstant::Null) => {
+                self.emit("push constant 0".to_string())
+            }
+            Term::Keyword(KeywordConstant::This) if self.inlining => {
+                self.emit("push temp 2".to_string())
+            }
+            Term::Keyword(KeywordConstant::This) => self.emit("push pointer 0".to_string()),
+            Term::Variable(name) => self.push_variable(name)?,
+            Term::Index(name, index) => {
+                self.push_variable(name)?;
+                self.expression(index)?;
+                self.emit("add".to_string());
+                self.emit("pop pointer 1".to_string());
+                self.emit("push that 0".to_string());
+            }
+            Term::Call(call) => self.call(call)?,
+            Term::Parenthesized(expression) => self.expression(expression)?,
+            Term::Unary(op, term) => {
+                self.term(term)?;
+                self.emit(
+                    match op {
+                        UnaryOp::Neg => "neg",
+                        UnaryOp::Not => "not",
+                    }
+                    .to_string(),
+                );
+            }
+            Term::Error(position) => bail!(
+                "Line {}, column {}: Cannot compile an expression with a syntax error",
+                position.line,
+                position.column
+            ),
+        }
+        Ok(())
+    }
+
+    // obj.m(...) は obj を、このクラスのメソッドの m(...) は this を最初の引数にする。
+    // Class.f(...) はそのまま呼ぶ
+    fn call(&mut self, call: &SubroutineCall) -> Result<()> {
+        let (class_name, receiver) = match &call.receiver {
+            Some(receiver) => match self.symbols.lookup(&receiver.name) {
+                Some(symbol) => {
+                    let Type::Class(class_name) = &symbol.ty else {
+                        bail!(
+                            "Line {}, column {}: Cannot call '{}' on '{}' of type {}",
+                            call.name.position.line,
+                            call.name.position.column,
+                            call.name.name,
+                            receiver.name,
+                            symbol.ty.name()
+                        );
+                    };
+                    (
+                        class_name.clone(),
+                        Some(format!("push {} {}", symbol.kind.segment(), symbol.index)),
+                    )
+                }
+                None => (receiver.name.clone(), None),
+            },
+            None => {
+                let is_method = !matches!(
+                    self.subroutines.get(&call.name.name),
+                    Some(SubroutineKind::Function | SubroutineKind::Constructor)
+                );
+                (
+                    self.class_name.clone(),
+                    is_method.then(|| "push pointer 0".to_string()),
+                )
+            }
+        };
+
+        let mut count = call.arguments.len();
+        let has_receiver = receiver.is_some();
+        if let Some(receiver) = receiver {
+            self.emit(receiver);
+            count += 1;
+        }
+        for argument in &call.arguments {
+            self.expression(argument)?;
+        }
+        let name = format!("{}.{}", class_name, call.name.name);
+        match self.inline.get(&name) {
+            Some(inline) if self.can_inline(inline, &class_name, count, has_receiver) => {
+                let inline = inline.clone();
+                self.inline_call(&inline, count)?
+            }
+            _ => self.emit(format!("call {} {}", name, count)),
+        }
+        Ok(())
+    }
+
+    // 呼び出しの形が定義と合うときだけ展開する（合わなければ検査で報告済み）
+    fn can_inline(&self, inline: &Inline, class_name: &str, count: usize, method: bool) -> bool {
+        inline.symbols.var_count(Kind::Argument) as usize == count
+            && (inline.kind == SubroutineKind::Method) == method
+            && (!inline.uses_static || class_name == self.class_name)
+    }
+
+    // スタックに積んだ引数を temp に移し、呼ばれる側のシンボルで式を生成する
+    fn inline_call(&mut self, inline: &Inline, count: usize) -> Result<()> {
+        for index in (0..count).rev() {
+            self.emit(format!("pop temp {}", 2 + index));
+        }
+        let symbols = std::mem::replace(&mut self.symbols, inline.symbols.clone());
+        self.inlining = true;
+        let result = self.expression(&inline.body);
+        self.inlining = false;
+        self.symbols = symbols;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, tokenizer::tokenize};
+    use rstest::rstest;
+
+    fn compile_expression(source: &str) -> Result<Vec<String>> {
+        let class = crate::parser::parse(
+            "class Main { static int s; field int f; \
+             method void m(int a) { var int v; return; } }",
+        )
+        .unwrap();
+        let mut symbols = SymbolTable::for_class(&class).unwrap();
+        symbols
+            .enter_subroutine("Main", &class.subroutines[0])
+            .unwrap();
+
+        let expression = Parser::new(tokenize(source).unwrap()).expression().unwrap();
+        let mut generator = CodeGenerator::new("Main", symbols);
+        generator.expression(&expression)?;
+        Ok(generator.into_commands())
+    }
+
+    #[rstest]
+    #[case("7", &["push constant 7"])]
+    #[case("1 + 2 * 3", &["push constant 1", "push constant 2", "add", "push constant 3", "call Math.multiply 2"])]
+    #[case("1 + (2 * 3)", &["push constant 1", "push constant 2", "push constant 3", "call Math.multiply 2", "add"])]
+    #[case("-a / ~v", &["push argument 1", "neg", "push local 0", "not", "call Math.divide 2"])]
+    #[case("(s < f) & true", &["push static 0", "push this 0", "lt", "push constant 0", "not", "and"])]
+    #[case("(null = false) | this", &["push constant 0", "push constant 0", "eq", "push pointer 0", "or"])]
+    #[case("\"Hi\"", &["push constant 2", "call String.new 1", "push constant 72", "call String.appendChar 2", "push constant 105", "call String.appendChar 2"])]
+    #[case("v[a] + 1", &["push local 0", "push argument 1", "add", "pop pointer 1", "push that 0", "push constant 1", "add"])]
+    #[case("Math.max(a, 3) - a", &["push argument 1", "push constant 3", "call Math.max 2", "push argument 1", "sub"])]
+    fn test_expression(#[case] source: &str, #[case] expected: &[&str]) {
+        assert_eq!(compile_expression(source).unwrap(), expected);
+    }
+
+    fn compile(source: &str) -> Vec<String> {
+        compile_class(&crate::parser::parse(source).unwrap(), &Options::default()).unwrap()
+    }
+
+    #[test]
+    fn test_statements() {
+        let commands = compile(
+            "class Main {\n\
+             static int total;\n\
+             function int sum(int n) {\n\
+             var int i;\n\
+             let i = 0;\n\
+             while (i < n) {\n\
+             if (i = 3) { do Output.printInt(i); } else { let total = total + i; }\n\
+             if (false) { return 1; }\n\
+             let i = i + 1;\n\
+             }\n\
+             return total;\n\
+             }\n\
+             function void main() { do Main.sum(5); return; }\n\
+             }",
+        );
+        assert_eq!(
+            commands,
+            [
+                "function Main.sum 1",
+                "push constant 0",
+                "pop local 0",
+                "label Main.sum.WHILE_EXP0",
+                "push local 0",
+                "push argument 0",
+                "lt",
+                "not",
+                "if-goto Main.sum.WHILE_END0",
+                "push local 0",
+                "push constant 3",
+                "eq",
+                "not",
+                "if-goto Main.sum.IF_FALSE0",
+                "push local 0",
+                "call Output.printInt 1",
+                "pop temp 0",
+                "goto Main.sum.IF_END0",
+                "label Main.sum.IF_FALSE0",
+                "push static 0",
+                "push local 0",
+                "add",
+                "pop static 0",
+                "label Main.sum.IF_END0",
+                "push constant 0",
+                "not",
+                "if-goto Main.sum.IF_FALSE1",
+                "push constant 1",
+                "return",
+                "label Main.sum.IF_FALSE1",
+                "push local 0",
+                "push constant 1",
+                "add",
+                "pop local 0",
+                "goto Main.sum.WHILE_EXP0",
+                "label Main.sum.WHILE_END0",
+                "push static 0",
+                "return",
+                "function Main.main 0",
+                "push constant 5",
+                "call Main.sum 1",
+                "pop temp 0",
+                "push constant 0",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array_assignment() {
+        let commands = compile(
+            "class Main { function void f(Array a, Array b, int i) { let a[i + 1] = 7; return; } }",
+        );
+        assert_eq!(
+            commands[1..12],
+            [
+                "push argument 0",
+                "push argument 2",
+                "push constant 1",
+                "add",
+                "add",
+                "push constant 7",
+                "pop temp 0",
+                "pop pointer 1",
+                "push temp 0",
+                "pop that 0",
+                "push constant 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_array_access() {
+        // 右辺の b[j] が that を書き換えても、左辺のアドレスはスタックに残っている
+        let commands = compile(
+            "class Main { function void f(Array a, Array b, int i, int j) {\n\
+             let a[i] = b[a[j]];\n\
+             return; } }",
+        );
+        assert_eq!(
+            commands[1..18],
+            [
+                "push argument 0",
+                "push argument 2",
+                "add",
+                "push argument 1",
+                "push argument 0",
+                "push argument 3",
+                "add",
+                "pop pointer 1",
+                "push that 0",
+                "add",
+                "pop pointer 1",
+                "push that 0",
+                "pop temp 0",
+                "pop pointer 1",
+                "push temp 0",
+                "pop that 0",
+                "push constant 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_objects() {
+        let commands = compile(
+            "class Point {\n\
+             field int x, y;\n\
+             constructor Point new(int ax) { let x = ax; return this; }\n\
+             method int getX() { return x; }\n\
+             method int twice() { return getX() + Point.origin(); }\n\
+             function int origin() { var Point p; let p = Point.new(0); return p.getX(); }\n\
+             function int helper() { return origin(); }\n\
+             }",
+        );
+        let expected = [
+            "function Point.new 0",
+            "push constant 2",
+            "call Memory.alloc 1",
+            "pop pointer 0",
+            "push argument 0",
+            "pop this 0",
+            "push pointer 0",
+            "return",
+            "function Point.getX 0",
+            "push argument 0",
+            "pop pointer 0",
+            "push this 0",
+            "return",
+            "function Point.twice 0",
+            "push argument 0",
+            "pop pointer 0",
+            "push pointer 0",
+            "call Point.getX 1",
+            "call Point.origin 0",
+            "add",
+            "return",
+            "function Point.origin 1",
+            "push constant 0",
+            "call Point.new 1",
+            "pop local 0",
+            "push local 0",
+            "call Point.getX 1",
+            "return",
+            "function Point.helper 0",
+            "call Point.origin 0",
+            "return",
+        ];
+        assert_eq!(commands, expected);
+    }
+
+    #[test]
+    fn test_multiply_by_power_of_two() {
+        let class =
+            crate::parser::parse("class Main { function int f(int x) { return x * 4 * 3; } }")
+                .unwrap();
+        assert_eq!(
+            compile_class(
+                &class,
+                &Options {
+                    optimize: true,
+                    ..Options::default()
+                }
+            )
+            .unwrap(),
+            [
+                "function Main.f 0",
+                "push argument 0",
+                "pop temp 1",
+                "push temp 1",
+                "push temp 1",
+                "add",
+                "pop temp 1",
+                "push temp 1",
+                "push temp 1",
+                "add",
+                "push constant 3",
+                "call Math.multiply 2",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        let ball = crate::parser::parse(
+            "class Ball { field int x, y; static int count; \
+             method int getX() { return x; } \
+             method int right(int dx) { return x + dx; } \
+             function int total() { return count; } }",
+        )
+        .unwrap();
+        let main = crate::parser::parse(
+            "class Main { function int f(Ball b) { return b.right(b.getX()) + Ball.total(); } }",
+        )
+        .unwrap();
+        let options = Options {
+            optimize: true,
+            inline: crate::optimize::inline_candidates(&[ball, main.clone()]),
+        };
+        assert_eq!(
+            compile_class(&main, &options).unwrap(),
+            [
+                "function Main.f 0",
+                "push argument 0",
+                "push argument 0",
+                "pop temp 2",
+                "push temp 2",
+                "pop pointer 1",
+                "push that 0",
+                "pop temp 3",
+                "pop temp 2",
+                "push temp 2",
+                "pop pointer 1",
+                "push that 0",
+                "push temp 3",
+                "add",
+                // static は Ball のファイルにあるので展開しない
+                "call Ball.total 0",
+                "add",
+                "return",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_on_primitive() {
+        let class =
+            crate::parser::parse("class A { function void f(int n) { do n.g(); return; } }")
+                .unwrap();
+        assert_eq!(
+            compile_class(&class, &Options::default())
+                .unwrap_err()
+                .to_string(),
+            "Line 1, column 41: Cannot call 'g' on 'n' of type int"
+        );
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        assert_eq!(
+            compile_expression("a + b").unwrap_err().to_string(),
+            "Line 1, column 5: Undefined variable 'b'"
+        );
+    }
+}
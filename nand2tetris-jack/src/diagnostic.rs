@@ -0,0 +1,112 @@
+use anyhow::{Result, bail};
+use nand2tetris_core::{
+    diagnostic::{emit, header},
+    sarif::Finding,
+};
+use std::{fmt, path::Path};
+
+use crate::ast::Position;
+
+pub use nand2tetris_core::diagnostic::Severity;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub position: Position,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn error(position: Position, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            position,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(position: Position, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            position,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Main.jack: Line 3, column 5
+    pub fn location(&self, file: &Path) -> String {
+        format!(
+            "{}: Line {}, column {}",
+            file.display(),
+            self.position.line,
+            self.position.column
+        )
+    }
+
+    // --sarif に書く形
+    pub fn finding(&self, file: &Path) -> Finding {
+        Finding {
+            severity: self.severity,
+            file: file.display().to_string(),
+            line: self.position.line,
+            column: Some(self.position.column),
+            message: self.message.clone(),
+        }
+    }
+}
+
+// Line 3, column 5: Expected ';' but found '}'
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Line {}, column {}: ",
+            self.position.line, self.position.column
+        )?;
+        if self.severity == Severity::Warning {
+            f.write_str("warning: ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+fn sorted(diagnostics: &[Diagnostic]) -> Vec<&Diagnostic> {
+    let mut sorted: Vec<&Diagnostic> = diagnostics.iter().collect();
+    sorted.sort_by_key(|d| (d.position.line, d.position.column));
+    sorted
+}
+
+// 位置の順に並べ、ファイル名を付けて1行ずつにする
+pub fn format_diagnostics(file: &Path, diagnostics: &[Diagnostic]) -> String {
+    sorted(diagnostics)
+        .iter()
+        .map(|d| format!("{}: {}\n", file.display(), d))
+        .collect()
+}
+
+// 位置の順に、ほかのツールと同じ形で標準エラーに出す
+pub fn emit_diagnostics(file: &Path, diagnostics: &[Diagnostic]) {
+    for d in sorted(diagnostics) {
+        emit(
+            d.severity,
+            &header(d.severity, &d.location(file), &d.message),
+        );
+    }
+}
+
+// エラーがあれば全部を位置の順に1つのエラーにまとめる
+pub fn into_result<T>(value: T, diagnostics: &[Diagnostic]) -> Result<T> {
+    let errors: Vec<String> = sorted(diagnostics)
+        .iter()
+        .filter(|d| d.is_error())
+        .map(|d| d.to_string())
+        .collect();
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(value)
+}
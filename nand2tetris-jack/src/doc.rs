@@ -0,0 +1,306 @@
+use clap::ValueEnum;
+
+use crate::{
+    ast::{Class, Subroutine, SubroutineKind, Type},
+    tokenizer::escape_xml,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+impl DocFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DocFormat::Markdown => "md",
+            DocFormat::Html => "html",
+        }
+    }
+}
+
+// クラスの API。コメントは宣言の直前のもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassDoc {
+    pub name: String,
+    pub comment: Option<String>,
+    pub subroutines: Vec<SubroutineDoc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubroutineDoc {
+    pub kind: SubroutineKind,
+    pub return_type: Option<Type>,
+    pub name: String,
+    pub parameters: Vec<(Type, String)>,
+    pub comment: Option<String>,
+}
+
+impl ClassDoc {
+    pub fn new(class: &Class, source: &str) -> Self {
+        let lines: Vec<&str> = source.lines().collect();
+        ClassDoc {
+            name: class.name.name.clone(),
+            comment: leading_comment(&lines, class.name.position.line),
+            subroutines: class
+                .subroutines
+                .iter()
+                .map(|subroutine| SubroutineDoc::new(subroutine, &lines))
+                .collect(),
+        }
+    }
+
+    // コメントの最初の文（一覧に出す）
+    pub fn summary(&self) -> Option<&str> {
+        let comment = self.comment.as_deref()?;
+        let paragraph = comment.split("\n\n").next().unwrap_or_default();
+        let end = paragraph
+            .char_indices()
+            .zip(paragraph.chars().skip(1))
+            .find(|((_, c), next)| *c == '.' && next.is_whitespace())
+            .map_or(paragraph.len(), |((i, _), _)| i + 1);
+        Some(&paragraph[..end])
+    }
+}
+
+impl SubroutineDoc {
+    fn new(subroutine: &Subroutine, lines: &[&str]) -> Self {
+        SubroutineDoc {
+            kind: subroutine.kind,
+            return_type: subroutine.return_type.clone(),
+            name: subroutine.name.name.clone(),
+            parameters: subroutine
+                .parameters
+                .iter()
+                .map(|parameter| (parameter.ty.clone(), parameter.name.name.clone()))
+                .collect(),
+            comment: leading_comment(lines, subroutine.name.position.line),
+        }
+    }
+
+    // method void moveTo(int x, int y)。型の名前は ty で書く
+    pub fn signature(&self, ty: impl Fn(&Type) -> String) -> String {
+        let kind = match self.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        };
+        let return_type = match &self.return_type {
+            Some(return_type) => ty(return_type),
+            None => "void".to_string(),
+        };
+        let parameters: Vec<String> = self
+            .parameters
+            .iter()
+            .map(|(parameter, name)| format!("{} {}", ty(parameter), name))
+            .collect();
+        format!(
+            "{} {} {}({})",
+            kind,
+            return_type,
+            self.name,
+            parameters.join(", ")
+        )
+    }
+}
+
+// line 行目（1 始まり）の宣言の直前にある // の行か /* */ のブロック。空行を挟むと使わない
+fn leading_comment(lines: &[&str], line: usize) -> Option<String> {
+    let mut comment: Vec<String> = Vec::new();
+    let mut index = line.checked_sub(1)?;
+    // 宣言の前の行が /* */ の中かどうか
+    let mut in_block = false;
+    while index > 0 {
+        index -= 1;
+        let text = lines.get(index)?.trim();
+        if in_block {
+            in_block = !text.starts_with("/*");
+            comment.push(strip_block(text));
+        } else if let Some(text) = text.strip_prefix("//") {
+            comment.push(text.trim().to_string());
+        } else if text.ends_with("*/") {
+            in_block = !text.starts_with("/*");
+            comment.push(strip_block(text));
+        } else {
+            break;
+        }
+    }
+    comment.reverse();
+    // /** と */ だけの行で残った前後の空行を落とす
+    let text = comment.join("\n").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+// /** や * 、*/ を除いた行の中身
+fn strip_block(text: &str) -> String {
+    let text = text.strip_suffix("*/").unwrap_or(text);
+    let text = text.trim_start_matches('/').trim_start_matches('*');
+    text.trim().to_string()
+}
+
+// 一覧。classes は名前順
+pub fn render_index(title: &str, classes: &[ClassDoc], format: DocFormat) -> String {
+    let extension = format.extension();
+    match format {
+        DocFormat::Markdown => {
+            let mut out = format!("# {}\n\n| Class | Description |\n| --- | --- |\n", title);
+            for class in classes {
+                out += &format!(
+                    "| [{}]({}.{}) | {} |\n",
+                    class.name,
+                    class.name,
+                    extension,
+                    class.summary().unwrap_or_default().replace('\n', " ")
+                );
+            }
+            out
+        }
+        DocFormat::Html => {
+            let mut body = format!("<h1>{}</h1>\n<table>\n", escape_xml(title));
+            for class in classes {
+                body += &format!(
+                    "<tr><td><a href=\"{}.{}\"><code>{}</code></a></td><td>{}</td></tr>\n",
+                    class.name,
+                    extension,
+                    class.name,
+                    escape_xml(class.summary().unwrap_or_default())
+                );
+            }
+            body += "</table>\n";
+            html_page(title, &body)
+        }
+    }
+}
+
+// クラスのページ。known は他のページがあるクラスで、HTML では型からリンクする
+pub fn render_class(class: &ClassDoc, known: &[&str], format: DocFormat) -> String {
+    match format {
+        DocFormat::Markdown => {
+            let mut out = format!("# {}\n\n", class.name);
+            if let Some(comment) = &class.comment {
+                out += &format!("{}\n\n", comment);
+            }
+            for subroutine in &class.subroutines {
+                out += &format!(
+                    "## {}\n\n```jack\n{}\n```\n\n",
+                    subroutine.name,
+                    subroutine.signature(|ty| ty.name().to_string())
+                );
+                if let Some(comment) = &subroutine.comment {
+                    out += &format!("{}\n\n", comment);
+                }
+            }
+            out.truncate(out.trim_end().len() + 1);
+            out
+        }
+        DocFormat::Html => {
+            let link = |ty: &Type| match ty {
+                Type::Class(name) if known.contains(&name.as_str()) => {
+                    format!("<a href=\"{}.html\">{}</a>", name, name)
+                }
+                _ => ty.name().to_string(),
+            };
+            let mut body = format!(
+                "<p><a href=\"index.html\">Index</a></p>\n<h1>{}</h1>\n",
+                class.name
+            );
+            if let Some(comment) = &class.comment {
+                body += &paragraphs(comment);
+            }
+            for subroutine in &class.subroutines {
+                body += &format!(
+                    "<h2 id=\"{}\">{}</h2>\n<pre><code>{}</code></pre>\n",
+                    subroutine.name,
+                    subroutine.name,
+                    subroutine.signature(link)
+                );
+                if let Some(comment) = &subroutine.comment {
+                    body += &paragraphs(comment);
+                }
+            }
+            html_page(&class.name, &body)
+        }
+    }
+}
+
+// 空行で段落に分ける
+fn paragraphs(comment: &str) -> String {
+    comment
+        .split("\n\n")
+        .map(|paragraph| format!("<p>{}</p>\n", escape_xml(paragraph)))
+        .collect()
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+         </head>\n<body>\n{}</body>\n</html>\n",
+        escape_xml(title),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    const SOURCE: &str = "/**
+ * A square on the screen.
+ * Moves by 2 pixels.
+ *
+ * Call dispose when done.
+ */
+class Square {
+    field int x;
+
+    /** Constructs a square at (x, y). */
+    constructor Square new(int ax, int ay) {
+        return this;
+    }
+
+    // Draws the square.
+    // Does not clear the old one.
+    method void draw() {
+        return;
+    }
+
+    function Square copy(Square other) {
+        return other;
+    }
+}";
+
+    #[test]
+    fn test_class_doc() {
+        let doc = ClassDoc::new(&parse(SOURCE).unwrap(), SOURCE);
+        assert_eq!(
+            doc.comment.as_deref(),
+            Some("A square on the screen.\nMoves by 2 pixels.\n\nCall dispose when done.")
+        );
+        assert_eq!(doc.summary(), Some("A square on the screen."));
+        let comments: Vec<Option<&str>> = doc
+            .subroutines
+            .iter()
+            .map(|subroutine| subroutine.comment.as_deref())
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                Some("Constructs a square at (x, y)."),
+                Some("Draws the square.\nDoes not clear the old one."),
+                None,
+            ]
+        );
+
+        let markdown = render_class(&doc, &["Square"], DocFormat::Markdown);
+        assert!(
+            markdown.contains("## new\n\n```jack\nconstructor Square new(int ax, int ay)\n```")
+        );
+        let html = render_class(&doc, &["Square"], DocFormat::Html);
+        assert!(html.contains(
+            "<pre><code>function <a href=\"Square.html\">Square</a> copy(\
+             <a href=\"Square.html\">Square</a> other)</code></pre>"
+        ));
+    }
+}
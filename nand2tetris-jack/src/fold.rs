@@ -0,0 +1,251 @@
+// 構文木を受け取って作り直す走査。Visit と同じく既定の実装は同じ名前の関数で子を
+// 作り直すだけなので、書き換えたいメソッドだけ上書きする。文を消したり増やしたりするときは
+// fold_block を上書きする
+
+use crate::ast::*;
+
+pub trait Fold {
+    fn fold_class(&mut self, class: Class) -> Class {
+        fold_class(self, class)
+    }
+
+    fn fold_class_var_dec(&mut self, dec: ClassVarDec) -> ClassVarDec {
+        fold_class_var_dec(self, dec)
+    }
+
+    fn fold_subroutine(&mut self, subroutine: Subroutine) -> Subroutine {
+        fold_subroutine(self, subroutine)
+    }
+
+    fn fold_parameter(&mut self, parameter: Parameter) -> Parameter {
+        fold_parameter(self, parameter)
+    }
+
+    fn fold_var_dec(&mut self, dec: VarDec) -> VarDec {
+        fold_var_dec(self, dec)
+    }
+
+    fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        fold_block(self, statements)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold_statement(self, statement)
+    }
+
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold_expression(self, expression)
+    }
+
+    fn fold_term(&mut self, term: Term) -> Term {
+        fold_term(self, term)
+    }
+
+    fn fold_call(&mut self, call: SubroutineCall) -> SubroutineCall {
+        fold_call(self, call)
+    }
+
+    fn fold_type(&mut self, ty: Type) -> Type {
+        ty
+    }
+
+    fn fold_identifier(&mut self, identifier: Identifier) -> Identifier {
+        identifier
+    }
+}
+
+pub fn fold_class<F: Fold + ?Sized>(f: &mut F, class: Class) -> Class {
+    Class {
+        name: f.fold_identifier(class.name),
+        vars: class
+            .vars
+            .into_iter()
+            .map(|dec| f.fold_class_var_dec(dec))
+            .collect(),
+        subroutines: class
+            .subroutines
+            .into_iter()
+            .map(|subroutine| f.fold_subroutine(subroutine))
+            .collect(),
+    }
+}
+
+pub fn fold_class_var_dec<F: Fold + ?Sized>(f: &mut F, dec: ClassVarDec) -> ClassVarDec {
+    ClassVarDec {
+        kind: dec.kind,
+        ty: f.fold_type(dec.ty),
+        names: dec
+            .names
+            .into_iter()
+            .map(|name| f.fold_identifier(name))
+            .collect(),
+    }
+}
+
+pub fn fold_subroutine<F: Fold + ?Sized>(f: &mut F, subroutine: Subroutine) -> Subroutine {
+    Subroutine {
+        kind: subroutine.kind,
+        return_type: subroutine.return_type.map(|ty| f.fold_type(ty)),
+        name: f.fold_identifier(subroutine.name),
+        parameters: subroutine
+            .parameters
+            .into_iter()
+            .map(|parameter| f.fold_parameter(parameter))
+            .collect(),
+        locals: subroutine
+            .locals
+            .into_iter()
+            .map(|dec| f.fold_var_dec(dec))
+            .collect(),
+        body: f.fold_block(subroutine.body),
+    }
+}
+
+pub fn fold_parameter<F: Fold + ?Sized>(f: &mut F, parameter: Parameter) -> Parameter {
+    Parameter {
+        ty: f.fold_type(parameter.ty),
+        name: f.fold_identifier(parameter.name),
+    }
+}
+
+pub fn fold_var_dec<F: Fold + ?Sized>(f: &mut F, dec: VarDec) -> VarDec {
+    VarDec {
+        ty: f.fold_type(dec.ty),
+        names: dec
+            .names
+            .into_iter()
+            .map(|name| f.fold_identifier(name))
+            .collect(),
+    }
+}
+
+pub fn fold_block<F: Fold + ?Sized>(f: &mut F, statements: Vec<Statement>) -> Vec<Statement> {
+    statements
+        .into_iter()
+        .map(|statement| f.fold_statement(statement))
+        .collect()
+}
+
+pub fn fold_statement<F: Fold + ?Sized>(f: &mut F, statement: Statement) -> Statement {
+    let kind = match statement.kind {
+        StatementKind::Let { name, index, value } => StatementKind::Let {
+            name: f.fold_identifier(name),
+            index: index.map(|index| f.fold_expression(index)),
+            value: f.fold_expression(value),
+        },
+        StatementKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => StatementKind::If {
+            condition: f.fold_expression(condition),
+            then_branch: f.fold_block(then_branch),
+            else_branch: else_branch.map(|else_branch| f.fold_block(else_branch)),
+        },
+        StatementKind::While { condition, body } => StatementKind::While {
+            condition: f.fold_expression(condition),
+            body: f.fold_block(body),
+        },
+        StatementKind::Do(call) => StatementKind::Do(f.fold_call(call)),
+        StatementKind::Return(value) => {
+            StatementKind::Return(value.map(|value| f.fold_expression(value)))
+        }
+        StatementKind::Error => StatementKind::Error,
+    };
+    Statement {
+        kind,
+        position: statement.position,
+    }
+}
+
+pub fn fold_expression<F: Fold + ?Sized>(f: &mut F, expression: Expression) -> Expression {
+    Expression {
+        term: f.fold_term(expression.term),
+        rest: expression
+            .rest
+            .into_iter()
+            .map(|(op, term)| (op, f.fold_term(term)))
+            .collect(),
+    }
+}
+
+pub fn fold_term<F: Fold + ?Sized>(f: &mut F, term: Term) -> Term {
+    match term {
+        Term::Variable(name) => Term::Variable(f.fold_identifier(name)),
+        Term::Index(name, index) => {
+            Term::Index(f.fold_identifier(name), Box::new(f.fold_expression(*index)))
+        }
+        Term::Call(call) => Term::Call(f.fold_call(call)),
+        Term::Parenthesized(expression) => {
+            Term::Parenthesized(Box::new(f.fold_expression(*expression)))
+        }
+        Term::Unary(op, term) => Term::Unary(op, Box::new(f.fold_term(*term))),
+        Term::Integer(_) | Term::String(_) | Term::Keyword(_) | Term::Error(_) => term,
+    }
+}
+
+pub fn fold_call<F: Fold + ?Sized>(f: &mut F, call: SubroutineCall) -> SubroutineCall {
+    SubroutineCall {
+        receiver: call.receiver.map(|receiver| f.fold_identifier(receiver)),
+        name: f.fold_identifier(call.name),
+        arguments: call
+            .arguments
+            .into_iter()
+            .map(|argument| f.fold_expression(argument))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate, parser::parse};
+
+    // 変数 from を to にし、do Output.println(); を消す
+    struct Rewrite;
+
+    impl Fold for Rewrite {
+        fn fold_identifier(&mut self, mut identifier: Identifier) -> Identifier {
+            if identifier.name == "from" {
+                identifier.name = "to".to_string();
+            }
+            identifier
+        }
+
+        fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+            fold_block(self, statements)
+                .into_iter()
+                .filter(|statement| {
+                    !matches!(&statement.kind, StatementKind::Do(call) if call.name.name == "println")
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_fold_class() {
+        let class = parse(
+            "class Main {
+                function int f(int from) {
+                    if (from > 0) { do Output.println(); }
+                    return from + 1;
+                }
+            }",
+        )
+        .unwrap();
+        let expected = parse(
+            "class Main {
+                function int f(int to) {
+                    if (to > 0) { }
+                    return to + 1;
+                }
+            }",
+        )
+        .unwrap();
+        let folded = Rewrite.fold_class(class);
+        assert_eq!(
+            generate(&folded, &[], 0).unwrap(),
+            generate(&expected, &[], 0).unwrap()
+        );
+    }
+}
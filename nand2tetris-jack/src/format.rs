@@ -0,0 +1,311 @@
+// Jack のフォーマッター（jackfmt）
+//
+//   - インデントは 4 つの空白。{ は行の最後に置き、} は 1 行に 1 つ（} else { だけは続ける）
+//   - 文と宣言は 1 行に 1 つ。; の後で改行する
+//   - 2 項演算子の前後、, と キーワードの後に空白を 1 つ。単項の - と ~、. と括弧の内側には置かない
+//   - 空行は 1 行にまとめて残す。{ の直後と } の直前の空行は消す
+//
+// コメントは消さない。前の行にあるものはその位置に、トークンと同じ行の後ろにあるものはその後ろに置く。
+// 文の途中の改行は残し、続きの行は 1 段下げる。式の途中の // コメントの後も同じように改行する
+
+use anyhow::Result;
+
+use crate::{
+    parser,
+    tokenizer::{Comment, Keyword, Token, TokenKind, scan_lossless},
+};
+
+const INDENT: &str = "    ";
+
+// 構文エラーのあるソースは整形しない（読み飛ばした部分を壊さないため）
+pub fn format(source: &str) -> Result<String> {
+    parser::parse(source)?;
+    let (tokens, comments, _) = scan_lossless(source);
+    let mut formatter = Formatter::default();
+    let mut comments = comments.iter().peekable();
+    for (i, token) in tokens.iter().enumerate() {
+        while let Some(comment) =
+            comments.next_if(|comment| (comment.line, comment.column) < (token.line, token.column))
+        {
+            formatter.comment(comment);
+        }
+        formatter.token(token, i.checked_sub(1).map(|i| &tokens[i]));
+    }
+    for comment in comments {
+        formatter.comment(comment);
+    }
+    formatter.out.truncate(formatter.out.trim_end().len());
+    formatter.out.push('\n');
+    Ok(formatter.out)
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    indent: usize,
+    // いまの行に何か書いた
+    line_open: bool,
+    // 次のトークンの前で改行する（; { } の後と、// コメントの後）
+    break_before: bool,
+    // 文や宣言の途中（次の行は続きとして 1 段下げる）
+    in_statement: bool,
+    // 最後に書いたトークンかコメントの、元のソースの最後の行
+    last_line: usize,
+    // 最後に書いたのが { か（直後の空行は消す）
+    after_open: bool,
+    // 最後に書いたトークンが単項演算子か（後ろに空白を入れない）
+    after_unary: bool,
+    // 最後に書いたのが }（else はその後ろに続ける）
+    after_close: bool,
+    // 閉じていない ( の次の桁の、元のソースと出力での位置
+    parens: Vec<(usize, usize)>,
+}
+
+impl Formatter {
+    fn newline(&mut self) {
+        if self.line_open {
+            self.out.push('\n');
+            self.line_open = false;
+        }
+    }
+
+    // 元のソースで空行を挟んでいれば 1 行だけ残す
+    fn blank_line(&mut self, line: usize) {
+        if !self.out.is_empty() && !self.after_open && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+    }
+
+    fn start_line(&mut self, extra: usize) {
+        for _ in 0..self.indent + extra {
+            self.out.push_str(INDENT);
+        }
+        self.line_open = true;
+    }
+
+    // 文の続きの行。元のソースで ( の次の桁にそろえてあれば、そろえたままにする
+    fn continue_line(&mut self, column: usize) {
+        match self.parens.last() {
+            Some(&(source, output)) if source == column => {
+                self.out.push_str(&" ".repeat(output - 1));
+                self.line_open = true;
+            }
+            _ => self.start_line(1),
+        }
+    }
+
+    // いまの行の次の桁（1 始まり）
+    fn column(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.chars().count() + 1
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        if self.line_open && comment.line == self.last_line {
+            // 同じ行の後ろのコメント
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+            self.break_before |= !comment.is_block();
+        } else {
+            self.newline();
+            self.blank_line(comment.line);
+            self.start_line(usize::from(self.in_statement));
+            let mut lines = comment.text.lines();
+            self.out
+                .push_str(lines.next().unwrap_or_default().trim_end());
+            // /** の続きの * の行は 1 つ空白を空けてそろえる。それ以外の行は元のまま
+            for line in lines {
+                self.out.push('\n');
+                match line.trim_start() {
+                    rest if rest.starts_with('*') => {
+                        for _ in 0..self.indent {
+                            self.out.push_str(INDENT);
+                        }
+                        self.out.push(' ');
+                        self.out.push_str(rest.trim_end());
+                    }
+                    _ => self.out.push_str(line.trim_end()),
+                }
+            }
+            self.break_before = true;
+            self.after_open = false;
+        }
+        self.after_close = false;
+        self.last_line = comment.end_line;
+    }
+
+    fn token(&mut self, token: &Token, previous: Option<&Token>) {
+        let closing = token.kind == TokenKind::Symbol('}');
+        if closing {
+            self.indent = self.indent.saturating_sub(1);
+            self.break_before |= self.line_open;
+            self.after_open = false;
+        }
+        if self.after_close && token.kind == TokenKind::Keyword(Keyword::Else) {
+            self.break_before = false;
+        }
+        // 文の途中の改行は残す（{ は前の行に付ける）
+        if self.in_statement && token.line > self.last_line && token.kind != TokenKind::Symbol('{')
+        {
+            self.break_before = true;
+        }
+        if self.break_before || !self.line_open {
+            self.newline();
+            if !closing {
+                self.blank_line(token.line);
+            }
+            if self.in_statement {
+                self.continue_line(token.column);
+            } else {
+                self.start_line(0);
+            }
+        } else if let Some(previous) = previous
+            && space_between(previous, token, self.after_unary)
+        {
+            self.out.push(' ');
+        }
+        self.out.push_str(&source_text(token));
+        self.after_unary = is_unary(previous, token);
+        self.break_before = false;
+        self.after_open = false;
+        self.after_close = closing;
+        self.last_line = token.line;
+
+        match token.kind {
+            TokenKind::Symbol('(') => {
+                self.parens.push((token.column + 1, self.column()));
+                self.in_statement = true;
+            }
+            TokenKind::Symbol(')') => {
+                self.parens.pop();
+                self.in_statement = true;
+            }
+            TokenKind::Symbol('{') => {
+                self.indent += 1;
+                self.break_before = true;
+                self.in_statement = false;
+                self.after_open = true;
+            }
+            TokenKind::Symbol(';') => {
+                self.break_before = true;
+                self.in_statement = false;
+            }
+            // } の後は else だけが同じ行に続く
+            TokenKind::Symbol('}') => {
+                self.in_statement = false;
+                self.break_before = true;
+            }
+            _ => self.in_statement = true,
+        }
+    }
+}
+
+// 同じ行に並べるトークンの間に空白を入れるか。unary は previous が単項演算子
+fn space_between(previous: &Token, token: &Token, unary: bool) -> bool {
+    use TokenKind::{Keyword as K, Symbol as S};
+    match (&previous.kind, &token.kind) {
+        _ if unary => false,
+        (S('}'), K(Keyword::Else)) => true,
+        (_, S(';' | ',' | ')' | ']' | '.' | '[')) => false,
+        (S('(' | '[' | '.'), _) => false,
+        // 呼び出しの ( は名前に付ける。if ( と while ( は離す
+        (TokenKind::Identifier(_), S('(')) => false,
+        _ => true,
+    }
+}
+
+// ~ と、値の後でない -（2 項の - は ) ] か識別子、定数の後にある）
+fn is_unary(previous: Option<&Token>, token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Symbol('~') => true,
+        TokenKind::Symbol('-') => match previous.map(|previous| &previous.kind) {
+            None => true,
+            Some(TokenKind::Symbol(symbol)) => !matches!(symbol, ')' | ']'),
+            Some(TokenKind::Keyword(keyword)) => !matches!(
+                keyword,
+                Keyword::True | Keyword::False | Keyword::Null | Keyword::This
+            ),
+            Some(_) => false,
+        },
+        _ => false,
+    }
+}
+
+// 文字列定数は " で囲む
+fn source_text(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::StringConstant(text) => format!("\"{}\"", text),
+        _ => token.text(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os::OS_SOURCES;
+
+    #[test]
+    fn test_format() {
+        let source = "/** Main
+  * class */
+class  Main{
+   field int x,y; // pos
+
+
+   /* entry */
+   function void main(){
+      var int i;   var Array a;
+      let a=Array.new(10);let i=-1;
+      if(~(i<0)){let a[i+1]=-i*2;}
+      else{ do Output.printString(\"a  b\");}
+      while (i < 10)
+      {
+         let i = i -
+           -1;
+      }
+      do Screen.drawLine(x, y,
+                         x, 0);
+      return;
+   }
+}
+";
+        let expected = "/** Main
+ * class */
+class Main {
+    field int x, y; // pos
+
+    /* entry */
+    function void main() {
+        var int i;
+        var Array a;
+        let a = Array.new(10);
+        let i = -1;
+        if (~(i < 0)) {
+            let a[i + 1] = -i * 2;
+        } else {
+            do Output.printString(\"a  b\");
+        }
+        while (i < 10) {
+            let i = i -
+                -1;
+        }
+        do Screen.drawLine(x, y,
+                           x, 0);
+        return;
+    }
+}
+";
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert!(format("class Main { function void f() { let x = ; } }").is_err());
+    }
+
+    // 同梱の OS はこの形で書いてある
+    #[test]
+    fn test_os_is_formatted() {
+        for (name, source) in OS_SOURCES {
+            assert_eq!(&format(source).unwrap(), source, "{}.jack", name);
+        }
+    }
+}
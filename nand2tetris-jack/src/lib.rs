@@ -0,0 +1,103 @@
+pub mod analysis;
+pub mod ast;
+pub mod codegen;
+pub mod diagnostic;
+pub mod doc;
+pub mod fold;
+pub mod format;
+pub mod lint;
+pub mod lsp;
+pub mod optimize;
+pub mod os;
+pub mod parser;
+pub mod pass;
+pub mod project;
+pub mod source_map;
+pub mod symbols;
+pub mod tokenizer;
+pub mod visit;
+pub mod xml;
+
+use anyhow::{Context, Result, bail, ensure};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// .jack のソースを VM コードにする。他のクラスの呼び出しは OS のものだけ確かめる
+pub fn compile(source: &str) -> Result<String> {
+    let class = parser::parse(source)?;
+    let signatures = analysis::Signatures::new(std::slice::from_ref(&class));
+    let diagnostics = analysis::check_class(&class, &signatures);
+    if !diagnostics.is_empty() {
+        let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        bail!("{}", lines.join("\n"));
+    }
+    generate(&class, std::slice::from_ref(&class), 0)
+}
+
+// 検査済みのクラスを VM コードにする。level 1 で定数を畳み、level 2 では project の
+// クラスの小さなサブルーチンの呼び出しも展開する
+pub fn generate(class: &ast::Class, project: &[ast::Class], level: u8) -> Result<String> {
+    Ok(generate_with_lines(class, project, level)?.0)
+}
+
+// VM コードと、その各行を生成した Jack の行（--source-map 用）
+pub fn generate_with_lines(
+    class: &ast::Class,
+    project: &[ast::Class],
+    level: u8,
+) -> Result<(String, Vec<usize>)> {
+    let (commands, lines) = if level == 0 {
+        codegen::compile_class_with_lines(class, &codegen::Options::default())?
+    } else {
+        let mut class = class.clone();
+        optimize::fold_class(&mut class);
+        let mut options = codegen::Options {
+            optimize: true,
+            ..codegen::Options::default()
+        };
+        if level >= 2 {
+            let mut project = project.to_vec();
+            project.iter_mut().for_each(optimize::fold_class);
+            options.inline = optimize::inline_candidates(&project);
+        }
+        codegen::compile_class_with_lines(&class, &options)?
+    };
+    Ok((commands.join("\n") + "\n", lines))
+}
+
+// ファイルならそれだけ、ディレクトリなら中の .jack を名前順に返す
+pub fn jack_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)
+        .context(format!("Failed to read directory '{}'", path.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jack"))
+        .collect();
+    files.sort();
+    ensure!(
+        !files.is_empty(),
+        "No .jack files found in '{}'",
+        path.display()
+    );
+    Ok(files)
+}
+
+// Main.jack -> <dir>/Main<suffix>.<extension>。dir を省くと入力と同じディレクトリ
+pub fn output_path(
+    input: &Path,
+    dir: Option<&Path>,
+    suffix: &str,
+    extension: &str,
+) -> Result<PathBuf> {
+    let stem = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid filename")?;
+    let dir = dir.or(input.parent()).unwrap_or(Path::new(""));
+    Ok(dir.join(format!("{}{}.{}", stem, suffix, extension)))
+}
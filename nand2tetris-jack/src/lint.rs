@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use crate::{ast::*, diagnostic::Diagnostic};
+
+// 読まれないローカル変数とフィールド、使われない引数、return の後ろの文を警告する
+pub fn lint_class(class: &Class) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // フィールドとして読まれた名前（ローカル変数や引数に隠されていないもの）
+    let mut field_reads = HashSet::new();
+
+    for subroutine in &class.subroutines {
+        let mut reads = HashSet::new();
+        statements_reads(&subroutine.body, &mut reads);
+        unreachable(&subroutine.body, &mut diagnostics);
+
+        for parameter in &subroutine.parameters {
+            if !reads.contains(parameter.name.name.as_str()) {
+                diagnostics.push(Diagnostic::warning(
+                    parameter.name.position,
+                    format!("Parameter '{}' is never used", parameter.name.name),
+                ));
+            }
+        }
+        let locals: Vec<&Identifier> = subroutine.locals.iter().flat_map(|v| &v.names).collect();
+        for name in &locals {
+            if !reads.contains(name.name.as_str()) {
+                diagnostics.push(Diagnostic::warning(
+                    name.position,
+                    format!("Variable '{}' is never read", name.name),
+                ));
+            }
+        }
+
+        if subroutine.kind != SubroutineKind::Function {
+            let shadowed: HashSet<&str> = locals
+                .iter()
+                .map(|name| name.name.as_str())
+                .chain(subroutine.parameters.iter().map(|p| p.name.name.as_str()))
+                .collect();
+            field_reads.extend(reads.difference(&shadowed).map(|name| name.to_string()));
+        }
+    }
+
+    for var in class.vars.iter().filter(|v| v.kind == ClassVarKind::Field) {
+        for name in &var.names {
+            if !field_reads.contains(&name.name) {
+                diagnostics.push(Diagnostic::warning(
+                    name.position,
+                    format!("Field '{}' is never read", name.name),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+// ブロックごとに return の直後の文を1つだけ報告する
+fn unreachable(statements: &[Statement], diagnostics: &mut Vec<Diagnostic>) {
+    if let Some(index) = statements
+        .iter()
+        .position(|s| matches!(s.kind, StatementKind::Return(_)))
+        && let Some(next) = statements.get(index + 1)
+    {
+        diagnostics.push(Diagnostic::warning(next.position, "Unreachable statement"));
+    }
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                unreachable(then_branch, diagnostics);
+                if let Some(else_branch) = else_branch {
+                    unreachable(else_branch, diagnostics);
+                }
+            }
+            StatementKind::While { body, .. } => unreachable(body, diagnostics),
+            _ => {}
+        }
+    }
+}
+
+// let x = ... の x は書き込みなので読み込みに数えない。let a[i] = ... の a は読み込み
+fn statements_reads<'a>(statements: &'a [Statement], reads: &mut HashSet<&'a str>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Let { name, index, value } => {
+                if let Some(index) = index {
+                    reads.insert(&name.name);
+                    expression_reads(index, reads);
+                }
+                expression_reads(value, reads);
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                expression_reads(condition, reads);
+                statements_reads(then_branch, reads);
+                if let Some(else_branch) = else_branch {
+                    statements_reads(else_branch, reads);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                expression_reads(condition, reads);
+                statements_reads(body, reads);
+            }
+            StatementKind::Do(call) => call_reads(call, reads),
+            StatementKind::Error => {}
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    expression_reads(value, reads);
+                }
+            }
+        }
+    }
+}
+
+fn expression_reads<'a>(expression: &'a Expression, reads: &mut HashSet<&'a str>) {
+    term_reads(&expression.term, reads);
+    for (_, term) in &expression.rest {
+        term_reads(term, reads);
+    }
+}
+
+fn term_reads<'a>(term: &'a Term, reads: &mut HashSet<&'a str>) {
+    match term {
+        Term::Variable(name) => {
+            reads.insert(&name.name);
+        }
+        Term::Index(name, index) => {
+            reads.insert(&name.name);
+            expression_reads(index, reads);
+        }
+        Term::Call(call) => call_reads(call, reads),
+        Term::Parenthesized(expression) => expression_reads(expression, reads),
+        Term::Unary(_, term) => term_reads(term, reads),
+        Term::Integer(_) | Term::String(_) | Term::Keyword(_) | Term::Error(_) => {}
+    }
+}
+
+// 受け手はクラス名かもしれないが、変数でなければ誰にも当たらない
+fn call_reads<'a>(call: &'a SubroutineCall, reads: &mut HashSet<&'a str>) {
+    if let Some(receiver) = &call.receiver {
+        reads.insert(&receiver.name);
+    }
+    for argument in &call.arguments {
+        expression_reads(argument, reads);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_lint_class() {
+        let class = parse(
+            "class Ball {\n\
+             field int x, y, unused;\n\
+             field Array trail;\n\
+             method int step(int dx, int dy) {\n\
+             var int old, scratch;\n\
+             var int y;\n\
+             let old = x + y;\n\
+             let scratch = 1;\n\
+             let y = 2;\n\
+             let trail[0] = old + dx;\n\
+             if (old) { return 1; let x = 2; }\n\
+             return x;\n\
+             do Output.println();\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+        let messages: Vec<String> = lint_class(&class).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Line 13, column 1: warning: Unreachable statement",
+                "Line 11, column 22: warning: Unreachable statement",
+                "Line 4, column 29: warning: Parameter 'dy' is never used",
+                "Line 5, column 14: warning: Variable 'scratch' is never read",
+                // 読まれた y はローカル変数で、フィールドの y ではない
+                "Line 2, column 14: warning: Field 'y' is never read",
+                "Line 2, column 17: warning: Field 'unused' is never read",
+            ]
+        );
+    }
+}
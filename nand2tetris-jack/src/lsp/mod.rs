@@ -0,0 +1,498 @@
+// Language Server Protocol サーバー（エディタから標準入出力で使う）
+//
+// 文書は全体で同期する。開いた文書を変えるたびにすべての開いた文書を検査して診断を送る
+mod protocol;
+mod workspace;
+
+pub use protocol::{read_message, write_message};
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    analysis::{Signatures, check_class},
+    diagnostic::Diagnostic,
+    format, lint,
+    parser::parse_with_diagnostics,
+    tokenizer::scan,
+};
+use protocol::{INVALID_PARAMS, METHOD_NOT_FOUND, REQUEST_FAILED, error, notification, response};
+use workspace::{Workspace, range};
+
+struct Server<'a> {
+    out: &'a mut dyn Write,
+    // 開いている文書のパス → 内容
+    documents: BTreeMap<PathBuf, String>,
+    exit: bool,
+}
+
+pub fn serve(input: &mut dyn BufRead, out: &mut dyn Write) -> Result<()> {
+    let mut server = Server {
+        out,
+        documents: BTreeMap::new(),
+        exit: false,
+    };
+    while !server.exit {
+        let Some(message) = read_message(input)? else {
+            break;
+        };
+        server.handle(&message)?;
+    }
+    Ok(())
+}
+
+impl Server<'_> {
+    fn handle(&mut self, message: &Value) -> Result<()> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+        // クライアントからの応答は使わない
+        if message.get("method").is_none() {
+            return Ok(());
+        }
+        let reply = match self.request(method, params) {
+            Ok(Some(result)) => response(id, result),
+            Ok(None) => error(
+                id,
+                METHOD_NOT_FOUND,
+                &format!("Unknown method '{}'", method),
+            ),
+            Err(e) if e.is::<InvalidParams>() => error(id, INVALID_PARAMS, &format!("{:#}", e)),
+            Err(e) => error(id, REQUEST_FAILED, &format!("{:#}", e)),
+        };
+        write_message(self.out, &reply)
+    }
+
+    // 知らないメソッドなら None
+    fn request(&mut self, method: &str, params: &Value) -> Result<Option<Value>> {
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "definitionProvider": true,
+                    "signatureHelpProvider": { "triggerCharacters": ["(", ","] },
+                    "documentFormattingProvider": true,
+                },
+                "serverInfo": { "name": "jack", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/completion" => {
+                let (path, line, column) = self.position(params)?;
+                self.workspace(&path)
+                    .completion(&path, &self.documents[&path], line, column)
+            }
+            "textDocument/definition" => {
+                let (path, line, column) = self.position(params)?;
+                self.workspace(&path)
+                    .definition(&path, &self.documents[&path], line, column)
+            }
+            "textDocument/signatureHelp" => {
+                let (path, line, column) = self.position(params)?;
+                self.workspace(&path)
+                    .signature_help(&path, &self.documents[&path], line, column)
+            }
+            "textDocument/formatting" => {
+                let path = self.document(params)?;
+                let text = &self.documents[&path];
+                let formatted = format::format(text)?;
+                if formatted == *text {
+                    json!([])
+                } else {
+                    // 文書全体を置き換える（最後の行の後ろまで）
+                    let lines = text.split('\n').count();
+                    json!([{
+                        "range": {
+                            "start": { "line": 0, "character": 0 },
+                            "end": { "line": lines, "character": 0 },
+                        },
+                        "newText": formatted,
+                    }])
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri_to_path(uri), text.to_string());
+            }
+            // 全体の同期なので最後の変更が文書全体
+            "textDocument/didChange" => {
+                let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                else {
+                    return Ok(());
+                };
+                self.documents.insert(uri_to_path(uri), text.to_string());
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri_to_path(uri));
+                let message = notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                );
+                return write_message(self.out, &message);
+            }
+            "exit" => {
+                self.exit = true;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+        self.publish_diagnostics()
+    }
+
+    fn publish_diagnostics(&mut self) -> Result<()> {
+        let paths: Vec<PathBuf> = self.documents.keys().cloned().collect();
+        for path in paths {
+            let diagnostics = self.diagnostics(&path);
+            let message = notification(
+                "textDocument/publishDiagnostics",
+                json!({ "uri": path_to_uri(&path), "diagnostics": diagnostics }),
+            );
+            write_message(self.out, &message)?;
+        }
+        Ok(())
+    }
+
+    // 構文エラーがなければ、プロジェクトのクラスに対する検査と lint も行う
+    fn diagnostics(&self, path: &Path) -> Vec<Value> {
+        let text = &self.documents[path];
+        let (class, mut diagnostics) = parse_with_diagnostics(text);
+        if let Some(class) = class
+            && !diagnostics.iter().any(Diagnostic::is_error)
+        {
+            let signatures = Signatures::new(&self.workspace(path).project_classes());
+            diagnostics.extend(check_class(&class, &signatures));
+            diagnostics.extend(lint::lint_class(&class));
+        }
+        let tokens = scan(text).0;
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                // 位置のトークンの長さだけ。トークンがなければ 1 文字
+                let length = tokens
+                    .iter()
+                    .find(|token| {
+                        (token.line, token.column)
+                            == (diagnostic.position.line, diagnostic.position.column)
+                    })
+                    .map_or(1, |token| token.text().len());
+                json!({
+                    "range": range(diagnostic.position, length),
+                    "severity": if diagnostic.is_error() { 1 } else { 2 },
+                    "source": "jack",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect()
+    }
+
+    fn workspace(&self, path: &Path) -> Workspace {
+        Workspace::load(path, &self.documents)
+    }
+
+    fn document(&self, params: &Value) -> Result<PathBuf> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .context(InvalidParams)?;
+        let path = uri_to_path(uri);
+        if !self.documents.contains_key(&path) {
+            return Err(InvalidParams).context(format!("Document '{}' is not open", uri));
+        }
+        Ok(path)
+    }
+
+    // 文書のパスと、1 始まりの行と列
+    fn position(&self, params: &Value) -> Result<(PathBuf, usize, usize)> {
+        let path = self.document(params)?;
+        let position = &params["position"];
+        let line = position["line"].as_u64().context(InvalidParams)?;
+        let column = position["character"].as_u64().context(InvalidParams)?;
+        Ok((path, line as usize + 1, column as usize + 1))
+    }
+}
+
+// パラメーターの誤り（INVALID_PARAMS で返す）
+#[derive(Debug)]
+struct InvalidParams;
+
+impl std::fmt::Display for InvalidParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid params")
+    }
+}
+
+impl std::error::Error for InvalidParams {}
+
+// file:///a%20b/Main.jack -> /a b/Main.jack
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Cursor};
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    // メッセージを順に流し、返ってきたメッセージをすべて読む
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut out = Vec::new();
+        serve(&mut Cursor::new(input), &mut out).unwrap();
+
+        let mut replies = Vec::new();
+        let mut reader = out.as_slice();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            replies.push(message);
+        }
+        replies
+    }
+
+    fn result(replies: &[Value], id: u64) -> &Value {
+        let reply = replies.iter().find(|reply| reply["id"] == id).unwrap();
+        &reply["result"]
+    }
+
+    fn labels(items: &Value) -> Vec<&str> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect()
+    }
+
+    fn position(uri: &str, line: u64, character: u64) -> Value {
+        json!({
+            "textDocument": { "uri": uri },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    const MAIN: &str = "class Main {
+    function void main() {
+        var Square s;
+        let s = Square.new(2, 3);
+        do s.moveBy(1, 2);
+        do Output.
+    }
+}
+";
+
+    #[test]
+    fn test_session() {
+        let dir = std::env::temp_dir().join(format!("jack-lsp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Square.jack"),
+            "class Square {
+    field int x, y;
+
+    /** Makes a square. */
+    constructor Square new(int ax, int ay) {
+        let x = ax;
+        let y = ay;
+        return this;
+    }
+
+    /** Moves by dx and dy. */
+    method void moveBy(int dx, int dy) {
+        let x = x + dx;
+        let y = y + dy;
+        return;
+    }
+}
+",
+        )
+        .unwrap();
+        let uri = path_to_uri(&dir.join("Main.jack"));
+        let open = |text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "jack", "version": 1, "text": text } },
+            })
+        };
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": MAIN.replace("do Output.\n", "do s.moveBy(1);\n") }],
+            },
+        });
+
+        let replies = session(&[
+            request(1, "initialize", json!({ "capabilities": {} })),
+            open(MAIN),
+            request(2, "textDocument/completion", position(&uri, 4, 14)),
+            request(3, "textDocument/completion", position(&uri, 5, 18)),
+            request(4, "textDocument/definition", position(&uri, 3, 24)),
+            request(5, "textDocument/definition", position(&uri, 4, 12)),
+            request(6, "textDocument/signatureHelp", position(&uri, 3, 30)),
+            change,
+            request(
+                7,
+                "textDocument/formatting",
+                json!({ "textDocument": { "uri": uri } }),
+            ),
+            request(8, "textDocument/hover", position(&uri, 0, 0)),
+            request(9, "shutdown", Value::Null),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(
+            result(&replies, 1)["capabilities"]["definitionProvider"],
+            true
+        );
+
+        // 書きかけの行は構文エラー
+        let diagnostics: Vec<&Value> = replies
+            .iter()
+            .filter(|reply| reply["method"] == "textDocument/publishDiagnostics")
+            .collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0]["params"]["uri"], uri);
+        assert_eq!(diagnostics[0]["params"]["diagnostics"][0]["severity"], 1);
+        assert_eq!(
+            diagnostics[0]["params"]["diagnostics"][0]["range"]["start"]["line"],
+            6
+        );
+        // 直したあとは引数の数の誤り
+        let fixed = &diagnostics[1]["params"]["diagnostics"];
+        assert_eq!(fixed.as_array().unwrap().len(), 1);
+        assert_eq!(fixed[0]["range"]["start"]["line"], 5);
+        assert!(fixed[0]["message"].as_str().unwrap().contains("moveBy"));
+
+        // s. はインスタンスのメソッド、Output. は関数
+        let methods = result(&replies, 2);
+        assert_eq!(labels(methods), ["moveBy"]);
+        assert_eq!(methods[0]["kind"], 2);
+        assert_eq!(methods[0]["detail"], "method void moveBy(int dx, int dy)");
+        assert_eq!(methods[0]["documentation"], "Moves by dx and dy.");
+        let functions = labels(result(&replies, 3));
+        assert!(functions.contains(&"printString"));
+        assert!(functions.contains(&"println"));
+
+        // Square.new の定義は Square.jack、s は宣言
+        let definition = result(&replies, 4);
+        assert_eq!(definition["uri"], path_to_uri(&dir.join("Square.jack")));
+        assert_eq!(
+            definition["range"]["start"],
+            json!({ "line": 4, "character": 23 })
+        );
+        let definition = result(&replies, 5);
+        assert_eq!(definition["uri"], uri);
+        assert_eq!(
+            definition["range"]["start"],
+            json!({ "line": 2, "character": 19 })
+        );
+
+        // Square.new(2, | の 2 つ目の引数
+        let help = result(&replies, 6);
+        assert_eq!(
+            help["signatures"][0]["label"],
+            "constructor Square new(int ax, int ay)"
+        );
+        assert_eq!(help["activeParameter"], 1);
+
+        // 整えてあるので変更はない
+        assert_eq!(result(&replies, 7), &json!([]));
+
+        let unknown = replies.iter().find(|reply| reply["id"] == 8).unwrap();
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(result(&replies, 9), &Value::Null);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_formatting_and_uri() {
+        let uri = "file:///tmp/jack%20lsp/Main.jack";
+        assert_eq!(uri_to_path(uri), PathBuf::from("/tmp/jack lsp/Main.jack"));
+        assert_eq!(path_to_uri(&uri_to_path(uri)), uri);
+
+        let replies = session(&[
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "text": "class Main{\nfunction void main(){return;}\n}" } },
+            }),
+            request(
+                1,
+                "textDocument/formatting",
+                json!({ "textDocument": { "uri": uri } }),
+            ),
+            request(
+                2,
+                "textDocument/formatting",
+                json!({ "textDocument": { "uri": "file:///x.jack" } }),
+            ),
+        ]);
+        let edits = result(&replies, 1);
+        assert_eq!(
+            edits[0]["range"]["end"],
+            json!({ "line": 3, "character": 0 })
+        );
+        assert_eq!(
+            edits[0]["newText"],
+            "class Main {\n    function void main() {\n        return;\n    }\n}\n"
+        );
+        let closed = replies.iter().find(|reply| reply["id"] == 2).unwrap();
+        assert_eq!(closed["error"]["code"], INVALID_PARAMS);
+    }
+}
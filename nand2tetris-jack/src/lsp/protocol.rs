@@ -0,0 +1,62 @@
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+
+// Content-Length ヘッダ付きの JSON-RPC のメッセージを1つ読む。入力が終わったら None
+pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            match length {
+                Some(_) => break,
+                None => continue,
+            }
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("Invalid LSP header '{}'", line);
+        };
+        if name.trim().eq_ignore_ascii_case("Content-Length") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context(format!("Invalid Content-Length '{}'", value.trim()))?,
+            );
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        serde_json::from_slice(&body).context("Invalid LSP message")?,
+    ))
+}
+
+pub fn write_message(out: &mut dyn Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+pub fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+// JSON-RPC と LSP のエラーコード
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const REQUEST_FAILED: i64 = -32803;
@@ -0,0 +1,416 @@
+// 開いたファイルのプロジェクトのクラスと、補完・定義へのジャンプ・シグネチャの表示
+//
+// プロジェクトは、ファイルのディレクトリか上の jack.toml のソースとライブラリ。なければファイルの
+// ディレクトリの .jack。同梱の OS のうちプロジェクトにないクラスも加える（定義の場所はない）。
+// 書きかけのコードでも使えるよう parse_tolerant で読む
+
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    ast::{Class, ClassVarKind, Identifier, Position, Subroutine, SubroutineKind, Type},
+    doc::{ClassDoc, SubroutineDoc},
+    jack_files, os,
+    parser::parse_tolerant,
+    project::{Project, manifest_path},
+    tokenizer::{Token, TokenKind, scan},
+};
+
+use super::path_to_uri;
+
+// CompletionItemKind
+const METHOD: u32 = 2;
+const FUNCTION: u32 = 3;
+const CONSTRUCTOR: u32 = 4;
+const FIELD: u32 = 5;
+const VARIABLE: u32 = 6;
+const CLASS: u32 = 7;
+
+pub struct Entry {
+    pub class: Class,
+    pub doc: ClassDoc,
+    // 同梱の OS なら None
+    pub path: Option<PathBuf>,
+}
+
+pub struct Workspace {
+    pub entries: Vec<Entry>,
+}
+
+// 変数の宣言
+struct Variable<'a> {
+    name: &'a Identifier,
+    ty: &'a Type,
+}
+
+impl Workspace {
+    // open は開いている文書の内容。ディスクの内容より優先する
+    pub fn load(file: &Path, open: &BTreeMap<PathBuf, String>) -> Self {
+        let mut files = project_files(file);
+        if !files.iter().any(|path| path == file) {
+            files.push(file.to_path_buf());
+        }
+        let mut entries = Vec::new();
+        for path in files {
+            let source = match open.get(&path) {
+                Some(text) => text.clone(),
+                None => match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+            };
+            let (class, _) = parse_tolerant(&source);
+            let doc = ClassDoc::new(&class, &source);
+            entries.push(Entry {
+                class,
+                doc,
+                path: Some(path),
+            });
+        }
+        for (name, source) in os::OS_SOURCES {
+            if entries.iter().any(|entry| entry.class.name.name == *name) {
+                continue;
+            }
+            let (class, _) = parse_tolerant(source);
+            let doc = ClassDoc::new(&class, source);
+            entries.push(Entry {
+                class,
+                doc,
+                path: None,
+            });
+        }
+        Workspace { entries }
+    }
+
+    pub fn class(&self, name: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.class.name.name == name)
+    }
+
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.path.as_deref() == Some(path))
+    }
+
+    // プロジェクトのクラス（OS を除く）
+    pub fn project_classes(&self) -> Vec<Class> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.path.is_some())
+            .map(|entry| entry.class.clone())
+            .collect()
+    }
+
+    // 変数ならその型のクラスのインスタンス、クラス名ならクラスそのもの (クラス名, インスタンスか)
+    fn receiver(&self, entry: &Entry, line: usize, name: &str) -> Option<(String, bool)> {
+        match variable(&entry.class, line, name) {
+            Some(Variable {
+                ty: Type::Class(class),
+                ..
+            }) => Some((class.clone(), true)),
+            Some(_) => None,
+            None => self.class(name).map(|_| (name.to_string(), false)),
+        }
+    }
+
+    pub fn definition(&self, path: &Path, text: &str, line: usize, column: usize) -> Value {
+        let Some(entry) = self.entry(path) else {
+            return Value::Null;
+        };
+        let tokens = scan(text).0;
+        let Some(index) = tokens.iter().position(|token| covers(token, line, column)) else {
+            return Value::Null;
+        };
+        let TokenKind::Identifier(name) = &tokens[index].kind else {
+            return Value::Null;
+        };
+        let symbol = |i: usize| tokens.get(i).map(|token| &token.kind);
+
+        // Receiver.name
+        if index >= 2 && symbol(index - 1) == Some(&TokenKind::Symbol('.')) {
+            let Some(TokenKind::Identifier(receiver)) = symbol(index - 2) else {
+                return Value::Null;
+            };
+            return match self.receiver(entry, line, receiver) {
+                Some((class, _)) => self.subroutine_location(&class, name),
+                None => Value::Null,
+            };
+        }
+        // name(...) はこのクラスのサブルーチン
+        if symbol(index + 1) == Some(&TokenKind::Symbol('(')) {
+            return self.subroutine_location(&entry.class.name.name, name);
+        }
+        if let Some(variable) = variable(&entry.class, line, name) {
+            return location(path, variable.name);
+        }
+        match self.class(name) {
+            Some(Entry {
+                class,
+                path: Some(path),
+                ..
+            }) => location(path, &class.name),
+            _ => Value::Null,
+        }
+    }
+
+    fn subroutine_location(&self, class: &str, name: &str) -> Value {
+        let Some(Entry {
+            class,
+            path: Some(path),
+            ..
+        }) = self.class(class)
+        else {
+            return Value::Null;
+        };
+        match class
+            .subroutines
+            .iter()
+            .find(|subroutine| subroutine.name.name == name)
+        {
+            Some(subroutine) => location(path, &subroutine.name),
+            None => Value::Null,
+        }
+    }
+
+    // . の後ろならクラスのサブルーチン、それ以外は見える変数とこのクラスのサブルーチンとクラス名
+    pub fn completion(&self, path: &Path, text: &str, line: usize, column: usize) -> Value {
+        let Some(entry) = self.entry(path) else {
+            return json!([]);
+        };
+        let before: String = text
+            .lines()
+            .nth(line - 1)
+            .unwrap_or_default()
+            .chars()
+            .take(column - 1)
+            .collect();
+        let before = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if let Some(before) = before.strip_suffix('.') {
+            let receiver = before
+                .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default();
+            let Some((class, instance)) = self.receiver(entry, line, receiver) else {
+                return json!([]);
+            };
+            let Some(target) = self.class(&class) else {
+                return json!([]);
+            };
+            let items: Vec<Value> = target
+                .doc
+                .subroutines
+                .iter()
+                .filter(|subroutine| (subroutine.kind == SubroutineKind::Method) == instance)
+                .map(subroutine_item)
+                .collect();
+            return json!(items);
+        }
+
+        let mut items = Vec::new();
+        if let Some(subroutine) = enclosing(&entry.class, line) {
+            for parameter in &subroutine.parameters {
+                items.push(variable_item(&parameter.name, &parameter.ty, "argument"));
+            }
+            for dec in &subroutine.locals {
+                for name in &dec.names {
+                    items.push(variable_item(name, &dec.ty, "var"));
+                }
+            }
+        }
+        for dec in &entry.class.vars {
+            let kind = match dec.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            };
+            for name in &dec.names {
+                items.push(variable_item(name, &dec.ty, kind));
+            }
+        }
+        items.extend(entry.doc.subroutines.iter().map(subroutine_item));
+        for other in &self.entries {
+            items.push(json!({
+                "label": other.class.name.name,
+                "kind": CLASS,
+                "documentation": other.doc.comment,
+            }));
+        }
+        json!(items)
+    }
+
+    // カーソルを囲む閉じていない ( の前の名前のシグネチャと、いまの引数の番号
+    pub fn signature_help(&self, path: &Path, text: &str, line: usize, column: usize) -> Value {
+        let Some(entry) = self.entry(path) else {
+            return Value::Null;
+        };
+        let tokens: Vec<Token> = scan(text)
+            .0
+            .into_iter()
+            .take_while(|token| (token.line, token.column) < (line, column))
+            .collect();
+        let (mut depth, mut commas) = (0usize, 0);
+        let mut open = None;
+        for (i, token) in tokens.iter().enumerate().rev() {
+            match token.kind {
+                TokenKind::Symbol(')' | ']') => depth += 1,
+                TokenKind::Symbol('(' | '[') if depth > 0 => depth -= 1,
+                TokenKind::Symbol('(') => {
+                    open = Some(i);
+                    break;
+                }
+                TokenKind::Symbol(',') if depth == 0 => commas += 1,
+                TokenKind::Symbol(';' | '{' | '}' | '[') => return Value::Null,
+                _ => {}
+            }
+        }
+        let Some(open) = open.filter(|&open| open > 0) else {
+            return Value::Null;
+        };
+        let TokenKind::Identifier(name) = &tokens[open - 1].kind else {
+            return Value::Null;
+        };
+        let class = match open
+            .checked_sub(3)
+            .map(|i| (&tokens[i].kind, &tokens[i + 1].kind))
+        {
+            Some((TokenKind::Identifier(receiver), TokenKind::Symbol('.'))) => {
+                match self.receiver(entry, line, receiver) {
+                    Some((class, _)) => class,
+                    None => return Value::Null,
+                }
+            }
+            _ => entry.class.name.name.clone(),
+        };
+        let Some(subroutine) = self.class(&class).and_then(|target| {
+            target
+                .doc
+                .subroutines
+                .iter()
+                .find(|subroutine| subroutine.name == *name)
+        }) else {
+            return Value::Null;
+        };
+        let parameters: Vec<Value> = subroutine
+            .parameters
+            .iter()
+            .map(|(ty, name)| json!({ "label": format!("{} {}", ty.name(), name) }))
+            .collect();
+        json!({
+            "signatures": [{
+                "label": declaration(subroutine),
+                "documentation": subroutine.comment,
+                "parameters": parameters,
+            }],
+            "activeSignature": 0,
+            "activeParameter": commas,
+        })
+    }
+}
+
+// file の上のディレクトリに jack.toml があり、file がそのプロジェクトのクラスならプロジェクトの
+// ファイル。なければ file のディレクトリの .jack
+fn project_files(file: &Path) -> Vec<PathBuf> {
+    let dir = file.parent().unwrap_or(Path::new(""));
+    for ancestor in dir.ancestors() {
+        let Some(manifest) = manifest_path(ancestor) else {
+            continue;
+        };
+        if let Ok(classes) = Project::load(&manifest).and_then(|project| project.classes()) {
+            // sources = ["."] なら dir/./Main.jack になるので . を除いて比べる
+            let files: Vec<PathBuf> = classes
+                .into_values()
+                .map(|path| {
+                    path.components()
+                        .filter(|c| *c != Component::CurDir)
+                        .collect()
+                })
+                .collect();
+            if files.iter().any(|path| path == file) {
+                return files;
+            }
+        }
+    }
+    jack_files(dir).unwrap_or_default()
+}
+
+// カーソルのある行を含むサブルーチン（宣言の行がその行より前の最後のもの）
+fn enclosing(class: &Class, line: usize) -> Option<&Subroutine> {
+    class
+        .subroutines
+        .iter()
+        .rev()
+        .find(|subroutine| subroutine.name.position.line <= line)
+}
+
+// サブルーチンのローカル変数と引数、クラスの変数の順に探す
+fn variable<'a>(class: &'a Class, line: usize, name: &str) -> Option<Variable<'a>> {
+    let mut found = Vec::new();
+    if let Some(subroutine) = enclosing(class, line) {
+        for dec in &subroutine.locals {
+            found.extend(dec.names.iter().map(|name| Variable { name, ty: &dec.ty }));
+        }
+        found.extend(subroutine.parameters.iter().map(|parameter| Variable {
+            name: &parameter.name,
+            ty: &parameter.ty,
+        }));
+    }
+    for dec in &class.vars {
+        found.extend(dec.names.iter().map(|name| Variable { name, ty: &dec.ty }));
+    }
+    found
+        .into_iter()
+        .find(|variable| variable.name.name == name)
+}
+
+fn covers(token: &Token, line: usize, column: usize) -> bool {
+    token.line == line && (token.column..=token.column + token.text().len()).contains(&column)
+}
+
+// LSP の位置は 0 始まり。列は文字で数える（Jack のソースはほぼ ASCII なので UTF-16 の
+// 単位との違いは扱わない）
+pub fn range(position: Position, length: usize) -> Value {
+    json!({
+        "start": { "line": position.line - 1, "character": position.column - 1 },
+        "end": { "line": position.line - 1, "character": position.column - 1 + length },
+    })
+}
+
+fn location(path: &Path, name: &Identifier) -> Value {
+    json!({ "uri": path_to_uri(path), "range": range(name.position, name.name.len()) })
+}
+
+fn declaration(subroutine: &SubroutineDoc) -> String {
+    subroutine.signature(|ty| ty.name().to_string())
+}
+
+fn subroutine_item(subroutine: &SubroutineDoc) -> Value {
+    let kind = match subroutine.kind {
+        SubroutineKind::Constructor => CONSTRUCTOR,
+        SubroutineKind::Function => FUNCTION,
+        SubroutineKind::Method => METHOD,
+    };
+    json!({
+        "label": subroutine.name,
+        "kind": kind,
+        "detail": declaration(subroutine),
+        "documentation": subroutine.comment,
+    })
+}
+
+fn variable_item(name: &Identifier, ty: &Type, kind: &str) -> Value {
+    let item_kind = if matches!(kind, "field" | "static") {
+        FIELD
+    } else {
+        VARIABLE
+    };
+    json!({
+        "label": name.name,
+        "kind": item_kind,
+        "detail": format!("{} {}", kind, ty.name()),
+    })
+}
@@ -0,0 +1,454 @@
+use anyhow::{Context, Error, Result, anyhow};
+
+use clap::{Args, Parser, Subcommand};
+use nand2tetris_core::{
+    ci::{self, CiArgs, set_ci},
+    cli::{ColorChoice, Fail, Failure, finish, is_quiet, set_color, set_quiet},
+    config::{Config, expand_home},
+    diagnostic::count,
+    sarif::{self, set_sarif},
+    status,
+};
+use nand2tetris_jack::{
+    analysis::{Signatures, check_class},
+    ast::Class,
+    diagnostic::{Diagnostic, emit_diagnostics},
+    doc::{ClassDoc, DocFormat, render_class, render_index},
+    format, generate_with_lines, jack_files, lint, lsp, os, output_path, parser,
+    pass::{Pipeline, Registry},
+    project::{Project, manifest_path},
+    source_map::{SourceMap, map_path},
+    symbols, tokenizer, xml,
+};
+use serde::Deserialize;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+#[derive(Parser)]
+#[command(
+    about = "Nand2Tetris Jack Compiler",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// A .jack file, a directory of .jack files, or a jack.toml project manifest (or a directory
+    /// containing one). Each Foo.jack is compiled to Foo.vm
+    #[arg(required = true)]
+    input: Option<PathBuf>,
+    /// Write the tokens of each file to <name>T.xml in the official project 10 format
+    #[arg(long)]
+    tokens_xml: bool,
+    /// Write the parse tree of each file to <name>.xml in the official project 10 format
+    #[arg(long)]
+    xml: bool,
+    /// Print the class and subroutine symbol tables of each file
+    #[arg(long)]
+    dump_symbols: bool,
+    /// Directory for the output files (default: next to each source file)
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+    /// Optimize the generated code: -O folds constant expressions and simplifies arithmetic,
+    /// -O2 also inlines small subroutines
+    #[arg(short = 'O', value_name = "LEVEL", num_args = 0..=1, default_missing_value = "1")]
+    optimize: Option<u8>,
+    /// Also write <name>.vm.map with the Jack line of each VM command, for the emulator's
+    /// debugger, profiler and coverage
+    #[arg(long)]
+    source_map: bool,
+    /// Link the bundled Jack OS: also write the .vm of every OS class (Math, Memory, Screen,
+    /// Output, Keyboard, String, Array, Sys) the program does not define itself
+    #[arg(long)]
+    os: bool,
+    /// Treat warnings as errors
+    #[arg(long)]
+    deny_warnings: bool,
+    /// Also write the errors and warnings with their locations as SARIF to this file, for
+    /// GitHub code scanning and other tools that annotate the source
+    #[arg(long, value_name = "FILE")]
+    sarif: Option<PathBuf>,
+    #[command(flatten)]
+    ci: CiArgs,
+    /// Rewrite the checked classes before generating code, in the order given: rename:OLD=NEW,
+    /// count-calls (a static calls_<name> counting the calls of each subroutine) or fold
+    #[arg(long = "pass", value_name = "PASS", value_delimiter = ',')]
+    passes: Vec<String>,
+    /// Print nothing but errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    // 設定ファイルの out_dir。jack.toml の output より弱い
+    #[arg(skip)]
+    config_out_dir: Option<PathBuf>,
+    /// When to color errors and warnings
+    #[arg(long, value_enum, default_value_t, global = true)]
+    color: ColorChoice,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate API documentation from the class and subroutine declarations and the comments
+    /// just above them
+    Jackdoc(JackdocArgs),
+    /// Format .jack files in place: 4-space indentation, one statement per line and spaces
+    /// around binary operators, keeping comments and single blank lines
+    Jackfmt(JackfmtArgs),
+    /// Serve the Language Server Protocol on stdin/stdout: diagnostics, completion, go to
+    /// definition, signature help and formatting for editors
+    Lsp,
+}
+
+#[derive(Args)]
+struct JackdocArgs {
+    /// A .jack file, a directory of .jack files, or a jack.toml project manifest (or a directory
+    /// containing one, whose sources and libraries are documented)
+    input: PathBuf,
+    #[arg(long, value_enum, default_value_t = DocFormat::Html)]
+    format: DocFormat,
+    /// Directory for the pages (default: doc next to the sources or the manifest)
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct JackfmtArgs {
+    /// .jack files or directories of .jack files
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Only report the files that are not formatted, and fail if there are any
+    #[arg(long)]
+    check: bool,
+}
+
+// 設定ファイルの [jack]。フラグのないものだけに使う
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Defaults {
+    out_dir: Option<PathBuf>,
+    optimize: Option<u8>,
+    deny_warnings: Option<bool>,
+}
+
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    set_quiet(cli.quiet);
+    set_color(cli.color);
+    set_sarif(env!("CARGO_PKG_NAME"), cli.sarif.clone());
+    set_ci(&cli.ci, "commands");
+    finish(apply_config(&mut cli).and_then(|()| match &cli.command {
+        Some(Command::Jackdoc(args)) => jackdoc(args),
+        Some(Command::Jackfmt(args)) => jackfmt(args),
+        Some(Command::Lsp) => lsp::serve(&mut io::stdin().lock(), &mut io::stdout()),
+        None => run(&cli),
+    }))
+}
+
+fn apply_config(cli: &mut Cli) -> Result<()> {
+    let defaults: Defaults = Config::load()?.section("jack")?;
+    cli.config_out_dir = defaults.out_dir.map(|dir| expand_home(&dir));
+    cli.optimize = cli.optimize.or(defaults.optimize);
+    cli.deny_warnings |= defaults.deny_warnings.unwrap_or(false);
+    Ok(())
+}
+
+// コンパイルする .jack ファイルと出力先、OS をつなぐか
+struct Inputs {
+    files: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    os: bool,
+    // マニフェストのパスの後に --pass のパスを実行する
+    passes: Vec<String>,
+}
+
+// マニフェストならその設定で、Main から参照されるクラスだけを使う
+fn inputs(cli: &Cli) -> Result<Inputs> {
+    let input = cli.input.as_deref().context("No input given")?;
+    let Some(manifest) = manifest_path(input) else {
+        return Ok(Inputs {
+            files: jack_files(input)?,
+            out_dir: cli.out_dir.clone().or(cli.config_out_dir.clone()),
+            os: cli.os,
+            passes: cli.passes.clone(),
+        });
+    };
+    let project = Project::load(&manifest)?;
+    Ok(Inputs {
+        files: project.resolve()?,
+        out_dir: Some(cli.out_dir.clone().unwrap_or_else(|| project.output_dir())),
+        os: cli.os || project.manifest.os,
+        passes: [project.manifest.passes, cli.passes.clone()].concat(),
+    })
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    // 解析結果を出すオプションがなければコンパイルする
+    let analyze = cli.tokens_xml || cli.xml || cli.dump_symbols;
+    let inputs = inputs(cli)?;
+    if let Some(dir) = &inputs.out_dir {
+        fs::create_dir_all(dir).context(format!("Failed to create '{}'", dir.display()))?;
+    }
+
+    if !analyze {
+        return compile(cli, &inputs);
+    }
+
+    for file in &inputs.files {
+        let source = fs::read_to_string(file)
+            .context(format!("Failed to read file '{}'", file.display()))?;
+        if cli.tokens_xml {
+            let tokens = tokenizer::tokenize(&source)
+                .map_err(|e| in_file(file, e))
+                .fail(Failure::Parse)?;
+            let output = output_path(file, inputs.out_dir.as_deref(), "T", "xml")?;
+            write_output(file, &output, &tokenizer::tokens_xml(&tokens))?;
+        }
+        if cli.xml || cli.dump_symbols {
+            let class = parser::parse(&source)
+                .map_err(|e| in_file(file, e))
+                .fail(Failure::Parse)?;
+            if cli.xml {
+                let output = output_path(file, inputs.out_dir.as_deref(), "", "xml")?;
+                write_output(file, &output, &xml::class_xml(&class))?;
+            }
+            if cli.dump_symbols {
+                print!(
+                    "{}",
+                    symbols::dump_symbols(&class)
+                        .map_err(|e| in_file(file, e))
+                        .fail(Failure::Semantic)?
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// 全クラスを先に解析し、クラスをまたいだ呼び出しも確かめてからコード生成する。
+// 構文エラーがあっても残りのファイルを読み、エラーをすべて表示する
+fn compile(cli: &Cli, inputs: &Inputs) -> Result<()> {
+    let mut pipeline = Pipeline::from_specs(&Registry::builtin(), &inputs.passes)?;
+    let mut classes = Vec::new();
+    let (mut errors, mut warnings) = (0, 0);
+    // 構文エラーがあれば意味のエラーより先に報告する種類にする
+    let mut syntax = false;
+    for file in inputs.files.iter().cloned() {
+        let source = fs::read_to_string(&file)
+            .context(format!("Failed to read file '{}'", file.display()))?;
+        let (class, diagnostics) = parser::parse_with_diagnostics(&source);
+        let (e, w) = report(&file, &diagnostics, warnings_fail(cli));
+        errors += e;
+        warnings += w;
+        syntax |= e > 0;
+        if let Some(class) = class {
+            classes.push((file, class, diagnostics.is_empty()));
+        }
+    }
+
+    let mut all: Vec<Class> = classes.iter().map(|(_, class, _)| class.clone()).collect();
+    let signatures = Signatures::new(&all);
+    // 構文エラーのあるクラスは意味の検査をしない（途中が抜けていて誤報になる）
+    for (file, class, _) in classes.iter().filter(|(_, _, parsed)| *parsed) {
+        let mut diagnostics = check_class(class, &signatures);
+        diagnostics.extend(lint::lint_class(class));
+        let (e, w) = report(file, &diagnostics, warnings_fail(cli));
+        errors += e;
+        warnings += w;
+    }
+    if errors > 0 {
+        let failure = if syntax {
+            Failure::Parse
+        } else {
+            Failure::Semantic
+        };
+        return Err(failure.wrap(anyhow!(
+            "Compilation failed with {}",
+            count(errors, "error")
+        )));
+    }
+    if cli.deny_warnings && warnings > 0 {
+        return Err(Failure::Warnings.wrap(anyhow!(
+            "Compilation failed with {} (--deny-warnings)",
+            count(warnings, "warning")
+        )));
+    }
+
+    // パスの書き換えた結果も確かめる
+    if !pipeline.is_empty() {
+        pipeline.run(&mut all).fail(Failure::Semantic)?;
+        let signatures = Signatures::new(&all);
+        for ((file, _, _), class) in classes.iter().zip(&all) {
+            errors += report(file, &check_class(class, &signatures), warnings_fail(cli)).0;
+        }
+        if errors > 0 {
+            return Err(Failure::Semantic.wrap(anyhow!(
+                "Compilation failed with {} after the passes",
+                count(errors, "error")
+            )));
+        }
+    }
+
+    for ((file, _, _), class) in classes.iter().zip(&all) {
+        let (code, lines) = generate_with_lines(class, &all, cli.optimize.unwrap_or(0))
+            .map_err(|e| in_file(file, e))
+            .fail(Failure::Semantic)?;
+        let output = output_path(file, inputs.out_dir.as_deref(), "", "vm")?;
+        write_output(file, &output, &code)?;
+        count_commands(file, &code);
+        if cli.source_map {
+            let map = SourceMap {
+                source: file.file_name().unwrap().to_string_lossy().into_owned(),
+                lines,
+            };
+            write_output(file, &map_path(&output), &map.to_json())?;
+        }
+    }
+
+    if inputs.os {
+        let defined: Vec<&str> = all.iter().map(|class| class.name.name.as_str()).collect();
+        // 出力先は最初のファイルと同じ場所
+        let first = &classes[0].0;
+        for (name, code) in os::compile_os(cli.optimize.unwrap_or(0))? {
+            if defined.contains(&name.as_str()) {
+                continue;
+            }
+            let source = first.with_file_name(format!("{}.jack", name));
+            let output = output_path(&source, inputs.out_dir.as_deref(), "", "vm")?;
+            let input = PathBuf::from(format!("<os>/{}.jack", name));
+            write_output(&input, &output, &code)?;
+            count_commands(&input, &code);
+        }
+    }
+    Ok(())
+}
+
+// クラスごとのページと一覧（index）を書く
+fn jackdoc(args: &JackdocArgs) -> Result<()> {
+    let (files, dir) = match manifest_path(&args.input) {
+        Some(manifest) => {
+            let project = Project::load(&manifest)?;
+            (project.classes()?.into_values().collect(), project.dir)
+        }
+        None if args.input.is_dir() => (jack_files(&args.input)?, args.input.clone()),
+        None => (
+            vec![args.input.clone()],
+            args.input.parent().unwrap_or(Path::new("")).to_path_buf(),
+        ),
+    };
+    let out_dir = args.out_dir.clone().unwrap_or_else(|| dir.join("doc"));
+    fs::create_dir_all(&out_dir).context(format!("Failed to create '{}'", out_dir.display()))?;
+
+    let mut docs = Vec::new();
+    for file in &files {
+        let source = fs::read_to_string(file)
+            .context(format!("Failed to read file '{}'", file.display()))?;
+        let class = parser::parse(&source)
+            .map_err(|e| in_file(file, e))
+            .fail(Failure::Parse)?;
+        docs.push((file, ClassDoc::new(&class, &source)));
+    }
+    docs.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+
+    let known: Vec<&str> = docs.iter().map(|(_, doc)| doc.name.as_str()).collect();
+    let extension = args.format.extension();
+    for (file, doc) in &docs {
+        let output = out_dir.join(format!("{}.{}", doc.name, extension));
+        write_output(file, &output, &render_class(doc, &known, args.format))?;
+    }
+    // 一覧の題はディレクトリ名
+    let title = fs::canonicalize(&dir)
+        .ok()
+        .and_then(|dir| Some(dir.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "API".to_string());
+    let docs: Vec<ClassDoc> = docs.into_iter().map(|(_, doc)| doc).collect();
+    let index = out_dir.join(format!("index.{}", extension));
+    fs::write(&index, render_index(&title, &docs, args.format))
+        .context(format!("Failed to write '{}'", index.display()))?;
+    status!("-> {}", index.display());
+    Ok(())
+}
+
+// 整形して書き戻す。--check なら整形されていないファイルを表示して失敗する
+fn jackfmt(args: &JackfmtArgs) -> Result<()> {
+    let mut unformatted = 0;
+    for input in &args.inputs {
+        for file in jack_files(input)? {
+            let source = fs::read_to_string(&file)
+                .context(format!("Failed to read file '{}'", file.display()))?;
+            let formatted = format::format(&source)
+                .map_err(|e| in_file(&file, e))
+                .fail(Failure::Parse)?;
+            if formatted == source {
+                continue;
+            }
+            if args.check {
+                println!("{}", file.display());
+                unformatted += 1;
+            } else {
+                fs::write(&file, formatted)
+                    .context(format!("Failed to write '{}'", file.display()))?;
+                status!("Formatted {}", file.display());
+            }
+        }
+    }
+    if unformatted > 0 {
+        return Err(anyhow!("{} files are not formatted", unformatted));
+    }
+    Ok(())
+}
+
+// 標準エラーに表示し（--quiet なら、失敗の理由にならない警告は表示しない）、エラーと警告の数を返す。
+// --sarif と --ci にはすべて数える
+fn report(file: &Path, diagnostics: &[Diagnostic], show_warnings: bool) -> (usize, usize) {
+    for diagnostic in diagnostics {
+        sarif::record(diagnostic.finding(file));
+    }
+    let errors: Vec<Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.is_error())
+        .cloned()
+        .collect();
+    let shown = if is_quiet() && !show_warnings {
+        &errors
+    } else {
+        diagnostics
+    };
+    emit_diagnostics(file, shown);
+    let errors = errors.len();
+    let warnings = diagnostics.len() - errors;
+    ci::count(&file.display().to_string(), errors, warnings);
+    (errors, warnings)
+}
+
+// 警告で失敗するなら、--quiet でも警告を表示する
+fn warnings_fail(cli: &Cli) -> bool {
+    cli.deny_warnings || cli.ci.fail_on_warning
+}
+
+// --ci の表の大きさ（コメントを除いた VM コマンドの数）
+fn count_commands(input: &Path, code: &str) {
+    let commands = code
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .count();
+    ci::output(&input.display().to_string(), commands);
+}
+
+fn write_output(input: &Path, output: &Path, text: &str) -> Result<()> {
+    fs::write(output, text).context(format!("Failed to write '{}'", output.display()))?;
+    status!("{} -> {}", input.display(), output.display());
+    Ok(())
+}
+
+// Main.jack: Line 3, column 5: ...（複数行のエラーは各行に付ける）
+fn in_file(file: &Path, error: Error) -> Error {
+    let lines: Vec<String> = error
+        .to_string()
+        .lines()
+        .map(|line| format!("{}: {}", file.display(), line))
+        .collect();
+    anyhow!("{}", lines.join("\n"))
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::*,
+    symbols::{Kind, SymbolTable},
+};
+
+// インライン展開する式の項の数の上限
+const INLINE_TERMS: usize = 4;
+// 展開先で引数（メソッドは this も）を置く temp 2 から temp 7 まで
+const INLINE_ARGUMENTS: usize = 6;
+
+// -O: 定数式を畳み、+ 0 や * 1 を消す。値は Hack と同じ16ビットで計算する
+pub fn fold_class(class: &mut Class) {
+    for subroutine in &mut class.subroutines {
+        fold_statements(&mut subroutine.body);
+    }
+}
+
+fn fold_statements(statements: &mut [Statement]) {
+    for statement in statements {
+        match &mut statement.kind {
+            StatementKind::Let { index, value, .. } => {
+                if let Some(index) = index {
+                    fold_expression(index);
+                }
+                fold_expression(value);
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                fold_expression(condition);
+                fold_statements(then_branch);
+                if let Some(else_branch) = else_branch {
+                    fold_statements(else_branch);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                fold_expression(condition);
+                fold_statements(body);
+            }
+            StatementKind::Do(call) => fold_call(call),
+            StatementKind::Error => {}
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    fold_expression(value);
+                }
+            }
+        }
+    }
+}
+
+fn fold_call(call: &mut SubroutineCall) {
+    for argument in &mut call.arguments {
+        fold_expression(argument);
+    }
+}
+
+pub fn fold_expression(expression: &mut Expression) {
+    fold_term(&mut expression.term);
+    for (_, term) in &mut expression.rest {
+        fold_term(term);
+    }
+
+    // 先頭から続く定数をまとめる
+    let mut rest = std::mem::take(&mut expression.rest).into_iter().peekable();
+    while let Some(value) = constant(&expression.term) {
+        let Some(folded) = rest
+            .peek()
+            .and_then(|(op, term)| apply(*op, value, constant(term)?))
+        else {
+            break;
+        };
+        rest.next();
+        expression.term = constant_term(folded);
+    }
+    let mut rest: Vec<(BinaryOp, Term)> = rest.collect();
+
+    // c + x は x + c にして、後ろの定数とまとめられるようにする。0 + x や 1 * x は x だけにする
+    if constant(&expression.term).is_some()
+        && let Some((op @ (BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or), term)) =
+            rest.first_mut()
+    {
+        std::mem::swap(&mut expression.term, term);
+        if is_identity(*op, constant(term)) {
+            rest.remove(0);
+        }
+    }
+
+    // x + 1 - 3 は x - 2 に、x * 2 * 4 は x * 8 にする。(x + a) + b = x + (a + b) は
+    // 16ビットの剰余でも成り立つ
+    let mut folded = Vec::new();
+    let mut pending: Option<(BinaryOp, i16)> = None;
+    for (op, term) in rest {
+        pending = match (op, constant(&term), pending) {
+            (BinaryOp::Add | BinaryOp::Sub, Some(value), Some((BinaryOp::Add, sum))) => {
+                Some((BinaryOp::Add, sum.wrapping_add(offset(op, value))))
+            }
+            (BinaryOp::Mul, Some(value), Some((BinaryOp::Mul, product))) => {
+                Some((BinaryOp::Mul, product.wrapping_mul(value)))
+            }
+            (BinaryOp::Add | BinaryOp::Sub, Some(value), _) => {
+                flush(&mut folded, pending);
+                Some((BinaryOp::Add, offset(op, value)))
+            }
+            (BinaryOp::Mul, Some(value), _) => {
+                flush(&mut folded, pending);
+                Some((BinaryOp::Mul, value))
+            }
+            _ => {
+                flush(&mut folded, pending);
+                if !is_identity(op, constant(&term)) {
+                    folded.push((op, term));
+                }
+                None
+            }
+        };
+    }
+    flush(&mut folded, pending);
+    expression.rest = folded;
+}
+
+fn fold_term(term: &mut Term) {
+    match term {
+        Term::Index(_, index) => fold_expression(index),
+        Term::Call(call) => fold_call(call),
+        Term::Parenthesized(expression) => {
+            fold_expression(expression);
+            // 項が1つだけなら括弧はいらない
+            if expression.rest.is_empty() {
+                *term = expression.term.clone();
+            }
+        }
+        Term::Unary(op, inner) => {
+            fold_term(inner);
+            if let Some(value) = constant(inner) {
+                *term = constant_term(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                });
+            }
+        }
+        Term::Integer(_)
+        | Term::String(_)
+        | Term::Keyword(_)
+        | Term::Variable(_)
+        | Term::Error(_) => {}
+    }
+}
+
+// 定数の項の値。null はポインタなので数に数えない
+fn constant(term: &Term) -> Option<i16> {
+    match term {
+        Term::Integer(value) => Some(*value as i16),
+        Term::Keyword(KeywordConstant::True) => Some(-1),
+        Term::Keyword(KeywordConstant::False) => Some(0),
+        Term::Unary(UnaryOp::Neg, inner) => constant(inner).map(i16::wrapping_neg),
+        Term::Unary(UnaryOp::Not, inner) => constant(inner).map(|value| !value),
+        _ => None,
+    }
+}
+
+fn constant_term(value: i16) -> Term {
+    match value {
+        0.. => Term::Integer(value as u16),
+        // -32768 は push constant で書けないので ~32767 にする
+        i16::MIN => Term::Unary(UnaryOp::Not, Box::new(Term::Integer(i16::MAX as u16))),
+        _ => Term::Unary(UnaryOp::Neg, Box::new(Term::Integer(value.unsigned_abs()))),
+    }
+}
+
+// 比較は Hack と同じく真を -1、偽を 0 にする。0 での割り算は実行時のエラーなので残す
+fn apply(op: BinaryOp, a: i16, b: i16) -> Option<i16> {
+    Some(match op {
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        BinaryOp::Div if b == 0 => return None,
+        BinaryOp::Div => a.wrapping_div(b),
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Lt => -((a < b) as i16),
+        BinaryOp::Gt => -((a > b) as i16),
+        BinaryOp::Eq => -((a == b) as i16),
+    })
+}
+
+fn offset(op: BinaryOp, value: i16) -> i16 {
+    if op == BinaryOp::Sub {
+        value.wrapping_neg()
+    } else {
+        value
+    }
+}
+
+// 結果を変えない演算
+fn is_identity(op: BinaryOp, value: Option<i16>) -> bool {
+    matches!(
+        (op, value),
+        (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Or, Some(0))
+            | (BinaryOp::Mul | BinaryOp::Div, Some(1))
+            | (BinaryOp::And, Some(-1))
+    )
+}
+
+fn flush(rest: &mut Vec<(BinaryOp, Term)>, pending: Option<(BinaryOp, i16)>) {
+    match pending {
+        None | Some((BinaryOp::Add, 0)) | Some((BinaryOp::Mul, 1)) => {}
+        Some((BinaryOp::Add, sum)) if sum < 0 && sum != i16::MIN => {
+            rest.push((BinaryOp::Sub, constant_term(sum.wrapping_neg())))
+        }
+        Some((op, value)) => rest.push((op, constant_term(value))),
+    }
+}
+
+// -O2: return 式 1 つだけの小さなサブルーチン。呼び出し元に式を展開する
+#[derive(Debug, Clone)]
+pub struct Inline {
+    pub kind: SubroutineKind,
+    // 式の変数を引くための、呼ばれる側のシンボルテーブル
+    pub symbols: SymbolTable,
+    pub body: Expression,
+    // static は VM ファイルごとなので、同じクラスからしか展開できない
+    pub uses_static: bool,
+}
+
+// Class.name から展開できるサブルーチンへの表
+pub fn inline_candidates(classes: &[Class]) -> HashMap<String, Inline> {
+    let mut candidates = HashMap::new();
+    for class in classes {
+        let Ok(mut symbols) = SymbolTable::for_class(class) else {
+            continue;
+        };
+        for subroutine in &class.subroutines {
+            let [
+                Statement {
+                    kind: StatementKind::Return(Some(body)),
+                    ..
+                },
+            ] = subroutine.body.as_slice()
+            else {
+                continue;
+            };
+            let arguments =
+                subroutine.parameters.len() + (subroutine.kind == SubroutineKind::Method) as usize;
+            if subroutine.kind == SubroutineKind::Constructor
+                || !subroutine.locals.is_empty()
+                || arguments > INLINE_ARGUMENTS
+                || symbols
+                    .enter_subroutine(&class.name.name, subroutine)
+                    .is_err()
+            {
+                continue;
+            }
+            let mut terms = 0;
+            if !inlinable_expression(body, &mut terms) || terms > INLINE_TERMS {
+                continue;
+            }
+            let mut names = Vec::new();
+            expression_names(body, &mut names);
+            let uses_static = names.iter().any(|name| {
+                symbols
+                    .lookup(name)
+                    .is_some_and(|symbol| symbol.kind == Kind::Static)
+            });
+            candidates.insert(
+                format!("{}.{}", class.name.name, subroutine.name.name),
+                Inline {
+                    kind: subroutine.kind,
+                    symbols: symbols.clone(),
+                    body: body.clone(),
+                    uses_static,
+                },
+            );
+        }
+    }
+    candidates
+}
+
+// 呼び出しを含まない式だけを展開する。* と / も Math の呼び出しになり、
+// 呼ばれた先で temp が書き換えられるかもしれないので除く
+fn inlinable_expression(expression: &Expression, terms: &mut usize) -> bool {
+    inlinable_term(&expression.term, terms)
+        && expression.rest.iter().all(|(op, term)| {
+            !matches!(op, BinaryOp::Mul | BinaryOp::Div) && inlinable_term(term, terms)
+        })
+}
+
+fn inlinable_term(term: &Term, terms: &mut usize) -> bool {
+    *terms += 1;
+    match term {
+        Term::Integer(_) | Term::Keyword(_) | Term::Variable(_) => true,
+        Term::Index(_, index) => inlinable_expression(index, terms),
+        Term::Parenthesized(expression) => inlinable_expression(expression, terms),
+        Term::Unary(_, term) => inlinable_term(term, terms),
+        Term::String(_) | Term::Call(_) | Term::Error(_) => false,
+    }
+}
+
+fn expression_names<'a>(expression: &'a Expression, names: &mut Vec<&'a str>) {
+    term_names(&expression.term, names);
+    for (_, term) in &expression.rest {
+        term_names(term, names);
+    }
+}
+
+fn term_names<'a>(term: &'a Term, names: &mut Vec<&'a str>) {
+    match term {
+        Term::Variable(name) => names.push(&name.name),
+        Term::Index(name, index) => {
+            names.push(&name.name);
+            expression_names(index, names);
+        }
+        Term::Parenthesized(expression) => expression_names(expression, names),
+        Term::Unary(_, term) => term_names(term, names),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, tokenizer::tokenize, xml};
+    use rstest::rstest;
+
+    // 畳んだ式を Jack の式として書き戻す
+    fn source(expression: &Expression) -> String {
+        let mut out = term_source(&expression.term);
+        for (op, term) in &expression.rest {
+            out += &format!(" {} {}", op.symbol(), term_source(term));
+        }
+        out
+    }
+
+    fn term_source(term: &Term) -> String {
+        match term {
+            Term::Integer(value) => value.to_string(),
+            Term::Keyword(keyword) => keyword.as_str().to_string(),
+            Term::Variable(name) => name.name.clone(),
+            Term::Index(name, index) => format!("{}[{}]", name.name, source(index)),
+            Term::Call(call) => {
+                let arguments: Vec<String> = call.arguments.iter().map(source).collect();
+                format!("{}({})", call.name.name, arguments.join(", "))
+            }
+            Term::Parenthesized(expression) => format!("({})", source(expression)),
+            Term::Unary(op, term) => format!("{}{}", op.symbol(), term_source(term)),
+            Term::String(_) | Term::Error(_) => unreachable!(),
+        }
+    }
+
+    #[rstest]
+    #[case("1 + 2 * 3", "9")]
+    #[case("x + (2 * 3)", "x + 6")]
+    #[case("x + 0", "x")]
+    #[case("x * 1 / 1", "x")]
+    #[case("0 + x * 2", "x * 2")]
+    #[case("1 * x", "x")]
+    #[case("3 + x", "x + 3")]
+    #[case("x + 1 - 3", "x - 2")]
+    #[case("x * 2 * 4 + 1 - 1", "x * 8")]
+    #[case("x - 2 + 2 * y", "x * y")]
+    #[case("2 - x", "2 - x")]
+    #[case("-(3 - 5)", "2")]
+    #[case("0 - 32767 - 1", "~32767")]
+    #[case("~(1 = 1)", "0")]
+    #[case("(2 < 3) & x", "x")]
+    #[case("x | (1 > 2)", "x")]
+    #[case("7 / 0", "7 / 0")]
+    #[case("a[1 + 1] + f(2 * 2)", "a[2] + f(4)")]
+    fn test_fold_expression(#[case] input: &str, #[case] expected: &str) {
+        let mut expression = Parser::new(tokenize(input).unwrap()).expression().unwrap();
+        fold_expression(&mut expression);
+        assert_eq!(source(&expression), expected);
+    }
+
+    #[test]
+    fn test_fold_class() {
+        let mut class = crate::parser::parse(
+            "class Main { function void main() { var int x; \
+             while (x < (10 * 10)) { let x = x + 0; } do Output.printInt(1 + 1); return; } }",
+        )
+        .unwrap();
+        fold_class(&mut class);
+        let folded = xml::class_xml(&class);
+        assert!(folded.contains("<integerConstant> 100 </integerConstant>"));
+        assert!(folded.contains("<integerConstant> 2 </integerConstant>"));
+        assert!(!folded.contains("<integerConstant> 0 </integerConstant>"));
+    }
+
+    #[test]
+    fn test_inline_candidates() {
+        let class = crate::parser::parse(
+            "class A { field int x; \
+             method int getX() { return x; } \
+             function int twice(int n) { return n + n; } \
+             function int scaled(int n) { return n * 3; } \
+             function int big(int n) { return n + n + n + n + n; } \
+             function int call() { return A.twice(1); } \
+             function int local() { var int v; return v; } \
+             constructor A new() { return this; } }",
+        )
+        .unwrap();
+        let candidates = inline_candidates(&[class]);
+        let mut names: Vec<&str> = candidates.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["A.getX", "A.twice"]);
+    }
+}
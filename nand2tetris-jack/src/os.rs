@@ -0,0 +1,128 @@
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::{
+    analysis,
+    ast::{Class, StatementKind, Term},
+    diagnostic::into_result,
+    generate, parser,
+};
+
+// 同梱の Jack OS のソース（クラス名, ソース）
+pub const OS_SOURCES: &[(&str, &str)] = &[
+    ("Array", include_str!("../os/Array.jack")),
+    ("Keyboard", include_str!("../os/Keyboard.jack")),
+    ("Math", include_str!("../os/Math.jack")),
+    ("Memory", include_str!("../os/Memory.jack")),
+    ("Output", include_str!("../os/Output.jack")),
+    ("Screen", include_str!("../os/Screen.jack")),
+    ("String", include_str!("../os/String.jack")),
+    ("Sys", include_str!("../os/Sys.jack")),
+];
+
+pub fn is_os_class(name: &str) -> bool {
+    OS_SOURCES.iter().any(|&(class, _)| class == name)
+}
+
+pub fn os_classes() -> Result<Vec<Class>> {
+    OS_SOURCES
+        .iter()
+        .map(|&(name, source)| parser::parse(source).map_err(|e| anyhow!("{}.jack: {}", name, e)))
+        .collect()
+}
+
+// OS の全クラスを VM コードにする（クラス名, VM コード）。level は generate と同じ
+pub fn compile_os(level: u8) -> Result<Vec<(String, String)>> {
+    let classes = os_classes()?;
+    let signatures = analysis::Signatures::new(&classes);
+    classes
+        .iter()
+        .map(|class| {
+            let name = &class.name.name;
+            into_result((), &analysis::check_class(class, &signatures))
+                .and_then(|_| generate(class, &classes, level))
+                .map(|code| (name.clone(), code))
+                .map_err(|e| anyhow!("{}.jack: {}", name, e))
+        })
+        .collect()
+}
+
+// Output.initMap の Output.glyphs 呼び出しから読んだ字形。k 番目は字 31 + k の 11 行で、
+// 0 番目は表にない字。各行の最下位ビットが左端（エミュレータの Output の代わりに使う）
+pub fn font() -> Result<Vec<[u8; 11]>> {
+    let source = OS_SOURCES
+        .iter()
+        .find(|&&(name, _)| name == "Output")
+        .map(|&(_, source)| source)
+        .context("Output.jack is not bundled")?;
+    let class = parser::parse(source)?;
+    let init_map = class
+        .subroutines
+        .iter()
+        .find(|subroutine| subroutine.name.name == "initMap")
+        .context("Output.initMap not found")?;
+
+    // 1 字 6 語で、各語に 2 行を詰めてある
+    let mut packed = vec![0u16; 96 * 6];
+    for statement in &init_map.body {
+        let StatementKind::Do(call) = &statement.kind else {
+            continue;
+        };
+        if call.name.name != "glyphs" {
+            continue;
+        }
+        let mut values = Vec::new();
+        for argument in &call.arguments {
+            match (&argument.term, argument.rest.is_empty()) {
+                (Term::Integer(value), true) => values.push(*value),
+                _ => bail!("Output.glyphs expects integer constants"),
+            }
+        }
+        let Some((&slot, words)) = values.split_first() else {
+            bail!("Output.glyphs expects a slot");
+        };
+        let start = slot as usize * 6;
+        packed
+            .get_mut(start..start + words.len())
+            .context("Output.glyphs slot out of range")?
+            .copy_from_slice(words);
+    }
+
+    Ok(packed
+        .chunks(6)
+        .map(|words| {
+            let mut rows = [0; 11];
+            for (i, row) in rows.iter_mut().enumerate() {
+                *row = (words[i / 2] >> (8 * (i % 2))) as u8;
+            }
+            rows
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::lint_class;
+
+    #[test]
+    fn test_os_compiles_cleanly() {
+        for class in os_classes().unwrap() {
+            let warnings: Vec<String> = lint_class(&class).iter().map(|d| d.to_string()).collect();
+            assert!(warnings.is_empty(), "{}: {:?}", class.name.name, warnings);
+        }
+        for level in 0..=2 {
+            let compiled = compile_os(level).unwrap();
+            let names: Vec<&str> = compiled.iter().map(|(name, _)| name.as_str()).collect();
+            assert_eq!(
+                names,
+                OS_SOURCES.iter().map(|&(name, _)| name).collect::<Vec<_>>()
+            );
+        }
+        assert!(is_os_class("Math"));
+        let font = font().unwrap();
+        assert_eq!(font.len(), 96);
+        // '!' は縦棒と点
+        assert_eq!(font[2], [0, 8, 8, 8, 8, 8, 8, 0, 8, 0, 0]);
+        assert!(!is_os_class("Main"));
+    }
+}
@@ -0,0 +1,694 @@
+use anyhow::Result;
+
+use crate::{
+    ast::*,
+    diagnostic::{Diagnostic, into_result},
+    tokenizer::{Keyword, Token, TokenKind, scan},
+};
+
+type ParseResult<T> = std::result::Result<T, Diagnostic>;
+
+// 最初のエラーで止めず、見つかったエラーを全部まとめて返す
+pub fn parse(source: &str) -> Result<Class> {
+    let (class, diagnostics) = parse_with_diagnostics(source);
+    let class = into_result(class, &diagnostics)?;
+    Ok(class.expect("a missing class is always reported"))
+}
+
+// 構文エラーのあとも文やクラスのメンバーの区切りから読み続ける。
+// class の宣言が読めなければ None
+pub fn parse_with_diagnostics(source: &str) -> (Option<Class>, Vec<Diagnostic>) {
+    let (tokens, mut diagnostics) = scan(source);
+    let mut parser = Parser::new(tokens);
+    let class = parser.class();
+    if class.is_ok() && parser.peek().is_some() {
+        let error = parser.error("end of file");
+        parser.diagnostics.push(error);
+    }
+    let class = class.map_err(|error| parser.diagnostics.push(error)).ok();
+    diagnostics.append(&mut parser.diagnostics);
+    (class, diagnostics)
+}
+
+// エディタ向け。途中までのコードでも必ずクラスを返す。足りないトークンは読んだことにし、
+// 名前は空の識別子、項は Term::Error、読めなかった文は StatementKind::Error で埋める
+pub fn parse_tolerant(source: &str) -> (Class, Vec<Diagnostic>) {
+    let (tokens, mut diagnostics) = scan(source);
+    let mut parser = Parser::tolerant(tokens);
+    let class = parser.class().expect("tolerant parsing never fails");
+    if parser.peek().is_some() {
+        let error = parser.error("end of file");
+        parser.diagnostics.push(error);
+    }
+    diagnostics.append(&mut parser.diagnostics);
+    (class, diagnostics)
+}
+
+// 再帰下降。Jack は LL(1) だが、項の識別子だけ2トークン先を見る
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // 読み飛ばして回復したエラー
+    diagnostics: Vec<Diagnostic>,
+    // parse_tolerant のとき、足りないトークンをエラーにせず補う
+    tolerant: bool,
+}
+
+const MEMBER_KEYWORDS: [Keyword; 5] = [
+    Keyword::Static,
+    Keyword::Field,
+    Keyword::Constructor,
+    Keyword::Function,
+    Keyword::Method,
+];
+
+const STATEMENT_KEYWORDS: [Keyword; 5] = [
+    Keyword::Let,
+    Keyword::If,
+    Keyword::While,
+    Keyword::Do,
+    Keyword::Return,
+];
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            diagnostics: Vec::new(),
+            tolerant: false,
+        }
+    }
+
+    pub fn tolerant(tokens: Vec<Token>) -> Self {
+        Parser {
+            tolerant: true,
+            ..Parser::new(tokens)
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn is_any_keyword(&self, keywords: &[Keyword]) -> bool {
+        matches!(self.peek(), Some(TokenKind::Keyword(k)) if keywords.contains(k))
+    }
+
+    // 文の途中のエラーから、次の文の先頭か ';' の後ろか '}' まで進む
+    fn recover_statement(&mut self, start: usize) {
+        if self.pos == start {
+            self.pos += 1;
+        }
+        while let Some(kind) = self.peek() {
+            match kind {
+                TokenKind::Symbol(';') => {
+                    self.pos += 1;
+                    return;
+                }
+                TokenKind::Symbol('}') => return,
+                _ if self.is_any_keyword(&STATEMENT_KEYWORDS) => return,
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    // メンバーの宣言のエラーから、括弧の外にある次のメンバーかクラスの '}' まで進む
+    fn recover_member(&mut self, start: usize) {
+        if self.pos == start {
+            self.pos += 1;
+        }
+        let mut depth = 0usize;
+        while let Some(kind) = self.peek() {
+            match kind {
+                TokenKind::Symbol('{') => depth += 1,
+                TokenKind::Symbol('}') if depth == 0 => return,
+                TokenKind::Symbol('}') => depth -= 1,
+                _ if depth == 0 && self.is_any_keyword(&MEMBER_KEYWORDS) => return,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn peek_second(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos + 1).map(|token| &token.kind)
+    }
+
+    fn position(&self) -> Position {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|token| Position {
+                line: token.line,
+                column: token.column,
+            })
+            .unwrap_or(Position { line: 1, column: 1 })
+    }
+
+    fn error(&self, expected: &str) -> Diagnostic {
+        let message = match self.tokens.get(self.pos) {
+            Some(token) => format!("Expected {} but found '{}'", expected, token.text()),
+            None => format!("Expected {} but reached the end of the file", expected),
+        };
+        Diagnostic::error(self.position(), message)
+    }
+
+    // expected がなかった。寛容モードでは記録して続ける。同じ位置で続けて見つかったものは
+    // 最初の 1 つだけ
+    fn missing(&mut self, expected: &str) -> ParseResult<()> {
+        let error = self.error(expected);
+        if !self.tolerant {
+            return Err(error);
+        }
+        if self.diagnostics.last().map(|last| last.position) != Some(error.position) {
+            self.diagnostics.push(error);
+        }
+        Ok(())
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn is_keyword(&self, keyword: Keyword) -> bool {
+        self.peek() == Some(&TokenKind::Keyword(keyword))
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        let found = self.is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> ParseResult<()> {
+        if !self.eat_symbol(symbol) {
+            return self.missing(&format!("'{}'", symbol));
+        }
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> ParseResult<Position> {
+        let position = self.position();
+        if !self.is_keyword(keyword) {
+            self.missing(&format!("'{}'", keyword))?;
+            return Ok(position);
+        }
+        self.pos += 1;
+        Ok(position)
+    }
+
+    fn identifier(&mut self) -> ParseResult<Identifier> {
+        let position = self.position();
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(Identifier { name, position })
+            }
+            _ => {
+                self.missing("an identifier")?;
+                Ok(Identifier {
+                    name: String::new(),
+                    position,
+                })
+            }
+        }
+    }
+
+    fn ty(&mut self) -> ParseResult<Type> {
+        let ty = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Int)) => Type::Int,
+            Some(TokenKind::Keyword(Keyword::Char)) => Type::Char,
+            Some(TokenKind::Keyword(Keyword::Boolean)) => Type::Boolean,
+            Some(TokenKind::Identifier(name)) => Type::Class(name.clone()),
+            _ => {
+                self.missing("a type")?;
+                return Ok(Type::Class(String::new()));
+            }
+        };
+        self.pos += 1;
+        Ok(ty)
+    }
+
+    // 'class' className '{' classVarDec* subroutineDec* '}'
+    pub fn class(&mut self) -> ParseResult<Class> {
+        self.expect_keyword(Keyword::Class)?;
+        let name = self.identifier()?;
+        self.expect_symbol('{')?;
+
+        let mut vars = Vec::new();
+        let mut subroutines = Vec::new();
+        while self.peek().is_some() && !self.is_symbol('}') {
+            let start = self.pos;
+            let member = match self.peek() {
+                Some(TokenKind::Keyword(Keyword::Static)) => self
+                    .class_var(ClassVarKind::Static)
+                    .map(|var| vars.push(var)),
+                Some(TokenKind::Keyword(Keyword::Field)) => self
+                    .class_var(ClassVarKind::Field)
+                    .map(|var| vars.push(var)),
+                _ => self
+                    .subroutine()
+                    .map(|subroutine| subroutines.push(subroutine)),
+            };
+            if let Err(error) = member {
+                self.diagnostics.push(error);
+                self.recover_member(start);
+            }
+        }
+        // 閉じ括弧がなくても読めたメンバーは返す。
+        // 途中のエラーでファイルの終わりまで読んだなら、それ以上は報告しない
+        if !self.eat_symbol('}') && (self.peek().is_some() || self.diagnostics.is_empty()) {
+            let error = self.error("'}'");
+            self.diagnostics.push(error);
+        }
+
+        Ok(Class {
+            name,
+            vars,
+            subroutines,
+        })
+    }
+
+    fn class_var(&mut self, kind: ClassVarKind) -> ParseResult<ClassVarDec> {
+        self.pos += 1;
+        let (ty, names) = self.var_names()?;
+        Ok(ClassVarDec { kind, ty, names })
+    }
+
+    // type varName (',' varName)* ';'
+    fn var_names(&mut self) -> ParseResult<(Type, Vec<Identifier>)> {
+        let ty = self.ty()?;
+        let mut names = vec![self.identifier()?];
+        while self.eat_symbol(',') {
+            names.push(self.identifier()?);
+        }
+        self.expect_symbol(';')?;
+        Ok((ty, names))
+    }
+
+    fn subroutine(&mut self) -> ParseResult<Subroutine> {
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Constructor)) => SubroutineKind::Constructor,
+            Some(TokenKind::Keyword(Keyword::Function)) => SubroutineKind::Function,
+            Some(TokenKind::Keyword(Keyword::Method)) => SubroutineKind::Method,
+            _ => return Err(self.error("a subroutine declaration or '}'")),
+        };
+        self.pos += 1;
+        let return_type = if self.is_keyword(Keyword::Void) {
+            self.pos += 1;
+            None
+        } else {
+            Some(self.ty()?)
+        };
+        let name = self.identifier()?;
+
+        self.expect_symbol('(')?;
+        let mut parameters = Vec::new();
+        if !self.is_symbol(')') {
+            loop {
+                let ty = self.ty()?;
+                let name = self.identifier()?;
+                parameters.push(Parameter { ty, name });
+                if !self.eat_symbol(',') {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(')')?;
+
+        self.expect_symbol('{')?;
+        let mut locals = Vec::new();
+        while self.is_keyword(Keyword::Var) {
+            let start = self.pos;
+            self.pos += 1;
+            match self.var_names() {
+                Ok((ty, names)) => locals.push(VarDec { ty, names }),
+                Err(error) => {
+                    self.diagnostics.push(error);
+                    self.recover_statement(start);
+                }
+            }
+        }
+        let body = self.statements();
+        self.expect_symbol('}')?;
+
+        Ok(Subroutine {
+            kind,
+            return_type,
+            name,
+            parameters,
+            locals,
+            body,
+        })
+    }
+
+    // '}' までの文
+    fn statements(&mut self) -> Vec<Statement> {
+        let mut statements = Vec::new();
+        while self.peek().is_some() && !self.is_symbol('}') {
+            let start = self.pos;
+            match self.statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    if self.tolerant {
+                        statements.push(Statement {
+                            kind: StatementKind::Error,
+                            position: error.position,
+                        });
+                    }
+                    self.diagnostics.push(error);
+                    self.recover_statement(start);
+                }
+            }
+        }
+        statements
+    }
+
+    fn block(&mut self) -> ParseResult<Vec<Statement>> {
+        self.expect_symbol('{')?;
+        let statements = self.statements();
+        self.expect_symbol('}')?;
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> ParseResult<Statement> {
+        let position = self.position();
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Let)) => {
+                self.pos += 1;
+                let name = self.identifier()?;
+                let index = if self.eat_symbol('[') {
+                    let index = self.expression()?;
+                    self.expect_symbol(']')?;
+                    Some(index)
+                } else {
+                    None
+                };
+                self.expect_symbol('=')?;
+                let value = self.expression()?;
+                self.expect_symbol(';')?;
+                StatementKind::Let { name, index, value }
+            }
+            Some(TokenKind::Keyword(Keyword::If)) => {
+                self.pos += 1;
+                self.expect_symbol('(')?;
+                let condition = self.expression()?;
+                self.expect_symbol(')')?;
+                let then_branch = self.block()?;
+                let else_branch = if self.is_keyword(Keyword::Else) {
+                    self.pos += 1;
+                    Some(self.block()?)
+                } else {
+                    None
+                };
+                StatementKind::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                }
+            }
+            Some(TokenKind::Keyword(Keyword::While)) => {
+                self.pos += 1;
+                self.expect_symbol('(')?;
+                let condition = self.expression()?;
+                self.expect_symbol(')')?;
+                let body = self.block()?;
+                StatementKind::While { condition, body }
+            }
+            Some(TokenKind::Keyword(Keyword::Do)) => {
+                self.pos += 1;
+                let name = self.identifier()?;
+                let call = self.call(name)?;
+                self.expect_symbol(';')?;
+                StatementKind::Do(call)
+            }
+            Some(TokenKind::Keyword(Keyword::Return)) => {
+                self.pos += 1;
+                let value = if self.is_symbol(';') {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                self.expect_symbol(';')?;
+                StatementKind::Return(value)
+            }
+            _ => return Err(self.error("a statement")),
+        };
+        Ok(Statement { kind, position })
+    }
+
+    pub fn expression(&mut self) -> ParseResult<Expression> {
+        let term = self.term()?;
+        let mut rest = Vec::new();
+        while let Some(op) = match self.peek() {
+            Some(TokenKind::Symbol(symbol)) => BinaryOp::from_symbol(*symbol),
+            _ => None,
+        } {
+            self.pos += 1;
+            rest.push((op, self.term()?));
+        }
+        Ok(Expression { term, rest })
+    }
+
+    fn term(&mut self) -> ParseResult<Term> {
+        let term = match self.peek() {
+            Some(TokenKind::IntegerConstant(value)) => Term::Integer(*value),
+            Some(TokenKind::StringConstant(text)) => Term::String(text.clone()),
+            Some(TokenKind::Keyword(Keyword::True)) => Term::Keyword(KeywordConstant::True),
+            Some(TokenKind::Keyword(Keyword::False)) => Term::Keyword(KeywordConstant::False),
+            Some(TokenKind::Keyword(Keyword::Null)) => Term::Keyword(KeywordConstant::Null),
+            Some(TokenKind::Keyword(Keyword::This)) => Term::Keyword(KeywordConstant::This),
+            Some(TokenKind::Symbol('(')) => {
+                self.pos += 1;
+                let expression = self.expression()?;
+                self.expect_symbol(')')?;
+                return Ok(Term::Parenthesized(Box::new(expression)));
+            }
+            Some(TokenKind::Symbol(symbol @ ('-' | '~'))) => {
+                let op = if *symbol == '-' {
+                    UnaryOp::Neg
+                } else {
+                    UnaryOp::Not
+                };
+                self.pos += 1;
+                return Ok(Term::Unary(op, Box::new(self.term()?)));
+            }
+            Some(TokenKind::Identifier(_)) => {
+                let is_call = matches!(self.peek_second(), Some(TokenKind::Symbol('(' | '.')));
+                let name = self.identifier()?;
+                if is_call {
+                    return Ok(Term::Call(self.call(name)?));
+                }
+                if self.eat_symbol('[') {
+                    let index = self.expression()?;
+                    self.expect_symbol(']')?;
+                    return Ok(Term::Index(name, Box::new(index)));
+                }
+                return Ok(Term::Variable(name));
+            }
+            _ => {
+                let position = self.position();
+                self.missing("an expression")?;
+                return Ok(Term::Error(position));
+            }
+        };
+        self.pos += 1;
+        Ok(term)
+    }
+
+    // first は読み終えた先頭の識別子
+    fn call(&mut self, first: Identifier) -> ParseResult<SubroutineCall> {
+        let (receiver, name) = if self.eat_symbol('.') {
+            (Some(first), self.identifier()?)
+        } else {
+            (None, first)
+        };
+        let mut arguments = Vec::new();
+        // 寛容モードで '(' がなければ引数なしの呼び出しにする
+        if !self.eat_symbol('(') {
+            self.missing("'('")?;
+            return Ok(SubroutineCall {
+                receiver,
+                name,
+                arguments,
+            });
+        }
+        if !self.is_symbol(')') {
+            arguments.push(self.expression()?);
+            while self.eat_symbol(',') {
+                arguments.push(self.expression()?);
+            }
+        }
+        self.expect_symbol(')')?;
+        Ok(SubroutineCall {
+            receiver,
+            name,
+            arguments,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn identifier(name: &str, line: usize, column: usize) -> Identifier {
+        Identifier {
+            name: name.to_string(),
+            position: Position { line, column },
+        }
+    }
+
+    #[test]
+    fn test_parse_class() {
+        let class = parse(
+            "class Point {\n\
+             field int x, y;\n\
+             static Point origin;\n\
+             method int getX() { return x; }\n\
+             function void main(int a, boolean b) {\n\
+             var Array v;\n\
+             let v[a] = -a + (b * 2);\n\
+             if (b) { do Output.printInt(v[0]); } else { do p.draw(); }\n\
+             while (~b) { do draw(); }\n\
+             return;\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+
+        assert_eq!(class.name.name, "Point");
+        assert_eq!(class.vars.len(), 2);
+        assert_eq!(class.vars[0].kind, ClassVarKind::Field);
+        assert_eq!(class.vars[0].names[1], identifier("y", 2, 14));
+        assert_eq!(class.vars[1].ty, Type::Class("Point".to_string()));
+
+        let main = &class.subroutines[1];
+        assert_eq!(main.kind, SubroutineKind::Function);
+        assert_eq!(main.return_type, None);
+        assert_eq!(main.parameters[1].ty, Type::Boolean);
+        assert_eq!(main.locals[0].names, [identifier("v", 6, 11)]);
+        assert_eq!(main.body.len(), 4);
+
+        let StatementKind::Let { name, index, value } = &main.body[0].kind else {
+            panic!("{:?}", main.body[0]);
+        };
+        assert_eq!(name.name, "v");
+        assert_eq!(
+            index.as_ref().unwrap().term,
+            Term::Variable(identifier("a", 7, 7))
+        );
+        assert_eq!(
+            value.term,
+            Term::Unary(
+                UnaryOp::Neg,
+                Box::new(Term::Variable(identifier("a", 7, 13)))
+            )
+        );
+        assert!(matches!(
+            value.rest[0],
+            (BinaryOp::Add, Term::Parenthesized(_))
+        ));
+
+        let StatementKind::If { else_branch, .. } = &main.body[1].kind else {
+            panic!("{:?}", main.body[1]);
+        };
+        let StatementKind::Do(call) = &else_branch.as_ref().unwrap()[0].kind else {
+            panic!("{:?}", else_branch);
+        };
+        assert_eq!(call.receiver.as_ref().unwrap().name, "p");
+        assert_eq!(call.name.name, "draw");
+        assert_eq!(
+            main.body[3].position,
+            Position {
+                line: 10,
+                column: 1
+            }
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "class A { function void f() { let x = 1 } }",
+        "Line 1, column 41: Expected ';' but found '}'"
+    )]
+    #[case(
+        "class A { int x; }",
+        "Line 1, column 11: Expected a subroutine declaration or '}' but found 'int'"
+    )]
+    #[case(
+        "class A { function void f() { foo(); } }",
+        "Line 1, column 31: Expected a statement but found 'foo'"
+    )]
+    #[case(
+        "class A { function void f() { return 1 + ; } }",
+        "Line 1, column 42: Expected an expression but found ';'"
+    )]
+    #[case(
+        "class A { function void f() {",
+        "Line 1, column 29: Expected '}' but reached the end of the file"
+    )]
+    #[case(
+        "class A { } class B { }",
+        "Line 1, column 13: Expected end of file but found 'class'"
+    )]
+    fn test_parse_error(#[case] source: &str, #[case] expected: &str) {
+        assert_eq!(parse(source).unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn test_parse_tolerant() {
+        let (class, diagnostics) = parse_tolerant(
+            "class Main {\n\
+             function void main() {\n\
+             var Game game;\n\
+             let game = ;\n\
+             foo;\n\
+             do game.\n\
+             }\n\
+             method void run(int",
+        );
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Line 4, column 12: Expected an expression but found ';'",
+                "Line 5, column 1: Expected a statement but found 'foo'",
+                "Line 7, column 1: Expected an identifier but found '}'",
+                "Line 8, column 17: Expected an identifier but reached the end of the file",
+            ]
+        );
+
+        let main = &class.subroutines[0];
+        let StatementKind::Let { value, .. } = &main.body[0].kind else {
+            panic!("{:?}", main.body[0]);
+        };
+        assert_eq!(
+            value.term,
+            Term::Error(Position {
+                line: 4,
+                column: 12
+            })
+        );
+        assert_eq!(main.body[1].kind, StatementKind::Error);
+        let StatementKind::Do(call) = &main.body[2].kind else {
+            panic!("{:?}", main.body[2]);
+        };
+        assert_eq!(call.receiver.as_ref().unwrap().name, "game");
+        assert_eq!(call.name, identifier("", 7, 1));
+
+        let run = &class.subroutines[1];
+        assert_eq!(run.name.name, "run");
+        assert_eq!(run.parameters[0].ty, Type::Int);
+        assert_eq!(run.parameters[0].name.name, "");
+    }
+}
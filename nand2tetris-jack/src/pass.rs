@@ -0,0 +1,304 @@
+// 解析とコード生成の間で構文木を書き換えるパス。--pass の名前から Registry で作り、
+// Pipeline が順に実行する
+//
+// Registry::builtin() のパス:
+//   rename:OLD=NEW  名前が OLD のクラス、サブルーチン、変数、型をすべて NEW にする
+//   count-calls     サブルーチンごとに static int calls_<名前> を足し、入るたびに 1 増やす
+//   fold            -O と同じく定数式を畳む
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::BTreeMap;
+
+use crate::{ast::*, fold::Fold, optimize};
+
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    // 全クラスをまとめて受け取る。クラスを足したり消したりはしない
+    fn run(&mut self, classes: &mut [Class]) -> Result<()>;
+}
+
+// パスの引数（rename:a=b の a=b）からパスを作る
+pub type PassFactory = fn(Option<&str>) -> Result<Box<dyn Pass>>;
+
+#[derive(Clone, Default)]
+pub struct Registry {
+    factories: BTreeMap<String, PassFactory>,
+}
+
+impl Registry {
+    pub fn builtin() -> Self {
+        let mut registry = Registry::default();
+        registry.register("rename", |argument| {
+            let argument = argument.context("Usage: rename:OLD=NEW")?;
+            let (from, to) = argument.split_once('=').context("Usage: rename:OLD=NEW")?;
+            Ok(Box::new(Rename::new(from, to)?))
+        });
+        registry.register("count-calls", |_| Ok(Box::new(CountCalls)));
+        registry.register("fold", |_| Ok(Box::new(FoldConstants)));
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, factory: PassFactory) {
+        self.factories.insert(name.to_string(), factory);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(|name| name.as_str())
+    }
+
+    // NAME か NAME:ARGUMENT
+    pub fn create(&self, spec: &str) -> Result<Box<dyn Pass>> {
+        let (name, argument) = match spec.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (spec, None),
+        };
+        let factory = self.factories.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.names().collect();
+            anyhow!("Unknown pass '{}' (available: {})", name, names.join(", "))
+        })?;
+        factory(argument).context(format!("Invalid pass '{}'", spec))
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn from_specs(registry: &Registry, specs: &[String]) -> Result<Self> {
+        let mut pipeline = Pipeline::new();
+        for spec in specs {
+            pipeline.add(registry.create(spec)?);
+        }
+        Ok(pipeline)
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) -> &mut Self {
+        self.passes.push(pass);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn run(&mut self, classes: &mut [Class]) -> Result<()> {
+        for pass in &mut self.passes {
+            let name = pass.name().to_string();
+            pass.run(classes)
+                .context(format!("Pass '{}' failed", name))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Rename {
+    from: String,
+    to: String,
+}
+
+impl Rename {
+    pub fn new(from: &str, to: &str) -> Result<Self> {
+        for name in [from, to] {
+            if !is_identifier(name) {
+                bail!("'{}' is not a Jack identifier", name);
+            }
+        }
+        Ok(Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+impl Pass for Rename {
+    fn name(&self) -> &str {
+        "rename"
+    }
+
+    fn run(&mut self, classes: &mut [Class]) -> Result<()> {
+        for class in classes.iter_mut() {
+            *class = self.fold_class(class.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Fold for Rename {
+    fn fold_identifier(&mut self, mut identifier: Identifier) -> Identifier {
+        if identifier.name == self.from {
+            identifier.name = self.to.clone();
+        }
+        identifier
+    }
+
+    fn fold_type(&mut self, ty: Type) -> Type {
+        match ty {
+            Type::Class(name) if name == self.from => Type::Class(self.to.clone()),
+            _ => ty,
+        }
+    }
+}
+
+// 英字か _ で始まり、英数字と _ が続く。キーワードは不可
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && crate::tokenizer::Keyword::from_word(name).is_none()
+}
+
+// 呼び出し回数はクラスの static calls_<名前> に数える。番号は既存の static の後ろ
+pub struct CountCalls;
+
+pub fn counter_name(subroutine: &str) -> String {
+    format!("calls_{}", subroutine)
+}
+
+impl Pass for CountCalls {
+    fn name(&self) -> &str {
+        "count-calls"
+    }
+
+    fn run(&mut self, classes: &mut [Class]) -> Result<()> {
+        for class in classes.iter_mut() {
+            let mut names = Vec::new();
+            for subroutine in &mut class.subroutines {
+                let name = Identifier {
+                    name: counter_name(&subroutine.name.name),
+                    position: subroutine.name.position,
+                };
+                // 同名の引数やローカル変数があると static を隠してしまう
+                let declared = class
+                    .vars
+                    .iter()
+                    .flat_map(|dec| &dec.names)
+                    .chain(subroutine.parameters.iter().map(|p| &p.name))
+                    .chain(subroutine.locals.iter().flat_map(|dec| &dec.names))
+                    .any(|declared| declared.name == name.name);
+                if declared {
+                    bail!(
+                        "'{}' is already declared in '{}.{}'",
+                        name.name,
+                        class.name.name,
+                        subroutine.name.name
+                    );
+                }
+                let increment = Statement {
+                    kind: StatementKind::Let {
+                        name: name.clone(),
+                        index: None,
+                        value: Expression {
+                            term: Term::Variable(name.clone()),
+                            rest: vec![(BinaryOp::Add, Term::Integer(1))],
+                        },
+                    },
+                    position: subroutine.name.position,
+                };
+                subroutine.body.insert(0, increment);
+                names.push(name);
+            }
+            if !names.is_empty() {
+                class.vars.push(ClassVarDec {
+                    kind: ClassVarKind::Static,
+                    ty: Type::Int,
+                    names,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct FoldConstants;
+
+impl Pass for FoldConstants {
+    fn name(&self) -> &str {
+        "fold"
+    }
+
+    fn run(&mut self, classes: &mut [Class]) -> Result<()> {
+        classes.iter_mut().for_each(optimize::fold_class);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate, parser::parse};
+
+    const SOURCE: &str = "class Main {
+        static int total;
+        function void main() {
+            var int count;
+            let count = Main.add(count, 2);
+            return;
+        }
+        function int add(int count, int n) {
+            return count + n;
+        }
+    }";
+
+    fn run(specs: &[&str], source: &str) -> Result<Class> {
+        let specs: Vec<String> = specs.iter().map(|spec| spec.to_string()).collect();
+        let mut pipeline = Pipeline::from_specs(&Registry::builtin(), &specs)?;
+        let mut classes = [parse(source)?];
+        pipeline.run(&mut classes)?;
+        let [class] = classes;
+        Ok(class)
+    }
+
+    #[test]
+    fn test_rename_and_count_calls() {
+        let class = run(&["rename:count=n2", "count-calls"], SOURCE).unwrap();
+        let expected = parse(
+            "class Main {
+                static int total;
+                static int calls_main, calls_add;
+                function void main() {
+                    var int n2;
+                    let calls_main = calls_main + 1;
+                    let n2 = Main.add(n2, 2);
+                    return;
+                }
+                function int add(int n2, int n) {
+                    let calls_add = calls_add + 1;
+                    return n2 + n;
+                }
+            }",
+        )
+        .unwrap();
+        assert_eq!(
+            generate(&class, &[], 0).unwrap(),
+            generate(&expected, &[], 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pass_errors() {
+        let error = |specs: &[&str], source: &str| format!("{:#}", run(specs, source).unwrap_err());
+        assert_eq!(
+            error(&["inline"], SOURCE),
+            "Unknown pass 'inline' (available: count-calls, fold, rename)"
+        );
+        assert_eq!(
+            error(&["rename:count=while"], SOURCE),
+            "Invalid pass 'rename:count=while': 'while' is not a Jack identifier"
+        );
+        assert_eq!(
+            error(
+                &["count-calls"],
+                "class A { function void f() { var int calls_f; return; } }"
+            ),
+            "Pass 'count-calls' failed: 'calls_f' is already declared in 'A.f'"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Class, Subroutine, SubroutineCall, Type},
+    jack_files, os, parser,
+    visit::{self, Visit},
+};
+
+pub const MANIFEST: &str = "jack.toml";
+
+// jack.toml。パスはマニフェストのディレクトリからの相対パス
+//
+// sources = ["src"]
+// libraries = ["../shapes", "../util/Random.jack"]
+// output = "build"
+// os = true
+// passes = ["count-calls"]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    // プロジェクトの .jack があるディレクトリ（既定はマニフェストのディレクトリ）
+    #[serde(default = "default_sources")]
+    pub sources: Vec<PathBuf>,
+    // 他のプロジェクトと共有するクラスのディレクトリか .jack ファイル
+    #[serde(default)]
+    pub libraries: Vec<PathBuf>,
+    // .vm の出力先（既定はマニフェストのディレクトリ）
+    pub output: Option<PathBuf>,
+    // 同梱の OS をつなぐ（--os と同じ）
+    #[serde(default)]
+    pub os: bool,
+    // コード生成の前に実行するパス（--pass と同じ書き方）
+    #[serde(default)]
+    pub passes: Vec<String>,
+}
+
+fn default_sources() -> Vec<PathBuf> {
+    vec![PathBuf::from(".")]
+}
+
+pub struct Project {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+
+// path が jack.toml か、jack.toml のあるディレクトリならマニフェストのパス
+pub fn manifest_path(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        Some(path.join(MANIFEST)).filter(|manifest| manifest.is_file())
+    } else {
+        path.extension()
+            .is_some_and(|ext| ext == "toml")
+            .then(|| path.to_path_buf())
+    }
+}
+
+impl Project {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read manifest '{}'", path.display()))?;
+        let manifest: Manifest =
+            toml::from_str(&text).context(format!("Invalid manifest '{}'", path.display()))?;
+        Ok(Project {
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            manifest,
+        })
+    }
+
+    pub fn output_dir(&self) -> PathBuf {
+        match &self.manifest.output {
+            Some(output) => self.dir.join(output),
+            None => self.dir.clone(),
+        }
+    }
+
+    // ソースとライブラリのクラス名 → ファイル。同じクラスが 2 か所にあればエラー
+    pub fn classes(&self) -> Result<BTreeMap<String, PathBuf>> {
+        let mut classes: BTreeMap<String, PathBuf> = BTreeMap::new();
+        let paths = self.manifest.sources.iter().chain(&self.manifest.libraries);
+        for path in paths {
+            for file in jack_files(&self.dir.join(path))? {
+                let name = file
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .context("Invalid filename")?
+                    .to_string();
+                if let Some(other) = classes.get(&name) {
+                    bail!(
+                        "Class '{}' is defined in both '{}' and '{}'",
+                        name,
+                        other.display(),
+                        file.display()
+                    );
+                }
+                classes.insert(name, file);
+            }
+        }
+        Ok(classes)
+    }
+
+    // Main（と OS の代わりのクラス）から参照をたどり、使われるクラスのファイルを返す。
+    // どこにもないクラスの参照はエラー（OS のクラスは除く）
+    pub fn resolve(&self) -> Result<Vec<PathBuf>> {
+        let classes = self.classes()?;
+        if !classes.contains_key("Main") {
+            bail!("No Main class in the sources of '{}'", self.dir.display());
+        }
+        // OS のクラスを置き換えるものは OS から呼ばれる
+        let mut pending: Vec<(String, Option<String>)> = classes
+            .keys()
+            .filter(|name| *name == "Main" || os::is_os_class(name))
+            .map(|name| (name.clone(), None))
+            .collect();
+        let mut used = BTreeSet::new();
+        while let Some((name, user)) = pending.pop() {
+            if used.contains(&name) {
+                continue;
+            }
+            let Some(file) = classes.get(&name) else {
+                if os::is_os_class(&name) {
+                    continue;
+                }
+                bail!(
+                    "Class '{}' used by '{}' is not in the sources or libraries",
+                    name,
+                    user.unwrap_or_default()
+                );
+            };
+            let source = fs::read_to_string(file)
+                .context(format!("Failed to read file '{}'", file.display()))?;
+            // 構文エラーはコンパイルのときに報告する
+            if let (Some(class), _) = parser::parse_with_diagnostics(&source) {
+                pending.extend(
+                    referenced_classes(&class)
+                        .into_iter()
+                        .map(|referenced| (referenced, Some(name.clone()))),
+                );
+            }
+            used.insert(name);
+        }
+        Ok(used.iter().map(|name| classes[name].clone()).collect())
+    }
+}
+
+// 型と、クラス名を受け手にした呼び出しで参照されるクラス
+pub fn referenced_classes(class: &Class) -> BTreeSet<String> {
+    let mut references = References {
+        fields: class
+            .vars
+            .iter()
+            .flat_map(|dec| dec.names.iter().map(|name| name.name.as_str()))
+            .collect(),
+        ..References::default()
+    };
+    references.visit_class(class);
+    references.classes.remove(&class.name.name);
+    references.classes
+}
+
+#[derive(Default)]
+struct References<'a> {
+    classes: BTreeSet<String>,
+    fields: HashSet<&'a str>,
+    // 受け手が変数ならクラスではない
+    variables: HashSet<&'a str>,
+}
+
+impl<'a> Visit<'a> for References<'a> {
+    fn visit_subroutine(&mut self, subroutine: &'a Subroutine) {
+        self.variables = self.fields.clone();
+        self.variables.extend(
+            subroutine
+                .parameters
+                .iter()
+                .map(|parameter| parameter.name.name.as_str()),
+        );
+        self.variables.extend(
+            subroutine
+                .locals
+                .iter()
+                .flat_map(|dec| dec.names.iter().map(|name| name.name.as_str())),
+        );
+        visit::visit_subroutine(self, subroutine);
+    }
+
+    fn visit_type(&mut self, ty: &'a Type) {
+        if let Type::Class(name) = ty {
+            self.classes.insert(name.clone());
+        }
+    }
+
+    fn visit_call(&mut self, call: &'a SubroutineCall) {
+        if let Some(receiver) = &call.receiver
+            && !self.variables.contains(receiver.name.as_str())
+        {
+            self.classes.insert(receiver.name.clone());
+        }
+        visit::visit_call(self, call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_referenced_classes() {
+        let class = parser::parse(
+            "class Main {
+                static Board board;
+                function void main() {
+                    var Ball ball;
+                    let ball = Ball.new(Random.next(), 3);
+                    do ball.move();
+                    do board.draw(Output.println());
+                    return;
+                }
+            }",
+        )
+        .unwrap();
+        assert_eq!(
+            referenced_classes(&class),
+            BTreeSet::from(["Ball", "Board", "Output", "Random"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn test_resolve_project() {
+        let root = std::env::temp_dir().join(format!("jack-project-{}", std::process::id()));
+        write(
+            &root.join("game"),
+            &[(
+                "jack.toml",
+                "sources = [\"src\"]\nlibraries = [\"../lib\"]\noutput = \"build\"\n",
+            )],
+        );
+        write(
+            &root.join("game/src"),
+            &[
+                (
+                    "Main.jack",
+                    "class Main { function void main() { do Shape.draw(); return; } }",
+                ),
+                (
+                    "Shape.jack",
+                    "class Shape { function void draw() { var Point p; do Math.abs(1); return; } }",
+                ),
+                ("Unused.jack", "class Unused { }"),
+            ],
+        );
+        write(
+            &root.join("lib"),
+            &[
+                ("Point.jack", "class Point { }"),
+                ("Random.jack", "class Random { }"),
+            ],
+        );
+
+        let manifest = manifest_path(&root.join("game")).unwrap();
+        let project = Project::load(&manifest).unwrap();
+        assert_eq!(project.output_dir(), root.join("game/build"));
+        let files: Vec<String> = project
+            .resolve()
+            .unwrap()
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files, ["Main.jack", "Point.jack", "Shape.jack"]);
+
+        // ライブラリにないクラスはエラー
+        fs::remove_file(root.join("lib/Point.jack")).unwrap();
+        let error = project.resolve().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Class 'Point' used by 'Shape' is not in the sources or libraries"
+        );
+        fs::remove_dir_all(&root).ok();
+    }
+}
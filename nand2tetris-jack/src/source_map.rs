@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// Foo.vm.map: Foo.vm の各行（1行に1コマンド）を生成した Foo.jack の行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceMap {
+    // .jack ファイルの名前（ディレクトリなし）
+    pub source: String,
+    // lines[i] が VM の i + 1 行目に対応する Jack の行
+    pub lines: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn jack_line(&self, vm_line: usize) -> Option<usize> {
+        self.lines.get(vm_line.checked_sub(1)?).copied()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap() + "\n"
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .context(format!("Failed to read file '{}'", path.display()))?;
+        serde_json::from_str(&text).context(format!("Invalid source map '{}'", path.display()))
+    }
+}
+
+// Foo.vm の隣の Foo.vm.map
+pub fn map_path(vm: &Path) -> std::path::PathBuf {
+    let mut name = vm.file_name().unwrap_or_default().to_os_string();
+    name.push(".map");
+    vm.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codegen, parser::parse};
+
+    #[test]
+    fn test_source_map() {
+        let class = parse(
+            "class Main {\n\
+             function void main() {\n\
+             var int x;\n\
+             if (x) {\n\
+             let x = 1;\n\
+             }\n\
+             return;\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+        let (commands, lines) =
+            codegen::compile_class_with_lines(&class, &Default::default()).unwrap();
+        let mapped: Vec<(usize, &str)> = lines
+            .iter()
+            .copied()
+            .zip(commands.iter().map(String::as_str))
+            .collect();
+        assert_eq!(
+            mapped,
+            [
+                (2, "function Main.main 1"),
+                (4, "push local 0"),
+                (4, "not"),
+                (4, "if-goto Main.main.IF_FALSE0"),
+                (5, "push constant 1"),
+                (5, "pop local 0"),
+                // then の後のラベルは if の行
+                (4, "label Main.main.IF_FALSE0"),
+                (7, "push constant 0"),
+                (7, "return"),
+            ]
+        );
+
+        let map = SourceMap {
+            source: "Main.jack".to_string(),
+            lines,
+        };
+        assert_eq!(map.jack_line(1), Some(2));
+        assert_eq!(map.jack_line(0), None);
+        assert_eq!(
+            serde_json::from_str::<SourceMap>(&map.to_json()).unwrap(),
+            map
+        );
+        assert_eq!(
+            map_path(Path::new("out/Main.vm")),
+            Path::new("out/Main.vm.map")
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{Result, bail};
+use std::fmt::{self, Write};
+
+use crate::ast::{Class, ClassVarKind, Identifier, Subroutine, SubroutineKind, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Static,
+    Field,
+    Argument,
+    Var,
+}
+
+impl Kind {
+    // 変数が置かれる VM のセグメント
+    pub fn segment(self) -> &'static str {
+        match self {
+            Kind::Static => "static",
+            Kind::Field => "this",
+            Kind::Argument => "argument",
+            Kind::Var => "local",
+        }
+    }
+
+    fn is_class_scope(self) -> bool {
+        matches!(self, Kind::Static | Kind::Field)
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Static => "static",
+            Kind::Field => "field",
+            Kind::Argument => "argument",
+            Kind::Var => "var",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub ty: Type,
+    pub kind: Kind,
+    // 種類ごとの通し番号。VM のセグメント内の位置になる
+    pub index: u16,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} -> {} {}",
+            self.kind,
+            self.ty.name(),
+            self.name,
+            self.kind.segment(),
+            self.index
+        )
+    }
+}
+
+// クラスのスコープ（static, field）とサブルーチンのスコープ（argument, var）の2段。
+// 名前はサブルーチンのスコープから先に探す
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    class: Vec<Symbol>,
+    subroutine: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    // クラス変数を登録したテーブル
+    pub fn for_class(class: &Class) -> Result<Self> {
+        let mut table = SymbolTable::new();
+        for var in &class.vars {
+            let kind = match var.kind {
+                ClassVarKind::Static => Kind::Static,
+                ClassVarKind::Field => Kind::Field,
+            };
+            for name in &var.names {
+                table.define(name, &var.ty, kind)?;
+            }
+        }
+        Ok(table)
+    }
+
+    // サブルーチンのスコープを空にする。メソッドは this が argument 0 になる
+    pub fn start_subroutine(&mut self, class_name: &str, kind: SubroutineKind) {
+        self.subroutine.clear();
+        if kind == SubroutineKind::Method {
+            self.subroutine.push(Symbol {
+                name: "this".to_string(),
+                ty: Type::Class(class_name.to_string()),
+                kind: Kind::Argument,
+                index: 0,
+            });
+        }
+    }
+
+    // サブルーチンの引数とローカル変数を登録する
+    pub fn enter_subroutine(&mut self, class_name: &str, subroutine: &Subroutine) -> Result<()> {
+        self.start_subroutine(class_name, subroutine.kind);
+        for parameter in &subroutine.parameters {
+            self.define(&parameter.name, &parameter.ty, Kind::Argument)?;
+        }
+        for var in &subroutine.locals {
+            for name in &var.names {
+                self.define(name, &var.ty, Kind::Var)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn define(&mut self, name: &Identifier, ty: &Type, kind: Kind) -> Result<&Symbol> {
+        let scope = if kind.is_class_scope() {
+            &mut self.class
+        } else {
+            &mut self.subroutine
+        };
+        if scope.iter().any(|symbol| symbol.name == name.name) {
+            bail!(
+                "Line {}, column {}: '{}' is already defined",
+                name.position.line,
+                name.position.column,
+                name.name
+            );
+        }
+        let index = scope.iter().filter(|symbol| symbol.kind == kind).count() as u16;
+        scope.push(Symbol {
+            name: name.name.clone(),
+            ty: ty.clone(),
+            kind,
+            index,
+        });
+        Ok(scope.last().unwrap())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.subroutine
+            .iter()
+            .chain(&self.class)
+            .find(|symbol| symbol.name == name)
+    }
+
+    pub fn var_count(&self, kind: Kind) -> u16 {
+        let scope = if kind.is_class_scope() {
+            &self.class
+        } else {
+            &self.subroutine
+        };
+        scope.iter().filter(|symbol| symbol.kind == kind).count() as u16
+    }
+
+    pub fn class_symbols(&self) -> &[Symbol] {
+        &self.class
+    }
+
+    pub fn subroutine_symbols(&self) -> &[Symbol] {
+        &self.subroutine
+    }
+}
+
+// --dump-symbols の出力
+pub fn dump_symbols(class: &Class) -> Result<String> {
+    let mut table = SymbolTable::for_class(class)?;
+    let mut out = format!("class {}\n", class.name.name);
+    for symbol in table.class_symbols() {
+        writeln!(out, "  {}", symbol).unwrap();
+    }
+    for subroutine in &class.subroutines {
+        table.enter_subroutine(&class.name.name, subroutine)?;
+        writeln!(out, "{}.{}", class.name.name, subroutine.name.name).unwrap();
+        for symbol in table.subroutine_symbols() {
+            writeln!(out, "  {}", symbol).unwrap();
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    const POINT: &str = "class Point {\n\
+                         field int x, y;\n\
+                         static int count;\n\
+                         method int distance(Point other, int scale) {\n\
+                         var int dx, x;\n\
+                         return 0;\n\
+                         }\n\
+                         function void reset() { var boolean done; return; }\n\
+                         }";
+
+    #[test]
+    fn test_symbol_table() {
+        let class = parse(POINT).unwrap();
+        let mut table = SymbolTable::for_class(&class).unwrap();
+        assert_eq!(table.var_count(Kind::Field), 2);
+        assert_eq!(table.var_count(Kind::Static), 1);
+
+        table
+            .enter_subroutine("Point", &class.subroutines[0])
+            .unwrap();
+        let this = table.lookup("this").unwrap();
+        assert_eq!((this.kind, this.index), (Kind::Argument, 0));
+        let scale = table.lookup("scale").unwrap();
+        assert_eq!((scale.kind, scale.index), (Kind::Argument, 2));
+        // ローカル変数がフィールドを隠す
+        let x = table.lookup("x").unwrap();
+        assert_eq!((x.kind, x.index), (Kind::Var, 1));
+        let y = table.lookup("y").unwrap();
+        assert_eq!((y.kind.segment(), y.index), ("this", 1));
+        assert_eq!(table.var_count(Kind::Var), 2);
+
+        // 関数に入るとメソッドの変数は消える
+        table
+            .enter_subroutine("Point", &class.subroutines[1])
+            .unwrap();
+        assert!(table.lookup("other").is_none());
+        assert_eq!(table.lookup("x").unwrap().kind, Kind::Field);
+        assert_eq!(table.lookup("done").unwrap().ty, Type::Boolean);
+    }
+
+    #[test]
+    fn test_duplicate_definition() {
+        let class =
+            parse("class A { field int a; static char a; function void f() { return; } }").unwrap();
+        assert_eq!(
+            SymbolTable::for_class(&class).unwrap_err().to_string(),
+            "Line 1, column 36: 'a' is already defined"
+        );
+
+        let class = parse("class A { function void f(int a) { var int a; return; } }").unwrap();
+        let mut table = SymbolTable::for_class(&class).unwrap();
+        assert!(table.enter_subroutine("A", &class.subroutines[0]).is_err());
+    }
+
+    #[test]
+    fn test_dump_symbols() {
+        let class = parse(POINT).unwrap();
+        assert_eq!(
+            dump_symbols(&class).unwrap(),
+            "class Point\n\
+             \x20 field int x -> this 0\n\
+             \x20 field int y -> this 1\n\
+             \x20 static int count -> static 0\n\
+             Point.distance\n\
+             \x20 argument Point this -> argument 0\n\
+             \x20 argument Point other -> argument 1\n\
+             \x20 argument int scale -> argument 2\n\
+             \x20 var int dx -> local 0\n\
+             \x20 var int x -> local 1\n\
+             Point.reset\n\
+             \x20 var boolean done -> local 0\n"
+        );
+    }
+}
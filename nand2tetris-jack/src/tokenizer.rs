@@ -0,0 +1,478 @@
+use anyhow::Result;
+use std::fmt::{self, Write};
+
+use crate::{
+    ast::Position,
+    diagnostic::{Diagnostic, into_result},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keyword {
+    Class,
+    Constructor,
+    Function,
+    Method,
+    Field,
+    Static,
+    Var,
+    Int,
+    Char,
+    Boolean,
+    Void,
+    True,
+    False,
+    Null,
+    This,
+    Let,
+    Do,
+    If,
+    Else,
+    While,
+    Return,
+}
+
+const KEYWORDS: [(&str, Keyword); 21] = [
+    ("class", Keyword::Class),
+    ("constructor", Keyword::Constructor),
+    ("function", Keyword::Function),
+    ("method", Keyword::Method),
+    ("field", Keyword::Field),
+    ("static", Keyword::Static),
+    ("var", Keyword::Var),
+    ("int", Keyword::Int),
+    ("char", Keyword::Char),
+    ("boolean", Keyword::Boolean),
+    ("void", Keyword::Void),
+    ("true", Keyword::True),
+    ("false", Keyword::False),
+    ("null", Keyword::Null),
+    ("this", Keyword::This),
+    ("let", Keyword::Let),
+    ("do", Keyword::Do),
+    ("if", Keyword::If),
+    ("else", Keyword::Else),
+    ("while", Keyword::While),
+    ("return", Keyword::Return),
+];
+
+impl Keyword {
+    pub fn from_word(word: &str) -> Option<Keyword> {
+        KEYWORDS
+            .iter()
+            .find(|(name, _)| *name == word)
+            .map(|&(_, keyword)| keyword)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|(_, keyword)| *keyword == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+// Jack の整数定数の最大値
+pub const MAX_INTEGER: u16 = 32767;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Symbol(char),
+    Identifier(String),
+    IntegerConstant(u16),
+    StringConstant(String),
+}
+
+// line と column は 1 始まり
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Token {
+    // 公式の xxxT.xml のタグ名
+    pub fn tag(&self) -> &'static str {
+        match self.kind {
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::Symbol(_) => "symbol",
+            TokenKind::Identifier(_) => "identifier",
+            TokenKind::IntegerConstant(_) => "integerConstant",
+            TokenKind::StringConstant(_) => "stringConstant",
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match &self.kind {
+            TokenKind::Keyword(keyword) => keyword.to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::Identifier(name) => name.clone(),
+            TokenKind::IntegerConstant(value) => value.to_string(),
+            TokenKind::StringConstant(text) => text.clone(),
+        }
+    }
+}
+
+// コメント。text は // や /* */ も含む。フォーマッターが元の位置に戻すのに使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+}
+
+impl Comment {
+    pub fn is_block(&self) -> bool {
+        self.text.starts_with("/*")
+    }
+}
+
+struct Scanner<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+    diagnostics: Vec<Diagnostic>,
+    comments: Vec<Comment>,
+}
+
+impl Scanner<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    // 2文字先を見る（コメントの判定用）
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&mut self, line: usize, column: usize, message: String) {
+        self.diagnostics
+            .push(Diagnostic::error(Position { line, column }, message));
+    }
+
+    // 空白とコメントを読み飛ばす
+    fn skip_trivia(&mut self) {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.next();
+                }
+                (Some('/'), Some('/')) => {
+                    let (line, column) = (self.line, self.column);
+                    let mut text = String::new();
+                    while let Some(c) = self.peek().filter(|&c| c != '\n') {
+                        text.push(c);
+                        self.next();
+                    }
+                    self.comments.push(Comment {
+                        text: text.trim_end().to_string(),
+                        line,
+                        column,
+                        end_line: line,
+                    });
+                }
+                (Some('/'), Some('*')) => {
+                    let (line, column) = (self.line, self.column);
+                    self.next();
+                    self.next();
+                    let mut text = String::from("/*");
+                    loop {
+                        match self.next() {
+                            Some('*') if self.peek() == Some('/') => {
+                                self.next();
+                                text.push_str("*/");
+                                break;
+                            }
+                            Some(c) => text.push(c),
+                            None => {
+                                self.error(line, column, "Unterminated comment".to_string());
+                                return;
+                            }
+                        }
+                    }
+                    self.comments.push(Comment {
+                        text,
+                        line,
+                        column,
+                        end_line: self.line,
+                    });
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn take_while(&mut self, first: char, pred: impl Fn(char) -> bool) -> String {
+        let mut word = String::from(first);
+        while let Some(c) = self.peek().filter(|&c| pred(c)) {
+            word.push(c);
+            self.next();
+        }
+        word
+    }
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let (tokens, diagnostics) = scan(source);
+    into_result(tokens, &diagnostics)
+}
+
+// エラーがあっても最後まで読む。壊れたトークンはそれらしい値で続ける
+pub fn scan(source: &str) -> (Vec<Token>, Vec<Diagnostic>) {
+    let (tokens, _, diagnostics) = scan_lossless(source);
+    (tokens, diagnostics)
+}
+
+// scan と同じだが、読み飛ばしたコメントも返す
+pub fn scan_lossless(source: &str) -> (Vec<Token>, Vec<Comment>, Vec<Diagnostic>) {
+    let mut scanner = Scanner {
+        chars: source.chars().peekable(),
+        line: 1,
+        column: 1,
+        diagnostics: Vec::new(),
+        comments: Vec::new(),
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        scanner.skip_trivia();
+        let (line, column) = (scanner.line, scanner.column);
+        let Some(c) = scanner.next() else {
+            break;
+        };
+
+        let kind = if SYMBOLS.contains(c) {
+            TokenKind::Symbol(c)
+        } else if c.is_ascii_digit() {
+            let digits = scanner.take_while(c, |c| c.is_ascii_digit());
+            match digits.parse::<u16>() {
+                Ok(value) if value <= MAX_INTEGER => TokenKind::IntegerConstant(value),
+                _ => {
+                    let message = format!(
+                        "Integer constant {} is out of range (0..={})",
+                        digits, MAX_INTEGER
+                    );
+                    scanner.error(line, column, message);
+                    TokenKind::IntegerConstant(MAX_INTEGER)
+                }
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let word = scanner.take_while(c, |c| c.is_ascii_alphanumeric() || c == '_');
+            match Keyword::from_word(&word) {
+                Some(keyword) => TokenKind::Keyword(keyword),
+                None => TokenKind::Identifier(word),
+            }
+        } else if c == '"' {
+            let mut text = String::new();
+            loop {
+                match scanner.peek() {
+                    Some('"') => {
+                        scanner.next();
+                        break;
+                    }
+                    Some('\n') | None => {
+                        scanner.error(line, column, "Unterminated string constant".to_string());
+                        break;
+                    }
+                    Some(c) => {
+                        text.push(c);
+                        scanner.next();
+                    }
+                }
+            }
+            TokenKind::StringConstant(text)
+        } else {
+            scanner.error(line, column, format!("Unexpected character '{}'", c));
+            continue;
+        };
+        tokens.push(Token { kind, line, column });
+    }
+
+    (tokens, scanner.comments, scanner.diagnostics)
+}
+
+// 公式の CompilationEngine と同じく <, >, & と " だけを置き換える
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 公式の xxxT.xml と同じ形式
+pub fn tokens_xml(tokens: &[Token]) -> String {
+    let mut out = String::from("<tokens>\n");
+    for token in tokens {
+        writeln!(
+            out,
+            "<{tag}> {} </{tag}>",
+            escape_xml(&token.text()),
+            tag = token.tag()
+        )
+        .unwrap();
+    }
+    out.push_str("</tokens>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let source = "/** doc */\nclass Main { // comment\n  let x = x+1; /* a\n b */ do Output.printString(\"a < b\");\n}";
+        assert_eq!(
+            kinds(source),
+            [
+                TokenKind::Keyword(Keyword::Class),
+                TokenKind::Identifier("Main".to_string()),
+                TokenKind::Symbol('{'),
+                TokenKind::Keyword(Keyword::Let),
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Symbol('='),
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Symbol('+'),
+                TokenKind::IntegerConstant(1),
+                TokenKind::Symbol(';'),
+                TokenKind::Keyword(Keyword::Do),
+                TokenKind::Identifier("Output".to_string()),
+                TokenKind::Symbol('.'),
+                TokenKind::Identifier("printString".to_string()),
+                TokenKind::Symbol('('),
+                TokenKind::StringConstant("a < b".to_string()),
+                TokenKind::Symbol(')'),
+                TokenKind::Symbol(';'),
+                TokenKind::Symbol('}'),
+            ]
+        );
+
+        let tokens = tokenize(source).unwrap();
+        assert_eq!((tokens[0].line, tokens[0].column), (2, 1));
+        assert_eq!((tokens[10].line, tokens[10].column), (4, 7));
+    }
+
+    #[test]
+    fn test_keyword_prefix_is_identifier() {
+        assert_eq!(
+            kinds("classy do_it"),
+            [
+                TokenKind::Identifier("classy".to_string()),
+                TokenKind::Identifier("do_it".to_string()),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "let x = 32768;",
+        "Line 1, column 9: Integer constant 32768 is out of range"
+    )]
+    #[case("let s = \"abc\n\";", "Line 1, column 9: Unterminated string constant")]
+    #[case("class A {\n  /* open", "Line 2, column 3: Unterminated comment")]
+    #[case("let x = y # 1;", "Line 1, column 11: Unexpected character '#'")]
+    fn test_tokenize_error(#[case] source: &str, #[case] expected: &str) {
+        let err = tokenize(source).unwrap_err().to_string();
+        assert!(err.starts_with(expected), "{}", err);
+    }
+
+    #[test]
+    fn test_scan_reports_every_error() {
+        let (tokens, diagnostics) = scan("let s = \"abc\nlet x = 99999 # 1;");
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "Line 1, column 9: Unterminated string constant",
+                "Line 2, column 9: Integer constant 99999 is out of range (0..=32767)",
+                "Line 2, column 15: Unexpected character '#'",
+            ]
+        );
+        assert_eq!(tokens.len(), 4 + 6);
+    }
+
+    #[test]
+    fn test_scan_lossless_keeps_comments() {
+        let (tokens, comments, _) = scan_lossless("/** a\n */ let x = 1; // one \nlet s = \"//\";");
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(
+            comments,
+            [
+                Comment {
+                    text: "/** a\n */".to_string(),
+                    line: 1,
+                    column: 1,
+                    end_line: 2,
+                },
+                Comment {
+                    text: "// one".to_string(),
+                    line: 2,
+                    column: 16,
+                    end_line: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokens_xml() {
+        let tokens = tokenize("if (x < 3 & y > \"&\") { return; }").unwrap();
+        assert_eq!(
+            tokens_xml(&tokens),
+            "<tokens>\n\
+             <keyword> if </keyword>\n\
+             <symbol> ( </symbol>\n\
+             <identifier> x </identifier>\n\
+             <symbol> &lt; </symbol>\n\
+             <integerConstant> 3 </integerConstant>\n\
+             <symbol> &amp; </symbol>\n\
+             <identifier> y </identifier>\n\
+             <symbol> &gt; </symbol>\n\
+             <stringConstant> &amp; </stringConstant>\n\
+             <symbol> ) </symbol>\n\
+             <symbol> { </symbol>\n\
+             <keyword> return </keyword>\n\
+             <symbol> ; </symbol>\n\
+             <symbol> } </symbol>\n\
+             </tokens>\n"
+        );
+    }
+}
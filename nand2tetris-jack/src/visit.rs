@@ -0,0 +1,222 @@
+// 構文木を読むだけの走査。各メソッドの既定の実装は同じ名前の関数で子をすべて訪れるので、
+// 必要なメソッドだけ上書きし、子も見るときはその関数を呼ぶ
+//
+// struct Calls(usize);
+// impl<'a> Visit<'a> for Calls {
+//     fn visit_call(&mut self, call: &'a SubroutineCall) {
+//         self.0 += 1;
+//         visit::visit_call(self, call);
+//     }
+// }
+
+use crate::ast::*;
+
+pub trait Visit<'a> {
+    fn visit_class(&mut self, class: &'a Class) {
+        visit_class(self, class);
+    }
+
+    fn visit_class_var_dec(&mut self, dec: &'a ClassVarDec) {
+        visit_class_var_dec(self, dec);
+    }
+
+    fn visit_subroutine(&mut self, subroutine: &'a Subroutine) {
+        visit_subroutine(self, subroutine);
+    }
+
+    fn visit_parameter(&mut self, parameter: &'a Parameter) {
+        visit_parameter(self, parameter);
+    }
+
+    fn visit_var_dec(&mut self, dec: &'a VarDec) {
+        visit_var_dec(self, dec);
+    }
+
+    // サブルーチンの本体と if、while の中の文の並び
+    fn visit_block(&mut self, statements: &'a [Statement]) {
+        visit_block(self, statements);
+    }
+
+    fn visit_statement(&mut self, statement: &'a Statement) {
+        visit_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &'a Expression) {
+        visit_expression(self, expression);
+    }
+
+    fn visit_term(&mut self, term: &'a Term) {
+        visit_term(self, term);
+    }
+
+    fn visit_call(&mut self, call: &'a SubroutineCall) {
+        visit_call(self, call);
+    }
+
+    fn visit_type(&mut self, _ty: &'a Type) {}
+
+    // 宣言された名前と、使われた変数、受け手、サブルーチンの名前
+    fn visit_identifier(&mut self, _identifier: &'a Identifier) {}
+}
+
+pub fn visit_class<'a, V: Visit<'a> + ?Sized>(v: &mut V, class: &'a Class) {
+    v.visit_identifier(&class.name);
+    for dec in &class.vars {
+        v.visit_class_var_dec(dec);
+    }
+    for subroutine in &class.subroutines {
+        v.visit_subroutine(subroutine);
+    }
+}
+
+pub fn visit_class_var_dec<'a, V: Visit<'a> + ?Sized>(v: &mut V, dec: &'a ClassVarDec) {
+    v.visit_type(&dec.ty);
+    for name in &dec.names {
+        v.visit_identifier(name);
+    }
+}
+
+pub fn visit_subroutine<'a, V: Visit<'a> + ?Sized>(v: &mut V, subroutine: &'a Subroutine) {
+    if let Some(ty) = &subroutine.return_type {
+        v.visit_type(ty);
+    }
+    v.visit_identifier(&subroutine.name);
+    for parameter in &subroutine.parameters {
+        v.visit_parameter(parameter);
+    }
+    for dec in &subroutine.locals {
+        v.visit_var_dec(dec);
+    }
+    v.visit_block(&subroutine.body);
+}
+
+pub fn visit_parameter<'a, V: Visit<'a> + ?Sized>(v: &mut V, parameter: &'a Parameter) {
+    v.visit_type(&parameter.ty);
+    v.visit_identifier(&parameter.name);
+}
+
+pub fn visit_var_dec<'a, V: Visit<'a> + ?Sized>(v: &mut V, dec: &'a VarDec) {
+    v.visit_type(&dec.ty);
+    for name in &dec.names {
+        v.visit_identifier(name);
+    }
+}
+
+pub fn visit_block<'a, V: Visit<'a> + ?Sized>(v: &mut V, statements: &'a [Statement]) {
+    for statement in statements {
+        v.visit_statement(statement);
+    }
+}
+
+pub fn visit_statement<'a, V: Visit<'a> + ?Sized>(v: &mut V, statement: &'a Statement) {
+    match &statement.kind {
+        StatementKind::Let { name, index, value } => {
+            v.visit_identifier(name);
+            if let Some(index) = index {
+                v.visit_expression(index);
+            }
+            v.visit_expression(value);
+        }
+        StatementKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            v.visit_expression(condition);
+            v.visit_block(then_branch);
+            if let Some(else_branch) = else_branch {
+                v.visit_block(else_branch);
+            }
+        }
+        StatementKind::While { condition, body } => {
+            v.visit_expression(condition);
+            v.visit_block(body);
+        }
+        StatementKind::Do(call) => v.visit_call(call),
+        StatementKind::Return(value) => {
+            if let Some(value) = value {
+                v.visit_expression(value);
+            }
+        }
+        StatementKind::Error => {}
+    }
+}
+
+pub fn visit_expression<'a, V: Visit<'a> + ?Sized>(v: &mut V, expression: &'a Expression) {
+    v.visit_term(&expression.term);
+    for (_, term) in &expression.rest {
+        v.visit_term(term);
+    }
+}
+
+pub fn visit_term<'a, V: Visit<'a> + ?Sized>(v: &mut V, term: &'a Term) {
+    match term {
+        Term::Variable(name) => v.visit_identifier(name),
+        Term::Index(name, index) => {
+            v.visit_identifier(name);
+            v.visit_expression(index);
+        }
+        Term::Call(call) => v.visit_call(call),
+        Term::Parenthesized(expression) => v.visit_expression(expression),
+        Term::Unary(_, term) => v.visit_term(term),
+        Term::Integer(_) | Term::String(_) | Term::Keyword(_) | Term::Error(_) => {}
+    }
+}
+
+pub fn visit_call<'a, V: Visit<'a> + ?Sized>(v: &mut V, call: &'a SubroutineCall) {
+    if let Some(receiver) = &call.receiver {
+        v.visit_identifier(receiver);
+    }
+    v.visit_identifier(&call.name);
+    for argument in &call.arguments {
+        v.visit_expression(argument);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    // 呼び出しの名前と、while の中の文の数
+    #[derive(Default)]
+    struct Collector<'a> {
+        calls: Vec<&'a str>,
+        loop_statements: usize,
+    }
+
+    impl<'a> Visit<'a> for Collector<'a> {
+        fn visit_statement(&mut self, statement: &'a Statement) {
+            if let StatementKind::While { body, .. } = &statement.kind {
+                self.loop_statements += body.len();
+            }
+            visit_statement(self, statement);
+        }
+
+        fn visit_call(&mut self, call: &'a SubroutineCall) {
+            self.calls.push(&call.name.name);
+            visit_call(self, call);
+        }
+    }
+
+    #[test]
+    fn test_visit_class() {
+        let class = parse(
+            "class Main {
+                function void main() {
+                    var int i;
+                    while (i < Math.max(3, f(g()))) {
+                        do Output.printInt(i);
+                        let i = i + 1;
+                    }
+                    return;
+                }
+            }",
+        )
+        .unwrap();
+        let mut collector = Collector::default();
+        collector.visit_class(&class);
+        assert_eq!(collector.calls, ["max", "f", "g", "printInt"]);
+        assert_eq!(collector.loop_statements, 2);
+    }
+}
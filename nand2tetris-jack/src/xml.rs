@@ -0,0 +1,372 @@
+use std::fmt::Write;
+
+use crate::{ast::*, tokenizer::escape_xml};
+
+// 公式の CompilationEngine と同じ構文木の XML（2文字ずつ字下げ）
+pub fn class_xml(class: &Class) -> String {
+    let mut writer = XmlWriter {
+        out: String::new(),
+        depth: 0,
+    };
+    writer.class(class);
+    writer.out
+}
+
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn open(&mut self, tag: &str) {
+        writeln!(self.out, "{:indent$}<{}>", "", tag, indent = self.depth * 2).unwrap();
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        writeln!(
+            self.out,
+            "{:indent$}</{}>",
+            "",
+            tag,
+            indent = self.depth * 2
+        )
+        .unwrap();
+    }
+
+    fn leaf(&mut self, tag: &str, text: &str) {
+        writeln!(
+            self.out,
+            "{:indent$}<{tag}> {} </{tag}>",
+            "",
+            escape_xml(text),
+            tag = tag,
+            indent = self.depth * 2
+        )
+        .unwrap();
+    }
+
+    fn keyword(&mut self, keyword: &str) {
+        self.leaf("keyword", keyword);
+    }
+
+    fn symbol(&mut self, symbol: char) {
+        self.leaf("symbol", &symbol.to_string());
+    }
+
+    fn identifier(&mut self, identifier: &Identifier) {
+        self.leaf("identifier", &identifier.name);
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Class(name) => self.leaf("identifier", name),
+            _ => self.keyword(ty.name()),
+        }
+    }
+
+    fn names(&mut self, names: &[Identifier]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.identifier(name);
+        }
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol('{');
+        for var in &class.vars {
+            self.open("classVarDec");
+            self.keyword(match var.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            });
+            self.ty(&var.ty);
+            self.names(&var.names);
+            self.symbol(';');
+            self.close("classVarDec");
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        self.symbol('}');
+        self.close("class");
+    }
+
+    fn subroutine(&mut self, subroutine: &Subroutine) {
+        self.open("subroutineDec");
+        self.keyword(match subroutine.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        });
+        match &subroutine.return_type {
+            Some(ty) => self.ty(ty),
+            None => self.keyword("void"),
+        }
+        self.identifier(&subroutine.name);
+        self.symbol('(');
+        self.open("parameterList");
+        for (i, parameter) in subroutine.parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.ty(&parameter.ty);
+            self.identifier(&parameter.name);
+        }
+        self.close("parameterList");
+        self.symbol(')');
+
+        self.open("subroutineBody");
+        self.symbol('{');
+        for var in &subroutine.locals {
+            self.open("varDec");
+            self.keyword("var");
+            self.ty(&var.ty);
+            self.names(&var.names);
+            self.symbol(';');
+            self.close("varDec");
+        }
+        self.statements(&subroutine.body);
+        self.symbol('}');
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close("statements");
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol('{');
+        self.statements(statements);
+        self.symbol('}');
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Let { name, index, value } => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(name);
+                if let Some(index) = index {
+                    self.symbol('[');
+                    self.expression(index);
+                    self.symbol(']');
+                }
+                self.symbol('=');
+                self.expression(value);
+                self.symbol(';');
+                self.close("letStatement");
+            }
+            StatementKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.keyword("else");
+                    self.block(else_branch);
+                }
+                self.close("ifStatement");
+            }
+            StatementKind::While { condition, body } => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(body);
+                self.close("whileStatement");
+            }
+            StatementKind::Do(call) => {
+                // 公式の出力では do の呼び出しは term で囲まない
+                self.open("doStatement");
+                self.keyword("do");
+                self.call(call);
+                self.symbol(';');
+                self.close("doStatement");
+            }
+            StatementKind::Return(value) => {
+                self.open("returnStatement");
+                self.keyword("return");
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.symbol(';');
+                self.close("returnStatement");
+            }
+            // 構文エラーのある木は書かない
+            StatementKind::Error => {}
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.term);
+        for (op, term) in &expression.rest {
+            self.symbol(op.symbol());
+            self.term(term);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+        match term {
+            Term::Integer(value) => self.leaf("integerConstant", &value.to_string()),
+            Term::String(text) => self.leaf("stringConstant", text),
+            Term::Keyword(keyword) => self.keyword(keyword.as_str()),
+            Term::Variable(name) => self.identifier(name),
+            Term::Index(name, index) => {
+                self.identifier(name);
+                self.symbol('[');
+                self.expression(index);
+                self.symbol(']');
+            }
+            Term::Call(call) => self.call(call),
+            Term::Parenthesized(expression) => {
+                self.symbol('(');
+                self.expression(expression);
+                self.symbol(')');
+            }
+            Term::Unary(op, term) => {
+                self.symbol(op.symbol());
+                self.term(term);
+            }
+            Term::Error(_) => {}
+        }
+        self.close("term");
+    }
+
+    fn call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol('.');
+        }
+        self.identifier(&call.name);
+        self.symbol('(');
+        self.open("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.expression(argument);
+        }
+        self.close("expressionList");
+        self.symbol(')');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_class_xml() {
+        let class = parse(
+            "class Main {\n\
+             static int n;\n\
+             function void main() {\n\
+             var Array a;\n\
+             let a[1] = -n < 2;\n\
+             do Output.println();\n\
+             return;\n\
+             }\n\
+             }",
+        )
+        .unwrap();
+        let expected = "\
+<class>
+  <keyword> class </keyword>
+  <identifier> Main </identifier>
+  <symbol> { </symbol>
+  <classVarDec>
+    <keyword> static </keyword>
+    <keyword> int </keyword>
+    <identifier> n </identifier>
+    <symbol> ; </symbol>
+  </classVarDec>
+  <subroutineDec>
+    <keyword> function </keyword>
+    <keyword> void </keyword>
+    <identifier> main </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <varDec>
+        <keyword> var </keyword>
+        <identifier> Array </identifier>
+        <identifier> a </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <statements>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> a </identifier>
+          <symbol> [ </symbol>
+          <expression>
+            <term>
+              <integerConstant> 1 </integerConstant>
+            </term>
+          </expression>
+          <symbol> ] </symbol>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <symbol> - </symbol>
+              <term>
+                <identifier> n </identifier>
+              </term>
+            </term>
+            <symbol> &lt; </symbol>
+            <term>
+              <integerConstant> 2 </integerConstant>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> Output </identifier>
+          <symbol> . </symbol>
+          <identifier> println </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+";
+        assert_eq!(class_xml(&class), expected);
+    }
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nand2tetris_vm::VmParser;
+
+fuzz_target!(|data: &[u8]| {
+    let src = String::from_utf8_lossy(data);
+    let mut parser = VmParser::new(&src);
+
+    while parser.has_more_commands() {
+        let _ = parser.parse();
+        parser.advance();
+    }
+});
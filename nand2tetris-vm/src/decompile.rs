@@ -0,0 +1,874 @@
+// .vm から Jack に似た疑似コードを作る。スタック操作を式に戻し、ラベルとジャンプの形から
+// if と while を復元する。形の合わないジャンプは goto のまま残す
+//
+// 変数の名前は分からないので、local 2 は local2、argument 1 は arg1、this 0 は field0、
+// static 3 は static3 になる。pointer 1 を通した that 0 の読み書きは a[i] に戻す
+
+use anyhow::{Context, Result, bail};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{Command, CommandType, parse_program};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Op<'a> {
+    Arithmetic(&'a str),
+    Push(&'a str, i32),
+    Pop(&'a str, i32),
+    Label(&'a str),
+    Goto(&'a str),
+    IfGoto(&'a str),
+    Call(&'a str, usize),
+    Function(&'a str, usize),
+    Return,
+}
+
+impl<'a> Op<'a> {
+    fn new(command: &'a Command) -> Result<Self> {
+        let arg1 = || command.arg1.as_deref().context("Missing argument");
+        let arg2 = || command.arg2.context("Missing argument");
+        Ok(match command.command_type {
+            CommandType::Arithmetic => Op::Arithmetic(arg1()?),
+            CommandType::Push => Op::Push(arg1()?, arg2()?),
+            CommandType::Pop => Op::Pop(arg1()?, arg2()?),
+            CommandType::Label => Op::Label(arg1()?),
+            CommandType::Goto => Op::Goto(arg1()?),
+            CommandType::IfGoto => Op::IfGoto(arg1()?),
+            CommandType::Call => Op::Call(arg1()?, arg2()? as usize),
+            CommandType::Function => Op::Function(arg1()?, arg2()? as usize),
+            CommandType::Return => Op::Return,
+        })
+    }
+
+    fn jump_target(&self) -> Option<&'a str> {
+        match self {
+            Op::Goto(label) | Op::IfGoto(label) => Some(label),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Int(i32),
+    Str(String),
+    Var(String),
+    Unary(char, Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn var(name: impl Into<String>) -> Self {
+        Expr::Var(name.into())
+    }
+
+    fn not(self) -> Self {
+        match self {
+            Expr::Unary('~', operand) => *operand,
+            _ => Expr::Unary('~', Box::new(self)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stmt {
+    Let(Expr, Expr),
+    Do(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Label(String),
+    Goto(String),
+    IfGoto(Expr, String),
+    // 文の境目でスタックに残った値
+    Push(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Constructor,
+    Function,
+    Method,
+}
+
+// 全ファイルの呼び出しから分かる関数の形
+#[derive(Debug, Clone)]
+struct FunctionInfo {
+    kind: Kind,
+    parameters: usize,
+    // 呼び出し元がすべて戻り値を捨てていれば void（return 0 を return にする）
+    void: bool,
+}
+
+// (ファイル名, VM コード) をまとめて疑似コードにする。関数の種類と引数の数は他のファイルの
+// 呼び出しからも推測する
+pub fn decompile(sources: &[(String, String)]) -> Result<String> {
+    let mut programs = Vec::new();
+    for (name, code) in sources {
+        let commands = parse_program(code).context(format!("{}.vm", name))?;
+        programs.push((name, commands));
+    }
+    let mut files = Vec::new();
+    for (name, commands) in &programs {
+        let ops = commands.iter().map(Op::new).collect::<Result<Vec<_>>>()?;
+        files.push((name.as_str(), ops));
+    }
+    let functions = function_infos(&files);
+
+    let mut out = String::new();
+    for (name, ops) in &files {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out += &decompile_file(name, ops, &functions)?;
+    }
+    Ok(out)
+}
+
+fn function_infos(files: &[(&str, Vec<Op>)]) -> HashMap<String, FunctionInfo> {
+    // 呼び出しの引数の数と、戻り値を捨てたか
+    let mut calls: HashMap<&str, Vec<(usize, bool)>> = HashMap::new();
+    for (_, ops) in files {
+        for (i, op) in ops.iter().enumerate() {
+            if let Op::Call(name, arguments) = op {
+                let discarded = ops.get(i + 1) == Some(&Op::Pop("temp", 0));
+                calls.entry(name).or_default().push((*arguments, discarded));
+            }
+        }
+    }
+
+    let mut functions = HashMap::new();
+    for (_, ops) in files {
+        for (start, end) in function_ranges(ops) {
+            let Op::Function(name, _) = ops[start] else {
+                continue;
+            };
+            let body = &ops[start + 1..end];
+            let kind = if body.starts_with(&[Op::Push("argument", 0), Op::Pop("pointer", 0)]) {
+                Kind::Method
+            } else if body.len() >= 3
+                && matches!(body[0], Op::Push("constant", _))
+                && body[1..3] == [Op::Call("Memory.alloc", 1), Op::Pop("pointer", 0)]
+            {
+                Kind::Constructor
+            } else {
+                Kind::Function
+            };
+            let sites = calls.get(name).map(Vec::as_slice).unwrap_or_default();
+            let used = body
+                .iter()
+                .filter_map(|op| match op {
+                    Op::Push("argument", i) | Op::Pop("argument", i) => Some(*i as usize + 1),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            let parameters = sites
+                .iter()
+                .map(|(arguments, _)| *arguments)
+                .max()
+                .unwrap_or(used);
+            // 呼び出しがなければ（Main.main など）すべての return が 0 を返すか
+            let void = if sites.is_empty() {
+                body.iter()
+                    .enumerate()
+                    .filter(|(_, op)| **op == Op::Return)
+                    .all(|(i, _)| i > 0 && body[i - 1] == Op::Push("constant", 0))
+            } else {
+                sites.iter().all(|(_, discarded)| *discarded)
+            };
+            functions.insert(
+                name.to_string(),
+                FunctionInfo {
+                    kind,
+                    parameters,
+                    void,
+                },
+            );
+        }
+    }
+    functions
+}
+
+// function から次の function の前まで
+fn function_ranges(ops: &[Op]) -> Vec<(usize, usize)> {
+    let starts: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| matches!(op, Op::Function(..)))
+        .map(|(i, _)| i)
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| (start, starts.get(n + 1).copied().unwrap_or(ops.len())))
+        .collect()
+}
+
+fn decompile_file(
+    file: &str,
+    ops: &[Op],
+    functions: &HashMap<String, FunctionInfo>,
+) -> Result<String> {
+    let mut statics = BTreeSet::new();
+    let mut fields = 0;
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::Push("static", index) | Op::Pop("static", index) => {
+                statics.insert(*index);
+            }
+            Op::Push("this", index) | Op::Pop("this", index) => {
+                fields = fields.max(*index as usize + 1);
+            }
+            // コンストラクタが確保する語数
+            Op::Call("Memory.alloc", 1)
+                if i > 0 && ops.get(i + 1) == Some(&Op::Pop("pointer", 0)) =>
+            {
+                if let Op::Push("constant", size) = ops[i - 1] {
+                    fields = fields.max(size as usize);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = format!("class {} {{\n", file);
+    if !statics.is_empty() {
+        let names: Vec<String> = statics.iter().map(|i| format!("static{}", i)).collect();
+        out += &format!("    static {};\n", names.join(", "));
+    }
+    if fields > 0 {
+        let names: Vec<String> = (0..fields).map(|i| format!("field{}", i)).collect();
+        out += &format!("    field {};\n", names.join(", "));
+    }
+
+    let ranges = function_ranges(ops);
+    // 最初の function より前のコマンド（Project 7 のテストなど）
+    let top = ranges.first().map_or(ops.len(), |(start, _)| *start);
+    if top > 0 {
+        out += "    // (commands outside any function)\n";
+        let body = Body::new(&ops[..top]);
+        render_block(&mut out, &body.statements(), 1, file, functions);
+    }
+    for (start, end) in ranges {
+        let Op::Function(name, locals) = ops[start] else {
+            bail!("Expected a function");
+        };
+        let info = &functions[name];
+        let (kind, skip) = match info.kind {
+            Kind::Constructor => ("constructor", 3),
+            Kind::Method => ("method", 2),
+            Kind::Function => ("function", 0),
+        };
+        // メソッドの argument 0 は this
+        let first = if info.kind == Kind::Method { 1 } else { 0 };
+        let parameters: Vec<String> = (first..info.parameters.max(first))
+            .map(|i| format!("arg{}", i))
+            .collect();
+        let short = match name.split_once('.') {
+            Some((class, short)) if class == file => short,
+            _ => name,
+        };
+        if !out.ends_with("{\n") {
+            out.push('\n');
+        }
+        out += &format!(
+            "    {}{} {}({}) {{\n",
+            kind,
+            if info.void { " void" } else { "" },
+            short,
+            parameters.join(", ")
+        );
+        if locals > 0 {
+            let names: Vec<String> = (0..locals).map(|i| format!("local{}", i)).collect();
+            out += &format!("        var {};\n", names.join(", "));
+        }
+        let body = Body::new(&ops[start + 1 + skip..end]);
+        let mut statements = body.statements();
+        if info.void {
+            remove_return_values(&mut statements);
+        }
+        render_block(&mut out, &statements, 2, file, functions);
+        out += "    }\n";
+    }
+    out += "}\n";
+    Ok(out)
+}
+
+struct Body<'a> {
+    ops: &'a [Op<'a>],
+    labels: HashMap<&'a str, usize>,
+    // ラベルへのジャンプの数
+    references: HashMap<&'a str, usize>,
+}
+
+impl<'a> Body<'a> {
+    fn new(ops: &'a [Op<'a>]) -> Self {
+        let mut labels = HashMap::new();
+        let mut references = HashMap::new();
+        for (i, op) in ops.iter().enumerate() {
+            if let Op::Label(label) = op {
+                labels.insert(*label, i);
+            }
+            if let Some(label) = op.jump_target() {
+                *references.entry(label).or_insert(0) += 1;
+            }
+        }
+        Body {
+            ops,
+            labels,
+            references,
+        }
+    }
+
+    fn statements(&self) -> Vec<Stmt> {
+        let mut statements = self.block(0, self.ops.len());
+        // 構造にしたジャンプのラベルは出さない
+        let mut targets = BTreeSet::new();
+        jump_targets(&statements, &mut targets);
+        remove_labels(&mut statements, &targets);
+        statements
+    }
+
+    // 1 つのジャンプからしか参照されない、範囲内のラベルの位置
+    fn single_target(&self, label: &str, start: usize, end: usize) -> Option<usize> {
+        let position = *self.labels.get(label)?;
+        (self.references.get(label) == Some(&1) && start < position && position < end)
+            .then_some(position)
+    }
+
+    // ops[start..end] を文にする
+    fn block(&self, start: usize, end: usize) -> Vec<Stmt> {
+        let mut state = State::default();
+        let mut i = start;
+        while i < end {
+            match self.ops[i] {
+                Op::Label(label) => {
+                    state.flush();
+                    // label L ... goto L は while。本体の先頭が後ろへの if-goto なら条件にする
+                    if let Some(back) = (i + 1..end).find(|&j| self.ops[j] == Op::Goto(label))
+                        && self.references.get(label) == Some(&1)
+                    {
+                        let mut body = self.block(i + 1, back);
+                        let exit = match self.ops.get(back + 1) {
+                            Some(Op::Label(exit)) if self.references.get(exit) == Some(&1) => {
+                                Some(*exit)
+                            }
+                            _ => None,
+                        };
+                        let condition = match body.first() {
+                            Some(Stmt::IfGoto(condition, target))
+                                if Some(target.as_str()) == exit =>
+                            {
+                                let condition = condition.clone().not();
+                                body.remove(0);
+                                condition
+                            }
+                            _ => Expr::var("true"),
+                        };
+                        state.out.push(Stmt::While(condition, body));
+                        i = back + 1;
+                        continue;
+                    }
+                    state.out.push(Stmt::Label(label.to_string()));
+                }
+                Op::IfGoto(target) => {
+                    let condition = state.pop();
+                    state.flush();
+                    if let Some((statement, next)) = self.structure_if(&condition, target, i, end) {
+                        state.out.push(statement);
+                        i = next;
+                        continue;
+                    }
+                    state.out.push(Stmt::IfGoto(condition, target.to_string()));
+                }
+                Op::Goto(target) => {
+                    state.flush();
+                    state.out.push(Stmt::Goto(target.to_string()));
+                }
+                Op::Return => {
+                    let value = state.pop();
+                    state.emit(Stmt::Return(Some(value)));
+                }
+                Op::Push(segment, index) => {
+                    let value = state.read(segment, index);
+                    state.stack.push(value);
+                }
+                Op::Pop(segment, index) => {
+                    let value = state.pop();
+                    state.write(segment, index, value);
+                }
+                Op::Arithmetic(command) => state.arithmetic(command),
+                Op::Call(name, arguments) => {
+                    let mut args = Vec::new();
+                    for _ in 0..arguments {
+                        args.push(state.pop());
+                    }
+                    args.reverse();
+                    state.stack.push(call(name, args));
+                }
+                Op::Function(..) => {}
+            }
+            i += 1;
+        }
+        state.flush();
+        state.out
+    }
+
+    // if-goto T の形を見る。T が次の goto F の後にあれば（公式のコンパイラ）T から F までが
+    // then、T が先にあれば（このリポジトリのコンパイラ）その手前までが条件の否定の then。
+    // どちらも then の最後が前への goto E なら E までが else
+    fn structure_if(
+        &self,
+        condition: &Expr,
+        target: &str,
+        i: usize,
+        end: usize,
+    ) -> Option<(Stmt, usize)> {
+        let (condition, then_start, join) = match (self.ops.get(i + 1), self.ops.get(i + 2)) {
+            (Some(Op::Goto(skip)), Some(Op::Label(label)))
+                if *label == target && self.references.get(target) == Some(&1) =>
+            {
+                (
+                    condition.clone(),
+                    i + 3,
+                    self.single_target(skip, i + 2, end)?,
+                )
+            }
+            _ => (
+                condition.clone().not(),
+                i + 1,
+                self.single_target(target, i, end)?,
+            ),
+        };
+        if join > then_start
+            && let Op::Goto(after) = self.ops[join - 1]
+            && let Some(after) = self.single_target(after, join, end)
+        {
+            let then_branch = self.block(then_start, join - 1);
+            let else_branch = self.block(join + 1, after);
+            return Some((Stmt::If(condition, then_branch, else_branch), after + 1));
+        }
+        let then_branch = self.block(then_start, join);
+        Some((Stmt::If(condition, then_branch, Vec::new()), join + 1))
+    }
+}
+
+// ブロックの中のスタックと、pointer 1 と temp に入れた値
+#[derive(Default)]
+struct State {
+    stack: Vec<Expr>,
+    that: Option<Expr>,
+    // (番号, 値, 読まれたか)。読まれずに文が来たら代入として出す
+    temps: Vec<(i32, Expr, bool)>,
+    out: Vec<Stmt>,
+}
+
+impl State {
+    // ブロックの前で積まれた値は分からない
+    fn pop(&mut self) -> Expr {
+        self.stack.pop().unwrap_or_else(|| Expr::var("pop()"))
+    }
+
+    fn emit(&mut self, statement: Stmt) {
+        self.flush_temps();
+        self.out.push(statement);
+    }
+
+    fn flush_temps(&mut self) {
+        for (index, value, read) in std::mem::take(&mut self.temps) {
+            if read {
+                continue;
+            }
+            // do の戻り値は temp 0 に捨てる
+            let statement = match value {
+                Expr::Call(..) if index == 0 => Stmt::Do(value),
+                _ => Stmt::Let(Expr::var(format!("temp{}", index)), value),
+            };
+            self.out.push(statement);
+        }
+    }
+
+    // ジャンプとラベルの前。残った値はそのまま出す
+    fn flush(&mut self) {
+        self.flush_temps();
+        for value in std::mem::take(&mut self.stack) {
+            self.out.push(Stmt::Push(value));
+        }
+        self.that = None;
+    }
+
+    fn read(&mut self, segment: &str, index: i32) -> Expr {
+        match segment {
+            "constant" => Expr::Int(index),
+            "pointer" if index == 0 => Expr::var("this"),
+            "pointer" => self.that.clone().unwrap_or_else(|| Expr::var("that")),
+            "that" => that_index(self.that.clone(), index),
+            "temp" => match self.temps.iter_mut().rev().find(|(i, _, _)| *i == index) {
+                Some((_, value, read)) => {
+                    *read = true;
+                    value.clone()
+                }
+                None => Expr::var(format!("temp{}", index)),
+            },
+            _ => Expr::var(variable(segment, index)),
+        }
+    }
+
+    fn write(&mut self, segment: &str, index: i32, value: Expr) {
+        match segment {
+            "temp" => {
+                // 読まれていない前の値は先に代入として出す
+                if self.temps.iter().any(|(i, _, read)| *i == index && !read) {
+                    self.flush_temps();
+                }
+                self.temps.retain(|(i, _, _)| *i != index);
+                self.temps.push((index, value, false));
+            }
+            "pointer" if index == 1 => self.that = Some(value),
+            "pointer" => self.emit(Stmt::Let(Expr::var("this"), value)),
+            "that" => {
+                let target = that_index(self.that.clone(), index);
+                self.emit(Stmt::Let(target, value));
+            }
+            _ => self.emit(Stmt::Let(Expr::var(variable(segment, index)), value)),
+        }
+    }
+
+    fn arithmetic(&mut self, command: &str) {
+        let op = match command {
+            "neg" | "not" => {
+                let operand = self.pop();
+                let value = match (command, operand) {
+                    ("neg", Expr::Int(n)) => Expr::Int(-n),
+                    ("not", Expr::Int(0)) => Expr::var("true"),
+                    ("neg", operand) => Expr::Unary('-', Box::new(operand)),
+                    (_, operand) => operand.not(),
+                };
+                self.stack.push(value);
+                return;
+            }
+            "add" => "+",
+            "sub" => "-",
+            "and" => "&",
+            "or" => "|",
+            "lt" => "<",
+            "gt" => ">",
+            _ => "=",
+        };
+        let right = self.pop();
+        let left = self.pop();
+        self.stack
+            .push(Expr::Binary(Box::new(left), op, Box::new(right)));
+    }
+}
+
+fn variable(segment: &str, index: i32) -> String {
+    match segment {
+        "local" => format!("local{}", index),
+        "argument" => format!("arg{}", index),
+        "this" => format!("field{}", index),
+        _ => format!("{}{}", segment, index),
+    }
+}
+
+// pointer 1 に a + i を入れた後の that 0 は a[i]
+fn that_index(that: Option<Expr>, index: i32) -> Expr {
+    match that {
+        Some(Expr::Binary(base, "+", offset)) if index == 0 => Expr::Index(base, offset),
+        Some(base) => Expr::Index(Box::new(base), Box::new(Expr::Int(index))),
+        None => Expr::Index(Box::new(Expr::var("that")), Box::new(Expr::Int(index))),
+    }
+}
+
+// 文字列の組み立てはリテラルに、Math.multiply と Math.divide は演算子に戻す
+fn call(name: &str, mut args: Vec<Expr>) -> Expr {
+    match (name, args.as_slice()) {
+        ("String.appendChar", _) if let Some(c) = appended_char(&args) => {
+            let mut text = match args.swap_remove(0) {
+                Expr::Str(text) => text,
+                _ => String::new(),
+            };
+            text.push(c);
+            Expr::Str(text)
+        }
+        ("Math.multiply" | "Math.divide", [_, _]) => {
+            let right = args.pop().unwrap();
+            let left = args.pop().unwrap();
+            let op = if name == "Math.multiply" { "*" } else { "/" };
+            Expr::Binary(Box::new(left), op, Box::new(right))
+        }
+        _ => Expr::Call(name.to_string(), args),
+    }
+}
+
+// 文字列か String.new(n) に足す印字できる文字
+fn appended_char(args: &[Expr]) -> Option<char> {
+    match args {
+        [string, Expr::Int(c)] if is_string(string) && (32..127).contains(c) => {
+            Some(*c as u8 as char)
+        }
+        _ => None,
+    }
+}
+
+fn is_string(expr: &Expr) -> bool {
+    match expr {
+        Expr::Str(_) => true,
+        Expr::Call(name, args) => name == "String.new" && matches!(args.as_slice(), [Expr::Int(_)]),
+        _ => false,
+    }
+}
+
+fn jump_targets(statements: &[Stmt], targets: &mut BTreeSet<String>) {
+    for statement in statements {
+        match statement {
+            Stmt::Goto(label) | Stmt::IfGoto(_, label) => {
+                targets.insert(label.clone());
+            }
+            Stmt::If(_, then_branch, else_branch) => {
+                jump_targets(then_branch, targets);
+                jump_targets(else_branch, targets);
+            }
+            Stmt::While(_, body) => jump_targets(body, targets),
+            _ => {}
+        }
+    }
+}
+
+fn remove_labels(statements: &mut Vec<Stmt>, targets: &BTreeSet<String>) {
+    statements
+        .retain(|statement| !matches!(statement, Stmt::Label(label) if !targets.contains(label)));
+    for statement in statements {
+        match statement {
+            Stmt::If(_, then_branch, else_branch) => {
+                remove_labels(then_branch, targets);
+                remove_labels(else_branch, targets);
+            }
+            Stmt::While(_, body) => remove_labels(body, targets),
+            _ => {}
+        }
+    }
+}
+
+fn remove_return_values(statements: &mut [Stmt]) {
+    for statement in statements {
+        match statement {
+            Stmt::Return(value) if *value == Some(Expr::Int(0)) => *value = None,
+            Stmt::If(_, then_branch, else_branch) => {
+                remove_return_values(then_branch);
+                remove_return_values(else_branch);
+            }
+            Stmt::While(_, body) => remove_return_values(body),
+            _ => {}
+        }
+    }
+}
+
+fn render_block(
+    out: &mut String,
+    statements: &[Stmt],
+    depth: usize,
+    file: &str,
+    functions: &HashMap<String, FunctionInfo>,
+) {
+    let indent = "    ".repeat(depth);
+    let expr = |expr: &Expr| render_expr(expr, file, functions);
+    for statement in statements {
+        match statement {
+            Stmt::Let(target, value) => {
+                *out += &format!("{}let {} = {};\n", indent, expr(target), expr(value));
+            }
+            Stmt::Do(call) => *out += &format!("{}do {};\n", indent, expr(call)),
+            Stmt::If(condition, then_branch, else_branch) => {
+                *out += &format!("{}if ({}) {{\n", indent, expr(condition));
+                render_block(out, then_branch, depth + 1, file, functions);
+                if !else_branch.is_empty() {
+                    *out += &format!("{}}} else {{\n", indent);
+                    render_block(out, else_branch, depth + 1, file, functions);
+                }
+                *out += &format!("{}}}\n", indent);
+            }
+            Stmt::While(condition, body) => {
+                *out += &format!("{}while ({}) {{\n", indent, expr(condition));
+                render_block(out, body, depth + 1, file, functions);
+                *out += &format!("{}}}\n", indent);
+            }
+            Stmt::Return(Some(value)) => *out += &format!("{}return {};\n", indent, expr(value)),
+            Stmt::Return(None) => *out += &format!("{}return;\n", indent),
+            Stmt::Label(label) => *out += &format!("{}label {}:\n", indent, label),
+            Stmt::Goto(label) => *out += &format!("{}goto {};\n", indent, label),
+            Stmt::IfGoto(condition, label) => {
+                *out += &format!("{}if ({}) goto {};\n", indent, expr(condition), label);
+            }
+            Stmt::Push(value) => *out += &format!("{}push {};\n", indent, expr(value)),
+        }
+    }
+}
+
+// Jack に優先順位はないので、右の項と単項演算子の中の二項演算だけ括弧を付ける
+fn render_expr(expr: &Expr, file: &str, functions: &HashMap<String, FunctionInfo>) -> String {
+    let render = |expr: &Expr| render_expr(expr, file, functions);
+    let operand = |expr: &Expr| match expr {
+        Expr::Binary(..) => format!("({})", render(expr)),
+        _ => render(expr),
+    };
+    match expr {
+        Expr::Int(n) => n.to_string(),
+        Expr::Str(text) => format!("\"{}\"", text),
+        Expr::Var(name) => name.clone(),
+        Expr::Unary(op, value) => format!("{}{}", op, operand(value)),
+        Expr::Binary(left, op, right) => {
+            format!("{} {} {}", render(left), op, operand(right))
+        }
+        Expr::Index(base, index) => format!("{}[{}]", operand(base), render(index)),
+        Expr::Call(name, args) => {
+            let is_method = functions
+                .get(name)
+                .is_some_and(|info| info.kind == Kind::Method);
+            let short = name
+                .split_once('.')
+                .map_or(name.as_str(), |(_, short)| short);
+            match args.split_first() {
+                // 同じクラスの this のメソッドは名前だけ
+                Some((Expr::Var(receiver), rest))
+                    if is_method
+                        && receiver == "this"
+                        && name.starts_with(&format!("{}.", file)) =>
+                {
+                    format!("{}({})", short, render_args(rest, &render))
+                }
+                Some((receiver, rest)) if is_method => {
+                    format!(
+                        "{}.{}({})",
+                        operand(receiver),
+                        short,
+                        render_args(rest, &render)
+                    )
+                }
+                _ => format!("{}({})", name, render_args(args, &render)),
+            }
+        }
+    }
+}
+
+fn render_args(args: &[Expr], render: &dyn Fn(&Expr) -> String) -> String {
+    args.iter().map(render).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str, code: &str) -> (String, String) {
+        (name.to_string(), code.replace("; ", "\n"))
+    }
+
+    #[test]
+    fn test_decompile_jack_output() {
+        // このリポジトリの Jack コンパイラの出力
+        let main = source(
+            "Main",
+            "function Main.main 2; push constant 0; pop local 0; \
+             label Main.main.WHILE_EXP0; push local 0; push constant 10; lt; not; \
+             if-goto Main.main.WHILE_END0; \
+             push local 0; push constant 2; call Math.multiply 2; push constant 3; add; \
+             pop local 1; \
+             push local 1; push constant 5; gt; not; if-goto Main.main.IF_FALSE1; \
+             push constant 2; call String.new 1; push constant 104; call String.appendChar 2; \
+             push constant 105; call String.appendChar 2; call Output.printString 1; pop temp 0; \
+             goto Main.main.IF_END1; label Main.main.IF_FALSE1; \
+             push static 0; push local 0; add; push local 1; pop temp 0; pop pointer 1; \
+             push temp 0; pop that 0; \
+             label Main.main.IF_END1; \
+             push local 0; push constant 1; add; pop local 0; \
+             goto Main.main.WHILE_EXP0; label Main.main.WHILE_END0; \
+             push constant 0; return",
+        );
+        let point = source(
+            "Point",
+            "function Point.new 0; push constant 2; call Memory.alloc 1; pop pointer 0; \
+             push argument 0; pop this 0; push pointer 0; return; \
+             function Point.getX 0; push argument 0; pop pointer 0; push this 0; return; \
+             function Point.twice 0; push argument 0; pop pointer 0; \
+             push pointer 0; call Point.getX 1; push argument 1; call Math.divide 2; return",
+        );
+        let uses = source(
+            "Uses",
+            "function Uses.f 0; push constant 1; call Point.new 1; call Point.getX 1; \
+             push constant 3; neg; \
+             call Point.new 1; push constant 2; call Point.twice 2; add; return",
+        );
+        assert_eq!(
+            decompile(&[main, point, uses]).unwrap(),
+            "class Main {
+    static static0;
+
+    function void main() {
+        var local0, local1;
+        let local0 = 0;
+        while (local0 < 10) {
+            let local1 = local0 * 2 + 3;
+            if (local1 > 5) {
+                do Output.printString(\"hi\");
+            } else {
+                let static0[local0] = local1;
+            }
+            let local0 = local0 + 1;
+        }
+        return;
+    }
+}
+
+class Point {
+    field field0, field1;
+
+    constructor new(arg0) {
+        let field0 = arg0;
+        return this;
+    }
+
+    method getX() {
+        return field0;
+    }
+
+    method twice(arg1) {
+        return getX() / arg1;
+    }
+}
+
+class Uses {
+    function f() {
+        return Point.new(1).getX() + Point.new(-3).twice(2);
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_decompile_course_if_and_goto() {
+        // 公式のコンパイラの if と、形にならないジャンプ
+        let code = source(
+            "Main",
+            "function Main.f 0; push argument 0; push constant 0; eq; if-goto IF_TRUE0; \
+             goto IF_FALSE0; label IF_TRUE0; push constant 1; return; label IF_FALSE0; \
+             label LOOP; push argument 0; if-goto LOOP; push constant 2; return",
+        );
+        assert_eq!(
+            decompile(&[code]).unwrap(),
+            "class Main {
+    function f(arg0) {
+        if (arg0 = 0) {
+            return 1;
+        }
+        label LOOP:
+        if (arg0) goto LOOP;
+        return 2;
+    }
+}
+"
+        );
+    }
+}
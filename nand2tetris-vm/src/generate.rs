@@ -0,0 +1,272 @@
+// 差分テスト用に、乱数で正しい VM プログラムを作る
+//
+// 必ず止まり、決まった範囲の RAM しか書かないプログラムにする。
+// - 関数は自分より後ろの関数しか呼ばないので、再帰しない
+// - ジャンプは前向きだけ。後ろ向きは回数の決まったループだけ
+// - 式は積んだ分を 1 つの値にまとめて下ろすので、if で飛び越えてもスタックの深さは変わらない
+// - pointer には POINTERS の範囲のアドレスしか入れない
+// 最上位のコードは Main.vm の先頭にあり、`label HALT` `goto HALT` で止まる。実行する前に
+// SP・LCL・ARG・THIS・THAT に REGISTERS を入れておく
+
+use nand2tetris_core::random::Random;
+use std::ops::Range;
+
+pub const REGISTERS: [u16; 5] = [256, 1000, 1100, 3000, 3010];
+// this・that はここから 16 語まで使う
+pub const POINTERS: Range<u16> = 3000..3500;
+pub const HALT: &str = "HALT";
+
+const FILES: [&str; 2] = ["Main", "Util"];
+// 最上位の local・argument と、temp・static で使う番号の数
+const SLOTS: u32 = 8;
+// this・that で使う番号の数
+const FIELDS: u32 = 16;
+const MAX_FUNCTIONS: usize = 5;
+const MAX_DEPTH: u32 = 3;
+
+struct Function {
+    name: String,
+    file: usize,
+    args: u32,
+    locals: u32,
+}
+
+struct Generator {
+    random: Random,
+    functions: Vec<Function>,
+    labels: usize,
+    // ファイルごとに、次のループのカウンタに使う static の番号
+    counters: [u32; FILES.len()],
+    code: Vec<String>,
+}
+
+// (ファイル名, VM コード) を translate_sources に渡す順に返す
+pub fn generate(seed: u64) -> Vec<(String, String)> {
+    let mut random = Random::new(seed);
+    let functions = (0..random.below(MAX_FUNCTIONS + 1))
+        .map(|i| {
+            let file = random.below(FILES.len());
+            Function {
+                name: format!("{}.f{}", FILES[file], i),
+                file,
+                args: random.below(4) as u32,
+                locals: random.below(4) as u32,
+            }
+        })
+        .collect();
+    let mut generator = Generator {
+        random,
+        functions,
+        labels: 0,
+        counters: [SLOTS; FILES.len()],
+        code: Vec::new(),
+    };
+
+    let mut sources = vec![Vec::new(); FILES.len()];
+    generator.block(None, 0);
+    generator.emit(format!("label {}", HALT));
+    generator.emit(format!("goto {}", HALT));
+    sources[0] = std::mem::take(&mut generator.code);
+    for i in 0..generator.functions.len() {
+        generator.function(i);
+        let file = generator.functions[i].file;
+        sources[file].append(&mut generator.code);
+    }
+
+    FILES
+        .iter()
+        .zip(sources)
+        .filter(|(_, code)| !code.is_empty())
+        .map(|(file, code)| (file.to_string(), code.join("\n") + "\n"))
+        .collect()
+}
+
+impl Generator {
+    fn emit(&mut self, line: String) {
+        self.code.push(line);
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn function(&mut self, i: usize) {
+        let header = format!(
+            "function {} {}",
+            self.functions[i].name, self.functions[i].locals
+        );
+        self.emit(header);
+        self.block(Some(i), 0);
+        self.expression(Some(i), 0);
+        self.emit("return".to_string());
+    }
+
+    // current は実行中の関数。None なら最上位
+    fn block(&mut self, current: Option<usize>, depth: u32) {
+        let statements = 1 + self.random.below(if depth == 0 { 6 } else { 3 });
+        for _ in 0..statements {
+            self.statement(current, depth);
+        }
+    }
+
+    fn statement(&mut self, current: Option<usize>, depth: u32) {
+        let nested = depth < 2;
+        match self.random.below(10) {
+            0 => {
+                let address = self.random.below(POINTERS.len()) as u16 + POINTERS.start;
+                self.emit(format!("push constant {}", address));
+                let pointer = self.random.below(2);
+                self.emit(format!("pop pointer {}", pointer));
+            }
+            1 if nested => {
+                let end = self.label();
+                self.expression(current, 0);
+                self.emit(format!("if-goto {}", end));
+                self.block(current, depth + 1);
+                self.emit(format!("label {}", end));
+            }
+            2 if nested => {
+                let then = self.label();
+                let end = self.label();
+                self.expression(current, 0);
+                self.emit(format!("if-goto {}", then));
+                self.block(current, depth + 1);
+                self.emit(format!("goto {}", end));
+                self.emit(format!("label {}", then));
+                self.block(current, depth + 1);
+                self.emit(format!("label {}", end));
+            }
+            // ループは入れ子にしない。カウンタはほかの文が書かない static
+            3 if depth == 0 => {
+                let file = current.map_or(0, |i| self.functions[i].file);
+                let counter = format!("static {}", self.counters[file]);
+                self.counters[file] += 1;
+                let start = self.label();
+                let count = 1 + self.random.below(3);
+                self.emit(format!("push constant {}", count));
+                self.emit(format!("pop {}", counter));
+                self.emit(format!("label {}", start));
+                self.block(current, depth + 1);
+                for line in [
+                    format!("push {}", counter),
+                    "push constant 1".to_string(),
+                    "sub".to_string(),
+                    format!("pop {}", counter),
+                    format!("push {}", counter),
+                    format!("if-goto {}", start),
+                ] {
+                    self.emit(line);
+                }
+            }
+            _ => {
+                self.expression(current, 0);
+                let (segment, index) = self.variable(current);
+                self.emit(format!("pop {} {}", segment, index));
+            }
+        }
+    }
+
+    // 値を 1 つ積む
+    fn expression(&mut self, current: Option<usize>, depth: u32) {
+        let callees = match current {
+            Some(i) => i + 1..self.functions.len(),
+            None => 0..self.functions.len(),
+        };
+        let choice = if depth >= MAX_DEPTH {
+            0
+        } else {
+            self.random.below(10)
+        };
+        match choice {
+            0..=3 => self.operand(current),
+            4..=6 => {
+                self.expression(current, depth + 1);
+                self.expression(current, depth + 1);
+                let op = *self
+                    .random
+                    .choose(&["add", "sub", "and", "or", "eq", "gt", "lt"]);
+                self.emit(op.to_string());
+            }
+            7 => {
+                self.expression(current, depth + 1);
+                let op = *self.random.choose(&["neg", "not"]);
+                self.emit(op.to_string());
+            }
+            8 if !callees.is_empty() => {
+                let callee = callees.start + self.random.below(callees.len());
+                let args = self.functions[callee].args;
+                for _ in 0..args {
+                    self.expression(current, depth + 1);
+                }
+                let name = self.functions[callee].name.clone();
+                self.emit(format!("call {} {}", name, args));
+            }
+            _ => self.operand(current),
+        }
+    }
+
+    fn operand(&mut self, current: Option<usize>) {
+        match self.random.below(3) {
+            0 => {
+                let (segment, index) = self.variable(current);
+                self.emit(format!("push {} {}", segment, index));
+            }
+            1 => {
+                let pointer = self.random.below(2);
+                self.emit(format!("push pointer {}", pointer));
+            }
+            _ => {
+                // 0・1・32767 のような境目の値がよく出るようにする
+                let value = if self.random.one_in(4) {
+                    self.random.below(16) as u16
+                } else {
+                    self.random.word() & 0x7fff
+                };
+                self.emit(format!("push constant {}", value));
+            }
+        }
+    }
+
+    // 読み書きしてよい (セグメント, 番号)
+    fn variable(&mut self, current: Option<usize>) -> (&'static str, u32) {
+        let (args, locals) = match current {
+            Some(i) => (self.functions[i].args, self.functions[i].locals),
+            None => (SLOTS, SLOTS),
+        };
+        let mut segments = vec![("static", SLOTS), ("temp", SLOTS)];
+        segments.extend([("this", FIELDS), ("that", FIELDS)]);
+        if args > 0 {
+            segments.push(("argument", args));
+        }
+        if locals > 0 {
+            segments.push(("local", locals));
+        }
+        let &(segment, count) = self.random.choose(&segments);
+        (segment, self.random.below(count as usize) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::{Interpreter, Stop};
+
+    #[test]
+    fn test_generated_programs_halt() {
+        for seed in 0..200 {
+            let sources = generate(seed);
+            assert_eq!(sources, generate(seed));
+            let mut interpreter = Interpreter::new(&sources).unwrap();
+            interpreter.ram[..5].copy_from_slice(&REGISTERS);
+            assert_eq!(
+                interpreter.run(1_000_000).unwrap(),
+                Stop::Halted,
+                "seed {}",
+                seed
+            );
+            // 最上位のコードは積んだものをすべて下ろしている
+            assert_eq!(interpreter.ram[0], REGISTERS[0], "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// VM コマンドをアセンブリに変換せずにそのまま実行する
+//
+// 変換器・アセンブラ・CPU エミュレータを突き合わせる差分テストの基準。メモリの使い方は
+// 変換結果と同じ（SP・LCL・ARG・THIS・THAT は RAM[0..5]、temp は RAM[5..13]、static は
+// RAM[16] から出現順）。戻り先は RAM に置かずに別に持つので、フレームの戻りアドレスの場所には
+// 0 を書く。ラベルは変換器と同じく関数ごとに分けない
+
+use anyhow::{Context, Result, bail, ensure};
+use std::collections::HashMap;
+
+use crate::{Command, CommandType, parse_program};
+
+pub const RAM_SIZE: usize = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // 最後のコマンドの後まで進んだ
+    Finished,
+    // `label X` の直後の `goto X` に来た
+    Halted,
+}
+
+pub struct Interpreter {
+    pub ram: Vec<u16>,
+    pub pc: usize,
+    pub steps: u64,
+    // (ファイル名, コマンド)
+    commands: Vec<(String, Command)>,
+    // ラベルと関数の名前 → コマンドの位置
+    labels: HashMap<String, usize>,
+    // "ファイル名.番号" → RAM アドレス
+    statics: HashMap<String, u16>,
+    returns: Vec<usize>,
+}
+
+impl Interpreter {
+    // (ファイル名, VM コード) を translate_sources と同じ順に並べる
+    pub fn new(sources: &[(String, String)]) -> Result<Self> {
+        let mut commands = Vec::new();
+        let mut labels = HashMap::new();
+        let mut statics = HashMap::new();
+        for (file, source) in sources {
+            for command in parse_program(source).context(format!("In {}.vm", file))? {
+                match command.command_type {
+                    CommandType::Label | CommandType::Function => {
+                        let name = command.arg1.clone().context("Missing label")?;
+                        ensure!(
+                            labels.insert(name.clone(), commands.len()).is_none(),
+                            "'{}' is defined twice",
+                            name
+                        );
+                    }
+                    CommandType::Push | CommandType::Pop
+                        if command.arg1.as_deref() == Some("static") =>
+                    {
+                        // アセンブラが変数を割り当てるのと同じく、最初に出てきた順
+                        let next = 16 + statics.len() as u16;
+                        let symbol = format!("{}.{}", file, command.arg2.unwrap_or_default());
+                        statics.entry(symbol).or_insert(next);
+                    }
+                    _ => {}
+                }
+                commands.push((file.clone(), command));
+            }
+        }
+        ensure!(
+            statics.len() <= 240,
+            "Too many static variables: {} (RAM[16..256] holds 240)",
+            statics.len()
+        );
+
+        Ok(Interpreter {
+            ram: vec![0; RAM_SIZE],
+            pc: 0,
+            steps: 0,
+            commands,
+            labels,
+            statics,
+            returns: Vec::new(),
+        })
+    }
+
+    pub fn is_halted(&self) -> bool {
+        match self.commands.get(self.pc) {
+            Some((_, command)) if command.command_type == CommandType::Goto => command
+                .arg1
+                .as_ref()
+                .and_then(|label| self.labels.get(label))
+                .is_some_and(|&target| target + 1 == self.pc),
+            _ => false,
+        }
+    }
+
+    pub fn run(&mut self, max_steps: u64) -> Result<Stop> {
+        loop {
+            if self.pc >= self.commands.len() {
+                return Ok(Stop::Finished);
+            }
+            if self.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            ensure!(
+                self.steps < max_steps,
+                "The program did not stop within {} steps",
+                max_steps
+            );
+            self.step()?;
+        }
+    }
+
+    pub fn step(&mut self) -> Result<()> {
+        let (file, command) = self
+            .commands
+            .get(self.pc)
+            .cloned()
+            .context("The program has already finished")?;
+        let name = command.arg1.as_deref().unwrap_or_default();
+        let n = command.arg2.unwrap_or_default();
+        self.pc += 1;
+        self.steps += 1;
+
+        match command.command_type {
+            CommandType::Arithmetic => self.arithmetic(name)?,
+            CommandType::Push => {
+                let value = match name {
+                    "constant" => n as u16,
+                    _ => {
+                        let address = self.address(&file, name, n)?;
+                        self.read(address)?
+                    }
+                };
+                self.push(value)?;
+            }
+            CommandType::Pop => {
+                let address = self.address(&file, name, n)?;
+                let value = self.pop()?;
+                self.write(address, value)?;
+            }
+            CommandType::Label => {}
+            CommandType::Goto => self.pc = self.label(name)?,
+            CommandType::IfGoto => {
+                if self.pop()? != 0 {
+                    self.pc = self.label(name)?;
+                }
+            }
+            CommandType::Call => {
+                let target = self.label(name)?;
+                self.push(0)?;
+                for register in 1..=4 {
+                    self.push(self.ram[register])?;
+                }
+                let sp = self.ram[0];
+                self.ram[2] = sp.wrapping_sub(5).wrapping_sub(n as u16);
+                self.ram[1] = sp;
+                self.returns.push(self.pc);
+                self.pc = target;
+            }
+            CommandType::Function => {
+                for _ in 0..n {
+                    self.push(0)?;
+                }
+            }
+            CommandType::Return => {
+                let frame = self.ram[1];
+                let value = self.pop()?;
+                self.write(self.ram[2], value)?;
+                self.ram[0] = self.ram[2].wrapping_add(1);
+                // THAT, THIS, ARG, LCL の順に戻す
+                for (register, offset) in (1..=4).rev().zip(1..) {
+                    self.ram[register] = self.read(frame.wrapping_sub(offset))?;
+                }
+                self.pc = self
+                    .returns
+                    .pop()
+                    .context("'return' outside of a function call")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, op: &str) -> Result<()> {
+        let y = self.pop()? as i16;
+        let value = match op {
+            "neg" => y.wrapping_neg(),
+            "not" => !y,
+            _ => {
+                let x = self.pop()? as i16;
+                match op {
+                    "add" => x.wrapping_add(y),
+                    "sub" => x.wrapping_sub(y),
+                    "and" => x & y,
+                    "or" => x | y,
+                    "eq" => -((x == y) as i16),
+                    "gt" => -((x > y) as i16),
+                    "lt" => -((x < y) as i16),
+                    _ => bail!("Unknown arithmetic command '{}'", op),
+                }
+            }
+        };
+        self.push(value as u16)
+    }
+
+    fn address(&self, file: &str, segment: &str, index: i32) -> Result<u16> {
+        let index = index as u16;
+        Ok(match segment {
+            "local" => self.ram[1].wrapping_add(index),
+            "argument" => self.ram[2].wrapping_add(index),
+            "this" => self.ram[3].wrapping_add(index),
+            "that" => self.ram[4].wrapping_add(index),
+            "pointer" => 3 + index,
+            "temp" => 5 + index,
+            "static" => self.statics[&format!("{}.{}", file, index)],
+            _ => bail!("Unknown segment '{}'", segment),
+        })
+    }
+
+    fn label(&self, name: &str) -> Result<usize> {
+        self.labels
+            .get(name)
+            .copied()
+            .with_context(|| format!("Unknown label or function '{}'", name))
+    }
+
+    fn read(&self, address: u16) -> Result<u16> {
+        self.ram
+            .get(address as usize)
+            .copied()
+            .with_context(|| format!("Address {} is out of RAM", address))
+    }
+
+    fn write(&mut self, address: u16, value: u16) -> Result<()> {
+        *self
+            .ram
+            .get_mut(address as usize)
+            .with_context(|| format!("Address {} is out of RAM", address))? = value;
+        Ok(())
+    }
+
+    fn push(&mut self, value: u16) -> Result<()> {
+        let sp = self.ram[0];
+        self.write(sp, value)?;
+        self.ram[0] = sp.wrapping_add(1);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u16> {
+        let sp = self.ram[0].wrapping_sub(1);
+        self.ram[0] = sp;
+        self.read(sp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(sources: &[(&str, &str)]) -> Interpreter {
+        let sources: Vec<(String, String)> = sources
+            .iter()
+            .map(|(file, source)| (file.to_string(), source.to_string()))
+            .collect();
+        let mut interpreter = Interpreter::new(&sources).unwrap();
+        interpreter.ram[0] = 256;
+        assert_eq!(interpreter.run(10_000).unwrap(), Stop::Halted);
+        interpreter
+    }
+
+    #[test]
+    fn test_arithmetic_compares_without_overflow() {
+        let vm = run(&[(
+            "Main",
+            "push constant 32767\npush constant 1\nneg\ngt\n\
+             push constant 32767\nneg\npush constant 2\nlt\n\
+             push constant 7\npush constant 9\nsub\n\
+             label HALT\ngoto HALT\n",
+        )]);
+        assert_eq!(vm.ram[0], 259);
+        assert_eq!(&vm.ram[256..259], &[0xffff, 0xffff, (-2i16) as u16]);
+    }
+
+    #[test]
+    fn test_call_and_statics() {
+        let vm = run(&[
+            (
+                "Main",
+                "push constant 3\npush constant 4\ncall Util.add 2\npop static 1\n\
+                 label HALT\ngoto HALT\n",
+            ),
+            (
+                "Util",
+                "function Util.add 1\npush argument 0\npush argument 1\nadd\npop local 0\n\
+                 push local 0\npop static 0\npush local 0\nreturn\n",
+            ),
+        ]);
+        // 先に出てくる Main.1 が RAM[16]、Util.0 が RAM[17]
+        assert_eq!(vm.ram[16], 7);
+        assert_eq!(vm.ram[17], 7);
+        assert_eq!(vm.ram[0], 256);
+        assert!(vm.returns.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let sources = vec![("Main".to_string(), "goto NOWHERE\n".to_string())];
+        let mut vm = Interpreter::new(&sources).unwrap();
+        assert!(vm.run(10).is_err());
+        let sources = vec![("Main".to_string(), "label A\nlabel A\n".to_string())];
+        assert!(Interpreter::new(&sources).is_err());
+    }
+}
@@ -0,0 +1,1752 @@
+pub mod decompile;
+pub mod generate;
+pub mod interpret;
+
+use anyhow::{Context, Result, bail, ensure};
+
+use nand2tetris_core::{
+    cli::Failure,
+    diagnostic::{at_line, in_file},
+    source::code_lines,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn validate_label(label: &str) -> Result<()> {
+    ensure!(!label.is_empty(), "label name cannot be empty");
+
+    let re = Regex::new(r"^[a-zA-Z_.:][a-zA-Z0-9_.:]*$").unwrap();
+
+    ensure!(
+        re.is_match(label),
+        "Invalid label name '{}': must start with letter or underscore, \
+            and contain only letters, digits, '_', '.', ':'",
+        label
+    );
+
+    Ok(())
+}
+
+fn validate_segment(segment: &str, index: i32, is_pop: bool) -> Result<()> {
+    let max_index = match segment {
+        "argument" | "local" | "static" | "this" | "that" | "constant" => 32767,
+        "pointer" => 1,
+        "temp" => 7,
+        _ => bail!("Unknown segment: '{}'", segment),
+    };
+
+    ensure!(
+        !(is_pop && segment == "constant"),
+        "Cannot pop to 'constant' segment"
+    );
+    ensure!(
+        (0..=max_index).contains(&index),
+        "Index {} out of range for segment '{}' (0..={})",
+        index,
+        segment,
+        max_index
+    );
+
+    Ok(())
+}
+
+fn validate_count(n: i32) -> Result<()> {
+    ensure!(
+        (0..=32767).contains(&n),
+        "Invalid count {}: must be between 0 and 32767",
+        n
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommandType {
+    Arithmetic,
+    Push,
+    Pop,
+    Label,
+    Goto,
+    IfGoto,
+    Call,
+    Function,
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub command_type: CommandType,
+    pub arg1: Option<String>,
+    pub arg2: Option<i32>,
+}
+
+pub struct VmParser {
+    lines: Vec<String>,
+    // 各コマンドの元の行番号（空行・コメントを含めて数える）
+    line_numbers: Vec<usize>,
+    current: usize,
+}
+
+impl VmParser {
+    pub fn new(input: &str) -> Self {
+        let (line_numbers, lines): (Vec<usize>, Vec<String>) = code_lines(input)
+            .map(|(number, line)| (number, line.to_string()))
+            .unzip();
+
+        VmParser {
+            lines,
+            line_numbers,
+            current: 0,
+        }
+    }
+
+    pub fn has_more_commands(&self) -> bool {
+        self.current < self.lines.len()
+    }
+
+    pub fn advance(&mut self) {
+        if self.has_more_commands() {
+            self.current += 1;
+        }
+    }
+
+    pub fn parse(&self) -> Result<Command> {
+        ensure!(self.has_more_commands(), "No more commands availavle");
+
+        let line = &self.lines[self.current];
+        let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+
+        let cmd_name = parts.first().context("Empty command")?;
+
+        match *cmd_name {
+            "add" | "sub" | "neg" | "eq" | "gt" | "lt" | "and" | "or" | "not" => Ok(Command {
+                command_type: CommandType::Arithmetic,
+                arg1: Some(cmd_name.to_string()),
+                arg2: None,
+            }),
+            "push" => {
+                let segment = parts
+                    .get(1)
+                    .context("Missing segment argument for 'push' command")?;
+                let index = parts
+                    .get(2)
+                    .context("Missing segment argument for 'push' command")?
+                    .parse()
+                    .context(format!(
+                        "Invalid index: '{}' is not a valid integer",
+                        parts[2]
+                    ))?;
+                validate_segment(segment, index, false)?;
+
+                Ok(Command {
+                    command_type: CommandType::Push,
+                    arg1: Some(segment.to_string()),
+                    arg2: Some(index),
+                })
+            }
+            "pop" => {
+                let segment = parts
+                    .get(1)
+                    .context("Missing segment argument for 'push' command")?;
+                let index = parts
+                    .get(2)
+                    .context("Missing segment argument for 'push' command")?
+                    .parse()
+                    .context(format!(
+                        "Invalid index: '{}' is not a valid integer",
+                        parts[2]
+                    ))?;
+                validate_segment(segment, index, true)?;
+
+                Ok(Command {
+                    command_type: CommandType::Pop,
+                    arg1: Some(segment.to_string()),
+                    arg2: Some(index),
+                })
+            }
+            "label" => {
+                let label = parts
+                    .get(1)
+                    .context("Missing label name for 'label' command")?;
+                validate_label(label).context("Invalid label in 'label' command")?;
+
+                Ok(Command {
+                    command_type: CommandType::Label,
+                    arg1: Some(label.to_string()),
+                    arg2: None,
+                })
+            }
+            "goto" => {
+                let label = parts
+                    .get(1)
+                    .context("Missing label name for 'goto' command")?;
+                validate_label(label).context("Invalid label in 'goto' command")?;
+
+                Ok(Command {
+                    command_type: CommandType::Goto,
+                    arg1: Some(label.to_string()),
+                    arg2: None,
+                })
+            }
+            "if-goto" => {
+                let label = parts
+                    .get(1)
+                    .context("Missing label name for 'if-goto' command")?;
+                validate_label(label).context("Invalid label in 'if-goto' command")?;
+
+                Ok(Command {
+                    command_type: CommandType::IfGoto,
+                    arg1: Some(label.to_string()),
+                    arg2: None,
+                })
+            }
+            "call" => {
+                let f_name = parts
+                    .get(1)
+                    .context("Missing function for 'call' command")?;
+                let n_vars: i32 = parts
+                    .get(2)
+                    .context("Missing local variable count for 'call' command")?
+                    .parse()
+                    .context("Invalid number for variable count")?;
+                validate_count(n_vars)?;
+
+                Ok(Command {
+                    command_type: CommandType::Call,
+                    arg1: Some(f_name.to_string()),
+                    arg2: Some(n_vars),
+                })
+            }
+            "function" => {
+                let f_name = parts
+                    .get(1)
+                    .context("Missing function for 'call' command")?;
+                let n_vars: i32 = parts
+                    .get(2)
+                    .context("Missing local variable count for 'call' command")?
+                    .parse()
+                    .context("Invalid number for variable count")?;
+                validate_count(n_vars)?;
+
+                Ok(Command {
+                    command_type: CommandType::Function,
+                    arg1: Some(f_name.to_string()),
+                    arg2: Some(n_vars),
+                })
+            }
+            "return" => Ok(Command {
+                command_type: CommandType::Return,
+                arg1: None,
+                arg2: None,
+            }),
+            _ => bail!(format!("Unkonown command: '{}'", cmd_name)),
+        }
+    }
+
+    // コメントと前後の空白を除いた現在のコマンド
+    pub fn current_line(&self) -> Option<&str> {
+        self.lines.get(self.current).map(String::as_str)
+    }
+
+    pub fn current_line_number(&self) -> usize {
+        self.line_numbers
+            .get(self.current)
+            .copied()
+            .unwrap_or(self.current + 1)
+    }
+}
+
+pub fn parse_program(input: &str) -> Result<Vec<Command>> {
+    let mut parser = VmParser::new(input);
+    let mut commands = Vec::new();
+
+    while parser.has_more_commands() {
+        let line_num = parser.current_line_number();
+        commands.push(
+            parser
+                .parse()
+                .map_err(|e| Failure::Parse.wrap(at_line(line_num, e)))?,
+        );
+        parser.advance();
+    }
+
+    Ok(commands)
+}
+
+// .vm ファイル名（拡張子なし）と行番号
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+// 変換結果のアセンブリと、その各行がどの VM コマンドから生成されたか
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Translation {
+    pub asm: String,
+    // アセンブリの行（0始まり）ごとの元の位置。ブートストラップは None
+    pub source_map: Vec<Option<SourceLocation>>,
+}
+
+impl Translation {
+    pub fn location(&self, asm_line: usize) -> Option<&SourceLocation> {
+        self.source_map.get(asm_line)?.as_ref()
+    }
+}
+
+// 1回の実行にかかるサイクル数の最小と最大
+// ブロック内のラベルへのジャンプは分岐の両方をたどり、ブロックの外へのジャンプで終わる
+pub fn cycle_cost(block: &[String]) -> (usize, usize) {
+    let mut labels = HashMap::new();
+    let mut instructions = Vec::new();
+    for line in block {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        match line.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            Some(label) => {
+                labels.insert(label, instructions.len());
+            }
+            None => instructions.push(line),
+        }
+    }
+
+    let mut costs = Vec::new();
+    walk_block(&instructions, &labels, 0, 0, &mut costs);
+    let min = costs.iter().copied().min().unwrap_or_default();
+    let max = costs.iter().copied().max().unwrap_or_default();
+    (min, max)
+}
+
+// gt と lt のサブルーチン。R15 に戻り先を入れて VM$gt か VM$lt に飛んでくる。x - y はあふれる
+// ことがある（32767 gt -1 など）ので、符号が違えば x の符号で決める。y を R13 に置き、結果は
+// x の場所に書く
+fn compare_routine(op: &str) -> Vec<String> {
+    let label = |name: &str| format!("VM${}${}", op, name);
+    let (x_positive, x_negative) = if op == "gt" {
+        ("TRUE", "FALSE")
+    } else {
+        ("FALSE", "TRUE")
+    };
+    let jump = if op == "gt" { "JGT" } else { "JLT" };
+    vec![
+        format!("(VM${})", op),
+        "@SP".to_string(),
+        "AM=M-1".to_string(),
+        "D=M".to_string(),
+        "@R13".to_string(),
+        "M=D".to_string(),
+        "@SP".to_string(),
+        "A=M-1".to_string(),
+        "D=M".to_string(),
+        format!("@{}", label("XNEG")),
+        "D;JLT".to_string(),
+        "@R13".to_string(),
+        "D=M".to_string(),
+        format!("@{}", label(x_positive)),
+        "D;JLT".to_string(),
+        format!("@{}", label("SAME")),
+        "0;JMP".to_string(),
+        format!("({})", label("XNEG")),
+        "@R13".to_string(),
+        "D=M".to_string(),
+        format!("@{}", label(x_negative)),
+        "D;JGE".to_string(),
+        format!("({})", label("SAME")),
+        "@SP".to_string(),
+        "A=M-1".to_string(),
+        "D=M".to_string(),
+        "@R13".to_string(),
+        "D=D-M".to_string(),
+        format!("@{}", label("TRUE")),
+        format!("D;{}", jump),
+        format!("({})", label("FALSE")),
+        "D=0".to_string(),
+        format!("@{}", label("END")),
+        "0;JMP".to_string(),
+        format!("({})", label("TRUE")),
+        "D=-1".to_string(),
+        format!("({})", label("END")),
+        "@SP".to_string(),
+        "A=M-1".to_string(),
+        "M=D".to_string(),
+        "@R15".to_string(),
+        "A=M".to_string(),
+        "0;JMP".to_string(),
+    ]
+}
+
+fn walk_block(
+    instructions: &[&str],
+    labels: &HashMap<&str, usize>,
+    start: usize,
+    mut cycles: usize,
+    costs: &mut Vec<usize>,
+) {
+    for index in start..instructions.len() {
+        cycles += 1;
+        let Some((_, jump)) = instructions[index].split_once(';') else {
+            continue;
+        };
+        let label = index
+            .checked_sub(1)
+            .and_then(|previous| instructions[previous].strip_prefix('@'));
+        // 比較のサブルーチンは実行して次の命令に戻ってくる
+        if let Some(op) = label.and_then(|label| label.strip_prefix("VM$"))
+            && (op == "gt" || op == "lt")
+        {
+            let (min, max) = cycle_cost(&compare_routine(op));
+            walk_block(instructions, labels, index + 1, cycles + min, costs);
+            walk_block(instructions, labels, index + 1, cycles + max, costs);
+            return;
+        }
+        // 最初の比較ではサブルーチンがその場にあり、戻り先はすぐ後ろ
+        if instructions[..index].ends_with(&["@R15", "A=M"]) {
+            continue;
+        }
+        // 直前の @LABEL がブロック内の先のラベルならその先もたどる。後ろへのジャンプは繰り返しなので数えない
+        let target = label
+            .and_then(|label| labels.get(label))
+            .filter(|&&target| target > index);
+        match target {
+            Some(&target) => walk_block(instructions, labels, target, cycles, costs),
+            None => costs.push(cycles),
+        }
+        if jump == "JMP" {
+            return;
+        }
+    }
+    costs.push(cycles);
+}
+
+fn format_cycles((min, max): (usize, usize)) -> String {
+    if min == max {
+        format!("{} cycles", min)
+    } else {
+        format!("{}-{} cycles", min, max)
+    }
+}
+
+// --annotate で関数ごとに合計する
+struct FunctionCost {
+    name: String,
+    // 関数のコメント行の位置
+    line: usize,
+    commands: usize,
+    instructions: usize,
+    min: usize,
+    max: usize,
+}
+
+struct CodeWriter {
+    output: Vec<String>,
+    source_map: Vec<Option<SourceLocation>>,
+    filename: String,
+    label_counter: i32,
+    call_counter: i32,
+    annotate: bool,
+    function: Option<FunctionCost>,
+    // 出力済みの比較のサブルーチン
+    compare_routines: HashSet<String>,
+}
+
+impl CodeWriter {
+    fn new(filename: &str) -> Self {
+        CodeWriter {
+            output: Vec::new(),
+            source_map: Vec::new(),
+            filename: filename.to_string(),
+            label_counter: 0,
+            call_counter: 0,
+            annotate: false,
+            function: None,
+            compare_routines: HashSet::new(),
+        }
+    }
+
+    // start 以降に出力したブロックの先頭に VM コマンドとサイクル数のコメントを付ける
+    fn annotate_block(&mut self, start: usize, command: &str) {
+        let block = &self.output[start..];
+        let cost = cycle_cost(block);
+        let instructions = block
+            .iter()
+            .filter(|line| !line.starts_with("//") && !line.starts_with('('))
+            .count();
+
+        let comment = format!("// {} ({})", command, format_cycles(cost));
+        // write_call などが付けた見出しのコメントは置き換える
+        if self
+            .output
+            .get(start)
+            .is_some_and(|line| line.starts_with("//"))
+        {
+            self.output[start] = comment;
+        } else {
+            self.output.insert(start, comment);
+        }
+
+        if let Some(function) = &mut self.function {
+            function.commands += 1;
+            function.instructions += instructions;
+            function.min += cost.0;
+            function.max += cost.1;
+        }
+    }
+
+    fn begin_function(&mut self, name: &str, line: usize) {
+        self.end_function();
+        self.function = Some(FunctionCost {
+            name: name.to_string(),
+            line,
+            commands: 0,
+            instructions: 0,
+            min: 0,
+            max: 0,
+        });
+    }
+
+    // 関数のコメントの次の行に合計を書く
+    fn end_function(&mut self) {
+        let Some(function) = self.function.take() else {
+            return;
+        };
+        let summary = format!(
+            "// {} total: {} commands, {} instructions, {} if each command runs once",
+            function.name,
+            function.commands,
+            function.instructions,
+            format_cycles((function.min, function.max))
+        );
+        self.output.insert(function.line + 1, summary);
+        let location = self.source_map.get(function.line).cloned().flatten();
+        self.source_map.insert(function.line + 1, location);
+    }
+
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = filename.to_string();
+    }
+
+    fn write_arithmetic(&mut self, cmd: &str) {
+        match cmd {
+            "add" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M".to_string(),
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=D+M".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "sub" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M".to_string(),
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=M-D".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "neg" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=-M".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "eq" => {
+                let true_label = format!("TRUE_{}", self.label_counter);
+                let end_label = format!("END_{}", self.label_counter);
+                self.label_counter += 1;
+
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M".to_string(),
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M-D".to_string(),
+                    format!("@{}", true_label),
+                    "D;JEQ".to_string(),
+                    "@SP".to_string(),
+                    "A=M".to_string(),
+                    "M=0".to_string(),
+                    format!("@{}", end_label),
+                    "0;JMP".to_string(),
+                    format!("({})", true_label),
+                    "@SP".to_string(),
+                    "A=M".to_string(),
+                    "M=-1".to_string(),
+                    format!("({})", end_label),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "gt" | "lt" => {
+                let return_label = format!("VM${}$ret{}", cmd, self.label_counter);
+                self.label_counter += 1;
+
+                self.output.extend(vec![
+                    format!("@{}", return_label),
+                    "D=A".to_string(),
+                    "@R15".to_string(),
+                    "M=D".to_string(),
+                ]);
+                // 最初に使うところにサブルーチンを置いてそのまま実行し、2 回目からは呼ぶ
+                if self.compare_routines.insert(cmd.to_string()) {
+                    self.output.extend(compare_routine(cmd));
+                } else {
+                    self.output
+                        .extend(vec![format!("@VM${}", cmd), "0;JMP".to_string()]);
+                }
+                self.output.push(format!("({})", return_label));
+            }
+            "and" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M".to_string(),
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=D&M".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "or" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "D=M".to_string(),
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=D|M".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            "not" => {
+                self.output.extend(vec![
+                    "@SP".to_string(),
+                    "M=M-1".to_string(),
+                    "A=M".to_string(),
+                    "M=!M".to_string(),
+                    "@SP".to_string(),
+                    "M=M+1".to_string(),
+                ]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_push(&mut self, segment: &str, index: i32) {
+        match segment {
+            "argument" => {
+                self.push_segment("ARG", index);
+            }
+            "local" => {
+                self.push_segment("LCL", index);
+            }
+            "static" => {
+                self.push_value(&format!("{}.{}", self.filename, index), false);
+            }
+            "constant" => {
+                self.push_value(&index.to_string(), true);
+            }
+            "this" => {
+                self.push_segment("THIS", index);
+            }
+            "that" => {
+                self.push_segment("THAT", index);
+            }
+            "pointer" => {
+                let register = if index == 0 { "THIS" } else { "THAT" };
+                self.push_value(register, false);
+            }
+            "temp" => {
+                self.push_value(&(5 + index).to_string(), false);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_pop(&mut self, segment: &str, index: i32) {
+        match segment {
+            "argument" => {
+                self.pop_segment("ARG", index);
+            }
+            "local" => {
+                self.pop_segment("LCL", index);
+            }
+            "static" => {
+                self.pop_direct(&format!("{}.{}", self.filename, index));
+            }
+            "this" => {
+                self.pop_segment("THIS", index);
+            }
+            "that" => {
+                self.pop_segment("THAT", index);
+            }
+            "pointer" => {
+                let register = if index == 0 { "THIS" } else { "THAT" };
+                self.pop_direct(register);
+            }
+            "temp" => {
+                self.pop_direct(&(5 + index).to_string());
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_label(&mut self, label: &str) {
+        self.output.push(format!("({})", label));
+    }
+
+    fn write_goto(&mut self, label: &str) {
+        self.output.push(format!("@{}", label));
+        self.output.push("0;JMP".to_string());
+    }
+
+    fn write_if_goto(&mut self, label: &str) {
+        self.output.extend(vec![
+            "@SP".to_string(),
+            "M=M-1".to_string(),
+            "A=M".to_string(),
+            "D=M".to_string(),
+            format!("@{}", label),
+            "D;JNE".to_string(),
+        ]);
+    }
+
+    fn write_call(&mut self, function_name: &str, n_args: i32) {
+        self.output.push("// call".to_string());
+
+        let return_address_symbol = format!("{}$ret{}", function_name, self.call_counter);
+        self.push_value(&return_address_symbol, true);
+
+        for register in ["LCL", "ARG", "THIS", "THAT"] {
+            self.push_value(register, false);
+        }
+
+        // ARGを引数の最初の座標を指すようにする
+        // returnAddress, LCL, ARG, THIS, THAT と nArgs分SPをインクリメントしているので、
+        // SP - 5 - nArgsでArgの最初の座標を指す
+        self.output.extend(vec![
+            "@SP".to_string(),
+            "D=M".to_string(),
+            format!("@{}", 5 + n_args),
+            "D=D-A".to_string(),
+            "@ARG".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        self.output.extend(vec![
+            "@SP".to_string(),
+            "D=M".to_string(),
+            "@LCL".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        self.write_goto(function_name);
+
+        self.output.push(format!("({return_address_symbol})"));
+
+        self.call_counter += 1;
+    }
+
+    fn write_function(&mut self, function_name: &str, n_args: i32) {
+        self.output.push("// function".to_string());
+
+        self.output.push(format!("({})", function_name));
+
+        for _ in 0..n_args {
+            self.write_push("constant", 0);
+        }
+    }
+
+    fn write_return(&mut self) {
+        self.output.push("// return".to_string());
+
+        // FRAME = LCL
+        self.output.extend(vec![
+            "@LCL".to_string(),
+            "D=M".to_string(),
+            "@13".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        // RET = *(FRAME - 5)
+        self.output.extend(vec![
+            "@5".to_string(),
+            "A=D-A".to_string(),
+            "D=M".to_string(),
+            "@R14".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        // *ARG = pop()
+        self.output.extend(vec![
+            "@SP".to_string(),
+            "M=M-1".to_string(),
+            "A=M".to_string(),
+            "D=M".to_string(),
+            "@ARG".to_string(),
+            "A=M".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        // SP = ARG + 1
+        self.output.extend(vec![
+            "@ARG".to_string(),
+            "D=M+1".to_string(),
+            "@SP".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        // THAT, THIS, ARG, LCL を復元
+        for segment in ["THAT", "THIS", "ARG", "LCL"] {
+            self.output.extend(vec![
+                "@R13".to_string(),
+                "AM=M-1".to_string(),
+                "D=M".to_string(),
+                format!("@{}", segment),
+                "M=D".to_string(),
+            ]);
+        }
+
+        // goto RET
+        self.output.extend(vec![
+            "@R14".to_string(),
+            "A=M".to_string(),
+            "0;JMP".to_string(),
+        ]);
+    }
+
+    fn write_bootstrap(&mut self) {
+        self.output.push("// bootstrap".to_string());
+
+        self.output.extend(vec![
+            "@256".to_string(),
+            "D=A".to_string(),
+            "@SP".to_string(),
+            "M=D".to_string(),
+        ]);
+
+        self.write_call("Sys.init", 0);
+        self.source_map.resize(self.output.len(), None);
+    }
+
+    fn get_output(&self) -> String {
+        self.output.join("\n")
+    }
+
+    // 前回の記録以降に出力した行を line 行目のコマンドに対応付ける
+    fn map_source(&mut self, line: usize) {
+        let location = SourceLocation {
+            file: self.filename.clone(),
+            line,
+        };
+        self.source_map.resize(self.output.len(), Some(location));
+    }
+
+    fn into_translation(mut self) -> Translation {
+        self.source_map.resize(self.output.len(), None);
+        Translation {
+            asm: self.get_output(),
+            source_map: self.source_map,
+        }
+    }
+
+    // 値を直接push（定数またはレジスタの値）
+    fn push_value(&mut self, value: &str, is_address: bool) {
+        let address = if is_address { "A" } else { "M" };
+        self.output.extend(vec![
+            format!("@{value}"),
+            format!("D={address}"),
+            "@SP".to_string(),
+            "A=M".to_string(),
+            "M=D".to_string(),
+            "@SP".to_string(),
+            "M=M+1".to_string(),
+        ]);
+    }
+
+    // ベースアドレス + index の値をpush
+    fn push_segment(&mut self, base: &str, index: i32) {
+        self.output.extend(vec![
+            format!("@{}", index),
+            "D=A".to_string(),
+            format!("@{}", base),
+            "A=D+M".to_string(),
+            "D=M".to_string(),
+            "@SP".to_string(),
+            "A=M".to_string(),
+            "M=D".to_string(),
+            "@SP".to_string(),
+            "M=M+1".to_string(),
+        ]);
+    }
+
+    // スタックからpopして直接アドレスに格納
+    fn pop_direct(&mut self, address: &str) {
+        self.output.extend(vec![
+            "@SP".to_string(),
+            "M=M-1".to_string(),
+            "A=M".to_string(),
+            "D=M".to_string(),
+            format!("@{}", address),
+            "M=D".to_string(),
+        ]);
+    }
+
+    // スタックからpopしてベースアドレス + index に格納
+    fn pop_segment(&mut self, base: &str, index: i32) {
+        self.output.extend(vec![
+            format!("@{}", index),
+            "D=A".to_string(),
+            format!("@{}", base),
+            "D=D+M".to_string(),
+            "@R13".to_string(),
+            "M=D".to_string(),
+            "@SP".to_string(),
+            "M=M-1".to_string(),
+            "A=M".to_string(),
+            "D=M".to_string(),
+            "@R13".to_string(),
+            "A=M".to_string(),
+            "M=D".to_string(),
+        ]);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranslateOptions {
+    pub bootstrap: bool,
+    // VM コマンドごとにコメントでサイクル数を書き、関数ごとに合計する
+    pub annotate: bool,
+    // ファイルの後に続けて変換する (ファイル名, VM コード)。OS のクラスを足すのに使う
+    pub libraries: Vec<(String, String)>,
+}
+
+pub struct VMTranslator;
+
+impl VMTranslator {
+    pub fn translate(input: &str, filename: &str) -> Result<String> {
+        let mut code_writer = CodeWriter::new(filename);
+
+        Self::translate_vm(input, filename, &mut code_writer)?;
+
+        Ok(code_writer.get_output())
+    }
+
+    fn translate_vm(input: &str, filename: &str, code_writer: &mut CodeWriter) -> Result<()> {
+        code_writer.set_filename(filename);
+        let mut parser = VmParser::new(input);
+
+        while parser.has_more_commands() {
+            let line_num = parser.current_line_number();
+            let start = code_writer.output.len();
+
+            let cmd = parser.parse().map_err(|e| {
+                Failure::Parse.wrap(in_file(&format!("{}.vm", filename), line_num, e))
+            })?;
+            if code_writer.annotate && cmd.command_type == CommandType::Function {
+                let name = cmd.arg1.as_deref().context("Missing function name")?;
+                code_writer.begin_function(name, start);
+            }
+
+            match cmd.command_type {
+                CommandType::Arithmetic => {
+                    let op = cmd.arg1.context("Missing arithmetic operatioin")?;
+
+                    code_writer.write_arithmetic(&op);
+                }
+                CommandType::Push => {
+                    let segment = cmd.arg1.context("Missing segment")?;
+                    let index = cmd.arg2.context("Missing segment")?;
+                    code_writer.write_push(&segment, index);
+                }
+                CommandType::Pop => {
+                    let segment = cmd.arg1.context("Missing segment")?;
+                    let index = cmd.arg2.context("Missing segment")?;
+                    code_writer.write_pop(&segment, index);
+                }
+                CommandType::Label => {
+                    let label = cmd.arg1.context("Missing label")?;
+                    code_writer.write_label(&label);
+                }
+                CommandType::Goto => {
+                    let label = cmd.arg1.context("Missing goto label")?;
+                    code_writer.write_goto(&label);
+                }
+                CommandType::IfGoto => {
+                    let label = cmd.arg1.context("Missing if-goto label")?;
+                    code_writer.write_if_goto(&label);
+                }
+                CommandType::Call => {
+                    let function_name = cmd.arg1.context("Missing function name")?;
+                    let n_args = cmd.arg2.context("Missing function name")?;
+                    code_writer.write_call(&function_name, n_args);
+                }
+                CommandType::Function => {
+                    let function_name = cmd.arg1.context("Missing function name")?;
+                    let n_args = cmd.arg2.context("Missing function name")?;
+                    code_writer.write_function(&function_name, n_args);
+                }
+                CommandType::Return => code_writer.write_return(),
+            }
+            if code_writer.annotate {
+                let command = parser.current_line().unwrap_or_default();
+                code_writer.annotate_block(start, command);
+            }
+            code_writer.map_source(line_num);
+            parser.advance();
+        }
+        code_writer.end_function();
+
+        Ok(())
+    }
+
+    pub fn translate_file(path: &Path, bootstrap: bool) -> Result<()> {
+        Self::translate_file_with(
+            path,
+            &TranslateOptions {
+                bootstrap,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn translate_file_with(path: &Path, options: &TranslateOptions) -> Result<()> {
+        let translation = Self::translate_path_with(path, options)?;
+        let output_path = Self::output_path(path)?;
+        fs::write(&output_path, translation.asm)
+            .context(format!("Failed to write '{}'", output_path.display()))?;
+        Ok(())
+    }
+
+    // ファイルなら <name>.asm、ディレクトリなら <dir>/<dir>.asm。"." や ".." には名前がないので
+    // 絶対パスにして名前を取る
+    pub fn output_path(path: &Path) -> Result<PathBuf> {
+        if path.is_dir() {
+            let absolute = fs::canonicalize(path)
+                .context(format!("Failed to read directory '{}'", path.display()))?;
+            let dir_name = absolute
+                .file_name()
+                .and_then(|s| s.to_str())
+                .context("Invalid directory name")?;
+            Ok(path.join(format!("{}.asm", dir_name)))
+        } else {
+            Ok(path.with_extension("asm"))
+        }
+    }
+
+    // ファイルを書かずにメモリ上で変換する
+    pub fn translate_path(path: &Path, bootstrap: bool) -> Result<Translation> {
+        Self::translate_path_with(
+            path,
+            &TranslateOptions {
+                bootstrap,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn translate_path_with(path: &Path, options: &TranslateOptions) -> Result<Translation> {
+        Self::translate_sources(&Self::read_sources(path)?, options)
+    }
+
+    // .vm ファイルかディレクトリの中の .vm（名前順）を (ファイル名, VM コード) にする
+    pub fn read_sources(path: &Path) -> Result<Vec<(String, String)>> {
+        let vm_files = if path.is_dir() {
+            Self::collect_vm_files(path)?
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut sources = Vec::new();
+        for vm_file in &vm_files {
+            let input = fs::read_to_string(vm_file)
+                .context(format!("Failed to read file '{}'", vm_file.display()))?;
+            let filename = vm_file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .context("Invalid filename")?;
+            sources.push((filename.to_string(), input));
+        }
+        Ok(sources)
+    }
+
+    // (ファイル名, VM コード) を順番に変換する。options.libraries はその後に続ける
+    pub fn translate_sources(
+        sources: &[(String, String)],
+        options: &TranslateOptions,
+    ) -> Result<Translation> {
+        let mut code_writer = CodeWriter::new("");
+        code_writer.annotate = options.annotate;
+
+        if options.bootstrap {
+            code_writer.write_bootstrap();
+            if options.annotate {
+                code_writer.annotate_block(0, "bootstrap");
+            }
+        }
+
+        for (filename, input) in sources.iter().chain(&options.libraries) {
+            Self::translate_vm(input, filename, &mut code_writer)?;
+        }
+
+        Ok(code_writer.into_translation())
+    }
+
+    fn collect_vm_files(dir: &Path) -> Result<Vec<PathBuf>> {
+        // ディレクトリ内の .vm ファイルを収集
+        let mut vm_files: Vec<PathBuf> = fs::read_dir(dir)
+            .context(format!("Failed to read directory '{}'", dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "vm"))
+            .collect();
+
+        ensure!(
+            !vm_files.is_empty(),
+            "No .vm files found in '{}'",
+            dir.display()
+        );
+
+        // ファイル名順にソート（再現性のため）
+        vm_files.sort();
+        Ok(vm_files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nand2tetris_asm::interpret::{Interpreter, Stop};
+    use rstest::rstest;
+
+    // ========================================
+    // validate_label
+    // ========================================
+
+    #[rstest]
+    #[case("LOOP")]
+    #[case("_private")]
+    #[case("test.label")]
+    #[case("foo:bar")]
+    #[case("a1b2c3")]
+    #[case("LOOP_START")]
+    #[case("LOOP.END")]
+    #[case("test:1")]
+    fn test_validate_label_ok(#[case] label: &str) {
+        assert!(validate_label(label).is_ok());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("123abc")]
+    #[case("123invalid")]
+    #[case("@invalid")]
+    #[case("hello world")]
+    #[case("-start")]
+    fn test_validate_label_err(#[case] label: &str) {
+        assert!(validate_label(label).is_err());
+    }
+
+    // ========================================
+    // Parser: コメント・空行・空入力
+    // ========================================
+
+    #[rstest]
+    #[case("// comment\npush constant 5 // inline\n// end", "@5")]
+    #[case("\n\n\npush constant 42\n\n\n", "@42")]
+    fn test_parser_filters_non_code(#[case] input: &str, #[case] expected: &str) {
+        let result = VMTranslator::translate(input, "test").unwrap();
+        assert!(result.contains(expected));
+    }
+
+    // "." にも名前がある（テストはクレートのディレクトリで動く）
+    #[test]
+    fn test_output_path_of_current_dir() {
+        assert_eq!(
+            VMTranslator::output_path(Path::new(".")).unwrap(),
+            Path::new("./nand2tetris-vm.asm")
+        );
+        assert_eq!(
+            VMTranslator::output_path(Path::new("Main.vm")).unwrap(),
+            Path::new("Main.asm")
+        );
+    }
+
+    #[rstest]
+    #[case("// just comments\n// another")]
+    #[case("")]
+    fn test_empty_output(#[case] input: &str) {
+        let result = VMTranslator::translate(input, "test").unwrap();
+        assert!(result.is_empty());
+    }
+
+    // ========================================
+    // Parser 単体
+    // ========================================
+
+    #[test]
+    fn test_parser_return_command() {
+        let parser = VmParser::new("return");
+        let cmd = parser.parse().unwrap();
+        assert_eq!(cmd.command_type, CommandType::Return);
+        assert!(cmd.arg1.is_none());
+        assert!(cmd.arg2.is_none());
+    }
+
+    #[test]
+    fn test_parse_program_json_roundtrip() {
+        let commands = parse_program("push constant 7\nlabel LOOP\nadd\nreturn").unwrap();
+        assert_eq!(commands.len(), 4);
+        assert_eq!(commands[0].command_type, CommandType::Push);
+        assert_eq!(commands[0].arg2, Some(7));
+
+        let json = serde_json::to_string(&commands).unwrap();
+        let decoded: Vec<Command> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, commands);
+    }
+
+    #[test]
+    fn test_parser_advance_and_bounds() {
+        let mut parser = VmParser::new("push constant 1\npush constant 2\npush constant 3");
+        assert!(parser.has_more_commands());
+        assert_eq!(parser.current_line_number(), 1);
+        parser.advance();
+        assert_eq!(parser.current_line_number(), 2);
+        parser.advance();
+        assert_eq!(parser.current_line_number(), 3);
+        parser.advance();
+        assert!(!parser.has_more_commands());
+        parser.advance(); // 超過しても panic しない
+        assert!(!parser.has_more_commands());
+    }
+
+    // ========================================
+    // エラーケース
+    // ========================================
+
+    #[rstest]
+    #[case("foobar")]
+    #[case("push")]
+    #[case("push constant")]
+    #[case("push constant abc")]
+    #[case("pop")]
+    #[case("pop local")]
+    #[case("goto")]
+    #[case("if-goto")]
+    #[case("call")]
+    #[case("call Foo.bar")]
+    #[case("call Foo.bar xyz")]
+    #[case("function")]
+    #[case("function Foo.bar")]
+    #[case("label")]
+    #[case("label @invalid")]
+    #[case("label 123invalid")]
+    #[case("push foo 1")]
+    #[case("pop constant 0")]
+    #[case("push temp 8")]
+    #[case("pop pointer 2")]
+    #[case("push local -1")]
+    #[case("push constant 40000")]
+    #[case("call Foo.bar -1")]
+    #[case("call Foo.bar 2147483647")]
+    #[case("function Foo.bar 2147483647")]
+    fn test_invalid_input(#[case] input: &str) {
+        assert!(VMTranslator::translate(input, "test").is_err());
+    }
+
+    // ========================================
+    // push セグメント
+    // ========================================
+
+    #[rstest]
+    #[case("push constant 17",  "test",   &["@17", "D=A"])]
+    #[case("push constant 100", "test",   &["@100", "D=A"])]
+    #[case("push local 0",      "test",   &["@LCL"])]
+    #[case("push argument 1",   "test",   &["@ARG"])]
+    #[case("push this 2",       "test",   &["@THIS"])]
+    #[case("push that 3",       "test",   &["@THAT"])]
+    #[case("push temp 2",       "test",   &["@7"])]
+    #[case("push temp 5",       "test",   &["@10"])]
+    #[case("push pointer 0",    "test",   &["@THIS", "D=M"])]
+    #[case("push pointer 1",    "test",   &["@THAT", "D=M"])]
+    #[case("push static 3",     "MyFile", &["@MyFile.3"])]
+    #[case("push static 0",     "Foo",    &["@Foo.0"])]
+    #[case("push static 0",     "Bar",    &["@Bar.0"])]
+    fn test_push(#[case] input: &str, #[case] filename: &str, #[case] expected: &[&str]) {
+        let result = VMTranslator::translate(input, filename).unwrap();
+        for s in expected {
+            assert!(
+                result.contains(s),
+                "Expected '{}' in output for '{}'",
+                s,
+                input
+            );
+        }
+    }
+
+    // ========================================
+    // pop セグメント
+    // ========================================
+
+    #[rstest]
+    #[case("pop local 0",    "test",   &["@LCL", "D=D+M"])]
+    #[case("pop argument 1", "test",   &["@ARG"])]
+    #[case("pop this 2",     "test",   &["@THIS"])]
+    #[case("pop that 3",     "test",   &["@THAT"])]
+    #[case("pop temp 0",     "test",   &["@5"])]
+    #[case("pop pointer 0",  "test",   &["@THIS"])]
+    #[case("pop pointer 1",  "test",   &["@THAT"])]
+    fn test_pop(#[case] input: &str, #[case] filename: &str, #[case] expected: &[&str]) {
+        let result = VMTranslator::translate(input, filename).unwrap();
+        for s in expected {
+            assert!(
+                result.contains(s),
+                "Expected '{}' in output for '{}'",
+                s,
+                input
+            );
+        }
+    }
+
+    // ========================================
+    // 算術・論理
+    // ========================================
+
+    #[rstest]
+    #[case("add", "M=D+M")]
+    #[case("sub", "M=M-D")]
+    #[case("neg", "M=-M")]
+    #[case("and", "M=D&M")]
+    #[case("or", "M=D|M")]
+    #[case("not", "M=!M")]
+    fn test_arithmetic(#[case] op: &str, #[case] expected: &str) {
+        let input = format!("push constant 3\npush constant 5\n{}", op);
+        let result = VMTranslator::translate(&input, "test").unwrap();
+        assert!(result.contains(expected));
+    }
+
+    // ========================================
+    // 比較
+    // ========================================
+
+    #[rstest]
+    #[case("eq", "D;JEQ", "(TRUE_0)")]
+    #[case("gt", "D;JGT", "(VM$gt$TRUE)")]
+    #[case("lt", "D;JLT", "(VM$lt$TRUE)")]
+    fn test_comparison(#[case] op: &str, #[case] expected_jump: &str, #[case] label: &str) {
+        let input = format!("push constant 3\npush constant 5\n{}", op);
+        let result = VMTranslator::translate(&input, "test").unwrap();
+        assert!(result.contains(expected_jump));
+        assert!(result.contains(label));
+    }
+
+    // gt と lt のサブルーチンは 1 つずつ
+    #[test]
+    fn test_comparison_routines() {
+        let input = "push constant 1\npush constant 2\ngt\n\
+                      push constant 3\npush constant 4\ngt\n\
+                      push constant 5\npush constant 6\nlt";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        let count = |line: &str| lines.iter().filter(|&&l| l == line).count();
+        assert_eq!(count("(VM$gt)"), 1);
+        assert_eq!(count("(VM$lt)"), 1);
+        assert_eq!(count("@VM$gt"), 1);
+        for label in ["(VM$gt$ret0)", "(VM$gt$ret1)", "(VM$lt$ret2)"] {
+            assert_eq!(count(label), 1);
+        }
+    }
+
+    #[test]
+    fn test_multiple_comparisons_unique_labels() {
+        let input = "push constant 1\npush constant 2\neq\n\
+                      push constant 3\npush constant 4\ngt\n\
+                      push constant 5\npush constant 6\nlt\n\
+                      push constant 7\npush constant 8\neq\n\
+                      push constant 9\npush constant 10\ngt";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        let count = |line: &str| lines.iter().filter(|&&l| l == line).count();
+        for label in [
+            "(TRUE_0)",
+            "(END_0)",
+            "(VM$gt$ret1)",
+            "(VM$lt$ret2)",
+            "(TRUE_3)",
+            "(END_3)",
+            "(VM$gt$ret4)",
+        ] {
+            assert_eq!(count(label), 1, "{}", label);
+        }
+    }
+
+    // x - y があふれても正しい結果になる（以前は 32767 gt -1 が false だった）
+    #[rstest]
+    #[case("push constant 32767\npush constant 1\nneg\ngt", 0xffff)]
+    #[case("push constant 32767\npush constant 1\nneg\nlt", 0)]
+    #[case("push constant 32767\nneg\npush constant 2\nlt", 0xffff)]
+    #[case("push constant 32767\nneg\npush constant 2\ngt", 0)]
+    #[case("push constant 1\npush constant 32767\nneg\nlt", 0)]
+    #[case("push constant 3\npush constant 5\nlt", 0xffff)]
+    #[case("push constant 5\npush constant 5\ngt", 0)]
+    fn test_comparison_overflow(#[case] input: &str, #[case] expected: u16) {
+        let asm = VMTranslator::translate(input, "test").unwrap();
+        let mut cpu = Interpreter::new(&format!("@256\nD=A\n@SP\nM=D\n{}", asm)).unwrap();
+        assert_eq!(cpu.run(1000).unwrap(), Stop::Finished);
+        assert_eq!(cpu.ram[0], 257);
+        assert_eq!(cpu.ram[256], expected, "{}", input);
+    }
+
+    // ========================================
+    // label / goto / if-goto
+    // ========================================
+
+    #[test]
+    fn test_label_goto_if_goto() {
+        let input = "label LOOP\ngoto END\nif-goto LOOP";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["(LOOP)", "@END", "0;JMP", "@LOOP", "D;JNE"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[rstest]
+    #[case("label loop_start", "(loop_start)")]
+    #[case("label LOOP.END", "(LOOP.END)")]
+    #[case("label test:1", "(test:1)")]
+    #[case("label _private", "(_private)")]
+    fn test_label_valid_chars(#[case] input: &str, #[case] expected: &str) {
+        let result = VMTranslator::translate(input, "test").unwrap();
+        assert!(result.contains(expected));
+    }
+
+    // ========================================
+    // call
+    // ========================================
+
+    #[test]
+    fn test_call() {
+        let result = VMTranslator::translate("call Foo.bar 3", "test").unwrap();
+        for s in [
+            "Foo.bar$ret0",
+            "@LCL",
+            "@ARG",
+            "@THIS",
+            "@THAT",
+            "@8",
+            "@Foo.bar",
+            "0;JMP",
+        ] {
+            assert!(result.contains(s), "Expected '{}'", s);
+        }
+    }
+
+    // ========================================
+    // function
+    // ========================================
+
+    #[test]
+    fn test_function() {
+        let result = VMTranslator::translate("function Foo.bar 2", "test").unwrap();
+        assert!(result.contains("(Foo.bar)"));
+        assert!(result.contains("@0"));
+    }
+
+    // ========================================
+    // return
+    // ========================================
+
+    #[test]
+    fn test_return() {
+        let result = VMTranslator::translate("return", "test").unwrap();
+        for s in ["@LCL", "@13", "@R14", "@5", "AM=M-1", "@ARG", "0;JMP"] {
+            assert!(result.contains(s), "Expected '{}'", s);
+        }
+    }
+
+    // ========================================
+    // 統合テスト
+    // ========================================
+
+    #[test]
+    fn test_simple_loop() {
+        let input = r#"
+push constant 0
+pop local 0
+label LOOP_START
+push local 0
+push constant 10
+lt
+if-goto LOOP_BODY
+goto LOOP_END
+label LOOP_BODY
+push local 0
+push constant 1
+add
+pop local 0
+goto LOOP_START
+label LOOP_END
+"#;
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in [
+            "(LOOP_START)",
+            "(LOOP_BODY)",
+            "(LOOP_END)",
+            "@LOOP_START",
+            "@LOOP_BODY",
+            "@LOOP_END",
+        ] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[test]
+    fn test_conditional_branch() {
+        let input = r#"
+push constant 5
+push constant 3
+gt
+if-goto TRUE_BRANCH
+push constant 0
+goto END
+label TRUE_BRANCH
+push constant 1
+label END
+"#;
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["(TRUE_BRANCH)", "(END)", "D;JNE"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[test]
+    fn test_nested_labels() {
+        let input = "label OUTER\npush constant 5\nlabel INNER\npush constant 10\ngoto OUTER";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        assert!(result.contains("(OUTER)"));
+        assert!(result.contains("(INNER)"));
+    }
+
+    #[test]
+    fn test_multiple_arithmetic_operations() {
+        let input = "push constant 10\npush constant 5\nsub\npush constant 2\nadd\nneg";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["M=M-D", "M=D+M", "M=-M"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[test]
+    fn test_all_segments() {
+        let input = r#"
+push constant 10
+push local 0
+push argument 1
+push this 2
+push that 3
+push temp 5
+push pointer 0
+push pointer 1
+pop local 0
+pop argument 1
+pop this 2
+pop that 3
+pop temp 5
+pop pointer 0
+pop pointer 1
+"#;
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["@LCL", "@ARG", "@THIS", "@THAT"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[test]
+    fn test_function_call_return_integration() {
+        let input = "function Main.main 0\npush constant 3\ncall Math.mul 1\nreturn\n\
+                      function Math.mul 1\npush argument 0\npop local 0\npush local 0\nreturn";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["(Main.main)", "(Math.mul)", "Math.mul$ret", "@R14"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    #[test]
+    fn test_fibonacci_like_loop() {
+        let input = "push constant 0\npop local 0\npush constant 1\npop local 1\n\
+                      label LOOP\npush local 0\npush local 1\nadd\npop local 1\npop local 0\n\
+                      push local 1\npush constant 100\nlt\nif-goto LOOP";
+        let result = VMTranslator::translate(input, "test").unwrap();
+        for s in ["(LOOP)", "@LOOP", "D;JNE", "M=D+M"] {
+            assert!(result.contains(s));
+        }
+    }
+
+    // ========================================
+    // ソースマップ
+    // ========================================
+
+    #[test]
+    fn test_translate_path_source_map() {
+        let dir = std::env::temp_dir().join(format!("vm-source-map-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "// entry\nfunction Sys.init 0\npush constant 7\nlabel END\ngoto END",
+        )
+        .unwrap();
+
+        let translation = VMTranslator::translate_path(&dir, true).unwrap();
+        let lines: Vec<&str> = translation.asm.lines().collect();
+        assert_eq!(lines.len(), translation.source_map.len());
+
+        // ブートストラップには対応する VM の行がない
+        assert_eq!(translation.location(0), None);
+
+        let push = lines.iter().position(|l| *l == "@7").unwrap();
+        assert_eq!(
+            translation.location(push),
+            Some(&SourceLocation {
+                file: "Main".to_string(),
+                line: 3,
+            })
+        );
+        let goto = lines.iter().rposition(|l| *l == "@END").unwrap();
+        assert_eq!(translation.location(goto).unwrap().line, 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // ========================================
+    // サイクル数
+    // ========================================
+
+    #[rstest]
+    #[case("push constant 7", (7, 7))]
+    #[case("eq", (15, 17))]
+    #[case("label LOOP", (0, 0))]
+    #[case("goto LOOP", (2, 2))]
+    #[case("if-goto LOOP", (6, 6))]
+    #[case("return", (43, 43))]
+    fn test_cycle_cost(#[case] input: &str, #[case] expected: (usize, usize)) {
+        let output = VMTranslator::translate(input, "Test").unwrap();
+        let lines: Vec<String> = output.lines().map(String::from).collect();
+        assert_eq!(cycle_cost(&lines), expected);
+    }
+
+    #[test]
+    fn test_cycle_cost_backward_jump() {
+        let lines: Vec<String> = ["(LOOP)", "@LOOP", "D;JGT", "D=0"]
+            .iter()
+            .map(|l| l.to_string())
+            .collect();
+        assert_eq!(cycle_cost(&lines), (2, 3));
+    }
+
+    #[test]
+    fn test_translate_annotated() {
+        let dir = std::env::temp_dir().join(format!("vm-annotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.f 1\npush constant 7 // seven\neq\nreturn",
+        )
+        .unwrap();
+
+        let options = TranslateOptions {
+            annotate: true,
+            ..Default::default()
+        };
+        let translation = VMTranslator::translate_path_with(&dir, &options).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let comments: Vec<&str> = translation
+            .asm
+            .lines()
+            .filter(|l| l.starts_with("//"))
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                "// function Main.f 1 (7 cycles)",
+                "// Main.f total: 4 commands, 77 instructions, 72-74 cycles if each command runs once",
+                "// push constant 7 (7 cycles)",
+                "// eq (15-17 cycles)",
+                "// return (43 cycles)",
+            ]
+        );
+        // 挿入したコメントの分もソースマップがずれない
+        assert_eq!(
+            translation.asm.lines().count(),
+            translation.source_map.len()
+        );
+        let push = translation.asm.lines().position(|l| l == "@7").unwrap();
+        assert_eq!(translation.location(push).unwrap().line, 2);
+    }
+
+    #[test]
+    fn test_translate_with_libraries() {
+        let dir = std::env::temp_dir().join(format!("vm-libraries-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Main.vm"),
+            "function Main.f 0\ncall Lib.g 0\nreturn",
+        )
+        .unwrap();
+
+        let options = TranslateOptions {
+            libraries: vec![(
+                "Lib".to_string(),
+                "function Lib.g 0\npush static 0\nreturn".to_string(),
+            )],
+            ..Default::default()
+        };
+        let translation = VMTranslator::translate_path_with(&dir, &options).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // ライブラリはファイルの後ろに、自分の名前で変換される
+        let lines: Vec<&str> = translation.asm.lines().collect();
+        let g = lines.iter().position(|&l| l == "(Lib.g)").unwrap();
+        assert!(lines.iter().position(|&l| l == "(Main.f)").unwrap() < g);
+        assert!(lines.contains(&"@Lib.0"));
+        let location = translation.location(g).unwrap();
+        assert_eq!((location.file.as_str(), location.line), ("Lib", 1));
+    }
+
+    #[test]
+    fn test_translate_error_location() {
+        let err = VMTranslator::translate("push constant 1\npop constant 2", "Main").unwrap_err();
+        assert!(err.to_string().starts_with("Main.vm: Line 2: "));
+    }
+}
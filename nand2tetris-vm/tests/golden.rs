@@ -0,0 +1,20 @@
+// tests/golden の .vm とディレクトリを変換し、コミットしてある .asm と比べる
+// ディレクトリはブートストラップ付き、単独のファイルはなしで変換する（プロジェクト 7・8 のテストと同じ）
+// 参照出力を書き直すには N2T_BLESS=1 cargo test --test golden
+
+use anyhow::Result;
+use nand2tetris_core::golden::check_corpus;
+use nand2tetris_vm::VMTranslator;
+use std::path::Path;
+
+fn translate(path: &Path) -> Result<String> {
+    Ok(VMTranslator::translate_path(path, path.is_dir())?.asm)
+}
+
+#[test]
+fn test_golden() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    if let Err(e) = check_corpus(&corpus, "vm", "asm", translate) {
+        panic!("{:#}", e);
+    }
+}
@@ -0,0 +1,403 @@
+@17
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@17
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+D=M-D
+@TRUE_0
+D;JEQ
+@SP
+A=M
+M=0
+@END_0
+0;JMP
+(TRUE_0)
+@SP
+A=M
+M=-1
+(END_0)
+@SP
+M=M+1
+@17
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@16
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+D=M-D
+@TRUE_1
+D;JEQ
+@SP
+A=M
+M=0
+@END_1
+0;JMP
+(TRUE_1)
+@SP
+A=M
+M=-1
+(END_1)
+@SP
+M=M+1
+@16
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@17
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+D=M-D
+@TRUE_2
+D;JEQ
+@SP
+A=M
+M=0
+@END_2
+0;JMP
+(TRUE_2)
+@SP
+A=M
+M=-1
+(END_2)
+@SP
+M=M+1
+@892
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@891
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$lt$ret3
+D=A
+@R15
+M=D
+(VM$lt)
+@SP
+AM=M-1
+D=M
+@R13
+M=D
+@SP
+A=M-1
+D=M
+@VM$lt$XNEG
+D;JLT
+@R13
+D=M
+@VM$lt$FALSE
+D;JLT
+@VM$lt$SAME
+0;JMP
+(VM$lt$XNEG)
+@R13
+D=M
+@VM$lt$TRUE
+D;JGE
+(VM$lt$SAME)
+@SP
+A=M-1
+D=M
+@R13
+D=D-M
+@VM$lt$TRUE
+D;JLT
+(VM$lt$FALSE)
+D=0
+@VM$lt$END
+0;JMP
+(VM$lt$TRUE)
+D=-1
+(VM$lt$END)
+@SP
+A=M-1
+M=D
+@R15
+A=M
+0;JMP
+(VM$lt$ret3)
+@891
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@892
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$lt$ret4
+D=A
+@R15
+M=D
+@VM$lt
+0;JMP
+(VM$lt$ret4)
+@891
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@891
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$lt$ret5
+D=A
+@R15
+M=D
+@VM$lt
+0;JMP
+(VM$lt$ret5)
+@32767
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@32766
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$gt$ret6
+D=A
+@R15
+M=D
+(VM$gt)
+@SP
+AM=M-1
+D=M
+@R13
+M=D
+@SP
+A=M-1
+D=M
+@VM$gt$XNEG
+D;JLT
+@R13
+D=M
+@VM$gt$TRUE
+D;JLT
+@VM$gt$SAME
+0;JMP
+(VM$gt$XNEG)
+@R13
+D=M
+@VM$gt$FALSE
+D;JGE
+(VM$gt$SAME)
+@SP
+A=M-1
+D=M
+@R13
+D=D-M
+@VM$gt$TRUE
+D;JGT
+(VM$gt$FALSE)
+D=0
+@VM$gt$END
+0;JMP
+(VM$gt$TRUE)
+D=-1
+(VM$gt$END)
+@SP
+A=M-1
+M=D
+@R15
+A=M
+0;JMP
+(VM$gt$ret6)
+@32766
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@32767
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$gt$ret7
+D=A
+@R15
+M=D
+@VM$gt
+0;JMP
+(VM$gt$ret7)
+@32766
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@32766
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@VM$gt$ret8
+D=A
+@R15
+M=D
+@VM$gt
+0;JMP
+(VM$gt$ret8)
+@57
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@31
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@53
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+M=D+M
+@SP
+M=M+1
+@112
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+M=M-D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+M=-M
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+M=D&M
+@SP
+M=M+1
+@82
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+D=M
+@SP
+M=M-1
+A=M
+M=D|M
+@SP
+M=M+1
+@SP
+M=M-1
+A=M
+M=!M
+@SP
+M=M+1
\ No newline at end of file
@@ -0,0 +1,170 @@
+// ブラウザから使うための wasm-bindgen のラッパー
+//
+// サーバーなしでアセンブル・VM 変換・実行をブラウザで行う。ファイルは使わず、ソースは文字列、
+// 機械語は Uint16Array でやり取りする。画面は JS が screen() で読んで描き、キーは set_key で渡す
+
+use nand2tetris_emu::screen::KeyLatch;
+use nand2tetris_vm::{TranslateOptions, VMTranslator};
+use wasm_bindgen::prelude::*;
+
+// エラーは CLI と同じ文面（"Line 12: invalid comp pattern: Q" など）の Error にする
+fn js_error(error: anyhow::Error) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+// Hack アセンブリを機械語にする
+#[wasm_bindgen]
+pub fn assemble(source: &str) -> Result<Vec<u16>, JsError> {
+    nand2tetris_asm::assemble_program(source)
+        .map(|program| program.words)
+        .map_err(js_error)
+}
+
+// names[i] の VM コード sources[i] を順に変換して Hack アセンブリにする。
+// names は拡張子のないファイル名（static 変数の名前に使う）
+#[wasm_bindgen]
+pub fn translate(
+    names: Vec<String>,
+    sources: Vec<String>,
+    bootstrap: bool,
+) -> Result<String, JsError> {
+    if names.len() != sources.len() {
+        return Err(JsError::new(&format!(
+            "{} names were given for {} sources",
+            names.len(),
+            sources.len()
+        )));
+    }
+    let sources: Vec<(String, String)> = names.into_iter().zip(sources).collect();
+    let options = TranslateOptions {
+        bootstrap,
+        ..TranslateOptions::default()
+    };
+    VMTranslator::translate_sources(&sources, &options)
+        .map(|translation| translation.asm)
+        .map_err(js_error)
+}
+
+// ROM に機械語を読み込んだ Hack コンピュータ
+#[wasm_bindgen]
+pub struct Machine {
+    machine: nand2tetris_emu::Machine,
+    // 画面は JS が screen() で読むので描かない
+    key: KeyLatch,
+}
+
+#[wasm_bindgen]
+impl Machine {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u16]) -> Result<Machine, JsError> {
+        let key = KeyLatch::default();
+        let machine = nand2tetris_emu::Machine::new(rom)
+            .map_err(js_error)?
+            .with_io(key.clone());
+        Ok(Machine { machine, key })
+    }
+
+    // 最大 cycles 命令を実行し、実行した数を返す。止まったら（halt かプログラムの末尾）
+    // それより少なくなる。アニメーションのフレームごとに呼ぶ
+    pub fn step(&mut self, cycles: u32) -> Result<u32, JsError> {
+        self.machine
+            .run_cycles(cycles as u64)
+            .map(|executed| executed as u32)
+            .map_err(js_error)
+    }
+
+    // 無限ループ（0;JMP で自分に飛ぶ）に入ったか、PC がプログラムの末尾を越えた
+    pub fn halted(&self) -> bool {
+        self.machine.stop_reason(None).is_some()
+    }
+
+    // RAM はそのままで PC とサイクル数を戻す
+    pub fn reset(&mut self) {
+        self.machine.reset();
+    }
+
+    // 押されているキーの Hack のキーコード（離したら 0）
+    pub fn set_key(&mut self, key: u16) {
+        self.key.set(key);
+        self.machine.set_key(key);
+    }
+
+    pub fn read_ram(&self, address: u16) -> Result<u16, JsError> {
+        self.machine.peek(address).map_err(js_error)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u16) -> Result<(), JsError> {
+        self.machine.poke(address, value).map_err(js_error)
+    }
+
+    // RAM[SCREEN..KBD] の 8192 語の写し。1 行 32 語、各語の LSB が左端のピクセル
+    pub fn screen(&self) -> Vec<u16> {
+        self.machine.screen().to_vec()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.machine.pc()
+    }
+
+    pub fn a(&self) -> u16 {
+        self.machine.a()
+    }
+
+    pub fn d(&self) -> u16 {
+        self.machine.d()
+    }
+
+    // JS の number で返す（2^53 サイクルまでは正確）
+    pub fn cycles(&self) -> f64 {
+        self.machine.cycles() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RAM[0] + RAM[1] を RAM[2] に入れて止まる
+    const ADD: &str = "@R0\nD=M\n@R1\nD=D+M\n@R2\nM=D\n(END)\n@END\n0;JMP\n";
+
+    #[test]
+    fn test_machine() {
+        let rom = assemble(ADD).unwrap();
+        assert_eq!(rom.len(), 8);
+        let mut machine = Machine::new(&rom).unwrap();
+        machine.write_ram(0, 2).unwrap();
+        machine.write_ram(1, 40).unwrap();
+        assert_eq!(machine.step(3).unwrap(), 3);
+        assert!(!machine.halted());
+        // 止まったらそれ以上は実行しない
+        assert_eq!(machine.step(100).unwrap(), 3);
+        assert!(machine.halted());
+        assert_eq!(machine.read_ram(2).unwrap(), 42);
+        assert_eq!(machine.pc(), 6);
+        assert_eq!(machine.cycles(), 6.0);
+
+        machine.set_key(65);
+        assert_eq!(machine.read_ram(24576).unwrap(), 65);
+        // 同期しても JS が押したキーのまま
+        machine.reset();
+        machine.step(10_000).unwrap();
+        assert_eq!(machine.read_ram(24576).unwrap(), 65);
+        assert_eq!(machine.screen().len(), 8192);
+    }
+
+    #[test]
+    fn test_translate() {
+        let asm = translate(
+            vec!["Main".to_string()],
+            vec!["push constant 7\npop static 0\n".to_string()],
+            false,
+        )
+        .unwrap();
+        assert!(asm.contains("@Main.0"), "{}", asm);
+        let rom = assemble(&asm).unwrap();
+        let mut machine = Machine::new(&rom).unwrap();
+        machine.write_ram(0, 256).unwrap();
+        machine.step(100).unwrap();
+        assert_eq!(machine.read_ram(16).unwrap(), 7);
+    }
+}